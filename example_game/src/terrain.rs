@@ -0,0 +1,162 @@
+use cgmath::{InnerSpace, Point2, Point3, Vector2};
+use ox::sandbox::{SandboxBlock, CHUNK_SIZE};
+use ox::world::mem_grid::utils::VoxelPosInLod;
+use ox::world::mem_grid::voxel::ChunkVoxels;
+use ox::world::{TlcPos, VoxelPos, WorldGenerator, WorldSeed};
+
+/// Our terrain: a lit platform in the TLC at the origin, surrounded by hills
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Terrain;
+
+/// www.pcg-random.org and www.shadertoy.com/view/XlGcRh
+fn randi(inp: u32) -> u32 {
+    let x = inp.wrapping_mul(747796405).wrapping_add(2891336453);
+    let x = ((x >> ((x >> 28) + 4)) ^ x).wrapping_mul(277803737);
+    (x >> 22) ^ x
+}
+fn rand(inp: u32) -> f32 {
+    randi(inp) as f32 / u32::MAX as f32
+}
+
+/// returns noise value and its derivatives
+/// https://www.shadertoy.com/view/MdX3Rr
+fn noised(pt: Point2<f64>) -> (f32, Vector2<f32>) {
+    // this will be bad at high integer values
+    let (xf, yf) = ((pt.x - pt.x.floor()) as f32, (pt.y - pt.y.floor()) as f32);
+    let ux = xf * xf * xf * (xf * (xf * 6.0 - 15.0) + 10.0);
+    let uy = yf * yf * yf * (yf * (yf * 6.0 - 15.0) + 10.0);
+    let dux = 30.0 * xf * xf * (xf * (xf - 2.0) + 1.0);
+    let duy = 30.0 * yf * yf * (yf * (yf - 2.0) + 1.0);
+
+    let tile_x = pt.x.floor() as i64;
+    let tile_y = pt.y.floor() as i64;
+    fn tile_seed(x: i64, y: i64) -> u32 {
+        (100_000_000 + x * 10_000 + y) as u32
+    }
+    let a = rand(tile_seed(tile_x, tile_y));
+    let b = rand(tile_seed(tile_x + 1, tile_y));
+    let c = rand(tile_seed(tile_x, tile_y + 1));
+    let d = rand(tile_seed(tile_x + 1, tile_y + 1));
+
+    let w = a - b - c + d;
+    (
+        a + (b - a) * ux + (c - a) * uy + w * ux * uy,
+        Vector2 {
+            x: dux * ((b - a) + w * uy),
+            y: duy * ((c - a) + w * ux),
+        },
+    )
+}
+
+/// Global y position of the platform's surface, where the camera starts
+pub const PLATFORM_HEIGHT: i64 = 8;
+const N_NOISE_LAYERS: usize = 6;
+const TILE_SIZE: u32 = 400;
+const NOISE_SCALE: f32 = 200.0;
+const BASE_TERRAIN_HEIGHT: f64 = 64.0 * -3.5;
+
+impl WorldGenerator for Terrain {
+    fn name(&self) -> &str {
+        "ox_game::terrain"
+    }
+
+    fn seed(&self) -> WorldSeed {
+        WorldSeed::default()
+    }
+
+    fn generate(
+        &self,
+        chunk_pos: TlcPos<i64>,
+        lvl: u8,
+        sublvl: u8,
+        sublvl_y: u8,
+        voxel_ids_out: &mut ChunkVoxels,
+        tlc_size: usize,
+        largest_chunk_lvl: u8,
+    ) {
+        let voxel_size = CHUNK_SIZE.size().pow(lvl as u32) * 2usize.pow(sublvl as u32);
+        let voxel_size_y = CHUNK_SIZE.size().pow(lvl as u32) * 2usize.pow(sublvl_y as u32);
+        let chunk_start_pt: VoxelPos<i64> = VoxelPos(chunk_pos.0 * tlc_size as i64);
+        let grid_size = tlc_size / voxel_size;
+        let grid_size_y = tlc_size / voxel_size_y;
+
+        for i in 0..voxel_ids_out.n_voxels() {
+            voxel_ids_out[i] = SandboxBlock::Air as u8;
+        }
+
+        for x_grid in 0..grid_size as u32 {
+            // world coord
+            let x = x_grid as i64 * voxel_size as i64 + chunk_start_pt.0.x;
+
+            for z_grid in 0..grid_size as u32 {
+                // world coord
+                let z = z_grid as i64 * voxel_size as i64 + chunk_start_pt.0.z;
+
+                // terrain height
+                let (dh2, height) = {
+                    let mut h = 0.0;
+                    let mut dh = Vector2::new(0.0, 0.0);
+                    let mut dh2 = None;
+                    for noise_layer in 0..N_NOISE_LAYERS {
+                        let tile_size_divisor = (1usize << noise_layer) as f64;
+                        let tile_coords = Point2 {
+                            x: tile_size_divisor * x as f64 / TILE_SIZE as f64,
+                            y: tile_size_divisor * z as f64 / TILE_SIZE as f64,
+                        };
+
+                        let (v, dv) = noised(tile_coords);
+                        dh += dv;
+                        let noise_scale = 1.0 / (1usize << noise_layer) as f32;
+                        h += (noise_scale * v / (1.0 + dh.dot(dh))) as f64;
+
+                        if noise_layer == 2 {
+                            dh2 = Some(dh);
+                        }
+                    }
+                    (dh2.unwrap(), h * NOISE_SCALE as f64 + BASE_TERRAIN_HEIGHT)
+                };
+
+                for y_grid in 0..grid_size_y as u32 {
+                    // world coord
+                    let y = y_grid as i64 * voxel_size_y as i64 + chunk_start_pt.0.y;
+
+                    // index in voxel_ids_out
+                    let idx = VoxelPosInLod {
+                        pos: Point3 {
+                            x: x_grid,
+                            y: y_grid,
+                            z: z_grid,
+                        },
+                        lvl,
+                        sublvl,
+                        sublvl_y,
+                    }
+                    .index(CHUNK_SIZE, largest_chunk_lvl);
+
+                    // lit platform
+                    let tlc_size_i = tlc_size as i64;
+                    if (0..tlc_size_i).contains(&x) && (0..tlc_size_i).contains(&z) {
+                        voxel_ids_out[idx] = if y < PLATFORM_HEIGHT {
+                            SandboxBlock::Stone
+                        } else if y == PLATFORM_HEIGHT && x % 8 == 0 && z % 8 == 0 {
+                            SandboxBlock::Lamp
+                        } else {
+                            SandboxBlock::Air
+                        } as u8;
+                    }
+
+                    // hills
+                    if (y as f64) < height {
+                        voxel_ids_out[idx] = if (y as f64) < height - 5.0 || dh2.dot(dh2) > 0.8 {
+                            SandboxBlock::Stone
+                        } else if (y as f64) < height - 1.0 {
+                            SandboxBlock::Dirt
+                        } else {
+                            SandboxBlock::Grass
+                        } as u8;
+                    }
+                }
+            }
+        }
+    }
+}
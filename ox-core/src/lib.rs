@@ -0,0 +1,11 @@
+//! Coordinates, chunk indexing and voxel containers of ox, without the renderer. Tools, servers and map viewers
+//! can depend on this crate to work with ox worlds without compiling Vulkan. ox re-exports everything here at its
+//! old paths (e.g. `ox::world::VoxelPos`, `ox::world::mem_grid::utils::ChunkSize`).
+
+pub mod math;
+pub mod units;
+pub mod utils;
+pub mod voxel;
+
+pub use units::{TlcPos, TlcVector, VoxelPos, VoxelVector, WorldScale};
+pub use utils::ChunkSize;
@@ -0,0 +1,220 @@
+use crate::units::VoxelPos;
+use cgmath::{Point3, Vector3};
+use hashbrown::HashSet;
+use std::collections::VecDeque;
+
+/// Iterator over the voxels a line segment passes through. See `voxels_on_line`.
+#[derive(Debug, Clone)]
+pub struct VoxelsOnLine {
+    pos: Point3<i64>,
+    end: Point3<i64>,
+    step: Vector3<i64>,
+    // Line parameter (0 at the start, 1 at the end) at which the next voxel boundary is crossed on each axis
+    t_max: Vector3<f32>,
+    // Change in line parameter needed to cross one voxel on each axis
+    t_delta: Vector3<f32>,
+    done: bool,
+}
+
+/// Voxels that the line segment from `a` to `b` passes through, in order from `a` to `b`, using 3D DDA. The
+/// voxel at integer position `p` covers `p..p + 1` on each axis, so this starts at `floor(a)` and ends at
+/// `floor(b)`. Consecutive voxels always share a face.
+pub fn voxels_on_line(a: VoxelPos<f32>, b: VoxelPos<f32>) -> VoxelsOnLine {
+    let pos = a.0.map(|x| x.floor() as i64);
+    let end = b.0.map(|x| x.floor() as i64);
+    let dir = b.0 - a.0;
+
+    let mut step = Vector3::new(0, 0, 0);
+    let mut t_max = Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+    let mut t_delta = Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+    for ax in 0..3 {
+        if dir[ax] > 0. {
+            step[ax] = 1;
+            t_delta[ax] = 1. / dir[ax];
+            t_max[ax] = (a.0[ax].floor() + 1. - a.0[ax]) * t_delta[ax];
+        } else if dir[ax] < 0. {
+            step[ax] = -1;
+            t_delta[ax] = -1. / dir[ax];
+            t_max[ax] = (a.0[ax] - a.0[ax].floor()) * t_delta[ax];
+        }
+    }
+
+    VoxelsOnLine {
+        pos,
+        end,
+        step,
+        t_max,
+        t_delta,
+        done: false,
+    }
+}
+
+impl Iterator for VoxelsOnLine {
+    type Item = VoxelPos<i64>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let current = self.pos;
+        if self.pos == self.end {
+            self.done = true;
+        } else {
+            // Step on the axis whose boundary is crossed first. Axes that already reached the end are skipped
+            // so floating point error can't make the line overshoot.
+            let ax = (0..3)
+                .filter(|&ax| self.pos[ax] != self.end[ax])
+                .min_by(|&i, &j| self.t_max[i].total_cmp(&self.t_max[j]))
+                .unwrap();
+            self.pos[ax] += self.step[ax];
+            self.t_max[ax] += self.t_delta[ax];
+        }
+        Some(VoxelPos(current))
+    }
+}
+
+/// All voxels in the box from `min` to `max` (inclusive), in x, y, z order.
+pub fn box_voxels(min: VoxelPos<i64>, max: VoxelPos<i64>) -> impl Iterator<Item = VoxelPos<i64>> {
+    let (min, max) = (min.0, max.0);
+    (min.x..=max.x).flat_map(move |x| {
+        (min.y..=max.y)
+            .flat_map(move |y| (min.z..=max.z).map(move |z| VoxelPos(Point3 { x, y, z })))
+    })
+}
+
+/// Voxels on the surface of the box from `min` to `max` (inclusive), each exactly once, in x, y, z order.
+pub fn box_shell(min: VoxelPos<i64>, max: VoxelPos<i64>) -> impl Iterator<Item = VoxelPos<i64>> {
+    let (min, max) = (min.0, max.0);
+    (min.x..=max.x).flat_map(move |x| {
+        (min.y..=max.y).flat_map(move |y| {
+            let on_edge = x == min.x || x == max.x || y == min.y || y == max.y;
+            // Away from the x/y edges only the min and max z faces are on the surface
+            let z_step = if on_edge {
+                1
+            } else {
+                (max.z - min.z).max(1) as usize
+            };
+            (min.z..=max.z)
+                .step_by(z_step)
+                .map(move |z| VoxelPos(Point3 { x, y, z }))
+        })
+    })
+}
+
+/// Face-connected (6-neighbor) flood fill from `start` over voxels for which `predicate` returns true. Returns
+/// the filled positions in breadth-first order, stopping after `limit` positions. Returns nothing if `start`
+/// doesn't satisfy `predicate`.
+pub fn flood_fill<F: FnMut(VoxelPos<i64>) -> bool>(
+    start: VoxelPos<i64>,
+    mut predicate: F,
+    limit: usize,
+) -> Vec<VoxelPos<i64>> {
+    const NEIGHBORS: [Vector3<i64>; 6] = [
+        Vector3::new(1, 0, 0),
+        Vector3::new(-1, 0, 0),
+        Vector3::new(0, 1, 0),
+        Vector3::new(0, -1, 0),
+        Vector3::new(0, 0, 1),
+        Vector3::new(0, 0, -1),
+    ];
+
+    let mut filled = vec![];
+    if limit == 0 || !predicate(start) {
+        return filled;
+    }
+
+    let mut visited = HashSet::new();
+    let mut queue = VecDeque::new();
+    visited.insert(start.0);
+    queue.push_back(start.0);
+
+    while let Some(pos) = queue.pop_front() {
+        filled.push(VoxelPos(pos));
+        if filled.len() >= limit {
+            break;
+        }
+        for offset in NEIGHBORS {
+            let neighbor = pos + offset;
+            if visited.insert(neighbor) && predicate(VoxelPos(neighbor)) {
+                queue.push_back(neighbor);
+            }
+        }
+    }
+
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(iter: impl Iterator<Item = VoxelPos<i64>>) -> Vec<Point3<i64>> {
+        iter.map(|p| p.0).collect()
+    }
+
+    #[test]
+    fn test_voxels_on_line() {
+        let line = points(voxels_on_line(
+            VoxelPos(Point3::new(0.5, 0.5, 0.5)),
+            VoxelPos(Point3::new(3.5, 1.5, 0.5)),
+        ));
+        assert_eq!(line.first(), Some(&Point3::new(0, 0, 0)));
+        assert_eq!(line.last(), Some(&Point3::new(3, 1, 0)));
+        assert_eq!(line.len(), 5);
+        for w in line.windows(2) {
+            let d = w[1] - w[0];
+            assert_eq!(d.x.abs() + d.y.abs() + d.z.abs(), 1);
+        }
+
+        // Negative direction and a single voxel
+        let line = points(voxels_on_line(
+            VoxelPos(Point3::new(0.5, 0.5, 0.5)),
+            VoxelPos(Point3::new(-1.5, 0.5, 0.5)),
+        ));
+        assert_eq!(
+            line,
+            vec![
+                Point3::new(0, 0, 0),
+                Point3::new(-1, 0, 0),
+                Point3::new(-2, 0, 0)
+            ]
+        );
+        let p = VoxelPos(Point3::new(2.2, -3.7, 1.0));
+        assert_eq!(points(voxels_on_line(p, p)), vec![Point3::new(2, -4, 1)]);
+    }
+
+    #[test]
+    fn test_box_shell() {
+        let (min, max) = (
+            VoxelPos(Point3::new(0, 0, 0)),
+            VoxelPos(Point3::new(3, 3, 3)),
+        );
+        let shell = points(box_shell(min, max));
+        assert_eq!(shell.len(), 4 * 4 * 4 - 2 * 2 * 2);
+        assert!(shell
+            .iter()
+            .all(|p| [p.x, p.y, p.z].iter().any(|&a| a == 0 || a == 3)));
+        assert_eq!(points(box_voxels(min, max)).len(), 64);
+
+        // Flat box
+        let flat = points(box_shell(
+            VoxelPos(Point3::new(0, 0, 5)),
+            VoxelPos(Point3::new(2, 2, 5)),
+        ));
+        assert_eq!(flat.len(), 9);
+    }
+
+    #[test]
+    fn test_flood_fill() {
+        // Fill the inside of a 3x3x3 cube
+        let inside = |p: VoxelPos<i64>| [p.0.x, p.0.y, p.0.z].iter().all(|a| (0..3).contains(a));
+        let filled = flood_fill(VoxelPos(Point3::new(1, 1, 1)), inside, 100);
+        assert_eq!(filled.len(), 27);
+
+        let filled = flood_fill(VoxelPos(Point3::new(1, 1, 1)), inside, 5);
+        assert_eq!(filled.len(), 5);
+        assert_eq!(filled[0].0, Point3::new(1, 1, 1));
+
+        assert!(flood_fill(VoxelPos(Point3::new(5, 5, 5)), inside, 100).is_empty());
+    }
+}
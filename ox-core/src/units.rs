@@ -0,0 +1,240 @@
+use crate::utils::ChunkSize;
+use cgmath::{BaseNum, EuclideanSpace, Point3, Vector3};
+use std::ops::{Add, AddAssign, Mul, Sub, SubAssign};
+
+/// Position in units of top level chunks
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TlcPos<T>(pub Point3<T>);
+
+/// Vector in units of top level chunks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TlcVector<T>(pub Vector3<T>);
+
+/// Position in units of 1 (i.e. LOD 0 voxels)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VoxelPos<T>(pub Point3<T>);
+
+/// Vector in units of 1 (i.e. LOD 0 voxels)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VoxelVector<T>(pub Vector3<T>);
+
+/// Implements the same arithmetic as cgmath's `Point3`/`Vector3` for a position and vector type in the same
+/// units, so positions in different units can't be mixed without an explicit conversion.
+macro_rules! impl_unit_arithmetic {
+    ($pos:ident, $vec:ident) => {
+        impl<T: BaseNum> Add<$vec<T>> for $pos<T> {
+            type Output = $pos<T>;
+            fn add(self, rhs: $vec<T>) -> $pos<T> {
+                $pos(self.0 + rhs.0)
+            }
+        }
+        impl<T: BaseNum> Sub<$vec<T>> for $pos<T> {
+            type Output = $pos<T>;
+            fn sub(self, rhs: $vec<T>) -> $pos<T> {
+                $pos(self.0 - rhs.0)
+            }
+        }
+        impl<T: BaseNum> Sub for $pos<T> {
+            type Output = $vec<T>;
+            fn sub(self, rhs: $pos<T>) -> $vec<T> {
+                $vec(self.0 - rhs.0)
+            }
+        }
+        impl<T: BaseNum> AddAssign<$vec<T>> for $pos<T> {
+            fn add_assign(&mut self, rhs: $vec<T>) {
+                self.0 += rhs.0;
+            }
+        }
+        impl<T: BaseNum> SubAssign<$vec<T>> for $pos<T> {
+            fn sub_assign(&mut self, rhs: $vec<T>) {
+                self.0 -= rhs.0;
+            }
+        }
+        impl<T: BaseNum> Add for $vec<T> {
+            type Output = $vec<T>;
+            fn add(self, rhs: $vec<T>) -> $vec<T> {
+                $vec(self.0 + rhs.0)
+            }
+        }
+        impl<T: BaseNum> Sub for $vec<T> {
+            type Output = $vec<T>;
+            fn sub(self, rhs: $vec<T>) -> $vec<T> {
+                $vec(self.0 - rhs.0)
+            }
+        }
+        impl<T: BaseNum> Mul<T> for $vec<T> {
+            type Output = $vec<T>;
+            fn mul(self, rhs: T) -> $vec<T> {
+                $vec(self.0 * rhs)
+            }
+        }
+    };
+}
+
+impl_unit_arithmetic!(TlcPos, TlcVector);
+impl_unit_arithmetic!(VoxelPos, VoxelVector);
+
+/// Length of a top level chunk in LOD 0 voxels
+fn tlc_size(chunk_size: ChunkSize, largest_chunk_lvl: u8) -> i64 {
+    chunk_size.size().pow(largest_chunk_lvl as u32) as i64
+}
+
+impl TlcPos<i64> {
+    /// Global position of the lowest corner of this top level chunk in LOD 0 voxels
+    #[must_use]
+    pub fn to_voxel(self, chunk_size: ChunkSize, largest_chunk_lvl: u8) -> VoxelPos<i64> {
+        VoxelPos(self.0 * tlc_size(chunk_size, largest_chunk_lvl))
+    }
+}
+
+impl TlcVector<i64> {
+    #[must_use]
+    pub fn to_voxel(self, chunk_size: ChunkSize, largest_chunk_lvl: u8) -> VoxelVector<i64> {
+        VoxelVector(self.0 * tlc_size(chunk_size, largest_chunk_lvl))
+    }
+}
+
+impl VoxelPos<i64> {
+    /// Top level chunk containing this global position
+    #[must_use]
+    pub fn to_tlc(self, chunk_size: ChunkSize, largest_chunk_lvl: u8) -> TlcPos<i64> {
+        let size = tlc_size(chunk_size, largest_chunk_lvl);
+        TlcPos(self.0.map(|a| a.div_euclid(size)))
+    }
+
+    /// Position within the top level chunk returned by `to_tlc`
+    #[must_use]
+    pub fn in_tlc(self, chunk_size: ChunkSize, largest_chunk_lvl: u8) -> VoxelPos<u32> {
+        let size = tlc_size(chunk_size, largest_chunk_lvl);
+        VoxelPos(self.0.map(|a| a.rem_euclid(size) as u32))
+    }
+
+    #[must_use]
+    pub fn to_f32(self) -> VoxelPos<f32> {
+        VoxelPos(self.0.map(|a| a as f32))
+    }
+}
+
+impl VoxelPos<f32> {
+    /// Position of the voxel this point is in
+    #[must_use]
+    pub fn floor(self) -> VoxelPos<i64> {
+        VoxelPos(self.0.map(|a| a.floor() as i64))
+    }
+
+    /// Top level chunk containing this global position
+    #[must_use]
+    pub fn to_tlc(self, chunk_size: ChunkSize, largest_chunk_lvl: u8) -> TlcPos<i64> {
+        self.floor().to_tlc(chunk_size, largest_chunk_lvl)
+    }
+}
+
+impl VoxelPos<u32> {
+    /// Global position of this position within `tlc`
+    #[must_use]
+    pub fn in_global(
+        self,
+        tlc: TlcPos<i64>,
+        chunk_size: ChunkSize,
+        largest_chunk_lvl: u8,
+    ) -> VoxelPos<i64> {
+        tlc.to_voxel(chunk_size, largest_chunk_lvl) + VoxelVector(self.0.map(|a| a as i64).to_vec())
+    }
+}
+
+/// Size of an LOD 0 voxel in meters (see `WorldMetadata::scale` in ox). Positions and distances in ox are in LOD 0
+/// voxels; this converts quantities given in meters (lengths, speeds and accelerations alike) to and from them, e.g.
+/// with the `in_voxels` methods of camera, ray and fog parameters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WorldScale {
+    meters_per_voxel: f32,
+}
+
+impl Default for WorldScale {
+    /// One meter per voxel
+    fn default() -> Self {
+        WorldScale::new(1.)
+    }
+}
+
+impl WorldScale {
+    /// Standard gravity in meters per second per second
+    pub const STANDARD_GRAVITY: f32 = 9.80665;
+
+    pub fn new(meters_per_voxel: f32) -> Self {
+        assert!(
+            meters_per_voxel.is_finite() && meters_per_voxel > 0.,
+            "Meters per voxel must be positive"
+        );
+        WorldScale { meters_per_voxel }
+    }
+
+    pub fn meters_per_voxel(&self) -> f32 {
+        self.meters_per_voxel
+    }
+
+    /// `meters` (or meters per second, ...) in LOD 0 voxels
+    pub fn to_voxels(&self, meters: f32) -> f32 {
+        meters / self.meters_per_voxel
+    }
+
+    /// `voxels` (or voxels per second, ...) in meters
+    pub fn to_meters(&self, voxels: f32) -> f32 {
+        voxels * self.meters_per_voxel
+    }
+
+    pub fn vector_to_voxels(&self, meters: Vector3<f32>) -> VoxelVector<f32> {
+        VoxelVector(meters / self.meters_per_voxel)
+    }
+
+    pub fn vector_to_meters(&self, voxels: VoxelVector<f32>) -> Vector3<f32> {
+        voxels.0 * self.meters_per_voxel
+    }
+
+    /// `STANDARD_GRAVITY` in voxels per second per second, e.g. for a character controller built on
+    /// `physics::sweep_aabb`
+    pub fn gravity(&self) -> f32 {
+        self.to_voxels(Self::STANDARD_GRAVITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_voxel_tlc_conversions() {
+        let cs = ChunkSize::new(3);
+        let pos = VoxelPos(Point3::new(-1i64, 64, 130));
+        let tlc = pos.to_tlc(cs, 2);
+        let in_tlc = pos.in_tlc(cs, 2);
+        assert_eq!(tlc, TlcPos(Point3::new(-1, 1, 2)));
+        assert_eq!(in_tlc, VoxelPos(Point3::new(63, 0, 2)));
+        assert_eq!(in_tlc.in_global(tlc, cs, 2), pos);
+        assert_eq!(
+            VoxelPos(Point3::new(-0.5f32, 63.9, 0.)).to_tlc(cs, 2),
+            TlcPos(Point3::new(-1, 0, 0))
+        );
+
+        let delta = TlcPos(Point3::new(2i64, 0, 0)) - tlc;
+        assert_eq!(delta, TlcVector(Vector3::new(3, -1, -2)));
+        assert_eq!(tlc + delta, TlcPos(Point3::new(2, 0, 0)));
+        assert_eq!(
+            delta.to_voxel(cs, 2),
+            VoxelVector(Vector3::new(192, -64, -128))
+        );
+    }
+
+    #[test]
+    fn test_world_scale() {
+        let scale = WorldScale::new(0.25);
+        assert_eq!(scale.to_voxels(2.), 8.);
+        assert_eq!(scale.to_meters(8.), 2.);
+        assert_eq!(
+            scale.vector_to_voxels(Vector3::new(1., -0.5, 0.)),
+            VoxelVector(Vector3::new(4., -2., 0.))
+        );
+        assert_eq!(scale.gravity(), WorldScale::STANDARD_GRAVITY * 4.);
+        assert_eq!(WorldScale::default().to_voxels(3.), 3.);
+    }
+}
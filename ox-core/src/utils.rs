@@ -2,7 +2,7 @@ use cgmath::{Array, Point3, Vector3};
 use getset::CopyGetters;
 use std::ops::{DerefMut, Index, IndexMut, Mul};
 
-use crate::world::VoxelPos;
+use crate::units::VoxelPos;
 
 pub fn squared<T: Copy + Mul<Output = T>>(x: T) -> T {
     x * x
@@ -11,9 +11,10 @@ pub fn cubed<T: Copy + Mul<Output = T>>(x: T) -> T {
     x * x * x
 }
 
-/// Standard indexing scheme to store a 3D cubic array in a 1D array. `size` is the length of the cube side.
+/// Standard indexing scheme to store a 3D cubic array in a 1D array. `size` is the length of the cube side. Y is the
+/// slowest changing axis, so the same scheme stores arrays that are `size` wide in x and z and any height in y.
 pub const fn index_for_pos(pos: Point3<u32>, size: usize) -> usize {
-    pos.x as usize + pos.y as usize * size as usize * size as usize + pos.z as usize * size as usize
+    pos.x as usize + pos.y as usize * size * size + pos.z as usize * size
 }
 
 pub fn amod(n: Point3<i64>, d: usize) -> Point3<usize> {
@@ -22,6 +23,15 @@ pub fn amod(n: Point3<i64>, d: usize) -> Point3<usize> {
         .unwrap()
 }
 
+/// `amod` with a different modulus for each axis
+pub fn amod_axes(n: Point3<i64>, d: Vector3<usize>) -> Point3<usize> {
+    Point3 {
+        x: n.x.rem_euclid(d.x as i64) as usize,
+        y: n.y.rem_euclid(d.y as i64) as usize,
+        z: n.z.rem_euclid(d.z as i64) as usize,
+    }
+}
+
 pub const fn pos_for_index(index: usize, size: usize) -> Point3<usize> {
     Point3 {
         x: index % size,
@@ -34,10 +44,14 @@ pub const fn pos_for_index(index: usize, size: usize) -> Point3<usize> {
 /// Position relative to bottom corner of current TLC in units of this LOD. For example,
 /// if LOD  lvl=0 and sublvl=2, pos_in_tlc should be in units 4x larger than highest
 /// fidelity voxels (i.e. 4 in world coords).
+///
+/// LODs can be anisotropic, in which case the y axis uses `sublvl_y` instead of `sublvl`.
+/// For isotropic LODs, `sublvl_y == sublvl`.
 pub struct VoxelPosInLod {
     pub pos: Point3<u32>, // position in units of current LOD voxels
     pub lvl: u8,
-    pub sublvl: u8,
+    pub sublvl: u8,   // sublvl for x and z axes
+    pub sublvl_y: u8, // sublvl for y axis
 }
 impl VoxelPosInLod {
     pub fn in_full_lod(pos: VoxelPos<u32>) -> Self {
@@ -45,17 +59,31 @@ impl VoxelPosInLod {
             pos: pos.0,
             lvl: 0,
             sublvl: 0,
+            sublvl_y: 0,
         }
     }
 
-    pub fn in_other_lod(self, lvl: u8, sublvl: u8, chunk_size: ChunkSize) -> Self {
-        let full_lod_pos = self
-            .pos
-            .map(|a| a << (chunk_size.exp() * self.lvl + self.sublvl));
+    /// Number of bits to shift a position in this LOD by in each axis to get full LOD units
+    fn axis_shifts(lvl: u8, sublvl: u8, sublvl_y: u8, chunk_size: ChunkSize) -> Vector3<u32> {
+        Vector3 {
+            x: (chunk_size.exp() * lvl + sublvl) as u32,
+            y: (chunk_size.exp() * lvl + sublvl_y) as u32,
+            z: (chunk_size.exp() * lvl + sublvl) as u32,
+        }
+    }
+
+    pub fn in_other_lod(self, lvl: u8, sublvl: u8, sublvl_y: u8, chunk_size: ChunkSize) -> Self {
+        let from_shifts = Self::axis_shifts(self.lvl, self.sublvl, self.sublvl_y, chunk_size);
+        let to_shifts = Self::axis_shifts(lvl, sublvl, sublvl_y, chunk_size);
         VoxelPosInLod {
-            pos: full_lod_pos.map(|a| a >> (chunk_size.exp() * lvl + sublvl)),
+            pos: Point3 {
+                x: (self.pos.x << from_shifts.x) >> to_shifts.x,
+                y: (self.pos.y << from_shifts.y) >> to_shifts.y,
+                z: (self.pos.z << from_shifts.z) >> to_shifts.z,
+            },
             lvl,
             sublvl,
+            sublvl_y,
         }
     }
 
@@ -64,26 +92,36 @@ impl VoxelPosInLod {
         // ENHANCEMENT: make largest_chunk_lvl const to allow loop unrolling here
         let mut idx = 0usize;
         for lvl in (self.lvl + 1..largest_chunk_lvl).rev() {
-            idx *= cubed(chunk_size.size()) as usize;
-            // block size for this level in units of self.lvl, self.sublvl
+            idx *= cubed(chunk_size.size());
+            // block size for this level in units of self.lvl, self.sublvl (self.sublvl_y for y)
             let lvl_block_size = 1u32 << (chunk_size.exp() * (lvl - self.lvl) - self.sublvl);
-            let pos_at_lvl = (self.pos / lvl_block_size as u32) % chunk_size.size() as u32;
+            let lvl_block_size_y = 1u32 << (chunk_size.exp() * (lvl - self.lvl) - self.sublvl_y);
+            let pos_at_lvl = Point3 {
+                x: self.pos.x / lvl_block_size,
+                y: self.pos.y / lvl_block_size_y,
+                z: self.pos.z / lvl_block_size,
+            } % chunk_size.size() as u32;
             idx += index_for_pos(pos_at_lvl, chunk_size.size());
         }
 
         // e.g., last_chunk_lvl_size = 4 if sublvl == 1 (voxels of size 2x2x2) and chunk size == 8
         let last_chunk_lvl_size = 1usize << (chunk_size.exp() - self.sublvl);
-        idx *= cubed(last_chunk_lvl_size);
-        idx += index_for_pos(self.pos % last_chunk_lvl_size as u32, last_chunk_lvl_size);
+        let last_chunk_lvl_size_y = 1usize << (chunk_size.exp() - self.sublvl_y);
+        idx *= squared(last_chunk_lvl_size) * last_chunk_lvl_size_y;
+        // (index_for_pos only depends on the x/z size)
+        idx += index_for_pos(
+            Point3 {
+                x: self.pos.x % last_chunk_lvl_size as u32,
+                y: self.pos.y % last_chunk_lvl_size_y as u32,
+                z: self.pos.z % last_chunk_lvl_size as u32,
+            },
+            last_chunk_lvl_size,
+        );
 
         idx
     }
 }
 
-pub fn tlc_size(chunk_size: ChunkSize, largest_lvl: u8, lvl: u8, sublvl: u8) -> usize {
-    1usize << (chunk_size.exp() * (largest_lvl - lvl) - sublvl)
-}
-
 pub struct IteratorWithIndexing<I, T>
 where
     I: IndexMut<usize>,
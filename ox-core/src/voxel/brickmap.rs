@@ -0,0 +1,251 @@
+//! Sparse storage for LOD chunks that are mostly empty or solid (see `LodChunkData::compact` in ox).
+
+use crate::voxel::data::{VoxelBitmask, VoxelTypeIDs};
+use crate::voxel::gpu_defs::{ChunkBitmask, ChunkVoxels};
+use std::mem::size_of;
+
+/// Voxels per brick. Bricks are runs of consecutive voxel indices, which are close together in space since
+/// voxel indices are ordered by chunk.
+pub const BRICK_VOXELS: usize = 512;
+const BITMASKS_PER_BRICK: usize = BRICK_VOXELS / 128;
+const IDS_PER_BRICK: usize = BRICK_VOXELS * VoxelTypeIDs::BITS_PER_VOXEL / 128;
+const IDS_PER_ELEMENT: usize = 128 / VoxelTypeIDs::BITS_PER_VOXEL;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Brick {
+    /// Every voxel in the brick has the same ID (0 if there are no IDs) and visibility
+    Uniform {
+        id: u8,
+        visible: bool,
+    },
+    Dense(Box<DenseBrick>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct DenseBrick {
+    bitmask: Box<[VoxelBitmask]>,
+    ids: Option<Box<[VoxelTypeIDs]>>,
+}
+
+/// Chunk data split into bricks of `BRICK_VOXELS` voxels, where bricks whose voxels are all the same are stored
+/// as a single ID instead of a bitmask and voxel IDs. Converts to and from the dense layout used by the GPU
+/// buffers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Brickmap {
+    bricks: Vec<Brick>,
+    bitmask_len: usize,
+    ids_len: Option<usize>, // `None` for LODs that only have a bitmask
+}
+
+impl Brickmap {
+    /// Chunk of `n_voxels` voxels that are all `id` (0 if `has_voxel_ids` is false) and `visible`
+    pub fn new_uniform(n_voxels: usize, has_voxel_ids: bool, id: u8, visible: bool) -> Self {
+        let bitmask_len = VoxelBitmask::new_vec(n_voxels).len();
+        Brickmap {
+            bricks: vec![Brick::Uniform { id, visible }; bitmask_len.div_ceil(BITMASKS_PER_BRICK)],
+            bitmask_len,
+            ids_len: has_voxel_ids.then(|| VoxelTypeIDs::new_vec(n_voxels).len()),
+        }
+    }
+
+    pub fn from_dense(bitmask: &ChunkBitmask, voxel_ids: Option<&ChunkVoxels>) -> Self {
+        let mut id_bricks = voxel_ids.map(|ids| ids.ids.chunks(IDS_PER_BRICK));
+        let bricks = bitmask
+            .bitmask
+            .chunks(BITMASKS_PER_BRICK)
+            .map(|bitmask| {
+                let ids = id_bricks
+                    .as_mut()
+                    .map(|bricks| bricks.next().unwrap_or(&[]));
+                match uniform_brick(bitmask, ids) {
+                    Some(brick) => brick,
+                    None => Brick::Dense(Box::new(DenseBrick {
+                        bitmask: bitmask.into(),
+                        ids: ids.map(|ids| ids.into()),
+                    })),
+                }
+            })
+            .collect();
+        Brickmap {
+            bricks,
+            bitmask_len: bitmask.bitmask.len(),
+            ids_len: voxel_ids.map(|ids| ids.ids.len()),
+        }
+    }
+
+    pub fn to_dense(&self) -> (ChunkBitmask, Option<ChunkVoxels>) {
+        let mut bitmask = Vec::with_capacity(self.bitmask_len);
+        let mut ids = self.ids_len.map(Vec::with_capacity);
+        for brick in self.bricks.iter() {
+            let n_bitmask = BITMASKS_PER_BRICK.min(self.bitmask_len - bitmask.len());
+            match brick {
+                Brick::Uniform { id, visible } => {
+                    let mask = if *visible { u128::MAX } else { 0 };
+                    bitmask.extend((0..n_bitmask).map(|_| VoxelBitmask { mask }));
+                    if let (Some(ids), Some(ids_len)) = (ids.as_mut(), self.ids_len) {
+                        let n_ids = IDS_PER_BRICK.min(ids_len - ids.len());
+                        ids.extend((0..n_ids).map(|_| VoxelTypeIDs {
+                            indices: [*id; IDS_PER_ELEMENT],
+                        }));
+                    }
+                }
+                Brick::Dense(brick) => {
+                    bitmask.extend_from_slice(&brick.bitmask);
+                    if let (Some(ids), Some(brick_ids)) = (ids.as_mut(), brick.ids.as_ref()) {
+                        ids.extend_from_slice(brick_ids);
+                    }
+                }
+            }
+        }
+        (ChunkBitmask { bitmask }, ids.map(|ids| ChunkVoxels { ids }))
+    }
+
+    pub fn n_voxels(&self) -> usize {
+        self.bitmask_len * 128
+    }
+
+    pub fn has_voxel_ids(&self) -> bool {
+        self.ids_len.is_some()
+    }
+
+    pub fn is_visible(&self, index: usize) -> bool {
+        match &self.bricks[index / BRICK_VOXELS] {
+            Brick::Uniform { visible, .. } => *visible,
+            Brick::Dense(brick) => {
+                let mask = brick.bitmask[index % BRICK_VOXELS / 128].mask;
+                mask & (1u128 << (index % 128)) > 0
+            }
+        }
+    }
+
+    /// `None` if this chunk has no voxel IDs
+    pub fn voxel_id(&self, index: usize) -> Option<u8> {
+        self.ids_len?;
+        Some(match &self.bricks[index / BRICK_VOXELS] {
+            Brick::Uniform { id, .. } => *id,
+            Brick::Dense(brick) => {
+                brick.ids.as_ref()?[index % BRICK_VOXELS / IDS_PER_ELEMENT].indices
+                    [index % IDS_PER_ELEMENT]
+            }
+        })
+    }
+
+    /// Approximate heap memory used
+    pub fn heap_bytes(&self) -> usize {
+        self.bricks.capacity() * size_of::<Brick>()
+            + self
+                .bricks
+                .iter()
+                .map(|brick| match brick {
+                    Brick::Uniform { .. } => 0,
+                    Brick::Dense(brick) => {
+                        size_of::<DenseBrick>()
+                            + brick.bitmask.len() * size_of::<VoxelBitmask>()
+                            + brick
+                                .ids
+                                .as_ref()
+                                .map_or(0, |ids| ids.len() * size_of::<VoxelTypeIDs>())
+                    }
+                })
+                .sum::<usize>()
+    }
+}
+
+/// Heap memory used by dense chunk data
+pub fn dense_heap_bytes(bitmask: &ChunkBitmask, voxel_ids: Option<&ChunkVoxels>) -> usize {
+    bitmask.bitmask.capacity() * size_of::<VoxelBitmask>()
+        + voxel_ids.map_or(0, |ids| ids.ids.capacity() * size_of::<VoxelTypeIDs>())
+}
+
+/// The brick as `Brick::Uniform` if all of its voxels are the same. Padding past the end of the chunk has to
+/// match too so that `Brickmap::to_dense` restores it exactly.
+fn uniform_brick(bitmask: &[VoxelBitmask], ids: Option<&[VoxelTypeIDs]>) -> Option<Brick> {
+    let visible = match bitmask.first()?.mask {
+        0 => false,
+        u128::MAX => true,
+        _ => return None,
+    };
+    if bitmask.iter().any(|b| b.mask != bitmask[0].mask) {
+        return None;
+    }
+    let id = match ids {
+        None => 0,
+        Some(ids) => {
+            let id = ids.first()?.indices[0];
+            if ids
+                .iter()
+                .any(|element| element.indices.iter().any(|&i| i != id))
+            {
+                return None;
+            }
+            id
+        }
+    };
+    Some(Brick::Uniform { id, visible })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_brickmap_round_trip() {
+        let n_voxels = BRICK_VOXELS * 4;
+        let mut bitmask = ChunkBitmask::new_blank(n_voxels);
+        let mut ids = ChunkVoxels::new_blank(n_voxels);
+        // Brick 1 is solid, brick 2 has one voxel set and bricks 0 and 3 are empty
+        for i in BRICK_VOXELS..BRICK_VOXELS * 2 {
+            bitmask.set_block_true(i);
+            ids[i] = 3;
+        }
+        bitmask.set_block_true(BRICK_VOXELS * 2 + 5);
+        ids[BRICK_VOXELS * 2 + 5] = 7;
+
+        let brickmap = Brickmap::from_dense(&bitmask, Some(&ids));
+        assert_eq!(
+            brickmap.bricks[..2],
+            [
+                Brick::Uniform {
+                    id: 0,
+                    visible: false
+                },
+                Brick::Uniform {
+                    id: 3,
+                    visible: true
+                }
+            ]
+        );
+        assert!(matches!(brickmap.bricks[2], Brick::Dense(_)));
+        assert!(brickmap.heap_bytes() * 2 < dense_heap_bytes(&bitmask, Some(&ids)));
+
+        for i in [
+            0,
+            BRICK_VOXELS + 1,
+            BRICK_VOXELS * 2 + 5,
+            BRICK_VOXELS * 2 + 6,
+        ] {
+            assert_eq!(brickmap.is_visible(i), bitmask.get(i));
+            assert_eq!(brickmap.voxel_id(i), Some(ids[i]));
+        }
+        assert_eq!(brickmap.to_dense(), (bitmask, Some(ids)));
+    }
+
+    #[test]
+    fn test_brickmap_small_chunk() {
+        // Smaller than a brick and a bitmask element, so the bitmask is padded
+        let mut bitmask = ChunkBitmask::new_blank(8);
+        for i in 0..8 {
+            bitmask.set_block_true(i);
+        }
+        let brickmap = Brickmap::from_dense(&bitmask, None);
+        assert!(brickmap.is_visible(7));
+        assert_eq!(brickmap.voxel_id(7), None);
+        assert_eq!(brickmap.to_dense(), (bitmask, None));
+
+        let empty = Brickmap::new_uniform(8, true, 0, false);
+        assert_eq!(
+            empty.to_dense(),
+            (ChunkBitmask::new_blank(8), Some(ChunkVoxels::new_blank(8)))
+        );
+    }
+}
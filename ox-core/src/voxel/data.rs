@@ -1,7 +1,7 @@
 use std::fmt::{Display, Formatter};
-use vulkano::buffer::BufferContents;
 
-#[derive(BufferContents, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "vulkano", derive(vulkano::buffer::BufferContents))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(C)]
 pub struct VoxelTypeIDs {
     pub indices: [u8; 128 / 8], // ENHANCEMENT: Make this generic somehow so you can use u16 or u32
@@ -14,12 +14,13 @@ impl VoxelTypeIDs {
             VoxelTypeIDs {
                 indices: [0; 128 / 8]
             };
-            (n_voxels * Self::BITS_PER_VOXEL + 127) / 128
+            (n_voxels * Self::BITS_PER_VOXEL).div_ceil(128)
         ]
     }
 }
 
-#[derive(BufferContents, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "vulkano", derive(vulkano::buffer::BufferContents))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 #[repr(C)]
 pub struct VoxelBitmask {
     pub mask: u128,
@@ -27,7 +28,7 @@ pub struct VoxelBitmask {
 
 impl VoxelBitmask {
     pub fn new_vec(n_voxels: usize) -> Vec<Self> {
-        vec![VoxelBitmask { mask: 0 }; (n_voxels + 127) / 128]
+        vec![VoxelBitmask { mask: 0 }; n_voxels.div_ceil(128)]
     }
 }
 
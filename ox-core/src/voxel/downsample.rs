@@ -0,0 +1,134 @@
+//! How the voxels of lower resolution LODs are picked from the higher resolution voxels they cover. Each LOD has
+//! its own `Downsample`, set with ox's `VoxelLODCreateParams::downsample`.
+
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// Visible voxel type among the higher resolution voxels that make up a lower resolution voxel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VisibleSample {
+    pub id: u8,
+    /// Number of higher resolution voxels of this type
+    pub count: u32,
+    /// See `VoxelTypeEnum::opacity`
+    pub opacity: f32,
+}
+
+/// Picks the voxel type of a lower resolution voxel from the higher resolution voxels it covers. Called from
+/// loading threads, so implementations need to be `Send + Sync`.
+pub trait DownsamplePolicy: Debug + Send + Sync {
+    /// ID of the voxel type to fill the voxel with, or `None` to leave it empty. `visible` has one sample per
+    /// visible voxel type among the `n_voxels` higher resolution voxels, sorted by ID. `fill_threshold` is the
+    /// LOD's (see `Downsample`).
+    fn pick(&self, n_voxels: u32, visible: &[VisibleSample], fill_threshold: f32) -> Option<u8>;
+}
+
+/// Sample with the largest `weight`, lowest ID on ties
+fn max_by_weight(visible: &[VisibleSample], weight: impl Fn(&VisibleSample) -> f32) -> Option<u8> {
+    visible
+        .iter()
+        .rev()
+        .max_by(|a, b| weight(a).total_cmp(&weight(b)))
+        .map(|sample| sample.id)
+}
+
+/// The most common visible type, if more than `fill_threshold` of the voxels are visible. The default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MajorityVote;
+
+impl DownsamplePolicy for MajorityVote {
+    fn pick(&self, n_voxels: u32, visible: &[VisibleSample], fill_threshold: f32) -> Option<u8> {
+        let n_visible: u32 = visible.iter().map(|sample| sample.count).sum();
+        if n_visible > 0 && n_visible as f32 > fill_threshold * n_voxels as f32 {
+            max_by_weight(visible, |sample| sample.count as f32)
+        } else {
+            None
+        }
+    }
+}
+
+/// The most common visible type if any voxel is visible, ignoring `fill_threshold`. Keeps thin features like
+/// fences or cables visible in the distance.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AnySolid;
+
+impl DownsamplePolicy for AnySolid {
+    fn pick(&self, n_voxels: u32, visible: &[VisibleSample], _: f32) -> Option<u8> {
+        MajorityVote.pick(n_voxels, visible, 0.)
+    }
+}
+
+/// Like `MajorityVote`, but each voxel counts by its type's opacity, so mostly transparent voxels (glass,
+/// leaves, ...) fill a lower resolution voxel less readily than opaque ones and lose to them when picking the type
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OpacityWeighted;
+
+impl DownsamplePolicy for OpacityWeighted {
+    fn pick(&self, n_voxels: u32, visible: &[VisibleSample], fill_threshold: f32) -> Option<u8> {
+        let weight = |sample: &VisibleSample| sample.count as f32 * sample.opacity.clamp(0., 1.);
+        let total: f32 = visible.iter().map(weight).sum();
+        if total > 0. && total > fill_threshold * n_voxels as f32 {
+            max_by_weight(visible, weight)
+        } else {
+            None
+        }
+    }
+}
+
+/// How an LOD's voxels are computed from the higher resolution LOD they are loaded from
+#[derive(Debug, Clone)]
+pub struct Downsample {
+    /// Fraction (0..1) of the higher resolution voxels that must be visible for a voxel to be filled. How it is
+    /// counted depends on `policy`.
+    pub fill_threshold: f32,
+    pub policy: Arc<dyn DownsamplePolicy>,
+}
+
+impl Default for Downsample {
+    /// `MajorityVote` with a threshold of 0, i.e. a voxel is filled if any voxel it covers is visible
+    fn default() -> Self {
+        Downsample::new(0., MajorityVote)
+    }
+}
+
+impl Downsample {
+    pub fn new(fill_threshold: f32, policy: impl DownsamplePolicy + 'static) -> Self {
+        assert!(
+            (0. ..=1.).contains(&fill_threshold),
+            "Fill threshold must be in 0..=1"
+        );
+        Downsample {
+            fill_threshold,
+            policy: Arc::new(policy),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(id: u8, count: u32, opacity: f32) -> VisibleSample {
+        VisibleSample { id, count, opacity }
+    }
+
+    #[test]
+    fn test_downsample_policies() {
+        let visible = [sample(1, 1, 1.), sample(2, 2, 0.1)];
+        assert_eq!(MajorityVote.pick(8, &visible, 0.), Some(2));
+        assert_eq!(MajorityVote.pick(8, &visible, 0.5), None);
+        assert_eq!(MajorityVote.pick(8, &[], 0.), None);
+        // Ties go to the lowest ID
+        assert_eq!(
+            MajorityVote.pick(8, &[sample(1, 2, 1.), sample(2, 2, 1.)], 0.),
+            Some(1)
+        );
+
+        assert_eq!(AnySolid.pick(8, &visible, 0.9), Some(2));
+        assert_eq!(AnySolid.pick(8, &[], 0.), None);
+
+        assert_eq!(OpacityWeighted.pick(8, &visible, 0.), Some(1));
+        assert_eq!(OpacityWeighted.pick(8, &visible, 0.15), None);
+        assert_eq!(OpacityWeighted.pick(8, &[sample(2, 8, 0.)], 0.), None);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::renderer::component::voxels::data::{VoxelBitmask, VoxelTypeIDs};
+use crate::voxel::data::{VoxelBitmask, VoxelTypeIDs};
 use std::ops::{Index, IndexMut};
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -0,0 +1,389 @@
+//! LOD sizes, the mapping between voxels of different LODs and downsampling on plain voxel ID slices. ox uses these
+//! to compute coarse LODs when a chunk is loaded, and offline tools (pregeneration, importers) can use them to produce
+//! identical LODs without depending on ox's voxel types.
+
+use crate::utils::{squared, ChunkSize, VoxelPosInLod};
+use crate::voxel::downsample::{Downsample, VisibleSample};
+use cgmath::Point3;
+use hashbrown::HashMap;
+
+/// Size (on one side) of top level chunks in units of an LOD's voxels, where the LOD is specified by lvl and sublvl.
+/// This number can be cubed to get the number of voxels.
+pub fn lod_tlc_size(chunk_size: ChunkSize, largest_lvl: u8, lvl: u8, sublvl: u8) -> usize {
+    1usize << (chunk_size.exp() * (largest_lvl - lvl) - sublvl)
+}
+
+/// Call `f` with every voxel position in a top level chunk at the given LOD
+pub fn apply_to_voxels_in_lod<F: FnMut(VoxelPosInLod)>(
+    lvl: u8,
+    sublvl: u8,
+    sublvl_y: u8,
+    chunk_size: ChunkSize,
+    largest_chunk_lvl: u8,
+    mut f: F,
+) {
+    let curr_lod_tlc_size = lod_tlc_size(chunk_size, largest_chunk_lvl, lvl, sublvl) as u32;
+    let curr_lod_tlc_size_y = lod_tlc_size(chunk_size, largest_chunk_lvl, lvl, sublvl_y) as u32;
+    for y in 0..curr_lod_tlc_size_y {
+        for z in 0..curr_lod_tlc_size {
+            for x in 0..curr_lod_tlc_size {
+                f(VoxelPosInLod {
+                    pos: Point3 { x, y, z },
+                    lvl,
+                    sublvl,
+                    sublvl_y,
+                });
+            }
+        }
+    }
+}
+
+/// Given a current lvl/sublvl and a lower lvl/sublvl, find all the voxels in the lower LOD that make
+/// up the voxel at `index`/`pt` in the current LOD and return an iterator over their indices.
+#[allow(clippy::too_many_arguments)]
+pub fn apply_to_voxel_indices_in_lower_lod<F: FnMut(usize)>(
+    voxel: VoxelPosInLod,
+    voxel_index: usize,
+    lower_lvl: u8,
+    lower_sublvl: u8,
+    lower_sublvl_y: u8,
+    chunk_size: ChunkSize,
+    largest_chunk_lvl: u8,
+    mut f: F,
+) {
+    if voxel.sublvl > 0 || voxel.sublvl_y > 0 {
+        // First find all voxels in same lvl but sublvl zero.
+        // If voxel.lvl == lower_lvl then just convert to lower_sublvl instead of zero.
+        // Thus, `target_sublvl` is either zero or `lower_sublvl` (same for y)
+        let (target_sublvl, target_sublvl_y, target_sublvl_is_final) = if voxel.lvl == lower_lvl {
+            debug_assert!(lower_sublvl <= voxel.sublvl && lower_sublvl_y <= voxel.sublvl_y);
+            (lower_sublvl, lower_sublvl_y, true)
+        } else {
+            (0, 0, false)
+        };
+
+        let scale_relative_to_target_sublvl = 1u32 << (voxel.sublvl - target_sublvl);
+        let scale_relative_to_target_sublvl_y = 1u32 << (voxel.sublvl_y - target_sublvl_y);
+        // botleft pos in target sublvl
+        let pos_in_target = Point3 {
+            x: voxel.pos.x * scale_relative_to_target_sublvl,
+            y: voxel.pos.y * scale_relative_to_target_sublvl_y,
+            z: voxel.pos.z * scale_relative_to_target_sublvl,
+        };
+        let start_idx_in_target = VoxelPosInLod {
+            pos: pos_in_target,
+            lvl: voxel.lvl,
+            sublvl: target_sublvl,
+            sublvl_y: target_sublvl_y,
+        }
+        .index(chunk_size, largest_chunk_lvl);
+        // index increment when shifting z by 1 in target sublvl
+        let target_z_incr = 1u32 << (chunk_size.exp() - target_sublvl);
+        // index increment when shifting y by 1 in target sublvl
+        let target_y_incr = target_z_incr * target_z_incr;
+        // (shifting x is always an increment of 1)
+
+        if target_sublvl_is_final {
+            for dy in 0..scale_relative_to_target_sublvl_y {
+                for dz in 0..scale_relative_to_target_sublvl {
+                    for dx in 0..scale_relative_to_target_sublvl {
+                        f(start_idx_in_target
+                            + (dx + dy * target_y_incr + dz * target_z_incr) as usize);
+                    }
+                }
+            }
+        } else {
+            for dy in 0..scale_relative_to_target_sublvl_y {
+                for dz in 0..scale_relative_to_target_sublvl {
+                    for dx in 0..scale_relative_to_target_sublvl {
+                        apply_to_voxel_indices_in_lower_lod_for_lvl(
+                            start_idx_in_target
+                                + (dx + dy * target_y_incr + dz * target_z_incr) as usize,
+                            voxel.lvl,
+                            lower_lvl,
+                            lower_sublvl,
+                            lower_sublvl_y,
+                            chunk_size,
+                            &mut f,
+                        );
+                    }
+                }
+            }
+        }
+    } else {
+        apply_to_voxel_indices_in_lower_lod_for_lvl(
+            voxel_index,
+            voxel.lvl,
+            lower_lvl,
+            lower_sublvl,
+            lower_sublvl_y,
+            chunk_size,
+            &mut f,
+        );
+    }
+}
+
+/// `apply_to_voxel_indices_in_lower_lod` special case where sublvl == 0
+fn apply_to_voxel_indices_in_lower_lod_for_lvl<F: FnMut(usize)>(
+    voxel_index: usize,
+    lvl: u8,
+    lower_lvl: u8,
+    lower_sublvl: u8,
+    lower_sublvl_y: u8,
+    chunk_size: ChunkSize,
+    f: &mut F,
+) {
+    let scale_relative_to_lower = 1u32 << (chunk_size.exp() * (lvl - lower_lvl) - lower_sublvl);
+    let scale_relative_to_lower_y = 1u32 << (chunk_size.exp() * (lvl - lower_lvl) - lower_sublvl_y);
+    let lower_voxels_per = (squared(scale_relative_to_lower) * scale_relative_to_lower_y) as usize;
+    let first_idx_in_lower = voxel_index * lower_voxels_per;
+    for idx in first_idx_in_lower..(first_idx_in_lower + lower_voxels_per) {
+        f(idx);
+    }
+}
+
+/// What downsampling needs to know about a voxel type (ox gets this from `VoxelTypeEnum`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VoxelTypeInfo {
+    pub is_visible: bool,
+    /// See `VisibleSample::opacity`
+    pub opacity: f32,
+}
+
+/// Counts the voxel types in the lower LOD voxels that make up one voxel of a higher LOD
+#[derive(Debug, Default)]
+pub struct DownsampleCounts {
+    count: u32,
+    // Count and opacity of each visible voxel type by ID
+    type_counts: HashMap<u8, (u32, f32)>,
+}
+
+impl DownsampleCounts {
+    pub fn add(&mut self, id: u8, info: VoxelTypeInfo) {
+        self.count += 1;
+        if info.is_visible {
+            self.type_counts.entry(id).or_insert((0, info.opacity)).0 += 1;
+        }
+    }
+
+    /// ID of the voxel type `downsample` picks for the counted voxels, `None` if it should be empty
+    pub fn result(self, downsample: &Downsample) -> Option<u8> {
+        let mut visible: Vec<VisibleSample> = self
+            .type_counts
+            .into_iter()
+            .map(|(id, (count, opacity))| VisibleSample { id, count, opacity })
+            .collect();
+        visible.sort_unstable_by_key(|sample| sample.id);
+        downsample
+            .policy
+            .pick(self.count, &visible, downsample.fill_threshold)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LodLevel {
+    pub lvl: u8,
+    pub sublvl: u8,
+    pub sublvl_y: u8,
+}
+
+impl LodLevel {
+    /// Number of voxels in a top level chunk at this LOD
+    pub fn n_voxels(&self, chunk_size: ChunkSize, largest_chunk_lvl: u8) -> usize {
+        let size = lod_tlc_size(chunk_size, largest_chunk_lvl, self.lvl, self.sublvl);
+        let size_y = lod_tlc_size(chunk_size, largest_chunk_lvl, self.lvl, self.sublvl_y);
+        size * size * size_y
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DownsampleParams {
+    pub chunk_size: ChunkSize,
+    pub largest_chunk_lvl: u8,
+    /// LOD of the source voxels. Must be higher resolution than `dst`.
+    pub src: LodLevel,
+    pub dst: LodLevel,
+    /// Pass the `downsample` of the destination LOD's `VoxelLODCreateParams` to match the runtime
+    pub downsample: Downsample,
+    /// ID of the empty voxel type, for destination voxels `downsample` picks no type for
+    pub empty_id: u8,
+}
+
+/// Compute the voxel IDs of one top level chunk at LOD `params.dst` from its voxel IDs at `params.src`. Both
+/// slices are in the same order as `ChunkVoxels`. `voxel_type` describes the voxel type of each ID. Each destination
+/// voxel gets the voxel type `params.downsample` picks from its source voxels, or `params.empty_id` if it picks none.
+pub fn downsample(
+    src: &[u8],
+    dst: &mut [u8],
+    params: &DownsampleParams,
+    voxel_type: impl Fn(u8) -> VoxelTypeInfo,
+) {
+    let DownsampleParams {
+        chunk_size,
+        largest_chunk_lvl,
+        src: src_lod,
+        dst: dst_lod,
+        ref downsample,
+        empty_id,
+    } = *params;
+    assert_eq!(
+        src.len(),
+        src_lod.n_voxels(chunk_size, largest_chunk_lvl),
+        "Source voxels don't match the source LOD"
+    );
+    assert_eq!(
+        dst.len(),
+        dst_lod.n_voxels(chunk_size, largest_chunk_lvl),
+        "Destination voxels don't match the destination LOD"
+    );
+
+    apply_to_voxels_in_lod(
+        dst_lod.lvl,
+        dst_lod.sublvl,
+        dst_lod.sublvl_y,
+        chunk_size,
+        largest_chunk_lvl,
+        |pos| {
+            let index = pos.index(chunk_size, largest_chunk_lvl);
+            let mut counts = DownsampleCounts::default();
+            apply_to_voxel_indices_in_lower_lod(
+                pos,
+                index,
+                src_lod.lvl,
+                src_lod.sublvl,
+                src_lod.sublvl_y,
+                chunk_size,
+                largest_chunk_lvl,
+                |idx| counts.add(src[idx], voxel_type(src[idx])),
+            );
+            dst[index] = counts.result(downsample).unwrap_or(empty_id);
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AIR: u8 = 0;
+    const STONE: u8 = 1;
+    const DIRT: u8 = 2;
+
+    fn voxel_type(id: u8) -> VoxelTypeInfo {
+        VoxelTypeInfo {
+            is_visible: id != AIR,
+            opacity: 1.,
+        }
+    }
+
+    #[test]
+    fn test_apply_to_all_voxels_in_lod_0_0() {
+        let mut indices = [false; 64 * 64 * 64];
+        let cs = ChunkSize::new(3);
+        apply_to_voxels_in_lod(0, 0, 0, cs, 2, |pos| {
+            let idx = pos.index(cs, 2);
+            assert!(!indices[idx]);
+            indices[idx] = true;
+        });
+        assert!(indices.into_iter().all(|x| x));
+    }
+
+    #[test]
+    fn test_apply_to_all_voxels_in_lod_0_1() {
+        let mut indices = [false; 32 * 32 * 32];
+        let cs = ChunkSize::new(3);
+        apply_to_voxels_in_lod(0, 1, 1, cs, 2, |pos| {
+            let idx = pos.index(cs, 2);
+            assert!(!indices[idx]);
+            indices[idx] = true;
+        });
+        assert!(indices.into_iter().all(|x| x));
+    }
+
+    #[test]
+    fn test_apply_to_all_voxels_in_lod_0_2() {
+        let mut indices = [false; 16 * 16 * 16];
+        let cs = ChunkSize::new(3);
+        apply_to_voxels_in_lod(0, 2, 2, cs, 2, |pos| {
+            let idx = pos.index(cs, 2);
+            assert!(!indices[idx]);
+            indices[idx] = true;
+        });
+        assert!(indices.into_iter().all(|x| x));
+    }
+
+    #[test]
+    fn test_apply_to_all_voxels_in_lod_1_0() {
+        let mut indices = [false; 8 * 8 * 8];
+        let cs = ChunkSize::new(3);
+        apply_to_voxels_in_lod(1, 0, 0, cs, 2, |pos| {
+            let idx = pos.index(cs, 2);
+            assert!(!indices[idx]);
+            indices[idx] = true;
+        });
+        assert!(indices.into_iter().all(|x| x));
+    }
+
+    #[test]
+    fn test_apply_to_all_voxels_in_lod_0_2_anisotropic() {
+        let mut indices = [false; 16 * 32 * 16];
+        let cs = ChunkSize::new(3);
+        apply_to_voxels_in_lod(0, 2, 1, cs, 2, |pos| {
+            let idx = pos.index(cs, 2);
+            assert!(!indices[idx]);
+            indices[idx] = true;
+        });
+        assert!(indices.into_iter().all(|x| x));
+    }
+
+    #[test]
+    fn test_downsample() {
+        let mut params = DownsampleParams {
+            chunk_size: ChunkSize::new(2),
+            largest_chunk_lvl: 2,
+            src: LodLevel {
+                lvl: 0,
+                sublvl: 0,
+                sublvl_y: 0,
+            },
+            dst: LodLevel {
+                lvl: 0,
+                sublvl: 1,
+                sublvl_y: 1,
+            },
+            downsample: Downsample::default(),
+            empty_id: AIR,
+        };
+        let (chunk_size, largest_chunk_lvl) = (params.chunk_size, params.largest_chunk_lvl);
+        let index = |x, y, z, sublvl| {
+            VoxelPosInLod {
+                pos: Point3 { x, y, z },
+                lvl: 0,
+                sublvl,
+                sublvl_y: sublvl,
+            }
+            .index(chunk_size, largest_chunk_lvl)
+        };
+
+        // Destination voxel (0, 0, 0) is made of source voxels (0..2, 0..2, 0..2)
+        // and destination voxel (1, 0, 0) is made of source voxels (2..4, 0..2, 0..2)
+        let mut src = vec![AIR; 16 * 16 * 16];
+        src[index(0, 0, 0, 0)] = STONE;
+        src[index(1, 0, 0, 0)] = DIRT;
+        src[index(0, 1, 0, 0)] = DIRT;
+        src[index(2, 0, 0, 0)] = DIRT;
+        src[index(3, 1, 1, 0)] = STONE;
+        let mut dst = vec![AIR; 8 * 8 * 8];
+
+        downsample(&src, &mut dst, &params, voxel_type);
+        assert_eq!(dst[index(0, 0, 0, 1)], DIRT);
+        // Ties go to the lowest ID
+        assert_eq!(dst[index(1, 0, 0, 1)], STONE);
+        assert_eq!(dst.iter().filter(|&&id| id != AIR).count(), 2);
+
+        params.downsample.fill_threshold = 0.3;
+        downsample(&src, &mut dst, &params, voxel_type);
+        assert_eq!(dst[index(0, 0, 0, 1)], DIRT);
+        assert_eq!(dst[index(1, 0, 0, 1)], AIR);
+    }
+}
@@ -0,0 +1,9 @@
+pub mod brickmap;
+pub mod data;
+pub mod downsample;
+pub mod gpu_defs;
+pub mod lod;
+
+pub use data::{VoxelBitmask, VoxelTypeIDs};
+pub use downsample::{Downsample, DownsamplePolicy};
+pub use gpu_defs::{ChunkBitmask, ChunkVoxels};
@@ -0,0 +1,54 @@
+//! Render the sandbox reference scenes and compare them with the hashes recorded in `reference_hashes.txt` (or the
+//! file given as the first argument), recording any that are missing. Run it before a refactor to record the
+//! hashes and again after it to check that nothing changed. Pass `--update` to overwrite the recorded hashes.
+//!
+//! `cargo run --release --features sandbox --example reference_scenes`
+
+use ox::sandbox::reference::{
+    check_reference_scene, ReferenceCheck, ReferenceHashes, ReferenceScene,
+};
+use std::path::PathBuf;
+use winit::dpi::PhysicalSize;
+
+const EXTENT: PhysicalSize<u32> = PhysicalSize::new(320, 240);
+
+fn main() {
+    let mut update = false;
+    let mut path = PathBuf::from("reference_hashes.txt");
+    for arg in std::env::args().skip(1) {
+        if arg == "--update" {
+            update = true;
+        } else {
+            path = PathBuf::from(arg);
+        }
+    }
+
+    let mut hashes = if update {
+        ReferenceHashes::default()
+    } else {
+        ReferenceHashes::load(&path).expect("Failed to read reference hashes")
+    };
+    let mut n_mismatched = 0;
+    for scene in ReferenceScene::ALL {
+        let (check, _) = check_reference_scene(&mut hashes, scene, EXTENT);
+        match check {
+            ReferenceCheck::Match => println!("{}: ok", scene.id()),
+            ReferenceCheck::Mismatch { expected, actual } => {
+                n_mismatched += 1;
+                println!(
+                    "{}: MISMATCH (expected {:08x}, got {:08x})",
+                    scene.id(),
+                    expected,
+                    actual
+                );
+            }
+            ReferenceCheck::Recorded(hash) => println!("{}: recorded {:08x}", scene.id(), hash),
+        }
+    }
+    hashes
+        .save(&path)
+        .expect("Failed to write reference hashes");
+    if n_mismatched > 0 {
+        std::process::exit(1);
+    }
+}
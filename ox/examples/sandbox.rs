@@ -0,0 +1,3 @@
+fn main() {
+    ox::sandbox::run(Default::default())
+}
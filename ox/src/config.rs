@@ -0,0 +1,171 @@
+//! Startup options for the renderer and world in one struct, so games can load them from a settings file (e.g.
+//! TOML, with the `serde` feature) and save them again after the user changes them.
+
+use crate::loader::{ChunkLoaderParams, LoadRateLimit};
+use crate::renderer::binding::BindingMapBuilder;
+use crate::world::mem_grid::utils::ChunkSize;
+use crate::world::mem_grid::voxel::{Downsample, LodConfig, VoxelLODCreateParams};
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct OxConfig {
+    /// Initial inner size of the window in physical pixels. If `None`, the platform default is used.
+    pub window_size: Option<[u32; 2]>,
+    /// Wait for vertical blank to present frames. When false, a mailbox or immediate present mode is used if
+    /// the surface supports one.
+    pub vsync: bool,
+    /// Fraction of the window resolution to render at. The built-in swapchain pipeline always renders at the
+    /// window resolution, so this is only for games that render to their own scaled target.
+    pub render_scale: f32,
+    /// Enable the Vulkan validation layer if it is installed
+    pub validation: bool,
+    /// Render distance in TLCs, used for the standard LOD config when `lods` is `None`
+    pub render_distance: usize,
+    /// Distance from the center of the loaded area (in LOD 0 voxels) the camera can move before the world shifts
+    pub load_dist_thresh: u32,
+    pub loader_threads: usize,
+    /// Cap on chunk loads started per second (see `LoadRateLimit`). `None` for no cap.
+    pub loader_rate_limit: Option<LoadRateLimit>,
+    /// LOD ladder, ordered by increasing lvl/sublvl. If `None`, `VoxelMemoryGrid::standard_config` is used.
+    pub lods: Option<Vec<LodSpec>>,
+    /// Size of an LOD 0 voxel in meters, see `WorldScale`
+    pub meters_per_voxel: f32,
+    /// Seed to generate the world with, e.g. `TerrainGenerator::new(WorldSeed(config.seed), ...)`
+    pub seed: u32,
+}
+
+/// One LOD of `OxConfig::lods`. Bindings are registered by name (see `OxConfig::lod_config`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LodSpec {
+    pub lvl: u8,
+    pub sublvl: u8,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub sublvl_y: Option<u8>,
+    /// Render distance in chunks of this LOD, not including the center chunk
+    pub render_distance: usize,
+    /// Render distance in the y axis. If `None`, this is the same as `render_distance`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub render_distance_y: Option<usize>,
+    /// Whether this LOD has voxel IDs in addition to its bitmask
+    pub voxel_ids: bool,
+}
+
+impl Default for OxConfig {
+    fn default() -> Self {
+        OxConfig {
+            window_size: None,
+            vsync: true,
+            render_scale: 1.,
+            validation: true,
+            render_distance: 11,
+            load_dist_thresh: 16,
+            loader_threads: 8,
+            loader_rate_limit: None,
+            lods: None,
+            meters_per_voxel: 1.,
+            seed: 0,
+        }
+    }
+}
+
+impl OxConfig {
+    /// LOD config with `N` LODs from `lods`, or the standard config if `lods` is `None` (then `N` must be 5).
+    /// Panics if the number of LODs doesn't match `N`.
+    pub fn lod_config<const N: usize>(
+        &self,
+        chunk_size: ChunkSize,
+        bindings: &mut BindingMapBuilder,
+    ) -> LodConfig<N> {
+        let lods: Vec<VoxelLODCreateParams> = match &self.lods {
+            None => LodConfig::standard(self.render_distance, chunk_size, bindings)
+                .into_parts()
+                .0
+                .into(),
+            Some(lods) => lods
+                .iter()
+                .map(|lod| lod.create_params(chunk_size, bindings))
+                .collect(),
+        };
+        let n_lods = lods.len();
+        LodConfig::new(
+            lods.try_into()
+                .unwrap_or_else(|_| panic!("OxConfig has {} LODs but {} were expected", n_lods, N)),
+            chunk_size,
+        )
+    }
+
+    pub fn loader_params(&self) -> ChunkLoaderParams {
+        ChunkLoaderParams {
+            n_threads: self.loader_threads,
+            rate_limit: self.loader_rate_limit,
+        }
+    }
+}
+
+impl LodSpec {
+    pub fn create_params(
+        &self,
+        chunk_size: ChunkSize,
+        bindings: &mut BindingMapBuilder,
+    ) -> VoxelLODCreateParams {
+        VoxelLODCreateParams {
+            voxel_resolution: chunk_size.size().pow(self.lvl as u32)
+                * 2usize.pow(self.sublvl as u32),
+            lvl: self.lvl,
+            sublvl: self.sublvl,
+            sublvl_y: self.sublvl_y,
+            render_area_size: self.render_distance * 2 + 1,
+            render_area_size_y: self.render_distance_y.map(|dist| dist * 2 + 1),
+            bitmask_binding: bindings
+                .register(&format!("lod_{}_{}_bitmask", self.lvl, self.sublvl)),
+            voxel_ids_binding: self
+                .voxel_ids
+                .then(|| bindings.register(&format!("lod_{}_{}_voxel_ids", self.lvl, self.sublvl))),
+            downsample: Downsample::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lod_config_from_ox_config() {
+        let chunk_size = ChunkSize::new(3);
+        let config = OxConfig::default();
+        let lods = config.lod_config::<5>(chunk_size, &mut BindingMapBuilder::new());
+        assert_eq!(lods.render_area_size(), 23);
+
+        let config = OxConfig {
+            lods: Some(vec![
+                LodSpec {
+                    lvl: 0,
+                    sublvl: 0,
+                    sublvl_y: None,
+                    render_distance: 1,
+                    render_distance_y: None,
+                    voxel_ids: true,
+                },
+                LodSpec {
+                    lvl: 1,
+                    sublvl: 0,
+                    sublvl_y: None,
+                    render_distance: 4,
+                    render_distance_y: Some(2),
+                    voxel_ids: false,
+                },
+            ]),
+            ..Default::default()
+        };
+        let lods = config.lod_config::<2>(chunk_size, &mut BindingMapBuilder::new());
+        assert_eq!(lods.lods()[0].voxel_resolution, 1);
+        assert_eq!(lods.lods()[1].voxel_resolution, 8);
+        assert!(lods.lods()[1].voxel_ids_binding.is_none());
+        assert_eq!(lods.render_area_size(), 9);
+        assert_eq!(lods.render_area_size_y(), 5);
+        assert_eq!(lods.tlc_size(), 8);
+    }
+}
@@ -1,6 +1,22 @@
+pub mod config;
 pub mod loader;
+pub mod lod;
+pub mod metrics;
+pub mod noise;
+pub mod photo;
+pub mod physics;
+pub mod pregen;
 pub mod ray;
+pub mod reference;
 pub mod renderer;
+#[cfg(feature = "sandbox")]
+pub mod sandbox;
+pub mod stress;
+pub mod timelapse;
 pub mod util;
 pub mod voxel_type;
 pub mod world;
+
+// Data model shared with tools that don't need the renderer, see the ox-core crate
+pub use ox_core;
+pub use ox_core::math;
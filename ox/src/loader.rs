@@ -1,10 +1,21 @@
+use crate::metrics::{names, MetricsSink};
+use crate::pregen::{pregen_queue, PregenProgress};
+use crate::world::diff::ChunkSource;
 use crate::world::mem_grid::{EditMemoryGridChunk, MemoryGrid, MemoryGridLoadChunks};
+use crate::world::storage::ChunkStore;
 use crate::world::{TlcPos, World};
+use cgmath::Point3;
 use getset::{CopyGetters, Getters};
+use hashbrown::HashSet;
 use priority_queue::PriorityQueue;
+use std::fmt;
 use std::hash::{Hash, Hasher};
-use std::sync::mpsc::{sync_channel, Receiver, TryRecvError};
-use std::thread;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, sync_channel, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct ChunkLoadQueueItem<D> {
@@ -35,26 +46,48 @@ mod layer_chunk {
 
     /// Chunk data from a single memory grid layer
     #[derive(Debug)]
-    pub struct LayerChunk<T>(Option<PresentLayerChunk<T>>);
+    pub struct LayerChunk<T> {
+        chunk: Option<PresentLayerChunk<T>>,
+        /// Incremented whenever data that is taken for loading should no longer be accepted back, e.g.
+        /// when this slot is reused for a different chunk. Kept while the data is taken.
+        version: u64,
+    }
 
     impl<T> LayerChunk<T> {
         pub fn new(data: T) -> Self {
-            Self(Some(PresentLayerChunk {
-                data,
-                validity: Validity::Invalid,
-            }))
+            Self {
+                chunk: Some(PresentLayerChunk {
+                    data,
+                    validity: Validity::Invalid,
+                }),
+                version: 0,
+            }
         }
 
         pub fn new_valid(data: T) -> Self {
-            Self(Some(PresentLayerChunk {
-                data,
-                validity: Validity::Valid,
-            }))
+            Self {
+                chunk: Some(PresentLayerChunk {
+                    data,
+                    validity: Validity::Valid,
+                }),
+                version: 0,
+            }
+        }
+
+        pub fn version(&self) -> u64 {
+            self.version
+        }
+
+        /// Make any load that is currently in flight for this chunk stale, so its result is not marked
+        /// valid when it is returned. This is done automatically when a memory grid shift reuses this
+        /// chunk's slot, when a region is invalidated, and whenever the data is edited through `get_mut`.
+        pub(crate) fn bump_version(&mut self) {
+            self.version += 1;
         }
 
         /// Returns referece data if it's valid (not invalid or missing)
         pub fn get(&self) -> Option<&T> {
-            self.0
+            self.chunk
                 .as_ref()
                 .map(|c| match c.validity {
                     Validity::Valid => Some(&c.data),
@@ -63,20 +96,24 @@ mod layer_chunk {
                 .flatten()
         }
 
-        /// Returns mutable reference to data if it's valid (not invalid or missing)
+        /// Returns mutable reference to data if it's valid (not invalid or missing). This counts as an edit, so
+        /// it bumps the version and no load that started before it is accepted back over the edited data.
         pub fn get_mut(&mut self) -> Option<&mut T> {
-            self.0
-                .as_mut()
-                .map(|c| match c.validity {
-                    Validity::Valid => Some(&mut c.data),
-                    Validity::Invalid => None,
-                })
-                .flatten()
+            match self.chunk.as_mut() {
+                Some(PresentLayerChunk {
+                    data,
+                    validity: Validity::Valid,
+                }) => {
+                    self.version += 1;
+                    Some(data)
+                }
+                _ => None,
+            }
         }
 
         /// Set the state to "invalid". Returns Err if data is missing.
         pub fn set_invalid(&mut self) -> Result<(), ()> {
-            if let Some(c) = self.0.as_mut() {
+            if let Some(c) = self.chunk.as_mut() {
                 match c.validity {
                     Validity::Valid => {
                         c.validity = Validity::Invalid;
@@ -90,8 +127,9 @@ mod layer_chunk {
         }
 
         /// Take data for loading. State should be "invalid" to do this according to the chunk loading process.
+        /// The current `version` should be recorded alongside the data and passed to `return_data`.
         pub fn take(&mut self) -> Option<T> {
-            self.0
+            self.chunk
                 .take()
                 .map(|c| {
                     debug_assert!(c.validity == Validity::Invalid);
@@ -100,12 +138,25 @@ mod layer_chunk {
                 .flatten()
         }
 
-        // pub fn return_data(&mut self, data: T) -> Result<(), ()> {
-        //     match self.0 {
-        //         None => {self = Self::new_valid(data); Ok(()) },
-        //         Some(_) => Err(())
-        //     }
-        // }
+        /// Return data previously taken with `take`, where `version` is the version when it was taken.
+        /// If the version has changed since then, the data is stale and is put back "invalid" instead of
+        /// "valid". Returns whether the data was still current.
+        pub fn return_data(&mut self, data: T, version: u64) -> bool {
+            debug_assert!(
+                self.chunk.is_none(),
+                "Returned data to a chunk that was not taken"
+            );
+            let current = version == self.version;
+            self.chunk = Some(PresentLayerChunk {
+                data,
+                validity: if current {
+                    Validity::Valid
+                } else {
+                    Validity::Invalid
+                },
+            });
+            current
+        }
     }
 }
 pub use layer_chunk::LayerChunk;
@@ -142,12 +193,213 @@ pub trait TakenChunk: Send {
     /// Return taken data to its original place, usually after chunk loading is done.
     /// May also include additional steps like, for voxel data, setting up a transfer
     /// region to update the chunk data on the GPU.
-    fn return_data(self, grid: &mut Self::MemoryGrid);
+    ///
+    /// Returns false if any of the data was stale (see `LayerChunk::return_data`), in which case
+    /// that data is left invalid and the loader will requeue the chunk if it should still be loaded.
+    fn return_data(self, grid: &mut Self::MemoryGrid) -> bool;
+
+    /// Load the chunk from saved data instead of the load function, e.g. from the voxel memory grid's chunk store
+    /// (see `VoxelMemoryGrid::set_chunk_store`). The loader calls this first on the loading thread and only calls the
+    /// load function if it returns false. Taken chunks that can't be loaded from saved data return false, which is
+    /// the default. Memory grids with other layers (e.g. from `layer_set!`) call this on their voxel layer in their
+    /// load function instead.
+    fn load_stored(&mut self) -> bool {
+        false
+    }
+}
+
+/// Runs on the loading thread right after a chunk is loaded, with the taken chunk and the queue item it was loaded
+/// for. See `ChunkLoader::add_post_processor`.
+pub type PostLoadProcessor<TC, QI> = Arc<dyn Fn(&mut TC, &ChunkLoadQueueItem<QI>) + Send + Sync>;
+
+struct PostLoadProcessors<TC, QI>(Arc<Vec<PostLoadProcessor<TC, QI>>>);
+
+impl<TC, QI> fmt::Debug for PostLoadProcessors<TC, QI> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} post-load processors", self.0.len())
+    }
+}
+
+/// Set by the loader when a chunk that is being loaded is no longer needed because it left the memory grid
+/// (e.g. it scrolled out of the render area). Load functions passed to `ChunkLoader::sync_cancellable` can check
+/// it to stop generating early. The chunk's data is discarded either way, since the memory grid marks chunks that
+/// leave it as stale.
+#[derive(Debug, Clone, Default)]
+pub struct LoadCancelToken(Arc<AtomicBool>);
+
+impl LoadCancelToken {
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// A chunk that is currently loading on one of the loader's worker threads
+#[derive(Debug)]
+struct LoadingThread<QI, BC> {
+    receiver: Receiver<LoadResult<BC>>,
+    item: ChunkLoadQueueItem<QI>, // kept to requeue the chunk if the result is stale
+    cancel: LoadCancelToken,
+}
+
+/// Sent back by a load job once it is done with a chunk
+#[derive(Debug)]
+struct LoadResult<BC> {
+    chunk_data: BC,
+    // The load function or a post processor panicked, so the data may be partially loaded
+    panicked: bool,
+}
+
+type LoadJob = Box<dyn FnOnce() + Send>;
+
+/// Outcome of one chunk in `ChunkLoader::pregenerate`
+#[derive(Debug)]
+enum PregenResult {
+    Generated(Result<(), String>),
+    /// Already in the store
+    Skipped,
+    Cancelled,
+}
+
+/// How long `ChunkLoader::pregenerate` waits for a chunk before checking whether the rate limit allows starting more
+const PREGEN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long dropping a `ChunkLoader` waits for loads that are still running, see `ChunkLoader::shutdown`
+const LOADER_DROP_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Worker threads owned by a `ChunkLoader` that run chunk loads. The loader only submits a job when one of its
+/// slots is free, so there is always a worker available and jobs don't wait behind each other.
+#[derive(Debug)]
+struct LoadThreadPool {
+    jobs: Option<Sender<LoadJob>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl LoadThreadPool {
+    fn new(n_threads: usize) -> Self {
+        let (sender, receiver) = channel::<LoadJob>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..n_threads)
+            .map(|i| {
+                let receiver = Arc::clone(&receiver);
+                thread::Builder::new()
+                    .name(format!("ox-chunk-loader-{}", i))
+                    .spawn(move || loop {
+                        // The lock is released before running the job
+                        let job = receiver.lock().unwrap().recv();
+                        match job {
+                            Ok(job) => job(),
+                            // The loader was dropped
+                            Err(_) => break,
+                        }
+                    })
+                    .unwrap()
+            })
+            .collect();
+        LoadThreadPool {
+            jobs: Some(sender),
+            workers,
+        }
+    }
+
+    fn spawn(&self, job: LoadJob) {
+        self.jobs
+            .as_ref()
+            .expect("Chunk loader thread pool was already stopped")
+            .send(job)
+            .unwrap();
+    }
+
+    /// Stop accepting jobs and let workers exit once they finish their current one. Waits up to `timeout` for
+    /// them to exit and detaches the ones that haven't. Returns the number of detached workers.
+    fn stop(&mut self, timeout: Duration) -> usize {
+        self.jobs = None;
+        let deadline = Instant::now() + timeout;
+        let mut detached = 0;
+        for worker in self.workers.drain(..) {
+            while !worker.is_finished() && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(1));
+            }
+            if worker.is_finished() {
+                let _ = worker.join();
+            } else {
+                detached += 1;
+            }
+        }
+        detached
+    }
+}
+
+/// Result of `ChunkLoader::shutdown`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LoaderShutdownReport {
+    /// Chunks that were still queued and never started loading. These are left invalid.
+    pub discarded_queued: usize,
+    /// Chunks that finished loading during shutdown and were returned to the world
+    pub returned: usize,
+    /// Chunks whose loading did not finish before the timeout. Their data was never returned, so they
+    /// are left missing.
+    pub left_missing: usize,
+}
+
+/// A chunk in a `SavedLoadQueue`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SavedQueueItem<D> {
+    pub pos: [i64; 3],
+    pub priority: u32,
+    pub data: D,
 }
 
+/// Chunks the loader still had to load, from `ChunkLoader::save_queue`. Save it alongside the world (with the
+/// `serde` feature) on exit and pass it to `ChunkLoader::restore_queue` on the next start, so a partially
+/// generated world keeps generating where it left off instead of only where the camera moves next.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SavedLoadQueue<D> {
+    /// Highest priority first
+    pub items: Vec<SavedQueueItem<D>>,
+}
+
+/// How far behind the chunk loader is, from `ChunkLoader::pressure`. Chunk sources can use this to decide
+/// how much work to batch, e.g. reading whole region files when the queue is deep.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoaderPressure {
+    pub queue_depth: usize,
+    pub active_threads: usize,
+    pub n_threads: usize,
+    /// Average number of chunks finished per `sync` over roughly the last `THROUGHPUT_WINDOW` syncs
+    pub throughput: f32,
+}
+
+impl LoaderPressure {
+    /// Estimated number of `sync`s until the current queue is drained, or infinity if nothing is
+    /// finishing (e.g. the loader just started)
+    pub fn syncs_to_drain(&self) -> f32 {
+        if self.queue_depth == 0 {
+            0.
+        } else if self.throughput > 0. {
+            self.queue_depth as f32 / self.throughput
+        } else {
+            f32::INFINITY
+        }
+    }
+
+    /// Whether chunks are waiting in the queue because all loading threads are busy
+    pub fn is_saturated(&self) -> bool {
+        self.queue_depth > 0 && self.active_threads == self.n_threads
+    }
+}
+
+/// Number of syncs `LoaderPressure::throughput` is averaged over
+const THROUGHPUT_WINDOW: f32 = 30.;
+
 #[derive(Debug, Getters, CopyGetters)]
 pub struct ChunkLoader<QI: Eq, BC> {
-    active_threads: Vec<Option<Receiver<BC>>>,
+    active_threads: Vec<Option<LoadingThread<QI, BC>>>,
     #[get = "pub"]
     queue: PriorityQueue<ChunkLoadQueueItem<QI>, u32>,
     #[get_copy = "pub"]
@@ -158,13 +410,104 @@ pub struct ChunkLoader<QI: Eq, BC> {
     skipped_loading_last: usize,
     #[get_copy = "pub"]
     finished_loading_last: usize,
+    /// Chunks that finished loading but were stale and so were discarded (and requeued if needed)
+    #[get_copy = "pub"]
+    stale_loading_last: usize,
+    /// Positions of the chunks whose data was returned to the memory grid in the last `sync`, e.g. to pass to
+    /// `VoxelMemoryGrid::stitch_seams`
+    #[get = "pub"]
+    returned_last: Vec<TlcPos<i64>>,
+    // Set by `shutdown`, after which no more chunks are accepted
+    #[get_copy = "pub"]
+    is_shut_down: bool,
+    /// Chunks whose load was cancelled because they left the memory grid while loading (see `LoadCancelToken`)
+    #[get_copy = "pub"]
+    cancelled_loading_last: usize,
+    /// Chunks whose load panicked (e.g. a bug in a generator). They are left unloaded and not requeued.
+    #[get_copy = "pub"]
+    failed_loading_last: usize,
+    // Exponential moving average of `finished_loading_last`
+    throughput: f32,
+    post_processors: PostLoadProcessors<BC, QI>,
+    pool: LoadThreadPool,
+    rate_limit: Option<LoadTokenBucket>,
 }
 
 pub struct ChunkLoaderParams {
     pub n_threads: usize,
+    /// Cap on how many chunk loads are started per second, independent of `n_threads`. `None` for no cap.
+    pub rate_limit: Option<LoadRateLimit>,
+}
+
+/// Limit on the rate chunk loads are started at, so background streaming doesn't saturate the CPU or disk
+/// (e.g. on a laptop while the player is idle). Queued chunks with at least `immediate_priority` (e.g. ones
+/// right next to the camera) can start up to `burst` loads at once; other chunks are held to `loads_per_sec`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LoadRateLimit {
+    pub loads_per_sec: f32,
+    /// Loads that can be started at once by immediate-priority chunks after the loader has been under its
+    /// limit for a while. At least 1.
+    pub burst: u32,
+    /// With the default `CenterDistance` priority policy, `u32::MAX - 10 * d` includes chunks within `d` TLCs of
+    /// the center of the memory grid (see `PriorityPolicy`).
+    pub immediate_priority: u32,
+}
+
+impl LoadRateLimit {
+    /// Limit with immediate priority for chunks within 2 TLCs of the center of the memory grid
+    pub fn new(loads_per_sec: f32, burst: u32) -> Self {
+        LoadRateLimit {
+            loads_per_sec,
+            burst,
+            immediate_priority: u32::MAX - 20,
+        }
+    }
 }
 
-impl<QI: Eq, BC: TakenChunk> ChunkLoader<QI, BC> {
+/// Token bucket for `LoadRateLimit`. Tokens refill at `loads_per_sec` up to `burst`, and each load that starts
+/// takes one. Only immediate-priority loads can take the last `burst - 1` tokens, so other loads never start
+/// faster than `loads_per_sec` and the burst is kept for when it's needed.
+#[derive(Debug, Clone)]
+struct LoadTokenBucket {
+    limit: LoadRateLimit,
+    tokens: f32,
+    last_refill: Instant,
+}
+
+impl LoadTokenBucket {
+    fn new(limit: LoadRateLimit, now: Instant) -> Self {
+        LoadTokenBucket {
+            limit,
+            tokens: limit.burst.max(1) as f32,
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let dt = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f32();
+        self.tokens =
+            (self.tokens + dt * self.limit.loads_per_sec).min(self.limit.burst.max(1) as f32);
+        self.last_refill = now;
+    }
+
+    fn can_start(&self, priority: u32) -> bool {
+        let reserved = if priority >= self.limit.immediate_priority {
+            0.
+        } else {
+            self.limit.burst.max(1) as f32 - 1.
+        };
+        self.tokens >= reserved + 1.
+    }
+
+    fn take(&mut self) {
+        self.tokens -= 1.;
+    }
+}
+
+impl<QI: Eq, BC> ChunkLoader<QI, BC> {
     pub fn new(params: ChunkLoaderParams) -> Self {
         ChunkLoader {
             active_threads: (0..params.n_threads).map(|_| None).collect(),
@@ -173,14 +516,233 @@ impl<QI: Eq, BC: TakenChunk> ChunkLoader<QI, BC> {
             started_loading_last: 0,
             skipped_loading_last: 0,
             finished_loading_last: 0,
+            stale_loading_last: 0,
+            returned_last: vec![],
+            cancelled_loading_last: 0,
+            failed_loading_last: 0,
+            is_shut_down: false,
+            throughput: 0.,
+            post_processors: PostLoadProcessors(Arc::new(vec![])),
+            pool: LoadThreadPool::new(params.n_threads),
+            rate_limit: params
+                .rate_limit
+                .map(|limit| LoadTokenBucket::new(limit, Instant::now())),
         }
     }
 
+    /// Change the cap on chunk loads started per second, e.g. to stream faster while the player is moving and
+    /// slower while they're idle. `None` removes the cap.
+    pub fn set_rate_limit(&mut self, rate_limit: Option<LoadRateLimit>) {
+        self.rate_limit = rate_limit.map(|limit| LoadTokenBucket::new(limit, Instant::now()));
+    }
+
+    pub fn rate_limit(&self) -> Option<LoadRateLimit> {
+        self.rate_limit.as_ref().map(|bucket| bucket.limit)
+    }
+
+    /// Run `processor` on the loading thread after each chunk is loaded, in the order processors were added.
+    /// It gets the taken chunk, so it can analyze the freshly loaded voxels (e.g. compute a heightmap, count ores,
+    /// build nav data) and store its output in the chunk's other layers without another pass on the main thread.
+    /// Only chunks that start loading after this is called are processed.
+    pub fn add_post_processor(
+        &mut self,
+        processor: impl Fn(&mut BC, &ChunkLoadQueueItem<QI>) + Send + Sync + 'static,
+    ) {
+        Arc::make_mut(&mut self.post_processors.0).push(Arc::new(processor));
+    }
+
+    /// Queue a chunk for loading. Ignored once the loader has been shut down.
     pub fn enqueue(&mut self, chunk: ChunkLoadQueueItem<QI>, priority: u32) {
+        if self.is_shut_down {
+            return;
+        }
         self.queue.push(chunk, priority);
         self.queued_last += 1;
     }
 
+    /// Queue a chunk for loading with the lowest priority, so it only loads once everything else has.
+    /// If the chunk is already queued, it keeps its existing priority and isn't counted in `queued_last`.
+    pub fn enqueue_background(&mut self, chunk: ChunkLoadQueueItem<QI>) {
+        if self.is_shut_down {
+            return;
+        }
+        // `None` if the chunk wasn't queued yet
+        if self.queue.push_increase(chunk, 0).is_none() {
+            self.queued_last += 1;
+        }
+    }
+
+    /// Chunks that are queued or still loading, highest priority first. Chunks that are loading are saved with
+    /// the highest possible priority since they would have finished first. Call this before `shutdown`, which
+    /// discards the queue.
+    pub fn save_queue(&self) -> SavedLoadQueue<QI>
+    where
+        QI: Clone,
+    {
+        let loading = self
+            .active_threads
+            .iter()
+            .flatten()
+            .map(|thread| (&thread.item, u32::MAX));
+        let queued = self.queue.iter().map(|(item, prio)| (item, *prio));
+        let mut items: Vec<_> = loading
+            .chain(queued)
+            .map(|(item, priority)| SavedQueueItem {
+                pos: item.pos.0.into(),
+                priority,
+                data: item.data.clone(),
+            })
+            .collect();
+        items.sort_by_key(|item| std::cmp::Reverse(item.priority));
+        SavedLoadQueue { items }
+    }
+
+    /// Queue the chunks in `saved`, e.g. from the last session. Chunks that are already queued keep the higher of
+    /// the two priorities. Chunks that are no longer in the memory grid or no longer need loading are skipped
+    /// when they come up, like any other queued chunk. Returns how many chunks were queued that weren't already.
+    pub fn restore_queue(&mut self, saved: SavedLoadQueue<QI>) -> usize {
+        if self.is_shut_down {
+            return 0;
+        }
+        let mut n = 0;
+        for item in saved.items {
+            let queue_item = ChunkLoadQueueItem {
+                pos: TlcPos(item.pos.into()),
+                data: item.data,
+            };
+            // `Some` if the chunk was already queued
+            let old_priority = self.queue.push_increase(queue_item, item.priority);
+            if old_priority.is_none() {
+                n += 1;
+            }
+        }
+        self.queued_last += n;
+        n
+    }
+
+    /// Remove any queued chunks at `positions`, e.g. because their data was installed directly with
+    /// `VoxelMemoryGrid::commit_bulk_load`. Chunks that are already loading are not affected.
+    pub fn dequeue(&mut self, positions: &[TlcPos<i64>]) {
+        let positions: HashSet<Point3<i64>> = positions.iter().map(|pos| pos.0).collect();
+        self.queue = std::mem::replace(&mut self.queue, PriorityQueue::new())
+            .into_iter()
+            .filter(|(item, _)| !positions.contains(&item.pos.0))
+            .collect();
+    }
+
+    /// Generate every top level chunk within `radius` of `center` (a cube of side `2 * radius + 1`) with
+    /// `generator` and save it to `store` without a memory grid or renderer, e.g. to prepare a server world before
+    /// players join. A loader that is only used for this can be made with `ChunkLoader::<(), ()>::new`. Chunks that
+    /// are already saved are skipped, so an interrupted pregeneration can be resumed by calling this again.
+    ///
+    /// Chunks are generated on this loader's threads, nearest to `center` first, and are held to its rate limit
+    /// like background chunks. Call this while the loader isn't loading chunks for a world. `progress_cb` is called
+    /// on the calling thread after each chunk finishes and can return false to stop, which cancels the chunks that
+    /// are still generating (they aren't saved). This blocks until all chunks are done or it is stopped.
+    pub fn pregenerate<D, S, G, P>(
+        &mut self,
+        store: Arc<S>,
+        generator: Arc<G>,
+        center: TlcPos<i64>,
+        radius: u32,
+        mut progress_cb: P,
+    ) -> PregenProgress
+    where
+        S: ChunkStore<D> + ?Sized + 'static,
+        G: Fn(TlcPos<i64>) -> D + Send + Sync + 'static,
+        P: FnMut(&PregenProgress) -> bool,
+    {
+        assert!(
+            self.active_loading_threads() == 0,
+            "ChunkLoader::pregenerate called while chunks are loading"
+        );
+        let mut queue = pregen_queue(center, radius);
+        let mut progress = PregenProgress {
+            total: queue.len(),
+            ..Default::default()
+        };
+        if self.is_shut_down || self.active_threads.is_empty() {
+            return progress;
+        }
+
+        let cancel = LoadCancelToken::default();
+        let (sender, receiver) = channel();
+        let mut in_flight = 0;
+        loop {
+            // Start chunks while there are free threads and the rate limit allows
+            while in_flight < self.active_threads.len() && !cancel.is_cancelled() {
+                if let Some(bucket) = self.rate_limit.as_mut() {
+                    bucket.refill(Instant::now());
+                    if !bucket.can_start(0) {
+                        break;
+                    }
+                }
+                let item = match queue.pop() {
+                    None => break,
+                    Some(item) => item,
+                };
+                if let Some(bucket) = self.rate_limit.as_mut() {
+                    bucket.take();
+                }
+                let store = Arc::clone(&store);
+                let generator = Arc::clone(&generator);
+                let job_cancel = cancel.clone();
+                let sender = sender.clone();
+                self.pool.spawn(Box::new(move || {
+                    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                        if job_cancel.is_cancelled() {
+                            return PregenResult::Cancelled;
+                        }
+                        if store.contains(item.pos) {
+                            return PregenResult::Skipped;
+                        }
+                        let data = generator(item.pos);
+                        if job_cancel.is_cancelled() {
+                            return PregenResult::Cancelled;
+                        }
+                        PregenResult::Generated(store.save(item.pos, &data))
+                    }))
+                    .unwrap_or_else(|_| {
+                        PregenResult::Generated(Err(format!(
+                            "generating chunk {:?} panicked",
+                            item.pos.0
+                        )))
+                    });
+                    let _ = sender.send(result);
+                }));
+                in_flight += 1;
+            }
+
+            if in_flight == 0 {
+                if queue.is_empty() || cancel.is_cancelled() {
+                    break;
+                }
+                // Waiting for the rate limit
+                thread::sleep(PREGEN_POLL_INTERVAL);
+                continue;
+            }
+            let result = match receiver.recv_timeout(PREGEN_POLL_INTERVAL) {
+                Ok(result) => result,
+                Err(_) => continue,
+            };
+            in_flight -= 1;
+            match result {
+                PregenResult::Cancelled => continue,
+                PregenResult::Skipped => progress.skipped += 1,
+                PregenResult::Generated(Ok(())) => progress.generated += 1,
+                PregenResult::Generated(Err(e)) => {
+                    println!("WARNING: {}", e);
+                    progress.failed += 1;
+                }
+            }
+            if !progress_cb(&progress) {
+                cancel.cancel();
+            }
+        }
+
+        progress
+    }
+
     pub fn active_loading_threads(&self) -> usize {
         self.active_threads
             .iter()
@@ -188,6 +750,42 @@ impl<QI: Eq, BC: TakenChunk> ChunkLoader<QI, BC> {
             .count()
     }
 
+    pub fn pressure(&self) -> LoaderPressure {
+        LoaderPressure {
+            queue_depth: self.queue.len(),
+            active_threads: self.active_loading_threads(),
+            n_threads: self.active_threads.len(),
+            throughput: self.throughput,
+        }
+    }
+
+    /// Positions of the next `n` chunks that will be loaded, highest priority first
+    pub fn upcoming(&self, n: usize) -> Vec<TlcPos<i64>> {
+        let mut queued: Vec<_> = self.queue.iter().collect();
+        queued.sort_by_key(|(_, prio)| std::cmp::Reverse(**prio));
+        queued
+            .into_iter()
+            .take(n)
+            .map(|(item, _)| item.pos)
+            .collect()
+    }
+
+    /// Pass the chunks that will be loaded next to `source.prefetch` so it can read them ahead of time. The
+    /// number of chunks is based on `pressure`: roughly what will be loaded in the next few syncs, or at most
+    /// `max` when the loader is backed up.
+    pub fn prefetch_upcoming(&self, source: &impl ChunkSource, max: usize) {
+        let pressure = self.pressure();
+        if pressure.queue_depth == 0 {
+            return;
+        }
+        let n = if pressure.is_saturated() {
+            max
+        } else {
+            max.min((pressure.throughput.ceil() as usize).max(pressure.n_threads) * 4)
+        };
+        source.prefetch(&self.upcoming(n));
+    }
+
     pub fn print_status(&self) {
         println!(
             "CHUNK LOADER:  in queue: {},  loading: {}",
@@ -195,8 +793,24 @@ impl<QI: Eq, BC: TakenChunk> ChunkLoader<QI, BC> {
             self.active_loading_threads(),
         );
         println!(
-            "  Last frame: queued: {}, started loading: {}, skipped loading: {}, finished loading: {}",
+            "  Last frame: queued: {}, started loading: {}, skipped loading: {}, finished loading: {}, stale: {}, cancelled: {}, failed: {}",
             self.queued_last, self.started_loading_last, self.skipped_loading_last, self.finished_loading_last,
+            self.stale_loading_last, self.cancelled_loading_last, self.failed_loading_last,
+        );
+    }
+
+    /// Report the counts from the last `sync` and the current queue state to `sink`. Call this once
+    /// after each `sync`.
+    pub fn report_metrics(&self, sink: &mut impl MetricsSink) {
+        sink.increment_counter(names::CHUNKS_LOADED, self.finished_loading_last as u64);
+        sink.increment_counter(names::CHUNKS_SKIPPED, self.skipped_loading_last as u64);
+        sink.increment_counter(names::CHUNKS_STALE, self.stale_loading_last as u64);
+        sink.increment_counter(names::CHUNKS_CANCELLED, self.cancelled_loading_last as u64);
+        sink.increment_counter(names::CHUNKS_FAILED, self.failed_loading_last as u64);
+        sink.set_gauge(names::QUEUE_DEPTH, self.queue.len() as f64);
+        sink.set_gauge(
+            names::ACTIVE_LOADING_THREADS,
+            self.active_loading_threads() as f64,
         );
     }
 }
@@ -220,34 +834,140 @@ where
         LP: Clone + Send + 'static,
         F: Fn(&mut TC, ChunkLoadQueueItem<QI>, LP) + Sync,
     {
+        self.sync_impl(
+            world,
+            move |chunk: &mut TC, item, lp, _: &LoadCancelToken| load(chunk, item, lp),
+            load_params,
+        )
+    }
+
+    /// Like `sync`, but `load` also gets the chunk's `LoadCancelToken` so it can stop early when the chunk leaves
+    /// the memory grid while it is loading (e.g. check it between generation passes).
+    pub fn sync_cancellable<F, LP, M>(
+        &mut self,
+        world: &mut World<TC::MemoryGrid>,
+        load: &'static F,
+        load_params: LP,
+    ) where
+        TC::MemoryGrid: EditMemoryGridChunk<M>,
+        for<'a> <TC::MemoryGrid as EditMemoryGridChunk<M>>::ChunkEditor<'a>:
+            TakeChunkForLoading<TC, QI>,
+        LP: Clone + Send + 'static,
+        F: Fn(&mut TC, ChunkLoadQueueItem<QI>, LP, &LoadCancelToken) + Sync,
+    {
+        self.sync_impl(world, load, load_params)
+    }
+
+    fn sync_impl<F, LP, M>(&mut self, world: &mut World<TC::MemoryGrid>, load: F, load_params: LP)
+    where
+        TC::MemoryGrid: EditMemoryGridChunk<M>,
+        for<'a> <TC::MemoryGrid as EditMemoryGridChunk<M>>::ChunkEditor<'a>:
+            TakeChunkForLoading<TC, QI>,
+        LP: Clone + Send + 'static,
+        F: Fn(&mut TC, ChunkLoadQueueItem<QI>, LP, &LoadCancelToken) + Copy + Send + 'static,
+    {
+        world
+            .owner_thread()
+            .debug_assert_current("ChunkLoader::sync");
+
         self.queued_last = 0;
         self.started_loading_last = 0;
         self.skipped_loading_last = 0;
         self.finished_loading_last = 0;
+        self.stale_loading_last = 0;
+        self.returned_last.clear();
+        self.cancelled_loading_last = 0;
+        self.failed_loading_last = 0;
+
+        // Cancel loads of chunks that left the memory grid. Their data was marked stale when they left, so it
+        // will be discarded when it is returned.
+        for thread in self.active_threads.iter().flatten() {
+            if !thread.cancel.is_cancelled() && world.edit_chunk::<M>(thread.item.pos).is_none() {
+                thread.cancel.cancel();
+                self.cancelled_loading_last += 1;
+            }
+        }
 
         // Receive chunks that have finished loading and return their data to `world`
+        let mut stale = vec![];
+        let mut failed = vec![];
         for thread_slot in self.active_threads.iter_mut() {
-            if let Some(receiver) = thread_slot {
-                match receiver.try_recv() {
-                    Ok(chunk_data) => {
+            if let Some(thread) = thread_slot {
+                match thread.receiver.try_recv() {
+                    Ok(LoadResult {
+                        chunk_data,
+                        panicked: false,
+                    }) => {
                         self.finished_loading_last += 1;
+                        let item = thread_slot.take().unwrap().item;
+                        if chunk_data.return_data(&mut world.mem_grid) {
+                            self.returned_last.push(item.pos);
+                        } else {
+                            self.stale_loading_last += 1;
+                            stale.push(item);
+                        }
+                    }
+                    Ok(LoadResult {
+                        chunk_data,
+                        panicked: true,
+                    }) => {
+                        // Return the data so the chunk's memory isn't lost, but it is marked invalid again below
+                        // since it may be partially loaded
+                        let item = thread_slot.take().unwrap().item;
                         chunk_data.return_data(&mut world.mem_grid);
-                        *thread_slot = None;
+                        failed.push(item.pos);
                     }
                     Err(TryRecvError::Disconnected) => {
-                        panic!("Thread disconnected before completing.")
+                        // Only happens if the job itself died, in which case the chunk's data is gone
+                        let item = thread_slot.take().unwrap().item;
+                        failed.push(item.pos);
                     }
                     Err(TryRecvError::Empty) => {}
                 }
             }
         }
+        for pos in failed {
+            self.failed_loading_last += 1;
+            if let Some(mut chunk) = world.edit_chunk::<M>(pos) {
+                let _ = chunk.mark_invalid();
+            }
+            println!(
+                "WARNING: loading chunk {:?} panicked, it was left unloaded",
+                pos.0
+            );
+        }
+        self.throughput +=
+            (self.finished_loading_last as f32 - self.throughput) / THROUGHPUT_WINDOW;
+
+        // The chunks that stale results were for changed while loading, so load them again if they are
+        // still in the memory grid and need it
+        for item in stale {
+            let still_load = world
+                .edit_chunk(item.pos)
+                .map_or(false, |chunk| chunk.should_still_load(&item.data));
+            if still_load {
+                let priority = world.chunk_loading_priority(item.pos);
+                self.enqueue(item, priority);
+            }
+        }
 
         // Enqueue new chunks for loading until queue is empty or there are no thread slots left
-        if !self.queue.is_empty() {
+        if !self.queue.is_empty() && !world.streaming_paused() {
+            if let Some(bucket) = self.rate_limit.as_mut() {
+                bucket.refill(Instant::now());
+            }
+            let post_processors = &self.post_processors.0;
             let mut requeue = vec![]; // chunks to try again next frame
             'threads: for thread_slot in self.active_threads.iter_mut() {
                 if thread_slot.is_none() {
                     loop {
+                        if let (Some(bucket), Some((_, prio))) =
+                            (self.rate_limit.as_ref(), self.queue.peek())
+                        {
+                            if !bucket.can_start(*prio) {
+                                break 'threads;
+                            }
+                        }
                         let (item, prio) = match self.queue.pop() {
                             None => break 'threads,
                             Some(x) => x,
@@ -261,21 +981,54 @@ where
                                 match chunk.mark_invalid() {
                                     Ok(()) => {
                                         self.started_loading_last += 1;
+                                        if let Some(bucket) = self.rate_limit.as_mut() {
+                                            bucket.take();
+                                        }
                                         let mut chunk_data =
                                             chunk.take_data_for_loading(&item.data);
                                         let lp = load_params.clone();
-                                        thread::spawn(|| {
-                                            let sender = sender; // move
-                                            load(&mut chunk_data, item, lp);
-                                            sender.send(chunk_data).unwrap_or_else(|e| {
-                                                panic!(
-                                                    "Failed to send loaded chunk back to main thread: {}",
-                                                    e
-                                                )
+                                        let queued_item = item.clone();
+                                        let processors = Arc::clone(post_processors);
+                                        let processed_item =
+                                            (!processors.is_empty()).then(|| item.clone());
+                                        let cancel = LoadCancelToken::default();
+                                        let job_cancel = cancel.clone();
+                                        self.pool.spawn(Box::new(move || {
+                                            let load_chunk = |chunk_data: &mut TC| {
+                                                // Cancelled chunks are discarded, so don't bother loading them
+                                                if job_cancel.is_cancelled() {
+                                                    return;
+                                                }
+                                                // Saved chunks are read instead of loaded
+                                                if !chunk_data.load_stored() {
+                                                    load(chunk_data, item, lp, &job_cancel);
+                                                }
+                                                if let Some(item) = processed_item {
+                                                    for processor in processors.iter() {
+                                                        processor(chunk_data, &item);
+                                                    }
+                                                }
+                                            };
+                                            // Catch panics so that they don't take down the worker or the
+                                            // chunk's data with them
+                                            let panicked =
+                                                panic::catch_unwind(AssertUnwindSafe(|| {
+                                                    load_chunk(&mut chunk_data)
+                                                }))
+                                                .is_err();
+                                            // If this fails, the loader was shut down or dropped without
+                                            // waiting for this chunk, so the data is just discarded.
+                                            let _ = sender.send(LoadResult {
+                                                chunk_data,
+                                                panicked,
                                             });
-                                        });
+                                        }));
 
-                                        *thread_slot = Some(receiver);
+                                        *thread_slot = Some(LoadingThread {
+                                            receiver,
+                                            item: queued_item,
+                                            cancel,
+                                        });
                                         break;
                                     }
                                     Err(()) => {
@@ -305,6 +1058,83 @@ where
             }
         }
     }
+
+    /// Stop accepting new chunks, discard the queue, and wait up to `timeout` for chunks that are
+    /// currently loading, returning their data to `world`. Loading threads that don't finish in time are
+    /// detached and their chunks are left missing. Call this before exiting instead of just dropping the
+    /// loader so that in-progress chunks aren't lost mid-load. To continue loading the discarded chunks in
+    /// the next session, call `save_queue` first.
+    pub fn shutdown(
+        &mut self,
+        world: &mut World<TC::MemoryGrid>,
+        timeout: Duration,
+    ) -> LoaderShutdownReport {
+        world
+            .owner_thread()
+            .debug_assert_current("ChunkLoader::shutdown");
+
+        self.is_shut_down = true;
+        let mut report = LoaderShutdownReport {
+            discarded_queued: self.queue.len(),
+            ..Default::default()
+        };
+        self.queue.clear();
+
+        let deadline = Instant::now() + timeout;
+        for thread_slot in self.active_threads.iter_mut() {
+            if let Some(thread) = thread_slot.take() {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                match thread.receiver.recv_timeout(remaining) {
+                    Ok(result) => {
+                        // Stale results aren't requeued since the loader is shutting down. Data from a load
+                        // that panicked is returned too so the chunk's memory isn't lost.
+                        result.chunk_data.return_data(&mut world.mem_grid);
+                        report.returned += 1;
+                    }
+                    Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => {
+                        // Let the load stop early since its result will be discarded
+                        thread.cancel.cancel();
+                        report.left_missing += 1;
+                    }
+                }
+            }
+        }
+
+        // Workers that are still loading are detached
+        self.pool.stop(if report.left_missing == 0 {
+            deadline.saturating_duration_since(Instant::now())
+        } else {
+            Duration::ZERO
+        });
+
+        if report.left_missing > 0 {
+            println!(
+                "WARNING: chunk loader shut down with {} chunks still loading; they were left missing",
+                report.left_missing
+            );
+        }
+
+        report
+    }
+}
+
+impl<QI: Eq, BC> Drop for ChunkLoader<QI, BC> {
+    fn drop(&mut self) {
+        // Dropping the receivers makes loading threads discard their chunk instead of waiting to send it, and
+        // cancelling lets loads that check their token stop early. Then wait a bounded time for the workers to
+        // finish, so a load that doesn't check its token can't hang the drop (e.g. on exit).
+        for thread in self.active_threads.drain(..).flatten() {
+            thread.cancel.cancel();
+            drop(thread.receiver);
+        }
+        let detached = self.pool.stop(LOADER_DROP_TIMEOUT);
+        if detached > 0 {
+            println!(
+                "WARNING: chunk loader dropped with {} loads still running; their threads were detached",
+                detached
+            );
+        }
+    }
 }
 
 #[cfg(test)]
@@ -318,6 +1148,7 @@ mod tests {
     use cgmath::{Array, Point3, Vector3};
 
     use crate::world::mem_grid::layer::{DefaultLayerChunkEditor, MemoryGridLayer};
+    use crate::world::storage::InMemoryChunkStore;
 
     use super::*;
 
@@ -327,6 +1158,7 @@ mod tests {
     struct TakenTestChunkEditor {
         data: bool,
         chunk_idx: usize,
+        version: u64,
     }
 
     impl<'a> TakeChunkForLoading<TakenTestChunkEditor, ()>
@@ -342,6 +1174,7 @@ mod tests {
 
         fn take_data_for_loading(&mut self, _: &()) -> TakenTestChunkEditor {
             TakenTestChunkEditor {
+                version: self.chunk.version(),
                 data: self.chunk.take().unwrap(),
                 chunk_idx: self.chunk_idx,
             }
@@ -351,8 +1184,8 @@ mod tests {
     impl TakenChunk for TakenTestChunkEditor {
         type MemoryGrid = TestMemoryGrid;
 
-        fn return_data(self, grid: &mut Self::MemoryGrid) {
-            grid.chunks_mut()[self.chunk_idx] = LayerChunk::new_valid(self.data)
+        fn return_data(self, grid: &mut Self::MemoryGrid) -> bool {
+            grid.chunks_mut()[self.chunk_idx].return_data(self.data, self.version)
         }
     }
 
@@ -378,7 +1211,10 @@ mod tests {
             (),
         );
         let mut world = World::new(mg, Camera::new(8, MG_SIZE), 8, 3);
-        let mut loader = ChunkLoader::new(ChunkLoaderParams { n_threads: 1 });
+        let mut loader = ChunkLoader::new(ChunkLoaderParams {
+            n_threads: 1,
+            rate_limit: None,
+        });
         // Load upper buffer chunks
         world.move_camera(
             &mut TestCameraController,
@@ -460,7 +1296,10 @@ mod tests {
         );
         let v = 2; // this doesn't matter
         let mut world = World::new(mg, Camera::new(v, MG_SIZE), v, v as u32);
-        let mut loader = ChunkLoader::new(ChunkLoaderParams { n_threads: 1 });
+        let mut loader = ChunkLoader::new(ChunkLoaderParams {
+            n_threads: 1,
+            rate_limit: None,
+        });
 
         fn load_f(editor: &mut TakenTestChunkEditor, _: ChunkLoadQueueItem<()>, _: ()) {
             assert!(!editor.data);
@@ -509,4 +1348,638 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_streaming_paused() {
+        let start_tlc = TlcPos(
+            Point3::<i64> { x: 0, y: 0, z: 0 } - Vector3::from_value(MG_SIZE as i64 / 2 - 1),
+        );
+        let mg = TestMemoryGrid::new(
+            (0..MG_SIZE * MG_SIZE * MG_SIZE)
+                .map(|_| LayerChunk::new(false))
+                .collect(),
+            start_tlc,
+            MG_SIZE,
+            (),
+            (),
+        );
+        let mut world = World::new(mg, Camera::new(8, MG_SIZE), 8, 3);
+        let mut loader = ChunkLoader::new(ChunkLoaderParams {
+            n_threads: 1,
+            rate_limit: None,
+        });
+
+        fn load_f(editor: &mut TakenTestChunkEditor, _: ChunkLoadQueueItem<()>, _: ()) {
+            editor.data = true;
+        }
+
+        world.set_streaming_paused(true);
+
+        // Grid should not shift or load buffer chunks while paused
+        world.move_camera(
+            &mut TestCameraController,
+            Duration::from_secs(0),
+            &mut loader,
+        );
+        assert_eq!(
+            *world.metadata().buffer_chunk_states(),
+            [BufferChunkState::Unloaded; 3]
+        );
+        assert!(loader.queue().is_empty());
+
+        let pos = TlcPos(Point3::<i64>::from_value(0));
+        loader.enqueue(ChunkLoadQueueItem { data: (), pos }, 1);
+        loader.sync(&mut world, &load_f, ());
+        assert_eq!(loader.started_loading_last(), 0);
+        assert_eq!(loader.queue().len(), 1);
+
+        world.set_streaming_paused(false);
+        loader.sync(&mut world, &load_f, ());
+        assert_eq!(loader.started_loading_last(), 1);
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let start_tlc = TlcPos(
+            Point3::<i64> { x: 0, y: 0, z: 0 } - Vector3::from_value(MG_SIZE as i64 / 2 - 1),
+        );
+        let mg = TestMemoryGrid::new(
+            (0..MG_SIZE * MG_SIZE * MG_SIZE)
+                .map(|_| LayerChunk::new(false))
+                .collect(),
+            start_tlc,
+            MG_SIZE,
+            (),
+            (),
+        );
+        let mut world = World::new(mg, Camera::new(8, MG_SIZE), 8, 3);
+        let mut loader = ChunkLoader::new(ChunkLoaderParams {
+            n_threads: 2,
+            rate_limit: None,
+        });
+
+        fn load_f(editor: &mut TakenTestChunkEditor, _: ChunkLoadQueueItem<()>, _: ()) {
+            editor.data = true;
+        }
+
+        for x in 0..4 {
+            let pos = TlcPos(Point3 { x, y: 0, z: 0 });
+            loader.enqueue(ChunkLoadQueueItem { data: (), pos }, 1);
+        }
+        loader.sync(&mut world, &load_f, ());
+        assert_eq!(loader.started_loading_last(), 2);
+
+        let report = world.shutdown(&mut loader, Duration::from_secs(10));
+        assert_eq!(
+            report,
+            LoaderShutdownReport {
+                discarded_queued: 2,
+                returned: 2,
+                left_missing: 0,
+            }
+        );
+        assert!(loader.is_shut_down());
+        assert_eq!(loader.active_loading_threads(), 0);
+
+        // No more chunks are accepted after shutdown
+        loader.enqueue(
+            ChunkLoadQueueItem {
+                data: (),
+                pos: TlcPos(Point3::from_value(0)),
+            },
+            1,
+        );
+        assert!(loader.queue().is_empty());
+    }
+
+    #[test]
+    fn test_save_and_restore_queue() {
+        let start_tlc = TlcPos(
+            Point3::<i64> { x: 0, y: 0, z: 0 } - Vector3::from_value(MG_SIZE as i64 / 2 - 1),
+        );
+        let mg = TestMemoryGrid::new(
+            (0..MG_SIZE * MG_SIZE * MG_SIZE)
+                .map(|_| LayerChunk::new(false))
+                .collect(),
+            start_tlc,
+            MG_SIZE,
+            (),
+            (),
+        );
+        let mut world = World::new(mg, Camera::new(8, MG_SIZE), 8, 3);
+        let mut loader = ChunkLoader::new(ChunkLoaderParams {
+            n_threads: 1,
+            rate_limit: None,
+        });
+
+        fn load_f(editor: &mut TakenTestChunkEditor, _: ChunkLoadQueueItem<()>, _: ()) {
+            editor.data = true;
+        }
+
+        for x in 0..3 {
+            let pos = TlcPos(Point3 { x, y: 0, z: 0 });
+            loader.enqueue(ChunkLoadQueueItem { data: (), pos }, 1 + x as u32);
+        }
+        loader.sync(&mut world, &load_f, ());
+        assert_eq!(loader.active_loading_threads(), 1);
+
+        // The chunk that is loading comes first, then the rest by priority
+        let saved = loader.save_queue();
+        let saved_xs: Vec<_> = saved.items.iter().map(|item| item.pos[0]).collect();
+        assert_eq!(saved_xs, vec![2, 1, 0]);
+        assert_eq!(saved.items[0].priority, u32::MAX);
+        world.shutdown(&mut loader, Duration::from_secs(10));
+
+        let mut loader: ChunkLoader<(), TakenTestChunkEditor> =
+            ChunkLoader::new(ChunkLoaderParams {
+                n_threads: 1,
+                rate_limit: None,
+            });
+        let pos = TlcPos(Point3 { x: 1, y: 0, z: 0 });
+        loader.enqueue(ChunkLoadQueueItem { data: (), pos }, 10);
+        // x = 1 was already queued, so it only gets the higher priority
+        assert_eq!(loader.restore_queue(saved), 2);
+        assert_eq!(loader.queued_last(), 3);
+        assert_eq!(loader.queue().len(), 3);
+        assert_eq!(
+            loader.upcoming(3),
+            vec![
+                TlcPos(Point3 { x: 2, y: 0, z: 0 }),
+                pos,
+                TlcPos(Point3 { x: 0, y: 0, z: 0 }),
+            ]
+        );
+
+        loader.dequeue(&[pos, TlcPos(Point3 { x: 5, y: 0, z: 0 })]);
+        assert_eq!(
+            loader.upcoming(3),
+            vec![
+                TlcPos(Point3 { x: 2, y: 0, z: 0 }),
+                TlcPos(Point3 { x: 0, y: 0, z: 0 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stale_load_requeued() {
+        let start_tlc = TlcPos(
+            Point3::<i64> { x: 0, y: 0, z: 0 } - Vector3::from_value(MG_SIZE as i64 / 2 - 1),
+        );
+        let mg = TestMemoryGrid::new(
+            (0..MG_SIZE * MG_SIZE * MG_SIZE)
+                .map(|_| LayerChunk::new(false))
+                .collect(),
+            start_tlc,
+            MG_SIZE,
+            (),
+            (),
+        );
+        let mut world = World::new(mg, Camera::new(8, MG_SIZE), 8, 3);
+        let mut loader = ChunkLoader::new(ChunkLoaderParams {
+            n_threads: 1,
+            rate_limit: None,
+        });
+
+        fn load_f(editor: &mut TakenTestChunkEditor, _: ChunkLoadQueueItem<()>, _: ()) {
+            editor.data = true;
+        }
+
+        let pos = TlcPos(Point3::<i64>::from_value(0));
+        loader.enqueue(ChunkLoadQueueItem { data: (), pos }, 1);
+        loader.sync(&mut world, &load_f, ());
+        assert_eq!(loader.started_loading_last(), 1);
+
+        // Chunk changes while its load is in flight, so the result should be discarded and the chunk reloaded
+        world.mem_grid.invalidate_region(pos, pos);
+
+        let mut stale = 0;
+        let mut returned = vec![];
+        while loader.active_loading_threads() > 0 {
+            loader.sync(&mut world, &load_f, ());
+            stale += loader.stale_loading_last();
+            returned.extend_from_slice(loader.returned_last());
+        }
+        assert_eq!(stale, 1);
+        // Only the reload that wasn't stale is returned
+        assert_eq!(returned, vec![pos]);
+        assert!(world.edit_chunk(pos).unwrap().chunk.get().unwrap());
+    }
+
+    #[test]
+    fn test_load_panic() {
+        let start_tlc = TlcPos(
+            Point3::<i64> { x: 0, y: 0, z: 0 } - Vector3::from_value(MG_SIZE as i64 / 2 - 1),
+        );
+        let mg = TestMemoryGrid::new(
+            (0..MG_SIZE * MG_SIZE * MG_SIZE)
+                .map(|_| LayerChunk::new(false))
+                .collect(),
+            start_tlc,
+            MG_SIZE,
+            (),
+            (),
+        );
+        let mut world = World::new(mg, Camera::new(8, MG_SIZE), 8, 3);
+        let mut loader = ChunkLoader::new(ChunkLoaderParams {
+            n_threads: 1,
+            rate_limit: None,
+        });
+
+        let bad_pos = TlcPos(Point3::<i64>::from_value(0));
+        let good_pos = TlcPos(Point3::<i64>::from_value(1));
+
+        // Generator with a bug for one chunk
+        fn load_f(editor: &mut TakenTestChunkEditor, item: ChunkLoadQueueItem<()>, _: ()) {
+            if item.pos == TlcPos(Point3::from_value(0)) {
+                panic!("generator bug");
+            }
+            editor.data = true;
+        }
+
+        loader.enqueue(
+            ChunkLoadQueueItem {
+                data: (),
+                pos: bad_pos,
+            },
+            2,
+        );
+        loader.enqueue(
+            ChunkLoadQueueItem {
+                data: (),
+                pos: good_pos,
+            },
+            1,
+        );
+
+        // The only worker should survive the panic and go on to load the other chunk
+        let mut failed = 0;
+        let mut returned = vec![];
+        while loader.active_loading_threads() > 0 || !loader.queue().is_empty() {
+            loader.sync(&mut world, &load_f, ());
+            failed += loader.failed_loading_last();
+            returned.extend_from_slice(loader.returned_last());
+        }
+        assert_eq!(failed, 1);
+        assert_eq!(returned, vec![good_pos]);
+        assert!(world.edit_chunk(good_pos).unwrap().chunk.get().unwrap());
+        // The failed chunk's data is back in the grid but still invalid
+        assert!(world.edit_chunk(bad_pos).unwrap().chunk.get().is_none());
+        assert!(world.edit_chunk(bad_pos).unwrap().mark_invalid().is_ok());
+    }
+
+    #[test]
+    fn test_pregenerate() {
+        let store = Arc::new(InMemoryChunkStore::new());
+        let center = TlcPos(Point3::new(10, 0, -3));
+        store.save(center, &0).unwrap();
+
+        let mut loader = ChunkLoader::<(), ()>::new(ChunkLoaderParams {
+            n_threads: 4,
+            rate_limit: None,
+        });
+        let generator = Arc::new(|pos: TlcPos<i64>| pos.0.x);
+        let mut calls = 0;
+        let progress = Arc::clone(&store).pregenerate(&mut loader, generator, center, 2, |_| {
+            calls += 1;
+            true
+        });
+
+        assert_eq!(
+            progress,
+            PregenProgress {
+                total: 125,
+                generated: 124,
+                skipped: 1,
+                failed: 0,
+            }
+        );
+        assert_eq!(calls, 125);
+        assert_eq!(store.len(), 125);
+        assert_eq!(store.take(TlcPos(Point3::new(12, 2, -1))), Some(12));
+        // Already stored chunk wasn't regenerated
+        assert_eq!(store.take(center), Some(0));
+    }
+
+    #[test]
+    fn test_pregenerate_stop() {
+        let store = Arc::new(InMemoryChunkStore::new());
+        let mut loader = ChunkLoader::<(), ()>::new(ChunkLoaderParams {
+            n_threads: 2,
+            rate_limit: None,
+        });
+        let center = TlcPos(Point3::new(0, 0, 0));
+        let progress = loader.pregenerate(
+            Arc::clone(&store),
+            Arc::new(|pos: TlcPos<i64>| pos.0.x),
+            center,
+            2,
+            |progress| progress.done() < 10,
+        );
+
+        // Chunks that were still generating when it stopped are cancelled rather than saved
+        assert!(progress.done() >= 10 && progress.done() < progress.total);
+        assert_eq!(store.len(), progress.generated);
+        // The loader's threads are still usable afterwards
+        let progress = loader.pregenerate(store, Arc::new(|_: TlcPos<i64>| 0), center, 2, |_| true);
+        assert_eq!(progress.done(), 125);
+    }
+
+    #[test]
+    fn test_invalidate_region() {
+        let start_tlc = TlcPos(
+            Point3::<i64> { x: 0, y: 0, z: 0 } - Vector3::from_value(MG_SIZE as i64 / 2 - 1),
+        );
+        let mg = TestMemoryGrid::new(
+            (0..MG_SIZE * MG_SIZE * MG_SIZE)
+                .map(|_| LayerChunk::new_valid(false))
+                .collect(),
+            start_tlc,
+            MG_SIZE,
+            (),
+            (),
+        );
+        let mut world = World::new(mg, Camera::new(8, MG_SIZE), 8, 3);
+        let mut loader = ChunkLoader::new(ChunkLoaderParams {
+            n_threads: 1,
+            rate_limit: None,
+        });
+
+        fn load_f(editor: &mut TakenTestChunkEditor, _: ChunkLoadQueueItem<()>, _: ()) {
+            editor.data = true;
+        }
+
+        // One chunk in the region is already loading when it is invalidated
+        let loading = TlcPos(Point3::<i64>::from_value(0));
+        world
+            .edit_chunk(loading)
+            .unwrap()
+            .chunk
+            .set_invalid()
+            .unwrap();
+        loader.enqueue(
+            ChunkLoadQueueItem {
+                data: (),
+                pos: loading,
+            },
+            1,
+        );
+        loader.sync(&mut world, &load_f, ());
+        assert_eq!(loader.started_loading_last(), 1);
+
+        // The region extends past the end of the loaded area
+        let max_tlc = TlcPos(Point3::new(1, 0, MG_SIZE as i64));
+        let mut invalidated = world.invalidate_region(loading, max_tlc, &mut loader);
+        invalidated.sort_by_key(|pos| (pos.0.x, pos.0.y, pos.0.z));
+        let expected: Vec<_> = (0..2)
+            .flat_map(|x| (0..MG_SIZE as i64 / 2).map(move |z| TlcPos(Point3::new(x, 0, z))))
+            .collect();
+        assert_eq!(invalidated, expected);
+        assert!(world
+            .edit_chunk(TlcPos(Point3::new(1, 0, 0)))
+            .unwrap()
+            .chunk
+            .get()
+            .is_none());
+
+        let mut stale = 0;
+        while loader.active_loading_threads() > 0 || !loader.queue().is_empty() {
+            loader.sync(&mut world, &load_f, ());
+            stale += loader.stale_loading_last();
+        }
+        // The load that was in flight is discarded and the chunk is loaded again
+        assert_eq!(stale, 1);
+        for pos in expected {
+            assert!(world.edit_chunk(pos).unwrap().chunk.get().unwrap());
+        }
+    }
+
+    #[test]
+    fn test_cancel_load_out_of_grid() {
+        let start_tlc = TlcPos(
+            Point3::<i64> { x: 0, y: 0, z: 0 } - Vector3::from_value(MG_SIZE as i64 / 2 - 1),
+        );
+        let mg = TestMemoryGrid::new(
+            (0..MG_SIZE * MG_SIZE * MG_SIZE)
+                .map(|_| LayerChunk::new(false))
+                .collect(),
+            start_tlc,
+            MG_SIZE,
+            (),
+            (),
+        );
+        let mut world = World::new(mg, Camera::new(8, MG_SIZE), 8, 3);
+        let mut loader = ChunkLoader::new(ChunkLoaderParams {
+            n_threads: 1,
+            rate_limit: None,
+        });
+
+        static CANCELLED: AtomicBool = AtomicBool::new(false);
+        const CORNER: i64 = -(MG_SIZE as i64) / 2 + 1;
+
+        // Loads of the corner chunk run until they are cancelled
+        fn load_f(
+            editor: &mut TakenTestChunkEditor,
+            item: ChunkLoadQueueItem<()>,
+            _: (),
+            cancel: &LoadCancelToken,
+        ) {
+            if item.pos.0 == Point3::from_value(CORNER) {
+                let deadline = Instant::now() + Duration::from_secs(5);
+                while !cancel.is_cancelled() && Instant::now() < deadline {
+                    thread::yield_now();
+                }
+                CANCELLED.store(cancel.is_cancelled(), Ordering::Relaxed);
+            }
+            editor.data = true;
+        }
+
+        let pos = TlcPos(Point3::from_value(CORNER));
+        loader.enqueue(ChunkLoadQueueItem { data: (), pos }, 1);
+        loader.sync_cancellable(&mut world, &load_f, ());
+        assert_eq!(loader.started_loading_last(), 1);
+
+        // Move the camera away until the corner chunk leaves the memory grid
+        while world.edit_chunk(pos).is_some() {
+            world.move_camera(
+                &mut TestCameraController,
+                Duration::from_secs(0),
+                &mut loader,
+            );
+        }
+
+        let mut cancelled = 0;
+        let mut stale = 0;
+        while stale == 0 {
+            loader.sync_cancellable(&mut world, &load_f, ());
+            cancelled += loader.cancelled_loading_last();
+            stale += loader.stale_loading_last();
+        }
+        assert_eq!(cancelled, 1);
+        assert!(CANCELLED.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn test_post_processors() {
+        let start_tlc = TlcPos(
+            Point3::<i64> { x: 0, y: 0, z: 0 } - Vector3::from_value(MG_SIZE as i64 / 2 - 1),
+        );
+        let mg = TestMemoryGrid::new(
+            (0..MG_SIZE * MG_SIZE * MG_SIZE)
+                .map(|_| LayerChunk::new(false))
+                .collect(),
+            start_tlc,
+            MG_SIZE,
+            (),
+            (),
+        );
+        let mut world = World::new(mg, Camera::new(8, MG_SIZE), 8, 3);
+        let mut loader = ChunkLoader::new(ChunkLoaderParams {
+            n_threads: 2,
+            rate_limit: None,
+        });
+
+        fn load_f(editor: &mut TakenTestChunkEditor, _: ChunkLoadQueueItem<()>, _: ()) {
+            editor.data = true;
+        }
+
+        // Processors run in order after the chunk is loaded and their changes are returned with the chunk
+        let processed = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        loader.add_post_processor(|editor: &mut TakenTestChunkEditor, _| {
+            assert!(editor.data);
+            editor.data = false;
+        });
+        let counter = Arc::clone(&processed);
+        loader.add_post_processor(move |editor: &mut TakenTestChunkEditor, _| {
+            assert!(!editor.data);
+            counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        });
+
+        let positions: Vec<_> = (0..3)
+            .map(|x| TlcPos(Point3::<i64> { x, y: 0, z: 0 }))
+            .collect();
+        for pos in positions.iter() {
+            loader.enqueue(
+                ChunkLoadQueueItem {
+                    data: (),
+                    pos: *pos,
+                },
+                1,
+            );
+        }
+        loader.sync(&mut world, &load_f, ());
+        while loader.active_loading_threads() > 0 || !loader.queue().is_empty() {
+            loader.sync(&mut world, &load_f, ());
+        }
+
+        assert_eq!(processed.load(std::sync::atomic::Ordering::Relaxed), 3);
+        for pos in positions {
+            assert_eq!(world.edit_chunk(pos).unwrap().chunk.get(), Some(&false));
+        }
+    }
+
+    #[test]
+    fn test_prefetch_upcoming() {
+        struct RecordingSource(std::cell::RefCell<Vec<TlcPos<i64>>>);
+        impl ChunkSource for RecordingSource {
+            fn chunk_positions(&self) -> Vec<TlcPos<i64>> {
+                vec![]
+            }
+            fn voxel_ids(&self, _: TlcPos<i64>) -> Option<Vec<u8>> {
+                None
+            }
+            fn prefetch(&self, positions: &[TlcPos<i64>]) {
+                self.0.borrow_mut().extend_from_slice(positions);
+            }
+        }
+
+        let mut loader: ChunkLoader<(), TakenTestChunkEditor> =
+            ChunkLoader::new(ChunkLoaderParams {
+                n_threads: 1,
+                rate_limit: None,
+            });
+        let source = RecordingSource(Default::default());
+        loader.prefetch_upcoming(&source, 8);
+        assert!(source.0.borrow().is_empty());
+
+        for x in 0..10 {
+            let pos = TlcPos(Point3 { x, y: 0, z: 0 });
+            loader.enqueue(ChunkLoadQueueItem { data: (), pos }, x as u32);
+        }
+        let pressure = loader.pressure();
+        assert_eq!(pressure.queue_depth, 10);
+        assert!(!pressure.is_saturated());
+        assert_eq!(pressure.syncs_to_drain(), f32::INFINITY);
+
+        // Nothing has finished loading yet, so it prefetches a few chunks per thread
+        loader.prefetch_upcoming(&source, 8);
+        let prefetched: Vec<_> = source.0.borrow().iter().map(|pos| pos.0.x).collect();
+        assert_eq!(prefetched, vec![9, 8, 7, 6]);
+    }
+
+    #[test]
+    fn test_load_token_bucket() {
+        let start = Instant::now();
+        let mut bucket = LoadTokenBucket::new(LoadRateLimit::new(2., 4), start);
+        let background = 0;
+        let immediate = u32::MAX;
+
+        // Background loads can't use the burst
+        assert!(bucket.can_start(background));
+        bucket.take();
+        assert!(!bucket.can_start(background));
+
+        // Immediate loads can use the rest of it
+        for _ in 0..3 {
+            assert!(bucket.can_start(immediate));
+            bucket.take();
+        }
+        assert!(!bucket.can_start(immediate));
+
+        // Refills at `loads_per_sec` up to `burst`
+        bucket.refill(start + Duration::from_millis(500));
+        assert!(bucket.can_start(immediate));
+        assert!(!bucket.can_start(background));
+        bucket.refill(start + Duration::from_secs(10));
+        assert!(bucket.can_start(background));
+        bucket.take();
+        assert!(!bucket.can_start(background));
+    }
+
+    #[test]
+    fn test_pool_stop_detaches_slow_workers() {
+        let mut pool = LoadThreadPool::new(2);
+        // Doesn't check a cancel token, like a blocking read from disk
+        pool.spawn(Box::new(|| thread::sleep(Duration::from_secs(5))));
+
+        let start = Instant::now();
+        assert_eq!(pool.stop(Duration::from_millis(50)), 1);
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        let mut pool = LoadThreadPool::new(2);
+        pool.spawn(Box::new(|| {}));
+        assert_eq!(pool.stop(Duration::from_secs(5)), 0);
+    }
+
+    #[test]
+    fn test_enqueue_background() {
+        let mut loader: ChunkLoader<(), TakenTestChunkEditor> =
+            ChunkLoader::new(ChunkLoaderParams {
+                n_threads: 1,
+                rate_limit: None,
+            });
+        let item = |x| ChunkLoadQueueItem {
+            data: (),
+            pos: TlcPos(Point3 { x, y: 0, z: 0 }),
+        };
+
+        loader.enqueue(item(0), 5);
+        loader.enqueue_background(item(0));
+        loader.enqueue_background(item(1));
+        loader.enqueue_background(item(1));
+        // Only the chunk that wasn't queued yet is counted, and the queued one keeps its priority
+        assert_eq!(loader.queued_last(), 2);
+        assert_eq!(loader.queue().len(), 2);
+        assert_eq!(loader.queue().get_priority(&item(0)), Some(&5));
+        assert_eq!(loader.queue().get_priority(&item(1)), Some(&0));
+    }
 }
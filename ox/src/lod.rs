@@ -0,0 +1,10 @@
+//! LOD downsampling on plain voxel ID slices, for offline tools (pregeneration, importers) that need to produce
+//! coarse LODs identical to the ones computed at runtime when a chunk is loaded. These live in
+//! `ox_core::voxel::lod` so tools can use them without depending on ox. To downsample the voxel types of a
+//! `VoxelTypeEnum`, pass `|id| voxel_type_info(VE::from_id(id))` to `downsample`.
+
+pub use crate::world::mem_grid::voxel::lod::voxel_type_info;
+pub use ox_core::voxel::lod::{
+    apply_to_voxel_indices_in_lower_lod, apply_to_voxels_in_lod, downsample, lod_tlc_size,
+    DownsampleCounts, DownsampleParams, LodLevel, VoxelTypeInfo,
+};
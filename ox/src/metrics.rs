@@ -0,0 +1,308 @@
+use hashbrown::HashMap;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// Names of the metrics reported by `ox` itself.
+pub mod names {
+    /// Counter: chunks that finished loading and were returned to the memory grid
+    pub const CHUNKS_LOADED: &str = "ox_loader_chunks_loaded_total";
+    /// Counter: chunks that were popped off the queue but no longer needed loading
+    pub const CHUNKS_SKIPPED: &str = "ox_loader_chunks_skipped_total";
+    /// Counter: chunks whose load result was stale (the chunk changed while loading) and was discarded
+    pub const CHUNKS_STALE: &str = "ox_loader_chunks_stale_total";
+    /// Counter: chunk loads that were cancelled because the chunk left the memory grid while loading
+    pub const CHUNKS_CANCELLED: &str = "ox_loader_chunks_cancelled_total";
+    /// Counter: chunk loads that panicked, leaving the chunk unloaded
+    pub const CHUNKS_FAILED: &str = "ox_loader_chunks_failed_total";
+    /// Gauge: chunks waiting in the loader queue
+    pub const QUEUE_DEPTH: &str = "ox_loader_queue_depth";
+    /// Gauge: loader threads currently loading a chunk
+    pub const ACTIVE_LOADING_THREADS: &str = "ox_loader_active_threads";
+    /// Histogram: time between consecutive frames in seconds
+    pub const FRAME_TIME: &str = "ox_renderer_frame_time_seconds";
+    /// Counter: bytes copied from staging buffers to device local buffers
+    pub const TRANSFER_BYTES: &str = "ox_renderer_transfer_bytes_total";
+}
+
+/// Default histogram buckets (upper bounds, in seconds) used for frame times.
+pub const DEFAULT_FRAME_TIME_BUCKETS: [f64; 10] = [
+    0.001, 0.002, 0.004, 0.008, 0.016, 0.033, 0.05, 0.1, 0.25, 1.0,
+];
+
+/// Number of timestamped samples kept per counter for computing rates.
+const N_RATE_SAMPLES: usize = 256;
+
+/// Destination for loader and renderer metrics. `ChunkLoader::report_metrics` and
+/// `Renderer::report_metrics` push their values into any implementation of this trait, so
+/// applications can forward metrics to whatever monitoring system they use.
+pub trait MetricsSink {
+    /// Add `value` to a monotonically increasing counter.
+    fn increment_counter(&mut self, name: &'static str, value: u64);
+
+    /// Set a gauge to its current value.
+    fn set_gauge(&mut self, name: &'static str, value: f64);
+
+    /// Record a single observation of a histogram.
+    fn observe_histogram(&mut self, name: &'static str, value: f64);
+}
+
+/// Sink that discards everything.
+impl MetricsSink for () {
+    fn increment_counter(&mut self, _: &'static str, _: u64) {}
+    fn set_gauge(&mut self, _: &'static str, _: f64) {}
+    fn observe_histogram(&mut self, _: &'static str, _: f64) {}
+}
+
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    /// Upper bound of each bucket, sorted ascending. There is an implicit `+Inf` bucket at the end.
+    bounds: Vec<f64>,
+    /// Non-cumulative count of observations in each bucket, including the `+Inf` bucket
+    counts: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    pub fn new(mut bounds: Vec<f64>) -> Self {
+        bounds.sort_by(|a, b| a.partial_cmp(b).expect("Histogram bounds cannot be NaN"));
+        let n_buckets = bounds.len() + 1;
+        Histogram {
+            bounds,
+            counts: vec![0; n_buckets],
+            sum: 0.,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|b| value <= *b)
+            .unwrap_or(self.bounds.len());
+        self.counts[bucket] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+
+    pub fn bounds(&self) -> &[f64] {
+        &self.bounds
+    }
+
+    /// Cumulative counts for each bucket (Prometheus `le` semantics), ending with the `+Inf` bucket.
+    pub fn cumulative_counts(&self) -> Vec<u64> {
+        self.counts
+            .iter()
+            .scan(0, |acc, c| {
+                *acc += c;
+                Some(*acc)
+            })
+            .collect()
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(self.sum / self.count as f64)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Counter {
+    total: u64,
+    /// Recent (time, total) samples used to compute rates
+    samples: VecDeque<(Instant, u64)>,
+}
+
+/// Default `MetricsSink` that keeps all metrics in memory. Counters keep a short history of
+/// timestamped samples so rates (e.g. chunks loaded per second) can be computed from them.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryMetrics {
+    counters: HashMap<&'static str, Counter>,
+    gauges: HashMap<&'static str, f64>,
+    histograms: HashMap<&'static str, Histogram>,
+}
+
+impl InMemoryMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use custom bucket bounds for a histogram. Histograms that are not registered this way use
+    /// `DEFAULT_FRAME_TIME_BUCKETS`. Replaces any existing observations for `name`.
+    pub fn register_histogram(&mut self, name: &'static str, bounds: Vec<f64>) {
+        self.histograms.insert(name, Histogram::new(bounds));
+    }
+
+    pub fn counter(&self, name: &str) -> u64 {
+        self.counters.get(name).map(|c| c.total).unwrap_or(0)
+    }
+
+    pub fn gauge(&self, name: &str) -> Option<f64> {
+        self.gauges.get(name).copied()
+    }
+
+    pub fn histogram(&self, name: &str) -> Option<&Histogram> {
+        self.histograms.get(name)
+    }
+
+    /// Average increase per second of a counter over (at most) the last `window`, based on the
+    /// samples taken each time the counter was incremented. Returns `None` if there are not
+    /// enough samples in the window.
+    pub fn rate(&self, name: &str, window: Duration) -> Option<f64> {
+        self.rate_at(name, window, Instant::now())
+    }
+
+    fn rate_at(&self, name: &str, window: Duration, now: Instant) -> Option<f64> {
+        let samples = &self.counters.get(name)?.samples;
+        let (last_t, last_v) = *samples.back()?;
+        let (first_t, first_v) = *samples
+            .iter()
+            .find(|(t, _)| now.saturating_duration_since(*t) <= window)?;
+        let dt = last_t.duration_since(first_t).as_secs_f64();
+        if dt <= 0. {
+            None
+        } else {
+            Some((last_v - first_v) as f64 / dt)
+        }
+    }
+
+    fn increment_counter_at(&mut self, name: &'static str, value: u64, t: Instant) {
+        let counter = self.counters.entry(name).or_default();
+        counter.total += value;
+        if counter.samples.len() == N_RATE_SAMPLES {
+            counter.samples.pop_front();
+        }
+        counter.samples.push_back((t, counter.total));
+    }
+
+    /// Write all metrics in the Prometheus text exposition format.
+    pub fn write_prometheus<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        let mut counters: Vec<_> = self.counters.iter().collect();
+        counters.sort_by_key(|(name, _)| **name);
+        for (name, counter) in counters {
+            writeln!(w, "# TYPE {} counter", name)?;
+            writeln!(w, "{} {}", name, counter.total)?;
+        }
+
+        let mut gauges: Vec<_> = self.gauges.iter().collect();
+        gauges.sort_by_key(|(name, _)| **name);
+        for (name, value) in gauges {
+            writeln!(w, "# TYPE {} gauge", name)?;
+            writeln!(w, "{} {}", name, value)?;
+        }
+
+        let mut histograms: Vec<_> = self.histograms.iter().collect();
+        histograms.sort_by_key(|(name, _)| **name);
+        for (name, hist) in histograms {
+            writeln!(w, "# TYPE {} histogram", name)?;
+            let cumulative = hist.cumulative_counts();
+            for (bound, count) in hist.bounds().iter().zip(cumulative.iter()) {
+                writeln!(w, "{}_bucket{{le=\"{}\"}} {}", name, bound, count)?;
+            }
+            writeln!(
+                w,
+                "{}_bucket{{le=\"+Inf\"}} {}",
+                name,
+                cumulative.last().unwrap()
+            )?;
+            writeln!(w, "{}_sum {}", name, hist.sum())?;
+            writeln!(w, "{}_count {}", name, hist.count())?;
+        }
+
+        Ok(())
+    }
+
+    /// Convenience wrapper around `write_prometheus` that returns a `String`.
+    pub fn export_prometheus(&self) -> String {
+        let mut out = vec![];
+        self.write_prometheus(&mut out)
+            .expect("Writing to a Vec should not fail");
+        String::from_utf8(out).unwrap()
+    }
+}
+
+impl MetricsSink for InMemoryMetrics {
+    fn increment_counter(&mut self, name: &'static str, value: u64) {
+        self.increment_counter_at(name, value, Instant::now());
+    }
+
+    fn set_gauge(&mut self, name: &'static str, value: f64) {
+        self.gauges.insert(name, value);
+    }
+
+    fn observe_histogram(&mut self, name: &'static str, value: f64) {
+        self.histograms
+            .entry(name)
+            .or_insert_with(|| Histogram::new(DEFAULT_FRAME_TIME_BUCKETS.to_vec()))
+            .observe(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets() {
+        let mut hist = Histogram::new(vec![1., 2., 4.]);
+        for v in [0.5, 1., 1.5, 3., 10.] {
+            hist.observe(v);
+        }
+        assert_eq!(hist.cumulative_counts(), vec![2, 3, 4, 5]);
+        assert_eq!(hist.count(), 5);
+        assert_eq!(hist.sum(), 16.);
+    }
+
+    #[test]
+    fn test_counter_rate() {
+        let mut metrics = InMemoryMetrics::new();
+        let t0 = Instant::now();
+        for i in 0..=10 {
+            metrics.increment_counter_at(
+                names::CHUNKS_LOADED,
+                5,
+                t0 + Duration::from_millis(100 * i),
+            );
+        }
+        assert_eq!(metrics.counter(names::CHUNKS_LOADED), 55);
+
+        let rate = metrics
+            .rate_at(
+                names::CHUNKS_LOADED,
+                Duration::from_secs(10),
+                t0 + Duration::from_secs(1),
+            )
+            .unwrap();
+        assert!((rate - 50.).abs() < 1e-6, "{}", rate);
+    }
+
+    #[test]
+    fn test_prometheus_export() {
+        let mut metrics = InMemoryMetrics::new();
+        metrics.increment_counter(names::TRANSFER_BYTES, 128);
+        metrics.set_gauge(names::QUEUE_DEPTH, 3.);
+        metrics.register_histogram(names::FRAME_TIME, vec![0.01, 0.1]);
+        metrics.observe_histogram(names::FRAME_TIME, 0.05);
+
+        let text = metrics.export_prometheus();
+        assert!(text.contains("ox_renderer_transfer_bytes_total 128\n"));
+        assert!(text.contains("ox_loader_queue_depth 3\n"));
+        assert!(text.contains("ox_renderer_frame_time_seconds_bucket{le=\"0.01\"} 0\n"));
+        assert!(text.contains("ox_renderer_frame_time_seconds_bucket{le=\"0.1\"} 1\n"));
+        assert!(text.contains("ox_renderer_frame_time_seconds_bucket{le=\"+Inf\"} 1\n"));
+        assert!(text.contains("ox_renderer_frame_time_seconds_count 1\n"));
+    }
+}
@@ -0,0 +1,335 @@
+//! Deterministic, seeded noise for chunk generators. The same seed always gives the same values on every
+//! platform, so worlds generated by different generators (or on different machines) line up.
+//!
+//! The batch methods sample many points at once, e.g. a whole chunk's heightmap or a column of voxels, into a
+//! caller-provided slice. Prefer them over calling `sample` per voxel: they avoid per-point overhead and
+//! `Fbm` accumulates whole octaves at a time over contiguous data, which the compiler can vectorize.
+
+/// 2D noise. Values are roughly in -1..1.
+pub trait Noise2 {
+    fn sample(&self, x: f64, z: f64) -> f32;
+
+    /// Sample a `size_x` by `size_z` grid of points spaced `spacing` apart starting at `origin`. `out` is
+    /// filled in x-major order (`out[x + z * size_x]`) and must hold `size_x * size_z` values.
+    fn sample_grid(
+        &self,
+        origin: [f64; 2],
+        spacing: f64,
+        size_x: usize,
+        size_z: usize,
+        out: &mut [f32],
+    ) {
+        assert_eq!(out.len(), size_x * size_z);
+        for (z, row) in out.chunks_exact_mut(size_x).enumerate() {
+            let pz = origin[1] + z as f64 * spacing;
+            for (x, v) in row.iter_mut().enumerate() {
+                *v = self.sample(origin[0] + x as f64 * spacing, pz);
+            }
+        }
+    }
+}
+
+/// 3D noise. Values are roughly in -1..1.
+pub trait Noise3 {
+    fn sample(&self, x: f64, y: f64, z: f64) -> f32;
+
+    /// Sample the column of points at `x`, `z` starting at `y_start` and spaced `y_step` apart, filling all
+    /// of `out`.
+    fn sample_column(&self, x: f64, z: f64, y_start: f64, y_step: f64, out: &mut [f32]) {
+        for (y, v) in out.iter_mut().enumerate() {
+            *v = self.sample(x, y_start + y as f64 * y_step, z);
+        }
+    }
+}
+
+/// www.pcg-random.org and www.shadertoy.com/view/XlGcRh
+fn hash(inp: u32) -> u32 {
+    let x = inp.wrapping_mul(747796405).wrapping_add(2891336453);
+    let x = ((x >> ((x >> 28) + 4)) ^ x).wrapping_mul(277803737);
+    (x >> 22) ^ x
+}
+
+pub(crate) fn hash_cell(seed: u32, x: i64, y: i64, z: i64) -> u32 {
+    let h = hash(seed ^ x as u32);
+    let h = hash(h ^ y as u32);
+    hash(h ^ z as u32)
+}
+
+/// Quintic fade curve used by Perlin noise
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6. - 15.) + 10.)
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+// Dot product of the distance vector with one of the 8 (2D) or 12 (3D) gradient directions picked by `h`
+fn grad2(h: u32, x: f64, z: f64) -> f64 {
+    const GRADS: [(f64, f64); 8] = [
+        (1., 0.),
+        (-1., 0.),
+        (0., 1.),
+        (0., -1.),
+        (
+            std::f64::consts::FRAC_1_SQRT_2,
+            std::f64::consts::FRAC_1_SQRT_2,
+        ),
+        (
+            -std::f64::consts::FRAC_1_SQRT_2,
+            std::f64::consts::FRAC_1_SQRT_2,
+        ),
+        (
+            std::f64::consts::FRAC_1_SQRT_2,
+            -std::f64::consts::FRAC_1_SQRT_2,
+        ),
+        (
+            -std::f64::consts::FRAC_1_SQRT_2,
+            -std::f64::consts::FRAC_1_SQRT_2,
+        ),
+    ];
+    let (gx, gz) = GRADS[(h & 7) as usize];
+    gx * x + gz * z
+}
+
+fn grad3(h: u32, x: f64, y: f64, z: f64) -> f64 {
+    const GRADS: [(f64, f64, f64); 12] = [
+        (1., 1., 0.),
+        (-1., 1., 0.),
+        (1., -1., 0.),
+        (-1., -1., 0.),
+        (1., 0., 1.),
+        (-1., 0., 1.),
+        (1., 0., -1.),
+        (-1., 0., -1.),
+        (0., 1., 1.),
+        (0., -1., 1.),
+        (0., 1., -1.),
+        (0., -1., -1.),
+    ];
+    let (gx, gy, gz) = GRADS[(h % 12) as usize];
+    gx * x + gy * y + gz * z
+}
+
+/// Classic gradient noise with a cell size of 1. Zero at integer coordinates.
+#[derive(Debug, Clone, Copy)]
+pub struct Perlin {
+    seed: u32,
+}
+
+impl Perlin {
+    pub fn new(seed: u32) -> Self {
+        Perlin { seed }
+    }
+}
+
+impl Noise2 for Perlin {
+    fn sample(&self, x: f64, z: f64) -> f32 {
+        let (x0, z0) = (x.floor(), z.floor());
+        let (fx, fz) = (x - x0, z - z0);
+        let (ix, iz) = (x0 as i64, z0 as i64);
+        let g = |dx: i64, dz: i64| {
+            grad2(
+                hash_cell(self.seed, ix + dx, 0, iz + dz),
+                fx - dx as f64,
+                fz - dz as f64,
+            )
+        };
+        let (u, v) = (fade(fx), fade(fz));
+        (lerp(lerp(g(0, 0), g(1, 0), u), lerp(g(0, 1), g(1, 1), u), v) * std::f64::consts::SQRT_2)
+            as f32
+    }
+}
+
+impl Noise3 for Perlin {
+    fn sample(&self, x: f64, y: f64, z: f64) -> f32 {
+        let (x0, y0, z0) = (x.floor(), y.floor(), z.floor());
+        let (fx, fy, fz) = (x - x0, y - y0, z - z0);
+        let (ix, iy, iz) = (x0 as i64, y0 as i64, z0 as i64);
+        let g = |dx: i64, dy: i64, dz: i64| {
+            grad3(
+                hash_cell(self.seed, ix + dx, iy + dy, iz + dz),
+                fx - dx as f64,
+                fy - dy as f64,
+                fz - dz as f64,
+            )
+        };
+        let (u, v, w) = (fade(fx), fade(fy), fade(fz));
+        let lo = lerp(
+            lerp(g(0, 0, 0), g(1, 0, 0), u),
+            lerp(g(0, 1, 0), g(1, 1, 0), u),
+            v,
+        );
+        let hi = lerp(
+            lerp(g(0, 0, 1), g(1, 0, 1), u),
+            lerp(g(0, 1, 1), g(1, 1, 1), u),
+            v,
+        );
+        lerp(lo, hi, w) as f32
+    }
+}
+
+/// 2D simplex noise. Cheaper than `Perlin` with fewer directional artifacts.
+#[derive(Debug, Clone, Copy)]
+pub struct Simplex {
+    seed: u32,
+}
+
+impl Simplex {
+    pub fn new(seed: u32) -> Self {
+        Simplex { seed }
+    }
+}
+
+impl Noise2 for Simplex {
+    fn sample(&self, x: f64, z: f64) -> f32 {
+        const F2: f64 = 0.36602540378443865; // (sqrt(3) - 1) / 2
+        const G2: f64 = 0.21132486540518713; // (3 - sqrt(3)) / 6
+
+        // Skew to find the simplex cell and unskew back to get the distance from its origin
+        let s = (x + z) * F2;
+        let (i, j) = ((x + s).floor(), (z + s).floor());
+        let t = (i + j) * G2;
+        let (x0, z0) = (x - (i - t), z - (j - t));
+        let (i1, j1) = if x0 > z0 { (1, 0) } else { (0, 1) };
+        let corners = [
+            (0, 0, x0, z0),
+            (i1, j1, x0 - i1 as f64 + G2, z0 - j1 as f64 + G2),
+            (1, 1, x0 - 1. + 2. * G2, z0 - 1. + 2. * G2),
+        ];
+
+        let (i, j) = (i as i64, j as i64);
+        let sum: f64 = corners
+            .iter()
+            .map(|&(di, dj, cx, cz)| {
+                let t = (0.5 - cx * cx - cz * cz).max(0.);
+                let h = hash_cell(self.seed, i + di, 0, j + dj);
+                t * t * t * t * grad2(h, cx, cz)
+            })
+            .sum();
+        (sum * 70.) as f32
+    }
+}
+
+/// Fractal Brownian motion: `octaves` layers of `noise`, each with `lacunarity` times the frequency and `gain`
+/// times the amplitude of the previous. The result is normalized so it stays roughly in -1..1.
+#[derive(Debug, Clone, Copy)]
+pub struct Fbm<N> {
+    pub noise: N,
+    pub octaves: u32,
+    /// Frequency of the first octave
+    pub frequency: f64,
+    pub lacunarity: f64,
+    pub gain: f32,
+}
+
+impl<N> Fbm<N> {
+    /// fBm with common defaults (lacunarity 2, gain 0.5)
+    pub fn new(noise: N, octaves: u32, frequency: f64) -> Self {
+        Fbm {
+            noise,
+            octaves,
+            frequency,
+            lacunarity: 2.,
+            gain: 0.5,
+        }
+    }
+
+    fn normalization(&self) -> f32 {
+        (0..self.octaves).map(|i| self.gain.powi(i as i32)).sum()
+    }
+}
+
+impl<N: Noise2> Noise2 for Fbm<N> {
+    fn sample(&self, x: f64, z: f64) -> f32 {
+        let (mut freq, mut amp, mut sum) = (self.frequency, 1., 0.);
+        for _ in 0..self.octaves {
+            sum += self.noise.sample(x * freq, z * freq) * amp;
+            freq *= self.lacunarity;
+            amp *= self.gain;
+        }
+        sum / self.normalization()
+    }
+
+    fn sample_grid(
+        &self,
+        origin: [f64; 2],
+        spacing: f64,
+        size_x: usize,
+        size_z: usize,
+        out: &mut [f32],
+    ) {
+        // Accumulate whole octaves at a time so the inner noise's batch method is used
+        assert_eq!(out.len(), size_x * size_z);
+        out.fill(0.);
+        let mut octave = vec![0.; out.len()];
+        let (mut freq, mut amp) = (self.frequency, 1.);
+        for _ in 0..self.octaves {
+            self.noise.sample_grid(
+                [origin[0] * freq, origin[1] * freq],
+                spacing * freq,
+                size_x,
+                size_z,
+                &mut octave,
+            );
+            for (v, o) in out.iter_mut().zip(octave.iter()) {
+                *v += o * amp;
+            }
+            freq *= self.lacunarity;
+            amp *= self.gain;
+        }
+        let norm = self.normalization();
+        out.iter_mut().for_each(|v| *v /= norm);
+    }
+}
+
+impl<N: Noise3> Noise3 for Fbm<N> {
+    fn sample(&self, x: f64, y: f64, z: f64) -> f32 {
+        let (mut freq, mut amp, mut sum) = (self.frequency, 1., 0.);
+        for _ in 0..self.octaves {
+            sum += self.noise.sample(x * freq, y * freq, z * freq) * amp;
+            freq *= self.lacunarity;
+            amp *= self.gain;
+        }
+        sum / self.normalization()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deterministic_and_seeded() {
+        let (a, b) = (Perlin::new(1), Perlin::new(2));
+        let v = Noise2::sample(&a, 3.7, -12.2);
+        assert_eq!(v, Noise2::sample(&Perlin::new(1), 3.7, -12.2));
+        assert_ne!(v, Noise2::sample(&b, 3.7, -12.2));
+        assert_eq!(Noise3::sample(&a, 5., -2., 9.), 0.);
+
+        for noise in [&a as &dyn Noise2, &Simplex::new(7), &Fbm::new(a, 5, 0.1)] {
+            for i in 0..1000 {
+                let v = noise.sample(i as f64 * 0.37, i as f64 * -0.91);
+                assert!((-1.1..=1.1).contains(&v), "{}", v);
+            }
+        }
+    }
+
+    #[test]
+    fn test_batch_matches_single() {
+        let fbm = Fbm::new(Simplex::new(3), 4, 0.05);
+        let mut grid = vec![0.; 6 * 4];
+        fbm.sample_grid([10., -3.], 0.5, 6, 4, &mut grid);
+        for z in 0..4 {
+            for x in 0..6 {
+                let v = fbm.sample(10. + x as f64 * 0.5, -3. + z as f64 * 0.5);
+                assert!((grid[x + z * 6] - v).abs() < 1e-5);
+            }
+        }
+
+        let perlin = Perlin::new(9);
+        let mut column = [0.; 8];
+        perlin.sample_column(1.5, 2.5, -4., 0.25, &mut column);
+        assert_eq!(column[3], Noise3::sample(&perlin, 1.5, -3.25, 2.5));
+    }
+}
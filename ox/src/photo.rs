@@ -0,0 +1,254 @@
+//! Photo mode: freeze the world, fly a free camera around and take screenshots with less noise and no overlay.
+
+use crate::loader::{ChunkLoader, TakenChunk};
+use crate::renderer::component::ubo::{DataComponentSetWithUbo, PhotoSettings};
+use crate::renderer::Renderer;
+use crate::timelapse::Frame;
+use crate::world::camera::controller::CameraController;
+use crate::world::camera::Camera;
+use crate::world::mem_grid::{MemoryGrid, MemoryGridLoadChunks};
+use crate::world::{VoxelPos, World};
+use cgmath::Rad;
+use std::time::Duration;
+use vulkano::command_buffer::allocator::CommandBufferAllocator;
+use vulkano::descriptor_set::allocator::DescriptorSetAllocator;
+
+/// State from before `PhotoMode::enter`, restored by `PhotoMode::exit`
+#[derive(Debug, Clone)]
+struct SavedState {
+    position: VoxelPos<f32>,
+    yaw: Rad<f32>,
+    pitch: Rad<f32>,
+    streaming_paused: bool,
+    render: PhotoSettings,
+}
+
+/// Coordinates photo mode across `World`, `Renderer` and a camera controller of its own (e.g. a
+/// `WinitCameraController` or a `CinematicController`):
+///
+/// - `enter` pauses streaming (see `World::set_streaming_paused`) so the loaded scene stays as it is, and
+///   applies `settings` to the renderer to hide the overlay and raise the sample count.
+/// - `update` moves the camera with the photo mode controller instead of the game's, ignoring collision.
+///   The game should skip its own simulation and input handling while `is_active`.
+/// - `exit` puts the camera back where it was and restores streaming and the render settings.
+///
+/// For even less noise than the sample count allows, draw several frames of the frozen scene with
+/// different `Ubo::time` seeds and average them with a `PhotoAccumulator`.
+#[derive(Debug)]
+pub struct PhotoMode<C> {
+    controller: C,
+    settings: PhotoSettings,
+    saved: Option<SavedState>,
+}
+
+impl<C: CameraController> PhotoMode<C> {
+    pub fn new(controller: C, settings: PhotoSettings) -> Self {
+        PhotoMode {
+            controller,
+            settings,
+            saved: None,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.saved.is_some()
+    }
+
+    /// Controller that moves the camera while photo mode is active. Forward input to it instead of the game's
+    /// controller.
+    pub fn controller_mut(&mut self) -> &mut C {
+        &mut self.controller
+    }
+
+    pub fn settings(&self) -> PhotoSettings {
+        self.settings
+    }
+
+    /// Takes effect immediately if photo mode is active
+    pub fn set_settings<D, DSA, CBA, DCBA>(
+        &mut self,
+        settings: PhotoSettings,
+        renderer: &mut Renderer<D, DSA, CBA, DCBA>,
+    ) where
+        D: DataComponentSetWithUbo,
+        DSA: DescriptorSetAllocator,
+        CBA: CommandBufferAllocator + 'static,
+        DCBA: CommandBufferAllocator + 'static,
+    {
+        self.settings = settings;
+        if self.is_active() {
+            renderer.set_photo_settings(settings);
+        }
+    }
+
+    /// Does nothing if photo mode is already active
+    pub fn enter<MG, D, DSA, CBA, DCBA>(
+        &mut self,
+        world: &mut World<MG>,
+        renderer: &mut Renderer<D, DSA, CBA, DCBA>,
+    ) where
+        MG: MemoryGrid,
+        D: DataComponentSetWithUbo,
+        DSA: DescriptorSetAllocator,
+        CBA: CommandBufferAllocator + 'static,
+        DCBA: CommandBufferAllocator + 'static,
+    {
+        if self.is_active() {
+            return;
+        }
+        let camera = world.camera();
+        self.saved = Some(SavedState {
+            position: camera.position,
+            yaw: camera.yaw,
+            pitch: camera.pitch,
+            streaming_paused: world.streaming_paused(),
+            render: renderer.photo_settings(),
+        });
+        world.set_streaming_paused(true);
+        renderer.set_photo_settings(self.settings);
+    }
+
+    /// Move the camera with the photo mode controller. Returns whether the camera moved, in which case a
+    /// redraw was requested. Does nothing if photo mode isn't active.
+    pub fn update<QI, MG, BC, D, DSA, CBA, DCBA>(
+        &mut self,
+        world: &mut World<MG>,
+        dt: Duration,
+        loader: &mut ChunkLoader<QI, BC>,
+        renderer: &mut Renderer<D, DSA, CBA, DCBA>,
+    ) -> bool
+    where
+        QI: Eq,
+        MG: MemoryGrid + MemoryGridLoadChunks<ChunkLoadQueueItemData = QI>,
+        BC: TakenChunk<MemoryGrid = MG>,
+        D: DataComponentSetWithUbo,
+        DSA: DescriptorSetAllocator,
+        CBA: CommandBufferAllocator + 'static,
+        DCBA: CommandBufferAllocator + 'static,
+    {
+        if !self.is_active() {
+            return false;
+        }
+        let before = world.camera().to_gpu_repr();
+        world.move_camera(&mut self.controller, dt, loader);
+        let moved = world.camera().to_gpu_repr() != before;
+        if moved {
+            renderer.request_redraw();
+        }
+        moved
+    }
+
+    /// Put the camera back where it was when photo mode was entered and restore streaming and the render
+    /// settings. Does nothing if photo mode isn't active.
+    pub fn exit<QI, MG, BC, D, DSA, CBA, DCBA>(
+        &mut self,
+        world: &mut World<MG>,
+        loader: &mut ChunkLoader<QI, BC>,
+        renderer: &mut Renderer<D, DSA, CBA, DCBA>,
+    ) where
+        QI: Eq,
+        MG: MemoryGrid + MemoryGridLoadChunks<ChunkLoadQueueItemData = QI>,
+        BC: TakenChunk<MemoryGrid = MG>,
+        D: DataComponentSetWithUbo,
+        DSA: DescriptorSetAllocator,
+        CBA: CommandBufferAllocator + 'static,
+        DCBA: CommandBufferAllocator + 'static,
+    {
+        let Some(saved) = self.saved.take() else {
+            return;
+        };
+        // Streaming is still paused, so this doesn't shift the grid
+        world.move_camera(&mut RestorePose(&saved), Duration::ZERO, loader);
+        world.set_streaming_paused(saved.streaming_paused);
+        renderer.set_photo_settings(saved.render);
+        renderer.request_redraw();
+    }
+}
+
+/// Moves the camera back to a saved pose, keeping its resolution and field of view in case they changed
+struct RestorePose<'a>(&'a SavedState);
+
+impl CameraController for RestorePose<'_> {
+    fn apply(&mut self, camera: &mut Camera, _: Duration) {
+        camera.position = self.0.position;
+        camera.yaw = self.0.yaw;
+        camera.pitch = self.0.pitch;
+    }
+}
+
+/// Averages frames of a still scene to reduce noise from the raytracer's random bounces. Each frame needs a
+/// different random seed (`Ubo::time`) for this to help.
+#[derive(Debug, Clone)]
+pub struct PhotoAccumulator {
+    width: u32,
+    height: u32,
+    sum: Vec<u32>,
+    n_frames: u32,
+}
+
+impl PhotoAccumulator {
+    pub fn new(width: u32, height: u32) -> Self {
+        PhotoAccumulator {
+            width,
+            height,
+            sum: vec![0; width as usize * height as usize * 4],
+            n_frames: 0,
+        }
+    }
+
+    /// Frames must all have the size the accumulator was created with
+    pub fn add(&mut self, frame: &Frame) {
+        assert_eq!(
+            (frame.width, frame.height),
+            (self.width, self.height),
+            "Accumulated frames must all be the same size"
+        );
+        for (sum, &c) in self.sum.iter_mut().zip(frame.rgba.iter()) {
+            *sum += c as u32;
+        }
+        self.n_frames += 1;
+    }
+
+    pub fn n_frames(&self) -> u32 {
+        self.n_frames
+    }
+
+    /// Start over, e.g. after the camera moved
+    pub fn clear(&mut self) {
+        self.sum.fill(0);
+        self.n_frames = 0;
+    }
+
+    /// Average of the frames added so far (black if there are none)
+    pub fn average(&self) -> Frame {
+        let n = self.n_frames.max(1);
+        Frame {
+            width: self.width,
+            height: self.height,
+            rgba: self.sum.iter().map(|&s| ((s + n / 2) / n) as u8).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accumulate_frames() {
+        let frame = |v: u8| Frame {
+            width: 2,
+            height: 1,
+            rgba: vec![v; 8],
+        };
+        let mut acc = PhotoAccumulator::new(2, 1);
+        assert_eq!(acc.average().rgba, vec![0; 8]);
+        acc.add(&frame(10));
+        acc.add(&frame(21));
+        assert_eq!(acc.n_frames(), 2);
+        assert_eq!(acc.average().rgba, vec![16; 8]);
+        acc.clear();
+        acc.add(&frame(255));
+        assert_eq!(acc.average().rgba, vec![255; 8]);
+    }
+}
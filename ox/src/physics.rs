@@ -0,0 +1,235 @@
+//! Collision queries against the LOD 0 voxels of the world, for player movement with gravity and the like.
+//!
+//! Positions are in LOD 0 voxels relative to the memory grid, like the camera position. Voxels are solid if they
+//! are visible in the bitmask, and voxels in chunks that aren't loaded or are outside the LOD 0 area count as empty.
+
+use crate::ray::ChunkEditorVoxels;
+use crate::voxel_type::VoxelTypeEnum;
+use crate::world::mem_grid::utils::{ChunkSize, VoxelPosInLod};
+use crate::world::mem_grid::{EditMemoryGridChunk, MemoryGrid};
+use crate::world::{TlcVector, World};
+use cgmath::{EuclideanSpace, Point3, Vector3};
+
+/// Gap left between a swept box and the voxel it stopped at, so that it doesn't count as overlapping the voxel
+/// afterwards because of rounding
+pub const SKIN: f32 = 1e-3;
+
+/// Axis-aligned box in LOD 0 voxels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    pub fn new(min: Point3<f32>, max: Point3<f32>) -> Self {
+        Aabb { min, max }
+    }
+
+    /// Box with its bottom face centered at `feet`, e.g. for a player
+    pub fn from_feet(feet: Point3<f32>, width: f32, height: f32) -> Self {
+        let half = width / 2.;
+        Aabb {
+            min: Point3::new(feet.x - half, feet.y, feet.z - half),
+            max: Point3::new(feet.x + half, feet.y + height, feet.z + half),
+        }
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    pub fn translated(&self, delta: Vector3<f32>) -> Self {
+        Aabb {
+            min: self.min + delta,
+            max: self.max + delta,
+        }
+    }
+
+    /// Voxels the box overlaps on axis `ax`. Touching a voxel doesn't count.
+    fn cells(&self, ax: usize) -> std::ops::RangeInclusive<i64> {
+        self.min[ax].floor() as i64..=self.max[ax].ceil() as i64 - 1
+    }
+}
+
+/// Result of `sweep_aabb`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SweepResult {
+    /// The box after moving
+    pub aabb: Aabb,
+    /// How far the box actually moved
+    pub delta: Vector3<f32>,
+    /// For each axis, the direction (1 or -1) the box was moving in when a voxel stopped it, or 0 if it wasn't
+    /// stopped on that axis
+    pub blocked: Vector3<i32>,
+}
+
+impl SweepResult {
+    /// Whether the box landed on a voxel while moving down, e.g. to reset vertical velocity and allow jumping
+    pub fn on_ground(&self) -> bool {
+        self.blocked.y == -1
+    }
+}
+
+/// Whether the LOD 0 voxel at `pos` is visible
+pub fn is_voxel_solid<
+    const N: usize,
+    VE: VoxelTypeEnum,
+    MG: MemoryGrid + EditMemoryGridChunk<M>,
+    M,
+>(
+    world: &mut World<MG>,
+    pos: Point3<i64>,
+    chunk_size: ChunkSize,
+    largest_chunk_lvl: u8,
+) -> bool
+where
+    for<'a> MG::ChunkEditor<'a>: ChunkEditorVoxels<VE, N>,
+{
+    let tlc_size = chunk_size.size().pow(largest_chunk_lvl as u32) as i64;
+    let tlc = world.mem_grid.start_tlc() + TlcVector(pos.map(|a| a.div_euclid(tlc_size)).to_vec());
+    let index = VoxelPosInLod {
+        pos: pos.map(|a| a.rem_euclid(tlc_size) as u32),
+        lvl: 0,
+        sublvl: 0,
+        sublvl_y: 0,
+    }
+    .index(chunk_size, largest_chunk_lvl);
+    world
+        .edit_chunk(tlc)
+        .and_then(|editor| {
+            let lod = editor.voxels().lods()[0].as_ref()?;
+            Some(lod.data().get()?.is_visible(index))
+        })
+        .unwrap_or(false)
+}
+
+/// Move `aabb` by `delta` (e.g. velocity times the frame time) through the world's voxels, see `sweep_aabb_with`.
+pub fn sweep_aabb<const N: usize, VE: VoxelTypeEnum, MG: MemoryGrid + EditMemoryGridChunk<M>, M>(
+    world: &mut World<MG>,
+    aabb: Aabb,
+    delta: Vector3<f32>,
+    chunk_size: ChunkSize,
+    largest_chunk_lvl: u8,
+) -> SweepResult
+where
+    for<'a> MG::ChunkEditor<'a>: ChunkEditorVoxels<VE, N>,
+{
+    sweep_aabb_with(aabb, delta, |pos| {
+        is_voxel_solid::<N, VE, MG, M>(world, pos, chunk_size, largest_chunk_lvl)
+    })
+}
+
+/// Move `aabb` by `delta`, stopping on each axis where it would enter a voxel for which `is_solid` returns true. The
+/// axes are moved one at a time (y first, then x and z) so the box slides along walls and floors instead of
+/// stopping completely. Every voxel along the way is checked, so fast movement can't pass through thin walls.
+/// Voxels the box already overlaps are ignored so it can move out of them (e.g. if one was placed inside it).
+pub fn sweep_aabb_with(
+    aabb: Aabb,
+    delta: Vector3<f32>,
+    mut is_solid: impl FnMut(Point3<i64>) -> bool,
+) -> SweepResult {
+    let mut result = SweepResult {
+        aabb,
+        delta: Vector3::new(0., 0., 0.),
+        blocked: Vector3::new(0, 0, 0),
+    };
+    for ax in [1, 0, 2] {
+        let (moved, blocked) = sweep_axis(&result.aabb, ax, delta[ax], &mut is_solid);
+        let mut axis_delta = Vector3::new(0., 0., 0.);
+        axis_delta[ax] = moved;
+        result.aabb = result.aabb.translated(axis_delta);
+        result.delta[ax] = moved;
+        if blocked {
+            result.blocked[ax] = if delta[ax] > 0. { 1 } else { -1 };
+        }
+    }
+    result
+}
+
+/// How far `aabb` can move along axis `ax` towards `d`, and whether it was stopped by a voxel
+fn sweep_axis(
+    aabb: &Aabb,
+    ax: usize,
+    d: f32,
+    is_solid: &mut impl FnMut(Point3<i64>) -> bool,
+) -> (f32, bool) {
+    if d == 0. {
+        return (0., false);
+    }
+    let (ax_b, ax_c) = ((ax + 1) % 3, (ax + 2) % 3);
+    let (cells_b, cells_c) = (aabb.cells(ax_b), aabb.cells(ax_c));
+    let mut layer_is_solid = |layer: i64| {
+        cells_b.clone().any(|b| {
+            cells_c.clone().any(|c| {
+                let mut pos = Point3::new(0, 0, 0);
+                pos[ax] = layer;
+                pos[ax_b] = b;
+                pos[ax_c] = c;
+                is_solid(pos)
+            })
+        })
+    };
+
+    if d > 0. {
+        let leading = aabb.max[ax];
+        for layer in leading.ceil() as i64..=(leading + d).ceil() as i64 - 1 {
+            if layer_is_solid(layer) {
+                return ((layer as f32 - leading - SKIN).clamp(0., d), true);
+            }
+        }
+    } else {
+        let leading = aabb.min[ax];
+        for layer in ((leading + d).floor() as i64..=leading.floor() as i64 - 1).rev() {
+            if layer_is_solid(layer) {
+                return (((layer + 1) as f32 - leading + SKIN).clamp(d, 0.), true);
+            }
+        }
+    }
+    (d, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sweep_aabb_lands_and_slides() {
+        // Floor below y = 0 and a wall at x = 5
+        let world = |p: Point3<i64>| p.y < 0 || p.x == 5;
+        let player = Aabb::from_feet(Point3::new(2.5, 3., 0.5), 0.6, 1.8);
+
+        // Falls onto the floor while moving towards the wall
+        let result = sweep_aabb_with(player, Vector3::new(1., -10., 0.), world);
+        assert!(result.on_ground());
+        assert!((result.aabb.min.y - SKIN).abs() < 1e-4, "{:?}", result);
+        assert_eq!(result.delta.x, 1.);
+
+        // Slides along the wall instead of stopping
+        let result = sweep_aabb_with(result.aabb, Vector3::new(5., 0., 2.), world);
+        assert_eq!(result.blocked, Vector3::new(1, 0, 0));
+        assert!(result.aabb.max.x < 5. && result.aabb.max.x > 5. - 2. * SKIN);
+        assert_eq!(result.delta.z, 2.);
+
+        // Standing still on the floor doesn't count as overlapping it
+        let result = sweep_aabb_with(result.aabb, Vector3::new(0., -0.5, 0.), world);
+        assert!(result.on_ground());
+        assert!(result.delta.y.abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_sweep_aabb_fast_and_stuck() {
+        // A thin wall at x = 50 can't be skipped, even in one large step
+        let wall = |p: Point3<i64>| p.x == 50;
+        let aabb = Aabb::new(Point3::new(0.2, 0.2, 0.2), Point3::new(0.8, 0.8, 0.8));
+        let result = sweep_aabb_with(aabb, Vector3::new(1000., 0., 0.), wall);
+        assert!(result.aabb.max.x < 50.);
+        let result = sweep_aabb_with(result.aabb, Vector3::new(-1000., 0., 0.), wall);
+        assert_eq!(result.delta.x, -1000.);
+
+        // A box inside a voxel can move out of it
+        let inside = Aabb::new(Point3::new(50.2, 0.2, 0.2), Point3::new(50.8, 0.8, 0.8));
+        let result = sweep_aabb_with(inside, Vector3::new(3., 0., 0.), wall);
+        assert_eq!(result.delta.x, 3.);
+    }
+}
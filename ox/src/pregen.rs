@@ -0,0 +1,55 @@
+use crate::loader::ChunkLoadQueueItem;
+use crate::world::{TlcPos, TlcVector};
+use cgmath::Vector3;
+
+/// Progress of `ChunkLoader::pregenerate`, passed to the progress callback after each chunk and returned at the end.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PregenProgress {
+    /// Chunks in the area being generated
+    pub total: usize,
+    pub generated: usize,
+    /// Chunks that were already in the store
+    pub skipped: usize,
+    /// Chunks whose generator panicked or that could not be stored
+    pub failed: usize,
+}
+
+impl PregenProgress {
+    pub fn done(&self) -> usize {
+        self.generated + self.skipped + self.failed
+    }
+}
+
+/// Chunks to pregenerate, nearest to `center` last so they can be popped off the end first.
+pub(crate) fn pregen_queue(center: TlcPos<i64>, radius: u32) -> Vec<ChunkLoadQueueItem<()>> {
+    let r = radius as i64;
+    let mut queue: Vec<_> = (-r..=r)
+        .flat_map(|x| {
+            (-r..=r).flat_map(move |y| {
+                (-r..=r).map(move |z| ChunkLoadQueueItem {
+                    pos: center + TlcVector(Vector3 { x, y, z }),
+                    data: (),
+                })
+            })
+        })
+        .collect();
+    queue.sort_by_key(|item| {
+        let d = item.pos.0 - center.0;
+        std::cmp::Reverse(d.x * d.x + d.y * d.y + d.z * d.z)
+    });
+    queue
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Point3;
+
+    #[test]
+    fn test_pregen_queue_nearest_first() {
+        let center = TlcPos(Point3::new(0, 0, 0));
+        let mut queue = pregen_queue(center, 1);
+        assert_eq!(queue.len(), 27);
+        assert_eq!(queue.pop().unwrap().pos, center);
+    }
+}
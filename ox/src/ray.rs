@@ -1,4 +1,4 @@
-use cgmath::{Array, InnerSpace, Point3, Vector3};
+use cgmath::{Array, ElementWise, EuclideanSpace, InnerSpace, Point3, Vector3};
 use std::ops::AddAssign;
 
 use crate::{
@@ -9,7 +9,7 @@ use crate::{
             voxel::grid::ChunkVoxelEditor,
             EditMemoryGridChunk, MemoryGrid,
         },
-        TlcPos, VoxelPos, VoxelVector, World,
+        TlcPos, VoxelPos, VoxelVector, World, WorldScale,
     },
 };
 
@@ -17,6 +17,15 @@ pub trait ChunkEditorVoxels<VE: VoxelTypeEnum, const N: usize> {
     fn voxels(&self) -> &ChunkVoxelEditor<'_, VE, N>;
 }
 
+impl<'a, VE: VoxelTypeEnum, const N: usize> ChunkEditorVoxels<VE, N>
+    for ChunkVoxelEditor<'a, VE, N>
+{
+    fn voxels(&self) -> &ChunkVoxelEditor<'_, VE, N> {
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct VoxelFace {
     pub ax: u8,    // 0, 1, or 2
     pub dir: bool, // true for positive, false for negative
@@ -36,6 +45,44 @@ pub struct RayVoxelIntersect {
     pub pos: VoxelPos<u32>,
     pub index: usize,
     pub face: VoxelFace,
+    /// Unit vector pointing out of the face that was hit, i.e. `face.delta()`
+    pub normal: Vector3<i32>,
+    /// Where the ray hit the voxel, relative to the bottom corner of the memory grid like the camera position. If
+    /// the ray started inside the voxel, this is the start of the ray.
+    pub hit_point: VoxelPos<f32>,
+}
+
+impl RayVoxelIntersect {
+    /// TLC and position within it of the voxel next to the hit face, e.g. to place a block where the player
+    /// clicked
+    pub fn adjacent_voxel(
+        &self,
+        chunk_size: ChunkSize,
+        largest_chunk_lvl: u8,
+    ) -> (TlcPos<i64>, VoxelPos<u32>) {
+        let global_pos = self.pos.in_global(self.tlc, chunk_size, largest_chunk_lvl)
+            + VoxelVector(self.normal.map(|a| a as i64));
+        (
+            global_pos.to_tlc(chunk_size, largest_chunk_lvl),
+            global_pos.in_tlc(chunk_size, largest_chunk_lvl),
+        )
+    }
+}
+
+/// Where a ray that starts at `origin` in direction `dir` hits `face` of the voxel whose bottom corner is at
+/// `voxel_min`, or `origin` if that is behind the start of the ray
+fn face_hit_point(
+    origin: Point3<f32>,
+    dir: Vector3<f32>,
+    voxel_min: Point3<f32>,
+    face: VoxelFace,
+) -> Point3<f32> {
+    let ax = face.ax as usize;
+    if dir[ax] == 0. {
+        return origin;
+    }
+    let plane = voxel_min[ax] + face.dir as i32 as f32;
+    origin + dir * ((plane - origin[ax]) / dir[ax]).max(0.)
 }
 
 pub struct RayPos {
@@ -46,6 +93,8 @@ pub struct RayPos {
     // which voxel is being examined during traversal
     ipos: Point3<i32>,
     last_crossed_ax: Option<usize>,
+    // position of the tlc's bottom corner relative to the memory grid
+    origin: Vector3<f32>,
 }
 
 pub enum CastRayInTlcResult {
@@ -64,16 +113,17 @@ pub fn cast_ray_in_tlc<VE: VoxelTypeEnum, const N: usize>(
         pos,
         ipos,
         last_crossed_ax,
+        origin,
     }: RayPos,
     ray_dir: Vector3<f32>,
     chunk_size: ChunkSize,
     largest_chunk_lvl: u8,
 ) -> Result<CastRayInTlcResult, ()> {
-    let chunk_voxels = match &editor.lods()[0] {
+    let chunk_data = match &editor.lods()[0] {
         None => return Ok(CastRayInTlcResult::OutOfArea),
         Some(lod) => match lod.data().get() {
             None => return Err(()),
-            Some(data) => data.voxel_ids().as_ref().unwrap(),
+            Some(data) => data,
         },
     };
 
@@ -81,6 +131,7 @@ pub fn cast_ray_in_tlc<VE: VoxelTypeEnum, const N: usize>(
 
     let ray_dir = ray_dir.normalize();
     let tlc_size = chunk_size.size().pow(largest_chunk_lvl as u32) as i32;
+    let (entry_pos, ray_dir_xyz) = (pos, ray_dir);
 
     // Identify axis (x, y, or z) the ray is most parallel to and set to axis A (with others set to B, C)
     let (ax_a, ax_b, ax_c) = if ray_dir.x.abs() > ray_dir.y.abs() {
@@ -158,14 +209,19 @@ pub fn cast_ray_in_tlc<VE: VoxelTypeEnum, const N: usize>(
     };
 
     let hit = |tlc, voxel_index, crossed_ax_abc, ipos: Point3<i32>| {
+        let pos = ipos_xyz(ipos);
+        let face = VoxelFace {
+            ax: [ax_a, ax_b, ax_c][crossed_ax_abc] as u8,
+            dir: ray_dir[crossed_ax_abc] < 0.0,
+        };
+        let hit_point = face_hit_point(entry_pos, ray_dir_xyz, pos.cast().unwrap(), face);
         Ok(CastRayInTlcResult::Hit(RayVoxelIntersect {
             tlc: tlc,
-            pos: VoxelPos(ipos_xyz(ipos).cast::<u32>().unwrap()),
+            pos: VoxelPos(pos.cast::<u32>().unwrap()),
             index: voxel_index,
-            face: VoxelFace {
-                ax: [ax_a, ax_b, ax_c][crossed_ax_abc] as u8,
-                dir: ray_dir[crossed_ax_abc] < 0.0,
-            },
+            face,
+            normal: face.delta().0,
+            hit_point: VoxelPos(hit_point + origin),
         }))
     };
 
@@ -185,7 +241,7 @@ pub fn cast_ray_in_tlc<VE: VoxelTypeEnum, const N: usize>(
         // axis B or C, ray origin will be at the point we crossed, not at a round A value.
         // This means that ipos may also not be at a block where we cross the A axis border.
         let idx = vox_idx(ipos);
-        if chunk_voxels[idx] != VE::empty().id() {
+        if chunk_data.voxel_id(idx).unwrap() != VE::empty().id() {
             return hit(tlc, idx, crossed_ax, ipos);
         }
 
@@ -241,7 +297,7 @@ pub fn cast_ray_in_tlc<VE: VoxelTypeEnum, const N: usize>(
                             },
                         };
                     let idx = vox_idx(ipos_to_check);
-                    if chunk_voxels[idx] != VE::empty().id() {
+                    if chunk_data.voxel_id(idx).unwrap() != VE::empty().id() {
                         return hit(tlc, idx, if b_first { 1 } else { 2 }, ipos_to_check);
                     }
                 }
@@ -256,11 +312,14 @@ pub fn cast_ray_in_tlc<VE: VoxelTypeEnum, const N: usize>(
         // If we went out of bounds in either b axis or c axis, break
         let mut oob_b_or_c = |ax_xyz: usize, ax_abc: usize| {
             let mut new_tlc = tlc;
+            let mut origin = origin;
             ipos.x -= a_dir;
             if ray_dir[ax_abc] > 0.0 {
                 new_tlc.0[ax_xyz] += 1;
+                origin[ax_xyz] += tlc_size as f32;
             } else {
                 new_tlc.0[ax_xyz] -= 1;
+                origin[ax_xyz] -= tlc_size as f32;
             }
 
             // Our position is going to be at the next round value in A axis, but
@@ -278,6 +337,7 @@ pub fn cast_ray_in_tlc<VE: VoxelTypeEnum, const N: usize>(
                 pos: pos_xyz(pos),
                 ipos: ipos_xyz(ipos),
                 last_crossed_ax: Some(ax_xyz),
+                origin,
             }))
         };
         if !b_ib && (c_ib || b_first) {
@@ -297,7 +357,7 @@ pub fn cast_ray_in_tlc<VE: VoxelTypeEnum, const N: usize>(
                     z: 0,
                 };
             let idx = vox_idx(ipos_to_check);
-            if chunk_voxels[idx] != VE::empty().id() {
+            if chunk_data.voxel_id(idx).unwrap() != VE::empty().id() {
                 // Reusing b_first here (with augmented meaning) to determine which axis was crossed for this check.
                 return hit(tlc, idx, if b_first { 2 } else { 1 }, ipos_to_check);
             }
@@ -312,6 +372,8 @@ pub fn cast_ray_in_tlc<VE: VoxelTypeEnum, const N: usize>(
         if ipos.x > max_pt.x {
             let mut tlc = tlc;
             tlc.0[ax_a] += 1;
+            let mut origin = origin;
+            origin[ax_a] += tlc_size as f32;
             pos.x = 0.0;
             ipos = pos.map(|a| a.floor() as i32);
             ipos.x = 0;
@@ -320,11 +382,14 @@ pub fn cast_ray_in_tlc<VE: VoxelTypeEnum, const N: usize>(
                 pos: pos_xyz(pos),
                 ipos: ipos_xyz(ipos),
                 last_crossed_ax: Some(ax_a),
+                origin,
             }));
         }
         if ipos.x < min_pt.x {
             let mut tlc = tlc;
             tlc.0[ax_a] -= 1;
+            let mut origin = origin;
+            origin[ax_a] -= tlc_size as f32;
             pos.x = tlc_size as f32;
             ipos = pos.map(|a| a.floor() as i32);
             ipos.x = tlc_size - 1;
@@ -333,6 +398,7 @@ pub fn cast_ray_in_tlc<VE: VoxelTypeEnum, const N: usize>(
                 pos: pos_xyz(pos),
                 ipos: ipos_xyz(ipos),
                 last_crossed_ax: Some(ax_a),
+                origin,
             }));
         }
 
@@ -366,13 +432,17 @@ where
     // When we trace the ray, if it goes outisde that, we need to switch chunks
 
     let tlc_size = chunk_size.size().pow(largest_chunk_lvl as u32) as i32;
-    let pos = start_pos.0
-        - Vector3::from_value((tlc_size as usize * (world.mem_grid.size() / 2 - 1)) as f32);
+    let origin = world
+        .mem_grid
+        .axis_sizes()
+        .map(|a| (tlc_size as usize * (a / 2 - 1)) as f32);
+    let pos = start_pos.0 - origin;
     let mut ray_pos = RayPos {
         pos,
         ipos: pos.map(|a| a.floor() as i32),
         tlc: world.mem_grid.center_chunk_pos(),
         last_crossed_ax: None,
+        origin,
     };
 
     for _ in 0..=1 {
@@ -393,3 +463,342 @@ where
 
     Ok(CastRayResult::Miss)
 }
+
+/// Options for `cast_ray_lod`
+#[derive(Clone, Copy, Debug)]
+pub struct CastRayLodParams {
+    /// Stop after this distance in LOD 0 voxels and return `Miss`
+    pub max_distance: f32,
+    /// Only use LOD 0 for the part of the ray closer than this distance, so that a hit there is always at full
+    /// resolution (the ray ends with `Miss` if it reaches a chunk without LOD 0 before this distance). Beyond
+    /// it, each chunk is tested with the same LOD the shader renders it at. 0 matches the shader exactly.
+    pub full_res_distance: f32,
+}
+
+impl Default for CastRayLodParams {
+    fn default() -> Self {
+        CastRayLodParams {
+            max_distance: f32::INFINITY,
+            full_res_distance: 0.,
+        }
+    }
+}
+
+impl CastRayLodParams {
+    /// These params with distances given in meters converted to LOD 0 voxels
+    pub fn in_voxels(self, scale: WorldScale) -> Self {
+        CastRayLodParams {
+            max_distance: scale.to_voxels(self.max_distance),
+            full_res_distance: scale.to_voxels(self.full_res_distance),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct LodRayIntersect {
+    pub tlc: TlcPos<i64>,
+    /// Index of the LOD that was hit in the memory grid's LODs
+    pub lod: usize,
+    pub lvl: u8,
+    pub sublvl: u8,
+    pub sublvl_y: u8,
+    /// Position within `tlc` in units of the LOD's voxels
+    pub pos: VoxelPos<u32>,
+    /// Index of the voxel in the LOD's chunk data
+    pub index: usize,
+    /// Voxel type ID, if the LOD stores voxel IDs and not only a bitmask
+    pub voxel_id: Option<u8>,
+    pub face: VoxelFace,
+    /// Distance along the ray to the hit in LOD 0 voxels
+    pub distance: f32,
+    /// Where the ray hit the voxel, relative to the bottom corner of the memory grid
+    pub hit_point: VoxelPos<f32>,
+}
+
+impl LodRayIntersect {
+    /// The intersect as returned by `cast_ray` if the hit was at full resolution
+    pub fn full_res(&self) -> Option<RayVoxelIntersect> {
+        (self.lvl == 0 && self.sublvl == 0 && self.sublvl_y == 0).then_some(RayVoxelIntersect {
+            tlc: self.tlc,
+            pos: self.pos,
+            index: self.index,
+            face: self.face,
+            normal: self.face.delta().0,
+            hit_point: self.hit_point,
+        })
+    }
+}
+
+pub enum CastRayLodResult {
+    Hit(LodRayIntersect),
+    Miss,
+}
+
+/// Traverse a grid of `dims` cells with a DDA from `q` (in units of cells) in direction `dir` (cells per unit
+/// of t) starting at `t`, until `is_solid` returns true for a cell or t passes `t_end`. `entered_ax` is the axis
+/// that was crossed to enter the grid, if any. Returns the solid cell, t when it was entered and the axis that
+/// was crossed to enter it.
+fn traverse_grid(
+    q: Point3<f32>,
+    dir: Vector3<f32>,
+    dims: Vector3<u32>,
+    t: f32,
+    t_end: f32,
+    entered_ax: Option<usize>,
+    mut is_solid: impl FnMut(Point3<u32>) -> bool,
+) -> Option<(Point3<u32>, f32, usize)> {
+    let mut cell = Point3::new(0i64, 0, 0);
+    let mut t_max = Vector3::from_value(f32::INFINITY);
+    let mut t_delta = Vector3::from_value(f32::INFINITY);
+    let mut step = Vector3::from_value(0i64);
+    for ax in 0..3 {
+        cell[ax] = (q[ax].floor() as i64).clamp(0, dims[ax] as i64 - 1);
+        if dir[ax] != 0. {
+            step[ax] = if dir[ax] > 0. { 1 } else { -1 };
+            t_delta[ax] = 1. / dir[ax].abs();
+            let next_border = (cell[ax] + (step[ax] > 0) as i64) as f32;
+            t_max[ax] = t + ((next_border - q[ax]) / dir[ax]).max(0.);
+        }
+    }
+    // The entry point is on the border in this axis, so make sure we start on the right side of it
+    if let Some(ax) = entered_ax {
+        if step[ax] != 0 {
+            cell[ax] = if step[ax] > 0 { 0 } else { dims[ax] as i64 - 1 };
+            t_max[ax] = t + t_delta[ax];
+        }
+    }
+
+    let mut t = t;
+    let mut crossed_ax = entered_ax.unwrap_or(0);
+    for _ in 0..TRAVERSAL_SAFETY_LIMIT {
+        let c = cell.cast::<u32>().unwrap();
+        if is_solid(c) {
+            return Some((c, t, crossed_ax));
+        }
+        let ax = if t_max.x < t_max.y {
+            if t_max.x < t_max.z {
+                0
+            } else {
+                2
+            }
+        } else if t_max.y < t_max.z {
+            1
+        } else {
+            2
+        };
+        if t_max[ax] > t_end {
+            return None;
+        }
+        t = t_max[ax];
+        t_max[ax] += t_delta[ax];
+        cell[ax] += step[ax];
+        crossed_ax = ax;
+        if cell[ax] < 0 || cell[ax] >= dims[ax] as i64 {
+            return None;
+        }
+    }
+    None
+}
+
+/// Cast a ray like `cast_ray`, but test each top level chunk with the LOD that the shader renders it at (the
+/// finest LOD whose loaded area contains it), so that results match what the player sees even far away.
+/// Error return means the ray entered a chunk whose data is not loaded.
+pub fn cast_ray_lod<const N: usize, VE: VoxelTypeEnum, MG: MemoryGrid + EditMemoryGridChunk<M>, M>(
+    world: &mut World<MG>,
+    // position relative to the bottom corner of the memory grid
+    start_pos: VoxelPos<f32>,
+    ray_dir: Vector3<f32>,
+    chunk_size: ChunkSize,
+    largest_chunk_lvl: u8,
+    params: CastRayLodParams,
+) -> Result<CastRayLodResult, ()>
+where
+    for<'a> MG::ChunkEditor<'a>: ChunkEditorVoxels<VE, N>,
+{
+    let dir = ray_dir.normalize();
+    let tlc_size = chunk_size.size().pow(largest_chunk_lvl as u32);
+    let start_tlc = world.mem_grid.start_tlc();
+
+    // Traverse top level chunks, then voxels within each one
+    let mut tlc_t = 0.;
+    let mut entered_ax = None;
+    let mut tlc_offset = start_pos.0.map(|a| (a / tlc_size as f32).floor() as i64);
+    for _ in 0..TRAVERSAL_SAFETY_LIMIT {
+        if tlc_t >= params.max_distance {
+            return Ok(CastRayLodResult::Miss);
+        }
+        let tlc = TlcPos(start_tlc.0 + tlc_offset.to_vec());
+        let tlc_min = tlc_offset.map(|a| (a * tlc_size as i64) as f32);
+
+        // Distance at which the ray leaves this TLC, and through which axis
+        let (exit_t, exit_ax) = (0..3)
+            .filter(|&ax| dir[ax] != 0.)
+            .map(|ax| {
+                let border = tlc_min[ax] + if dir[ax] > 0. { tlc_size as f32 } else { 0. };
+                ((border - start_pos.0[ax]) / dir[ax], ax)
+            })
+            .fold((f32::INFINITY, 0), |a, b| if b.0 < a.0 { b } else { a });
+
+        let Some(editor) = world.edit_chunk(tlc) else {
+            return Ok(CastRayLodResult::Miss);
+        };
+        let editor = editor.voxels();
+        let full_res_only = tlc_t < params.full_res_distance;
+        let Some((lod_i, lod)) = editor
+            .lods()
+            .iter()
+            .enumerate()
+            .take(if full_res_only { 1 } else { N })
+            .find_map(|(i, lod)| lod.as_ref().map(|lod| (i, lod)))
+        else {
+            return Ok(CastRayLodResult::Miss);
+        };
+        let data = lod.data().get().ok_or(())?;
+
+        let (lvl, sublvl, sublvl_y) = (lod.lvl(), lod.sublvl(), lod.sublvl_y());
+        let voxel_size = Vector3 {
+            x: chunk_size.size().pow(lvl as u32) << sublvl,
+            y: chunk_size.size().pow(lvl as u32) << sublvl_y,
+            z: chunk_size.size().pow(lvl as u32) << sublvl,
+        };
+        let dims = voxel_size.map(|s| (tlc_size / s) as u32);
+        let scale = voxel_size.map(|s| s as f32);
+        let entry = start_pos.0 + dir * tlc_t - tlc_min.to_vec();
+        let index_of = |pos: Point3<u32>| {
+            VoxelPosInLod {
+                pos,
+                lvl,
+                sublvl,
+                sublvl_y,
+            }
+            .index(chunk_size, largest_chunk_lvl)
+        };
+
+        if let Some((pos, t, ax)) = traverse_grid(
+            Point3::from_vec(entry.to_vec().div_element_wise(scale)),
+            dir.div_element_wise(scale),
+            dims,
+            tlc_t,
+            exit_t.min(params.max_distance),
+            entered_ax,
+            |pos| data.is_visible(index_of(pos)),
+        ) {
+            let index = index_of(pos);
+            return Ok(CastRayLodResult::Hit(LodRayIntersect {
+                tlc,
+                lod: lod_i,
+                lvl,
+                sublvl,
+                sublvl_y,
+                pos: VoxelPos(pos),
+                index,
+                voxel_id: data.voxel_id(index),
+                face: VoxelFace {
+                    ax: ax as u8,
+                    dir: dir[ax] < 0.0,
+                },
+                distance: t,
+                hit_point: VoxelPos(start_pos.0 + dir * t),
+            }));
+        }
+
+        tlc_offset[exit_ax] += if dir[exit_ax] > 0. { 1 } else { -1 };
+        entered_ax = Some(exit_ax);
+        tlc_t = exit_t;
+    }
+
+    Ok(CastRayLodResult::Miss)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_face_hit_point() {
+        // A ray going in +x hits the voxel's -x face
+        let face = VoxelFace { ax: 0, dir: false };
+        let voxel_min = Point3::new(2., 1., 1.);
+        assert_eq!(face.delta().0, Vector3::new(-1, 0, 0));
+        assert_eq!(
+            face_hit_point(
+                Point3::new(0.5, 1.5, 1.5),
+                Vector3::new(1., 0.5, 0.),
+                voxel_min,
+                face
+            ),
+            Point3::new(2., 2.25, 1.5)
+        );
+        // Starting inside the voxel
+        assert_eq!(
+            face_hit_point(
+                Point3::new(2.5, 1.5, 1.5),
+                Vector3::new(1., 0., 0.),
+                voxel_min,
+                face
+            ),
+            Point3::new(2.5, 1.5, 1.5)
+        );
+    }
+
+    #[test]
+    fn test_traverse_grid() {
+        let dims = Vector3::from_value(4);
+        let solid = |target: Point3<u32>| move |pos: Point3<u32>| pos == target;
+
+        // Straight along x from the middle of the first cell
+        let (pos, t, ax) = traverse_grid(
+            Point3::new(0.5, 1.5, 1.5),
+            Vector3::new(1., 0., 0.),
+            dims,
+            0.,
+            f32::INFINITY,
+            None,
+            solid(Point3::new(3, 1, 1)),
+        )
+        .unwrap();
+        assert_eq!(pos, Point3::new(3, 1, 1));
+        assert!((t - 2.5).abs() < 1e-5);
+        assert_eq!(ax, 0);
+
+        // Entering through the top face with cells twice as tall as the ray's units
+        let (pos, t, ax) = traverse_grid(
+            Point3::new(1.5, 4., 1.5),
+            Vector3::new(0., -0.5, 0.),
+            dims,
+            10.,
+            f32::INFINITY,
+            Some(1),
+            solid(Point3::new(1, 1, 1)),
+        )
+        .unwrap();
+        assert_eq!(pos, Point3::new(1, 1, 1));
+        assert!((t - 14.).abs() < 1e-5);
+        assert_eq!(ax, 1);
+
+        // Stops at t_end and when leaving the grid
+        let through = |t_end| {
+            traverse_grid(
+                Point3::new(0.5, 0.5, 0.5),
+                Vector3::new(1., 1., 0.).normalize(),
+                dims,
+                0.,
+                t_end,
+                None,
+                solid(Point3::new(3, 3, 0)),
+            )
+        };
+        assert!(through(1.).is_none());
+        assert_eq!(through(10.).unwrap().0, Point3::new(3, 3, 0));
+        assert!(traverse_grid(
+            Point3::new(0.5, 0.5, 0.5),
+            Vector3::new(-1., 0., 0.),
+            dims,
+            0.,
+            f32::INFINITY,
+            None,
+            solid(Point3::new(3, 0, 0)),
+        )
+        .is_none());
+    }
+}
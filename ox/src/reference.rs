@@ -0,0 +1,138 @@
+use crate::ray::{cast_ray, CastRayResult, ChunkEditorVoxels};
+use crate::timelapse::Frame;
+use crate::voxel_type::VoxelTypeEnum;
+use crate::world::camera::Camera;
+use crate::world::mem_grid::utils::ChunkSize;
+use crate::world::mem_grid::{EditMemoryGridChunk, MemoryGrid};
+use crate::world::{VoxelPos, World};
+use cgmath::{EuclideanSpace, Point3, Vector3};
+
+/// Render `world` as seen by `camera` on the CPU. This is slow and meant for golden image tests: compare
+/// its output with `compare_frames` against GPU output rendered with `SwapchainPipelineParams::albedo_only`
+/// (e.g. `sandbox::reference::render_albedo_scene`) to catch regressions in buffer packing, index math or drift
+/// between the shader and Rust layouts.
+///
+/// Pixels are mapped to rays the same way as in the shader (see `CameraUBO`). Each pixel is the color of the
+/// material of the first LOD 0 voxel its ray hits, or black if it hits nothing or leaves the LOD 0 area.
+/// Lighting, the crosshair and the highlight outline are not drawn.
+pub fn render_reference<const N: usize, VE: VoxelTypeEnum, MG, M>(
+    world: &mut World<MG>,
+    camera: &Camera,
+    width: u32,
+    height: u32,
+    chunk_size: ChunkSize,
+    largest_chunk_lvl: u8,
+) -> Frame
+where
+    MG: MemoryGrid + EditMemoryGridChunk<M>,
+    for<'a> MG::ChunkEditor<'a>: ChunkEditorVoxels<VE, N>,
+{
+    let materials = VE::materials();
+    let ubo = camera.to_gpu_repr();
+    let (eye, viewport_center, right_dir, up_dir) = (
+        Vector3::from(ubo.eye),
+        Vector3::from(ubo.viewport_center),
+        Vector3::from(ubo.right_dir),
+        Vector3::from(ubo.up_dir),
+    );
+
+    let mut rgba = Vec::with_capacity(width as usize * height as usize * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let u = x as f32 / width as f32 * 2. - 1.;
+            let v = y as f32 / height as f32 * 2. - 1.;
+            let pixel_ray_origin = viewport_center + right_dir * u - up_dir * v;
+            let pixel_ray_dir = pixel_ray_origin - eye;
+
+            let color = match cast_ray::<N, VE, MG, M>(
+                world,
+                VoxelPos(Point3::from_vec(pixel_ray_origin)),
+                pixel_ray_dir,
+                chunk_size,
+                largest_chunk_lvl,
+            ) {
+                Ok(CastRayResult::Hit(intersect)) => world
+                    .edit_chunk(intersect.tlc)
+                    .and_then(|editor| {
+                        let lod = editor.voxels().lods()[0].as_ref()?;
+                        let id = lod.data().get()?.voxel_id(intersect.index)?;
+                        Some(materials[id as usize].color)
+                    })
+                    .unwrap_or([0.; 3]),
+                _ => [0.; 3],
+            };
+            rgba.extend(color.map(|c| (c.clamp(0., 1.) * 255.).round() as u8));
+            rgba.push(255);
+        }
+    }
+
+    Frame {
+        width,
+        height,
+        rgba,
+    }
+}
+
+/// Differences between two frames of the same size, from `compare_frames`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameDiff {
+    /// Pixels where some channel differs by more than the tolerance
+    pub n_differing_pixels: usize,
+    pub n_pixels: usize,
+    /// Largest difference in any channel of any pixel
+    pub max_channel_diff: u8,
+}
+
+impl FrameDiff {
+    /// Whether at most `max_fraction` of the pixels differ, e.g. to allow for the crosshair or edge pixels
+    /// where the CPU and GPU disagree due to floating point error.
+    pub fn within(&self, max_fraction: f32) -> bool {
+        self.n_differing_pixels as f32 <= self.n_pixels as f32 * max_fraction
+    }
+}
+
+/// Compare two frames, counting pixels where a color channel differs by more than `tolerance`. Alpha is
+/// ignored. Panics if the frames have different sizes.
+pub fn compare_frames(a: &Frame, b: &Frame, tolerance: u8) -> FrameDiff {
+    assert_eq!(
+        (a.width, a.height),
+        (b.width, b.height),
+        "Compared frames have different sizes"
+    );
+    let mut diff = FrameDiff {
+        n_differing_pixels: 0,
+        n_pixels: a.width as usize * a.height as usize,
+        max_channel_diff: 0,
+    };
+    for (pa, pb) in a.rgba.chunks_exact(4).zip(b.rgba.chunks_exact(4)) {
+        let max = (0..3).map(|i| pa[i].abs_diff(pb[i])).max().unwrap();
+        diff.max_channel_diff = diff.max_channel_diff.max(max);
+        if max > tolerance {
+            diff.n_differing_pixels += 1;
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_frames() {
+        let frame = |rgba: Vec<u8>| Frame {
+            width: 2,
+            height: 1,
+            rgba,
+        };
+        let a = frame(vec![10, 20, 30, 255, 0, 0, 0, 255]);
+        let b = frame(vec![12, 20, 30, 0, 0, 100, 0, 255]);
+
+        let diff = compare_frames(&a, &b, 2);
+        assert_eq!(diff.n_differing_pixels, 1);
+        assert_eq!(diff.max_channel_diff, 100);
+        assert!(diff.within(0.5));
+        assert!(!diff.within(0.25));
+        assert_eq!(compare_frames(&a, &a, 0).n_differing_pixels, 0);
+    }
+}
@@ -0,0 +1,361 @@
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use vulkano::descriptor_set::layout::DescriptorType;
+use vulkano::shader::ShaderModule;
+
+/// Kind of descriptor a binding refers to. Used to type `Binding` handles so that, for example, a
+/// storage buffer binding can't accidentally be passed where a uniform buffer is expected.
+pub trait BindingKind {
+    const NAME: &'static str;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StorageImage;
+impl BindingKind for StorageImage {
+    const NAME: &'static str = "storage image";
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UniformBuffer;
+impl BindingKind for UniformBuffer {
+    const NAME: &'static str = "uniform buffer";
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StorageBuffer;
+impl BindingKind for StorageBuffer {
+    const NAME: &'static str = "storage buffer";
+}
+
+/// Handle to a shader binding registered with `BindingMapBuilder`. Handles can only be created by a builder, so
+/// every handle refers to a binding index that doesn't collide with any other handle from the same builder.
+/// Handles from separate builders can share an index.
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct Binding<K: BindingKind> {
+    index: u32,
+    _kind: PhantomData<K>,
+}
+
+impl<K: BindingKind> Clone for Binding<K> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<K: BindingKind> Copy for Binding<K> {}
+
+impl<K: BindingKind> Binding<K> {
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+}
+
+impl<K: BindingKind> From<Binding<K>> for u32 {
+    fn from(binding: Binding<K>) -> u32 {
+        binding.index
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BindingEntry {
+    pub name: String,
+    pub index: u32,
+    pub kind: &'static str,
+}
+
+/// Registers named shader bindings and checks them for collisions. Each renderer component's binding
+/// should be registered here and the returned handle passed to its constructor instead of a raw `u32`.
+///
+/// Bindings can either be assigned explicitly with `register_at` (when they are hard coded in the
+/// shader) or automatically with `register`, which uses the lowest free index.
+#[derive(Debug, Default)]
+pub struct BindingMapBuilder {
+    entries: Vec<BindingEntry>,
+}
+
+impl BindingMapBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a binding at the lowest index not used yet.
+    pub fn register<K: BindingKind>(&mut self, name: &str) -> Binding<K> {
+        let index = (0..).find(|i| self.entry_at(*i).is_none()).unwrap();
+        self.register_at(name, index)
+    }
+
+    /// Register a binding at a specific index. Panics if `index` or `name` is already registered.
+    pub fn register_at<K: BindingKind>(&mut self, name: &str, index: u32) -> Binding<K> {
+        if let Some(existing) = self.entry_at(index) {
+            panic!(
+                "Binding {} for \"{}\" collides with existing binding \"{}\"",
+                index, name, existing.name
+            );
+        }
+        assert!(
+            self.entries.iter().all(|e| e.name != name),
+            "Binding name \"{}\" registered more than once",
+            name
+        );
+        self.entries.push(BindingEntry {
+            name: name.to_string(),
+            index,
+            kind: K::NAME,
+        });
+        Binding {
+            index,
+            _kind: PhantomData,
+        }
+    }
+
+    fn entry_at(&self, index: u32) -> Option<&BindingEntry> {
+        self.entries.iter().find(|e| e.index == index)
+    }
+
+    pub fn build(mut self) -> BindingMap {
+        self.entries.sort_by_key(|e| e.index);
+        BindingMap {
+            entries: self.entries,
+        }
+    }
+}
+
+/// Final set of bindings produced by `BindingMapBuilder`. `Display` prints a table of all bindings,
+/// which is useful for checking against the shader's `layout(binding = ...)` declarations.
+#[derive(Debug, Clone)]
+pub struct BindingMap {
+    entries: Vec<BindingEntry>, // sorted by index
+}
+
+impl BindingMap {
+    pub fn entries(&self) -> &[BindingEntry] {
+        &self.entries
+    }
+
+    pub fn get(&self, name: &str) -> Option<&BindingEntry> {
+        self.entries.iter().find(|e| e.name == name)
+    }
+
+    /// Human-readable report of all bindings (same as the `Display` impl)
+    pub fn report(&self) -> String {
+        self.to_string()
+    }
+
+    /// Check the bindings against the descriptors the shader declares (from its reflection info). Every
+    /// binding the shader uses has to be registered with the same kind. Registered bindings the shader
+    /// doesn't use are fine, since the shader compiler drops unused declarations. Returns a description of
+    /// every mismatch.
+    pub fn check_shader(&self, shader: &Arc<ShaderModule>) -> Result<(), String> {
+        let entry_point = shader
+            .single_entry_point()
+            .ok_or_else(|| "Shader doesn't have exactly one entry point".to_string())?;
+        self.check_reflected(
+            entry_point
+                .info()
+                .descriptor_binding_requirements
+                .iter()
+                .map(|(&(set, binding), requirements)| {
+                    let kind = requirements
+                        .descriptor_types
+                        .iter()
+                        .find_map(|ty| kind_name(*ty));
+                    (set, binding, kind)
+                }),
+        )
+    }
+
+    /// `check_shader` for reflected `(set, binding, kind name)` descriptors. The kind is `None` for
+    /// descriptor types there is no `BindingKind` for.
+    fn check_reflected(
+        &self,
+        reflected: impl IntoIterator<Item = (u32, u32, Option<&'static str>)>,
+    ) -> Result<(), String> {
+        let mut reflected: Vec<_> = reflected.into_iter().collect();
+        reflected.sort_by_key(|(set, binding, _)| (*set, *binding));
+
+        let mut errors = vec![];
+        for (set, index, kind) in reflected {
+            if set != 0 {
+                errors.push(format!(
+                    "Shader declares binding {} in descriptor set {}, but only set 0 is bound",
+                    index, set
+                ));
+                continue;
+            }
+            let Some(kind) = kind else {
+                errors.push(format!(
+                    "Shader binding {} has a descriptor type with no binding kind",
+                    index
+                ));
+                continue;
+            };
+            match self.entries.iter().find(|e| e.index == index) {
+                None => errors.push(format!(
+                    "Shader binding {} ({}) isn't registered",
+                    index, kind
+                )),
+                Some(entry) if entry.kind != kind => errors.push(format!(
+                    "Binding {} \"{}\" is registered as a {} but the shader declares a {}",
+                    index, entry.name, entry.kind, kind
+                )),
+                Some(_) => {}
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+}
+
+/// `BindingKind::NAME` of the kind that corresponds to `descriptor_type`, if there is one
+fn kind_name(descriptor_type: DescriptorType) -> Option<&'static str> {
+    match descriptor_type {
+        DescriptorType::StorageImage => Some(StorageImage::NAME),
+        DescriptorType::UniformBuffer => Some(UniformBuffer::NAME),
+        DescriptorType::StorageBuffer => Some(StorageBuffer::NAME),
+        _ => None,
+    }
+}
+
+/// Define a shader's bindings in one place. This generates a struct with a `Binding` handle for each
+/// binding, a `register` function that registers all of them with a `BindingMapBuilder`, and a `TABLE` of
+/// `(index, name, kind)` for each binding. Set `SwapchainPipelineParams::bindings` to the built map to check
+/// the shader's actual bindings against it when the pipeline is created.
+///
+/// Kinds are `image` (`StorageImage`), `uniform` (`UniformBuffer`) and `storage` (`StorageBuffer`). The
+/// field names are also the binding names, so they show up in `ShaderDefs::with_bindings` as
+/// `BINDING_<NAME>`.
+///
+/// ```ignore
+/// ox::shader_interface! {
+///     pub struct RaytraceBindings {
+///         0 => image image,
+///         1 => storage materials,
+///         2 => uniform camera,
+///     }
+/// }
+///
+/// let mut builder = BindingMapBuilder::new();
+/// let bindings = RaytraceBindings::register(&mut builder);
+/// let camera = RendererCamera::new(bindings.camera, memory_allocator);
+/// ```
+#[macro_export]
+macro_rules! shader_interface {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $name:ident {
+            $($index:literal => $kind:ident $field:ident),* $(,)?
+        }
+    ) => {
+        $(#[$attr])*
+        #[derive(Debug, Clone, Copy)]
+        $vis struct $name {
+            $(pub $field: $crate::renderer::binding::Binding<$crate::shader_interface!(@kind $kind)>,)*
+        }
+
+        impl $name {
+            /// `(index, name, kind)` of each binding
+            #[allow(dead_code)]
+            pub const TABLE: &'static [(u32, &'static str, &'static str)] = &[
+                $((
+                    $index,
+                    stringify!($field),
+                    <$crate::shader_interface!(@kind $kind) as $crate::renderer::binding::BindingKind>::NAME,
+                ),)*
+            ];
+
+            /// Register every binding at its index. Panics if any of them collide with bindings already
+            /// registered with `builder`.
+            pub fn register(builder: &mut $crate::renderer::binding::BindingMapBuilder) -> Self {
+                $name {
+                    $($field: builder.register_at(stringify!($field), $index),)*
+                }
+            }
+        }
+    };
+    (@kind image) => { $crate::renderer::binding::StorageImage };
+    (@kind uniform) => { $crate::renderer::binding::UniformBuffer };
+    (@kind storage) => { $crate::renderer::binding::StorageBuffer };
+}
+
+impl fmt::Display for BindingMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Shader bindings:")?;
+        for entry in self.entries.iter() {
+            writeln!(
+                f,
+                "  {:>3}  {:<16}  {}",
+                entry.index, entry.kind, entry.name
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_bindings() {
+        let mut builder = BindingMapBuilder::new();
+        let image: Binding<StorageImage> = builder.register_at("image", 0);
+        let camera: Binding<UniformBuffer> = builder.register_at("camera", 2);
+        let materials: Binding<StorageBuffer> = builder.register("materials");
+        let ubo: Binding<UniformBuffer> = builder.register("ubo");
+        assert_eq!(
+            [
+                image.index(),
+                camera.index(),
+                materials.index(),
+                ubo.index()
+            ],
+            [0, 2, 1, 3]
+        );
+
+        let map = builder.build();
+        assert_eq!(
+            map.entries().iter().map(|e| e.index).collect::<Vec<_>>(),
+            vec![0, 1, 2, 3]
+        );
+        assert_eq!(map.get("camera").unwrap().kind, UniformBuffer::NAME);
+        assert!(map.report().contains("materials"));
+    }
+
+    #[test]
+    fn test_check_reflected() {
+        let mut builder = BindingMapBuilder::new();
+        let _: Binding<StorageImage> = builder.register_at("image", 0);
+        let _: Binding<StorageBuffer> = builder.register_at("materials", 1);
+        let _: Binding<UniformBuffer> = builder.register_at("unused", 5);
+        let map = builder.build();
+
+        assert!(map
+            .check_reflected([
+                (0, 1, Some(StorageBuffer::NAME)),
+                (0, 0, Some(StorageImage::NAME))
+            ])
+            .is_ok());
+
+        let err = map
+            .check_reflected([
+                (0, 1, Some(UniformBuffer::NAME)),
+                (0, 2, Some(StorageBuffer::NAME)),
+                (1, 0, Some(StorageImage::NAME)),
+            ])
+            .unwrap_err();
+        assert_eq!(err.lines().count(), 3);
+        assert!(err.contains("\"materials\""));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_binding_collision() {
+        let mut builder = BindingMapBuilder::new();
+        let _: Binding<StorageBuffer> = builder.register_at("a", 4);
+        let _: Binding<StorageBuffer> = builder.register_at("b", 4);
+    }
+}
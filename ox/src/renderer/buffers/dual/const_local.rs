@@ -24,4 +24,6 @@ impl<T: ?Sized> BufferScheme for ConstantDeviceLocalBuffer<T> {
     fn record_repeated_transfer<L, A: CommandBufferAllocator>(&self, _: &mut AutoCommandBufferBuilder<L, A>) { }
 
     fn record_transfer_jit<L, A: CommandBufferAllocator>(&mut self, _: &mut AutoCommandBufferBuilder<L, A>) { }
+
+    fn pending_transfer_bytes(&self) -> u64 { 0 }
 }
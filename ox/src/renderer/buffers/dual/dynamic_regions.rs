@@ -1,14 +1,20 @@
+use crate::renderer::buffers::dual::{staging_slice, write_staging};
 use crate::renderer::buffers::BufferScheme;
 use derive_new::new;
 use getset::Getters;
+use hashbrown::HashMap;
 use smallvec::SmallVec;
 use std::cmp::max;
+use std::fmt;
 use std::mem;
 use std::mem::size_of;
+use std::sync::Arc;
+use std::thread;
 use vulkano::buffer::{BufferContents, Subbuffer};
 use vulkano::command_buffer::allocator::CommandBufferAllocator;
 use vulkano::command_buffer::{AutoCommandBufferBuilder, BufferCopy, CopyBufferInfo};
 use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::memory::allocator::MemoryAllocator;
 
 /// Dual buffer scheme where different regions are copied each frame
 #[derive(new, Debug, Getters)]
@@ -17,6 +23,88 @@ pub struct DualBufferWithDynamicCopyRegions<T: BufferContents> {
     device_local: Subbuffer<[T]>,
     #[get = "pub"]
     copy_regions: Vec<BufferCopy>,
+    /// If set, `staging` only holds the regions written since the last transfer (see `pool_staging`).
+    /// Otherwise it mirrors `device_local`.
+    #[new(default)]
+    pool: Option<StagingPool>,
+}
+
+/// Number of transfers over which the peak staging usage is measured before a pool is shrunk
+const STAGING_SHRINK_WINDOW: u32 = 256;
+
+/// Picks the capacity (in elements) of a pooled staging buffer: it grows to fit the regions written before a
+/// transfer and shrinks again once a whole window of transfers used much less than it.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct StagingSizer {
+    capacity: usize,
+    min_capacity: usize,
+    // Most elements used by a single transfer in the current window
+    peak: usize,
+    transfers: u32,
+}
+
+impl StagingSizer {
+    pub fn new(capacity: usize) -> Self {
+        StagingSizer {
+            capacity,
+            min_capacity: capacity,
+            peak: 0,
+            transfers: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Capacity needed to hold `used` elements, growing to the next power of two if needed
+    pub fn fit(&mut self, used: usize) -> usize {
+        if used > self.capacity {
+            self.capacity = used.next_power_of_two();
+        }
+        self.capacity
+    }
+
+    /// Record a transfer that used `used` elements. Returns the new capacity if the pool should shrink, which is
+    /// when every transfer in the last window used at most a quarter of it. It never shrinks below the initial
+    /// capacity.
+    pub fn end_transfer(&mut self, used: usize) -> Option<usize> {
+        self.peak = max(self.peak, used);
+        self.transfers += 1;
+        if self.transfers < STAGING_SHRINK_WINDOW {
+            return None;
+        }
+        let target = max(
+            self.min_capacity,
+            (mem::take(&mut self.peak) * 2).next_power_of_two(),
+        );
+        self.transfers = 0;
+        if target * 2 <= self.capacity {
+            self.capacity = target;
+            Some(target)
+        } else {
+            None
+        }
+    }
+}
+
+struct StagingPool {
+    allocator: Arc<dyn MemoryAllocator>,
+    sizer: StagingSizer,
+    // Elements of the staging buffer written since the last transfer
+    used: usize,
+    // Staging offset of each (device local offset, length) written since the last transfer, so writing the same
+    // region twice reuses its slot and only the latest data is copied
+    slots: HashMap<(usize, usize), usize>,
+}
+
+impl fmt::Debug for StagingPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StagingPool")
+            .field("sizer", &self.sizer)
+            .field("used", &self.used)
+            .finish()
+    }
 }
 
 impl<T: BufferContents> BufferScheme for DualBufferWithDynamicCopyRegions<T> {
@@ -47,6 +135,19 @@ impl<T: BufferContents> BufferScheme for DualBufferWithDynamicCopyRegions<T> {
                 })
                 .unwrap();
         }
+
+        if let Some(pool) = self.pool.as_mut() {
+            // The copy recorded above keeps the current staging buffer alive, so it can be replaced right away
+            let used = mem::take(&mut pool.used);
+            pool.slots.clear();
+            if let Some(capacity) = pool.sizer.end_transfer(used) {
+                self.staging = staging_slice(Arc::clone(&pool.allocator), capacity);
+            }
+        }
+    }
+
+    fn pending_transfer_bytes(&self) -> u64 {
+        self.copy_regions.iter().map(|r| r.size).sum()
     }
 }
 
@@ -60,16 +161,18 @@ impl<T: BufferContents + Copy + std::fmt::Debug> DualBufferWithDynamicCopyRegion
         &mut self,
         copies: I,
     ) {
-        let mut write = self.staging.write().unwrap();
+        if self.pool.is_some() {
+            return self.update_pooled_staging_buffer_and_prep_copy(copies);
+        }
+        let mut write = write_staging(&self.staging);
 
+        let mut writes = vec![];
         for (src, region) in copies {
             // Regions here are in bytes, so we need to rescale them to be indices
-            // copy from src to staging buffer
             let src_offset = region.src_offset as usize / size_of::<T>();
             let dst_offset = region.dst_offset as usize / size_of::<T>();
             let size = max(1, (region.size as usize) / size_of::<T>());
-            write[dst_offset..dst_offset + size]
-                .copy_from_slice(&src[src_offset..src_offset + size]);
+            writes.push((&src[src_offset..src_offset + size], dst_offset));
 
             // queue copy from staging buffer to GPU
             self.copy_regions.push(BufferCopy {
@@ -79,5 +182,181 @@ impl<T: BufferContents + Copy + std::fmt::Debug> DualBufferWithDynamicCopyRegion
                 ..Default::default()
             });
         }
+
+        write_regions(&mut write, writes);
+    }
+
+    /// Replace the staging buffer, which mirrors the whole device local buffer, with a pooled one that only holds
+    /// the regions written before each transfer, starting with room for `capacity` elements. The pool is reused
+    /// for every transfer and resized automatically (see `StagingSizer`), which saves most of the host memory
+    /// when only a small part of the buffer changes each frame. Must be called before any regions are written.
+    pub fn pool_staging(&mut self, allocator: Arc<dyn MemoryAllocator>, capacity: usize) {
+        assert!(
+            self.copy_regions.is_empty(),
+            "Staging can only be pooled before any regions are written"
+        );
+        let capacity = capacity.max(1);
+        self.staging = staging_slice(Arc::clone(&allocator), capacity);
+        self.pool = Some(StagingPool {
+            allocator,
+            sizer: StagingSizer::new(capacity),
+            used: 0,
+            slots: HashMap::new(),
+        });
+    }
+
+    /// Capacity of the staging buffer in elements
+    pub fn staging_len(&self) -> usize {
+        self.staging.len() as usize
+    }
+
+    fn update_pooled_staging_buffer_and_prep_copy<
+        'a,
+        I: IntoIterator<Item = (&'a [T], &'a BufferCopy)>,
+    >(
+        &mut self,
+        copies: I,
+    ) {
+        let pool = self.pool.as_mut().unwrap();
+        let used_before = pool.used;
+
+        // Assign staging slots first so the buffer only has to grow once for the whole batch
+        let mut writes = vec![];
+        for (src, region) in copies {
+            let src_offset = region.src_offset as usize / size_of::<T>();
+            let dst_offset = region.dst_offset as usize / size_of::<T>();
+            let size = max(1, (region.size as usize) / size_of::<T>());
+            let slot = match pool.slots.get(&(dst_offset, size)) {
+                Some(&slot) => slot,
+                None => {
+                    let slot = pool.used;
+                    pool.used += size;
+                    pool.slots.insert((dst_offset, size), slot);
+                    self.copy_regions.push(BufferCopy {
+                        src_offset: (slot * size_of::<T>()) as u64,
+                        dst_offset: region.dst_offset as u64,
+                        size: region.size as u64,
+                        ..Default::default()
+                    });
+                    slot
+                }
+            };
+            writes.push((&src[src_offset..src_offset + size], slot));
+        }
+
+        let capacity = pool.sizer.fit(pool.used);
+        if capacity > self.staging.len() as usize {
+            // Keep the regions that were already written since the last transfer
+            let grown = staging_slice(Arc::clone(&pool.allocator), capacity);
+            write_staging(&grown)[..used_before]
+                .copy_from_slice(&self.staging.read().unwrap()[..used_before]);
+            self.staging = grown;
+        }
+
+        write_regions(&mut write_staging(&self.staging), writes);
+    }
+}
+
+/// Total size of the writes in one `write_regions` call above which they are split across threads, e.g.
+/// after a teleport when many whole chunks are updated at once
+const PARALLEL_WRITE_MIN_BYTES: usize = 4 << 20;
+
+/// Copy each `(src, dst_offset)` into `dst` in order. Large batches of writes that don't overlap are split
+/// across threads.
+fn write_regions<T: Copy + Send + Sync>(dst: &mut [T], writes: Vec<(&[T], usize)>) {
+    let total_len: usize = writes.iter().map(|(src, _)| src.len()).sum();
+    let n_threads = thread::available_parallelism().map_or(1, |n| n.get());
+
+    if total_len * size_of::<T>() >= PARALLEL_WRITE_MIN_BYTES && n_threads > 1 {
+        let mut sorted = writes.clone();
+        sorted.sort_by_key(|(_, offset)| *offset);
+        if sorted.windows(2).all(|w| w[0].1 + w[0].0.len() <= w[1].1) {
+            // Split `dst` into a disjoint slice for each write
+            let mut jobs = Vec::with_capacity(sorted.len());
+            let (mut rest, mut rest_start) = (dst, 0);
+            for (src, offset) in sorted {
+                let (target, tail) = rest[offset - rest_start..].split_at_mut(src.len());
+                jobs.push((src, target));
+                (rest, rest_start) = (tail, offset + src.len());
+            }
+
+            let len_per_thread = total_len / n_threads + 1;
+            thread::scope(|s| {
+                let mut group = vec![];
+                let mut group_len = 0;
+                for (src, target) in jobs {
+                    group_len += src.len();
+                    group.push((src, target));
+                    if group_len >= len_per_thread {
+                        let group = mem::take(&mut group);
+                        s.spawn(move || {
+                            for (src, target) in group {
+                                target.copy_from_slice(src);
+                            }
+                        });
+                        group_len = 0;
+                    }
+                }
+                for (src, target) in group {
+                    target.copy_from_slice(src);
+                }
+            });
+            return;
+        }
+    }
+
+    for (src, offset) in writes {
+        dst[offset..offset + src.len()].copy_from_slice(src);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_staging_sizer() {
+        let mut sizer = StagingSizer::new(64);
+        assert_eq!(sizer.fit(10), 64);
+        assert_eq!(sizer.fit(100), 128);
+        assert_eq!(sizer.end_transfer(100), None);
+
+        // Shrinks once a whole window used little of the capacity, but not below the initial capacity
+        assert_eq!(sizer.fit(1000), 1024);
+        for _ in 0..STAGING_SHRINK_WINDOW - 2 {
+            assert_eq!(sizer.end_transfer(20), None);
+        }
+        assert_eq!(sizer.end_transfer(1000), None);
+        for _ in 0..STAGING_SHRINK_WINDOW - 1 {
+            assert_eq!(sizer.end_transfer(100), None);
+        }
+        assert_eq!(sizer.end_transfer(20), Some(256));
+        for _ in 0..STAGING_SHRINK_WINDOW - 1 {
+            assert_eq!(sizer.end_transfer(0), None);
+        }
+        assert_eq!(sizer.end_transfer(0), Some(64));
+        assert_eq!(sizer.capacity(), 64);
+    }
+
+    #[test]
+    fn test_write_regions() {
+        let src: Vec<u32> = (0..(PARALLEL_WRITE_MIN_BYTES as u32)).collect();
+        let mut dst = vec![0u32; src.len() * 2];
+        let writes = (0..64)
+            .rev()
+            .map(|i| {
+                let len = src.len() / 64;
+                (&src[i * len..(i + 1) * len], i * len * 2)
+            })
+            .collect();
+        write_regions(&mut dst, writes);
+        assert_eq!(dst[1], 1);
+        assert_eq!(dst[src.len() / 64 * 2 + 5], src.len() as u32 / 64 + 5);
+        assert_eq!(dst[src.len() / 64 + 5], 0);
+
+        // Overlapping writes are applied in order
+        let mut dst = vec![0u32; 4];
+        write_regions(&mut dst, vec![(&[1, 1, 1][..], 0), (&[2, 2][..], 1)]);
+        assert_eq!(dst, vec![1, 2, 2, 0]);
     }
 }
@@ -1,16 +1,24 @@
 use derive_new::new;
-use vulkano::buffer::{BufferContents, BufferWriteGuard, Subbuffer};
+use vulkano::buffer::{BufferContents, BufferReadGuard, BufferWriteGuard, Subbuffer};
 use vulkano::command_buffer::{AutoCommandBufferBuilder, CopyBufferInfo};
 use vulkano::command_buffer::allocator::CommandBufferAllocator;
 use vulkano::descriptor_set::WriteDescriptorSet;
 use crate::renderer::buffers::{BufferScheme};
+use crate::renderer::buffers::dual::write_staging;
 
 
-/// Dual buffer scheme where the whole staging buffer is copied to the device local buffer every frame
+/// Dual buffer scheme where the whole staging buffer is copied to the device local buffer in each frame's
+/// transfer after it was written. Writes bump a generation counter, so frames where it wasn't written don't
+/// copy anything.
 #[derive(new)]
 pub struct DualBufferWithFullCopy<T: ?Sized> {
     staging: Subbuffer<T>,
     device_local: Subbuffer<T>,
+    // Starts ahead of `transferred_generation` so the initial data is copied in the first transfer
+    #[new(value = "1")]
+    generation: u64,
+    #[new(default)]
+    transferred_generation: u64,
 }
 
 
@@ -22,27 +30,72 @@ impl<T: ?Sized> BufferScheme for DualBufferWithFullCopy<T> {
         ))
     }
 
-    fn record_repeated_transfer<L, A: CommandBufferAllocator>(&self, builder: &mut AutoCommandBufferBuilder<L, A>) {
+    // Copied in `record_transfer_jit` instead so that unchanged buffers can be skipped
+    fn record_repeated_transfer<L, A: CommandBufferAllocator>(&self, _: &mut AutoCommandBufferBuilder<L, A>) { }
+
+    fn record_transfer_jit<L, A: CommandBufferAllocator>(&mut self, builder: &mut AutoCommandBufferBuilder<L, A>) {
+        if !self.is_dirty() {
+            return;
+        }
         builder
             .copy_buffer(CopyBufferInfo::buffers(
                 self.staging.clone(),
                 self.device_local.clone(),
             ))
             .unwrap();
+        self.transferred_generation = self.generation;
     }
 
-    fn record_transfer_jit<L, A: CommandBufferAllocator>(&mut self, _: &mut AutoCommandBufferBuilder<L, A>) { }
+    fn pending_transfer_bytes(&self) -> u64 {
+        if self.is_dirty() {
+            self.staging.size()
+        } else {
+            0
+        }
+    }
+}
+
+impl<T: ?Sized> DualBufferWithFullCopy<T> {
+    /// Incremented every time the staging buffer is written
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Whether the staging buffer was written since it was last copied to the device local buffer
+    pub fn is_dirty(&self) -> bool {
+        self.generation != self.transferred_generation
+    }
 }
 
 
 impl<T: BufferContents> DualBufferWithFullCopy<T> {
+    /// Marks the buffer dirty whether or not anything is actually changed through the guard
     pub fn write_staging(&mut self) -> BufferWriteGuard<'_, T> {
-        self.staging.write().unwrap()
+        self.generation += 1;
+        write_staging(&self.staging)
+    }
+
+    pub fn read_staging(&self) -> BufferReadGuard<'_, T> {
+        self.staging.read().unwrap()
+    }
+
+    /// Write `value` only if it differs from the current value, so an unchanged value isn't copied again.
+    /// Returns whether it was written.
+    pub fn set_if_changed(&mut self, value: T) -> bool
+    where
+        T: PartialEq,
+    {
+        if *self.read_staging() == value {
+            return false;
+        }
+        *self.write_staging() = value;
+        true
     }
 }
 
 impl<T: BufferContents> DualBufferWithFullCopy<[T]> {
     pub fn write_staging(&mut self) -> BufferWriteGuard<'_, [T]> {
-        self.staging.write().unwrap()
+        self.generation += 1;
+        write_staging(&self.staging)
     }
 }
\ No newline at end of file
@@ -1,5 +1,5 @@
 use std::sync::Arc;
-use vulkano::buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::buffer::{Buffer, BufferContents, BufferCreateInfo, BufferUsage, BufferWriteGuard, Subbuffer};
 use vulkano::command_buffer::allocator::CommandBufferAllocator;
 use vulkano::command_buffer::{AutoCommandBufferBuilder, CopyBufferInfo};
 use vulkano::memory::allocator::{AllocationCreateInfo, MemoryAllocatePreference, MemoryAllocator, MemoryTypeFilter};
@@ -160,4 +160,44 @@ impl<T: BufferContents> DualBuffer<[T]> {
             vec![],
         )
     }
-}
\ No newline at end of file
+}
+
+/// Host visible staging buffer with room for `len` elements
+pub(crate) fn staging_slice<T: BufferContents>(
+    allocator: Arc<dyn MemoryAllocator>,
+    len: usize,
+) -> Subbuffer<[T]> {
+    Buffer::new_slice(
+        allocator,
+        BufferCreateInfo {
+            usage: BufferUsage::TRANSFER_SRC,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter {
+                required_flags: MemoryPropertyFlags::HOST_VISIBLE,
+                ..Default::default()
+            },
+            allocate_preference: MemoryAllocatePreference::AlwaysAllocate,
+            ..Default::default()
+        },
+        len as u64,
+    )
+    .unwrap()
+}
+
+/// Write access to a staging buffer. Staging buffers are read by the transfer that copies them to the device
+/// local buffers, so they may only be written once that transfer has finished, which
+/// `Renderer::start_updating_staging_buffers` guarantees by waiting for it. Panics (instead of writing data the
+/// transfer is still reading) if that wait was skipped.
+pub(crate) fn write_staging<T: BufferContents + ?Sized>(
+    staging: &Subbuffer<T>,
+) -> BufferWriteGuard<'_, T> {
+    staging.write().unwrap_or_else(|e| {
+        panic!(
+            "Staging buffer written while a transfer may still be reading it ({}). Update staging buffers \
+             through `Renderer::start_updating_staging_buffers`, which waits for the last transfer.",
+            e
+        )
+    })
+}
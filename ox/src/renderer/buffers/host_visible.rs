@@ -0,0 +1,82 @@
+use crate::renderer::buffers::BufferScheme;
+use std::sync::Arc;
+use vulkano::buffer::{
+    Buffer, BufferContents, BufferCreateInfo, BufferUsage, BufferWriteGuard, Subbuffer,
+};
+use vulkano::command_buffer::allocator::CommandBufferAllocator;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::memory::allocator::{
+    AllocationCreateInfo, MemoryAllocatePreference, MemoryAllocator, MemoryTypeFilter,
+};
+use vulkano::memory::MemoryPropertyFlags;
+
+/// Buffer scheme with a single host visible buffer that is read directly by shaders, so there is
+/// nothing to transfer. Only suitable for small buffers that change every frame (e.g. the camera),
+/// since reads may be slower than from device local memory. The buffer must not be written while
+/// the GPU may be reading it, i.e. only between the end of one compute pass and the submission of the
+/// next (see `BufferScheme::late_latch`).
+pub struct HostVisibleBuffer<T: ?Sized> {
+    buffer: Subbuffer<T>,
+}
+
+impl<T: ?Sized> BufferScheme for HostVisibleBuffer<T> {
+    fn bind(&self, descriptor_writes: &mut Vec<WriteDescriptorSet>, binding: u32) {
+        descriptor_writes.push(WriteDescriptorSet::buffer(binding, self.buffer.clone()))
+    }
+
+    fn record_repeated_transfer<L, A: CommandBufferAllocator>(
+        &self,
+        _: &mut AutoCommandBufferBuilder<L, A>,
+    ) {
+    }
+
+    fn record_transfer_jit<L, A: CommandBufferAllocator>(
+        &mut self,
+        _: &mut AutoCommandBufferBuilder<L, A>,
+    ) {
+    }
+
+    fn pending_transfer_bytes(&self) -> u64 {
+        0
+    }
+}
+
+impl<T: BufferContents> HostVisibleBuffer<T> {
+    /// Allocate the buffer in host visible memory, preferably memory that is also device local. Returns
+    /// `None` if the device has no host visible memory usable for this kind of buffer, in which case a
+    /// `DualBuffer` should be used instead.
+    pub fn from_data(
+        data: T,
+        allocator: Arc<dyn MemoryAllocator>,
+        is_uniform: bool,
+    ) -> Option<HostVisibleBuffer<T>> {
+        Buffer::from_data(
+            allocator,
+            BufferCreateInfo {
+                usage: if is_uniform {
+                    BufferUsage::UNIFORM_BUFFER
+                } else {
+                    BufferUsage::STORAGE_BUFFER
+                },
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter {
+                    required_flags: MemoryPropertyFlags::HOST_VISIBLE,
+                    preferred_flags: MemoryPropertyFlags::DEVICE_LOCAL,
+                    ..Default::default()
+                },
+                allocate_preference: MemoryAllocatePreference::AlwaysAllocate,
+                ..Default::default()
+            },
+            data,
+        )
+        .ok()
+        .map(|buffer| HostVisibleBuffer { buffer })
+    }
+
+    pub fn write(&mut self) -> BufferWriteGuard<'_, T> {
+        self.buffer.write().unwrap()
+    }
+}
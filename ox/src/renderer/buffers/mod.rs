@@ -3,6 +3,9 @@ use vulkano::command_buffer::{AutoCommandBufferBuilder};
 use vulkano::descriptor_set::WriteDescriptorSet;
 
 pub mod dual;
+mod host_visible;
+
+pub use host_visible::HostVisibleBuffer;
 
 
 pub trait BufferScheme {
@@ -17,4 +20,20 @@ pub trait BufferScheme {
         &mut self,
         builder: &mut AutoCommandBufferBuilder<L, A>,
     );
+
+    /// Number of bytes that will be copied to the device local buffer by the next transfer. The renderer skips
+    /// a frame's transfer entirely when this is 0 for every component, so it must be nonzero whenever
+    /// `record_transfer_jit` would record anything.
+    fn pending_transfer_bytes(&self) -> u64;
+
+    /// Called by `Renderer::draw_frame` after the previous frame's compute pass finished and right before
+    /// the next one is submitted. Schemes that shaders read directly from host memory (see
+    /// `HostVisibleBuffer`) write their latest data here so it is as fresh as possible.
+    fn late_latch(&mut self) {}
+
+    /// Whether the buffers were replaced since the last call, so their binding has to be rewritten (see
+    /// `DataComponentSet::take_dirty_bindings`). Resets the flag.
+    fn take_dirty(&mut self) -> bool {
+        false
+    }
 }
\ No newline at end of file
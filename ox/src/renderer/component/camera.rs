@@ -1,39 +1,225 @@
+use crate::renderer::binding::{Binding, UniformBuffer};
 use crate::renderer::buffers::dual::{DualBuffer, DualBufferWithFullCopy};
-use crate::renderer::component::DataComponent;
+use crate::renderer::buffers::{BufferScheme, HostVisibleBuffer};
+use crate::renderer::component::{DataComponent, DataComponentSet};
 use crate::world::camera::Camera;
 use crate::world::VoxelPos;
-use cgmath::{Angle, Array, Point3, Rad, Vector3};
+use cgmath::{Angle, Rad, Vector3};
 use std::sync::Arc;
 use vulkano::buffer::BufferContents;
+use vulkano::command_buffer::allocator::CommandBufferAllocator;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::descriptor_set::WriteDescriptorSet;
 use vulkano::memory::allocator::MemoryAllocator;
 
-pub type RendererCamera = DataComponent<DualBufferWithFullCopy<CameraUBO>>;
+pub type RendererCamera = DataComponent<CameraBufferScheme>;
 impl RendererCamera {
-    pub fn new(binding: u32, allocator: Arc<dyn MemoryAllocator>) -> Self {
+    pub fn new(binding: Binding<UniformBuffer>, allocator: Arc<dyn MemoryAllocator>) -> Self {
         DataComponent {
-            buffer_scheme: DualBuffer::from_data(CameraUBO::new_blank(), allocator, true)
-                .with_full_copy(),
-            binding,
+            buffer_scheme: CameraBufferScheme::Staged(
+                DualBuffer::from_data(CameraUBO::new_blank(), allocator, true).with_full_copy(),
+            ),
+            binding: binding.index(),
         }
     }
 
+    /// Like `new`, but the camera is written to host visible memory that the shader reads directly, right
+    /// before each frame's compute pass is submitted (see `DataComponentSet::late_latch`). This skips the
+    /// transfer, so camera movement shows up a frame sooner. Falls back to `new` if the device can't
+    /// provide a host visible uniform buffer.
+    pub fn new_late_latched(
+        binding: Binding<UniformBuffer>,
+        allocator: Arc<dyn MemoryAllocator>,
+    ) -> Self {
+        match HostVisibleBuffer::from_data(CameraUBO::new_blank(), Arc::clone(&allocator), true) {
+            Some(buffer) => DataComponent {
+                buffer_scheme: CameraBufferScheme::LateLatched {
+                    buffer,
+                    latest: CameraUBO::new_blank(),
+                },
+                binding: binding.index(),
+            },
+            None => {
+                println!(
+                    "WARNING: No host visible memory for the camera, it will not be late latched"
+                );
+                Self::new(binding, allocator)
+            }
+        }
+    }
+
+    pub fn is_late_latched(&self) -> bool {
+        matches!(self.buffer_scheme, CameraBufferScheme::LateLatched { .. })
+    }
+
+    /// Set the camera for the next frame. When late latched this can be called up until `draw_frame`.
     pub fn update_staging_buffer(&mut self, camera: &Camera) {
-        let mut w = self.buffer_scheme.write_staging();
-        w.update(camera, VoxelPos(Point3::<f32>::from_value(0.)));
+        match &mut self.buffer_scheme {
+            CameraBufferScheme::Staged(buffer) => {
+                buffer.set_if_changed(camera.to_gpu_repr());
+            }
+            CameraBufferScheme::LateLatched { latest, .. } => *latest = camera.to_gpu_repr(),
+        }
+    }
+}
+
+/// Cameras of a renderer's viewports (see `renderer::viewport::Viewport`), one per viewport in the same order.
+/// They all use the main camera's binding, but are only bound when their viewport is drawn (see
+/// `DataComponentSet::bind_viewport`), so this binds nothing for the main image.
+pub struct ViewportCameras {
+    cameras: Vec<RendererCamera>,
+}
+
+impl ViewportCameras {
+    /// `n` late latched cameras (see `RendererCamera::new_late_latched`) for the main camera's `binding`
+    pub fn new(
+        n: usize,
+        binding: Binding<UniformBuffer>,
+        allocator: Arc<dyn MemoryAllocator>,
+    ) -> Self {
+        ViewportCameras {
+            cameras: (0..n)
+                .map(|_| RendererCamera::new_late_latched(binding, Arc::clone(&allocator)))
+                .collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.cameras.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cameras.is_empty()
+    }
+
+    /// Set the camera of viewport `viewport` for the next frame, see `RendererCamera::update_staging_buffer`
+    pub fn update_staging_buffer(&mut self, viewport: usize, camera: &Camera) {
+        self.cameras[viewport].update_staging_buffer(camera);
+    }
+}
+
+impl DataComponentSet for ViewportCameras {
+    fn bind(&self, _descriptor_writes: &mut Vec<WriteDescriptorSet>) {}
+
+    fn record_repeated_buffer_transfer<L, A: CommandBufferAllocator>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L, A>,
+    ) {
+        for camera in self.cameras.iter() {
+            camera.record_repeated_buffer_transfer(builder);
+        }
+    }
+
+    fn record_buffer_transfer_jit<L, A: CommandBufferAllocator>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<L, A>,
+    ) {
+        for camera in self.cameras.iter_mut() {
+            camera.record_buffer_transfer_jit(builder);
+        }
+    }
+
+    fn pending_transfer_bytes(&self) -> u64 {
+        self.cameras
+            .iter()
+            .map(|c| c.pending_transfer_bytes())
+            .sum()
+    }
+
+    fn late_latch(&mut self) {
+        for camera in self.cameras.iter_mut() {
+            camera.late_latch();
+        }
+    }
+
+    fn bind_viewport(&self, viewport: usize, descriptor_writes: &mut Vec<WriteDescriptorSet>) {
+        if let Some(camera) = self.cameras.get(viewport) {
+            camera.buffer_scheme.bind(descriptor_writes, camera.binding);
+        }
+    }
+}
+
+pub enum CameraBufferScheme {
+    /// Copied to a device local buffer with the rest of the frame's transfer
+    Staged(DualBufferWithFullCopy<CameraUBO>),
+    /// Read directly by the shader. `latest` is written to `buffer` when the frame is submitted.
+    LateLatched {
+        buffer: HostVisibleBuffer<CameraUBO>,
+        latest: CameraUBO,
+    },
+}
+
+impl BufferScheme for CameraBufferScheme {
+    fn bind(&self, descriptor_writes: &mut Vec<WriteDescriptorSet>, binding: u32) {
+        match self {
+            CameraBufferScheme::Staged(buffer) => buffer.bind(descriptor_writes, binding),
+            CameraBufferScheme::LateLatched { buffer, .. } => {
+                buffer.bind(descriptor_writes, binding)
+            }
+        }
+    }
+
+    fn record_repeated_transfer<L, A: CommandBufferAllocator>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L, A>,
+    ) {
+        if let CameraBufferScheme::Staged(buffer) = self {
+            buffer.record_repeated_transfer(builder);
+        }
+    }
+
+    fn record_transfer_jit<L, A: CommandBufferAllocator>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<L, A>,
+    ) {
+        if let CameraBufferScheme::Staged(buffer) = self {
+            buffer.record_transfer_jit(builder);
+        }
+    }
+
+    fn pending_transfer_bytes(&self) -> u64 {
+        match self {
+            CameraBufferScheme::Staged(buffer) => buffer.pending_transfer_bytes(),
+            CameraBufferScheme::LateLatched { .. } => 0,
+        }
+    }
+
+    fn late_latch(&mut self) {
+        if let CameraBufferScheme::LateLatched { buffer, latest } = self {
+            *buffer.write() = *latest;
+        }
     }
 }
 
-/// Uniform buffer object containing camera info that gets passed to the GPU
-#[derive(BufferContents, Debug, Clone)]
+/// Uniform buffer object containing camera info that gets passed to the GPU. This is the exact data
+/// used by the raytrace pass, so custom passes can use it too (see `Camera::to_gpu_repr`). The layout
+/// is stable and matches this std140 GLSL block:
+///
+/// ```glsl
+/// uniform Camera {
+///     vec3 eye;
+///     vec3 viewport_center;
+///     vec3 right_dir;
+///     vec3 up_dir;
+/// } camera;
+/// ```
+///
+/// A pixel at `uv` (each component in -1..1) is seen along the ray from `eye` through
+/// `viewport_center + right_dir * uv.x - up_dir * uv.y`.
+#[derive(BufferContents, Debug, Clone, Copy, PartialEq)]
 #[repr(C)]
 pub struct CameraUBO {
-    eye: [f32; 3],
+    /// Camera position
+    pub eye: [f32; 3],
     _pad1: f32,
-    viewport_center: [f32; 3],
+    /// Center of the viewport plane, `viewport_dist` in front of `eye`
+    pub viewport_center: [f32; 3],
     _pad2: f32,
-    right_dir: [f32; 3], // should be normalized
+    /// Vector from the viewport center to its right edge (horizontal half-extent, so this encodes fov)
+    pub right_dir: [f32; 3],
     _pad3: f32,
-    up_dir: [f32; 3], // should be normalized
+    /// Vector from the viewport center to its top edge (vertical half-extent, so this encodes fov)
+    pub up_dir: [f32; 3],
     _pad4: f32,
 }
 
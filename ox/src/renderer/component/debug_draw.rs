@@ -0,0 +1,322 @@
+//! Debug shapes (lines, boxes, spheres and text) drawn over the rendered image, e.g. to see chunk bounds,
+//! raycasts or physics sweeps. Gameplay code fills a `DebugDraw` each frame and hands it to
+//! `Renderer::submit_debug_draw`, which sends it to a `RendererDebugDraw` for the raytrace shader to draw.
+
+use crate::renderer::binding::{Binding, StorageBuffer};
+use crate::renderer::buffers::dual::{DualBuffer, DualBufferWithFullCopy};
+use crate::renderer::component::{DataComponent, DataComponentSet};
+use crate::world::VoxelPos;
+use std::sync::Arc;
+use vulkano::buffer::BufferContents;
+use vulkano::memory::allocator::MemoryAllocator;
+
+/// Most primitives drawn in a frame. Must match `N_DEBUG_PRIMITIVES` in the shader.
+pub const N_DEBUG_PRIMITIVES: usize = 1024;
+
+/// Values of `DebugPrimitive::kind`, matching `DEBUG_*` in the shader
+const KIND_LINE: u32 = 0;
+const KIND_AABB: u32 = 1;
+const KIND_SPHERE: u32 = 2;
+const KIND_GLYPH: u32 = 3;
+
+/// Bit of `DebugPrimitive::flags` set if the primitive is hidden behind voxels
+const FLAG_DEPTH_TEST: u32 = 1;
+
+/// One shape for the shader to draw. Text is one primitive per character. Matches this scalar GLSL struct:
+///
+/// ```glsl
+/// struct DebugPrimitive {
+///     vec3 a;
+///     uint kind;
+///     vec3 b;
+///     float radius;
+///     vec3 color;
+///     uint flags;
+/// };
+/// ```
+#[derive(BufferContents, Debug, Clone, Copy, PartialEq, Default)]
+#[repr(C)]
+pub struct DebugPrimitive {
+    /// Line start, box min corner, sphere center or text position, relative to the memory grid
+    pub a: [f32; 3],
+    pub kind: u32,
+    /// Line end or box max corner. For text, `b[0]` is the character's column in its string.
+    pub b: [f32; 3],
+    pub radius: f32,
+    pub color: [f32; 3],
+    /// `FLAG_DEPTH_TEST`, and for text the character's 3x5 bitmap (see `glyph`) in the upper 16 bits
+    pub flags: u32,
+}
+
+/// Buffer shared with the raytrace shader. Matches this scalar GLSL block:
+///
+/// ```glsl
+/// buffer DebugDraw {
+///     uint n_primitives;
+///     DebugPrimitive primitives[N_DEBUG_PRIMITIVES];
+/// } debug_draw;
+/// ```
+#[derive(BufferContents, Debug, Clone, Copy, PartialEq)]
+#[repr(C)]
+pub struct DebugDrawBuffer {
+    pub n_primitives: u32,
+    pub primitives: [DebugPrimitive; N_DEBUG_PRIMITIVES],
+}
+
+impl Default for DebugDrawBuffer {
+    fn default() -> Self {
+        DebugDrawBuffer {
+            n_primitives: 0,
+            primitives: [DebugPrimitive::default(); N_DEBUG_PRIMITIVES],
+        }
+    }
+}
+
+/// Shapes to draw in the next frame. Positions are relative to the memory grid like `Camera::position`, so
+/// convert world positions with the grid's start TLC, and redraw after the grid shifts.
+///
+/// Shapes are outlines a few pixels wide whatever their distance, and text is drawn at a fixed pixel size. Each
+/// call chooses whether the shape is depth tested, i.e. hidden where voxels are in front of it, or drawn on top
+/// of everything. Nothing is drawn while the overlay is hidden (see `PhotoSettings::hide_overlay`).
+#[derive(Debug, Clone, Default)]
+pub struct DebugDraw {
+    primitives: Vec<DebugPrimitive>,
+    n_dropped: usize,
+}
+
+impl DebugDraw {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn push(&mut self, primitive: DebugPrimitive) {
+        if self.primitives.len() < N_DEBUG_PRIMITIVES {
+            self.primitives.push(primitive);
+        } else {
+            self.n_dropped += 1;
+        }
+    }
+
+    fn flags(depth_test: bool) -> u32 {
+        if depth_test {
+            FLAG_DEPTH_TEST
+        } else {
+            0
+        }
+    }
+
+    pub fn line(&mut self, a: VoxelPos<f32>, b: VoxelPos<f32>, color: [f32; 3], depth_test: bool) {
+        self.push(DebugPrimitive {
+            a: a.0.into(),
+            kind: KIND_LINE,
+            b: b.0.into(),
+            radius: 0.,
+            color,
+            flags: Self::flags(depth_test),
+        });
+    }
+
+    /// Edges of the axis aligned box with corners `a` and `b`
+    pub fn aabb(&mut self, a: VoxelPos<f32>, b: VoxelPos<f32>, color: [f32; 3], depth_test: bool) {
+        let corner = |f: fn(f32, f32) -> f32| [f(a.0.x, b.0.x), f(a.0.y, b.0.y), f(a.0.z, b.0.z)];
+        self.push(DebugPrimitive {
+            a: corner(f32::min),
+            kind: KIND_AABB,
+            b: corner(f32::max),
+            radius: 0.,
+            color,
+            flags: Self::flags(depth_test),
+        });
+    }
+
+    /// Outline of the sphere as seen from the camera
+    pub fn sphere(
+        &mut self,
+        center: VoxelPos<f32>,
+        radius: f32,
+        color: [f32; 3],
+        depth_test: bool,
+    ) {
+        self.push(DebugPrimitive {
+            a: center.0.into(),
+            kind: KIND_SPHERE,
+            b: [0.; 3],
+            radius,
+            color,
+            flags: Self::flags(depth_test),
+        });
+    }
+
+    /// Single line of text starting at `pos` on screen. Letters are drawn in upper case, and characters
+    /// without a glyph (see `glyph`) as `?`.
+    pub fn text3d(&mut self, pos: VoxelPos<f32>, text: &str, color: [f32; 3], depth_test: bool) {
+        for (column, c) in text.chars().enumerate() {
+            if c == ' ' {
+                continue;
+            }
+            let bitmap = glyph(c).unwrap_or_else(|| glyph('?').unwrap());
+            self.push(DebugPrimitive {
+                a: pos.0.into(),
+                kind: KIND_GLYPH,
+                b: [column as f32, 0., 0.],
+                radius: 0.,
+                color,
+                flags: Self::flags(depth_test) | ((bitmap as u32) << 16),
+            });
+        }
+    }
+
+    /// Primitives drawn so far this frame. Text counts one per character.
+    pub fn len(&self) -> usize {
+        self.primitives.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.primitives.is_empty()
+    }
+
+    /// Primitives dropped this frame because there were already `N_DEBUG_PRIMITIVES`
+    pub fn n_dropped(&self) -> usize {
+        self.n_dropped
+    }
+
+    pub fn clear(&mut self) {
+        self.primitives.clear();
+        self.n_dropped = 0;
+    }
+
+    pub fn primitives(&self) -> &[DebugPrimitive] {
+        &self.primitives
+    }
+}
+
+/// 3x5 bitmap of `c` (case insensitive), rows from the top, each row's leftmost pixel in its highest bit
+pub fn glyph(c: char) -> Option<u16> {
+    Some(match c.to_ascii_uppercase() {
+        '0' => 0b111_101_101_101_111,
+        '1' => 0b010_110_010_010_111,
+        '2' => 0b111_001_111_100_111,
+        '3' => 0b111_001_011_001_111,
+        '4' => 0b101_101_111_001_001,
+        '5' => 0b111_100_111_001_111,
+        '6' => 0b111_100_111_101_111,
+        '7' => 0b111_001_010_010_010,
+        '8' => 0b111_101_111_101_111,
+        '9' => 0b111_101_111_001_111,
+        'A' => 0b010_101_111_101_101,
+        'B' => 0b110_101_110_101_110,
+        'C' => 0b011_100_100_100_011,
+        'D' => 0b110_101_101_101_110,
+        'E' => 0b111_100_110_100_111,
+        'F' => 0b111_100_110_100_100,
+        'G' => 0b011_100_101_101_011,
+        'H' => 0b101_101_111_101_101,
+        'I' => 0b111_010_010_010_111,
+        'J' => 0b001_001_001_101_010,
+        'K' => 0b101_101_110_101_101,
+        'L' => 0b100_100_100_100_111,
+        'M' => 0b101_111_111_101_101,
+        'N' => 0b110_101_101_101_101,
+        'O' => 0b010_101_101_101_010,
+        'P' => 0b110_101_110_100_100,
+        'Q' => 0b010_101_101_110_011,
+        'R' => 0b110_101_110_101_101,
+        'S' => 0b011_100_010_001_110,
+        'T' => 0b111_010_010_010_010,
+        'U' => 0b101_101_101_101_111,
+        'V' => 0b101_101_101_101_010,
+        'W' => 0b101_101_111_111_101,
+        'X' => 0b101_101_010_101_101,
+        'Y' => 0b101_101_010_010_010,
+        'Z' => 0b111_001_010_100_111,
+        '.' => 0b000_000_000_000_010,
+        ',' => 0b000_000_000_010_100,
+        ':' => 0b000_010_000_010_000,
+        '-' => 0b000_000_111_000_000,
+        '+' => 0b000_010_111_010_000,
+        '=' => 0b000_111_000_111_000,
+        '/' => 0b001_001_010_100_100,
+        '(' => 0b001_010_010_010_001,
+        ')' => 0b100_010_010_010_100,
+        '[' => 0b011_010_010_010_011,
+        ']' => 0b110_010_010_010_110,
+        '_' => 0b000_000_000_000_111,
+        '!' => 0b010_010_010_000_010,
+        '?' => 0b111_001_010_000_010,
+        ' ' => 0,
+        _ => return None,
+    })
+}
+
+pub type RendererDebugDraw = DataComponent<DualBufferWithFullCopy<DebugDrawBuffer>>;
+
+impl RendererDebugDraw {
+    pub fn new(binding: Binding<StorageBuffer>, allocator: Arc<dyn MemoryAllocator>) -> Self {
+        DataComponent {
+            buffer_scheme: DualBuffer::from_data(DebugDrawBuffer::default(), allocator, false)
+                .with_full_copy(),
+            binding: binding.index(),
+        }
+    }
+
+    /// Draw `draw`'s primitives instead of the previous ones. Returns whether they changed.
+    pub fn set(&mut self, draw: &DebugDraw) -> bool {
+        let primitives = draw.primitives();
+        {
+            let staging = self.buffer_scheme.read_staging();
+            if staging.primitives[..staging.n_primitives as usize] == *primitives {
+                return false;
+            }
+        }
+        let mut staging = self.buffer_scheme.write_staging();
+        staging.n_primitives = primitives.len() as u32;
+        staging.primitives[..primitives.len()].copy_from_slice(primitives);
+        true
+    }
+}
+
+/// Implemented by component sets that contain a `RendererDebugDraw` so that the renderer can update it through
+/// `Renderer::submit_debug_draw`.
+pub trait DataComponentSetWithDebugDraw: DataComponentSet {
+    fn debug_draw_mut(&mut self) -> &mut RendererDebugDraw;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Point3;
+
+    fn pos(x: f32, y: f32, z: f32) -> VoxelPos<f32> {
+        VoxelPos(Point3 { x, y, z })
+    }
+
+    #[test]
+    fn test_debug_draw() {
+        let mut draw = DebugDraw::new();
+        draw.aabb(pos(2., 0., 5.), pos(1., 3., 4.), [1., 0., 0.], true);
+        assert_eq!(draw.primitives()[0].a, [1., 0., 4.]);
+        assert_eq!(draw.primitives()[0].b, [2., 3., 5.]);
+        assert_eq!(draw.primitives()[0].flags, FLAG_DEPTH_TEST);
+
+        // Spaces take a column but aren't drawn, and characters without a glyph are drawn as '?'
+        draw.text3d(pos(0., 0., 0.), "a b~", [1., 1., 1.], false);
+        assert_eq!(draw.len(), 4);
+        let glyphs = &draw.primitives()[1..];
+        assert_eq!(
+            glyphs.iter().map(|p| p.b[0]).collect::<Vec<_>>(),
+            vec![0., 2., 3.]
+        );
+        assert_eq!(glyphs[0].flags >> 16, glyph('A').unwrap() as u32);
+        assert_eq!(glyphs[2].flags >> 16, glyph('?').unwrap() as u32);
+
+        for _ in 0..N_DEBUG_PRIMITIVES {
+            draw.line(pos(0., 0., 0.), pos(1., 1., 1.), [0., 1., 0.], false);
+        }
+        assert_eq!(draw.len(), N_DEBUG_PRIMITIVES);
+        assert_eq!(draw.n_dropped(), 4);
+
+        draw.clear();
+        assert!(draw.is_empty());
+        assert_eq!(draw.n_dropped(), 0);
+    }
+}
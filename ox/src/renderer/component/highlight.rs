@@ -0,0 +1,101 @@
+use crate::ray::RayVoxelIntersect;
+use crate::renderer::binding::{Binding, UniformBuffer};
+use crate::renderer::buffers::dual::{DualBuffer, DualBufferWithFullCopy};
+use crate::renderer::component::{DataComponent, DataComponentSet};
+use crate::world::{TlcPos, VoxelPos};
+use std::sync::Arc;
+use vulkano::buffer::BufferContents;
+use vulkano::memory::allocator::MemoryAllocator;
+
+/// A single LOD 0 voxel to draw an outline around, e.g. the voxel the player is looking at.
+#[derive(Debug, Clone, Copy)]
+pub struct HighlightedVoxel {
+    pub tlc: TlcPos<i64>,
+    /// Position within `tlc` in LOD 0 voxels
+    pub pos: VoxelPos<u32>,
+}
+
+impl From<&RayVoxelIntersect> for HighlightedVoxel {
+    fn from(intersect: &RayVoxelIntersect) -> Self {
+        HighlightedVoxel {
+            tlc: intersect.tlc,
+            pos: intersect.pos,
+        }
+    }
+}
+
+/// Uniform buffer describing the highlighted voxel. Matches this std140 GLSL block:
+///
+/// ```glsl
+/// uniform Highlight {
+///     ivec3 tlc;
+///     uint enabled;
+///     uvec3 pos;
+///     float width;
+///     vec3 color;
+/// } highlight;
+/// ```
+#[derive(BufferContents, Debug, Clone, Copy, PartialEq)]
+#[repr(C)]
+pub struct HighlightUBO {
+    pub tlc: [i32; 3], // ENHANCEMENT: These should really be i64, but glsl uses 32 bit ints
+    /// 0 when nothing is highlighted
+    pub enabled: u32,
+    pub pos: [u32; 3],
+    /// Outline width in LOD 0 voxels
+    pub width: f32,
+    pub color: [f32; 3],
+    _pad: f32,
+}
+
+impl Default for HighlightUBO {
+    fn default() -> Self {
+        HighlightUBO {
+            tlc: [0; 3],
+            enabled: 0,
+            pos: [0; 3],
+            width: 0.03,
+            color: [0.05, 0.05, 0.05],
+            _pad: 0.0,
+        }
+    }
+}
+
+pub type RendererHighlight = DataComponent<DualBufferWithFullCopy<HighlightUBO>>;
+
+impl RendererHighlight {
+    pub fn new(binding: Binding<UniformBuffer>, allocator: Arc<dyn MemoryAllocator>) -> Self {
+        DataComponent {
+            buffer_scheme: DualBuffer::from_data(HighlightUBO::default(), allocator, true)
+                .with_full_copy(),
+            binding: binding.index(),
+        }
+    }
+
+    /// Returns whether the highlight changed
+    pub fn set(&mut self, voxel: Option<HighlightedVoxel>) -> bool {
+        let mut ubo = *self.buffer_scheme.read_staging();
+        match voxel {
+            None => ubo.enabled = 0,
+            Some(HighlightedVoxel { tlc, pos }) => {
+                ubo.enabled = 1;
+                ubo.tlc = tlc.0.map(|a| a as i32).into();
+                ubo.pos = pos.0.into();
+            }
+        }
+        self.buffer_scheme.set_if_changed(ubo)
+    }
+
+    /// Set the outline color and width (in LOD 0 voxels)
+    pub fn set_style(&mut self, color: [f32; 3], width: f32) {
+        let mut ubo = self.buffer_scheme.write_staging();
+        ubo.color = color;
+        ubo.width = width;
+    }
+}
+
+/// Implemented by component sets that contain a `RendererHighlight` so that the renderer can update it
+/// through `Renderer::set_highlighted_voxel`.
+pub trait DataComponentSetWithHighlight: DataComponentSet {
+    fn highlight_mut(&mut self) -> &mut RendererHighlight;
+}
@@ -0,0 +1,334 @@
+//! Luminance histogram of the rendered image, read back by the CPU each frame for auto-exposure.
+
+use crate::renderer::binding::{Binding, StorageBuffer};
+use crate::renderer::buffers::{BufferScheme, HostVisibleBuffer};
+use crate::renderer::component::DataComponentSet;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use vulkano::buffer::BufferContents;
+use vulkano::command_buffer::allocator::CommandBufferAllocator;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::memory::allocator::MemoryAllocator;
+
+/// Number of histogram bins, evenly spaced in log2 luminance from `LUMINANCE_MIN_LOG2` to `LUMINANCE_MAX_LOG2`.
+/// Must match `N_LUMINANCE_BINS` in the shader.
+pub const N_LUMINANCE_BINS: usize = 64;
+/// Luminances at or below `2^LUMINANCE_MIN_LOG2` (including black) go in the first bin
+pub const LUMINANCE_MIN_LOG2: f32 = -10.;
+/// Luminances at or above `2^LUMINANCE_MAX_LOG2` go in the last bin
+pub const LUMINANCE_MAX_LOG2: f32 = 6.;
+
+/// Buffer shared with the raytrace shader. Matches this std430 GLSL block:
+///
+/// ```glsl
+/// buffer Luminance {
+///     float exposure;
+///     uint bins[N_LUMINANCE_BINS];
+/// } luminance;
+/// ```
+#[derive(BufferContents, Debug, Clone, Copy)]
+#[repr(C)]
+pub struct LuminanceBuffer {
+    /// Multiplier applied to the traced light before it is written to the image
+    pub exposure: f32,
+    /// Pixels counted in each bin, before `exposure` is applied
+    pub bins: [u32; N_LUMINANCE_BINS],
+}
+
+/// Histogram of the log2 luminance of a frame's pixels, before exposure. The shader only counts every 4th pixel
+/// in each direction to keep atomic contention down.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LuminanceHistogram {
+    pub bins: [u32; N_LUMINANCE_BINS],
+}
+
+impl Default for LuminanceHistogram {
+    fn default() -> Self {
+        LuminanceHistogram {
+            bins: [0; N_LUMINANCE_BINS],
+        }
+    }
+}
+
+impl LuminanceHistogram {
+    /// Luminance at the center of bin `i`
+    pub fn bin_luminance(i: usize) -> f32 {
+        let bin_width = (LUMINANCE_MAX_LOG2 - LUMINANCE_MIN_LOG2) / N_LUMINANCE_BINS as f32;
+        (LUMINANCE_MIN_LOG2 + (i as f32 + 0.5) * bin_width).exp2()
+    }
+
+    pub fn n_pixels(&self) -> u32 {
+        self.bins.iter().sum()
+    }
+
+    /// Geometric mean luminance, or `None` if no pixels were counted
+    pub fn average(&self) -> Option<f32> {
+        let n = self.n_pixels();
+        if n == 0 {
+            return None;
+        }
+        let sum_log2: f32 = self
+            .bins
+            .iter()
+            .enumerate()
+            .map(|(i, &count)| count as f32 * Self::bin_luminance(i).log2())
+            .sum();
+        Some((sum_log2 / n as f32).exp2())
+    }
+
+    /// Luminance that a fraction `p` (0 to 1) of pixels are at or below, or `None` if no pixels were counted
+    pub fn percentile(&self, p: f32) -> Option<f32> {
+        let n = self.n_pixels();
+        if n == 0 {
+            return None;
+        }
+        let target = (p.clamp(0., 1.) * n as f32).ceil().max(1.) as u32;
+        let mut count = 0;
+        for (i, &bin) in self.bins.iter().enumerate() {
+            count += bin;
+            if count >= target {
+                return Some(Self::bin_luminance(i));
+            }
+        }
+        Some(Self::bin_luminance(N_LUMINANCE_BINS - 1))
+    }
+}
+
+/// Which statistic of the `LuminanceHistogram` auto-exposure brings to `AutoExposure::target`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LuminanceMetering {
+    /// Geometric mean of all pixels
+    Average,
+    /// Luminance that this fraction of pixels are at or below, e.g. 0.9 to keep bright areas from blowing out
+    Percentile(f32),
+}
+
+/// Adjusts exposure over time so the metered luminance of the scene ends up at `target`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AutoExposure {
+    pub metering: LuminanceMetering,
+    /// Output luminance the metered luminance is exposed to
+    pub target: f32,
+    /// How quickly exposure adapts. Each second, exposure closes `1 - e^-speed` of the gap (in stops) to where it
+    /// should be.
+    pub speed: f32,
+    pub min_exposure: f32,
+    pub max_exposure: f32,
+}
+
+impl Default for AutoExposure {
+    fn default() -> Self {
+        AutoExposure {
+            metering: LuminanceMetering::Average,
+            target: 0.4,
+            speed: 1.5,
+            min_exposure: 0.25,
+            max_exposure: 4.,
+        }
+    }
+}
+
+impl AutoExposure {
+    /// Exposure that would bring the metered luminance of `histogram` to `target`, within the bounds. `None` if
+    /// the histogram is empty.
+    pub fn target_exposure(&self, histogram: &LuminanceHistogram) -> Option<f32> {
+        let metered = match self.metering {
+            LuminanceMetering::Average => histogram.average(),
+            LuminanceMetering::Percentile(p) => histogram.percentile(p),
+        }?;
+        Some((self.target / metered).clamp(self.min_exposure, self.max_exposure))
+    }
+
+    /// Move `exposure` towards `target_exposure` for `dt` seconds of adaptation
+    pub fn adapt(&self, exposure: f32, target_exposure: f32, dt: Duration) -> f32 {
+        let t = 1. - (-self.speed * dt.as_secs_f32()).exp();
+        let log2 = exposure.log2() + (target_exposure.log2() - exposure.log2()) * t;
+        log2.exp2().clamp(self.min_exposure, self.max_exposure)
+    }
+}
+
+/// How the raytraced light is mapped to the output image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorConfig {
+    /// Fixed exposure, used when `auto_exposure` is `None`. This is also the starting exposure when auto-exposure
+    /// is enabled.
+    pub exposure: f32,
+    pub auto_exposure: Option<AutoExposure>,
+}
+
+impl Default for ColorConfig {
+    fn default() -> Self {
+        ColorConfig {
+            exposure: 1.,
+            auto_exposure: None,
+        }
+    }
+}
+
+/// Luminance histogram and exposure for the shader's `LUMINANCE` define. Only use this with it set, since the
+/// binding doesn't exist otherwise.
+///
+/// The shader counts pixels into a host visible buffer. Right before each frame (see
+/// `DataComponentSet::late_latch`), the previous frame's histogram is read back and cleared, and the exposure is
+/// adapted and written to the same buffer for the new frame to use, so there is one frame of latency.
+pub struct RendererLuminance {
+    buffer: HostVisibleBuffer<LuminanceBuffer>,
+    binding: u32,
+    config: ColorConfig,
+    histogram: LuminanceHistogram,
+    exposure: f32,
+    target_exposure: f32,
+    last_latch: Option<Instant>,
+}
+
+impl RendererLuminance {
+    pub fn new(
+        binding: Binding<StorageBuffer>,
+        memory_allocator: Arc<dyn MemoryAllocator>,
+        config: ColorConfig,
+    ) -> Self {
+        RendererLuminance {
+            buffer: HostVisibleBuffer::from_data(
+                LuminanceBuffer {
+                    exposure: config.exposure,
+                    bins: [0; N_LUMINANCE_BINS],
+                },
+                memory_allocator,
+                false,
+            )
+            .expect("No host visible memory for the luminance histogram"),
+            binding: binding.index(),
+            config,
+            histogram: LuminanceHistogram::default(),
+            exposure: config.exposure,
+            target_exposure: config.exposure,
+            last_latch: None,
+        }
+    }
+
+    /// Takes effect on the next frame. Changing `exposure` also resets auto-exposure to start from it.
+    pub fn set_config(&mut self, config: ColorConfig) {
+        if config.exposure != self.config.exposure {
+            self.exposure = config.exposure;
+            self.target_exposure = config.exposure;
+        }
+        self.config = config;
+    }
+
+    pub fn config(&self) -> ColorConfig {
+        self.config
+    }
+
+    /// Histogram of the last frame that finished
+    pub fn histogram(&self) -> &LuminanceHistogram {
+        &self.histogram
+    }
+
+    /// Exposure of the frame being drawn
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    /// Whether auto-exposure is still noticeably changing the exposure (see
+    /// `Renderer::request_redraw_if_adapting`)
+    pub fn is_adapting(&self) -> bool {
+        self.config.auto_exposure.is_some()
+            && (self.exposure.log2() - self.target_exposure.log2()).abs() > 0.02
+    }
+}
+
+impl DataComponentSet for RendererLuminance {
+    fn bind(&self, descriptor_writes: &mut Vec<WriteDescriptorSet>) {
+        self.buffer.bind(descriptor_writes, self.binding);
+    }
+
+    fn record_repeated_buffer_transfer<L, A: CommandBufferAllocator>(
+        &self,
+        _: &mut AutoCommandBufferBuilder<L, A>,
+    ) {
+    }
+
+    fn record_buffer_transfer_jit<L, A: CommandBufferAllocator>(
+        &mut self,
+        _: &mut AutoCommandBufferBuilder<L, A>,
+    ) {
+    }
+
+    fn pending_transfer_bytes(&self) -> u64 {
+        0
+    }
+
+    fn late_latch(&mut self) {
+        let now = Instant::now();
+        let dt = self
+            .last_latch
+            .map_or(Duration::ZERO, |last| now.saturating_duration_since(last));
+        self.last_latch = Some(now);
+
+        let mut buffer = self.buffer.write();
+        self.histogram.bins = buffer.bins;
+        buffer.bins = [0; N_LUMINANCE_BINS];
+        match self.config.auto_exposure {
+            None => self.exposure = self.config.exposure,
+            Some(auto) => {
+                if let Some(target) = auto.target_exposure(&self.histogram) {
+                    self.target_exposure = target;
+                }
+                self.exposure = auto.adapt(self.exposure, self.target_exposure, dt);
+            }
+        }
+        buffer.exposure = self.exposure;
+    }
+}
+
+/// Implemented by component sets that contain a `RendererLuminance` so that the renderer can change the exposure
+/// through `Renderer::set_color_config`.
+pub trait DataComponentSetWithLuminance: DataComponentSet {
+    fn luminance(&self) -> &RendererLuminance;
+    fn luminance_mut(&mut self) -> &mut RendererLuminance;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_luminance_histogram_stats() {
+        let mut histogram = LuminanceHistogram::default();
+        assert_eq!(histogram.average(), None);
+        assert_eq!(histogram.percentile(0.5), None);
+
+        histogram.bins[10] = 3;
+        histogram.bins[50] = 1;
+        assert_eq!(histogram.n_pixels(), 4);
+        assert_eq!(
+            histogram.percentile(0.5),
+            Some(LuminanceHistogram::bin_luminance(10))
+        );
+        assert_eq!(
+            histogram.percentile(1.),
+            Some(LuminanceHistogram::bin_luminance(50))
+        );
+        let expected = (3. * LuminanceHistogram::bin_luminance(10).log2()
+            + LuminanceHistogram::bin_luminance(50).log2())
+            / 4.;
+        assert!((histogram.average().unwrap().log2() - expected).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_auto_exposure_adapts_within_bounds() {
+        let auto = AutoExposure::default();
+        let mut histogram = LuminanceHistogram::default();
+        // A dark scene
+        histogram.bins[20] = 100;
+        let target = auto.target_exposure(&histogram).unwrap();
+        assert_eq!(target, auto.max_exposure);
+
+        assert_eq!(auto.adapt(1., target, Duration::ZERO), 1.);
+        let step = auto.adapt(1., target, Duration::from_millis(100));
+        assert!(step > 1. && step < target);
+        let settled = auto.adapt(1., target, Duration::from_secs(60));
+        assert!((settled - target).abs() < 1e-3);
+    }
+}
@@ -1,9 +1,14 @@
-use crate::renderer::buffers::dual::{ConstantDeviceLocalBuffer, DualBuffer};
-use crate::renderer::component::DataComponent;
+use crate::renderer::binding::{Binding, StorageBuffer};
+use crate::renderer::buffers::dual::{DualBuffer, DualBufferWithDynamicCopyRegions};
+use crate::renderer::buffers::BufferScheme;
+use crate::renderer::component::{DataComponent, DataComponentSet};
+use crate::voxel_type::{VoxelSchema, VoxelTypeEnum};
+use std::mem::size_of;
 use std::sync::Arc;
 use vulkano::buffer::BufferContents;
 use vulkano::command_buffer::allocator::CommandBufferAllocator;
-use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::{AutoCommandBufferBuilder, BufferCopy};
+use vulkano::descriptor_set::WriteDescriptorSet;
 use vulkano::memory::allocator::MemoryAllocator;
 
 #[derive(BufferContents, Debug, Clone, Copy)]
@@ -17,7 +22,26 @@ pub struct Material {
     pub emission_strength: f32,
     pub specular_prob_perpendicular: f32,
     pub specular_prob_parallel: f32,
-    pub _pad3: [f32; 2],
+    /// Fraction of rays that hit the surface, from 0 (fully transparent) to 1 (opaque). The others are refracted
+    /// into the voxel and pass through it (and voxels of the same type behind it), tinted by `color`. Also the
+    /// default `VoxelTypeEnum::opacity`.
+    pub alpha: f32,
+    /// Index of refraction of transparent materials, e.g. 1.33 for water or 1.5 for glass. Must be at least 1 (air).
+    pub ior: f32,
+}
+
+impl Material {
+    /// GLSL declaration with the same layout, see `renderer::shader_defs`
+    pub const GLSL: &'static str = "struct Material {
+    vec3 color;
+    vec3 specular_color;
+    vec3 emission_color;
+    float emission_strength; // [0,1]
+    float specular_prob_perpendicular; // [0,1]
+    float specular_prob_parallel; // [0,1]
+    float alpha; // [0,1], 1 is opaque
+    float ior; // >= 1
+};";
 }
 
 impl Default for Material {
@@ -29,30 +53,294 @@ impl Default for Material {
             emission_strength: 0.,
             specular_prob_perpendicular: 0.,
             specular_prob_parallel: 0.,
+            alpha: 1.,
+            ior: 1.,
             _pad1: 0.,
             _pad2: 0.,
-            _pad3: [0., 0.],
         }
     }
 }
 
-pub type MaterialList = DataComponent<ConstantDeviceLocalBuffer<[Material]>>;
+impl Material {
+    /// Transparent material that tints light passing through it with `color`, e.g.
+    /// `Material::transparent([0.6, 0.8, 0.9], 0.2, 1.33)` for water
+    pub fn transparent(color: [f32; 3], alpha: f32, ior: f32) -> Self {
+        Material {
+            color,
+            alpha,
+            ior,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_opaque(&self) -> bool {
+        self.alpha >= 1.
+    }
+}
+
+pub type MaterialList = DataComponent<DualBufferWithDynamicCopyRegions<Material>>;
+
+/// Copy region covering a whole list of `n` materials
+fn full_copy_region(n: usize) -> BufferCopy {
+    BufferCopy {
+        size: (n * size_of::<Material>()) as u64,
+        ..Default::default()
+    }
+}
 
 impl MaterialList {
+    /// Uploads `materials` with `one_time_transfer_builder`. They can be changed later with `update`.
     pub fn new<L, A: CommandBufferAllocator>(
         materials: &[Material],
         memory_allocator: Arc<dyn MemoryAllocator>,
-        binding: u32,
+        binding: Binding<StorageBuffer>,
         one_time_transfer_builder: &mut AutoCommandBufferBuilder<L, A>,
     ) -> MaterialList {
+        let mut buffer_scheme =
+            DualBuffer::from_iter(materials.iter().copied(), memory_allocator, false)
+                .with_copy_regions();
+        buffer_scheme
+            .update_staging_buffer_and_prep_copy([(materials, &full_copy_region(materials.len()))]);
+        buffer_scheme.record_transfer_jit(one_time_transfer_builder);
+
         DataComponent {
-            buffer_scheme: DualBuffer::from_iter(
-                materials.iter().copied(),
-                memory_allocator,
-                false,
-            )
-            .without_staging_buffer(one_time_transfer_builder),
+            buffer_scheme,
+            binding: binding.index(),
+        }
+    }
+
+    /// `new` with the materials of every voxel type of the `VoxelSchema` `S`
+    pub fn from_schema<S: VoxelSchema, L, A: CommandBufferAllocator>(
+        memory_allocator: Arc<dyn MemoryAllocator>,
+        binding: Binding<StorageBuffer>,
+        one_time_transfer_builder: &mut AutoCommandBufferBuilder<L, A>,
+    ) -> MaterialList {
+        S::validate();
+        MaterialList::new(
+            &S::VoxelType::materials(),
+            memory_allocator,
+            binding,
+            one_time_transfer_builder,
+        )
+    }
+
+    /// Replace the materials, e.g. from a material editor, and queue them to be uploaded with the next transfer.
+    /// Must be called while staging buffers can be updated (see `Renderer::start_updating_staging_buffers`).
+    /// Panics if `materials` has a different length than the list this was created with.
+    pub fn update(&mut self, materials: &[Material]) {
+        assert_eq!(
+            materials.len(),
+            self.buffer_scheme.staging_len(),
+            "Material list can't change length after it is created"
+        );
+        self.buffer_scheme
+            .update_staging_buffer_and_prep_copy([(materials, &full_copy_region(materials.len()))]);
+    }
+}
+
+/// Emission a material uses instead of its own during part of the day, e.g. for lamps that only emit at night.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EmissiveVariant {
+    /// Time of day (0..1) this variant starts being used. If `start > end`, the range wraps around 1 to 0.
+    pub start: f32,
+    pub end: f32,
+    pub emission_color: [f32; 3],
+    pub emission_strength: f32,
+}
+
+impl EmissiveVariant {
+    pub fn is_active(&self, time_of_day: f32) -> bool {
+        if self.start <= self.end {
+            (self.start..self.end).contains(&time_of_day)
+        } else {
+            time_of_day >= self.start || time_of_day < self.end
+        }
+    }
+}
+
+/// Index of the first variant of each material that is active at `time_of_day`
+fn active_variants(variants: &[Vec<EmissiveVariant>], time_of_day: f32) -> Vec<Option<usize>> {
+    variants
+        .iter()
+        .map(|vs| vs.iter().position(|v| v.is_active(time_of_day)))
+        .collect()
+}
+
+/// Material list whose emission can change with the time of day. Use this instead of `MaterialList` when some
+/// voxel types have emissive variants (see `VoxelTypeEnum::emissive_variants`) and call `set_time_of_day`
+/// each frame. Only materials whose emission changed are re-uploaded.
+#[derive(Debug)]
+pub struct ScheduledMaterialList {
+    component: DataComponent<DualBufferWithDynamicCopyRegions<Material>>,
+    base: Vec<Material>,
+    /// Emissive variants of each material, in order of priority
+    variants: Vec<Vec<EmissiveVariant>>,
+    /// Variant currently uploaded for each material
+    active: Vec<Option<usize>>,
+    /// Materials as currently uploaded
+    resolved: Vec<Material>,
+}
+
+impl ScheduledMaterialList {
+    /// `variants` has the emissive variants of each material in `materials`, e.g. from
+    /// `VoxelTypeEnum::all_emissive_variants`. The whole list is uploaded by the first transfer.
+    pub fn new(
+        materials: &[Material],
+        variants: Vec<Vec<EmissiveVariant>>,
+        time_of_day: f32,
+        memory_allocator: Arc<dyn MemoryAllocator>,
+        binding: Binding<StorageBuffer>,
+    ) -> Self {
+        assert_eq!(
+            materials.len(),
+            variants.len(),
+            "Each material should have a (possibly empty) list of emissive variants"
+        );
+        let active = active_variants(&variants, time_of_day);
+        let resolved: Vec<_> = materials
+            .iter()
+            .zip(active.iter().zip(variants.iter()))
+            .map(|(m, (a, vs))| Self::resolve(m, a.map(|i| &vs[i])))
+            .collect();
+
+        let mut buffer_scheme =
+            DualBuffer::from_iter(resolved.iter().copied(), memory_allocator, false)
+                .with_copy_regions();
+        buffer_scheme.update_staging_buffer_and_prep_copy([(
+            resolved.as_slice(),
+            &full_copy_region(resolved.len()),
+        )]);
+
+        ScheduledMaterialList {
+            component: DataComponent {
+                buffer_scheme,
+                binding: binding.index(),
+            },
+            base: materials.to_vec(),
+            variants,
+            active,
+            resolved,
+        }
+    }
+
+    /// `new` with the materials and emissive variants of every voxel type of the `VoxelSchema` `S`
+    pub fn from_schema<S: VoxelSchema>(
+        time_of_day: f32,
+        memory_allocator: Arc<dyn MemoryAllocator>,
+        binding: Binding<StorageBuffer>,
+    ) -> Self {
+        S::validate();
+        ScheduledMaterialList::new(
+            &S::VoxelType::materials(),
+            S::VoxelType::all_emissive_variants(),
+            time_of_day,
+            memory_allocator,
             binding,
+        )
+    }
+
+    fn resolve(base: &Material, variant: Option<&EmissiveVariant>) -> Material {
+        match variant {
+            None => *base,
+            Some(v) => Material {
+                emission_color: v.emission_color,
+                emission_strength: v.emission_strength,
+                ..*base
+            },
         }
     }
+
+    /// Switch each material to the emission for `time_of_day` (0..1) and queue the materials that changed to
+    /// be uploaded with the next transfer. Returns whether any changed, e.g. to request a redraw.
+    pub fn set_time_of_day(&mut self, time_of_day: f32) -> bool {
+        let active = active_variants(&self.variants, time_of_day);
+        let mut regions = vec![];
+        for (i, a) in active.iter().enumerate() {
+            if *a != self.active[i] {
+                self.resolved[i] = Self::resolve(&self.base[i], a.map(|v| &self.variants[i][v]));
+                let offset = (i * size_of::<Material>()) as u64;
+                regions.push(BufferCopy {
+                    src_offset: offset,
+                    dst_offset: offset,
+                    size: size_of::<Material>() as u64,
+                    ..Default::default()
+                });
+            }
+        }
+        self.active = active;
+
+        let changed = !regions.is_empty();
+        if changed {
+            let resolved = self.resolved.as_slice();
+            self.component
+                .buffer_scheme
+                .update_staging_buffer_and_prep_copy(regions.iter().map(|r| (resolved, r)));
+        }
+        changed
+    }
+
+    /// Materials as they are currently uploaded
+    pub fn resolved(&self) -> &[Material] {
+        &self.resolved
+    }
+}
+
+impl DataComponentSet for ScheduledMaterialList {
+    fn bind(&self, descriptor_writes: &mut Vec<WriteDescriptorSet>) {
+        self.component.bind(descriptor_writes);
+    }
+
+    fn record_repeated_buffer_transfer<L, A: CommandBufferAllocator>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L, A>,
+    ) {
+        self.component.record_repeated_buffer_transfer(builder);
+    }
+
+    fn record_buffer_transfer_jit<L, A: CommandBufferAllocator>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<L, A>,
+    ) {
+        self.component.record_buffer_transfer_jit(builder);
+    }
+
+    fn pending_transfer_bytes(&self) -> u64 {
+        self.component.pending_transfer_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_material_layout() {
+        // std430 layout of the GLSL struct: vec3s are aligned to 16 bytes and the struct is padded to 16 bytes
+        assert_eq!(size_of::<Material>(), 64);
+        let m = Material::transparent([1.; 3], 0.25, 1.5);
+        let base = &m as *const Material as usize;
+        assert_eq!(&m.alpha as *const f32 as usize - base, 56);
+        assert_eq!(&m.ior as *const f32 as usize - base, 60);
+        assert!(!m.is_opaque());
+        assert!(Material::default().is_opaque());
+    }
+
+    #[test]
+    fn test_active_variants() {
+        let variant = |start, end| EmissiveVariant {
+            start,
+            end,
+            emission_color: [1.; 3],
+            emission_strength: 1.,
+        };
+        let night = variant(0.75, 0.25);
+        assert!(night.is_active(0.9));
+        assert!(night.is_active(0.1));
+        assert!(!night.is_active(0.5));
+
+        let variants = vec![vec![], vec![variant(0.4, 0.6), night]];
+        assert_eq!(active_variants(&variants, 0.5), vec![None, Some(0)]);
+        assert_eq!(active_variants(&variants, 0.0), vec![None, Some(1)]);
+        assert_eq!(active_variants(&variants, 0.3), vec![None, None]);
+    }
 }
@@ -1,11 +1,16 @@
 use crate::renderer::buffers::BufferScheme;
+use crate::renderer::PassId;
 use vulkano::command_buffer::allocator::CommandBufferAllocator;
 use vulkano::command_buffer::AutoCommandBufferBuilder;
 use vulkano::descriptor_set::WriteDescriptorSet;
 
 pub mod camera;
+pub mod debug_draw;
+pub mod highlight;
+pub mod luminance;
 pub mod materials;
 pub mod ubo;
+pub mod user_ubo;
 pub mod voxels;
 
 pub trait DataComponentSet {
@@ -20,6 +25,44 @@ pub trait DataComponentSet {
         &mut self,
         builder: &mut AutoCommandBufferBuilder<L, A>,
     );
+
+    /// Number of bytes that will be copied from staging buffers to device local buffers by the next transfer.
+    fn pending_transfer_bytes(&self) -> u64;
+
+    /// Like `record_buffer_transfer_jit`, but for bulk uploads that can lag behind by a few frames, e.g. far LODs
+    /// (see `VoxelData::set_background_lods`). These are recorded into their own command buffer, which the
+    /// renderer only submits when its `UploadBudget` allows. Component sets that contain such
+    /// components must forward this and `pending_background_transfer_bytes` to them, otherwise their background
+    /// uploads never happen.
+    fn record_background_transfer_jit<L, A: CommandBufferAllocator>(
+        &mut self,
+        _builder: &mut AutoCommandBufferBuilder<L, A>,
+    ) {
+    }
+
+    /// Number of bytes `record_background_transfer_jit` would copy
+    fn pending_background_transfer_bytes(&self) -> u64 {
+        0
+    }
+
+    /// Called by `Renderer::draw_frame` right before a frame's compute pass is submitted, once the GPU is
+    /// done with the previous one. Component sets with late latched components (e.g. a `RendererCamera`
+    /// created with `new_late_latched`) must forward this to them.
+    fn late_latch(&mut self) {}
+
+    /// Called by `Renderer::set_pass_enabled`. Component sets that contain components with passes that
+    /// can be disabled (e.g. `VoxelData`) should forward this to them.
+    fn set_pass_enabled(&mut self, _pass: PassId, _enabled: bool) {}
+
+    /// Add the bindings whose buffers were replaced since the last call (see `BufferScheme::take_dirty`) to
+    /// `dirty`, so `Renderer::rebind_dirty_components` only rewrites those. Component sets whose components can
+    /// replace their buffers must forward this to them.
+    fn take_dirty_bindings(&mut self, _dirty: &mut Vec<u32>) {}
+
+    /// Add the writes that replace the ones from `bind` when viewport `viewport` is drawn (see
+    /// `renderer::viewport::Viewport`), e.g. the buffer of that viewport's camera. Component sets that contain
+    /// `ViewportCameras` must forward this to them.
+    fn bind_viewport(&self, _viewport: usize, _descriptor_writes: &mut Vec<WriteDescriptorSet>) {}
 }
 
 #[derive(Debug)]
@@ -52,4 +95,18 @@ impl<B: BufferScheme> DataComponentSet for DataComponent<B> {
     ) {
         self.buffer_scheme.record_transfer_jit(builder);
     }
+
+    fn pending_transfer_bytes(&self) -> u64 {
+        self.buffer_scheme.pending_transfer_bytes()
+    }
+
+    fn late_latch(&mut self) {
+        self.buffer_scheme.late_latch();
+    }
+
+    fn take_dirty_bindings(&mut self, dirty: &mut Vec<u32>) {
+        if self.buffer_scheme.take_dirty() {
+            dirty.push(self.binding);
+        }
+    }
 }
@@ -1,5 +1,9 @@
+use crate::renderer::binding::{Binding, UniformBuffer};
 use crate::renderer::buffers::dual::{DualBuffer, DualBufferWithFullCopy};
-use crate::renderer::component::DataComponent;
+use crate::renderer::component::{DataComponent, DataComponentSet};
+use crate::world::mem_grid::voxel::grid::VoxelMemoryGridMetadata;
+use crate::world::WorldScale;
+use cgmath::{InnerSpace, Vector3};
 use std::sync::Arc;
 use vulkano::buffer::BufferContents;
 use vulkano::memory::allocator::MemoryAllocator;
@@ -10,15 +14,299 @@ pub struct Ubo {
     pub sun_dir: [f32; 3],
     pub time: u32,
     pub start_tlc: [i32; 3], // ENHANCEMENT: These should really be i64, but glsl uses 32 bit ints
+    /// Distance from the camera in LOD 0 voxels where fog starts, see `FogParams`
+    pub fog_start: f32,
+    pub fog_color: [f32; 3],
+    pub fog_end: f32,
+    /// Non-zero to skip drawing the crosshair, highlight outline and debug shapes, see `PhotoSettings`
+    pub hide_overlay: u32,
+    /// Rays traced per pixel each frame
+    pub samples_per_pixel: u32,
+    /// Scale of the crosshair and other overlay elements, usually the window's scale factor (see
+    /// `Renderer::scale_factor`) so they keep their size on high-DPI displays
+    pub ui_scale: f32,
+    _pad: u32,
+    /// Sun and sky light, see `SkyParams`
+    pub sun_color: [f32; 3],
+    pub sun_intensity: f32,
+    pub sky_zenith_color: [f32; 3],
+    pub sky_intensity: f32,
+    pub sky_horizon_color: [f32; 3],
+    /// Cosine of the sun's angular radius
+    pub sun_cos_radius: f32,
+}
+
+impl Default for Ubo {
+    fn default() -> Self {
+        let fog = FogParams::disabled();
+        let photo = PhotoSettings::default();
+        let sky = SkyParams::default();
+        Ubo {
+            sun_dir: sky.sun_dir,
+            time: 0,
+            start_tlc: [0; 3],
+            fog_start: fog.start,
+            fog_color: fog.color,
+            fog_end: fog.end,
+            hide_overlay: photo.hide_overlay as u32,
+            samples_per_pixel: photo.samples_per_pixel,
+            ui_scale: 1.,
+            _pad: 0,
+            sun_color: sky.sun_color,
+            sun_intensity: sky.sun_intensity,
+            sky_zenith_color: sky.zenith_color,
+            sky_intensity: sky.sky_intensity,
+            sky_horizon_color: sky.horizon_color,
+            sun_cos_radius: sky.sun_cos_radius,
+        }
+    }
+}
+
+impl Ubo {
+    pub fn set_fog(&mut self, fog: FogParams) {
+        self.fog_start = fog.start;
+        self.fog_color = fog.color;
+        self.fog_end = fog.end;
+    }
+
+    pub fn fog(&self) -> FogParams {
+        FogParams {
+            start: self.fog_start,
+            end: self.fog_end,
+            color: self.fog_color,
+        }
+    }
+
+    pub fn set_photo_settings(&mut self, settings: PhotoSettings) {
+        self.hide_overlay = settings.hide_overlay as u32;
+        self.samples_per_pixel = settings.samples_per_pixel.max(1);
+    }
+
+    pub fn photo_settings(&self) -> PhotoSettings {
+        PhotoSettings {
+            hide_overlay: self.hide_overlay != 0,
+            samples_per_pixel: self.samples_per_pixel,
+        }
+    }
+
+    pub fn set_sky(&mut self, sky: SkyParams) {
+        self.sun_dir = sky.sun_dir;
+        self.sun_color = sky.sun_color;
+        self.sun_intensity = sky.sun_intensity;
+        self.sky_zenith_color = sky.zenith_color;
+        self.sky_horizon_color = sky.horizon_color;
+        self.sky_intensity = sky.sky_intensity;
+        self.sun_cos_radius = sky.sun_cos_radius;
+    }
+
+    pub fn sky(&self) -> SkyParams {
+        SkyParams {
+            sun_dir: self.sun_dir,
+            sun_color: self.sun_color,
+            sun_intensity: self.sun_intensity,
+            zenith_color: self.sky_zenith_color,
+            horizon_color: self.sky_horizon_color,
+            sky_intensity: self.sky_intensity,
+            sun_cos_radius: self.sun_cos_radius,
+        }
+    }
+}
+
+/// Light from the sun and sky, which is where all light in the scene comes from (besides emissive materials).
+/// Rays that leave the world hit the sun if they are within its radius of `sun_dir`, and otherwise the sky, which
+/// fades from `horizon_color` to `zenith_color` going up and is brighter towards the sun. Once the sun sets, the sun
+/// and most of the sky light fade out. Start from `SkyParams::default()` and change it with the `with_*` methods, or
+/// animate the sun with `at_time_of_day`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkyParams {
+    /// Direction towards the sun (normalized, +y is up)
+    pub sun_dir: [f32; 3],
+    pub sun_color: [f32; 3],
+    pub sun_intensity: f32,
+    /// Color of the sky straight up
+    pub zenith_color: [f32; 3],
+    /// Color of the sky at and below the horizon. Usually the same as the fog color (see `FogParams`).
+    pub horizon_color: [f32; 3],
+    /// Brightness of the sky looking towards the sun. Looking away from the sun, it is half this.
+    pub sky_intensity: f32,
+    /// Cosine of the sun's angular radius
+    pub sun_cos_radius: f32,
+}
+
+impl Default for SkyParams {
+    fn default() -> Self {
+        SkyParams {
+            sun_dir: [0.39036, 0.78072, 0.48795],
+            sun_color: [1.0, 0.98, 0.9],
+            sun_intensity: 1.5,
+            zenith_color: [0.6, 0.78, 0.98],
+            horizon_color: FogParams::DEFAULT_COLOR,
+            sky_intensity: 1.5,
+            sun_cos_radius: 0.999,
+        }
+    }
+}
+
+impl SkyParams {
+    /// How far the sun's path is tilted from passing straight overhead, towards +z
+    pub const SUN_PATH_TILT: f32 = 0.5;
+
+    /// `sun_dir` is normalized
+    pub fn with_sun_dir(self, sun_dir: Vector3<f32>) -> Self {
+        SkyParams {
+            sun_dir: sun_dir.normalize().into(),
+            ..self
+        }
+    }
+
+    pub fn with_sun(self, color: [f32; 3], intensity: f32) -> Self {
+        SkyParams {
+            sun_color: color,
+            sun_intensity: intensity,
+            ..self
+        }
+    }
+
+    pub fn with_sky(self, zenith_color: [f32; 3], horizon_color: [f32; 3], intensity: f32) -> Self {
+        SkyParams {
+            zenith_color,
+            horizon_color,
+            sky_intensity: intensity,
+            ..self
+        }
+    }
+
+    /// Angular radius of the sun in radians
+    pub fn with_sun_radius(self, radius: f32) -> Self {
+        SkyParams {
+            sun_cos_radius: radius.cos(),
+            ..self
+        }
+    }
+
+    /// This sky with the sun where it is at `hours` (wrapped to 0-24) into the day. The sun rises in +x at 6,
+    /// is highest at 12 (tilted by `SUN_PATH_TILT`) and sets in -x at 18.
+    pub fn at_time_of_day(self, hours: f32) -> Self {
+        let angle = (hours.rem_euclid(24.) - 6.) / 24. * std::f32::consts::TAU;
+        let (elevation, horizontal) = angle.sin_cos();
+        let (tilt_sin, tilt_cos) = Self::SUN_PATH_TILT.sin_cos();
+        self.with_sun_dir(Vector3::new(
+            horizontal,
+            elevation * tilt_cos,
+            elevation * tilt_sin,
+        ))
+    }
+}
+
+/// Distance fog blended over the first hit of each camera ray. Fog increases linearly from none at `start` to
+/// fully `color` at `end` (distances in LOD 0 voxels). Fog is disabled when `end <= start`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FogParams {
+    pub start: f32,
+    pub end: f32,
+    pub color: [f32; 3],
+}
+
+impl FogParams {
+    /// Roughly the color of the sky near the horizon, so that fogged terrain fades into the sky
+    pub const DEFAULT_COLOR: [f32; 3] = [0.7, 0.9, 1.0];
+
+    pub fn disabled() -> Self {
+        FogParams {
+            start: 0.,
+            end: 0.,
+            color: FogParams::DEFAULT_COLOR,
+        }
+    }
+
+    /// Fog that ends where the largest LOD's render area ends (as seen from anywhere in the center TLC),
+    /// hiding the edge of the loaded chunks
+    pub fn for_render_area(metadata: &VoxelMemoryGridMetadata) -> Self {
+        let end = ((metadata.render_area_size() - 1) / 2 * metadata.tlc_size()) as f32;
+        FogParams {
+            start: end * 0.6,
+            end,
+            color: FogParams::DEFAULT_COLOR,
+        }
+    }
+
+    /// This fog with `start` and `end` given in meters converted to LOD 0 voxels
+    pub fn in_voxels(self, scale: WorldScale) -> Self {
+        FogParams {
+            start: scale.to_voxels(self.start),
+            end: scale.to_voxels(self.end),
+            ..self
+        }
+    }
+}
+
+/// Overlay and quality settings of the raytrace pass, changed by `photo::PhotoMode` to take screenshots
+/// without the crosshair and with less noise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhotoSettings {
+    /// Skip drawing the crosshair, highlight outline and debug shapes (see `DebugDraw`)
+    pub hide_overlay: bool,
+    /// Rays traced per pixel each frame (at least 1). Frame time grows about linearly with this.
+    pub samples_per_pixel: u32,
+}
+
+impl Default for PhotoSettings {
+    fn default() -> Self {
+        PhotoSettings {
+            hide_overlay: false,
+            samples_per_pixel: 2,
+        }
+    }
+}
+
+impl PhotoSettings {
+    /// Overlay hidden and 8 times the default samples
+    pub fn photo() -> Self {
+        PhotoSettings {
+            hide_overlay: true,
+            samples_per_pixel: 16,
+        }
+    }
 }
 
 pub type RendererUBO = DataComponent<DualBufferWithFullCopy<Ubo>>;
 
 impl RendererUBO {
-    pub fn new(value: Ubo, memory_allocator: Arc<dyn MemoryAllocator>, binding: u32) -> Self {
+    pub fn new(
+        value: Ubo,
+        memory_allocator: Arc<dyn MemoryAllocator>,
+        binding: Binding<UniformBuffer>,
+    ) -> Self {
         DataComponent {
             buffer_scheme: DualBuffer::from_data(value, memory_allocator, true).with_full_copy(),
-            binding,
+            binding: binding.index(),
         }
     }
 }
+
+/// Implemented by component sets that contain a `RendererUBO` so that the renderer can update it through
+/// `Renderer::set_fog`, `Renderer::set_sky`, `Renderer::set_photo_settings` and `Renderer::set_ui_scale`.
+pub trait DataComponentSetWithUbo: DataComponentSet {
+    fn ubo_mut(&mut self) -> &mut RendererUBO;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_time_of_day() {
+        let at = |hours: f32| Vector3::from(SkyParams::default().at_time_of_day(hours).sun_dir);
+        let eps = 1e-5;
+
+        let sunrise = at(6.);
+        assert!((sunrise - Vector3::unit_x()).magnitude() < eps);
+        let noon = at(12.);
+        assert!(noon.x.abs() < eps && noon.y > 0.8 && noon.z > 0.);
+        assert!((noon.magnitude() - 1.).abs() < eps);
+        assert!((at(18.) + Vector3::unit_x()).magnitude() < eps);
+        assert!(at(0.).y < 0.);
+        assert!((at(36.) - noon).magnitude() < eps);
+        assert!((at(-6.) - at(18.)).magnitude() < eps);
+    }
+}
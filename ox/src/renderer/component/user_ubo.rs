@@ -0,0 +1,85 @@
+use crate::renderer::binding::{Binding, UniformBuffer};
+use crate::renderer::buffers::dual::{DualBuffer, DualBufferWithFullCopy};
+use crate::renderer::component::{DataComponent, DataComponentSet};
+use std::sync::Arc;
+use vulkano::buffer::{BufferContents, BufferWriteGuard};
+use vulkano::command_buffer::allocator::CommandBufferAllocator;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::memory::allocator::MemoryAllocator;
+
+/// Uniform buffer with a game defined layout, for per-frame parameters that `Ubo` doesn't have (wind time,
+/// screen shake, custom effects, ...). Like `Ubo`, it is written to a staging buffer and copied to the GPU
+/// every frame.
+///
+/// `T` must match a std140 uniform block in the shader at the binding passed to `new`, e.g.:
+///
+/// ```ignore
+/// #[derive(BufferContents, Debug, Clone, Copy, Default)]
+/// #[repr(C)]
+/// struct Effects {
+///     wind_time: f32,
+///     shake: f32,
+/// }
+///
+/// // layout(binding = BINDING_EFFECTS) uniform Effects { float wind_time; float shake; } effects;
+/// let effects = UserUbo::new(Effects::default(), bindings.register("effects"), allocator);
+/// ```
+///
+/// Add it to your `DataComponentSet` and forward to it like any other component.
+pub struct UserUbo<T: BufferContents> {
+    component: DataComponent<DualBufferWithFullCopy<T>>,
+}
+
+impl<T: BufferContents> UserUbo<T> {
+    pub fn new(
+        value: T,
+        binding: Binding<UniformBuffer>,
+        allocator: Arc<dyn MemoryAllocator>,
+    ) -> Self {
+        UserUbo {
+            component: DataComponent {
+                buffer_scheme: DualBuffer::from_data(value, allocator, true).with_full_copy(),
+                binding: binding.index(),
+            },
+        }
+    }
+
+    /// Replace the whole value. It is copied to the GPU in the next frame.
+    pub fn set(&mut self, value: T) {
+        *self.write() = value;
+    }
+
+    /// Edit the value in place. It is copied to the GPU in the next frame.
+    pub fn write(&mut self) -> BufferWriteGuard<'_, T> {
+        self.component.buffer_scheme.write_staging()
+    }
+
+    pub fn binding(&self) -> u32 {
+        self.component.binding
+    }
+}
+
+impl<T: BufferContents> DataComponentSet for UserUbo<T> {
+    fn bind(&self, descriptor_writes: &mut Vec<WriteDescriptorSet>) {
+        self.component.bind(descriptor_writes);
+    }
+
+    fn record_repeated_buffer_transfer<L, A: CommandBufferAllocator>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L, A>,
+    ) {
+        self.component.record_repeated_buffer_transfer(builder);
+    }
+
+    fn record_buffer_transfer_jit<L, A: CommandBufferAllocator>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<L, A>,
+    ) {
+        self.component.record_buffer_transfer_jit(builder);
+    }
+
+    fn pending_transfer_bytes(&self) -> u64 {
+        self.component.pending_transfer_bytes()
+    }
+}
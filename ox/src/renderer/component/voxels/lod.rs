@@ -1,24 +1,33 @@
 use super::data::{VoxelBitmask, VoxelTypeIDs};
+use crate::renderer::binding::{Binding, StorageBuffer};
 use crate::renderer::buffers::{
     dual::{DualBuffer, DualBufferWithDynamicCopyRegions},
     BufferScheme,
 };
 use crate::renderer::component::{DataComponent, DataComponentSet};
+use std::borrow::Cow;
+use std::mem::size_of;
 use std::sync::Arc;
 use vulkano::command_buffer::allocator::CommandBufferAllocator;
 use vulkano::command_buffer::{AutoCommandBufferBuilder, BufferCopy};
 use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::DeviceOwned;
 use vulkano::memory::allocator::MemoryAllocator;
 
 #[derive(Debug, Clone)]
 pub struct VoxelIDUpdate<'a> {
-    pub ids: &'a [VoxelTypeIDs],
+    pub ids: Cow<'a, [VoxelTypeIDs]>,
     pub updated_region: BufferCopy,
 }
 
+/// Changed region of a chunk in one LOD, borrowed from the memory grid (see `VoxelMemoryGrid::get_updates`), or
+/// converted to the GPU layout if the chunk is stored sparsely.
+/// `RendererVoxelLOD::update_staging_buffers_and_prep_copy` copies the data into the renderer's staging buffers
+/// before it returns, so updates only need to live until then; the transfer to the GPU reads the staging
+/// buffers, never the memory grid.
 #[derive(Debug, Clone)]
 pub struct VoxelLODUpdate<'a> {
-    pub bitmask: &'a [VoxelBitmask],
+    pub bitmask: Cow<'a, [VoxelBitmask]>,
     pub bitmask_updated_region: BufferCopy,
     pub id_update: Option<VoxelIDUpdate<'a>>,
 }
@@ -27,6 +36,7 @@ pub struct VoxelLODUpdate<'a> {
 pub struct RendererVoxelLOD {
     pub bitmask_buffers: DataComponent<DualBufferWithDynamicCopyRegions<VoxelBitmask>>,
     pub id_buffers: Option<DataComponent<DualBufferWithDynamicCopyRegions<VoxelTypeIDs>>>,
+    voxels_per_tlc: usize,
 }
 
 impl RendererVoxelLOD {
@@ -36,10 +46,39 @@ impl RendererVoxelLOD {
     >(
         bitmask_iter: BMI,
         voxel_id_iter: Option<VII>,
-        bitmask_binding: u32,
-        voxel_id_binding: Option<u32>,
+        bitmask_binding: Binding<StorageBuffer>,
+        voxel_id_binding: Option<Binding<StorageBuffer>>,
+        voxels_per_tlc: usize,
         memory_allocator: Arc<dyn MemoryAllocator>,
     ) -> Self {
+        let max_range = memory_allocator
+            .device()
+            .physical_device()
+            .properties()
+            .max_storage_buffer_range as usize;
+        let check_size = |kind: &str, binding: u32, bytes: usize| {
+            assert!(
+                bytes <= max_range,
+                "Voxel {} buffer at binding {} is {} bytes, but the device's largest storage buffer is {} bytes",
+                kind,
+                binding,
+                bytes,
+                max_range
+            );
+        };
+        check_size(
+            "bitmask",
+            bitmask_binding.index(),
+            bitmask_iter.len() * size_of::<VoxelBitmask>(),
+        );
+        if let (Some(iter), Some(binding)) = (&voxel_id_iter, voxel_id_binding) {
+            check_size(
+                "ID",
+                binding.index(),
+                iter.len() * size_of::<VoxelTypeIDs>(),
+            );
+        }
+
         RendererVoxelLOD {
             bitmask_buffers: DataComponent {
                 buffer_scheme: DualBuffer::from_iter(
@@ -48,16 +87,34 @@ impl RendererVoxelLOD {
                     false,
                 )
                 .with_copy_regions(),
-                binding: bitmask_binding,
+                binding: bitmask_binding.index(),
             },
             id_buffers: voxel_id_iter.map(|iter| DataComponent {
                 buffer_scheme: DualBuffer::from_iter(iter, memory_allocator, false)
                     .with_copy_regions(),
-                binding: voxel_id_binding.unwrap(),
+                binding: voxel_id_binding.unwrap().index(),
             }),
+            voxels_per_tlc,
+        }
+    }
+
+    /// Use pooled staging buffers sized for `chunks_per_frame` chunk updates per frame instead of staging
+    /// buffers that mirror the whole LOD (see `DualBufferWithDynamicCopyRegions::pool_staging`).
+    pub fn pool_staging(&mut self, allocator: Arc<dyn MemoryAllocator>, chunks_per_frame: usize) {
+        self.bitmask_buffers.buffer_scheme.pool_staging(
+            Arc::clone(&allocator),
+            VoxelBitmask::new_vec(self.voxels_per_tlc).len() * chunks_per_frame,
+        );
+        if let Some(comp) = &mut self.id_buffers {
+            comp.buffer_scheme.pool_staging(
+                allocator,
+                VoxelTypeIDs::new_vec(self.voxels_per_tlc).len() * chunks_per_frame,
+            );
         }
     }
 
+    /// Copy the updated regions into the staging buffers and queue them for the next transfer. Must be called
+    /// through `Renderer::start_updating_staging_buffers` (see `VoxelLODUpdate`).
     pub fn update_staging_buffers_and_prep_copy(&mut self, updates: &Vec<VoxelLODUpdate>) {
         if updates.len() == 0 {
             return;
@@ -68,7 +125,7 @@ impl RendererVoxelLOD {
             .update_staging_buffer_and_prep_copy(
                 updates
                     .iter()
-                    .map(|u| (u.bitmask, &u.bitmask_updated_region)),
+                    .map(|u| (&*u.bitmask, &u.bitmask_updated_region)),
             );
         match &mut self.id_buffers {
             None => {}
@@ -78,7 +135,7 @@ impl RendererVoxelLOD {
                         let id_u = u.id_update.as_ref().expect(
                             "Renderer did not receive ID update for LOD that has voxel IDs.",
                         );
-                        (id_u.ids, &id_u.updated_region)
+                        (&*id_u.ids, &id_u.updated_region)
                     }));
             }
         };
@@ -116,4 +173,20 @@ impl DataComponentSet for RendererVoxelLOD {
             comp.buffer_scheme.record_transfer_jit(builder);
         }
     }
+
+    fn pending_transfer_bytes(&self) -> u64 {
+        self.bitmask_buffers.pending_transfer_bytes()
+            + self
+                .id_buffers
+                .as_ref()
+                .map(|comp| comp.pending_transfer_bytes())
+                .unwrap_or(0)
+    }
+
+    fn take_dirty_bindings(&mut self, dirty: &mut Vec<u32>) {
+        self.bitmask_buffers.take_dirty_bindings(dirty);
+        if let Some(comp) = &mut self.id_buffers {
+            comp.take_dirty_bindings(dirty);
+        }
+    }
 }
@@ -0,0 +1,121 @@
+use super::data::VoxelTypeIDs;
+use crate::renderer::binding::{Binding, StorageBuffer};
+use crate::renderer::buffers::{
+    dual::{DualBuffer, DualBufferWithDynamicCopyRegions},
+    BufferScheme,
+};
+use crate::renderer::component::{DataComponent, DataComponentSet};
+use crate::world::mem_grid::voxel::micro::{MicrovoxelGrid, MicrovoxelUpdate};
+use std::sync::Arc;
+use vulkano::buffer::BufferContents;
+use vulkano::command_buffer::allocator::CommandBufferAllocator;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::memory::allocator::MemoryAllocator;
+
+/// Entry of the microvoxel slot table. Matches this GLSL struct:
+///
+/// ```glsl
+/// struct MicrovoxelSlot {
+///     ivec3 chunk;
+///     uint in_use;
+/// };
+/// ```
+#[derive(BufferContents, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct MicrovoxelSlot {
+    /// Global position of the level 1 chunk in units of level 1 chunks
+    pub chunk: [i32; 3], // ENHANCEMENT: These should really be i64, but glsl uses 32 bit ints
+    /// 0 when the slot is free
+    pub in_use: u32,
+}
+
+/// Microvoxel IDs and slot table for a `MicrovoxelGrid`. Only use this with the shader's `MICROVOXELS` define
+/// set, since the bindings don't exist otherwise.
+#[derive(Debug)]
+pub struct RendererMicrovoxels {
+    pub id_buffers: DataComponent<DualBufferWithDynamicCopyRegions<VoxelTypeIDs>>,
+    pub slot_buffers: DataComponent<DualBufferWithDynamicCopyRegions<MicrovoxelSlot>>,
+}
+
+impl RendererMicrovoxels {
+    pub fn new(
+        grid: &mut MicrovoxelGrid,
+        voxel_id_binding: Binding<StorageBuffer>,
+        slot_binding: Binding<StorageBuffer>,
+        memory_allocator: Arc<dyn MemoryAllocator>,
+    ) -> Self {
+        // Everything is copied to the GPU here, so the pending updates are no longer needed
+        let MicrovoxelUpdate { ids, slots, .. } = grid.get_updates();
+        RendererMicrovoxels {
+            id_buffers: DataComponent {
+                buffer_scheme: DualBuffer::from_iter(
+                    ids.iter().copied(),
+                    Arc::clone(&memory_allocator),
+                    false,
+                )
+                .with_copy_regions(),
+                binding: voxel_id_binding.index(),
+            },
+            slot_buffers: DataComponent {
+                buffer_scheme: DualBuffer::from_iter(
+                    slots.iter().copied(),
+                    memory_allocator,
+                    false,
+                )
+                .with_copy_regions(),
+                binding: slot_binding.index(),
+            },
+        }
+    }
+
+    pub fn update_staging_buffers_and_prep_copy(&mut self, update: &MicrovoxelUpdate) {
+        self.id_buffers
+            .buffer_scheme
+            .update_staging_buffer_and_prep_copy(
+                update
+                    .ids_updated_regions
+                    .iter()
+                    .map(|region| (update.ids, region)),
+            );
+        self.slot_buffers
+            .buffer_scheme
+            .update_staging_buffer_and_prep_copy(
+                update
+                    .slots_updated_regions
+                    .iter()
+                    .map(|region| (update.slots, region)),
+            );
+    }
+}
+
+impl DataComponentSet for RendererMicrovoxels {
+    fn bind(&self, descriptor_writes: &mut Vec<WriteDescriptorSet>) {
+        self.id_buffers.bind(descriptor_writes);
+        self.slot_buffers.bind(descriptor_writes);
+    }
+
+    fn record_repeated_buffer_transfer<L, A: CommandBufferAllocator>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L, A>,
+    ) {
+        self.id_buffers
+            .buffer_scheme
+            .record_repeated_transfer(builder);
+        self.slot_buffers
+            .buffer_scheme
+            .record_repeated_transfer(builder);
+    }
+
+    fn record_buffer_transfer_jit<L, A: CommandBufferAllocator>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<L, A>,
+    ) {
+        self.id_buffers.buffer_scheme.record_transfer_jit(builder);
+        self.slot_buffers.buffer_scheme.record_transfer_jit(builder);
+    }
+
+    fn pending_transfer_bytes(&self) -> u64 {
+        self.id_buffers.pending_transfer_bytes() + self.slot_buffers.pending_transfer_bytes()
+    }
+}
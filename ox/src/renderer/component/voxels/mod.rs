@@ -1,26 +1,78 @@
 use crate::renderer::component::voxels::lod::{RendererVoxelLOD, VoxelLODUpdate};
 use crate::renderer::component::DataComponentSet;
+use crate::renderer::PassId;
+use std::sync::Arc;
 use vulkano::command_buffer::allocator::CommandBufferAllocator;
 use vulkano::command_buffer::AutoCommandBufferBuilder;
 use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::memory::allocator::MemoryAllocator;
 
-pub mod data;
 pub mod lod;
+pub mod micro;
+
+pub use ox_core::voxel::data;
 
 pub struct VoxelData<const N: usize> {
     lods: [RendererVoxelLOD; N],
+    upload_enabled: [bool; N],
+    // LODs uploaded by the background transfer instead of every frame
+    background: [bool; N],
 }
 
 impl<const N: usize> VoxelData<N> {
     pub fn new(lods: [RendererVoxelLOD; N]) -> Self {
-        VoxelData { lods }
+        VoxelData {
+            lods,
+            upload_enabled: [true; N],
+            background: [false; N],
+        }
+    }
+
+    /// Upload LODs from index `first_lod` on (the far ones) in the renderer's background transfer instead of every
+    /// frame's transfer, so bulk uploads while a lot of chunks are streaming in don't hold up the camera and close
+    /// detail (see `UploadBudget`). Pass `N` to upload every LOD every frame, which is the default.
+    pub fn set_background_lods(&mut self, first_lod: usize) {
+        for (i, background) in self.background.iter_mut().enumerate() {
+            *background = i >= first_lod;
+        }
     }
 
+    /// Enabled LODs that are uploaded by the background transfer if `background`, or every frame otherwise
+    fn uploaded_lods(
+        &mut self,
+        background: bool,
+    ) -> impl Iterator<Item = &mut RendererVoxelLOD> + '_ {
+        self.lods
+            .iter_mut()
+            .zip(self.upload_enabled.iter().zip(self.background))
+            .filter(move |(_, (enabled, bg))| **enabled && *bg == background)
+            .map(|(lod, _)| lod)
+    }
+
+    fn pending_bytes(&self, background: bool) -> u64 {
+        self.lods
+            .iter()
+            .zip(self.upload_enabled.iter().zip(self.background))
+            .filter(|(_, (enabled, bg))| **enabled && *bg == background)
+            .map(|(lod, _)| lod.pending_transfer_bytes())
+            .sum()
+    }
+
+    /// Copy each LOD's updates into its staging buffers. The updates aren't kept, so the memory grid can be
+    /// edited again as soon as this returns (see `VoxelLODUpdate`).
     pub fn update_staging_buffers_and_prep_copy(&mut self, updates: [Vec<VoxelLODUpdate>; N]) {
         for (lod, lod_updates) in self.lods.iter_mut().zip(updates.into_iter()) {
             lod.update_staging_buffers_and_prep_copy(&lod_updates);
         }
     }
+
+    /// Pool the staging buffers of every LOD, sized for `chunks_per_frame` chunk updates per frame. The pools
+    /// grow if more chunks are updated before a transfer and shrink again when they stay mostly unused.
+    pub fn pool_staging(&mut self, allocator: Arc<dyn MemoryAllocator>, chunks_per_frame: usize) {
+        for lod in self.lods.iter_mut() {
+            lod.pool_staging(Arc::clone(&allocator), chunks_per_frame);
+        }
+    }
 }
 
 impl<const N: usize> DataComponentSet for VoxelData<N> {
@@ -43,8 +95,38 @@ impl<const N: usize> DataComponentSet for VoxelData<N> {
         &mut self,
         builder: &mut AutoCommandBufferBuilder<L, A>,
     ) {
-        for lod in self.lods.iter_mut() {
+        // Copy regions of disabled LODs are kept and copied once they are enabled again
+        for lod in self.uploaded_lods(false) {
             lod.record_buffer_transfer_jit(builder);
         }
     }
+
+    fn pending_transfer_bytes(&self) -> u64 {
+        self.pending_bytes(false)
+    }
+
+    fn record_background_transfer_jit<L, A: CommandBufferAllocator>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<L, A>,
+    ) {
+        for lod in self.uploaded_lods(true) {
+            lod.record_buffer_transfer_jit(builder);
+        }
+    }
+
+    fn pending_background_transfer_bytes(&self) -> u64 {
+        self.pending_bytes(true)
+    }
+
+    fn set_pass_enabled(&mut self, pass: PassId, enabled: bool) {
+        if let PassId::LodUpload(lod) = pass {
+            self.upload_enabled[lod] = enabled;
+        }
+    }
+
+    fn take_dirty_bindings(&mut self, dirty: &mut Vec<u32>) {
+        for lod in self.lods.iter_mut() {
+            lod.take_dirty_bindings(dirty);
+        }
+    }
 }
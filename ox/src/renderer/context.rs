@@ -1,54 +1,199 @@
+use crate::config::OxConfig;
 use std::sync::Arc;
 use vulkano::device::physical::PhysicalDevice;
 use vulkano::device::{
-    Device, DeviceCreateInfo, DeviceExtensions, Queue, QueueCreateInfo, QueueFlags,
+    Device, DeviceCreateInfo, DeviceExtensions, Features, Queue, QueueCreateInfo, QueueFlags,
 };
 use vulkano::instance::debug::ValidationFeatureEnable;
-use vulkano::instance::{Instance, InstanceCreateInfo, InstanceExtensions};
+use vulkano::instance::{Instance, InstanceCreateFlags, InstanceCreateInfo, InstanceExtensions};
 use vulkano::memory::allocator::StandardMemoryAllocator;
-use vulkano::swapchain::Surface;
+use vulkano::swapchain::{PresentMode, Surface};
 use vulkano::VulkanLibrary;
+use winit::dpi::PhysicalSize;
 use winit::event_loop::EventLoop;
 use winit::window::{CursorGrabMode, Window, WindowBuilder};
 
+/// Vulkan objects shared by the renderer.
+///
+/// Frames are written to swapchain images by a compute shader on `compute_queue` and presented on
+/// `present_queue`. Where possible these are the same queue. Otherwise they come from different queue
+/// families, and swapchain images are created with concurrent sharing between the two families instead of
+/// transferring ownership of each image every frame (see `QueueFamilies`).
+///
+/// A headless context (`Context::new_headless`) has no surface and can only be used with
+/// `Renderer::new_offscreen`. Its present queue is the compute queue.
 pub struct Context {
     pub instance: Arc<Instance>,
-    pub surface: Arc<Surface>,
+    /// `None` for a headless context
+    pub surface: Option<Arc<Surface>>,
     pub physical_device: Arc<PhysicalDevice>,
     pub device: Arc<Device>,
     pub transfer_queue: Arc<Queue>,
     pub compute_queue: Arc<Queue>,
-    pub graphics_queue: Arc<Queue>,
+    /// Queue that can present to `surface`
+    pub present_queue: Arc<Queue>,
     pub memory_allocator: Arc<StandardMemoryAllocator>,
+    /// Present mode the swapchain is created with (see `choose_present_mode`)
+    pub present_mode: PresentMode,
 }
-impl Context {
-    pub fn new(event_loop: &EventLoop<()>) -> (Self, Arc<Window>) {
-        let library = VulkanLibrary::new().expect("no local Vulkan library/DLL");
 
-        let required_extensions = Surface::required_extensions(event_loop);
-        let instance = Instance::new(
-            library,
-            InstanceCreateInfo {
-                enabled_extensions: required_extensions.union(&InstanceExtensions {
-                    ext_validation_features: true,
-                    ..Default::default()
-                }),
-                enabled_layers: vec![
-                    "VK_LAYER_KHRONOS_validation".to_string(),
-                    // "VK_LAYER_LUNARG_api_dump".to_string(),
-                ],
-                enabled_validation_features: vec![
+/// Queue family indices used by `Context`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueFamilies {
+    pub transfer: u32,
+    pub compute: u32,
+    pub present: u32,
+}
+
+impl QueueFamilies {
+    /// Pick queue families given each family's flags and whether it can present to the surface. A compute
+    /// family that can also present is preferred so that frames don't need to be shared between families.
+    pub fn select(families: &[QueueFlags], supports_present: impl Fn(u32) -> bool) -> Self {
+        let first = |pred: &dyn Fn(u32, &QueueFlags) -> bool| {
+            families
+                .iter()
+                .enumerate()
+                .find(|(i, flags)| pred(*i as u32, flags))
+                .map(|(i, _)| i as u32)
+        };
+        let transfer = first(&|_, f| f.contains(QueueFlags::TRANSFER)).expect("No transfer queue");
+        let compute = first(&|i, f| f.contains(QueueFlags::COMPUTE) && supports_present(i))
+            .or_else(|| first(&|_, f| f.contains(QueueFlags::COMPUTE)))
+            .expect("No compute queue");
+        let present = if supports_present(compute) {
+            compute
+        } else {
+            first(&|i, _| supports_present(i)).expect("No queue family can present to the surface")
+        };
+        QueueFamilies {
+            transfer,
+            compute,
+            present,
+        }
+    }
+
+    /// Whether the compute and present families differ, in which case swapchain images are shared
+    /// concurrently between them
+    pub fn separate_present(&self) -> bool {
+        self.compute != self.present
+    }
+
+    /// Distinct families, in the order transfer, compute, present
+    pub fn unique(&self) -> Vec<u32> {
+        let mut unique = vec![];
+        for family in [self.transfer, self.compute, self.present] {
+            if !unique.contains(&family) {
+                unique.push(family);
+            }
+        }
+        unique
+    }
+}
+
+const VALIDATION_LAYER: &str = "VK_LAYER_KHRONOS_validation";
+
+/// Present mode for `vsync`. Without vsync, mailbox is preferred over immediate to avoid tearing. FIFO is
+/// always supported, so it is the fallback either way.
+pub fn choose_present_mode(vsync: bool, supported: &[PresentMode]) -> PresentMode {
+    if vsync {
+        return PresentMode::Fifo;
+    }
+    [PresentMode::Mailbox, PresentMode::Immediate]
+        .into_iter()
+        .find(|mode| supported.contains(mode))
+        .unwrap_or(PresentMode::Fifo)
+}
+
+/// Create an instance with `required_extensions`, plus validation if `validation` is set and the validation
+/// layer is installed.
+/// Portability subset devices (e.g. MoltenVK on macOS) are included when enumerating devices; vulkano
+/// enables `VK_KHR_portability_enumeration` if the driver supports it and `VK_KHR_portability_subset`
+/// on devices that need it.
+pub(crate) fn create_instance(
+    library: Arc<VulkanLibrary>,
+    required_extensions: InstanceExtensions,
+    validation: bool,
+) -> Arc<Instance> {
+    let has_validation_layer = validation
+        && library
+            .layer_properties()
+            .map(|mut layers| layers.any(|l| l.name() == VALIDATION_LAYER))
+            .unwrap_or(false);
+    if validation && !has_validation_layer {
+        println!(
+            "WARNING: {} is not installed, running without validation",
+            VALIDATION_LAYER
+        );
+    }
+    let enabled_layers = if has_validation_layer {
+        vec![
+            VALIDATION_LAYER.to_string(),
+            // "VK_LAYER_LUNARG_api_dump".to_string(),
+        ]
+    } else {
+        vec![]
+    };
+    let validation_features = library
+        .supported_extensions_with_layers(enabled_layers.iter().map(String::as_str))
+        .map(|e| e.ext_validation_features)
+        .unwrap_or(false);
+
+    Instance::new(
+        library,
+        InstanceCreateInfo {
+            flags: InstanceCreateFlags::ENUMERATE_PORTABILITY,
+            enabled_extensions: required_extensions.union(&InstanceExtensions {
+                ext_validation_features: validation_features,
+                ..Default::default()
+            }),
+            enabled_layers,
+            enabled_validation_features: if validation_features {
+                vec![
                     ValidationFeatureEnable::BestPractices,
                     ValidationFeatureEnable::GpuAssisted,
                     ValidationFeatureEnable::GpuAssistedReserveBindingSlot,
                     ValidationFeatureEnable::SynchronizationValidation,
-                ],
-                ..Default::default()
+                ]
+            } else {
+                vec![]
             },
-        )
-        .expect("failed to create instance");
+            ..Default::default()
+        },
+    )
+    .expect("failed to create instance")
+}
+
+/// First physical device that supports `extensions`, preferring devices that implement all of Vulkan over
+/// portability subset devices.
+pub(crate) fn select_physical_device(
+    instance: &Arc<Instance>,
+    extensions: &DeviceExtensions,
+) -> Arc<PhysicalDevice> {
+    instance
+        .enumerate_physical_devices()
+        .unwrap()
+        .filter(|p| p.supported_extensions().contains(extensions))
+        .min_by_key(|p| p.supported_extensions().khr_portability_subset)
+        .expect("No devices supporting the required extensions.")
+}
+
+impl Context {
+    pub fn new(event_loop: &EventLoop<()>) -> (Self, Arc<Window>) {
+        Self::with_config(event_loop, &OxConfig::default())
+    }
 
-        let window = Arc::new(WindowBuilder::new().build(&event_loop).unwrap());
+    /// Create the context and window with the window size, vsync and validation options from `config`
+    pub fn with_config(event_loop: &EventLoop<()>, config: &OxConfig) -> (Self, Arc<Window>) {
+        let library = VulkanLibrary::new().expect("no local Vulkan library/DLL");
+
+        let required_extensions = Surface::required_extensions(event_loop);
+        let instance = create_instance(library, required_extensions, config.validation);
+
+        let mut window_builder = WindowBuilder::new();
+        if let Some([width, height]) = config.window_size {
+            window_builder = window_builder.with_inner_size(PhysicalSize::new(width, height));
+        }
+        let window = Arc::new(window_builder.build(&event_loop).unwrap());
         window
             .set_cursor_grab(CursorGrabMode::Locked)
             .unwrap_or_default();
@@ -62,102 +207,168 @@ impl Context {
             .downcast::<Window>()
             .unwrap();
 
+        (Self::from_surface(instance, Some(surface), config), window)
+    }
+
+    /// Create a context without a window or surface, e.g. for rendering frames offscreen in tests or batch
+    /// renders (see `Renderer::new_offscreen`). Only the validation option of `config` is used.
+    pub fn new_headless(config: &OxConfig) -> Self {
+        let library = VulkanLibrary::new().expect("no local Vulkan library/DLL");
+        let instance = create_instance(library, InstanceExtensions::empty(), config.validation);
+        Self::from_surface(instance, None, config)
+    }
+
+    fn from_surface(
+        instance: Arc<Instance>,
+        surface: Option<Arc<Surface>>,
+        config: &OxConfig,
+    ) -> Self {
         let device_extensions = DeviceExtensions {
-            khr_swapchain: true,
+            khr_swapchain: surface.is_some(),
             ext_scalar_block_layout: true,
             ..DeviceExtensions::empty()
         };
 
-        let physical_device = instance
-            .enumerate_physical_devices()
-            .unwrap()
-            .next()
-            .expect("No devices.");
-        let transfer_queue_family_i = physical_device
-            .queue_family_properties()
-            .iter()
-            .position(|f| f.queue_flags.contains(QueueFlags::TRANSFER))
-            .expect("No transfer queue") as u32;
-        let compute_queue_family_i = physical_device
-            .queue_family_properties()
-            .iter()
-            .position(|f| f.queue_flags.contains(QueueFlags::COMPUTE))
-            .expect("No compute queue") as u32;
-        let graphics_queue_family_i = physical_device
-            .queue_family_properties()
-            .iter()
-            .position(|f| f.queue_flags.contains(QueueFlags::GRAPHICS))
-            .expect("No graphics queue") as u32;
-
-        let (queue_idxs, queue_family_idxs) = {
-            if transfer_queue_family_i == compute_queue_family_i
-                && compute_queue_family_i == graphics_queue_family_i
-            {
-                (vec![transfer_queue_family_i], vec![0, 0, 0])
-            } else if transfer_queue_family_i == compute_queue_family_i {
-                (
-                    vec![transfer_queue_family_i, graphics_queue_family_i],
-                    vec![0, 0, 1],
-                )
-            } else if transfer_queue_family_i == graphics_queue_family_i {
-                (
-                    vec![transfer_queue_family_i, compute_queue_family_i],
-                    vec![0, 1, 0],
-                )
-            } else if compute_queue_family_i == graphics_queue_family_i {
-                (
-                    vec![transfer_queue_family_i, compute_queue_family_i],
-                    vec![0, 1, 1],
-                )
-            } else {
-                (
-                    vec![
-                        transfer_queue_family_i,
-                        compute_queue_family_i,
-                        graphics_queue_family_i,
-                    ],
-                    vec![0, 1, 2],
-                )
-            }
+        let physical_device = select_physical_device(&instance, &device_extensions);
+
+        // Lets the shader write frames to whichever storage image format the surface offers (see
+        // `OutputFormat`). Virtually all desktop devices support it.
+        let enabled_features = Features {
+            shader_storage_image_write_without_format: physical_device
+                .supported_features()
+                .shader_storage_image_write_without_format,
+            ..Features::empty()
         };
+        let families = QueueFamilies::select(
+            &physical_device
+                .queue_family_properties()
+                .iter()
+                .map(|f| f.queue_flags)
+                .collect::<Vec<_>>(),
+            // Without a surface nothing is presented, so any family will do
+            |i| {
+                surface.as_ref().map_or(true, |surface| {
+                    physical_device.surface_support(i, surface).unwrap_or(false)
+                })
+            },
+        );
+        let unique_families = families.unique();
 
         let (device, queues) = Device::new(
             Arc::clone(&physical_device),
             DeviceCreateInfo {
-                queue_create_infos: queue_idxs
+                queue_create_infos: unique_families
                     .iter()
-                    .map(|_| QueueCreateInfo {
-                        queue_family_index: transfer_queue_family_i,
+                    .map(|&queue_family_index| QueueCreateInfo {
+                        queue_family_index,
                         ..Default::default()
                     })
                     .collect(),
                 enabled_extensions: device_extensions,
+                enabled_features,
                 ..Default::default()
             },
         )
         .expect("failed to create device");
 
+        // One queue is created per unique family, in the order of `unique_families`
         let queues: Vec<_> = queues.collect();
-        let (transfer_queue, compute_queue, graphics_queue) = (
-            queues[queue_family_idxs[0] as usize].clone(),
-            queues[queue_family_idxs[1] as usize].clone(),
-            queues[queue_family_idxs[2] as usize].clone(),
+        let queue_for = |family: u32| {
+            Arc::clone(&queues[unique_families.iter().position(|&f| f == family).unwrap()])
+        };
+        let (transfer_queue, compute_queue, present_queue) = (
+            queue_for(families.transfer),
+            queue_for(families.compute),
+            queue_for(families.present),
         );
 
         let memory_allocator = Arc::new(StandardMemoryAllocator::new_default(Arc::clone(&device)));
 
-        (
-            Context {
-                instance,
-                surface,
-                physical_device,
-                device,
-                transfer_queue,
-                compute_queue,
-                graphics_queue,
-                memory_allocator,
-            },
-            window,
-        )
+        let present_mode = choose_present_mode(
+            config.vsync,
+            &surface
+                .as_ref()
+                .and_then(|surface| {
+                    physical_device
+                        .surface_present_modes(surface, Default::default())
+                        .ok()
+                })
+                .map(|modes| modes.collect::<Vec<_>>())
+                .unwrap_or_default(),
+        );
+
+        Context {
+            instance,
+            surface,
+            physical_device,
+            device,
+            transfer_queue,
+            compute_queue,
+            present_queue,
+            memory_allocator,
+            present_mode,
+        }
+    }
+
+    /// Whether the shader can write frames to storage images declared without a format, which `OutputFormat`
+    /// relies on to draw to BGRA as well as RGBA swapchain images.
+    pub fn supports_write_without_format(&self) -> bool {
+        self.device
+            .enabled_features()
+            .shader_storage_image_write_without_format
+    }
+
+    /// Whether the device only implements the Vulkan portability subset (e.g. MoltenVK on macOS), in
+    /// which case some optional features are unavailable.
+    pub fn is_portability_subset(&self) -> bool {
+        self.physical_device
+            .supported_extensions()
+            .khr_portability_subset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_queue_family_fallback() {
+        let all = QueueFlags::GRAPHICS | QueueFlags::COMPUTE | QueueFlags::TRANSFER;
+        let compute = QueueFlags::COMPUTE | QueueFlags::TRANSFER;
+
+        // A compute family that can present is used for both
+        let families = QueueFamilies::select(&[compute, all], |i| i == 1);
+        assert_eq!(
+            families,
+            QueueFamilies {
+                transfer: 0,
+                compute: 1,
+                present: 1
+            }
+        );
+        assert_eq!(families.unique(), vec![0, 1]);
+
+        // Otherwise presenting falls back to a different family
+        let families = QueueFamilies::select(&[compute, QueueFlags::GRAPHICS], |i| i == 1);
+        assert_eq!(families.compute, 0);
+        assert_eq!(families.present, 1);
+        assert!(families.separate_present());
+        assert_eq!(families.unique(), vec![0, 1]);
+    }
+
+    #[test]
+    fn test_choose_present_mode() {
+        let all = [
+            PresentMode::Fifo,
+            PresentMode::Immediate,
+            PresentMode::Mailbox,
+        ];
+        assert_eq!(choose_present_mode(true, &all), PresentMode::Fifo);
+        assert_eq!(choose_present_mode(false, &all), PresentMode::Mailbox);
+        assert_eq!(
+            choose_present_mode(false, &all[..2]),
+            PresentMode::Immediate
+        );
+        assert_eq!(choose_present_mode(false, &[]), PresentMode::Fifo);
     }
 }
@@ -0,0 +1,70 @@
+use crate::renderer::context::choose_present_mode;
+use vulkano::swapchain::PresentMode;
+
+/// How `Renderer` trades input latency for GPU throughput, set with `Renderer::set_latency_mode`. Each mode sets the
+/// number of frames in flight, what happens to staging buffer updates while a transfer is in flight and the present
+/// mode together, since they only make sense in combination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LatencyMode {
+    /// One frame in flight, staging buffer updates wait for the last transfer so they make it into the next frame,
+    /// and mailbox (or immediate) present. Input shows up on screen as soon as possible, but the GPU idles between
+    /// frames.
+    LowLatency,
+    /// As many frames in flight as there are swapchain images, staging buffer updates are skipped while a transfer
+    /// is in flight (see `Renderer::try_start_updating_staging_buffers`) and FIFO present. Keeps the GPU busy, but
+    /// frames show input a few frames later.
+    Throughput,
+}
+
+impl LatencyMode {
+    /// Most frames submitted but not yet presented, or `None` for as many as there are swapchain images
+    pub fn max_frames_in_flight(&self) -> Option<usize> {
+        match self {
+            LatencyMode::LowLatency => Some(1),
+            LatencyMode::Throughput => None,
+        }
+    }
+
+    /// Whether `Renderer::try_start_updating_staging_buffers` skips the update instead of waiting while a
+    /// transfer is in flight
+    pub fn skips_blocked_staging_updates(&self) -> bool {
+        match self {
+            LatencyMode::LowLatency => false,
+            LatencyMode::Throughput => true,
+        }
+    }
+
+    /// Present mode to use given the modes the surface supports
+    pub fn present_mode(&self, supported: &[PresentMode]) -> PresentMode {
+        match self {
+            LatencyMode::LowLatency => choose_present_mode(false, supported),
+            LatencyMode::Throughput => PresentMode::Fifo,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_mode_present_mode() {
+        let all = [
+            PresentMode::Fifo,
+            PresentMode::Immediate,
+            PresentMode::Mailbox,
+        ];
+        assert_eq!(
+            LatencyMode::LowLatency.present_mode(&all),
+            PresentMode::Mailbox
+        );
+        assert_eq!(
+            LatencyMode::LowLatency.present_mode(&[PresentMode::Fifo]),
+            PresentMode::Fifo
+        );
+        assert_eq!(
+            LatencyMode::Throughput.present_mode(&all),
+            PresentMode::Fifo
+        );
+    }
+}
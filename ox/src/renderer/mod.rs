@@ -1,22 +1,48 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::marker::PhantomData;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use vulkano::command_buffer::allocator::CommandBufferAllocator;
 use vulkano::descriptor_set::allocator::DescriptorSetAllocator;
-use winit::dpi::PhysicalSize;
+use winit::dpi::{LogicalSize, PhysicalSize};
 use winit::window::Window;
 
+pub mod binding;
 pub mod buffers;
 pub mod component;
 pub mod context;
+mod latency;
+pub mod offscreen;
+pub mod output_format;
 mod pipeline;
+pub mod shader_defs;
 pub mod swapchain;
+mod target;
 pub mod test_context;
 mod transfer;
 pub mod utils;
+pub mod viewport;
 
+use crate::metrics::{names, MetricsSink};
+use crate::renderer::component::debug_draw::{DataComponentSetWithDebugDraw, DebugDraw};
+use crate::renderer::component::highlight::{DataComponentSetWithHighlight, HighlightedVoxel};
+use crate::renderer::component::luminance::{
+    ColorConfig, DataComponentSetWithLuminance, LuminanceHistogram,
+};
+use crate::renderer::component::ubo::{
+    DataComponentSetWithUbo, FogParams, PhotoSettings, SkyParams,
+};
 use crate::renderer::component::DataComponentSet;
+pub use crate::renderer::latency::LatencyMode;
+use crate::renderer::offscreen::OffscreenPipeline;
+use crate::renderer::output_format::OutputFormat;
 use crate::renderer::swapchain::SwapchainPipelineParams;
+use crate::renderer::target::RenderTarget;
 use crate::renderer::transfer::TransferManager;
+pub use crate::renderer::transfer::UploadBudget;
+use crate::renderer::viewport::Viewport;
+use crate::util::OwnerThread;
 use context::Context;
 use swapchain::SwapchainPipeline;
 
@@ -28,12 +54,77 @@ pub struct Renderer<
 > {
     component_set: D,
     context: Context,
-    swapchain_pipeline: SwapchainPipeline<DSA, CBA>,
+    target: RenderTarget<DSA, CBA>,
     transfer_manager: TransferManager<DCBA>,
+    frame_stats: FrameStats,
+    transfer_enabled: bool,
+    upload_budget: UploadBudget,
+    // Frames the pending background uploads have waited since the last background transfer
+    background_deferred_frames: u32,
+    /// Whether something changed since the last frame that `draw_frame_if_changed` can't detect itself
+    needs_redraw: bool,
+    // Set by `suspend`, see `is_suspended`
+    suspended: bool,
+    // Set when the window is zero sized or the surface can't be presented to
+    surface_unavailable: bool,
+    // Window size from a resize while suspended, applied on `resume`
+    pending_resize: Option<PhysicalSize<u32>>,
+    // Scale factor of the window (1 for offscreen renderers), see `scale_factor`
+    scale_factor: f64,
+    // Set by `set_latency_mode`
+    latency_mode: Option<LatencyMode>,
+    owner_thread: OwnerThread,
 }
 
+/// Stage of drawing a frame that can be turned off with `Renderer::set_pass_enabled`, e.g. to find which
+/// stage is responsible for a frame time regression without rebuilding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PassId {
+    /// All copies from staging buffers to device local buffers. While disabled, the GPU keeps rendering
+    /// the data from the last transfer.
+    Transfer,
+    /// Voxel data uploads for one LOD (by index in `VoxelData`). Changes made while disabled are uploaded
+    /// once it is enabled again.
+    LodUpload(usize),
+}
+
+/// GPU work that was still running when `Renderer::shutdown` timed out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShutdownTimeout {
+    /// Copies from staging buffers to device local buffers
+    pub transfer: bool,
+    /// Frames being drawn, presented or read back
+    pub render: bool,
+}
+
+impl Display for ShutdownTimeout {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let work = match (self.transfer, self.render) {
+            (true, true) => "Transfers and frames",
+            (true, false) => "Transfers",
+            _ => "Frames",
+        };
+        write!(f, "{work} were still running on the GPU when the renderer shut down")
+    }
+}
+
+impl Error for ShutdownTimeout {}
+
+/// Stats about the last drawn frame, used for reporting metrics
+#[derive(Debug, Default)]
+struct FrameStats {
+    last_frame_start: Option<Instant>,
+    last_frame_time: Option<Duration>,
+    last_transfer_bytes: u64,
+}
+
+/// Access to the renderer's components for updating their staging buffers. Creating one waits for the last
+/// transfer to finish, so staging buffers can be written without racing the GPU's reads of them; writing a
+/// staging buffer any other way while a transfer is in flight panics.
 pub struct RendererComponentEditor<'a, D> {
     pub component_set: &'a mut D,
+    /// Makes the editor `!Send` so it can't be moved off the thread that owns the renderer
+    _not_send: PhantomData<*const ()>,
 }
 
 impl<
@@ -47,20 +138,66 @@ impl<
         context: Context,
         swapchain_pipeline_params: SwapchainPipelineParams<DSA, CBA>,
         window: &Window,
-        mut component_set: D,
+        component_set: D,
         dynamic_command_buffer_allocator: DCBA,
     ) -> Self {
         let swapchain_pipeline = SwapchainPipeline::new(
             Arc::clone(&context.device),
             Arc::clone(&context.compute_queue),
-            Arc::clone(&context.graphics_queue),
+            Arc::clone(&context.present_queue),
             window.inner_size(),
             &component_set,
             Arc::clone(&context.physical_device),
-            Arc::clone(&context.surface),
+            Arc::clone(context.surface.as_ref().expect(
+                "Renderer::new needs a context with a surface, use Renderer::new_offscreen for a headless context",
+            )),
+            context.present_mode,
             swapchain_pipeline_params,
         );
+        let mut renderer = Self::with_target(
+            context,
+            RenderTarget::Swapchain(swapchain_pipeline),
+            component_set,
+            dynamic_command_buffer_allocator,
+        );
+        renderer.scale_factor = window.scale_factor();
+        renderer
+    }
 
+    /// Create a renderer that draws frames of size `extent` into an image instead of a window, e.g. for visual
+    /// regression tests and batch renders. Use it like a windowed renderer and read each frame with
+    /// `read_frame`. `context` can be headless (see `Context::new_headless`). The shader writes frames in
+    /// `offscreen::OFFSCREEN_FORMAT`.
+    pub fn new_offscreen(
+        context: Context,
+        pipeline_params: SwapchainPipelineParams<DSA, CBA>,
+        extent: PhysicalSize<u32>,
+        component_set: D,
+        dynamic_command_buffer_allocator: DCBA,
+    ) -> Self {
+        let offscreen_pipeline = OffscreenPipeline::new(
+            Arc::clone(&context.device),
+            Arc::clone(&context.compute_queue),
+            Arc::clone(&context.memory_allocator),
+            extent,
+            &component_set,
+            &context.physical_device,
+            pipeline_params,
+        );
+        Self::with_target(
+            context,
+            RenderTarget::Offscreen(offscreen_pipeline),
+            component_set,
+            dynamic_command_buffer_allocator,
+        )
+    }
+
+    fn with_target(
+        context: Context,
+        target: RenderTarget<DSA, CBA>,
+        mut component_set: D,
+        dynamic_command_buffer_allocator: DCBA,
+    ) -> Self {
         let transfer_manager = TransferManager::new(
             &context,
             &mut component_set,
@@ -70,39 +207,554 @@ impl<
         Renderer {
             component_set,
             context,
-            swapchain_pipeline,
+            target,
             transfer_manager,
+            frame_stats: FrameStats::default(),
+            transfer_enabled: true,
+            upload_budget: UploadBudget::default(),
+            background_deferred_frames: 0,
+            needs_redraw: true,
+            suspended: false,
+            surface_unavailable: false,
+            pending_resize: None,
+            scale_factor: 1.,
+            latency_mode: None,
+            owner_thread: OwnerThread::current(),
         }
     }
 
+    pub fn owner_thread(&self) -> OwnerThread {
+        self.owner_thread
+    }
+
+    /// Resize the swapchain to the window's new size. A zero sized window (e.g. minimized) pauses drawing until
+    /// the window is resized again. While suspended, the resize is applied on `resume`.
     pub fn window_resized(&mut self, new_dimensions: PhysicalSize<u32>) {
-        self.swapchain_pipeline
-            .resize(&new_dimensions, &self.component_set);
+        self.owner_thread
+            .debug_assert_current("Renderer::window_resized");
+        if self.suspended {
+            self.pending_resize = Some(new_dimensions);
+            return;
+        }
+        self.surface_unavailable = self
+            .target
+            .resize(&new_dimensions, &self.component_set)
+            .is_err();
+        self.needs_redraw = true;
+    }
+
+    /// Call on `WindowEvent::ScaleFactorChanged`, e.g. when the window is moved to a display with a different
+    /// scale. Frames are always drawn at the window's physical size, so this resizes the swapchain to
+    /// `new_inner_size` (physical pixels) like `window_resized` and remembers `scale_factor` for UI layout.
+    pub fn scale_factor_changed(&mut self, scale_factor: f64, new_inner_size: PhysicalSize<u32>) {
+        self.scale_factor = scale_factor;
+        self.window_resized(new_inner_size);
+    }
+
+    /// Scale factor of the window (e.g. 1.5 on a display set to 150%), or 1 for offscreen renderers. Sizes of
+    /// UI and overlay elements given in logical pixels should be multiplied by this, see also `set_ui_scale`.
+    pub fn scale_factor(&self) -> f64 {
+        self.scale_factor
     }
 
     pub fn recreate_swapchain(&mut self) {
-        self.swapchain_pipeline.recreate();
+        self.owner_thread
+            .debug_assert_current("Renderer::recreate_swapchain");
+        self.surface_unavailable = self.target.recreate().is_err();
+        self.needs_redraw = true;
+    }
+
+    /// Stop drawing frames, e.g. on `winit::event::Event::Suspended` or when the game is paused in the
+    /// background. Waits for in-flight GPU work so nothing uses the surface while suspended. `draw_frame` does
+    /// nothing until `resume` is called.
+    pub fn suspend(&mut self) {
+        self.owner_thread.debug_assert_current("Renderer::suspend");
+        if self.suspended {
+            return;
+        }
+        self.suspended = true;
+        let timeout = Some(Duration::from_secs(3));
+        let transfer = self.transfer_manager.wait_idle(timeout);
+        let target = self.target.wait_idle(timeout);
+        if transfer.and(target).is_err() {
+            println!("WARNING: GPU work did not finish before the renderer was suspended");
+        }
+    }
+
+    /// Start drawing frames again after `suspend`, e.g. on `winit::event::Event::Resumed`. The swapchain is
+    /// recreated (with the window size from any resize while suspended) since the surface may have changed.
+    /// Does nothing if not suspended.
+    pub fn resume(&mut self) {
+        self.owner_thread.debug_assert_current("Renderer::resume");
+        if !self.suspended {
+            return;
+        }
+        self.suspended = false;
+        let dimensions = self
+            .pending_resize
+            .take()
+            .unwrap_or_else(|| self.target.dimensions());
+        self.window_resized(dimensions);
+    }
+
+    /// Whether `suspend` was called without `resume`
+    pub fn is_suspended(&self) -> bool {
+        self.suspended
+    }
+
+    /// Whether frames are currently not drawn, either because the renderer is suspended or because there is
+    /// nothing to present to (a zero sized window or a lost surface). Frames are drawn again once the window is
+    /// resized or the renderer is resumed.
+    pub fn is_paused(&self) -> bool {
+        self.suspended || self.surface_unavailable
     }
 
     pub fn start_updating_staging_buffers(&mut self) -> RendererComponentEditor<D> {
+        self.owner_thread
+            .debug_assert_current("Renderer::start_updating_staging_buffers");
         self.transfer_manager
             .wait_for_staging_buffers(Some(Duration::from_secs(3)));
         RendererComponentEditor {
             component_set: &mut self.component_set,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Like `start_updating_staging_buffers`, but in a `LatencyMode` that skips blocked staging updates (see
+    /// `LatencyMode::skips_blocked_staging_updates`), returns `None` instead of waiting if the last transfer is
+    /// still in flight. The caller should then keep its changes and try again next frame.
+    pub fn try_start_updating_staging_buffers(&mut self) -> Option<RendererComponentEditor<D>> {
+        let skip = self
+            .latency_mode
+            .is_some_and(|mode| mode.skips_blocked_staging_updates());
+        if skip && !self.transfer_manager.is_idle() {
+            return None;
+        }
+        Some(self.start_updating_staging_buffers())
+    }
+
+    /// Set the frames in flight, whether blocked staging updates are skipped and the present mode together, see
+    /// `LatencyMode`. Until this is called, the present mode is the context's (see `Context::present_mode`) and
+    /// frames in flight are only limited by the number of swapchain images.
+    pub fn set_latency_mode(&mut self, mode: LatencyMode) {
+        self.owner_thread
+            .debug_assert_current("Renderer::set_latency_mode");
+        self.latency_mode = Some(mode);
+        let supported = self
+            .context
+            .surface
+            .as_ref()
+            .and_then(|surface| {
+                self.context
+                    .physical_device
+                    .surface_present_modes(surface, Default::default())
+                    .ok()
+            })
+            .map(|modes| modes.collect::<Vec<_>>())
+            .unwrap_or_default();
+        if self
+            .target
+            .set_frame_pacing(
+                mode.max_frames_in_flight(),
+                mode.present_mode(&supported),
+                &self.component_set,
+            )
+            .is_err()
+        {
+            self.surface_unavailable = true;
+        }
+        self.needs_redraw = true;
+    }
+
+    pub fn latency_mode(&self) -> Option<LatencyMode> {
+        self.latency_mode
+    }
+
+    /// Bind all of the components' current buffers. Needed after buffers are replaced, e.g. when a buffer scheme
+    /// reallocates a buffer to grow it; resizing the window also does this. Prefer
+    /// `rebind_dirty_components` when the components report which bindings changed.
+    pub fn rebind_components(&mut self) {
+        self.owner_thread
+            .debug_assert_current("Renderer::rebind_components");
+        // Everything is rebound, so clear the dirty bindings
+        self.component_set.take_dirty_bindings(&mut vec![]);
+        self.target.rebind(&self.component_set);
+        self.needs_redraw = true;
+    }
+
+    /// Rebind only the bindings whose buffers were replaced (see `DataComponentSet::take_dirty_bindings`),
+    /// keeping the rest of the descriptor writes and the compute pipeline. Does nothing if no binding
+    /// changed. Returns whether anything was rebound.
+    pub fn rebind_dirty_components(&mut self) -> bool {
+        self.owner_thread
+            .debug_assert_current("Renderer::rebind_dirty_components");
+        let mut dirty = vec![];
+        self.component_set.take_dirty_bindings(&mut dirty);
+        if dirty.is_empty() {
+            return false;
+        }
+        self.target.rebind_bindings(&self.component_set, &dirty);
+        self.needs_redraw = true;
+        true
+    }
+
+    /// Draw `viewports` (e.g. a rear-view mirror or minimap) from their own cameras before the main image each
+    /// frame, replacing any previous ones. See `Viewport` for how they are bound.
+    pub fn set_viewports(&mut self, viewports: Vec<Viewport>) {
+        self.owner_thread
+            .debug_assert_current("Renderer::set_viewports");
+        self.target.set_viewports(viewports, &self.component_set);
+        self.needs_redraw = true;
+    }
+
+    /// Enable or disable a stage of drawing frames. All stages are enabled by default.
+    pub fn set_pass_enabled(&mut self, pass: PassId, enabled: bool) {
+        self.owner_thread
+            .debug_assert_current("Renderer::set_pass_enabled");
+        if pass == PassId::Transfer {
+            self.transfer_enabled = enabled;
+        }
+        self.component_set.set_pass_enabled(pass, enabled);
+        self.needs_redraw = true;
+    }
+
+    /// Limit how much is uploaded per frame, see `UploadBudget`
+    pub fn set_upload_budget(&mut self, budget: UploadBudget) {
+        self.upload_budget = budget;
+    }
+
+    /// Pass this to `VoxelMemoryGrid::get_updates` so chunk updates are held back when they don't fit
+    pub fn upload_budget(&self) -> &UploadBudget {
+        &self.upload_budget
+    }
+
+    /// Make the next `draw_frame_if_changed` draw a frame. Call this when the camera moved or voxel data or
+    /// other components were updated.
+    pub fn request_redraw(&mut self) {
+        self.needs_redraw = true;
+    }
+
+    /// Like `draw_frame`, but if nothing changed since the last frame (see `request_redraw`), skip
+    /// recording and dispatching the frame entirely and leave the previous image on screen. This saves
+    /// almost all GPU and CPU work while the scene is static, e.g. when the player is idle. Note that
+    /// the previous image is kept as is, including its noise. Returns whether a frame was drawn.
+    pub fn draw_frame_if_changed(&mut self) -> bool {
+        if self.needs_redraw {
+            self.draw_frame();
+            true
+        } else {
+            // Don't report the time spent idle as a frame time
+            self.frame_stats.last_frame_start = None;
+            self.frame_stats.last_frame_time = None;
+            self.frame_stats.last_transfer_bytes = 0;
+            false
         }
     }
 
+    /// Does nothing while paused (see `is_paused`).
     pub fn draw_frame(&mut self) {
-        self.swapchain_pipeline
+        self.owner_thread
+            .debug_assert_current("Renderer::draw_frame");
+        if self.is_paused() {
+            // Draw once unpaused, and don't report the time spent paused as a frame time
+            self.needs_redraw = true;
+            self.frame_stats.last_frame_start = None;
+            self.frame_stats.last_frame_time = None;
+            self.frame_stats.last_transfer_bytes = 0;
+            return;
+        }
+        self.needs_redraw = false;
+        let frame_start = Instant::now();
+        self.frame_stats.last_frame_time = self
+            .frame_stats
+            .last_frame_start
+            .map(|last| frame_start - last);
+        self.frame_stats.last_frame_start = Some(frame_start);
+
+        self.target
             .wait_for_compute_done(Some(Duration::from_secs(3)));
+        self.component_set.late_latch();
 
-        let transfer_fence = self.transfer_manager.start_transfer(
-            Arc::clone(&self.context.device),
-            Arc::clone(&self.context.transfer_queue),
-            &mut self.component_set,
-        );
+        // Skip the transfer when no component has anything to copy (e.g. the camera didn't move and no chunks
+        // were edited), waiting on the last one instead
+        let pending_transfer_bytes = self.component_set.pending_transfer_bytes();
+        let pending_background_bytes = self.component_set.pending_background_transfer_bytes();
+        self.frame_stats.last_transfer_bytes = 0;
+        if self.transfer_enabled && pending_transfer_bytes > 0 {
+            self.frame_stats.last_transfer_bytes += pending_transfer_bytes;
+            self.transfer_manager.start_transfer(
+                Arc::clone(&self.context.device),
+                Arc::clone(&self.context.transfer_queue),
+                &mut self.component_set,
+            );
+        }
+        if self.transfer_enabled && pending_background_bytes > 0 {
+            if self.upload_budget.should_submit_background(
+                pending_transfer_bytes,
+                pending_background_bytes,
+                self.background_deferred_frames,
+            ) {
+                self.frame_stats.last_transfer_bytes += pending_background_bytes;
+                self.background_deferred_frames = 0;
+                self.transfer_manager.start_background_transfer(
+                    Arc::clone(&self.context.device),
+                    Arc::clone(&self.context.transfer_queue),
+                    &mut self.component_set,
+                );
+            } else {
+                self.background_deferred_frames += 1;
+                // Draw another frame to submit them even if nothing else changes
+                self.needs_redraw = true;
+            }
+        }
+        let transfer_fence = self.transfer_manager.last_transfer();
 
-        self.swapchain_pipeline
-            .present(Arc::clone(&self.context.device), transfer_fence);
+        if self
+            .target
+            .present(Arc::clone(&self.context.device), transfer_fence)
+            .is_err()
+        {
+            self.surface_unavailable = true;
+            self.needs_redraw = true;
+        }
+    }
+
+    /// Wait for the last frame drawn by an offscreen renderer (see `new_offscreen`) and return it as 8-bit RGBA,
+    /// row major starting at the top left, with the size of `frame_size`. Wrap it in a `timelapse::Frame` to
+    /// compare it with `reference::compare_frames` or save it. Panics if the renderer draws to a window.
+    pub fn read_frame(&self) -> Vec<u8> {
+        self.owner_thread
+            .debug_assert_current("Renderer::read_frame");
+        match &self.target {
+            RenderTarget::Offscreen(offscreen) => {
+                offscreen.read_frame(Some(Duration::from_secs(3)))
+            }
+            RenderTarget::Swapchain(_) => {
+                panic!("Renderer::read_frame needs an offscreen renderer (see Renderer::new_offscreen)")
+            }
+        }
+    }
+
+    /// Size of the frames being drawn, i.e. of the window or the offscreen image, in physical pixels. This is
+    /// the resolution to give the camera (see `World::set_camera_res`).
+    pub fn frame_size(&self) -> PhysicalSize<u32> {
+        self.target.dimensions()
+    }
+
+    /// Format and color space of the frames being drawn, negotiated with the surface when the renderer was created
+    /// (see `OutputFormat::negotiate`)
+    pub fn output_format(&self) -> OutputFormat {
+        self.target.output_format()
+    }
+
+    /// `frame_size` in logical pixels, for laying out UI
+    pub fn logical_frame_size(&self) -> LogicalSize<f64> {
+        self.frame_size().to_logical(self.scale_factor)
+    }
+
+    /// Wait up to `timeout` for all in-flight GPU work (transfers, compute and present) to finish so
+    /// that nothing is still using the renderer's resources when it is dropped. Returns `Err` with the work
+    /// that didn't finish in time. This is also done (with a short timeout) when the renderer is dropped.
+    pub fn shutdown(&mut self, timeout: Duration) -> Result<(), ShutdownTimeout> {
+        self.owner_thread.debug_assert_current("Renderer::shutdown");
+        self.wait_idle(timeout)
+    }
+
+    fn wait_idle(&self, timeout: Duration) -> Result<(), ShutdownTimeout> {
+        let transfer = self.transfer_manager.wait_idle(Some(timeout)).is_err();
+        let render = self.target.wait_idle(Some(timeout)).is_err();
+        if transfer || render {
+            Err(ShutdownTimeout { transfer, render })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Report the time since the previous frame and the bytes transferred for the last frame to
+    /// `sink`. Call this once after each `draw_frame`.
+    pub fn report_metrics(&self, sink: &mut impl MetricsSink) {
+        if let Some(frame_time) = self.frame_stats.last_frame_time {
+            sink.observe_histogram(names::FRAME_TIME, frame_time.as_secs_f64());
+        }
+        sink.increment_counter(names::TRANSFER_BYTES, self.frame_stats.last_transfer_bytes);
+    }
+}
+
+impl<
+        D: DataComponentSetWithHighlight,
+        DSA: DescriptorSetAllocator,
+        CBA: CommandBufferAllocator + 'static,
+        DCBA: CommandBufferAllocator + 'static,
+    > Renderer<D, DSA, CBA, DCBA>
+{
+    /// Draw an outline around `voxel` (typically the target of `cast_ray`), or remove the outline if
+    /// `None`. The outline is drawn wherever the voxel's box is visible, without checking whether other
+    /// voxels are in front of it.
+    pub fn set_highlighted_voxel(&mut self, voxel: Option<HighlightedVoxel>) {
+        self.owner_thread
+            .debug_assert_current("Renderer::set_highlighted_voxel");
+        self.transfer_manager
+            .wait_for_staging_buffers(Some(Duration::from_secs(3)));
+        if self.component_set.highlight_mut().set(voxel) {
+            self.needs_redraw = true;
+        }
+    }
+}
+
+impl<
+        D: DataComponentSetWithDebugDraw,
+        DSA: DescriptorSetAllocator,
+        CBA: CommandBufferAllocator + 'static,
+        DCBA: CommandBufferAllocator + 'static,
+    > Renderer<D, DSA, CBA, DCBA>
+{
+    /// Draw `draw`'s shapes from the next frame on, replacing the ones submitted before, and clear `draw` for
+    /// the next frame. Call this once per frame before `draw_frame`, also when nothing was drawn, so the
+    /// previous frame's shapes go away.
+    pub fn submit_debug_draw(&mut self, draw: &mut DebugDraw) {
+        self.owner_thread
+            .debug_assert_current("Renderer::submit_debug_draw");
+        self.transfer_manager
+            .wait_for_staging_buffers(Some(Duration::from_secs(3)));
+        if self.component_set.debug_draw_mut().set(draw) {
+            self.needs_redraw = true;
+        }
+        draw.clear();
+    }
+}
+
+impl<
+        D: DataComponentSetWithUbo,
+        DSA: DescriptorSetAllocator,
+        CBA: CommandBufferAllocator + 'static,
+        DCBA: CommandBufferAllocator + 'static,
+    > Renderer<D, DSA, CBA, DCBA>
+{
+    /// Set the distance fog, e.g. to `FogParams::for_render_area` to hide the edge of the loaded chunks.
+    pub fn set_fog(&mut self, fog: FogParams) {
+        self.owner_thread.debug_assert_current("Renderer::set_fog");
+        self.transfer_manager
+            .wait_for_staging_buffers(Some(Duration::from_secs(3)));
+        let ubo = &mut self.component_set.ubo_mut().buffer_scheme;
+        if ubo.read_staging().fog() != fog {
+            ubo.write_staging().set_fog(fog);
+            self.needs_redraw = true;
+        }
+    }
+
+    /// Set the sun and sky light, see `SkyParams`
+    pub fn set_sky(&mut self, sky: SkyParams) {
+        self.owner_thread.debug_assert_current("Renderer::set_sky");
+        self.transfer_manager
+            .wait_for_staging_buffers(Some(Duration::from_secs(3)));
+        let ubo = &mut self.component_set.ubo_mut().buffer_scheme;
+        if ubo.read_staging().sky() != sky {
+            ubo.write_staging().set_sky(sky);
+            self.needs_redraw = true;
+        }
+    }
+
+    pub fn sky(&mut self) -> SkyParams {
+        self.component_set
+            .ubo_mut()
+            .buffer_scheme
+            .read_staging()
+            .sky()
+    }
+
+    /// Move the sun to where it is at `hours` into the day, keeping the rest of the sky as is. See
+    /// `SkyParams::at_time_of_day`. Call this every frame with the game's clock to animate the sun.
+    pub fn set_time_of_day(&mut self, hours: f32) {
+        let sky = self.sky().at_time_of_day(hours);
+        self.set_sky(sky);
+    }
+
+    /// Hide the crosshair and highlight outline and/or change the number of rays per pixel, see
+    /// `photo::PhotoMode`.
+    pub fn set_photo_settings(&mut self, settings: PhotoSettings) {
+        self.owner_thread
+            .debug_assert_current("Renderer::set_photo_settings");
+        self.transfer_manager
+            .wait_for_staging_buffers(Some(Duration::from_secs(3)));
+        let ubo = &mut self.component_set.ubo_mut().buffer_scheme;
+        if ubo.read_staging().photo_settings() != settings {
+            ubo.write_staging().set_photo_settings(settings);
+            self.needs_redraw = true;
+        }
+    }
+
+    /// Scale the crosshair, usually by `scale_factor` so it looks the same size on high-DPI displays
+    pub fn set_ui_scale(&mut self, ui_scale: f32) {
+        self.owner_thread.debug_assert_current("Renderer::set_ui_scale");
+        self.transfer_manager
+            .wait_for_staging_buffers(Some(Duration::from_secs(3)));
+        let ubo = &mut self.component_set.ubo_mut().buffer_scheme;
+        if ubo.read_staging().ui_scale != ui_scale {
+            ubo.write_staging().ui_scale = ui_scale;
+            self.needs_redraw = true;
+        }
+    }
+
+    pub fn photo_settings(&mut self) -> PhotoSettings {
+        self.component_set
+            .ubo_mut()
+            .buffer_scheme
+            .read_staging()
+            .photo_settings()
+    }
+}
+
+impl<
+        D: DataComponentSetWithLuminance,
+        DSA: DescriptorSetAllocator,
+        CBA: CommandBufferAllocator + 'static,
+        DCBA: CommandBufferAllocator + 'static,
+    > Renderer<D, DSA, CBA, DCBA>
+{
+    /// Set a fixed exposure or enable auto-exposure. Takes effect on the next frame.
+    pub fn set_color_config(&mut self, config: ColorConfig) {
+        self.owner_thread
+            .debug_assert_current("Renderer::set_color_config");
+        let luminance = self.component_set.luminance_mut();
+        if luminance.config() != config {
+            luminance.set_config(config);
+            self.needs_redraw = true;
+        }
+    }
+
+    pub fn color_config(&self) -> ColorConfig {
+        self.component_set.luminance().config()
+    }
+
+    /// Exposure of the last frame drawn
+    pub fn exposure(&self) -> f32 {
+        self.component_set.luminance().exposure()
+    }
+
+    /// Luminance histogram of the last frame that finished, e.g. for a custom exposure or tonemapping curve
+    pub fn luminance_histogram(&self) -> &LuminanceHistogram {
+        self.component_set.luminance().histogram()
+    }
+
+    /// Request a redraw if auto-exposure is still adapting, so it keeps adapting when frames are only drawn
+    /// with `draw_frame_if_changed`. Call this each frame before drawing.
+    pub fn request_redraw_if_adapting(&mut self) {
+        if self.component_set.luminance().is_adapting() {
+            self.needs_redraw = true;
+        }
+    }
+}
+
+impl<
+        D: DataComponentSet,
+        DSA: DescriptorSetAllocator + 'static,
+        CBA: CommandBufferAllocator + 'static,
+        DCBA: CommandBufferAllocator + 'static,
+    > Drop for Renderer<D, DSA, CBA, DCBA>
+{
+    fn drop(&mut self) {
+        if let Err(e) = self.wait_idle(Duration::from_secs(3)) {
+            println!("WARNING: {} (when dropped)", e);
+        }
     }
 }
@@ -0,0 +1,291 @@
+//! Render target for drawing frames without a window, e.g. for visual regression tests and batch renders. See
+//! `Renderer::new_offscreen`.
+
+use crate::renderer::component::DataComponentSet;
+use crate::renderer::pipeline::{device_workgroup_size, ComputeRenderPipeline};
+use crate::renderer::swapchain::{GpuFence, SwapchainPipelineParams};
+use crate::renderer::viewport::Viewport;
+use std::sync::Arc;
+use std::time::Duration;
+use vulkano::buffer::{Buffer, BufferCreateInfo, BufferUsage, Subbuffer};
+use vulkano::command_buffer::allocator::CommandBufferAllocator;
+use vulkano::command_buffer::{
+    AutoCommandBufferBuilder, CommandBufferUsage, CopyImageToBufferInfo, PrimaryAutoCommandBuffer,
+};
+use vulkano::descriptor_set::allocator::DescriptorSetAllocator;
+use vulkano::device::physical::PhysicalDevice;
+use vulkano::device::{Device, Queue};
+use vulkano::format::Format;
+use vulkano::image::{Image, ImageCreateInfo, ImageType, ImageUsage};
+use vulkano::memory::allocator::{
+    AllocationCreateInfo, MemoryAllocatePreference, MemoryAllocator, MemoryTypeFilter,
+};
+use vulkano::memory::MemoryPropertyFlags;
+use vulkano::sync::{self, GpuFuture};
+use winit::dpi::PhysicalSize;
+
+/// Format of offscreen frames, so frames from `Renderer::read_frame` are 8-bit RGBA
+pub const OFFSCREEN_FORMAT: Format = Format::R8G8B8A8_UNORM;
+
+/// Renders frames into an image instead of a swapchain and copies each frame to a host visible buffer so it
+/// can be read back
+pub struct OffscreenPipeline<
+    DSA: DescriptorSetAllocator + 'static,
+    CBA: CommandBufferAllocator + 'static,
+> {
+    params: SwapchainPipelineParams<DSA, CBA>,
+    queue: Arc<Queue>,
+    memory_allocator: Arc<dyn MemoryAllocator>,
+    image: Arc<Image>,
+    // Copy of `image` made after each frame
+    readback: Subbuffer<[u8]>,
+    copy_command_buffer: Arc<PrimaryAutoCommandBuffer<CBA>>,
+    pipeline: ComputeRenderPipeline<CBA>,
+    dimensions: PhysicalSize<u32>,
+    fence: Option<Arc<GpuFence>>,
+}
+
+impl<DSA: DescriptorSetAllocator + 'static, CBA: CommandBufferAllocator + 'static>
+    OffscreenPipeline<DSA, CBA>
+{
+    pub fn new(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        memory_allocator: Arc<dyn MemoryAllocator>,
+        dimensions: PhysicalSize<u32>,
+        component_set: &impl DataComponentSet,
+        physical_device: &PhysicalDevice,
+        params: SwapchainPipelineParams<DSA, CBA>,
+    ) -> Self {
+        assert!(
+            dimensions.width > 0 && dimensions.height > 0,
+            "Offscreen frames can't be zero sized"
+        );
+        let (image, readback) = create_target(&memory_allocator, dimensions);
+        let copy_command_buffer =
+            record_copy(&params.command_buffer_allocator, &queue, &image, &readback);
+
+        let [workgroup_width, workgroup_height] = params
+            .workgroup_size
+            .unwrap_or_else(|| device_workgroup_size(physical_device));
+        let pipeline = ComputeRenderPipeline::new(
+            workgroup_width,
+            workgroup_height,
+            params.albedo_only,
+            device,
+            Arc::clone(&params.shader),
+            Arc::clone(&queue),
+            &[Arc::clone(&image)],
+            params.image_binding.index(),
+            &params.descriptor_set_allocator,
+            &params.command_buffer_allocator,
+            &dimensions,
+            component_set,
+            params.bindings.as_ref(),
+        );
+
+        OffscreenPipeline {
+            params,
+            queue,
+            memory_allocator,
+            image,
+            readback,
+            copy_command_buffer,
+            pipeline,
+            dimensions,
+            fence: None,
+        }
+    }
+
+    /// Returns `Err` if `dimensions` is zero sized, in which case nothing is changed
+    pub fn resize(
+        &mut self,
+        dimensions: &PhysicalSize<u32>,
+        component_set: &impl DataComponentSet,
+    ) -> Result<(), ()> {
+        if dimensions.width == 0 || dimensions.height == 0 {
+            return Err(());
+        }
+        // The last frame may still be reading from the old image
+        let _ = self.wait_idle(Some(Duration::from_secs(3)));
+        let (image, readback) = create_target(&self.memory_allocator, *dimensions);
+        self.copy_command_buffer = record_copy(
+            &self.params.command_buffer_allocator,
+            &self.queue,
+            &image,
+            &readback,
+        );
+        self.image = image;
+        self.readback = readback;
+        self.dimensions = *dimensions;
+        self.pipeline.recreate(
+            &[Arc::clone(&self.image)],
+            &self.params.descriptor_set_allocator,
+            &self.params.command_buffer_allocator,
+            dimensions,
+            component_set,
+        );
+        Ok(())
+    }
+
+    pub fn dimensions(&self) -> PhysicalSize<u32> {
+        self.dimensions
+    }
+
+    /// Recreate descriptor sets so that they bind the component set's current buffers
+    pub fn rebind(&mut self, component_set: &impl DataComponentSet) {
+        self.pipeline.recreate(
+            &[Arc::clone(&self.image)],
+            &self.params.descriptor_set_allocator,
+            &self.params.command_buffer_allocator,
+            &self.dimensions,
+            component_set,
+        );
+    }
+
+    /// Draw `viewports` before the main image each frame, replacing any previous ones (see `Viewport`)
+    pub fn set_viewports(
+        &mut self,
+        viewports: Vec<Viewport>,
+        component_set: &impl DataComponentSet,
+    ) {
+        // The last frame may still be drawing the old viewports
+        let _ = self.wait_idle(Some(Duration::from_secs(3)));
+        self.pipeline.set_viewports(viewports);
+        self.rebind(component_set);
+    }
+
+    /// Like `rebind`, but only rewrite `bindings`
+    pub fn rebind_bindings(&mut self, component_set: &impl DataComponentSet, bindings: &[u32]) {
+        self.pipeline.rebind_bindings(
+            &[Arc::clone(&self.image)],
+            &self.params.descriptor_set_allocator,
+            &self.params.command_buffer_allocator,
+            &self.dimensions,
+            component_set,
+            bindings,
+        );
+    }
+
+    pub fn wait_for_compute_done(&self, timeout: Option<Duration>) {
+        if let Some(fence) = &self.fence {
+            fence.wait(timeout).unwrap();
+        }
+    }
+
+    /// Wait for the last frame to be rendered and copied. Returns `Err` if it didn't finish within `timeout`.
+    pub fn wait_idle(&self, timeout: Option<Duration>) -> Result<(), ()> {
+        match &self.fence {
+            Some(fence) => fence.wait(timeout).map_err(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    /// Render a frame once `transfer_fence` is signaled and copy it to the readback buffer
+    pub fn render(&mut self, device: Arc<Device>, transfer_fence: Option<&Arc<GpuFence>>) {
+        let previous_future = match self.fence.clone() {
+            None => {
+                let mut now = sync::now(device);
+                now.cleanup_finished();
+
+                now.boxed()
+            }
+            Some(fence) => fence.boxed(),
+        };
+        let previous_future = match transfer_fence {
+            Some(transfer_fence) => previous_future.join(Arc::clone(transfer_fence)).boxed(),
+            None => previous_future,
+        };
+
+        let future = (Box::new(
+            self.pipeline
+                .execute(previous_future, 0)
+                .then_execute(
+                    Arc::clone(&self.queue),
+                    Arc::clone(&self.copy_command_buffer),
+                )
+                .unwrap(),
+        ) as Box<dyn GpuFuture>)
+            .then_signal_fence_and_flush();
+
+        self.fence = match future {
+            Ok(value) => Some(Arc::new(value)),
+            Err(e) => {
+                println!("failed to flush future: {e:?}");
+                None
+            }
+        };
+    }
+
+    /// Wait for the last frame and return it as 8-bit RGBA, row major starting at the top left
+    pub fn read_frame(&self, timeout: Option<Duration>) -> Vec<u8> {
+        self.wait_for_compute_done(timeout);
+        self.readback.read().unwrap().to_vec()
+    }
+}
+
+/// Image to render into and a host visible buffer to copy it to
+fn create_target(
+    memory_allocator: &Arc<dyn MemoryAllocator>,
+    dimensions: PhysicalSize<u32>,
+) -> (Arc<Image>, Subbuffer<[u8]>) {
+    let image = Image::new(
+        Arc::clone(memory_allocator),
+        ImageCreateInfo {
+            image_type: ImageType::Dim2d,
+            format: OFFSCREEN_FORMAT,
+            extent: [dimensions.width, dimensions.height, 1],
+            usage: ImageUsage::STORAGE | ImageUsage::TRANSFER_SRC,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter {
+                required_flags: MemoryPropertyFlags::DEVICE_LOCAL,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    let readback = Buffer::new_slice(
+        Arc::clone(memory_allocator),
+        BufferCreateInfo {
+            usage: BufferUsage::TRANSFER_DST,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter {
+                required_flags: MemoryPropertyFlags::HOST_VISIBLE,
+                ..Default::default()
+            },
+            allocate_preference: MemoryAllocatePreference::AlwaysAllocate,
+            ..Default::default()
+        },
+        dimensions.width as u64 * dimensions.height as u64 * 4,
+    )
+    .unwrap();
+
+    (image, readback)
+}
+
+fn record_copy<CBA: CommandBufferAllocator>(
+    command_buffer_allocator: &CBA,
+    queue: &Queue,
+    image: &Arc<Image>,
+    readback: &Subbuffer<[u8]>,
+) -> Arc<PrimaryAutoCommandBuffer<CBA>> {
+    let mut builder = AutoCommandBufferBuilder::primary(
+        command_buffer_allocator,
+        queue.queue_family_index(),
+        CommandBufferUsage::MultipleSubmit,
+    )
+    .unwrap();
+    builder
+        .copy_image_to_buffer(CopyImageToBufferInfo::image_buffer(
+            Arc::clone(image),
+            readback.clone(),
+        ))
+        .unwrap();
+    builder.build().unwrap()
+}
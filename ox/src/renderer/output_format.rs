@@ -0,0 +1,156 @@
+//! Format of the swapchain images the raytracing shader draws frames into. The shader declares its output image
+//! without a format (using `shader_storage_image_write_without_format`, which `Context` enables), so it can write
+//! to any format that supports storage images. Writes are converted per channel, so BGRA formats get the same
+//! colors as RGBA ones. What has to be checked is that the surface offers such a format at all, which
+//! `OutputFormat::negotiate` does.
+
+use crate::renderer::offscreen::OFFSCREEN_FORMAT;
+use vulkano::device::physical::PhysicalDevice;
+use vulkano::format::{Format, FormatFeatures};
+use vulkano::swapchain::{ColorSpace, Surface};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputFormat {
+    pub format: Format,
+    pub color_space: ColorSpace,
+}
+
+impl OutputFormat {
+    /// Formats tried first, in order. The shader's output is tuned for 8-bit UNORM. sRGB formats never support
+    /// storage images, so they can't be used.
+    pub const PREFERRED: [Format; 4] = [
+        Format::R8G8B8A8_UNORM,
+        Format::B8G8R8A8_UNORM,
+        Format::A8B8G8R8_UNORM_PACK32,
+        Format::A2B10G10R10_UNORM_PACK32,
+    ];
+
+    /// Format of frames drawn by an offscreen renderer (see `Renderer::new_offscreen`)
+    pub fn offscreen() -> Self {
+        OutputFormat {
+            format: OFFSCREEN_FORMAT,
+            color_space: ColorSpace::SrgbNonLinear,
+        }
+    }
+
+    /// Pick the format to create the swapchain with from the ones `surface` supports. Returns `Err` describing the
+    /// surface's formats if none of them can be written by the shader.
+    pub fn negotiate(physical_device: &PhysicalDevice, surface: &Surface) -> Result<Self, String> {
+        let surface_formats = physical_device
+            .surface_formats(surface, Default::default())
+            .map_err(|e| format!("Failed to get the surface's formats: {e:?}"))?;
+        choose_output_format(&surface_formats, |format| {
+            physical_device
+                .format_properties(format)
+                .map_or(false, |properties| {
+                    properties
+                        .optimal_tiling_features
+                        .intersects(FormatFeatures::STORAGE_IMAGE)
+                })
+        })
+    }
+
+    /// Whether the format stores blue before red, e.g. `B8G8R8A8_UNORM`. The shader doesn't need to know, but
+    /// code that reads frames back as bytes does.
+    pub fn is_bgra(&self) -> bool {
+        matches!(
+            self.format,
+            Format::B8G8R8A8_UNORM
+                | Format::B8G8R8A8_SNORM
+                | Format::B8G8R8A8_UINT
+                | Format::B8G8R8A8_SINT
+                | Format::A2R10G10B10_UNORM_PACK32
+        )
+    }
+}
+
+/// Output format from `surface_formats` (as listed by the surface), where `supports_storage` says whether a format
+/// can be a storage image on the device. `OutputFormat::PREFERRED` formats are picked first, and sRGB non-linear
+/// color spaces over others (e.g. HDR ones).
+pub fn choose_output_format(
+    surface_formats: &[(Format, ColorSpace)],
+    supports_storage: impl Fn(Format) -> bool,
+) -> Result<OutputFormat, String> {
+    let mut usable: Vec<_> = surface_formats
+        .iter()
+        .copied()
+        .filter(|&(format, _)| supports_storage(format))
+        .collect();
+    usable.sort_by_key(|&(_, color_space)| color_space != ColorSpace::SrgbNonLinear);
+
+    OutputFormat::PREFERRED
+        .iter()
+        .find_map(|preferred| usable.iter().find(|(format, _)| format == preferred))
+        .or_else(|| usable.first())
+        .map(|&(format, color_space)| OutputFormat {
+            format,
+            color_space,
+        })
+        .ok_or_else(|| {
+            format!(
+                "None of the surface's formats ({:?}) support storage images, which the raytracing shader needs \
+                to write frames",
+                surface_formats
+                    .iter()
+                    .map(|(format, _)| *format)
+                    .collect::<Vec<_>>()
+            )
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_choose_output_format() {
+        let storage = |format: Format| format != Format::B8G8R8A8_SRGB;
+
+        // BGRA is used when RGBA isn't offered, sRGB formats are skipped
+        let format = choose_output_format(
+            &[
+                (Format::B8G8R8A8_SRGB, ColorSpace::SrgbNonLinear),
+                (Format::B8G8R8A8_UNORM, ColorSpace::SrgbNonLinear),
+            ],
+            storage,
+        )
+        .unwrap();
+        assert_eq!(format.format, Format::B8G8R8A8_UNORM);
+        assert!(format.is_bgra());
+
+        // Preferred formats first, then sRGB non-linear color spaces
+        let format = choose_output_format(
+            &[
+                (Format::R16G16B16A16_SFLOAT, ColorSpace::SrgbNonLinear),
+                (Format::R8G8B8A8_UNORM, ColorSpace::ExtendedSrgbLinear),
+                (Format::R8G8B8A8_UNORM, ColorSpace::SrgbNonLinear),
+            ],
+            storage,
+        )
+        .unwrap();
+        assert_eq!(
+            format,
+            OutputFormat {
+                format: Format::R8G8B8A8_UNORM,
+                color_space: ColorSpace::SrgbNonLinear
+            }
+        );
+        assert_eq!(
+            choose_output_format(
+                &[(Format::R16G16B16A16_SFLOAT, ColorSpace::SrgbNonLinear)],
+                storage
+            )
+            .unwrap()
+            .format,
+            Format::R16G16B16A16_SFLOAT
+        );
+
+        let err = choose_output_format(
+            &[(Format::B8G8R8A8_SRGB, ColorSpace::SrgbNonLinear)],
+            storage,
+        )
+        .unwrap_err();
+        assert!(err.contains("B8G8R8A8_SRGB"));
+        assert!(choose_output_format(&[], storage).is_err());
+    }
+}
@@ -1,9 +1,12 @@
+use crate::renderer::binding::BindingMap;
 use crate::renderer::component::{DataComponentSet};
+use crate::renderer::viewport::Viewport;
 use std::sync::Arc;
 use vulkano::command_buffer::allocator::{CommandBufferAllocator};
 use vulkano::command_buffer::{AutoCommandBufferBuilder, CommandBufferExecFuture, CommandBufferUsage, PrimaryAutoCommandBuffer};
 use vulkano::descriptor_set::allocator::{DescriptorSetAllocator};
 use vulkano::descriptor_set::{PersistentDescriptorSet, WriteDescriptorSet};
+use vulkano::device::physical::PhysicalDevice;
 use vulkano::device::{Device, Queue};
 use vulkano::image::view::{ImageView, ImageViewCreateInfo};
 use vulkano::image::Image;
@@ -12,36 +15,112 @@ use vulkano::pipeline::layout::PipelineDescriptorSetLayoutCreateInfo;
 use vulkano::pipeline::{
     ComputePipeline, Pipeline, PipelineBindPoint, PipelineLayout, PipelineShaderStageCreateInfo,
 };
-use vulkano::shader::ShaderModule;
+use vulkano::shader::{ShaderModule, SpecializationConstant};
 use vulkano::sync::GpuFuture;
 use winit::dpi::PhysicalSize;
 
+/// Workgroup size for the raytracing shader. Aims for 64 invocations per workgroup, or one subgroup if
+/// subgroups are larger so none are partially idle, and as square as possible so rays in a workgroup are
+/// close together. Stays within the device's limits.
+pub(crate) fn select_workgroup_size(
+    subgroup_size: Option<u32>,
+    max_invocations: u32,
+    max_size: [u32; 3],
+) -> [u32; 2] {
+    let invocations = 64u32.max(subgroup_size.unwrap_or(1)).min(max_invocations);
+    // Round down to a power of two. Subgroup sizes are always powers of two, so this is still a multiple.
+    let invocations = 1u32 << invocations.ilog2();
+
+    let width = (1u32 << ((invocations.trailing_zeros() + 1) / 2)).min(max_size[0]);
+    let height = (invocations / width).min(max_size[1]);
+    [width, height]
+}
+
+/// Workgroup size to use on `physical_device` (see `select_workgroup_size`)
+pub(crate) fn device_workgroup_size(physical_device: &PhysicalDevice) -> [u32; 2] {
+    let properties = physical_device.properties();
+    select_workgroup_size(
+        properties.subgroup_size,
+        properties.max_compute_work_group_invocations,
+        properties.max_compute_work_group_size,
+    )
+}
+
+/// Number of workgroups to dispatch to cover an image of `dimensions`. Rounds up so that the partial
+/// workgroups at the right and bottom edges of sizes that aren't a multiple of the workgroup size are still
+/// drawn; the shader skips invocations outside the image (see the `imageSize` check in `raytrace.comp`).
+pub(crate) fn dispatch_size(dimensions: PhysicalSize<u32>, workgroup_size: [u32; 2]) -> [u32; 3] {
+    [
+        dimensions.width.div_ceil(workgroup_size[0]),
+        dimensions.height.div_ceil(workgroup_size[1]),
+        1,
+    ]
+}
+
+/// Replace the writes in `cached` for each of `bindings` with the ones for that binding in `fresh`. A binding
+/// can have several writes (e.g. runs of an array of buffers), so all of them are replaced.
+fn replace_bindings<W>(
+    cached: &mut Vec<W>,
+    fresh: Vec<W>,
+    bindings: &[u32],
+    binding: impl Fn(&W) -> u32,
+) {
+    cached.retain(|w| !bindings.contains(&binding(w)));
+    cached.extend(fresh.into_iter().filter(|w| bindings.contains(&binding(w))));
+}
+
+/// Writes for drawing a viewport: `shared` with the bindings that `overrides` has writes for replaced by them
+fn with_overrides<W: Clone>(
+    shared: &[W],
+    overrides: Vec<W>,
+    binding: impl Fn(&W) -> u32,
+) -> Vec<W> {
+    let bindings: Vec<u32> = overrides.iter().map(&binding).collect();
+    let mut writes = shared.to_vec();
+    replace_bindings(&mut writes, overrides, &bindings, binding);
+    writes
+}
+
 pub struct ComputeRenderPipeline<CBA: CommandBufferAllocator + 'static> {
     subgroup_width: u32,
     subgroup_height: u32,
     image_binding: u32,
-    device: Arc<Device>,
-    shader: Arc<ShaderModule>,
     queue: Arc<Queue>,
+    pipeline: Arc<ComputePipeline>,
+    /// Descriptor writes for the component set's bindings, which are the same for every image. Kept so
+    /// that `rebind_bindings` only has to replace the writes of bindings that changed.
+    component_writes: Vec<WriteDescriptorSet>,
+    /// Extra views drawn before the main image, see `Viewport`
+    viewports: Vec<Viewport>,
+    /// Writes from `DataComponentSet::bind_viewport` for each of `viewports`
+    viewport_overrides: Vec<Vec<WriteDescriptorSet>>,
     command_buffers: Vec<Arc<PrimaryAutoCommandBuffer<CBA>>>,
 }
 
 impl<CBA: CommandBufferAllocator> ComputeRenderPipeline<CBA> {
-    pub fn create_command_buffers<DSA: 'static + DescriptorSetAllocator>(
+    fn create_pipeline(
         subgroup_width: u32,
         subgroup_height: u32,
+        albedo_only: bool,
         device: Arc<Device>,
         shader: Arc<ShaderModule>,
-        queue: Arc<Queue>,
-        images: &[Arc<Image>],
-        image_binding: u32,
-        descriptor_set_allocator: &DSA,
-        command_buffer_allocator: &CBA,
-        dimensions: &PhysicalSize<u32>,
-        component_set: &impl DataComponentSet,
-    ) -> Vec<Arc<PrimaryAutoCommandBuffer<CBA>>> {
-        let stage = PipelineShaderStageCreateInfo::new(shader.single_entry_point().unwrap());
-        let pipeline = ComputePipeline::new(
+    ) -> Arc<ComputePipeline> {
+        // The shader's workgroup size is set with specialization constants 0 and 1, and `ALBEDO_ONLY` with 2
+        let entry_point = shader
+            .specialize(
+                [
+                    (0, SpecializationConstant::U32(subgroup_width)),
+                    (1, SpecializationConstant::U32(subgroup_height)),
+                    (2, SpecializationConstant::Bool(albedo_only)),
+                ]
+                .into_iter()
+                .collect(),
+            )
+            .unwrap()
+            .single_entry_point()
+            .unwrap();
+        let stage = PipelineShaderStageCreateInfo::new(entry_point);
+        ComputePipeline::new(
             Arc::clone(&device),
             None,
             ComputePipelineCreateInfo::stage_layout(
@@ -55,17 +134,59 @@ impl<CBA: CommandBufferAllocator> ComputeRenderPipeline<CBA> {
                 .unwrap(),
             ),
         )
-        .unwrap();
+        .unwrap()
+    }
+
+    /// Create a descriptor set for each image from `self.component_writes` and record a command buffer
+    /// that dispatches the pipeline for each viewport and then with it. The pipeline itself is reused.
+    fn create_command_buffers<DSA: 'static + DescriptorSetAllocator>(
+        &self,
+        images: &[Arc<Image>],
+        descriptor_set_allocator: &DSA,
+        command_buffer_allocator: &CBA,
+        dimensions: &PhysicalSize<u32>,
+    ) -> Vec<Arc<PrimaryAutoCommandBuffer<CBA>>> {
+        let pipeline = &self.pipeline;
+        let viewport_descriptor_sets: Vec<(
+            Arc<PersistentDescriptorSet<DSA::Alloc>>,
+            PhysicalSize<u32>,
+        )> = self
+            .viewports
+            .iter()
+            .zip(self.viewport_overrides.iter())
+            .map(|(viewport, overrides)| {
+                let mut descriptor_writes = vec![WriteDescriptorSet::image_view(
+                    self.image_binding,
+                    ImageView::new_default(Arc::clone(viewport.image())).unwrap(),
+                )];
+                descriptor_writes.extend(with_overrides(
+                    &self.component_writes,
+                    overrides.clone(),
+                    |w| w.binding(),
+                ));
+                descriptor_writes
+                    .extend(self.viewports.iter().filter_map(|v| v.placeholder_write()));
+
+                let descriptor_set = PersistentDescriptorSet::new(
+                    descriptor_set_allocator,
+                    pipeline.layout().set_layouts().get(0).unwrap().clone(),
+                    descriptor_writes,
+                    [],
+                )
+                .unwrap();
+                (descriptor_set, viewport.extent())
+            })
+            .collect();
 
         let compute_descriptor_sets: Vec<Arc<PersistentDescriptorSet<DSA::Alloc>>> = images
             .iter()
             .map(|image| {
                 let mut descriptor_writes = vec![WriteDescriptorSet::image_view(
-                    image_binding,
+                    self.image_binding,
                     ImageView::new(image.clone(), ImageViewCreateInfo::from_image(image)).unwrap(),
                 )];
-
-                component_set.bind(&mut descriptor_writes);
+                descriptor_writes.extend(self.component_writes.iter().cloned());
+                descriptor_writes.extend(self.viewports.iter().filter_map(|v| v.texture_write()));
 
                 PersistentDescriptorSet::new(
                     descriptor_set_allocator,
@@ -82,14 +203,29 @@ impl<CBA: CommandBufferAllocator> ComputeRenderPipeline<CBA> {
             .map(|descriptor_set| {
                 let mut builder = AutoCommandBufferBuilder::primary(
                     command_buffer_allocator,
-                    queue.queue_family_index(),
+                    self.queue.queue_family_index(),
                     CommandBufferUsage::MultipleSubmit,
                 )
                 .unwrap();
 
+                builder.bind_pipeline_compute(pipeline.clone()).unwrap();
+                // Viewports first, so the main image can draw their images
+                for (viewport_descriptor_set, extent) in viewport_descriptor_sets.iter() {
+                    builder
+                        .bind_descriptor_sets(
+                            PipelineBindPoint::Compute,
+                            pipeline.layout().clone(),
+                            0,
+                            Arc::clone(viewport_descriptor_set),
+                        )
+                        .unwrap()
+                        .dispatch(dispatch_size(
+                            *extent,
+                            [self.subgroup_width, self.subgroup_height],
+                        ))
+                        .unwrap();
+                }
                 builder
-                    .bind_pipeline_compute(pipeline.clone())
-                    .unwrap()
                     .bind_descriptor_sets(
                         PipelineBindPoint::Compute,
                         pipeline.layout().clone(),
@@ -97,11 +233,10 @@ impl<CBA: CommandBufferAllocator> ComputeRenderPipeline<CBA> {
                         Arc::clone(descriptor_set),
                     )
                     .unwrap()
-                    .dispatch([
-                        (dimensions.width + subgroup_width - 1) / subgroup_width,
-                        (dimensions.height + subgroup_height - 1) / subgroup_height,
-                        1,
-                    ])
+                    .dispatch(dispatch_size(
+                        *dimensions,
+                        [self.subgroup_width, self.subgroup_height],
+                    ))
                     .unwrap();
 
                 builder.build().unwrap()
@@ -112,6 +247,7 @@ impl<CBA: CommandBufferAllocator> ComputeRenderPipeline<CBA> {
     pub fn new<DSA: DescriptorSetAllocator + 'static>(
         subgroup_width: u32,
         subgroup_height: u32,
+        albedo_only: bool,
         device: Arc<Device>,
         shader: Arc<ShaderModule>,
         queue: Arc<Queue>,
@@ -121,30 +257,46 @@ impl<CBA: CommandBufferAllocator> ComputeRenderPipeline<CBA> {
         command_buffer_allocator: &CBA,
         dimensions: &PhysicalSize<u32>,
         component_set: &impl DataComponentSet,
+        bindings: Option<&BindingMap>,
     ) -> Self {
-        ComputeRenderPipeline {
+        if let Some(bindings) = bindings {
+            if let Err(e) = bindings.check_shader(&shader) {
+                panic!(
+                    "Shader bindings don't match the registered bindings:\n{}\n{}",
+                    e, bindings
+                );
+            }
+        }
+        let mut component_writes = vec![];
+        component_set.bind(&mut component_writes);
+        let mut pipeline = ComputeRenderPipeline {
             subgroup_width,
             subgroup_height,
             image_binding,
-            device: Arc::clone(&device),
-            shader: Arc::clone(&shader),
-            queue: Arc::clone(&queue),
-            command_buffers: Self::create_command_buffers(
+            queue,
+            pipeline: Self::create_pipeline(
                 subgroup_width,
                 subgroup_height,
+                albedo_only,
                 device,
                 shader,
-                queue,
-                images,
-                image_binding,
-                descriptor_set_allocator,
-                command_buffer_allocator,
-                dimensions,
-                component_set,
             ),
-        }
+            component_writes,
+            viewports: vec![],
+            viewport_overrides: vec![],
+            command_buffers: vec![],
+        };
+        pipeline.command_buffers = pipeline.create_command_buffers(
+            images,
+            descriptor_set_allocator,
+            command_buffer_allocator,
+            dimensions,
+        );
+        pipeline
     }
 
+    /// Recreate descriptor sets and command buffers for new images (e.g. after the swapchain was resized),
+    /// binding all of the component set's current buffers.
     pub fn recreate<DSA: DescriptorSetAllocator + 'static>(
         &mut self,
         images: &[Arc<Image>],
@@ -153,22 +305,119 @@ impl<CBA: CommandBufferAllocator> ComputeRenderPipeline<CBA> {
         dimensions: &PhysicalSize<u32>,
         component_set: &impl DataComponentSet,
     ) {
-        self.command_buffers = Self::create_command_buffers(
-            self.subgroup_width,
-            self.subgroup_height,
-            Arc::clone(&self.device),
-            Arc::clone(&self.shader),
-            Arc::clone(&self.queue),
+        self.component_writes.clear();
+        component_set.bind(&mut self.component_writes);
+        self.bind_viewports(component_set);
+        self.command_buffers = self.create_command_buffers(
             images,
-            self.image_binding,
             descriptor_set_allocator,
             command_buffer_allocator,
             dimensions,
-            component_set,
         );
     }
 
+    /// Like `recreate` for the same images, but only replace the descriptor writes of `bindings` and keep
+    /// the rest. Descriptor sets can't be changed once created, so new ones are still made for each image.
+    pub fn rebind_bindings<DSA: DescriptorSetAllocator + 'static>(
+        &mut self,
+        images: &[Arc<Image>],
+        descriptor_set_allocator: &DSA,
+        command_buffer_allocator: &CBA,
+        dimensions: &PhysicalSize<u32>,
+        component_set: &impl DataComponentSet,
+        bindings: &[u32],
+    ) {
+        let mut fresh = vec![];
+        component_set.bind(&mut fresh);
+        replace_bindings(&mut self.component_writes, fresh, bindings, |w| w.binding());
+        self.bind_viewports(component_set);
+        self.command_buffers = self.create_command_buffers(
+            images,
+            descriptor_set_allocator,
+            command_buffer_allocator,
+            dimensions,
+        );
+    }
+
+    /// Replace the viewports drawn before the main image. Takes effect once the command buffers are recreated
+    /// with `recreate`.
+    pub fn set_viewports(&mut self, viewports: Vec<Viewport>) {
+        self.viewports = viewports;
+    }
+
+    pub fn viewports(&self) -> &[Viewport] {
+        &self.viewports
+    }
+
+    fn bind_viewports(&mut self, component_set: &impl DataComponentSet) {
+        self.viewport_overrides = (0..self.viewports.len())
+            .map(|i| {
+                let mut overrides = vec![];
+                component_set.bind_viewport(i, &mut overrides);
+                overrides
+            })
+            .collect();
+    }
+
     pub fn execute<F: GpuFuture>(&self, future: F, index: usize) -> CommandBufferExecFuture<F> {
-        future.then_execute(Arc::clone(&self.queue), Arc::clone(&self.command_buffers[index])).unwrap()
+        future
+            .then_execute(
+                Arc::clone(&self.queue),
+                Arc::clone(&self.command_buffers[index]),
+            )
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replace_bindings() {
+        let mut cached = vec![(1, "materials"), (4, "lod a"), (4, "lod b"), (8, "bitmask")];
+        let fresh = vec![(1, "new materials"), (4, "new lod"), (8, "new bitmask")];
+        replace_bindings(&mut cached, fresh, &[4, 8], |w| w.0);
+        assert_eq!(
+            cached,
+            vec![(1, "materials"), (4, "new lod"), (8, "new bitmask")]
+        );
+    }
+
+    #[test]
+    fn test_with_overrides() {
+        let shared = vec![(1, "materials"), (2, "camera"), (3, "ubo")];
+        assert_eq!(
+            with_overrides(&shared, vec![(2, "mirror camera")], |w| w.0),
+            vec![(1, "materials"), (3, "ubo"), (2, "mirror camera")]
+        );
+        assert_eq!(with_overrides(&shared, vec![], |w| w.0), shared);
+    }
+
+    #[test]
+    fn test_select_workgroup_size() {
+        assert_eq!(select_workgroup_size(Some(32), 1024, [1024; 3]), [8, 8]);
+        assert_eq!(select_workgroup_size(Some(64), 1024, [1024; 3]), [8, 8]);
+        assert_eq!(select_workgroup_size(Some(128), 1024, [1024; 3]), [16, 8]);
+        assert_eq!(select_workgroup_size(None, 1024, [1024; 3]), [8, 8]);
+        assert_eq!(select_workgroup_size(Some(32), 32, [1024; 3]), [8, 4]);
+        assert_eq!(
+            select_workgroup_size(Some(32), 1024, [4, 1024, 64]),
+            [4, 16]
+        );
+    }
+
+    #[test]
+    fn test_dispatch_size() {
+        assert_eq!(
+            dispatch_size(PhysicalSize::new(1920, 1080), [8, 8]),
+            [240, 135, 1]
+        );
+        // Partial workgroups at the edges are still dispatched
+        assert_eq!(
+            dispatch_size(PhysicalSize::new(1366, 767), [8, 8]),
+            [171, 96, 1]
+        );
+        assert_eq!(dispatch_size(PhysicalSize::new(1, 1), [16, 8]), [1, 1, 1]);
     }
 }
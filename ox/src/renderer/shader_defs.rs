@@ -0,0 +1,193 @@
+//! GLSL header with definitions that have to match the Rust side configuration: chunk size, LOD render areas,
+//! binding indices and the `Material` struct layout. Shaders `#include` the generated header instead of hard
+//! coding these values so they can't drift from the Rust constants.
+//!
+//! Since `vulkano_shaders::shader!` compiles shaders when the Rust code is compiled, the header has to exist
+//! next to the shader before then. Generate it once with `ShaderDefs::write_if_changed` (e.g. on startup in
+//! debug builds, or from a test) and commit it; if the configuration changes, the header is rewritten and the
+//! next build picks it up. Use `ShaderDefs::check_matches` on startup to refuse to run shaders that were compiled
+//! with a different header.
+
+use crate::renderer::binding::BindingMap;
+use crate::renderer::component::materials::Material;
+use crate::renderer::component::voxels::data::VoxelTypeIDs;
+use crate::world::mem_grid::utils::ChunkSize;
+use crate::world::mem_grid::voxel::VoxelLODCreateParams;
+use std::fmt::Display;
+use std::path::Path;
+
+const HEADER_GUARD: &str = "OX_DEFS_GLSL";
+
+#[derive(Debug, Clone, Default)]
+pub struct ShaderDefs {
+    lines: Vec<String>,
+}
+
+impl ShaderDefs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Definitions for a voxel memory grid created from `lod_params` (e.g. `VoxelMemoryGrid::standard_config`).
+    /// With a `LodConfig`, use `LodConfig::shader_defs` instead.
+    pub fn for_voxel_grid(lod_params: &[VoxelLODCreateParams], chunk_size: ChunkSize) -> Self {
+        let largest = lod_params
+            .iter()
+            .max_by_key(|lod| (lod.lvl, lod.sublvl))
+            .expect("No LOD params");
+        // Only lvl 0 has sublvls
+        let sublvls: Vec<_> = lod_params.iter().filter(|lod| lod.lvl == 0).collect();
+        let render_n_tlcs_sublvl: Vec<_> = sublvls
+            .iter()
+            .map(|lod| lod.render_area_size.to_string())
+            .collect();
+        let render_n_tlcs_y_sublvl: Vec<_> = sublvls
+            .iter()
+            .map(|lod| lod.resolved_render_area_size_y().to_string())
+            .collect();
+        let sublvl_y: Vec<_> = sublvls
+            .iter()
+            .map(|lod| lod.resolved_sublvl_y().to_string())
+            .collect();
+
+        Self::new()
+            .define("CHUNK_SIZE", chunk_size.size())
+            .define("N_CHUNK_LVLS", largest.lvl)
+            .define("RENDER_N_TLCS", largest.render_area_size)
+            .define("RENDER_N_TLCS_Y", largest.resolved_render_area_size_y())
+            .define("N_MATERIAL_ID_BITS", VoxelTypeIDs::BITS_PER_VOXEL)
+            .line(format!("const uint N_SUBLVLS = {};", sublvls.len()))
+            .line(format!(
+                "const uint RENDER_N_TLCS_SUBLVL[N_SUBLVLS] = uint[]({});",
+                render_n_tlcs_sublvl.join(", ")
+            ))
+            .line(format!(
+                "const uint RENDER_N_TLCS_Y_SUBLVL[N_SUBLVLS] = uint[]({});",
+                render_n_tlcs_y_sublvl.join(", ")
+            ))
+            .line(format!(
+                "const uint SUBLVL_Y[N_SUBLVLS] = uint[]({});",
+                sublvl_y.join(", ")
+            ))
+    }
+
+    /// Define `BINDING_<NAME>` for each registered binding, e.g. `BINDING_LOD_0_0_BITMASK` for
+    /// "lod_0_0_bitmask", to use in `layout(binding = ...)` declarations.
+    pub fn with_bindings(mut self, bindings: &BindingMap) -> Self {
+        for entry in bindings.entries() {
+            self = self.define(
+                &format!("BINDING_{}", entry.name.to_uppercase()),
+                entry.index,
+            );
+        }
+        self
+    }
+
+    /// Declare the `Material` struct matching `renderer::component::materials::Material`
+    pub fn with_material_struct(self) -> Self {
+        self.line(Material::GLSL.to_string())
+    }
+
+    pub fn define(self, name: &str, value: impl Display) -> Self {
+        self.line(format!("#define {} {}", name, value))
+    }
+
+    /// Add a raw line of GLSL
+    pub fn line(mut self, line: String) -> Self {
+        self.lines.push(line);
+        self
+    }
+
+    /// Header text to `#include` in the shader
+    pub fn glsl(&self) -> String {
+        let mut s = format!(
+            "// Generated by ox::renderer::shader_defs from the Rust configuration. Do not edit.\n\
+            #ifndef {HEADER_GUARD}\n#define {HEADER_GUARD}\n\n"
+        );
+        for line in self.lines.iter() {
+            s.push_str(line);
+            s.push('\n');
+        }
+        s.push_str(&format!("\n#endif // {HEADER_GUARD}\n"));
+        s
+    }
+
+    /// Check that `compiled_glsl`, the header the shaders were compiled with (e.g. `include_str!` of the header
+    /// file), matches these definitions. Shaders compiled with a different header would use the wrong sizes and
+    /// bindings, so the error names the first line that differs.
+    pub fn check_matches(&self, compiled_glsl: &str) -> Result<(), String> {
+        let glsl = self.glsl();
+        if glsl == compiled_glsl {
+            return Ok(());
+        }
+        let (expected, found): (Vec<_>, Vec<_>) =
+            (glsl.lines().collect(), compiled_glsl.lines().collect());
+        let i = (0..expected.len().max(found.len()))
+            .find(|&i| expected.get(i) != found.get(i))
+            .unwrap_or(0);
+        Err(format!(
+            "Shader definitions don't match the configuration: line {} is {:?} but should be {:?}",
+            i + 1,
+            found.get(i).copied().unwrap_or_default(),
+            expected.get(i).copied().unwrap_or_default(),
+        ))
+    }
+
+    /// Write the header to `path` unless it already has the same contents. Returns whether it was written, in
+    /// which case shaders compiled with the old header are out of date until the next build.
+    pub fn write_if_changed(&self, path: impl AsRef<Path>) -> std::io::Result<bool> {
+        let glsl = self.glsl();
+        if std::fs::read_to_string(path.as_ref()).is_ok_and(|existing| existing == glsl) {
+            return Ok(false);
+        }
+        std::fs::write(path, glsl)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::binding::{
+        Binding, BindingMapBuilder, StorageBuffer, StorageImage, UniformBuffer,
+    };
+    use crate::world::mem_grid::voxel::VoxelMemoryGrid;
+
+    #[test]
+    fn test_shared_shader_defs_up_to_date() {
+        // Same configuration as the sandbox
+        let mut bindings = BindingMapBuilder::new();
+        let _: Binding<StorageImage> = bindings.register_at("image", 0);
+        let _: Binding<StorageBuffer> = bindings.register_at("materials", 1);
+        let _: Binding<UniformBuffer> = bindings.register_at("camera", 2);
+        let _: Binding<UniformBuffer> = bindings.register_at("ubo", 3);
+        let _: Binding<UniformBuffer> = bindings.register_at("highlight", 13);
+        let chunk_size = ChunkSize::new(3);
+        let lod_params = VoxelMemoryGrid::standard_config(11, chunk_size, &mut bindings);
+
+        let defs = ShaderDefs::for_voxel_grid(&lod_params, chunk_size)
+            .with_bindings(&bindings.build())
+            .with_material_struct();
+        assert_eq!(
+            defs.glsl(),
+            include_str!("../../../shaders/ox_defs.glsl"),
+            "shaders/ox_defs.glsl is out of date, regenerate it with ShaderDefs::write_if_changed"
+        );
+    }
+
+    #[test]
+    fn test_check_matches() {
+        let defs = ShaderDefs::new()
+            .define("CHUNK_SIZE", 8)
+            .define("N_CHUNK_LVLS", 2);
+        assert_eq!(defs.check_matches(&defs.glsl()), Ok(()));
+
+        let changed = ShaderDefs::new()
+            .define("CHUNK_SIZE", 16)
+            .define("N_CHUNK_LVLS", 2);
+        let err = defs.check_matches(&changed.glsl()).unwrap_err();
+        assert!(err.contains("#define CHUNK_SIZE 16"), "{}", err);
+        assert!(err.contains("#define CHUNK_SIZE 8"), "{}", err);
+        assert!(defs.check_matches("").is_err());
+    }
+}
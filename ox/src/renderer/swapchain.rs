@@ -1,5 +1,10 @@
+use crate::renderer::binding::{Binding, BindingMap, StorageImage};
 use crate::renderer::component::DataComponentSet;
-use crate::renderer::pipeline::ComputeRenderPipeline;
+use crate::renderer::output_format::OutputFormat;
+use crate::renderer::pipeline::{device_workgroup_size, ComputeRenderPipeline};
+use crate::renderer::viewport::Viewport;
+use smallvec::{smallvec, SmallVec};
+use std::collections::VecDeque;
 use std::sync::Arc;
 use std::time::Duration;
 use vulkano::command_buffer::allocator::CommandBufferAllocator;
@@ -8,17 +13,26 @@ use vulkano::device::physical::PhysicalDevice;
 use vulkano::device::{Device, Queue};
 use vulkano::image::{Image, ImageUsage};
 use vulkano::shader::ShaderModule;
-use vulkano::swapchain::{Surface, Swapchain, SwapchainCreateInfo, SwapchainPresentInfo};
+use vulkano::swapchain::{
+    PresentMode, Surface, Swapchain, SwapchainCreateInfo, SwapchainPresentInfo,
+};
 use vulkano::sync::future::FenceSignalFuture;
-use vulkano::sync::GpuFuture;
+use vulkano::sync::{GpuFuture, Sharing};
 use vulkano::{swapchain, sync, Validated, VulkanError};
 use winit::dpi::PhysicalSize;
 
 pub struct SwapchainPipelineParams<DSA: DescriptorSetAllocator, CBA: CommandBufferAllocator> {
-    pub subgroup_width: u32,
-    pub subgroup_height: u32,
-    pub image_binding: u32,
+    /// Workgroup size (width, height) of the raytracing shader. If `None`, it is picked based on the
+    /// device's subgroup size and limits; set it to tune performance on a specific device.
+    pub workgroup_size: Option<[u32; 2]>,
+    pub image_binding: Binding<StorageImage>,
     pub shader: Arc<ShaderModule>,
+    /// If set, the shader's bindings are checked against these when the pipeline is created (see
+    /// `BindingMap::check_shader`), which panics if they don't match.
+    pub bindings: Option<BindingMap>,
+    /// Draw only the color of the first voxel each ray hits (black for the sky) instead of lighting the scene, to
+    /// compare with `reference::render_reference`. Sets the shader's `ALBEDO_ONLY` specialization constant (2).
+    pub albedo_only: bool,
     pub descriptor_set_allocator: DSA,
     pub command_buffer_allocator: CBA,
 }
@@ -31,7 +45,7 @@ pub struct SwapchainPipeline<
 > {
     params: SwapchainPipelineParams<DSA, CBA>,
     images: Vec<Arc<Image>>,
-    graphics_queue: Arc<Queue>,
+    present_queue: Arc<Queue>,
     swapchain: Arc<Swapchain>,
     pipeline: ComputeRenderPipeline<CBA>,
 
@@ -39,6 +53,14 @@ pub struct SwapchainPipeline<
     compute_fence: Option<Arc<GpuFence>>,
     present_fences: Vec<Option<Arc<GpuFence>>>,
     prev_fence_i: u32,
+    /// Present mode the swapchain is (re)created with, see `set_present_mode`
+    present_mode: PresentMode,
+    /// Format and color space the swapchain images were created with, see `OutputFormat::negotiate`
+    output_format: OutputFormat,
+    /// See `set_max_frames_in_flight`
+    max_frames_in_flight: Option<usize>,
+    /// Present fences of the frames submitted since, oldest first. Only tracked with `max_frames_in_flight`.
+    in_flight: VecDeque<Arc<GpuFence>>,
 }
 
 impl<DSA: DescriptorSetAllocator + 'static, CBA: CommandBufferAllocator + 'static>
@@ -47,76 +69,123 @@ impl<DSA: DescriptorSetAllocator + 'static, CBA: CommandBufferAllocator + 'stati
     pub fn new(
         device: Arc<Device>,
         compute_queue: Arc<Queue>,
-        graphics_queue: Arc<Queue>,
+        present_queue: Arc<Queue>,
         dimensions: PhysicalSize<u32>,
         component_set: &impl DataComponentSet,
         physical_device: Arc<PhysicalDevice>,
         surface: Arc<Surface>,
+        present_mode: PresentMode,
         params: SwapchainPipelineParams<DSA, CBA>,
     ) -> Self {
+        // Images are written on the compute queue and presented on the present queue. When those are in
+        // different families, share the images between them rather than transferring ownership every frame.
+        let image_sharing = image_sharing(&compute_queue, &present_queue);
+        let output_format = OutputFormat::negotiate(&physical_device, &surface)
+            .unwrap_or_else(|e| panic!("Failed to create swapchain: {e}"));
+        if !device
+            .enabled_features()
+            .shader_storage_image_write_without_format
+        {
+            println!(
+                "WARNING: shader_storage_image_write_without_format is not supported, so the shader may not be able \
+                to write to {:?} swapchain images",
+                output_format.format
+            );
+        }
         let (swapchain, images) = (|| {
             let caps = physical_device
                 .surface_capabilities(&surface, Default::default())
                 .expect("failed to get surface capabilities");
 
             let composite_alpha = caps.supported_composite_alpha.into_iter().next().unwrap();
-            for (image_format, _) in physical_device
-                .surface_formats(&surface, Default::default())
-                .unwrap()
-            {
-                if let Ok(stuff) = Swapchain::new(
-                    Arc::clone(&device),
-                    surface.clone(),
-                    SwapchainCreateInfo {
-                        min_image_count: caps.min_image_count,
-                        image_format,
-                        image_extent: dimensions.into(),
-                        image_usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::STORAGE,
-                        composite_alpha,
-                        ..Default::default()
-                    },
-                ) {
-                    return stuff;
-                }
-            }
-
-            panic!("Failed to create swapchain.");
+            Swapchain::new(
+                Arc::clone(&device),
+                surface.clone(),
+                SwapchainCreateInfo {
+                    min_image_count: caps.min_image_count,
+                    image_format: output_format.format,
+                    image_color_space: output_format.color_space,
+                    image_extent: dimensions.into(),
+                    image_usage: ImageUsage::COLOR_ATTACHMENT | ImageUsage::STORAGE,
+                    image_sharing: image_sharing.clone(),
+                    composite_alpha,
+                    present_mode,
+                    ..Default::default()
+                },
+            )
+            .unwrap_or_else(|e| panic!("Failed to create swapchain with {output_format:?}: {e:?}"))
         })();
 
+        let [workgroup_width, workgroup_height] = params
+            .workgroup_size
+            .unwrap_or_else(|| device_workgroup_size(&physical_device));
         let pipeline = ComputeRenderPipeline::new(
-            params.subgroup_width,
-            params.subgroup_height,
+            workgroup_width,
+            workgroup_height,
+            params.albedo_only,
             device,
             Arc::clone(&params.shader),
             compute_queue,
             images.as_slice(),
-            params.image_binding,
+            params.image_binding.index(),
             &params.descriptor_set_allocator,
             &params.command_buffer_allocator,
             &dimensions,
             component_set,
+            params.bindings.as_ref(),
         );
 
         let len = images.len();
         SwapchainPipeline {
             params,
             images,
-            graphics_queue,
+            present_queue,
             swapchain,
             pipeline,
             recreate: false,
             compute_fence: None,
             present_fences: vec![None; len],
             prev_fence_i: 0,
+            present_mode,
+            output_format,
+            max_frames_in_flight: None,
+            in_flight: VecDeque::new(),
+        }
+    }
+
+    /// Wait for earlier frames before drawing a new one so that at most `max` frames are in flight, or `None` to
+    /// only be limited by the number of swapchain images
+    pub fn set_max_frames_in_flight(&mut self, max: Option<usize>) {
+        self.max_frames_in_flight = max;
+        if max.is_none() {
+            self.in_flight.clear();
         }
     }
 
+    /// Recreate the swapchain with `present_mode`. Returns `Err` if it can't be recreated right now, in which case
+    /// the mode is applied the next time it is (e.g. when the window is resized).
+    pub fn set_present_mode(
+        &mut self,
+        present_mode: PresentMode,
+        component_set: &impl DataComponentSet,
+    ) -> Result<(), ()> {
+        if present_mode == self.present_mode {
+            return Ok(());
+        }
+        self.present_mode = present_mode;
+        self.recreate()?;
+        self.rebind(component_set);
+        Ok(())
+    }
+
+    /// Returns `Err` if the swapchain couldn't be recreated, e.g. because the window is minimized, in which case
+    /// nothing is changed
     pub fn resize(
         &mut self,
         dimensions: &PhysicalSize<u32>,
         component_set: &impl DataComponentSet,
-    ) {
-        self.recreate_with_dims(*dimensions);
+    ) -> Result<(), ()> {
+        self.recreate_with_dims(*dimensions)?;
         self.pipeline.recreate(
             &self.images,
             &self.params.descriptor_set_allocator,
@@ -124,22 +193,77 @@ impl<DSA: DescriptorSetAllocator + 'static, CBA: CommandBufferAllocator + 'stati
             dimensions,
             component_set,
         );
+        Ok(())
+    }
+
+    pub fn dimensions(&self) -> PhysicalSize<u32> {
+        PhysicalSize::from(self.swapchain.image_extent())
+    }
+
+    pub fn output_format(&self) -> OutputFormat {
+        self.output_format
+    }
+
+    /// Recreate descriptor sets so that they bind the component set's current buffers
+    pub fn rebind(&mut self, component_set: &impl DataComponentSet) {
+        let dimensions: [u32; 2] = self.swapchain.image_extent();
+        self.pipeline.recreate(
+            &self.images,
+            &self.params.descriptor_set_allocator,
+            &self.params.command_buffer_allocator,
+            &PhysicalSize::from(dimensions),
+            component_set,
+        );
+    }
+
+    /// Draw `viewports` before the main image each frame, replacing any previous ones (see `Viewport`)
+    pub fn set_viewports(
+        &mut self,
+        viewports: Vec<Viewport>,
+        component_set: &impl DataComponentSet,
+    ) {
+        self.pipeline.set_viewports(viewports);
+        self.rebind(component_set);
+    }
+
+    /// Like `rebind`, but only rewrite `bindings`
+    pub fn rebind_bindings(&mut self, component_set: &impl DataComponentSet, bindings: &[u32]) {
+        let dimensions: [u32; 2] = self.swapchain.image_extent();
+        self.pipeline.rebind_bindings(
+            &self.images,
+            &self.params.descriptor_set_allocator,
+            &self.params.command_buffer_allocator,
+            &PhysicalSize::from(dimensions),
+            component_set,
+            bindings,
+        );
     }
 
-    pub fn recreate(&mut self) {
-        self.recreate_with_dims(self.swapchain.image_extent());
+    pub fn recreate(&mut self) -> Result<(), ()> {
+        self.recreate_with_dims(self.swapchain.image_extent())
     }
 
-    pub fn recreate_with_dims(&mut self, dimensions: impl Into<[u32; 2]>) {
+    /// Returns `Err` if `dimensions` is zero sized (e.g. the window is minimized) or the surface can't currently
+    /// be presented to, in which case the old swapchain is kept
+    pub fn recreate_with_dims(&mut self, dimensions: impl Into<[u32; 2]>) -> Result<(), ()> {
+        let image_extent = dimensions.into();
+        if image_extent.contains(&0) {
+            return Err(());
+        }
         let (new_swapchain, new_images) = match self.swapchain.recreate(SwapchainCreateInfo {
-            image_extent: dimensions.into(),
+            image_extent,
+            present_mode: self.present_mode,
             ..self.swapchain.create_info()
         }) {
             Ok(r) => r,
-            Err(_e) => panic!("failed to recreate swapchain: {_e:?}"),
+            Err(e) => {
+                println!("WARNING: failed to recreate swapchain: {e:?}");
+                return Err(());
+            }
         };
         self.swapchain = new_swapchain;
         self.images = new_images;
+        Ok(())
     }
 
     pub fn wait_for_compute_done(&self, timeout: Option<Duration>) {
@@ -148,16 +272,41 @@ impl<DSA: DescriptorSetAllocator + 'static, CBA: CommandBufferAllocator + 'stati
         }
     }
 
+    /// Wait for all submitted compute and present work to finish. Returns `Err` if any of it didn't
+    /// finish within `timeout`.
+    pub fn wait_idle(&self, timeout: Option<Duration>) -> Result<(), ()> {
+        let mut result = Ok(());
+        for fence in self
+            .compute_fence
+            .iter()
+            .chain(self.present_fences.iter().flatten())
+        {
+            if fence.wait(timeout).is_err() {
+                result = Err(());
+            }
+        }
+        result
+    }
+
+    /// Returns `Err` if the surface can't be presented to, e.g. because the window was minimized or the surface
+    /// was lost. Nothing is submitted then.
     pub fn present(
         &mut self,
         device: Arc<Device>,
-        transfer_fence: &Arc<FenceSignalFuture<Box<dyn GpuFuture>>>,
-    ) {
+        transfer_fence: Option<&Arc<FenceSignalFuture<Box<dyn GpuFuture>>>>,
+    ) -> Result<(), ()> {
         if self.recreate {
-            self.recreate();
+            self.recreate()?;
             self.recreate = false;
         }
 
+        if let Some(max) = self.max_frames_in_flight {
+            while self.in_flight.len() >= max.max(1) {
+                let oldest = self.in_flight.pop_front().unwrap();
+                oldest.wait(Some(Duration::from_secs(3))).unwrap();
+            }
+        }
+
         let (image_i, suboptimal, acquire_future) = match swapchain::acquire_next_image(
             Arc::clone(&self.swapchain),
             Some(Duration::from_secs(3)),
@@ -165,7 +314,11 @@ impl<DSA: DescriptorSetAllocator + 'static, CBA: CommandBufferAllocator + 'stati
             Ok(r) => r,
             Err(Validated::Error(VulkanError::OutOfDate)) => {
                 self.recreate = true;
-                return;
+                return Ok(());
+            }
+            Err(Validated::Error(VulkanError::SurfaceLost)) => {
+                println!("WARNING: surface was lost");
+                return Err(());
             }
             Err(_e) => {
                 panic!("failed to acquire next image: {_e:?}")
@@ -193,9 +346,11 @@ impl<DSA: DescriptorSetAllocator + 'static, CBA: CommandBufferAllocator + 'stati
             Some(fence) => fence.boxed(),
         };
 
-        let curr_future = previous_future
-            .join(Arc::clone(transfer_fence))
-            .join(acquire_future);
+        let previous_future = match transfer_fence {
+            Some(transfer_fence) => previous_future.join(Arc::clone(transfer_fence)).boxed(),
+            None => previous_future,
+        };
+        let curr_future = previous_future.join(acquire_future);
 
         let compute_future = (Box::new(self.pipeline.execute(curr_future, image_i as usize))
             as Box<dyn GpuFuture>)
@@ -211,14 +366,20 @@ impl<DSA: DescriptorSetAllocator + 'static, CBA: CommandBufferAllocator + 'stati
 
         let future = (Box::new(
             Arc::clone(self.compute_fence.as_ref().unwrap()).then_swapchain_present(
-                Arc::clone(&self.graphics_queue),
+                Arc::clone(&self.present_queue),
                 SwapchainPresentInfo::swapchain_image_index(Arc::clone(&self.swapchain), image_i),
             ),
         ) as Box<dyn GpuFuture>)
             .then_signal_fence_and_flush();
 
         self.present_fences[image_i as usize] = match future {
-            Ok(value) => Some(Arc::new(value)),
+            Ok(value) => {
+                let fence = Arc::new(value);
+                if self.max_frames_in_flight.is_some() {
+                    self.in_flight.push_back(Arc::clone(&fence));
+                }
+                Some(fence)
+            }
             Err(Validated::Error(VulkanError::OutOfDate)) => {
                 self.recreate = true;
                 None
@@ -230,5 +391,19 @@ impl<DSA: DescriptorSetAllocator + 'static, CBA: CommandBufferAllocator + 'stati
         };
 
         self.prev_fence_i = image_i;
+        Ok(())
+    }
+}
+
+/// Sharing mode for swapchain images written on `compute_queue` and presented on `present_queue`
+fn image_sharing(compute_queue: &Queue, present_queue: &Queue) -> Sharing<SmallVec<[u32; 4]>> {
+    let (compute, present) = (
+        compute_queue.queue_family_index(),
+        present_queue.queue_family_index(),
+    );
+    if compute == present {
+        Sharing::Exclusive
+    } else {
+        Sharing::Concurrent(smallvec![compute, present])
     }
 }
@@ -0,0 +1,134 @@
+use crate::renderer::component::DataComponentSet;
+use crate::renderer::offscreen::OffscreenPipeline;
+use crate::renderer::output_format::OutputFormat;
+use crate::renderer::swapchain::{GpuFence, SwapchainPipeline};
+use crate::renderer::viewport::Viewport;
+use std::sync::Arc;
+use std::time::Duration;
+use vulkano::command_buffer::allocator::CommandBufferAllocator;
+use vulkano::descriptor_set::allocator::DescriptorSetAllocator;
+use vulkano::device::Device;
+use vulkano::swapchain::PresentMode;
+use winit::dpi::PhysicalSize;
+
+/// Where `Renderer` draws frames to
+pub(crate) enum RenderTarget<
+    DSA: DescriptorSetAllocator + 'static,
+    CBA: CommandBufferAllocator + 'static,
+> {
+    Swapchain(SwapchainPipeline<DSA, CBA>),
+    Offscreen(OffscreenPipeline<DSA, CBA>),
+}
+
+impl<DSA: DescriptorSetAllocator + 'static, CBA: CommandBufferAllocator + 'static>
+    RenderTarget<DSA, CBA>
+{
+    pub fn resize(
+        &mut self,
+        dimensions: &PhysicalSize<u32>,
+        component_set: &impl DataComponentSet,
+    ) -> Result<(), ()> {
+        match self {
+            RenderTarget::Swapchain(swapchain) => swapchain.resize(dimensions, component_set),
+            RenderTarget::Offscreen(offscreen) => offscreen.resize(dimensions, component_set),
+        }
+    }
+
+    /// Recreate the swapchain. Offscreen targets have nothing to recreate.
+    pub fn recreate(&mut self) -> Result<(), ()> {
+        match self {
+            RenderTarget::Swapchain(swapchain) => swapchain.recreate(),
+            RenderTarget::Offscreen(_) => Ok(()),
+        }
+    }
+
+    pub fn dimensions(&self) -> PhysicalSize<u32> {
+        match self {
+            RenderTarget::Swapchain(swapchain) => swapchain.dimensions(),
+            RenderTarget::Offscreen(offscreen) => offscreen.dimensions(),
+        }
+    }
+
+    pub fn output_format(&self) -> OutputFormat {
+        match self {
+            RenderTarget::Swapchain(swapchain) => swapchain.output_format(),
+            RenderTarget::Offscreen(_) => OutputFormat::offscreen(),
+        }
+    }
+
+    pub fn rebind(&mut self, component_set: &impl DataComponentSet) {
+        match self {
+            RenderTarget::Swapchain(swapchain) => swapchain.rebind(component_set),
+            RenderTarget::Offscreen(offscreen) => offscreen.rebind(component_set),
+        }
+    }
+
+    pub fn rebind_bindings(&mut self, component_set: &impl DataComponentSet, bindings: &[u32]) {
+        match self {
+            RenderTarget::Swapchain(swapchain) => {
+                swapchain.rebind_bindings(component_set, bindings)
+            }
+            RenderTarget::Offscreen(offscreen) => {
+                offscreen.rebind_bindings(component_set, bindings)
+            }
+        }
+    }
+
+    pub fn set_viewports(
+        &mut self,
+        viewports: Vec<Viewport>,
+        component_set: &impl DataComponentSet,
+    ) {
+        match self {
+            RenderTarget::Swapchain(swapchain) => swapchain.set_viewports(viewports, component_set),
+            RenderTarget::Offscreen(offscreen) => offscreen.set_viewports(viewports, component_set),
+        }
+    }
+
+    /// Limit the frames in flight and set the present mode, see `LatencyMode`. Offscreen targets don't present and
+    /// wait for each frame before drawing the next, so this does nothing for them.
+    pub fn set_frame_pacing(
+        &mut self,
+        max_frames_in_flight: Option<usize>,
+        present_mode: PresentMode,
+        component_set: &impl DataComponentSet,
+    ) -> Result<(), ()> {
+        match self {
+            RenderTarget::Swapchain(swapchain) => {
+                swapchain.set_max_frames_in_flight(max_frames_in_flight);
+                swapchain.set_present_mode(present_mode, component_set)
+            }
+            RenderTarget::Offscreen(_) => Ok(()),
+        }
+    }
+
+    pub fn wait_for_compute_done(&self, timeout: Option<Duration>) {
+        match self {
+            RenderTarget::Swapchain(swapchain) => swapchain.wait_for_compute_done(timeout),
+            RenderTarget::Offscreen(offscreen) => offscreen.wait_for_compute_done(timeout),
+        }
+    }
+
+    pub fn wait_idle(&self, timeout: Option<Duration>) -> Result<(), ()> {
+        match self {
+            RenderTarget::Swapchain(swapchain) => swapchain.wait_idle(timeout),
+            RenderTarget::Offscreen(offscreen) => offscreen.wait_idle(timeout),
+        }
+    }
+
+    /// Draw a frame after `transfer_fence`, presenting it or copying it for `read_frame`. Returns `Err` if
+    /// the swapchain's surface can't be presented to.
+    pub fn present(
+        &mut self,
+        device: Arc<Device>,
+        transfer_fence: Option<&Arc<GpuFence>>,
+    ) -> Result<(), ()> {
+        match self {
+            RenderTarget::Swapchain(swapchain) => swapchain.present(device, transfer_fence),
+            RenderTarget::Offscreen(offscreen) => {
+                offscreen.render(device, transfer_fence);
+                Ok(())
+            }
+        }
+    }
+}
@@ -3,11 +3,12 @@ use vulkano::device::physical::PhysicalDevice;
 use vulkano::device::{
     Device, DeviceCreateInfo, DeviceExtensions, Queue, QueueCreateInfo, QueueFlags,
 };
-use vulkano::instance::debug::ValidationFeatureEnable;
-use vulkano::instance::{Instance, InstanceCreateInfo, InstanceExtensions};
+use vulkano::instance::{Instance, InstanceExtensions};
 use vulkano::memory::allocator::StandardMemoryAllocator;
 use vulkano::VulkanLibrary;
 
+use crate::renderer::context::{create_instance, select_physical_device};
+
 pub struct TestContext {
     pub instance: Arc<Instance>,
     pub physical_device: Arc<PhysicalDevice>,
@@ -20,35 +21,11 @@ pub struct TestContext {
 impl TestContext {
     pub fn new() -> TestContext {
         let library = VulkanLibrary::new().expect("no local Vulkan library/DLL");
-        let instance = Instance::new(
-            library,
-            InstanceCreateInfo {
-                enabled_extensions: InstanceExtensions {
-                    ext_validation_features: true,
-                    ..Default::default()
-                },
-                enabled_layers: vec![
-                    "VK_LAYER_KHRONOS_validation".to_string(),
-                    // "VK_LAYER_LUNARG_api_dump".to_string(),
-                ],
-                enabled_validation_features: vec![
-                    ValidationFeatureEnable::BestPractices,
-                    ValidationFeatureEnable::GpuAssisted,
-                    ValidationFeatureEnable::GpuAssistedReserveBindingSlot,
-                    ValidationFeatureEnable::SynchronizationValidation,
-                ],
-                ..Default::default()
-            },
-        )
-        .expect("failed to create instance");
+        let instance = create_instance(library, InstanceExtensions::empty(), true);
 
         let device_extensions = DeviceExtensions::empty();
 
-        let physical_device = instance
-            .enumerate_physical_devices()
-            .unwrap()
-            .next()
-            .expect("No devices.");
+        let physical_device = select_physical_device(&instance, &device_extensions);
         let transfer_queue_family_i = physical_device
             .queue_family_properties()
             .iter()
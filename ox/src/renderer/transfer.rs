@@ -8,13 +8,76 @@ use vulkano::command_buffer::allocator::{
 };
 use vulkano::command_buffer::{
     AutoCommandBufferBuilder, CommandBufferInheritanceInfo, CommandBufferUsage,
-    SecondaryCommandBufferAbstract,
+    PrimaryCommandBufferAbstract, SecondaryCommandBufferAbstract,
 };
 use vulkano::device::{Device, Queue};
 use vulkano::sync;
 use vulkano::sync::future::FenceSignalFuture;
 use vulkano::sync::GpuFuture;
 
+/// Most data `Renderer` uploads per frame, so a burst of chunks finishing loading in one frame is spread over the next
+/// frames instead of causing a hitch. Set it with `Renderer::set_upload_budget` and pass `Renderer::upload_budget` to
+/// `VoxelMemoryGrid::get_updates`, which returns the chunk updates that fit, nearest chunks first, and keeps the rest
+/// queued for later frames. No limit by default.
+///
+/// The budget also decides when the renderer submits the background transfer, which uploads bulk data that can lag
+/// behind by a few frames (e.g. far LODs, see `VoxelData::set_background_lods`). The background transfer is
+/// submitted after the frame's own transfer so close detail is never queued behind it, and the frame still waits for
+/// both since its compute pass reads the uploaded buffers. So this controls which frames pay for background uploads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UploadBudget {
+    /// Most bytes to upload per frame, or `None` for no limit. The background transfer is submitted in a frame if it
+    /// fits in the bytes left over by the frame's own transfer, and a frame with nothing else to transfer always
+    /// takes it.
+    pub max_bytes: Option<u64>,
+    /// Most copy regions (usually whole chunks) to upload per frame, or `None` for no limit
+    pub max_regions: Option<usize>,
+    /// Submit the background transfer regardless of the budget once it has been deferred for this many frames,
+    /// so far data can't fall behind indefinitely while close data keeps changing
+    pub max_deferred_background_frames: u32,
+}
+
+impl Default for UploadBudget {
+    fn default() -> Self {
+        UploadBudget {
+            max_bytes: None,
+            max_regions: None,
+            max_deferred_background_frames: 30,
+        }
+    }
+}
+
+impl UploadBudget {
+    /// Whether every upload fits, so nothing ever has to be queued for a later frame
+    pub fn is_unlimited(&self) -> bool {
+        self.max_bytes.is_none() && self.max_regions.is_none()
+    }
+
+    /// Whether a region of `bytes` can be uploaded in a frame that already uploads `regions` regions of
+    /// `frame_bytes` in total. The first region of a frame always fits, so a region larger than the budget can't
+    /// hold up the others indefinitely.
+    pub fn fits(&self, frame_bytes: u64, regions: usize, bytes: u64) -> bool {
+        regions == 0
+            || (frame_bytes.saturating_add(bytes) <= self.max_bytes.unwrap_or(u64::MAX)
+                && regions < self.max_regions.unwrap_or(usize::MAX))
+    }
+
+    /// Whether to submit `background_bytes` of background uploads in a frame that transfers `frame_bytes`, after
+    /// they have been deferred for `deferred_frames` frames
+    pub fn should_submit_background(
+        &self,
+        frame_bytes: u64,
+        background_bytes: u64,
+        deferred_frames: u32,
+    ) -> bool {
+        background_bytes > 0
+            && (frame_bytes == 0
+                || frame_bytes.saturating_add(background_bytes)
+                    <= self.max_bytes.unwrap_or(u64::MAX)
+                || deferred_frames >= self.max_deferred_background_frames)
+    }
+}
+
 pub struct TransferManager<CBA: CommandBufferAllocator> {
     always_transfer_command_buffer: Arc<dyn SecondaryCommandBufferAbstract>,
     dynamic_command_buffer_allocator: CBA,
@@ -62,21 +125,33 @@ impl<CBA: CommandBufferAllocator + 'static> TransferManager<CBA> {
         }
     }
 
+    /// Wait for the last transfer to finish. Returns `Err` if it didn't finish within `timeout`.
+    pub fn wait_idle(&self, timeout: Option<Duration>) -> Result<(), ()> {
+        match &self.transfer_fence {
+            Some(tf) => tf.wait(timeout).map_err(|_| ()),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether the last transfer has finished, without waiting for it
+    pub fn is_idle(&self) -> bool {
+        match &self.transfer_fence {
+            Some(tf) => tf.wait(Some(Duration::ZERO)).is_ok(),
+            None => true,
+        }
+    }
+
+    /// Fence for the most recently started transfer, if any
+    pub fn last_transfer(&self) -> Option<&Arc<FenceSignalFuture<Box<dyn GpuFuture>>>> {
+        self.transfer_fence.as_ref()
+    }
+
     pub fn start_transfer(
         &mut self,
         device: Arc<Device>,
         queue: Arc<Queue>,
         component_set: &mut impl DataComponentSet,
     ) -> &Arc<FenceSignalFuture<Box<dyn GpuFuture>>> {
-        let previous_transfer_future = match self.transfer_fence.clone() {
-            None => {
-                let mut now = sync::now(device);
-                now.cleanup_finished();
-                now.boxed()
-            }
-            Some(future) => future.boxed(),
-        };
-
         let transfer_command_buffer = {
             let mut builder = AutoCommandBufferBuilder::primary(
                 &self.dynamic_command_buffer_allocator,
@@ -94,9 +169,52 @@ impl<CBA: CommandBufferAllocator + 'static> TransferManager<CBA> {
             builder.build().unwrap()
         };
 
+        self.submit(device, queue, transfer_command_buffer)
+    }
+
+    /// Submit the components' background uploads (see `DataComponentSet::record_background_transfer_jit`) in their
+    /// own command buffer after the last transfer. The returned fence (also `last_transfer` from now on) signals
+    /// once both are done.
+    pub fn start_background_transfer(
+        &mut self,
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        component_set: &mut impl DataComponentSet,
+    ) -> &Arc<FenceSignalFuture<Box<dyn GpuFuture>>> {
+        let background_command_buffer = {
+            let mut builder = AutoCommandBufferBuilder::primary(
+                &self.dynamic_command_buffer_allocator,
+                queue.queue_family_index(),
+                CommandBufferUsage::MultipleSubmit,
+            )
+            .unwrap();
+
+            component_set.record_background_transfer_jit(&mut builder);
+
+            builder.build().unwrap()
+        };
+
+        self.submit(device, queue, background_command_buffer)
+    }
+
+    fn submit(
+        &mut self,
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        command_buffer: Arc<impl PrimaryCommandBufferAbstract + 'static>,
+    ) -> &Arc<FenceSignalFuture<Box<dyn GpuFuture>>> {
+        let previous_transfer_future = match self.transfer_fence.clone() {
+            None => {
+                let mut now = sync::now(device);
+                now.cleanup_finished();
+                now.boxed()
+            }
+            Some(future) => future.boxed(),
+        };
+
         let transfer_future = (Box::new(
             previous_transfer_future
-                .then_execute(queue, transfer_command_buffer)
+                .then_execute(queue, command_buffer)
                 .unwrap(),
         ) as Box<dyn GpuFuture>)
             .then_signal_fence_and_flush();
@@ -112,3 +230,39 @@ impl<CBA: CommandBufferAllocator + 'static> TransferManager<CBA> {
         self.transfer_fence.as_ref().unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_upload_budget() {
+        assert!(UploadBudget::default().fits(u64::MAX / 2, 1000, 1000));
+
+        let budget = UploadBudget {
+            max_bytes: Some(100),
+            max_regions: Some(2),
+            ..Default::default()
+        };
+        assert!(budget.fits(0, 0, 1000));
+        assert!(budget.fits(40, 1, 60));
+        assert!(!budget.fits(41, 1, 60));
+        assert!(!budget.fits(0, 2, 1));
+    }
+
+    #[test]
+    fn test_upload_budget_background() {
+        assert!(UploadBudget::default().should_submit_background(u64::MAX / 2, 1000, 0));
+
+        let budget = UploadBudget {
+            max_bytes: Some(100),
+            max_regions: None,
+            max_deferred_background_frames: 3,
+        };
+        assert!(!budget.should_submit_background(10, 0, 10));
+        assert!(budget.should_submit_background(0, 1000, 0));
+        assert!(budget.should_submit_background(40, 60, 0));
+        assert!(!budget.should_submit_background(41, 60, 2));
+        assert!(budget.should_submit_background(41, 60, 3));
+    }
+}
@@ -0,0 +1,111 @@
+//! Extra views of the world drawn each frame besides the main image, e.g. a rear-view mirror or a minimap. See
+//! `Renderer::set_viewports`.
+
+use crate::renderer::binding::{Binding, StorageImage};
+use crate::renderer::offscreen::OFFSCREEN_FORMAT;
+use std::sync::Arc;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::image::view::ImageView;
+use vulkano::image::{Image, ImageCreateInfo, ImageType, ImageUsage};
+use vulkano::memory::allocator::{AllocationCreateInfo, MemoryAllocator, MemoryTypeFilter};
+use vulkano::memory::MemoryPropertyFlags;
+use winit::dpi::PhysicalSize;
+
+/// A view of the world drawn with the raytracing shader into its own image, at its own resolution, before each
+/// frame's main image. Every binding is the same as for the main image except the image itself and the bindings
+/// the component set replaces with `DataComponentSet::bind_viewport`, typically the camera (see
+/// `ViewportCameras`). Viewports are numbered by their index in `Renderer::set_viewports`.
+///
+/// If the viewport has a texture binding, its image is also bound there (as a storage image in
+/// `OFFSCREEN_FORMAT`) when the main image is drawn, so the shader can draw it into the frame, e.g. as a picture in
+/// picture. While viewports are drawn, a 1x1 placeholder is bound there instead.
+#[derive(Clone)]
+pub struct Viewport {
+    image: Arc<Image>,
+    texture: Option<ViewportTexture>,
+}
+
+#[derive(Clone)]
+struct ViewportTexture {
+    binding: u32,
+    placeholder: Arc<Image>,
+}
+
+impl Viewport {
+    pub fn new(
+        extent: PhysicalSize<u32>,
+        texture_binding: Option<Binding<StorageImage>>,
+        memory_allocator: Arc<dyn MemoryAllocator>,
+    ) -> Self {
+        assert!(
+            extent.width > 0 && extent.height > 0,
+            "Viewports can't be zero sized"
+        );
+        Viewport {
+            image: viewport_image(&memory_allocator, extent),
+            texture: texture_binding.map(|binding| ViewportTexture {
+                binding: binding.index(),
+                placeholder: viewport_image(&memory_allocator, PhysicalSize::new(1, 1)),
+            }),
+        }
+    }
+
+    /// Image the viewport is drawn into
+    pub fn image(&self) -> &Arc<Image> {
+        &self.image
+    }
+
+    /// Resolution of the viewport. Give its camera the same resolution (see `Camera::resolution`).
+    pub fn extent(&self) -> PhysicalSize<u32> {
+        let [width, height, _] = self.image.extent();
+        PhysicalSize::new(width, height)
+    }
+
+    pub fn texture_binding(&self) -> Option<u32> {
+        self.texture.as_ref().map(|texture| texture.binding)
+    }
+
+    /// Write binding the viewport's image at its texture binding, for drawing the main image
+    pub(crate) fn texture_write(&self) -> Option<WriteDescriptorSet> {
+        self.texture.as_ref().map(|texture| {
+            WriteDescriptorSet::image_view(
+                texture.binding,
+                ImageView::new_default(Arc::clone(&self.image)).unwrap(),
+            )
+        })
+    }
+
+    /// Write binding the placeholder at the viewport's texture binding, for drawing viewports
+    pub(crate) fn placeholder_write(&self) -> Option<WriteDescriptorSet> {
+        self.texture.as_ref().map(|texture| {
+            WriteDescriptorSet::image_view(
+                texture.binding,
+                ImageView::new_default(Arc::clone(&texture.placeholder)).unwrap(),
+            )
+        })
+    }
+}
+
+fn viewport_image(
+    memory_allocator: &Arc<dyn MemoryAllocator>,
+    extent: PhysicalSize<u32>,
+) -> Arc<Image> {
+    Image::new(
+        Arc::clone(memory_allocator),
+        ImageCreateInfo {
+            image_type: ImageType::Dim2d,
+            format: OFFSCREEN_FORMAT,
+            extent: [extent.width, extent.height, 1],
+            usage: ImageUsage::STORAGE | ImageUsage::TRANSFER_SRC,
+            ..Default::default()
+        },
+        AllocationCreateInfo {
+            memory_type_filter: MemoryTypeFilter {
+                required_flags: MemoryPropertyFlags::DEVICE_LOCAL,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    )
+    .unwrap()
+}
@@ -0,0 +1,70 @@
+use crate::voxel_type::{Material, VoxelSchema, VoxelTypeDefinition, VoxelTypeEnum};
+use enum_iterator::Sequence;
+use num_derive::{FromPrimitive, ToPrimitive};
+
+/// Default block set for the sandbox
+#[derive(Debug, Sequence, Clone, Copy, FromPrimitive, ToPrimitive, PartialEq, Eq, Hash)]
+pub enum SandboxBlock {
+    Air,
+    Grass,
+    Dirt,
+    Stone,
+    Lamp,
+}
+
+/// Voxel data model of the sandbox, see `VoxelSchema`
+pub struct SandboxSchema;
+
+impl VoxelSchema for SandboxSchema {
+    type VoxelType = SandboxBlock;
+    type Id = u8;
+    type Attributes = ();
+}
+
+impl SandboxBlock {
+    /// Blocks that can be placed, in the order of the number keys that select them
+    pub const PLACEABLE: [SandboxBlock; 4] = [
+        SandboxBlock::Grass,
+        SandboxBlock::Dirt,
+        SandboxBlock::Stone,
+        SandboxBlock::Lamp,
+    ];
+}
+
+impl VoxelTypeEnum for SandboxBlock {
+    type VoxelAttributes = ();
+
+    fn def(&self) -> VoxelTypeDefinition<()> {
+        use SandboxBlock::*;
+        let material = match self {
+            Air => Material::default(),
+            Grass => Material {
+                color: [0.36, 0.6, 0.28],
+                ..Default::default()
+            },
+            Dirt => Material {
+                color: [0.44, 0.32, 0.25],
+                ..Default::default()
+            },
+            Stone => Material {
+                color: [0.53, 0.5, 0.42],
+                ..Default::default()
+            },
+            Lamp => Material {
+                color: [1., 0.9, 0.7],
+                emission_color: [1., 0.9, 0.7],
+                emission_strength: 2.0,
+                ..Default::default()
+            },
+        };
+        VoxelTypeDefinition {
+            material,
+            is_visible: *self != Air,
+            attributes: (),
+        }
+    }
+
+    fn empty() -> SandboxBlock {
+        SandboxBlock::Air
+    }
+}
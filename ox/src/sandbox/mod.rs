@@ -0,0 +1,615 @@
+//! A ready-made creative mode loop: flat terrain (or any `WorldGenerator` of `SandboxBlock`s), a default block
+//! set, and place/break bindings. It only uses public APIs, so it doubles as a starting point for a game of your
+//! own.
+//!
+//! Controls: move with the camera controller's keys and the mouse, left click to break the highlighted
+//! block, right click to place the selected block, 1-4 to select a block, and P to toggle photo mode. The
+//! crosshair is drawn by `raytrace.comp` itself.
+
+use crate::loader::{ChunkLoader, ChunkLoaderParams};
+use crate::photo::PhotoMode;
+use crate::ray::{cast_ray, CastRayResult, RayVoxelIntersect};
+use crate::renderer::binding::{Binding, BindingMap, BindingMapBuilder, StorageImage};
+use crate::renderer::component::camera::RendererCamera;
+use crate::renderer::component::highlight::{DataComponentSetWithHighlight, RendererHighlight};
+use crate::renderer::component::materials::MaterialList;
+use crate::renderer::component::ubo::{
+    DataComponentSetWithUbo, FogParams, PhotoSettings, RendererUBO, Ubo,
+};
+use crate::renderer::component::voxels::VoxelData;
+use crate::renderer::component::DataComponentSet;
+use crate::renderer::context::Context;
+use crate::renderer::swapchain::SwapchainPipelineParams;
+use crate::renderer::utils::standard_one_time_transfer_builder;
+use crate::renderer::{PassId, Renderer, UploadBudget};
+use crate::world::camera::collision::CameraCollision;
+use crate::world::camera::controller::winit::{
+    WinitCameraController, WinitCameraControllerSettings,
+};
+use crate::world::camera::Camera;
+use crate::world::generator::{load_generated, GeneratorLoadParams};
+use crate::world::mem_grid::utils::{ChunkSize, VoxelPosInLod};
+use crate::world::mem_grid::voxel::grid::{TakenChunkVoxelEditor, VoxelChunkLoadQueueItemData};
+use crate::world::mem_grid::voxel::{LodConfig, VoxelMemoryGrid};
+use crate::world::mem_grid::MemoryGrid;
+use crate::world::{TlcPos, World, WorldGenerator};
+use cgmath::Point3;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use vulkano::command_buffer::allocator::{
+    CommandBufferAllocator, StandardCommandBufferAllocator,
+    StandardCommandBufferAllocatorCreateInfo,
+};
+use vulkano::command_buffer::{AutoCommandBufferBuilder, PrimaryCommandBufferAbstract};
+use vulkano::descriptor_set::allocator::StandardDescriptorSetAllocator;
+use vulkano::descriptor_set::WriteDescriptorSet;
+use vulkano::device::Device;
+use vulkano::memory::allocator::MemoryAllocator;
+use vulkano::sync::GpuFuture;
+use winit::event::{DeviceEvent, ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
+use winit::event_loop::{ControlFlow, EventLoop};
+
+mod blocks;
+pub mod reference;
+mod terrain;
+pub use blocks::{SandboxBlock, SandboxSchema};
+pub use terrain::FlatTerrain;
+
+pub const CHUNK_SIZE: ChunkSize = ChunkSize::new(3);
+pub const N_LODS: usize = 5;
+
+mod raytrace_shader {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "../shaders/raytrace.comp",
+    }
+}
+
+crate::shader_interface! {
+    /// Bindings of the raytracing shader that aren't part of the LOD config. These need to match the
+    /// `layout(binding = ...)` declarations in the shader, which is checked when the pipeline is created.
+    struct RaytraceBindings {
+        0 => image image,
+        1 => storage materials,
+        2 => uniform camera,
+        3 => uniform ubo,
+        13 => storage highlight,
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SandboxSettings<G = FlatTerrain> {
+    /// Render distance in top level chunks, see `VoxelMemoryGrid::standard_config`. The shader is compiled with
+    /// `shaders/ox_defs.glsl`, which is generated for the default of 11.
+    pub render_distance: usize,
+    /// Generates every chunk, with voxel IDs of `SandboxBlock`
+    pub generator: G,
+    /// Global y position the camera starts just above, e.g. the generator's ground height
+    pub spawn_height: i64,
+    pub n_loader_threads: usize,
+    pub camera_speed: f32,
+    pub camera_sensitivity: f32,
+    /// Most chunk data to upload per frame. The lvl 1 and 2 LODs are uploaded in the background transfer, which
+    /// waits for frames with room left in this budget.
+    pub upload_budget: UploadBudget,
+}
+
+impl<G> SandboxSettings<G> {
+    /// Default settings for another generator
+    pub fn new(generator: G, spawn_height: i64) -> Self {
+        SandboxSettings {
+            render_distance: 11,
+            generator,
+            spawn_height,
+            n_loader_threads: 8,
+            camera_speed: 10.,
+            camera_sensitivity: 0.001,
+            upload_budget: UploadBudget {
+                max_bytes: Some(4 * 1024 * 1024),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl Default for SandboxSettings {
+    fn default() -> Self {
+        let terrain = FlatTerrain {
+            ground_height: 0,
+            dirt_depth: 3,
+        };
+        SandboxSettings::new(terrain, terrain.ground_height)
+    }
+}
+
+pub type SandboxWorld = World<VoxelMemoryGrid<N_LODS>>;
+pub type SandboxChunkLoader =
+    ChunkLoader<VoxelChunkLoadQueueItemData<N_LODS>, TakenChunkVoxelEditor<SandboxBlock, N_LODS>>;
+
+/// Parameters passed to each chunk load
+pub type SandboxLoadParams<G = FlatTerrain> = GeneratorLoadParams<G>;
+
+pub struct SandboxComponents {
+    pub voxel_data: VoxelData<N_LODS>,
+    pub material_list: MaterialList,
+    pub camera: RendererCamera,
+    pub ubo: RendererUBO,
+    pub highlight: RendererHighlight,
+}
+
+impl DataComponentSet for SandboxComponents {
+    fn bind(&self, descriptor_writes: &mut Vec<WriteDescriptorSet>) {
+        self.voxel_data.bind(descriptor_writes);
+        self.material_list.bind(descriptor_writes);
+        self.camera.bind(descriptor_writes);
+        self.ubo.bind(descriptor_writes);
+        self.highlight.bind(descriptor_writes);
+    }
+
+    fn record_repeated_buffer_transfer<L, A: CommandBufferAllocator>(
+        &self,
+        builder: &mut AutoCommandBufferBuilder<L, A>,
+    ) {
+        self.voxel_data.record_repeated_buffer_transfer(builder);
+        self.material_list.record_repeated_buffer_transfer(builder);
+        self.camera.record_repeated_buffer_transfer(builder);
+        self.ubo.record_repeated_buffer_transfer(builder);
+        self.highlight.record_repeated_buffer_transfer(builder);
+    }
+
+    fn record_buffer_transfer_jit<L, A: CommandBufferAllocator>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<L, A>,
+    ) {
+        self.voxel_data.record_buffer_transfer_jit(builder);
+        self.material_list.record_buffer_transfer_jit(builder);
+        self.camera.record_buffer_transfer_jit(builder);
+        self.ubo.record_buffer_transfer_jit(builder);
+        self.highlight.record_buffer_transfer_jit(builder);
+    }
+
+    fn pending_transfer_bytes(&self) -> u64 {
+        self.voxel_data.pending_transfer_bytes()
+            + self.material_list.pending_transfer_bytes()
+            + self.camera.pending_transfer_bytes()
+            + self.ubo.pending_transfer_bytes()
+            + self.highlight.pending_transfer_bytes()
+    }
+
+    fn record_background_transfer_jit<L, A: CommandBufferAllocator>(
+        &mut self,
+        builder: &mut AutoCommandBufferBuilder<L, A>,
+    ) {
+        self.voxel_data.record_background_transfer_jit(builder);
+    }
+
+    fn pending_background_transfer_bytes(&self) -> u64 {
+        self.voxel_data.pending_background_transfer_bytes()
+    }
+
+    fn late_latch(&mut self) {
+        self.camera.late_latch();
+    }
+
+    fn set_pass_enabled(&mut self, pass: PassId, enabled: bool) {
+        self.voxel_data.set_pass_enabled(pass, enabled);
+    }
+
+    fn take_dirty_bindings(&mut self, dirty: &mut Vec<u32>) {
+        self.voxel_data.take_dirty_bindings(dirty);
+    }
+}
+
+impl DataComponentSetWithHighlight for SandboxComponents {
+    fn highlight_mut(&mut self) -> &mut RendererHighlight {
+        &mut self.highlight
+    }
+}
+
+impl DataComponentSetWithUbo for SandboxComponents {
+    fn ubo_mut(&mut self) -> &mut RendererUBO {
+        &mut self.ubo
+    }
+}
+
+/// Create the renderer components for a world whose memory grid starts at `start_tlc` and upload the materials
+fn new_components(
+    context: &Context,
+    bindings: &RaytraceBindings,
+    voxel_data: VoxelData<N_LODS>,
+    start_tlc: TlcPos<i64>,
+) -> SandboxComponents {
+    let mut one_time_transfer_builder = standard_one_time_transfer_builder(context);
+
+    let components = SandboxComponents {
+        voxel_data,
+        material_list: MaterialList::from_schema::<SandboxSchema, _, _>(
+            Arc::clone(&context.memory_allocator) as Arc<dyn MemoryAllocator>,
+            bindings.materials,
+            &mut one_time_transfer_builder,
+        ),
+        camera: RendererCamera::new_late_latched(
+            bindings.camera,
+            Arc::clone(&context.memory_allocator) as Arc<dyn MemoryAllocator>,
+        ),
+        ubo: RendererUBO::new(
+            Ubo {
+                sun_dir: [0.39036, 0.78072, 0.48795],
+                start_tlc: [
+                    start_tlc.0.x as i32,
+                    start_tlc.0.y as i32,
+                    start_tlc.0.z as i32,
+                ],
+                time: 0,
+                ..Default::default()
+            },
+            Arc::clone(&context.memory_allocator) as Arc<dyn MemoryAllocator>,
+            bindings.ubo,
+        ),
+        highlight: RendererHighlight::new(
+            bindings.highlight,
+            Arc::clone(&context.memory_allocator) as Arc<dyn MemoryAllocator>,
+        ),
+    };
+
+    one_time_transfer_builder
+        .build()
+        .unwrap()
+        .execute(Arc::clone(&context.transfer_queue))
+        .unwrap()
+        .then_signal_fence_and_flush()
+        .unwrap()
+        .wait(None)
+        .unwrap();
+
+    components
+}
+
+/// Pipeline for the sandbox's raytracing shader. `albedo_only` draws only voxel colors, see
+/// `SwapchainPipelineParams::albedo_only`.
+fn pipeline_params(
+    device: &Arc<Device>,
+    image_binding: Binding<StorageImage>,
+    bindings: BindingMap,
+    albedo_only: bool,
+) -> SwapchainPipelineParams<StandardDescriptorSetAllocator, StandardCommandBufferAllocator> {
+    SwapchainPipelineParams {
+        workgroup_size: None,
+        image_binding,
+        shader: raytrace_shader::load(Arc::clone(device)).expect("Failed to load shader"),
+        bindings: Some(bindings),
+        albedo_only,
+        descriptor_set_allocator: StandardDescriptorSetAllocator::new(
+            Arc::clone(device),
+            Default::default(),
+        ),
+        command_buffer_allocator: StandardCommandBufferAllocator::new(
+            Arc::clone(device),
+            Default::default(),
+        ),
+    }
+}
+
+/// Open a window and run the sandbox until it is closed
+pub fn run<G: WorldGenerator + 'static>(settings: SandboxSettings<G>) -> ! {
+    let event_loop = EventLoop::new();
+    let (renderer_context, window) = Context::new(&event_loop);
+
+    let mut bindings = BindingMapBuilder::new();
+    let raytrace_bindings = RaytraceBindings::register(&mut bindings);
+
+    let config: LodConfig<N_LODS> =
+        LodConfig::standard(settings.render_distance, CHUNK_SIZE, &mut bindings);
+    let bindings = bindings.build();
+
+    // The shader includes definitions generated from the configuration above and is compiled with the Rust code,
+    // so refuse to run it if it was compiled with definitions for a different configuration. Debug builds
+    // regenerate the header first so that rebuilding fixes it.
+    let defs = config
+        .shader_defs()
+        .with_bindings(&bindings)
+        .with_material_struct();
+    if let Err(e) = defs.check_matches(include_str!("../../../shaders/ox_defs.glsl")) {
+        if cfg!(debug_assertions) {
+            defs.write_if_changed(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/../shaders/ox_defs.glsl"
+            ))
+            .expect("Failed to write shader definitions");
+            panic!("{}. They have been regenerated, rebuild to use them.", e);
+        }
+        panic!("{}", e);
+    }
+
+    let tlc_size = config.tlc_size() as i64;
+    let grid_size = config.render_area_size() as i64 + 1;
+
+    // Start with the camera (which starts in the center of the loaded area) just above the ground
+    let start_tlc = TlcPos(Point3::<i64> {
+        x: -grid_size / 2,
+        y: settings.spawn_height.div_euclid(tlc_size) - (grid_size / 2 - 1),
+        z: -grid_size / 2,
+    });
+
+    let (voxel_mem_grid, mut renderer_voxel_data_component) =
+        VoxelMemoryGrid::from_schema::<SandboxSchema>(
+            config,
+            Arc::clone(&renderer_context.memory_allocator) as Arc<dyn MemoryAllocator>,
+            start_tlc,
+        );
+    // Upload the lvl 1 and 2 LODs (after the three LOD 0 sublvls) in the background transfer
+    renderer_voxel_data_component.set_background_lods(3);
+    // Only a few chunks change per frame, so staging doesn't need to mirror the whole render area. The pools
+    // grow when more chunks finish loading in one frame.
+    renderer_voxel_data_component.pool_staging(
+        Arc::clone(&renderer_context.memory_allocator) as Arc<dyn MemoryAllocator>,
+        32,
+    );
+
+    let renderer_components = new_components(
+        &renderer_context,
+        &raytrace_bindings,
+        renderer_voxel_data_component,
+        start_tlc,
+    );
+
+    let dev = Arc::clone(&renderer_context.device);
+    let mut renderer = Renderer::new(
+        renderer_context,
+        pipeline_params(&dev, raytrace_bindings.image, bindings, false),
+        &window,
+        renderer_components,
+        StandardCommandBufferAllocator::new(
+            dev,
+            StandardCommandBufferAllocatorCreateInfo::default(),
+        ),
+    );
+
+    let voxel_md = voxel_mem_grid.metadata().clone();
+    renderer.set_fog(FogParams::for_render_area(&voxel_md));
+    renderer.set_upload_budget(settings.upload_budget);
+    let largest_chunk_lvl = voxel_md.largest_lod().lvl();
+    let mem_grid_size = voxel_mem_grid.size();
+    let mut world: SandboxWorld = World::new(
+        voxel_mem_grid,
+        Camera::new(voxel_md.tlc_size(), mem_grid_size),
+        voxel_md.tlc_size(),
+        16,
+    );
+    let dims = window.inner_size();
+    world.set_camera_res(dims.width, dims.height);
+    renderer.set_ui_scale(renderer.scale_factor() as f32);
+    let mut loader: SandboxChunkLoader = ChunkLoader::new(ChunkLoaderParams {
+        n_threads: settings.n_loader_threads,
+        rate_limit: None,
+    });
+    let load_params = SandboxLoadParams::new(settings.generator, voxel_md.clone());
+
+    world.queue_load_all(&mut loader);
+
+    // Event loop
+
+    let mut last_render_time = Instant::now();
+    let start_time = Instant::now();
+    // variables to track input since last frame
+    let mut window_resized = false;
+    let mut camera_controller =
+        WinitCameraController::with_settings(WinitCameraControllerSettings {
+            speed: settings.camera_speed,
+            sensitivity: settings.camera_sensitivity,
+            collision: Some(CameraCollision::default()),
+            ..Default::default()
+        });
+    // Free camera without collision that doesn't stream chunks in
+    let mut photo_mode = PhotoMode::new(
+        WinitCameraController::with_settings(WinitCameraControllerSettings {
+            speed: settings.camera_speed,
+            sensitivity: settings.camera_sensitivity,
+            ..Default::default()
+        }),
+        PhotoSettings::photo(),
+    );
+    let mut left_clicked = false;
+    let mut right_clicked = false;
+    let mut selected_block = SandboxBlock::PLACEABLE[0];
+
+    event_loop.run(move |event, _, control_flow| match event {
+        Event::DeviceEvent {
+            event: DeviceEvent::MouseMotion { delta },
+            ..
+        } => {
+            if photo_mode.is_active() {
+                photo_mode.controller_mut().process_mouse(delta.0, delta.1)
+            } else {
+                camera_controller.process_mouse(delta.0, delta.1)
+            }
+        }
+        Event::DeviceEvent {
+            event:
+                DeviceEvent::Button {
+                    button,
+                    state: ElementState::Pressed,
+                },
+            ..
+        } if !photo_mode.is_active() => match button {
+            1 => left_clicked = true,
+            3 => right_clicked = true,
+            _ => {}
+        },
+        Event::WindowEvent { event, .. } => match event {
+            WindowEvent::CloseRequested => {
+                *control_flow = ControlFlow::Exit;
+            }
+            WindowEvent::Resized(_) => {
+                window_resized = true;
+            }
+            WindowEvent::ScaleFactorChanged {
+                scale_factor,
+                new_inner_size,
+            } => {
+                renderer.scale_factor_changed(scale_factor, *new_inner_size);
+                renderer.set_ui_scale(scale_factor as f32);
+                world.set_camera_res(new_inner_size.width, new_inner_size.height);
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(key),
+                        state,
+                        ..
+                    },
+                ..
+            } => {
+                if key == VirtualKeyCode::P && state == ElementState::Pressed {
+                    if photo_mode.is_active() {
+                        photo_mode.exit(&mut world, &mut loader, &mut renderer);
+                    } else {
+                        photo_mode.enter(&mut world, &mut renderer);
+                    }
+                }
+                if photo_mode.is_active() {
+                    photo_mode.controller_mut().process_keyboard(key, state);
+                    return;
+                }
+
+                camera_controller.process_keyboard(key, state);
+
+                let slot = match key {
+                    VirtualKeyCode::Key1 => Some(0),
+                    VirtualKeyCode::Key2 => Some(1),
+                    VirtualKeyCode::Key3 => Some(2),
+                    VirtualKeyCode::Key4 => Some(3),
+                    _ => None,
+                };
+                if let Some(slot) = slot {
+                    selected_block = SandboxBlock::PLACEABLE[slot];
+                }
+            }
+            _ => (),
+        },
+        Event::MainEventsCleared => {
+            // Lock cursor in window
+            let _ = window.set_cursor_grab(winit::window::CursorGrabMode::None);
+            let _ = window.set_cursor_grab(winit::window::CursorGrabMode::Confined);
+            window.set_cursor_visible(false);
+
+            if window_resized {
+                let dims = window.inner_size();
+                renderer.window_resized(dims);
+                world.set_camera_res(dims.width, dims.height);
+                window_resized = false;
+            }
+
+            let frame_start = Instant::now();
+            let dt = frame_start - last_render_time;
+            last_render_time = frame_start;
+
+            if photo_mode.is_active() {
+                photo_mode.update(&mut world, dt, &mut loader, &mut renderer);
+            } else {
+                let camera_before = world.camera().to_gpu_repr();
+                world.move_camera_with_collision::<_, N_LODS, SandboxBlock, SandboxBlock>(
+                    &mut camera_controller,
+                    dt,
+                    &mut loader,
+                    CHUNK_SIZE,
+                    largest_chunk_lvl,
+                );
+                if world.camera().to_gpu_repr() != camera_before {
+                    renderer.request_redraw();
+                }
+            }
+
+            loader.sync::<_, _, SandboxBlock>(
+                &mut world,
+                &load_generated::<G, SandboxBlock, N_LODS>,
+                load_params.clone(),
+            );
+            world.stitch_seams::<SandboxBlock>(loader.returned_last());
+
+            // Find the voxel we're looking at and outline it
+            let camera = world.camera().clone();
+            let target = cast_ray::<N_LODS, SandboxBlock, _, SandboxBlock>(
+                &mut world,
+                camera.pos().to_owned(),
+                camera.viewport_center() - camera.pos().0,
+                CHUNK_SIZE,
+                largest_chunk_lvl,
+            );
+            renderer.set_highlighted_voxel(match &target {
+                Ok(CastRayResult::Hit(intersect)) => Some(intersect.into()),
+                _ => None,
+            });
+
+            if let Ok(CastRayResult::Hit(intersect)) = target {
+                let RayVoxelIntersect {
+                    pos, index, tlc, ..
+                } = intersect;
+                if left_clicked {
+                    if let Some(mut editor) = world.edit_chunk::<SandboxBlock>(tlc) {
+                        let _ = editor.set_voxel(pos, index, SandboxBlock::Air, &voxel_md);
+                    }
+                }
+                if right_clicked {
+                    let (new_tlc, new_pos) =
+                        intersect.adjacent_voxel(CHUNK_SIZE, largest_chunk_lvl);
+                    // Placing is only possible where LOD 0 is loaded
+                    if let Some(mut editor) = world.edit_chunk::<SandboxBlock>(new_tlc) {
+                        if editor.lods()[0].is_some() {
+                            let _ = editor.set_voxel(
+                                new_pos,
+                                VoxelPosInLod {
+                                    pos: new_pos.0,
+                                    lvl: 0,
+                                    sublvl: 0,
+                                    sublvl_y: 0,
+                                }
+                                .index(CHUNK_SIZE, largest_chunk_lvl),
+                                selected_block,
+                                &voxel_md,
+                            );
+                        }
+                    }
+                }
+            }
+            left_clicked = false;
+            right_clicked = false;
+
+            // Apply updates to staging buffers through the renderer
+            let voxel_updates = world.mem_grid.get_updates(renderer.upload_budget());
+            if voxel_updates.iter().any(|lod| !lod.is_empty()) {
+                renderer.request_redraw();
+            }
+            {
+                let render_editor = renderer.start_updating_staging_buffers();
+                let components = render_editor.component_set;
+                components
+                    .voxel_data
+                    .update_staging_buffers_and_prep_copy(voxel_updates);
+                components.camera.update_staging_buffer(world.camera());
+                let mut ubo = components.ubo.buffer_scheme.write_staging();
+                ubo.time = (frame_start.duration_since(start_time).as_micros() / 100) as u32;
+                let start_tlc = world.mem_grid.start_tlc();
+                ubo.start_tlc.copy_from_slice(&[
+                    start_tlc.0.x as i32,
+                    start_tlc.0.y as i32,
+                    start_tlc.0.z as i32,
+                ]);
+            }
+
+            renderer.draw_frame_if_changed();
+        }
+        Event::Suspended => renderer.suspend(),
+        Event::Resumed => renderer.resume(),
+        Event::LoopDestroyed => {
+            let report = world.shutdown(&mut loader, Duration::from_secs(5));
+            if report.left_missing > 0 {
+                println!("{:?}", report);
+            }
+            if let Err(e) = renderer.shutdown(Duration::from_secs(3)) {
+                println!("WARNING: {}", e);
+            }
+        }
+        _ => (),
+    })
+}
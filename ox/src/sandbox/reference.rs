@@ -0,0 +1,380 @@
+//! Fixed scenes rendered with the sandbox setup on a headless renderer, for checking locally that a refactor
+//! (e.g. to buffer packing, LOD math or the shader) didn't change what is drawn. Record the hashes of the
+//! scenes before the change and compare after it, e.g. with `examples/reference_scenes.rs`.
+//!
+//! Everything that could vary between runs is fixed: the scenes are generated from constant seeds, every chunk
+//! is loaded before the frame is drawn, and the camera and `Ubo::time` are the same each time. The output still
+//! depends on the GPU and driver, so hashes are only comparable on the machine that recorded them and aren't
+//! checked into the repository.
+
+use super::{
+    new_components, pipeline_params, FlatTerrain, RaytraceBindings, SandboxBlock,
+    SandboxChunkLoader, SandboxSchema, SandboxWorld, CHUNK_SIZE, N_LODS,
+};
+use crate::config::OxConfig;
+use crate::loader::{ChunkLoader, ChunkLoaderParams};
+use crate::reference::render_reference;
+use crate::renderer::binding::BindingMapBuilder;
+use crate::renderer::context::Context;
+use crate::renderer::Renderer;
+use crate::stress::{StressGenerator, StressPattern};
+use crate::timelapse::Frame;
+use crate::voxel_type::VoxelTypeEnum;
+use crate::world::camera::Camera;
+use crate::world::generator::{load_generated, GeneratorLoadParams};
+use crate::world::mem_grid::voxel::{LodConfig, VoxelMemoryGrid};
+use crate::world::mem_grid::MemoryGrid;
+use crate::world::storage::checksum;
+use crate::world::{TlcPos, World, WorldGenerator};
+use cgmath::{Point3, Rad};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use vulkano::command_buffer::allocator::{
+    StandardCommandBufferAllocator, StandardCommandBufferAllocatorCreateInfo,
+};
+use vulkano::memory::allocator::MemoryAllocator;
+use winit::dpi::PhysicalSize;
+
+/// Render distance of reference scenes, see `VoxelMemoryGrid::standard_config`
+const RENDER_DISTANCE: usize = 5;
+/// Give up if loading a scene's chunks takes longer than this
+const LOAD_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ReferenceScene {
+    /// The sandbox's default flat terrain, for basic LOD 0 lighting and the transitions to coarser LODs
+    Flat,
+    /// Sparse floating voxels at every LOD (`StressPattern::Noise`), for far LODs and ray traversal
+    SparseNoise,
+    /// `StressPattern::Checkerboard`, the worst case for bitmask and voxel ID packing
+    Checkerboard,
+}
+
+impl ReferenceScene {
+    pub const ALL: [ReferenceScene; 3] = [
+        ReferenceScene::Flat,
+        ReferenceScene::SparseNoise,
+        ReferenceScene::Checkerboard,
+    ];
+
+    /// Name used in `ReferenceHashes` files
+    pub fn id(&self) -> &'static str {
+        match self {
+            ReferenceScene::Flat => "flat",
+            ReferenceScene::SparseNoise => "sparse_noise",
+            ReferenceScene::Checkerboard => "checkerboard",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|scene| scene.id() == id)
+    }
+
+    /// Generator for the scene's chunks
+    fn generator(&self) -> Arc<dyn WorldGenerator> {
+        let pattern = match self {
+            ReferenceScene::Flat => return Arc::new(FLAT_TERRAIN),
+            ReferenceScene::SparseNoise => StressPattern::Noise {
+                density: 0.02,
+                seed: 2510,
+            },
+            ReferenceScene::Checkerboard => StressPattern::Checkerboard,
+        };
+        Arc::new(StressGenerator::new(
+            pattern,
+            CHUNK_SIZE,
+            SandboxBlock::Stone.id(),
+            SandboxBlock::Air.id(),
+        ))
+    }
+}
+
+const FLAT_TERRAIN: FlatTerrain = FlatTerrain {
+    ground_height: 0,
+    dirt_depth: 3,
+};
+
+/// Render `scene` at `extent` on a new headless renderer and return the frame. This loads the whole scene
+/// first, so it takes a few seconds.
+pub fn render_reference_scene(scene: ReferenceScene, extent: PhysicalSize<u32>) -> Frame {
+    render_scene(scene, extent, false, Rad(0.35), |_| ()).0
+}
+
+/// Render `scene` at `extent` with only voxel colors (see `SwapchainPipelineParams::albedo_only`) on a new
+/// headless renderer, and the same world and camera with `reference::render_reference` on the CPU. Returns the
+/// GPU and CPU frames, which should only differ at voxel edges (compare them with `reference::compare_frames`).
+/// The camera looks steeply down so every ray hits LOD 0 voxels, which is all the CPU renderer draws.
+pub fn render_albedo_scene(scene: ReferenceScene, extent: PhysicalSize<u32>) -> (Frame, Frame) {
+    render_scene(scene, extent, true, Rad(1.2), |world| {
+        let camera = world.camera().clone();
+        let largest_chunk_lvl = world.mem_grid.metadata().largest_lod().lvl();
+        render_reference::<N_LODS, SandboxBlock, _, SandboxBlock>(
+            world,
+            &camera,
+            extent.width,
+            extent.height,
+            CHUNK_SIZE,
+            largest_chunk_lvl,
+        )
+    })
+}
+
+/// Load all of `scene` and draw one frame of it on a new headless renderer, with the camera pitched by `pitch`.
+/// `after_draw` is called with the world before it is shut down.
+fn render_scene<T>(
+    scene: ReferenceScene,
+    extent: PhysicalSize<u32>,
+    albedo_only: bool,
+    pitch: Rad<f32>,
+    after_draw: impl FnOnce(&mut SandboxWorld) -> T,
+) -> (Frame, T) {
+    let context = Context::new_headless(&OxConfig::default());
+
+    let mut bindings = BindingMapBuilder::new();
+    let raytrace_bindings = RaytraceBindings::register(&mut bindings);
+    let config: LodConfig<N_LODS> = LodConfig::standard(RENDER_DISTANCE, CHUNK_SIZE, &mut bindings);
+    let tlc_size = config.tlc_size() as i64;
+    let grid_size = config.render_area_size() as i64 + 1;
+    // Same as the sandbox: the camera starts in the center TLC, just above the flat terrain's ground
+    let start_tlc = TlcPos(Point3::<i64> {
+        x: -grid_size / 2,
+        y: FLAT_TERRAIN.ground_height.div_euclid(tlc_size) - (grid_size / 2 - 1),
+        z: -grid_size / 2,
+    });
+
+    let (voxel_mem_grid, voxel_data) = VoxelMemoryGrid::from_schema::<SandboxSchema>(
+        config,
+        Arc::clone(&context.memory_allocator) as Arc<dyn MemoryAllocator>,
+        start_tlc,
+    );
+    let components = new_components(&context, &raytrace_bindings, voxel_data, start_tlc);
+
+    let dev = Arc::clone(&context.device);
+    let mut renderer = Renderer::new_offscreen(
+        context,
+        pipeline_params(&dev, raytrace_bindings.image, bindings.build(), albedo_only),
+        extent,
+        components,
+        StandardCommandBufferAllocator::new(
+            dev,
+            StandardCommandBufferAllocatorCreateInfo::default(),
+        ),
+    );
+
+    let metadata = voxel_mem_grid.metadata().clone();
+    let mem_grid_size = voxel_mem_grid.size();
+    let mut camera = Camera::new(metadata.tlc_size(), mem_grid_size);
+    camera.yaw = Rad(0.6);
+    camera.pitch = pitch;
+    let mut world: SandboxWorld = World::new(voxel_mem_grid, camera, metadata.tlc_size(), 16);
+    world.set_camera_res(extent.width, extent.height);
+
+    let mut loader: SandboxChunkLoader = ChunkLoader::new(ChunkLoaderParams {
+        n_threads: 8,
+        rate_limit: None,
+    });
+    let load_params = GeneratorLoadParams {
+        generator: scene.generator(),
+        metadata,
+    };
+    world.queue_load_all(&mut loader);
+    let load_start = Instant::now();
+    loop {
+        loader.sync::<_, _, SandboxBlock>(
+            &mut world,
+            &load_generated::<dyn WorldGenerator, SandboxBlock, N_LODS>,
+            load_params.clone(),
+        );
+        world.stitch_seams::<SandboxBlock>(loader.returned_last());
+        if loader.queue().is_empty() && loader.active_loading_threads() == 0 {
+            break;
+        }
+        assert!(
+            load_start.elapsed() < LOAD_TIMEOUT,
+            "Timed out loading reference scene {}",
+            scene.id()
+        );
+        std::thread::sleep(Duration::from_millis(1));
+    }
+
+    let voxel_updates = world.mem_grid.get_updates(renderer.upload_budget());
+    {
+        let render_editor = renderer.start_updating_staging_buffers();
+        let components = render_editor.component_set;
+        components
+            .voxel_data
+            .update_staging_buffers_and_prep_copy(voxel_updates);
+        components.camera.update_staging_buffer(world.camera());
+    }
+    renderer.draw_frame();
+    let rgba = renderer.read_frame();
+    let after = after_draw(&mut world);
+    let _ = world.shutdown(&mut loader, Duration::from_secs(5));
+    let _ = renderer.shutdown(Duration::from_secs(3));
+
+    let frame = Frame {
+        width: extent.width,
+        height: extent.height,
+        rgba,
+    };
+    (frame, after)
+}
+
+/// Hash of a frame's size and pixels, as stored in `ReferenceHashes`
+pub fn frame_hash(frame: &Frame) -> u32 {
+    let mut bytes = Vec::with_capacity(8 + frame.rgba.len());
+    bytes.extend(frame.width.to_le_bytes());
+    bytes.extend(frame.height.to_le_bytes());
+    bytes.extend(&frame.rgba);
+    checksum(&bytes)
+}
+
+/// Recorded `frame_hash`es of reference scenes by scene and size. Stored as a text file with one
+/// `<scene id> <width>x<height> <hash>` line per entry.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReferenceHashes {
+    hashes: BTreeMap<(String, u32, u32), u32>,
+}
+
+impl ReferenceHashes {
+    /// Read hashes from `path`. A missing file gives no hashes; malformed lines are skipped with a warning.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e),
+        };
+        let mut hashes = Self::default();
+        for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            match Self::parse_line(line) {
+                Some((key, hash)) => {
+                    hashes.hashes.insert(key, hash);
+                }
+                None => println!("WARNING: Skipping malformed reference hash line '{}'", line),
+            }
+        }
+        Ok(hashes)
+    }
+
+    fn parse_line(line: &str) -> Option<((String, u32, u32), u32)> {
+        let mut parts = line.split_whitespace();
+        let id = parts.next()?.to_string();
+        let (width, height) = parts.next()?.split_once('x')?;
+        let hash = u32::from_str_radix(parts.next()?, 16).ok()?;
+        Some(((id, width.parse().ok()?, height.parse().ok()?), hash))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let text: String = self
+            .hashes
+            .iter()
+            .map(|((id, width, height), hash)| {
+                format!("{} {}x{} {:08x}\n", id, width, height, hash)
+            })
+            .collect();
+        fs::write(path, text)
+    }
+
+    pub fn get(&self, scene: ReferenceScene, extent: PhysicalSize<u32>) -> Option<u32> {
+        self.hashes
+            .get(&(scene.id().to_string(), extent.width, extent.height))
+            .copied()
+    }
+
+    pub fn insert(&mut self, scene: ReferenceScene, extent: PhysicalSize<u32>, hash: u32) {
+        self.hashes
+            .insert((scene.id().to_string(), extent.width, extent.height), hash);
+    }
+}
+
+/// Result of `check_reference_scene`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReferenceCheck {
+    Match,
+    Mismatch {
+        expected: u32,
+        actual: u32,
+    },
+    /// There was no hash for the scene and size yet, so the new one was added
+    Recorded(u32),
+}
+
+/// Render `scene` at `extent` and compare its hash with the one in `hashes`, recording it if there is none.
+/// Also returns the frame so it can be saved or compared with `reference::compare_frames` on a mismatch.
+pub fn check_reference_scene(
+    hashes: &mut ReferenceHashes,
+    scene: ReferenceScene,
+    extent: PhysicalSize<u32>,
+) -> (ReferenceCheck, Frame) {
+    let frame = render_reference_scene(scene, extent);
+    let actual = frame_hash(&frame);
+    let check = match hashes.get(scene, extent) {
+        Some(expected) if expected == actual => ReferenceCheck::Match,
+        Some(expected) => ReferenceCheck::Mismatch { expected, actual },
+        None => {
+            hashes.insert(scene, extent, actual);
+            ReferenceCheck::Recorded(actual)
+        }
+    };
+    (check, frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reference::compare_frames;
+
+    #[test]
+    fn test_reference_hashes_round_trip() {
+        for scene in ReferenceScene::ALL {
+            assert_eq!(ReferenceScene::from_id(scene.id()), Some(scene));
+        }
+
+        let extent = PhysicalSize::new(64, 48);
+        let mut hashes = ReferenceHashes::default();
+        hashes.insert(ReferenceScene::Flat, extent, 0xdeadbeef);
+        hashes.insert(ReferenceScene::Checkerboard, extent, 7);
+
+        let path =
+            std::env::temp_dir().join(format!("ox_reference_hashes_{}.txt", std::process::id()));
+        hashes.save(&path).unwrap();
+        fs::write(
+            &path,
+            fs::read_to_string(&path).unwrap() + "not a hash line\n",
+        )
+        .unwrap();
+        let loaded = ReferenceHashes::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, hashes);
+        assert_eq!(loaded.get(ReferenceScene::Flat, extent), Some(0xdeadbeef));
+        assert_eq!(
+            loaded.get(ReferenceScene::Flat, PhysicalSize::new(64, 64)),
+            None
+        );
+        assert_eq!(
+            ReferenceHashes::load(&path).unwrap(),
+            ReferenceHashes::default()
+        );
+
+        let frame = |rgba| Frame {
+            width: 1,
+            height: 1,
+            rgba,
+        };
+        assert_ne!(
+            frame_hash(&frame(vec![0, 0, 0, 255])),
+            frame_hash(&frame(vec![0, 0, 1, 255]))
+        );
+    }
+    #[test]
+    #[ignore = "needs a GPU and takes a few seconds"]
+    fn test_albedo_matches_cpu_reference() {
+        let (gpu, cpu) = render_albedo_scene(ReferenceScene::Flat, PhysicalSize::new(96, 64));
+        let diff = compare_frames(&gpu, &cpu, 2);
+        assert!(diff.within(0.02), "{:?}", diff);
+    }
+}
@@ -0,0 +1,98 @@
+use super::blocks::SandboxBlock;
+use super::CHUNK_SIZE;
+use crate::world::mem_grid::utils::VoxelPosInLod;
+use crate::world::mem_grid::voxel::ChunkVoxels;
+use crate::world::{TlcPos, WorldGenerator, WorldSeed};
+use cgmath::Point3;
+
+/// Flat terrain: a layer of grass on top of `dirt_depth` voxels of dirt, with stone below that
+#[derive(Clone, Copy, Debug)]
+pub struct FlatTerrain {
+    /// Global y position of the first air voxel above the ground
+    pub ground_height: i64,
+    pub dirt_depth: i64,
+}
+
+impl FlatTerrain {
+    /// Block at global LOD 0 height `y`
+    pub fn block_at(&self, y: i64) -> SandboxBlock {
+        if y >= self.ground_height {
+            SandboxBlock::Air
+        } else if y == self.ground_height - 1 {
+            SandboxBlock::Grass
+        } else if y >= self.ground_height - 1 - self.dirt_depth {
+            SandboxBlock::Dirt
+        } else {
+            SandboxBlock::Stone
+        }
+    }
+}
+
+impl WorldGenerator for FlatTerrain {
+    fn name(&self) -> &str {
+        "ox::sandbox::flat"
+    }
+
+    /// Flat terrain is the same for every seed
+    fn seed(&self) -> WorldSeed {
+        WorldSeed::default()
+    }
+
+    fn generate(
+        &self,
+        chunk_pos: TlcPos<i64>,
+        lvl: u8,
+        sublvl: u8,
+        sublvl_y: u8,
+        voxel_ids_out: &mut ChunkVoxels,
+        tlc_size: usize,
+        largest_chunk_lvl: u8,
+    ) {
+        let voxel_size = CHUNK_SIZE.size().pow(lvl as u32) * 2usize.pow(sublvl as u32);
+        let voxel_size_y = CHUNK_SIZE.size().pow(lvl as u32) * 2usize.pow(sublvl_y as u32);
+        let grid_size = (tlc_size / voxel_size) as u32;
+        let grid_size_y = (tlc_size / voxel_size_y) as u32;
+        let chunk_start_y = chunk_pos.0.y * tlc_size as i64;
+
+        for y_grid in 0..grid_size_y {
+            // Use the top LOD 0 voxel so the surface is still grass in lower LODs
+            let y = chunk_start_y + (y_grid as i64 + 1) * voxel_size_y as i64 - 1;
+            let block = self.block_at(y) as u8;
+            for x_grid in 0..grid_size {
+                for z_grid in 0..grid_size {
+                    let idx = VoxelPosInLod {
+                        pos: Point3 {
+                            x: x_grid,
+                            y: y_grid,
+                            z: z_grid,
+                        },
+                        lvl,
+                        sublvl,
+                        sublvl_y,
+                    }
+                    .index(CHUNK_SIZE, largest_chunk_lvl);
+                    voxel_ids_out[idx] = block;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flat_terrain_layers() {
+        let terrain = FlatTerrain {
+            ground_height: 10,
+            dirt_depth: 3,
+        };
+        assert_eq!(terrain.block_at(10), SandboxBlock::Air);
+        assert_eq!(terrain.block_at(9), SandboxBlock::Grass);
+        assert_eq!(terrain.block_at(8), SandboxBlock::Dirt);
+        assert_eq!(terrain.block_at(6), SandboxBlock::Dirt);
+        assert_eq!(terrain.block_at(5), SandboxBlock::Stone);
+        assert_eq!(terrain.block_at(-100), SandboxBlock::Stone);
+    }
+}
@@ -0,0 +1,138 @@
+//! Cheap built-in chunk generator for benchmarks and loader tests, so performance work on chunk loading and
+//! transfers has a standard workload that doesn't depend on a game's terrain generation.
+
+use crate::noise::hash_cell;
+use crate::world::mem_grid::utils::{ChunkSize, VoxelPosInLod};
+use crate::world::mem_grid::voxel::ChunkVoxels;
+use crate::world::{TlcPos, WorldGenerator, WorldSeed};
+use cgmath::Point3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StressPattern {
+    /// Each voxel is solid with probability `density` (0..1), picked deterministically from its position
+    Noise { density: f32, seed: u32 },
+    /// Alternating solid and empty voxels along every axis, the worst case for LOD aggregation and bitmasks
+    Checkerboard,
+}
+
+/// Generates chunks of `solid` and `empty` voxels in a `StressPattern`. Positions are in voxels of the LOD being
+/// generated, so coarser LODs get the same pattern at a larger scale instead of a downsampled one.
+///
+/// ```ignore
+/// let gen = StressGenerator::new(StressPattern::Checkerboard, CHUNK_SIZE, Block::Dirt.id(), Block::Air.id());
+/// let params = GeneratorLoadParams::new(gen, voxel_md.clone());
+/// loader.sync(&mut world, &load_generated::<StressGenerator, Block, N>, params.clone());
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct StressGenerator {
+    pub pattern: StressPattern,
+    pub chunk_size: ChunkSize,
+    pub solid: u8,
+    pub empty: u8,
+}
+
+impl StressGenerator {
+    pub fn new(pattern: StressPattern, chunk_size: ChunkSize, solid: u8, empty: u8) -> Self {
+        StressGenerator {
+            pattern,
+            chunk_size,
+            solid,
+            empty,
+        }
+    }
+
+    fn is_solid(&self, x: i64, y: i64, z: i64) -> bool {
+        match self.pattern {
+            StressPattern::Noise { density, seed } => {
+                (hash_cell(seed, x, y, z) as f64 / u32::MAX as f64) < density as f64
+            }
+            StressPattern::Checkerboard => (x + y + z).rem_euclid(2) == 0,
+        }
+    }
+}
+
+impl WorldGenerator for StressGenerator {
+    fn name(&self) -> &str {
+        "ox::stress"
+    }
+
+    /// The noise pattern's seed. Checkerboards don't use one.
+    fn seed(&self) -> WorldSeed {
+        match self.pattern {
+            StressPattern::Noise { seed, .. } => WorldSeed(seed),
+            StressPattern::Checkerboard => WorldSeed::default(),
+        }
+    }
+
+    fn generate(
+        &self,
+        chunk_pos: TlcPos<i64>,
+        lvl: u8,
+        sublvl: u8,
+        sublvl_y: u8,
+        voxel_ids_out: &mut ChunkVoxels,
+        tlc_size: usize,
+        largest_chunk_lvl: u8,
+    ) {
+        let voxel_size = self.chunk_size.size().pow(lvl as u32) * 2usize.pow(sublvl as u32);
+        let voxel_size_y = self.chunk_size.size().pow(lvl as u32) * 2usize.pow(sublvl_y as u32);
+        let (grid_size, grid_size_y) = (tlc_size / voxel_size, tlc_size / voxel_size_y);
+        // Chunk start in units of this LOD's voxels
+        let start = Point3 {
+            x: chunk_pos.0.x * grid_size as i64,
+            y: chunk_pos.0.y * grid_size_y as i64,
+            z: chunk_pos.0.z * grid_size as i64,
+        };
+
+        for x in 0..grid_size as u32 {
+            for y in 0..grid_size_y as u32 {
+                for z in 0..grid_size as u32 {
+                    let idx = VoxelPosInLod {
+                        pos: Point3 { x, y, z },
+                        lvl,
+                        sublvl,
+                        sublvl_y,
+                    }
+                    .index(self.chunk_size, largest_chunk_lvl);
+                    voxel_ids_out[idx] = if self.is_solid(
+                        start.x + x as i64,
+                        start.y + y as i64,
+                        start.z + z as i64,
+                    ) {
+                        self.solid
+                    } else {
+                        self.empty
+                    };
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn count_solid(pattern: StressPattern, pos: TlcPos<i64>) -> usize {
+        let chunk_size = ChunkSize::new(2);
+        let mut voxels = ChunkVoxels::new_blank(16 * 16 * 16);
+        StressGenerator::new(pattern, chunk_size, 1, 0).generate(pos, 0, 0, 0, &mut voxels, 16, 2);
+        (0..16 * 16 * 16).filter(|&i| voxels[i] == 1).count()
+    }
+
+    #[test]
+    fn test_stress_patterns() {
+        let pos = TlcPos(Point3::new(3, -1, 0));
+        assert_eq!(
+            count_solid(StressPattern::Checkerboard, pos),
+            16 * 16 * 16 / 2
+        );
+
+        let noise = |density| StressPattern::Noise { density, seed: 5 };
+        assert_eq!(count_solid(noise(0.), pos), 0);
+        assert_eq!(count_solid(noise(1.), pos), 16 * 16 * 16);
+        let n = count_solid(noise(0.25), pos);
+        assert!((900..1150).contains(&n), "{}", n);
+        assert_eq!(n, count_solid(noise(0.25), pos));
+    }
+}
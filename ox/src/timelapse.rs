@@ -0,0 +1,270 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::PathBuf;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// A captured frame in 8-bit RGBA, row major starting at the top left.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub enum TimelapseOutput {
+    /// Write `frame_000000.png`, `frame_000001.png`, ... into this directory
+    PngSequence { dir: PathBuf },
+    /// Pipe frames into an `ffmpeg` process that encodes them into an MP4 at `path`. Requires `ffmpeg`
+    /// to be on the `PATH`.
+    #[cfg(feature = "timelapse-mp4")]
+    Mp4 { path: PathBuf, fps: u32 },
+}
+
+#[derive(Debug, Clone)]
+pub struct TimelapseParams {
+    /// Time between captured frames
+    pub interval: Duration,
+    pub output: TimelapseOutput,
+    /// Number of threads encoding frames. MP4 output always uses one thread since frames must be written in order.
+    pub n_threads: usize,
+    /// Maximum number of frames waiting to be encoded. Frames captured while this many are pending are dropped
+    /// (see `Timelapse::dropped_frames`) so memory usage stays bounded.
+    pub max_pending_frames: usize,
+}
+
+/// Records a frame every `interval` and encodes them on background threads, e.g. to watch a long build session
+/// or how chunk streaming behaves over time.
+///
+/// The renderer does not read frames back from the GPU itself, so frames are provided by the caller through the
+/// closure passed to `capture_if_due`, which is only called when a frame is actually due.
+pub struct Timelapse {
+    interval: Duration,
+    last_capture: Option<Instant>,
+    next_frame_idx: u64,
+    dropped_frames: usize,
+    sender: Option<SyncSender<(u64, Frame)>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Timelapse {
+    pub fn new(params: TimelapseParams) -> io::Result<Self> {
+        assert!(
+            params.max_pending_frames > 0,
+            "max_pending_frames must be > 0"
+        );
+        let (sender, receiver) = sync_channel::<(u64, Frame)>(params.max_pending_frames);
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        let workers = match params.output {
+            TimelapseOutput::PngSequence { dir } => {
+                fs::create_dir_all(&dir)?;
+                (0..params.n_threads.max(1))
+                    .map(|_| {
+                        let receiver = Arc::clone(&receiver);
+                        let dir = dir.clone();
+                        thread::spawn(move || {
+                            while let Some((idx, frame)) = recv_next(&receiver) {
+                                let path = dir.join(format!("frame_{:06}.png", idx));
+                                if let Err(e) = write_png(&path, &frame) {
+                                    println!(
+                                        "WARNING: failed to write timelapse frame {:?}: {}",
+                                        path, e
+                                    );
+                                }
+                            }
+                        })
+                    })
+                    .collect()
+            }
+            #[cfg(feature = "timelapse-mp4")]
+            TimelapseOutput::Mp4 { path, fps } => {
+                let receiver = Arc::clone(&receiver);
+                vec![thread::spawn(move || mp4::encode(&receiver, &path, fps))]
+            }
+        };
+
+        Ok(Timelapse {
+            interval: params.interval,
+            last_capture: None,
+            next_frame_idx: 0,
+            dropped_frames: 0,
+            sender: Some(sender),
+            workers,
+        })
+    }
+
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.last_capture
+            .map(|t| now.duration_since(t) >= self.interval)
+            .unwrap_or(true)
+    }
+
+    /// If a frame is due, call `capture` and queue its result for encoding. Call this once per frame.
+    /// Returns whether a frame was captured.
+    pub fn capture_if_due<F: FnOnce() -> Frame>(&mut self, now: Instant, capture: F) -> bool {
+        if !self.is_due(now) {
+            return false;
+        }
+        self.last_capture = Some(now);
+        self.submit(capture());
+        true
+    }
+
+    /// Queue a frame for encoding regardless of the interval. If too many frames are already pending, the frame
+    /// is dropped.
+    pub fn submit(&mut self, frame: Frame) {
+        debug_assert!(frame.rgba.len() == frame.width as usize * frame.height as usize * 4);
+        let sender = self.sender.as_ref().unwrap();
+        match sender.try_send((self.next_frame_idx, frame)) {
+            Ok(()) => self.next_frame_idx += 1,
+            Err(TrySendError::Full(_)) => self.dropped_frames += 1,
+            Err(TrySendError::Disconnected(_)) => {
+                panic!("Timelapse encoding threads exited early")
+            }
+        }
+    }
+
+    /// Number of frames queued for encoding so far
+    pub fn frames_captured(&self) -> u64 {
+        self.next_frame_idx
+    }
+
+    /// Number of frames dropped because too many were waiting to be encoded
+    pub fn dropped_frames(&self) -> usize {
+        self.dropped_frames
+    }
+
+    /// Wait for all pending frames to be encoded.
+    pub fn finish(mut self) {
+        self.join_workers();
+    }
+
+    fn join_workers(&mut self) {
+        // Dropping the sender makes the workers exit once the queue is empty
+        self.sender = None;
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                println!("WARNING: timelapse encoding thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for Timelapse {
+    fn drop(&mut self) {
+        self.join_workers();
+    }
+}
+
+fn recv_next(receiver: &Mutex<Receiver<(u64, Frame)>>) -> Option<(u64, Frame)> {
+    receiver.lock().unwrap().recv().ok()
+}
+
+fn write_png(path: &PathBuf, frame: &Frame) -> Result<(), png::EncodingError> {
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        frame.width,
+        frame.height,
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&frame.rgba)
+}
+
+#[cfg(feature = "timelapse-mp4")]
+mod mp4 {
+    use super::{recv_next, Frame};
+    use std::io::Write;
+    use std::path::Path;
+    use std::process::{Child, Command, Stdio};
+    use std::sync::mpsc::Receiver;
+    use std::sync::Mutex;
+
+    fn spawn_ffmpeg(path: &Path, fps: u32, width: u32, height: u32) -> std::io::Result<Child> {
+        Command::new("ffmpeg")
+            .args([
+                "-loglevel",
+                "error",
+                "-y",
+                "-f",
+                "rawvideo",
+                "-pix_fmt",
+                "rgba",
+            ])
+            .args(["-s", &format!("{}x{}", width, height)])
+            .args(["-r", &fps.to_string()])
+            .args(["-i", "-", "-pix_fmt", "yuv420p"])
+            .arg(path)
+            .stdin(Stdio::piped())
+            .spawn()
+    }
+
+    pub(super) fn encode(receiver: &Mutex<Receiver<(u64, Frame)>>, path: &Path, fps: u32) {
+        // ffmpeg is started with the first frame since that determines the video size
+        let mut ffmpeg: Option<(Child, u32, u32)> = None;
+        while let Some((_, frame)) = recv_next(receiver) {
+            if ffmpeg.is_none() {
+                match spawn_ffmpeg(path, fps, frame.width, frame.height) {
+                    Ok(child) => ffmpeg = Some((child, frame.width, frame.height)),
+                    Err(e) => {
+                        println!("WARNING: failed to start ffmpeg for timelapse: {}", e);
+                        return;
+                    }
+                }
+            }
+            let (child, width, height) = ffmpeg.as_mut().unwrap();
+            if (frame.width, frame.height) != (*width, *height) {
+                println!(
+                    "WARNING: skipping timelapse frame with different size than the first frame"
+                );
+                continue;
+            }
+            if let Err(e) = child.stdin.as_mut().unwrap().write_all(&frame.rgba) {
+                println!("WARNING: failed to write timelapse frame to ffmpeg: {}", e);
+                return;
+            }
+        }
+        if let Some((mut child, _, _)) = ffmpeg {
+            drop(child.stdin.take());
+            let _ = child.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_png_sequence() {
+        let dir = std::env::temp_dir().join(format!("ox_timelapse_test_{}", std::process::id()));
+        let mut timelapse = Timelapse::new(TimelapseParams {
+            interval: Duration::from_secs(1),
+            output: TimelapseOutput::PngSequence { dir: dir.clone() },
+            n_threads: 2,
+            max_pending_frames: 4,
+        })
+        .unwrap();
+
+        let frame = || Frame {
+            width: 2,
+            height: 2,
+            rgba: vec![255; 16],
+        };
+        let t0 = Instant::now();
+        assert!(timelapse.capture_if_due(t0, frame));
+        assert!(!timelapse.capture_if_due(t0 + Duration::from_millis(500), frame));
+        assert!(timelapse.capture_if_due(t0 + Duration::from_secs(1), frame));
+        assert_eq!(timelapse.frames_captured(), 2);
+        timelapse.finish();
+
+        assert!(dir.join("frame_000000.png").exists());
+        assert!(dir.join("frame_000001.png").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
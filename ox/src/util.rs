@@ -1,3 +1,5 @@
+use std::thread::{self, ThreadId};
+
 pub struct Zip<I: Iterator, const N: usize>([I; N]);
 
 impl<I: Iterator, const N: usize> Iterator for Zip<I, N> {
@@ -22,3 +24,51 @@ impl<I: Iterator, const N: usize> Iterator for Zip<I, N> {
 pub fn zip<I: Iterator, const N: usize>(iterators: [I; N]) -> Zip<I, N> {
     Zip(iterators)
 }
+
+/// The thread an object was created on. Objects like `World` and `Renderer` store this and check it
+/// in methods that must not be called from other threads, because the chunk loading state machine
+/// (which hands out raw chunk data to loader threads) is not safe to drive from multiple threads.
+/// Checks only happen when debug assertions are enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OwnerThread(ThreadId);
+
+impl OwnerThread {
+    pub fn current() -> Self {
+        OwnerThread(thread::current().id())
+    }
+
+    pub fn id(&self) -> ThreadId {
+        self.0
+    }
+
+    pub fn is_current(&self) -> bool {
+        thread::current().id() == self.0
+    }
+
+    /// Panics (in debug builds) if not called from the owner thread. `what` is used in the panic
+    /// message, e.g. "World::edit_chunk".
+    #[track_caller]
+    pub fn debug_assert_current(&self, what: &str) {
+        debug_assert!(
+            self.is_current(),
+            "{} must be called from the thread that created it (created on {:?}, called from {:?})",
+            what,
+            self.0,
+            thread::current().id(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_owner_thread() {
+        let owner = OwnerThread::current();
+        owner.debug_assert_current("test");
+
+        let res = thread::spawn(move || owner.debug_assert_current("test")).join();
+        assert!(res.is_err() == cfg!(debug_assertions));
+    }
+}
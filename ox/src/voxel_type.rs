@@ -1,4 +1,4 @@
-pub use crate::renderer::component::materials::Material;
+pub use crate::renderer::component::materials::{EmissiveVariant, Material};
 use enum_iterator::{all, Sequence};
 use num_traits::{FromPrimitive, ToPrimitive};
 use std::{fmt::Debug, hash::Hash};
@@ -9,6 +9,16 @@ pub struct VoxelTypeDefinition<A> {
     pub attributes: A,
 }
 
+/// What to do with voxel IDs that aren't a variant of the voxel type enum, e.g. in a world saved by a newer
+/// version of the game that added block types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownVoxelPolicy {
+    ReplaceWithEmpty,
+    ReplaceWith(u8),
+    /// Panic
+    Error,
+}
+
 /// Trait for enum of all block types that must be defined. The first value (repr = 0) is assumed to
 /// be an empty block (e.g. 'air').
 pub trait VoxelTypeEnum:
@@ -21,15 +31,90 @@ pub trait VoxelTypeEnum:
     fn empty() -> Self;
 
     fn materials() -> Vec<Material> {
-        // Check that ID fits in a u8.
-        // ENHANCEMENT: Make this generic to u16, etc if possible
-        assert!(Self::CARDINALITY <= 2usize.pow(8));
+        // Check that ID fits in a u8, see `VoxelId`
+        assert!(Self::CARDINALITY <= u8::CAPACITY);
         all::<Self>()
             .map(|voxel_def| voxel_def.def().material)
             .collect()
     }
 
+    /// Emission to use instead of the material's at certain times of day, in order of priority. Only used with
+    /// `ScheduledMaterialList`.
+    fn emissive_variants(&self) -> Vec<EmissiveVariant> {
+        vec![]
+    }
+
+    /// `emissive_variants` of every voxel type, in the same order as `materials`
+    fn all_emissive_variants() -> Vec<Vec<EmissiveVariant>> {
+        all::<Self>()
+            .map(|voxel_def| voxel_def.emissive_variants())
+            .collect()
+    }
+
     fn id(&self) -> u8 {
         self.to_u8().unwrap()
     }
+
+    /// How much this voxel type hides what is behind it, from 0 (not at all) to 1. Only used to pick coarse LOD
+    /// voxels with `OpacityWeighted` (see `Downsample`), so e.g. glass or leaves don't fill distant LODs as solidly
+    /// as stone. Defaults to the material's `alpha`.
+    fn opacity(&self) -> f32 {
+        self.def().material.alpha
+    }
+
+    /// How unknown voxel IDs are handled by `from_id` when voxel data is decoded and LODs are calculated.
+    fn unknown_voxel_policy() -> UnknownVoxelPolicy {
+        UnknownVoxelPolicy::Error
+    }
+
+    /// Voxel type for `id`, applying `unknown_voxel_policy` if it isn't a known ID. Returns `None` if the
+    /// policy is `Error` or replaces it with another unknown ID.
+    fn try_from_id(id: u8) -> Option<Self> {
+        Self::from_u8(id).or_else(|| match Self::unknown_voxel_policy() {
+            UnknownVoxelPolicy::ReplaceWithEmpty => Some(Self::empty()),
+            UnknownVoxelPolicy::ReplaceWith(replacement) => Self::from_u8(replacement),
+            UnknownVoxelPolicy::Error => None,
+        })
+    }
+
+    /// Same as `try_from_id`, but panics if `id` can't be decoded
+    fn from_id(id: u8) -> Self {
+        Self::try_from_id(id).unwrap_or_else(|| {
+            panic!(
+                "Unknown voxel ID {} (see VoxelTypeEnum::unknown_voxel_policy)",
+                id
+            )
+        })
+    }
+}
+
+/// Format voxel IDs are stored in, both on the CPU and in the voxel ID buffers the shaders read. Only `u8` is
+/// implemented: wider IDs would also need `ChunkVoxels` and the shaders' voxel ID buffers to change.
+pub trait VoxelId: Copy + Debug + Eq + Hash + Send + Sync + 'static {
+    /// Number of distinct IDs, i.e. the most voxel types that can be stored
+    const CAPACITY: usize;
+}
+
+impl VoxelId for u8 {
+    const CAPACITY: usize = 1 << u8::BITS;
+}
+
+/// The voxel data model of a game: its voxel types, the format their IDs are stored in and their attributes.
+/// Implement this once per game (on a marker type) and create the memory grid and materials with
+/// `VoxelMemoryGrid::from_schema` and `MaterialList::from_schema`, which check that the pieces fit together,
+/// instead of passing the voxel type enum around on its own. `SchemaChunkEditor` names the chunk editor.
+pub trait VoxelSchema {
+    type VoxelType: VoxelTypeEnum<VoxelAttributes = Self::Attributes>;
+    type Id: VoxelId;
+    type Attributes;
+
+    /// Panics if the voxel types don't fit in `Id`
+    fn validate() {
+        assert!(
+            Self::VoxelType::CARDINALITY <= Self::Id::CAPACITY,
+            "{} voxel types don't fit in voxel IDs with {} values",
+            Self::VoxelType::CARDINALITY,
+            Self::Id::CAPACITY,
+        );
+    }
 }
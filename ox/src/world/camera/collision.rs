@@ -0,0 +1,124 @@
+use crate::world::WorldScale;
+use cgmath::{Array, InnerSpace, Point3, Vector3};
+
+/// Keeps a camera out of visible voxels by treating it as a sphere. Enable it for a controller through
+/// `CameraController::collision` and move the camera with `World::move_camera_with_collision`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CameraCollision {
+    /// Radius of the sphere in LOD 0 voxels. Should be larger than the camera's `viewport_dist` so the
+    /// viewport can't end up inside a voxel.
+    pub radius: f32,
+}
+
+impl Default for CameraCollision {
+    fn default() -> Self {
+        CameraCollision { radius: 0.3 }
+    }
+}
+
+impl CameraCollision {
+    /// This collision with `radius` given in meters converted to LOD 0 voxels
+    pub fn in_voxels(self, scale: WorldScale) -> Self {
+        CameraCollision {
+            radius: scale.to_voxels(self.radius),
+        }
+    }
+}
+
+/// Move a sphere of `radius` from `from` towards `to`, stopping on each axis where it would overlap a voxel
+/// for which `is_solid` returns true, so that it slides along walls instead of stopping completely. The
+/// movement is split into steps of at most half the radius so fast movement can't skip over thin walls.
+/// If the sphere already overlaps a solid voxel at `from` (e.g. one was placed on top of it), it moves
+/// freely so it can get out. Returns the position the sphere ends up at.
+pub fn constrain_movement(
+    from: Point3<f32>,
+    to: Point3<f32>,
+    radius: f32,
+    mut is_solid: impl FnMut(Point3<i64>) -> bool,
+) -> Point3<f32> {
+    assert!(radius > 0., "Camera collision radius must be positive");
+    if sphere_overlaps_solid(from, radius, &mut is_solid) {
+        return to;
+    }
+
+    let delta = to - from;
+    let n_steps = (delta.magnitude() / (radius / 2.)).ceil().max(1.) as u32;
+    let step = delta / n_steps as f32;
+    let mut pos = from;
+    for _ in 0..n_steps {
+        for ax in 0..3 {
+            let mut next = pos;
+            next[ax] += step[ax];
+            if !sphere_overlaps_solid(next, radius, &mut is_solid) {
+                pos = next;
+            }
+        }
+    }
+    pos
+}
+
+fn sphere_overlaps_solid(
+    center: Point3<f32>,
+    radius: f32,
+    is_solid: &mut impl FnMut(Point3<i64>) -> bool,
+) -> bool {
+    let min = (center - Vector3::from_value(radius)).map(|a| a.floor() as i64);
+    let max = (center + Vector3::from_value(radius)).map(|a| a.floor() as i64);
+    for x in min.x..=max.x {
+        for y in min.y..=max.y {
+            for z in min.z..=max.z {
+                // Closest point in the voxel to the center of the sphere
+                let closest = Point3 {
+                    x: center.x.clamp(x as f32, (x + 1) as f32),
+                    y: center.y.clamp(y as f32, (y + 1) as f32),
+                    z: center.z.clamp(z as f32, (z + 1) as f32),
+                };
+                if (closest - center).magnitude2() < radius * radius && is_solid(Point3 { x, y, z })
+                {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constrain_movement() {
+        // Wall at x = 5
+        let wall = |p: Point3<i64>| p.x == 5;
+
+        // Stops before the wall but keeps moving along it
+        let pos = constrain_movement(
+            Point3::new(3.5, 0.5, 0.5),
+            Point3::new(7.5, 2.5, 0.5),
+            0.3,
+            wall,
+        );
+        assert!((pos.x - 4.7).abs() < 0.2, "{:?}", pos);
+        assert!((pos.y - 2.5).abs() < 1e-4, "{:?}", pos);
+
+        // Can't skip over the wall in a single large step
+        let pos = constrain_movement(
+            Point3::new(4.5, 0.5, 0.5),
+            Point3::new(100., 0.5, 0.5),
+            0.3,
+            wall,
+        );
+        assert!(pos.x < 5., "{:?}", pos);
+
+        // Moves freely when already inside a solid voxel
+        let pos = constrain_movement(
+            Point3::new(5.5, 0.5, 0.5),
+            Point3::new(7.5, 0.5, 0.5),
+            0.3,
+            wall,
+        );
+        assert_eq!(pos, Point3::new(7.5, 0.5, 0.5));
+    }
+}
@@ -0,0 +1,190 @@
+use crate::world::camera::controller::{clamp_pitch, CameraController};
+use crate::world::camera::Camera;
+use cgmath::{Rad, Vector3, Zero};
+use std::f32::consts::PI;
+use std::time::Duration;
+
+/// Camera pose at a point in a `CinematicController`'s timeline
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CameraKeyframe {
+    /// Seconds since the start of the cinematic
+    pub time: f32,
+    /// Position in LOD 0 voxels relative to where the camera was when the cinematic started. Camera positions
+    /// are relative to the memory grid, which moves as the world shifts, so absolute positions wouldn't stay
+    /// valid.
+    pub offset: [f32; 3],
+    pub yaw: f32,   // radians
+    pub pitch: f32, // radians
+}
+
+/// Moves the camera smoothly through a list of keyframes, e.g. for trailers or to reproduce a camera path
+/// while debugging. Positions are interpolated with a Catmull-Rom spline so the path passes through every
+/// keyframe without sharp turns, and angles are interpolated linearly (yaw the short way around).
+#[derive(Debug, Clone)]
+pub struct CinematicController {
+    keyframes: Vec<CameraKeyframe>,
+    time: f32,
+    // Offset the camera has been moved by so far, so only the difference is applied each frame
+    applied_offset: Vector3<f32>,
+    /// Start over at the first keyframe after the last one instead of stopping there
+    pub looping: bool,
+}
+
+impl CinematicController {
+    /// `keyframes` must not be empty and must be ordered by time
+    pub fn new(keyframes: Vec<CameraKeyframe>) -> Self {
+        assert!(
+            !keyframes.is_empty(),
+            "Cinematic needs at least one keyframe"
+        );
+        assert!(
+            keyframes.windows(2).all(|w| w[0].time <= w[1].time),
+            "Cinematic keyframes must be ordered by time"
+        );
+        CinematicController {
+            keyframes,
+            time: 0.,
+            applied_offset: Vector3::zero(),
+            looping: false,
+        }
+    }
+
+    pub fn keyframes(&self) -> &[CameraKeyframe] {
+        &self.keyframes
+    }
+
+    /// Seconds since the start of the cinematic
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// Time of the last keyframe
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().unwrap().time
+    }
+
+    pub fn is_finished(&self) -> bool {
+        !self.looping && self.time >= self.duration()
+    }
+
+    /// Jump to `time`. The camera moves there on the next `apply`.
+    pub fn seek(&mut self, time: f32) {
+        self.time = time.clamp(0., self.duration());
+    }
+
+    /// Interpolated pose at `time`
+    pub fn pose_at(&self, time: f32) -> CameraKeyframe {
+        let kfs = &self.keyframes;
+        if time <= kfs[0].time {
+            return CameraKeyframe { time, ..kfs[0] };
+        }
+        let i = kfs.iter().rposition(|kf| kf.time <= time).unwrap();
+        if i == kfs.len() - 1 {
+            return CameraKeyframe { time, ..kfs[i] };
+        }
+
+        let (a, b) = (&kfs[i], &kfs[i + 1]);
+        let u = if b.time > a.time {
+            (time - a.time) / (b.time - a.time)
+        } else {
+            1.
+        };
+        let p = |j: usize| Vector3::from(kfs[j].offset);
+        let offset = catmull_rom(
+            p(i.saturating_sub(1)),
+            p(i),
+            p(i + 1),
+            p((i + 2).min(kfs.len() - 1)),
+            u,
+        );
+        CameraKeyframe {
+            time,
+            offset: offset.into(),
+            yaw: a.yaw + wrap_angle(b.yaw - a.yaw) * u,
+            pitch: a.pitch + (b.pitch - a.pitch) * u,
+        }
+    }
+}
+
+impl CameraController for CinematicController {
+    fn apply(&mut self, camera: &mut Camera, dt: Duration) {
+        let duration = self.duration();
+        self.time += dt.as_secs_f32();
+        if self.time > duration {
+            self.time = if self.looping && duration > 0. {
+                self.time % duration
+            } else {
+                duration
+            };
+        }
+
+        let pose = self.pose_at(self.time);
+        let offset = Vector3::from(pose.offset);
+        camera.position.0 += offset - self.applied_offset;
+        self.applied_offset = offset;
+        camera.yaw = Rad(pose.yaw);
+        camera.pitch = clamp_pitch(Rad(pose.pitch));
+    }
+}
+
+/// Uniform Catmull-Rom spline through `p1` and `p2` at `u` in [0, 1]
+fn catmull_rom(
+    p0: Vector3<f32>,
+    p1: Vector3<f32>,
+    p2: Vector3<f32>,
+    p3: Vector3<f32>,
+    u: f32,
+) -> Vector3<f32> {
+    let (u2, u3) = (u * u, u * u * u);
+    (p1 * 2.
+        + (p2 - p0) * u
+        + (p0 * 2. - p1 * 5. + p2 * 4. - p3) * u2
+        + (p1 * 3. - p0 - p2 * 3. + p3) * u3)
+        * 0.5
+}
+
+/// `angle` wrapped to [-PI, PI]
+fn wrap_angle(angle: f32) -> f32 {
+    (angle + PI).rem_euclid(2. * PI) - PI
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframe(time: f32, x: f32, yaw: f32) -> CameraKeyframe {
+        CameraKeyframe {
+            time,
+            offset: [x, 0., 0.],
+            yaw,
+            pitch: 0.,
+        }
+    }
+
+    #[test]
+    fn test_cinematic_passes_through_keyframes() {
+        let mut controller = CinematicController::new(vec![
+            keyframe(0., 0., 0.),
+            keyframe(1., 4., 3.),
+            keyframe(3., 6., -3.),
+        ]);
+        for kf in controller.keyframes().to_vec() {
+            let pose = controller.pose_at(kf.time);
+            assert!((pose.offset[0] - kf.offset[0]).abs() < 1e-5, "{:?}", pose);
+        }
+        // Yaw goes the short way around from 3 to -3 radians
+        let yaw = controller.pose_at(2.).yaw;
+        assert!(yaw > 3., "{}", yaw);
+
+        let mut camera = Camera::new(8, 4);
+        let start = camera.position.0;
+        controller.apply(&mut camera, Duration::from_secs(1));
+        assert!((camera.position.0.x - start.x - 4.).abs() < 1e-5);
+        // The camera moves relative to where it is, e.g. after the world shifted
+        camera.position.0.x -= 8.;
+        controller.apply(&mut camera, Duration::from_secs(5));
+        assert!((camera.position.0.x - start.x - 6. + 8.).abs() < 1e-4);
+        assert!(controller.is_finished());
+    }
+}
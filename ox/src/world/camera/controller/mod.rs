@@ -1,8 +1,25 @@
+use std::f32::consts::PI;
 use std::time::Duration;
+use cgmath::Rad;
+use super::collision::CameraCollision;
 use super::Camera;
+pub mod cinematic;
+pub mod smoothing;
 pub mod winit;
 
+const SAFE_FRAC_PI_2: f32 = PI / 2.0 - 0.0001;
+
+/// Keep the camera's pitch from reaching straight up/down, where yaw stops being meaningful
+pub fn clamp_pitch(pitch: Rad<f32>) -> Rad<f32> {
+    Rad(pitch.0.clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2))
+}
 
 pub trait CameraController {
     fn apply(&mut self, camera: &mut Camera, dt: Duration);
+
+    /// Collision to use when moving the camera with `World::move_camera_with_collision`. `None` (the default)
+    /// lets the camera fly through voxels.
+    fn collision(&self) -> Option<CameraCollision> {
+        None
+    }
 }
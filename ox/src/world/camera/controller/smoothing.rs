@@ -0,0 +1,125 @@
+//! Framerate independent smoothing and acceleration for camera controllers, so that controllers driven by
+//! different inputs (mouse, gamepad, replays, scripts) move the camera the same way at any framerate.
+
+use crate::world::WorldScale;
+use cgmath::{InnerSpace, Vector3, Zero};
+use std::ops::{Add, Mul, Sub};
+
+/// Fraction of the remaining distance to a target to cover in a step of `dt` seconds, such that half of the
+/// distance is covered every `half_life` seconds regardless of how the time is split into steps. A
+/// `half_life` of 0 (or less) covers the whole distance immediately.
+pub fn smoothing_factor(half_life: f32, dt: f32) -> f32 {
+    if half_life <= 0. {
+        1.
+    } else {
+        1. - 0.5f32.powf(dt / half_life)
+    }
+}
+
+/// Value that exponentially approaches a target, e.g. a look direction following mouse or stick input
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Smoothed<V> {
+    pub value: V,
+    pub target: V,
+    /// Seconds to cover half of the distance to the target, see `smoothing_factor`
+    pub half_life: f32,
+}
+
+impl<V: Copy + Add<Output = V> + Sub<Output = V> + Mul<f32, Output = V>> Smoothed<V> {
+    pub fn new(value: V, half_life: f32) -> Self {
+        Smoothed {
+            value,
+            target: value,
+            half_life,
+        }
+    }
+
+    /// Move towards the target by `dt` seconds and return the new value
+    pub fn update(&mut self, dt: f32) -> V {
+        self.value = self.value + (self.target - self.value) * smoothing_factor(self.half_life, dt);
+        self.value
+    }
+}
+
+/// Velocity that speeds up towards a desired direction at a constant rate and slows down to a stop when
+/// there is no input, instead of changing instantly
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AccelerationCurve {
+    /// Voxels per second
+    pub max_speed: f32,
+    /// Voxels per second per second while there is input
+    pub acceleration: f32,
+    /// Voxels per second per second while there is no input
+    pub deceleration: f32,
+}
+
+impl AccelerationCurve {
+    /// This curve with speeds and accelerations given in meters converted to LOD 0 voxels
+    pub fn in_voxels(self, scale: WorldScale) -> Self {
+        AccelerationCurve {
+            max_speed: scale.to_voxels(self.max_speed),
+            acceleration: scale.to_voxels(self.acceleration),
+            deceleration: scale.to_voxels(self.deceleration),
+        }
+    }
+
+    /// Velocity after `dt` seconds starting from `velocity`. `input` is the desired direction scaled by how
+    /// much of `max_speed` to reach (e.g. a stick's deflection); it is clamped to a length of 1.
+    pub fn step(&self, velocity: Vector3<f32>, input: Vector3<f32>, dt: f32) -> Vector3<f32> {
+        let input = if input.magnitude2() > 1. {
+            input.normalize()
+        } else {
+            input
+        };
+        let (target, rate) = if input.is_zero() {
+            (Vector3::zero(), self.deceleration)
+        } else {
+            (input * self.max_speed, self.acceleration)
+        };
+        let diff = target - velocity;
+        let max_change = rate * dt;
+        if diff.magnitude() <= max_change {
+            target
+        } else {
+            velocity + diff.normalize() * max_change
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_smoothing_is_framerate_independent() {
+        let mut coarse = Smoothed::new(0f32, 0.1);
+        let mut fine = coarse;
+        coarse.target = 1.;
+        fine.target = 1.;
+        coarse.update(0.1);
+        for _ in 0..10 {
+            fine.update(0.01);
+        }
+        assert!((coarse.value - 0.5).abs() < 1e-5);
+        assert!((fine.value - 0.5).abs() < 1e-5);
+        assert_eq!(smoothing_factor(0., 0.01), 1.);
+    }
+
+    #[test]
+    fn test_acceleration_curve() {
+        let curve = AccelerationCurve {
+            max_speed: 10.,
+            acceleration: 20.,
+            deceleration: 40.,
+        };
+        let forward = Vector3::new(1., 0., 0.);
+        let v = curve.step(Vector3::zero(), forward, 0.25);
+        assert_eq!(v, Vector3::new(5., 0., 0.));
+        let v = curve.step(v, forward * 2., 1.);
+        assert_eq!(v, Vector3::new(10., 0., 0.));
+        let v = curve.step(v, Vector3::zero(), 0.125);
+        assert_eq!(v, Vector3::new(5., 0., 0.));
+        assert_eq!(curve.step(v, Vector3::zero(), 1.), Vector3::zero());
+    }
+}
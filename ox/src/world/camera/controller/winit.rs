@@ -1,10 +1,124 @@
-use crate::world::camera::controller::CameraController;
+use crate::world::camera::collision::CameraCollision;
+use crate::world::camera::controller::smoothing::smoothing_factor;
+use crate::world::camera::controller::{clamp_pitch, CameraController};
 use crate::world::camera::Camera;
+use crate::world::WorldScale;
 use cgmath::{InnerSpace, Rad, Vector3};
-use std::f32::consts::PI;
 use std::time::Duration;
 use winit::event::{ElementState, VirtualKeyCode};
 
+/// Whether a modifier action (sprint/crouch) is active while its key is held or is toggled on each press.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ButtonMode {
+    Hold,
+    Toggle,
+}
+
+/// Keys bound to each camera action. Each action can have multiple keys bound to it.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KeyMap {
+    pub forward: Vec<VirtualKeyCode>,
+    pub backward: Vec<VirtualKeyCode>,
+    pub left: Vec<VirtualKeyCode>,
+    pub right: Vec<VirtualKeyCode>,
+    pub up: Vec<VirtualKeyCode>,
+    pub down: Vec<VirtualKeyCode>,
+    pub sprint: Vec<VirtualKeyCode>,
+    pub crouch: Vec<VirtualKeyCode>,
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        use VirtualKeyCode::*;
+        KeyMap {
+            forward: vec![W, Up],
+            backward: vec![S, Down],
+            left: vec![A, Left],
+            right: vec![D, Right],
+            up: vec![Space],
+            down: vec![LShift],
+            sprint: vec![LControl],
+            crouch: vec![C],
+        }
+    }
+}
+
+/// User-facing settings for `WinitCameraController`. These can be changed at any time through
+/// `WinitCameraController::settings_mut`, e.g. from an options menu.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WinitCameraControllerSettings {
+    /// LOD 0 voxels per second, see `in_voxels` to give it in meters per second
+    pub speed: f32,
+    pub sensitivity: f32,
+    pub invert_x: bool,
+    pub invert_y: bool,
+    pub key_map: KeyMap,
+    pub sprint_mode: ButtonMode,
+    pub crouch_mode: ButtonMode,
+    pub sprint_multiplier: f32, // speed multiplier while sprinting
+    pub crouch_multiplier: f32, // speed multiplier while crouching
+    /// Keep the camera from clipping into voxels (see `World::move_camera_with_collision`)
+    pub collision: Option<CameraCollision>,
+    /// Seconds for the camera to turn half of the way to where the mouse points it, see
+    /// `smoothing::smoothing_factor`. 0 turns immediately.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub look_half_life: f32,
+}
+
+impl Default for WinitCameraControllerSettings {
+    fn default() -> Self {
+        WinitCameraControllerSettings {
+            speed: 1.0,
+            sensitivity: 0.005,
+            invert_x: false,
+            invert_y: false,
+            key_map: KeyMap::default(),
+            sprint_mode: ButtonMode::Hold,
+            crouch_mode: ButtonMode::Hold,
+            sprint_multiplier: 2.0,
+            crouch_multiplier: 0.25,
+            collision: None,
+            look_half_life: 0.,
+        }
+    }
+}
+
+impl WinitCameraControllerSettings {
+    /// These settings with `speed` (and the collision radius) given in meters converted to LOD 0 voxels
+    pub fn in_voxels(self, scale: WorldScale) -> Self {
+        WinitCameraControllerSettings {
+            speed: scale.to_voxels(self.speed),
+            collision: self.collision.map(|collision| collision.in_voxels(scale)),
+            ..self
+        }
+    }
+}
+
+/// State of a sprint/crouch modifier
+#[derive(Debug, Default)]
+struct ModifierState {
+    active: bool,
+    key_down: bool, // used to ignore key repeat events when toggling
+}
+
+impl ModifierState {
+    fn process(&mut self, mode: ButtonMode, state: ElementState) {
+        let pressed = state == ElementState::Pressed;
+        match mode {
+            ButtonMode::Hold => self.active = pressed,
+            ButtonMode::Toggle => {
+                if pressed && !self.key_down {
+                    self.active = !self.active;
+                }
+            }
+        }
+        self.key_down = pressed;
+    }
+}
+
 #[derive(Debug)]
 pub struct WinitCameraController {
     amount_left: f32,
@@ -15,47 +129,60 @@ pub struct WinitCameraController {
     amount_down: f32,
     rotate_horizontal: f32,
     rotate_vertical: f32,
-    speed: f32,
-    sensitivity: f32,
+    sprint: ModifierState,
+    crouch: ModifierState,
+    settings: WinitCameraControllerSettings,
 }
 
 // loosely based on  https://sotrh.github.io/learn-wgpu/intermediate/tutorial12-camera/#cleaning-up-lib-rs
 
-const SAFE_FRAC_PI_2: f32 = PI / 2.0 - 0.0001;
-
 impl CameraController for WinitCameraController {
     fn apply(&mut self, camera: &mut Camera, dt: Duration) {
         let dt = dt.as_secs_f32();
+        let speed = self.speed();
 
         // Move forward/backward and left/right
         let (yaw_sin, yaw_cos) = camera.yaw.0.sin_cos();
         let forward = Vector3::new(yaw_cos, 0.0, -yaw_sin).normalize();
         let right = Vector3::new(-yaw_sin, 0.0, -yaw_cos).normalize();
-        camera.position.0 +=
-            forward * (self.amount_forward - self.amount_backward) * self.speed * dt;
-        camera.position.0 += right * (self.amount_right - self.amount_left) * self.speed * dt;
+        camera.position.0 += forward * (self.amount_forward - self.amount_backward) * speed * dt;
+        camera.position.0 += right * (self.amount_right - self.amount_left) * speed * dt;
 
         // Move up/down. Since we don't use roll, we can just
         // modify the y coordinate directly.
-        camera.position.0.y += (self.amount_up - self.amount_down) * self.speed * dt;
+        camera.position.0.y += (self.amount_up - self.amount_down) * speed * dt;
 
-        // Rotate
-        camera.yaw += Rad(self.rotate_horizontal) * self.sensitivity;
-        camera.pitch += Rad(self.rotate_vertical) * self.sensitivity;
+        // Rotate by the smoothed part of the mouse movement, keeping the rest for the next frames
+        let smoothing = smoothing_factor(self.settings.look_half_life, dt);
+        let (rotate_horizontal, rotate_vertical) = (
+            self.rotate_horizontal * smoothing,
+            self.rotate_vertical * smoothing,
+        );
+        let invert_x = if self.settings.invert_x { -1.0 } else { 1.0 };
+        let invert_y = if self.settings.invert_y { -1.0 } else { 1.0 };
+        camera.yaw += Rad(rotate_horizontal) * self.settings.sensitivity * invert_x;
+        camera.pitch += Rad(rotate_vertical) * self.settings.sensitivity * invert_y;
 
-        self.rotate_horizontal = 0.0;
-        self.rotate_vertical = 0.0;
+        self.rotate_horizontal -= rotate_horizontal;
+        self.rotate_vertical -= rotate_vertical;
 
-        // Keep the camera's angle from going too high/low.
-        if camera.pitch < -Rad(SAFE_FRAC_PI_2) {
-            camera.pitch = -Rad(SAFE_FRAC_PI_2);
-        } else if camera.pitch > Rad(SAFE_FRAC_PI_2) {
-            camera.pitch = Rad(SAFE_FRAC_PI_2);
-        }
+        camera.pitch = clamp_pitch(camera.pitch);
+    }
+
+    fn collision(&self) -> Option<CameraCollision> {
+        self.settings.collision
     }
 }
 impl WinitCameraController {
     pub fn new(speed: f32, sensitivity: f32) -> Self {
+        Self::with_settings(WinitCameraControllerSettings {
+            speed,
+            sensitivity,
+            ..Default::default()
+        })
+    }
+
+    pub fn with_settings(settings: WinitCameraControllerSettings) -> Self {
         Self {
             amount_left: 0.0,
             amount_right: 0.0,
@@ -65,44 +192,87 @@ impl WinitCameraController {
             amount_down: 0.0,
             rotate_horizontal: 0.0,
             rotate_vertical: 0.0,
-            speed,
-            sensitivity,
+            sprint: ModifierState::default(),
+            crouch: ModifierState::default(),
+            settings,
         }
     }
 
+    pub fn settings(&self) -> &WinitCameraControllerSettings {
+        &self.settings
+    }
+
+    /// Settings can be changed at any time and take effect on the next `apply`.
+    pub fn settings_mut(&mut self) -> &mut WinitCameraControllerSettings {
+        &mut self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: WinitCameraControllerSettings) {
+        self.settings = settings;
+    }
+
+    pub fn is_sprinting(&self) -> bool {
+        self.sprint.active
+    }
+
+    pub fn is_crouching(&self) -> bool {
+        self.crouch.active
+    }
+
+    /// Current movement speed including sprint/crouch multipliers
+    pub fn speed(&self) -> f32 {
+        let mut speed = self.settings.speed;
+        if self.sprint.active {
+            speed *= self.settings.sprint_multiplier;
+        }
+        if self.crouch.active {
+            speed *= self.settings.crouch_multiplier;
+        }
+        speed
+    }
+
     pub fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
         let amount = if state == ElementState::Pressed {
             1.0
         } else {
             0.0
         };
-        match key {
-            VirtualKeyCode::W | VirtualKeyCode::Up => {
-                self.amount_forward = amount;
-                true
-            }
-            VirtualKeyCode::S | VirtualKeyCode::Down => {
-                self.amount_backward = amount;
-                true
-            }
-            VirtualKeyCode::A | VirtualKeyCode::Left => {
-                self.amount_left = amount;
-                true
-            }
-            VirtualKeyCode::D | VirtualKeyCode::Right => {
-                self.amount_right = amount;
-                true
-            }
-            VirtualKeyCode::Space => {
-                self.amount_up = amount;
-                true
-            }
-            VirtualKeyCode::LShift => {
-                self.amount_down = amount;
-                true
-            }
-            _ => false,
+        let key_map = &self.settings.key_map;
+        let mut handled = false;
+        // Check every action since the same key could be bound to more than one
+        if key_map.forward.contains(&key) {
+            self.amount_forward = amount;
+            handled = true;
+        }
+        if key_map.backward.contains(&key) {
+            self.amount_backward = amount;
+            handled = true;
+        }
+        if key_map.left.contains(&key) {
+            self.amount_left = amount;
+            handled = true;
+        }
+        if key_map.right.contains(&key) {
+            self.amount_right = amount;
+            handled = true;
+        }
+        if key_map.up.contains(&key) {
+            self.amount_up = amount;
+            handled = true;
+        }
+        if key_map.down.contains(&key) {
+            self.amount_down = amount;
+            handled = true;
+        }
+        if key_map.sprint.contains(&key) {
+            self.sprint.process(self.settings.sprint_mode, state);
+            handled = true;
+        }
+        if key_map.crouch.contains(&key) {
+            self.crouch.process(self.settings.crouch_mode, state);
+            handled = true;
         }
+        handled
     }
 
     pub fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
@@ -1,6 +1,8 @@
+use crate::renderer::component::camera::CameraUBO;
 use crate::world::VoxelPos;
-use cgmath::{Angle, Point3, Rad, Vector3};
+use cgmath::{Angle, Array, Point3, Rad, Vector3};
 
+pub mod collision;
 pub mod controller;
 
 #[derive(Debug, Clone)]
@@ -16,13 +18,22 @@ pub struct Camera {
 impl Camera {
     /// Create a camera at the center position of the center top level chunk.
     pub fn new(tlc_size: usize, mem_grid_size: usize) -> Camera {
+        Self::new_with_size_y(tlc_size, mem_grid_size, mem_grid_size)
+    }
+
+    /// Like `new`, for a memory grid with a different size in y (see `MemoryGrid::size_y`)
+    pub fn new_with_size_y(
+        tlc_size: usize,
+        mem_grid_size: usize,
+        mem_grid_size_y: usize,
+    ) -> Camera {
         // Camera position is relative to the memory grid.
-        let offset = ((mem_grid_size / 2 - 1) * tlc_size + tlc_size / 2) as f32;
+        let offset = |size: usize| ((size / 2 - 1) * tlc_size + tlc_size / 2) as f32;
         Camera {
             position: VoxelPos(Point3 {
-                x: offset,
-                y: offset,
-                z: offset,
+                x: offset(mem_grid_size),
+                y: offset(mem_grid_size_y),
+                z: offset(mem_grid_size),
             }),
             yaw: Rad(0.),
             pitch: Rad(0.),
@@ -36,16 +47,29 @@ impl Camera {
         &self.position
     }
 
-    pub fn viewport_center(&self) -> Point3<f32> {
+    /// Unit vector in the direction the camera is looking
+    pub fn forward(&self) -> Vector3<f32> {
         let (yaw_sin, yaw_cos) = self.yaw.sin_cos();
         let (pitch_sin, pitch_cos) = self.pitch.sin_cos();
-        (self.position.0
-            + Vector3 {
-                x: yaw_cos * pitch_cos * self.viewport_dist,
-                y: -pitch_sin * self.viewport_dist,
-                z: -yaw_sin * pitch_cos * self.viewport_dist,
-            })
-        .try_into()
-        .unwrap()
+        Vector3 {
+            x: yaw_cos * pitch_cos,
+            y: -pitch_sin,
+            z: -yaw_sin * pitch_cos,
+        }
+    }
+
+    pub fn viewport_center(&self) -> Point3<f32> {
+        self.position.0 + self.forward() * self.viewport_dist
+    }
+
+    /// Camera data in the same form the raytrace pass receives it, for custom shaders/passes.
+    /// Positions are relative to the memory grid like `position`.
+    pub fn to_gpu_repr(&self) -> CameraUBO {
+        self.to_gpu_repr_with_origin(VoxelPos(Point3::from_value(0.)))
+    }
+
+    /// Like `to_gpu_repr` but with positions relative to `origin`.
+    pub fn to_gpu_repr_with_origin(&self, origin: VoxelPos<f32>) -> CameraUBO {
+        CameraUBO::new(self, origin)
     }
 }
@@ -0,0 +1,142 @@
+use crate::world::storage::InMemoryChunkStore;
+use crate::world::TlcPos;
+use cgmath::Point3;
+use hashbrown::HashMap;
+use std::collections::BTreeMap;
+
+/// A set of chunks whose LOD 0 voxel IDs can be read, e.g. a chunk store or a snapshot of part of a world.
+pub trait ChunkSource {
+    fn chunk_positions(&self) -> Vec<TlcPos<i64>>;
+
+    /// LOD 0 voxel IDs of the chunk at `pos`, or `None` if it isn't in this source
+    fn voxel_ids(&self, pos: TlcPos<i64>) -> Option<Vec<u8>>;
+
+    /// Hint that the chunks at `positions` will be read soon, in that order. Called by
+    /// `ChunkLoader::prefetch_upcoming` so sources backed by disk can batch reads or load region files ahead of
+    /// time. Does nothing by default.
+    fn prefetch(&self, _positions: &[TlcPos<i64>]) {}
+}
+
+/// Snapshot of chunks' voxel IDs keyed by TLC position
+impl<D: AsRef<[u8]>> ChunkSource for HashMap<Point3<i64>, D> {
+    fn chunk_positions(&self) -> Vec<TlcPos<i64>> {
+        self.keys().map(|&pos| TlcPos(pos)).collect()
+    }
+
+    fn voxel_ids(&self, pos: TlcPos<i64>) -> Option<Vec<u8>> {
+        self.get(&pos.0).map(|ids| ids.as_ref().to_vec())
+    }
+}
+
+impl<D: AsRef<[u8]>> ChunkSource for InMemoryChunkStore<D> {
+    fn chunk_positions(&self) -> Vec<TlcPos<i64>> {
+        self.positions()
+    }
+
+    fn voxel_ids(&self, pos: TlcPos<i64>) -> Option<Vec<u8>> {
+        self.with_chunk(pos, |ids| ids.as_ref().to_vec())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkDiff {
+    /// Chunk is in both sources and this many voxels differ
+    Changed {
+        n_voxels: usize,
+    },
+    OnlyInA,
+    OnlyInB,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiffReport {
+    /// Chunks that differ, ordered by position. Chunks that are identical in both sources are left out.
+    pub chunks: BTreeMap<(i64, i64, i64), ChunkDiff>,
+    /// Number of chunks that were in both sources
+    pub n_compared: usize,
+}
+
+impl DiffReport {
+    pub fn is_identical(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    /// Total number of differing voxels in chunks that are in both sources
+    pub fn n_changed_voxels(&self) -> usize {
+        self.chunks
+            .values()
+            .map(|d| match d {
+                ChunkDiff::Changed { n_voxels } => *n_voxels,
+                _ => 0,
+            })
+            .sum()
+    }
+}
+
+/// Compare the chunks in `a` and `b` voxel by voxel, e.g. to check that a world was saved and loaded correctly
+/// or to find which chunks need to be sent to keep two copies of a world in sync. If a chunk's voxel arrays have
+/// different lengths, the extra voxels count as changed.
+pub fn diff(a: &impl ChunkSource, b: &impl ChunkSource) -> DiffReport {
+    let mut report = DiffReport::default();
+    let key = |pos: TlcPos<i64>| (pos.0.x, pos.0.y, pos.0.z);
+
+    for pos in a.chunk_positions() {
+        let ids_a = a.voxel_ids(pos).unwrap();
+        match b.voxel_ids(pos) {
+            None => {
+                report.chunks.insert(key(pos), ChunkDiff::OnlyInA);
+            }
+            Some(ids_b) => {
+                report.n_compared += 1;
+                let n_voxels = ids_a
+                    .iter()
+                    .zip(ids_b.iter())
+                    .filter(|(x, y)| x != y)
+                    .count()
+                    + ids_a.len().abs_diff(ids_b.len());
+                if n_voxels > 0 {
+                    report
+                        .chunks
+                        .insert(key(pos), ChunkDiff::Changed { n_voxels });
+                }
+            }
+        }
+    }
+    for pos in b.chunk_positions() {
+        if a.voxel_ids(pos).is_none() {
+            report.chunks.insert(key(pos), ChunkDiff::OnlyInB);
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        let mut a = HashMap::new();
+        let mut b = HashMap::new();
+        a.insert(Point3::new(0, 0, 0), vec![1u8, 2, 3]);
+        b.insert(Point3::new(0, 0, 0), vec![1u8, 2, 3]);
+        a.insert(Point3::new(1, 0, 0), vec![1, 2, 3]);
+        b.insert(Point3::new(1, 0, 0), vec![1, 0, 0]);
+        a.insert(Point3::new(0, -1, 0), vec![0]);
+        b.insert(Point3::new(0, 5, 0), vec![0]);
+
+        let report = diff(&a, &b);
+        assert_eq!(report.n_compared, 2);
+        assert_eq!(report.n_changed_voxels(), 2);
+        assert_eq!(
+            report.chunks.into_iter().collect::<Vec<_>>(),
+            vec![
+                ((0, -1, 0), ChunkDiff::OnlyInA),
+                ((0, 5, 0), ChunkDiff::OnlyInB),
+                ((1, 0, 0), ChunkDiff::Changed { n_voxels: 2 }),
+            ]
+        );
+        assert!(diff(&a, &a).is_identical());
+    }
+}
@@ -0,0 +1,227 @@
+//! Bridge for keeping an external ECS (e.g. hecs or bevy_ecs) consistent with a scrolling memory grid. Entities
+//! (usually the ECS's entity IDs) are attached to the chunks they are in, and after the grid shifts,
+//! `ChunkEntityMap::sync` reports which of them were evicted with their chunk and how the slots of the chunks that
+//! are still loaded moved.
+
+use crate::world::mem_grid::utils::{amod_axes, index_for_pos, squared};
+use crate::world::mem_grid::MemoryGrid;
+use crate::world::TlcPos;
+use cgmath::{EuclideanSpace, Point3, Vector3};
+use getset::CopyGetters;
+
+/// Entities attached to each chunk in the loaded area of a memory grid (not including buffer chunks). Entities
+/// are stored by physical grid slot like layer data, so nothing moves when the grid shifts; only chunks that
+/// scroll out of the loaded area are evicted.
+#[derive(Debug, CopyGetters)]
+pub struct ChunkEntityMap<E> {
+    #[get_copy = "pub"]
+    start_tlc: TlcPos<i64>,
+    // Grid size including buffer chunks, like `MemoryGrid::size`
+    #[get_copy = "pub"]
+    size: usize,
+    // Like `MemoryGrid::size_y`
+    #[get_copy = "pub"]
+    size_y: usize,
+    // Position and entities of the chunk in each physical slot
+    slots: Vec<Option<(TlcPos<i64>, Vec<E>)>>,
+}
+
+/// A chunk with entities that is still loaded after a shift and whose slot (index in the loaded area relative to
+/// its start TLC) changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkSlotRemap {
+    pub pos: TlcPos<i64>,
+    pub old_slot: usize,
+    pub new_slot: usize,
+}
+
+/// Result of `ChunkEntityMap::sync`
+#[derive(Debug)]
+pub struct ChunkShiftEvents<E> {
+    /// Chunks that scrolled out of the loaded area along with the entities that were attached to them. These
+    /// entities are no longer tracked and should be despawned or saved.
+    pub evicted: Vec<(TlcPos<i64>, Vec<E>)>,
+    /// Chunks with entities that are still loaded, in no particular order
+    pub remapped: Vec<ChunkSlotRemap>,
+}
+
+impl<E> ChunkShiftEvents<E> {
+    pub fn is_empty(&self) -> bool {
+        self.evicted.is_empty() && self.remapped.is_empty()
+    }
+
+    /// `(old_slot, new_slot)` for each chunk in `remapped`
+    pub fn slot_remaps(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.remapped.iter().map(|r| (r.old_slot, r.new_slot))
+    }
+}
+
+impl<E> ChunkEntityMap<E> {
+    /// Empty map matching `grid`'s current size and position
+    pub fn new(grid: &impl MemoryGrid) -> Self {
+        Self::new_at_with_size_y(grid.start_tlc(), grid.size(), grid.size_y())
+    }
+
+    pub fn new_at(start_tlc: TlcPos<i64>, size: usize) -> Self {
+        Self::new_at_with_size_y(start_tlc, size, size)
+    }
+
+    /// Like `new_at`, for a grid with a different size in y (see `MemoryGrid::size_y`)
+    pub fn new_at_with_size_y(start_tlc: TlcPos<i64>, size: usize, size_y: usize) -> Self {
+        ChunkEntityMap {
+            start_tlc,
+            size,
+            size_y,
+            slots: (0..squared(size) * size_y).map(|_| None).collect(),
+        }
+    }
+
+    /// Slot of `pos` in the loaded area (index relative to the start TLC), or `None` if it isn't loaded
+    pub fn slot(&self, pos: TlcPos<i64>) -> Option<usize> {
+        self.slot_in(pos, self.start_tlc)
+    }
+
+    fn slot_in(&self, pos: TlcPos<i64>, start_tlc: TlcPos<i64>) -> Option<usize> {
+        let rel = (pos.0 - start_tlc.0).cast::<u32>()?;
+        let size = self.size;
+        if rel.x as usize >= size - 1
+            || rel.y as usize >= self.size_y - 1
+            || rel.z as usize >= size - 1
+        {
+            return None;
+        }
+        Some(index_for_pos(Point3::from_vec(rel), size))
+    }
+
+    fn physical_slot(&self, pos: TlcPos<i64>) -> usize {
+        let sizes = Vector3::new(self.size, self.size_y, self.size);
+        index_for_pos(amod_axes(pos.0, sizes).cast::<u32>().unwrap(), self.size)
+    }
+
+    /// Attach `entity` to the chunk at `pos`. Fails if the chunk isn't in the loaded area.
+    pub fn attach(&mut self, pos: TlcPos<i64>, entity: E) -> Result<(), ()> {
+        self.slot(pos).ok_or(())?;
+        let idx = self.physical_slot(pos);
+        let slot = self.slots[idx].get_or_insert_with(|| (pos, vec![]));
+        debug_assert!(
+            slot.0 == pos,
+            "Chunk entity map was not synced after a shift"
+        );
+        slot.1.push(entity);
+        Ok(())
+    }
+
+    /// Entities attached to the chunk at `pos`
+    pub fn entities(&self, pos: TlcPos<i64>) -> &[E] {
+        self.slot(pos)
+            .and_then(|_| self.slots[self.physical_slot(pos)].as_ref())
+            .filter(|(slot_pos, _)| *slot_pos == pos)
+            .map_or(&[][..], |(_, entities)| entities.as_slice())
+    }
+
+    /// Update the map after `grid` may have shifted (e.g. after `World::move_camera`), evicting entities in chunks
+    /// that are no longer loaded
+    pub fn sync(&mut self, grid: &impl MemoryGrid) -> ChunkShiftEvents<E> {
+        assert_eq!(
+            (grid.size(), grid.size_y()),
+            (self.size, self.size_y),
+            "Memory grid size changed"
+        );
+        self.shift_to(grid.start_tlc())
+    }
+
+    pub fn shift_to(&mut self, start_tlc: TlcPos<i64>) -> ChunkShiftEvents<E> {
+        let mut events = ChunkShiftEvents {
+            evicted: vec![],
+            remapped: vec![],
+        };
+        if start_tlc == self.start_tlc {
+            return events;
+        }
+
+        let old_start_tlc = self.start_tlc;
+        let mut slots = std::mem::take(&mut self.slots);
+        for slot in slots.iter_mut() {
+            let Some((pos, _)) = slot else { continue };
+            let pos = *pos;
+            match self.slot_in(pos, start_tlc) {
+                None => events.evicted.push(slot.take().unwrap()),
+                Some(new_slot) => events.remapped.push(ChunkSlotRemap {
+                    pos,
+                    old_slot: self.slot_in(pos, old_start_tlc).unwrap(),
+                    new_slot,
+                }),
+            }
+        }
+        self.slots = slots;
+        self.start_tlc = start_tlc;
+        events
+    }
+}
+
+impl<E: PartialEq> ChunkEntityMap<E> {
+    /// Detach `entity` from the chunk at `pos`. Returns whether it was attached there.
+    pub fn detach(&mut self, pos: TlcPos<i64>, entity: &E) -> bool {
+        if self.slot(pos).is_none() {
+            return false;
+        }
+        let idx = self.physical_slot(pos);
+        let Some((slot_pos, entities)) = self.slots[idx].as_mut() else {
+            return false;
+        };
+        if *slot_pos != pos {
+            return false;
+        }
+        let Some(i) = entities.iter().position(|e| e == entity) else {
+            return false;
+        };
+        entities.swap_remove(i);
+        if entities.is_empty() {
+            self.slots[idx] = None;
+        }
+        true
+    }
+
+    /// Move `entity` from the chunk at `from` to the chunk at `to`, e.g. when it crosses a chunk border. Fails
+    /// (leaving it where it was) if it isn't attached at `from` or `to` isn't loaded.
+    pub fn move_entity(&mut self, from: TlcPos<i64>, to: TlcPos<i64>, entity: E) -> Result<(), ()> {
+        if self.slot(to).is_none() || !self.detach(from, &entity) {
+            return Err(());
+        }
+        self.attach(to, entity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Array;
+
+    #[test]
+    fn test_chunk_entity_map_shift() {
+        let mut map = ChunkEntityMap::new_at(TlcPos(Point3::from_value(0)), 4);
+        let a = TlcPos(Point3 { x: 0, y: 1, z: 1 });
+        let b = TlcPos(Point3 { x: 2, y: 1, z: 1 });
+        map.attach(a, 1).unwrap();
+        map.attach(b, 2).unwrap();
+        map.attach(b, 3).unwrap();
+        // Buffer chunks and positions outside the loaded area can't have entities
+        assert!(map.attach(TlcPos(Point3 { x: 3, y: 0, z: 0 }), 4).is_err());
+        assert_eq!(map.entities(b), &[2, 3]);
+
+        let events = map.shift_to(TlcPos(Point3 { x: 1, y: 0, z: 0 }));
+        assert_eq!(events.evicted, vec![(a, vec![1])]);
+        assert_eq!(events.slot_remaps().collect::<Vec<_>>(), vec![(22, 21)]);
+        assert!(map.entities(a).is_empty());
+        assert_eq!(map.entities(b), &[2, 3]);
+
+        // Newly loaded chunks start out empty
+        let c = TlcPos(Point3 { x: 3, y: 1, z: 1 });
+        assert!(map.entities(c).is_empty());
+        map.move_entity(b, c, 3).unwrap();
+        assert_eq!(map.entities(b), &[2]);
+        assert_eq!(map.entities(c), &[3]);
+        assert!(map.move_entity(b, c, 3).is_err());
+        assert!(map.shift_to(TlcPos(Point3 { x: 1, y: 0, z: 0 })).is_empty());
+    }
+}
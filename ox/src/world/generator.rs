@@ -0,0 +1,385 @@
+//! Seeded chunk generation. A `WorldGenerator` fills chunks from a seed so the same world is generated every time
+//! it's loaded, and `load_generated` plugs one into `ChunkLoader::sync` as the load function. `TerrainGenerator`
+//! is a built-in generator with noise terrain and caves, for games that don't need their own yet.
+
+use crate::loader::ChunkLoadQueueItem;
+use crate::noise::{hash_cell, Fbm, Noise2, Noise3, Perlin, Simplex};
+use crate::voxel_type::VoxelTypeEnum;
+use crate::world::manifest::WorldManifest;
+use crate::world::mem_grid::utils::{ChunkSize, VoxelPosInLod};
+use crate::world::mem_grid::voxel::grid::{
+    TakenChunkVoxelEditor, VoxelChunkLoadQueueItemData, VoxelMemoryGridMetadata,
+};
+use crate::world::mem_grid::voxel::{ChunkVoxels, VoxelLODCreateParams};
+use crate::world::storage::checksum;
+use crate::world::TlcPos;
+use cgmath::Point3;
+use std::sync::Arc;
+
+/// Seed of a generated world. Generators derive a separate seed for each kind of noise they use with `derive`, so
+/// e.g. the heightmap and caves don't line up with each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WorldSeed(pub u32);
+
+impl WorldSeed {
+    /// Seed from text the player typed in, like a world name. The same text always gives the same seed.
+    pub fn from_text(text: &str) -> Self {
+        WorldSeed(checksum(text.as_bytes()))
+    }
+
+    /// Seed for the `stream`th independent use of this seed
+    pub fn derive(self, stream: u32) -> u32 {
+        hash_cell(self.0, stream as i64, 0, 0)
+    }
+}
+
+/// Generates the voxels of chunks from a seed. Generating the same chunk with the same seed must always give the
+/// same voxels, since chunks are generated again whenever they are loaded.
+pub trait WorldGenerator: Send + Sync {
+    /// Identifies the generator in a `WorldManifest`
+    fn name(&self) -> &str;
+
+    /// Increase this when the generator changes in a way that changes existing chunks (see `WorldManifest`)
+    fn version(&self) -> u32 {
+        1
+    }
+
+    fn seed(&self) -> WorldSeed;
+
+    /// Fill `voxel_ids_out` for the chunk at `chunk_pos`. Takes the same arguments as the generation function
+    /// passed to `TakenChunkVoxelEditor::load_new`.
+    #[allow(clippy::too_many_arguments)]
+    fn generate(
+        &self,
+        chunk_pos: TlcPos<i64>,
+        lvl: u8,
+        sublvl: u8,
+        sublvl_y: u8,
+        voxel_ids_out: &mut ChunkVoxels,
+        tlc_size: usize,
+        largest_chunk_lvl: u8,
+    );
+
+    /// Manifest for a world made by this generator, to save alongside it
+    fn manifest(
+        &self,
+        chunk_size: ChunkSize,
+        tlc_size: usize,
+        lods: &[VoxelLODCreateParams],
+        spawn: [f64; 3],
+    ) -> WorldManifest {
+        WorldManifest::new(
+            self.seed().0,
+            chunk_size,
+            tlc_size,
+            lods,
+            spawn,
+            self.name(),
+            self.version(),
+        )
+    }
+}
+
+/// Parameters for `load_generated`. `G` can be `dyn WorldGenerator` to pick the generator at runtime.
+#[derive(Debug)]
+pub struct GeneratorLoadParams<G: ?Sized> {
+    pub generator: Arc<G>,
+    pub metadata: VoxelMemoryGridMetadata,
+}
+
+impl<G: ?Sized> Clone for GeneratorLoadParams<G> {
+    fn clone(&self) -> Self {
+        GeneratorLoadParams {
+            generator: Arc::clone(&self.generator),
+            metadata: self.metadata.clone(),
+        }
+    }
+}
+
+impl<G: WorldGenerator> GeneratorLoadParams<G> {
+    pub fn new(generator: G, metadata: VoxelMemoryGridMetadata) -> Self {
+        GeneratorLoadParams {
+            generator: Arc::new(generator),
+            metadata,
+        }
+    }
+}
+
+impl<G: WorldGenerator + ?Sized> GeneratorLoadParams<G> {
+    /// Generate the voxels of the chunk at `pos` into `editor`. Use this in your own load function if your memory
+    /// grid has other layers to load, e.g. `params.load(&mut editor.voxel, chunk.pos)`.
+    pub fn load<VE: VoxelTypeEnum, const N: usize>(
+        &self,
+        editor: &mut TakenChunkVoxelEditor<VE, N>,
+        pos: TlcPos<i64>,
+    ) {
+        editor.load_new(
+            pos,
+            |pos, lvl, sublvl, sublvl_y, voxels, tlc_size, largest_chunk_lvl| {
+                self.generator.generate(
+                    pos,
+                    lvl,
+                    sublvl,
+                    sublvl_y,
+                    voxels,
+                    tlc_size,
+                    largest_chunk_lvl,
+                )
+            },
+            &self.metadata,
+        );
+    }
+}
+
+/// Chunk load function for a `VoxelMemoryGrid` that generates every chunk with a `WorldGenerator`, e.g.
+/// `loader.sync(&mut world, &load_generated::<TerrainGenerator, Block, N>, params.clone())`
+pub fn load_generated<G: WorldGenerator + ?Sized, VE: VoxelTypeEnum, const N: usize>(
+    editor: &mut TakenChunkVoxelEditor<VE, N>,
+    chunk: ChunkLoadQueueItem<VoxelChunkLoadQueueItemData<N>>,
+    params: GeneratorLoadParams<G>,
+) {
+    params.load(editor, chunk.pos);
+}
+
+/// Voxel IDs `TerrainGenerator` fills chunks with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TerrainBlocks {
+    pub air: u8,
+    /// Top voxel of the ground, e.g. grass
+    pub surface: u8,
+    /// Voxels under the surface, e.g. dirt
+    pub subsurface: u8,
+    /// Everything below the subsurface
+    pub stone: u8,
+}
+
+/// Rolling terrain from a heightmap of layered noise, with caves carved out of it by 3D noise. Distances are in
+/// LOD 0 voxels. Lower LODs are generated from the same noise at their own resolution, so they line up with LOD 0.
+#[derive(Debug, Clone, Copy)]
+pub struct TerrainGenerator {
+    pub seed: WorldSeed,
+    pub chunk_size: ChunkSize,
+    pub blocks: TerrainBlocks,
+    /// Average global y position of the surface
+    pub base_height: f64,
+    /// How far the surface goes above and below `base_height`
+    pub amplitude: f64,
+    /// Layers of noise in the heightmap. Each one has twice the frequency and half the amplitude of the last.
+    pub octaves: u32,
+    /// Frequency of the first layer of the heightmap, in cycles per voxel
+    pub frequency: f64,
+    /// Number of `subsurface` voxels under the surface voxel
+    pub subsurface_depth: i64,
+    /// Frequency of the cave noise, in cycles per voxel
+    pub cave_frequency: f64,
+    /// Voxels where the cave noise (roughly -1..1) is above this are carved out. Higher values give fewer, smaller
+    /// caves and 1 or more turns caves off.
+    pub cave_threshold: f32,
+    /// Caves are only carved this many voxels or more under the surface, so they don't riddle the ground
+    pub cave_min_depth: f64,
+}
+
+impl TerrainGenerator {
+    pub fn new(seed: WorldSeed, chunk_size: ChunkSize, blocks: TerrainBlocks) -> Self {
+        TerrainGenerator {
+            seed,
+            chunk_size,
+            blocks,
+            base_height: 0.,
+            amplitude: 48.,
+            octaves: 5,
+            frequency: 1. / 512.,
+            subsurface_depth: 3,
+            cave_frequency: 1. / 48.,
+            cave_threshold: 0.3,
+            cave_min_depth: 8.,
+        }
+    }
+
+    fn heightmap(&self) -> Fbm<Simplex> {
+        Fbm::new(
+            Simplex::new(self.seed.derive(0)),
+            self.octaves,
+            self.frequency,
+        )
+    }
+
+    fn caves(&self) -> Fbm<Perlin> {
+        Fbm::new(Perlin::new(self.seed.derive(1)), 2, self.cave_frequency)
+    }
+
+    /// Global y position of the first air voxel above the surface at `x`, `z`
+    pub fn surface_height(&self, x: i64, z: i64) -> i64 {
+        self.height_for_noise(self.heightmap().sample(x as f64, z as f64))
+    }
+
+    fn height_for_noise(&self, noise: f32) -> i64 {
+        (self.base_height + noise as f64 * self.amplitude).floor() as i64
+    }
+
+    /// Voxel at global LOD 0 position `y` in a column whose surface is at `surface_height`, where `cave` is the
+    /// cave noise there
+    fn block_at(&self, y: i64, surface_height: i64, cave: f32) -> u8 {
+        if y >= surface_height {
+            self.blocks.air
+        } else if cave > self.cave_threshold && ((surface_height - y) as f64) >= self.cave_min_depth
+        {
+            self.blocks.air
+        } else if y == surface_height - 1 {
+            self.blocks.surface
+        } else if y >= surface_height - 1 - self.subsurface_depth {
+            self.blocks.subsurface
+        } else {
+            self.blocks.stone
+        }
+    }
+}
+
+impl WorldGenerator for TerrainGenerator {
+    fn name(&self) -> &str {
+        "ox::terrain"
+    }
+
+    fn seed(&self) -> WorldSeed {
+        self.seed
+    }
+
+    fn generate(
+        &self,
+        chunk_pos: TlcPos<i64>,
+        lvl: u8,
+        sublvl: u8,
+        sublvl_y: u8,
+        voxel_ids_out: &mut ChunkVoxels,
+        tlc_size: usize,
+        largest_chunk_lvl: u8,
+    ) {
+        let voxel_size = self.chunk_size.size().pow(lvl as u32) * 2usize.pow(sublvl as u32);
+        let voxel_size_y = self.chunk_size.size().pow(lvl as u32) * 2usize.pow(sublvl_y as u32);
+        let (grid_size, grid_size_y) = (tlc_size / voxel_size, tlc_size / voxel_size_y);
+        let chunk_start = chunk_pos.0 * tlc_size as i64;
+
+        let mut heights = vec![0.; grid_size * grid_size];
+        self.heightmap().sample_grid(
+            [chunk_start.x as f64, chunk_start.z as f64],
+            voxel_size as f64,
+            grid_size,
+            grid_size,
+            &mut heights,
+        );
+        let caves = self.caves();
+        let caves_enabled = self.cave_threshold < 1.;
+        let mut cave_column = vec![0.; grid_size_y];
+
+        for z_grid in 0..grid_size {
+            for x_grid in 0..grid_size {
+                let surface_height = self.height_for_noise(heights[x_grid + z_grid * grid_size]);
+                // Use the top LOD 0 voxel of each voxel so the surface is in the same place in lower LODs
+                let y_for =
+                    |y_grid: usize| chunk_start.y + (y_grid as i64 + 1) * voxel_size_y as i64 - 1;
+                // Skip sampling caves in columns that are all air
+                if caves_enabled && y_for(0) < surface_height {
+                    caves.sample_column(
+                        (chunk_start.x + (x_grid * voxel_size) as i64) as f64,
+                        (chunk_start.z + (z_grid * voxel_size) as i64) as f64,
+                        y_for(0) as f64,
+                        voxel_size_y as f64,
+                        &mut cave_column,
+                    );
+                } else {
+                    cave_column.fill(-1.);
+                }
+
+                for (y_grid, &cave) in cave_column.iter().enumerate() {
+                    let idx = VoxelPosInLod {
+                        pos: Point3 {
+                            x: x_grid as u32,
+                            y: y_grid as u32,
+                            z: z_grid as u32,
+                        },
+                        lvl,
+                        sublvl,
+                        sublvl_y,
+                    }
+                    .index(self.chunk_size, largest_chunk_lvl);
+                    voxel_ids_out[idx] = self.block_at(y_for(y_grid), surface_height, cave);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCKS: TerrainBlocks = TerrainBlocks {
+        air: 0,
+        surface: 1,
+        subsurface: 2,
+        stone: 3,
+    };
+
+    fn generate(generator: &TerrainGenerator, pos: TlcPos<i64>) -> Vec<u8> {
+        let mut voxels = ChunkVoxels::new_blank(16 * 16 * 16);
+        generator.generate(pos, 0, 0, 0, &mut voxels, 16, 2);
+        (0..16 * 16 * 16).map(|i| voxels[i]).collect()
+    }
+
+    fn voxel(voxels: &[u8], x: u32, y: u32, z: u32) -> u8 {
+        let idx = VoxelPosInLod {
+            pos: Point3 { x, y, z },
+            lvl: 0,
+            sublvl: 0,
+            sublvl_y: 0,
+        }
+        .index(ChunkSize::new(2), 2);
+        voxels[idx]
+    }
+
+    #[test]
+    fn test_terrain_deterministic() {
+        let generator = TerrainGenerator {
+            amplitude: 8.,
+            frequency: 1. / 16.,
+            cave_threshold: 0.,
+            cave_min_depth: 4.,
+            ..TerrainGenerator::new(WorldSeed(7), ChunkSize::new(2), BLOCKS)
+        };
+        let pos = TlcPos(Point3::new(2, -1, -3));
+        let voxels = generate(&generator, pos);
+        assert_eq!(voxels, generate(&generator, pos));
+        let other_seed = TerrainGenerator {
+            seed: WorldSeed(8),
+            ..generator
+        };
+        assert_ne!(voxels, generate(&other_seed, pos));
+        // Some caves are carved out below the surface
+        assert!(voxels.iter().any(|&v| v == BLOCKS.air));
+        assert!(voxels.iter().any(|&v| v == BLOCKS.stone));
+
+        assert_eq!(WorldSeed::from_text("ox"), WorldSeed::from_text("ox"));
+        assert_ne!(WorldSeed(1).derive(0), WorldSeed(1).derive(1));
+    }
+
+    #[test]
+    fn test_terrain_layers() {
+        // Flat terrain without caves
+        let generator = TerrainGenerator {
+            base_height: 8.,
+            amplitude: 0.,
+            cave_threshold: 1.,
+            ..TerrainGenerator::new(WorldSeed(1), ChunkSize::new(2), BLOCKS)
+        };
+        assert_eq!(generator.surface_height(100, -5), 8);
+        let voxels = generate(&generator, TlcPos(Point3::new(0, 0, 0)));
+        for (x, z) in [(0, 0), (5, 11), (15, 15)] {
+            assert_eq!(voxel(&voxels, x, 8, z), BLOCKS.air);
+            assert_eq!(voxel(&voxels, x, 7, z), BLOCKS.surface);
+            assert_eq!(voxel(&voxels, x, 6, z), BLOCKS.subsurface);
+            assert_eq!(voxel(&voxels, x, 4, z), BLOCKS.subsurface);
+            assert_eq!(voxel(&voxels, x, 3, z), BLOCKS.stone);
+        }
+    }
+}
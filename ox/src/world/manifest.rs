@@ -0,0 +1,246 @@
+use crate::world::camera::Camera;
+use crate::world::mem_grid::utils::ChunkSize;
+use crate::world::mem_grid::voxel::VoxelLODCreateParams;
+use crate::world::mem_grid::MemoryGrid;
+use crate::world::{VoxelPos, World};
+use cgmath::{EuclideanSpace, Point3};
+
+/// LOD settings that affect what is saved for each chunk. Render area sizes only change how much is loaded at
+/// once, so they are left out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LodManifest {
+    pub lvl: u8,
+    pub sublvl: u8,
+    pub sublvl_y: u8,
+}
+
+impl From<&VoxelLODCreateParams> for LodManifest {
+    fn from(params: &VoxelLODCreateParams) -> Self {
+        LodManifest {
+            lvl: params.lvl,
+            sublvl: params.sublvl,
+            sublvl_y: params.resolved_sublvl_y(),
+        }
+    }
+}
+
+/// Describes a saved world so it can be checked against the game's current config before it's loaded. Save
+/// it alongside the world's chunk data with any serde format (requires the `serde` feature).
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WorldManifest {
+    /// Version of this manifest's format, `WorldManifest::FORMAT_VERSION` when it was created
+    pub format_version: u32,
+    pub seed: u32,
+    pub chunk_size_exp: u8,
+    pub tlc_size: usize,
+    pub lods: Vec<LodManifest>,
+    /// Global position (in LOD 0 voxels) the camera starts at when the world is loaded
+    pub spawn: [f64; 3],
+    /// Identifies the chunk generator so a world isn't extended with terrain from a different one
+    pub generator: String,
+    pub generator_version: u32,
+}
+
+/// Why a saved `WorldManifest` can't be loaded with the current one
+#[derive(Debug, Clone, PartialEq)]
+pub enum ManifestMismatch {
+    /// The save was written by a newer version of ox
+    FormatVersion {
+        saved: u32,
+        supported: u32,
+    },
+    Seed {
+        saved: u32,
+        current: u32,
+    },
+    ChunkSize {
+        saved: u8,
+        current: u8,
+    },
+    TlcSize {
+        saved: usize,
+        current: usize,
+    },
+    Lods,
+    Generator {
+        saved: String,
+        current: String,
+    },
+    /// The save was generated by a newer version of the generator
+    GeneratorVersion {
+        saved: u32,
+        current: u32,
+    },
+}
+
+impl WorldManifest {
+    pub const FORMAT_VERSION: u32 = 1;
+
+    pub fn new(
+        seed: u32,
+        chunk_size: ChunkSize,
+        tlc_size: usize,
+        lods: &[VoxelLODCreateParams],
+        spawn: [f64; 3],
+        generator: impl Into<String>,
+        generator_version: u32,
+    ) -> Self {
+        WorldManifest {
+            format_version: Self::FORMAT_VERSION,
+            seed,
+            chunk_size_exp: chunk_size.exp(),
+            tlc_size,
+            lods: lods.iter().map(LodManifest::from).collect(),
+            spawn,
+            generator: generator.into(),
+            generator_version,
+        }
+    }
+
+    /// Check that a world saved with this manifest can be loaded with the `current` config. Everything that
+    /// changes how chunk data is laid out or generated must match, except that saves from an older generator
+    /// version are accepted with a warning since new chunks may not line up with saved ones. The spawn point
+    /// is not checked.
+    pub fn check_compatible(&self, current: &WorldManifest) -> Result<(), ManifestMismatch> {
+        if self.format_version > Self::FORMAT_VERSION {
+            return Err(ManifestMismatch::FormatVersion {
+                saved: self.format_version,
+                supported: Self::FORMAT_VERSION,
+            });
+        }
+        if self.seed != current.seed {
+            return Err(ManifestMismatch::Seed {
+                saved: self.seed,
+                current: current.seed,
+            });
+        }
+        if self.chunk_size_exp != current.chunk_size_exp {
+            return Err(ManifestMismatch::ChunkSize {
+                saved: self.chunk_size_exp,
+                current: current.chunk_size_exp,
+            });
+        }
+        if self.tlc_size != current.tlc_size {
+            return Err(ManifestMismatch::TlcSize {
+                saved: self.tlc_size,
+                current: current.tlc_size,
+            });
+        }
+        if self.lods != current.lods {
+            return Err(ManifestMismatch::Lods);
+        }
+        if self.generator != current.generator {
+            return Err(ManifestMismatch::Generator {
+                saved: self.generator.clone(),
+                current: current.generator.clone(),
+            });
+        }
+        if self.generator_version > current.generator_version {
+            return Err(ManifestMismatch::GeneratorVersion {
+                saved: self.generator_version,
+                current: current.generator_version,
+            });
+        }
+        if self.generator_version < current.generator_version {
+            println!(
+                "WARNING: World was generated with {} version {} but is being loaded with version {}",
+                self.generator, self.generator_version, current.generator_version
+            );
+        }
+        Ok(())
+    }
+}
+
+impl<MG: MemoryGrid> World<MG> {
+    /// Create a world for a save described by `saved` after checking it against `current`, the manifest for
+    /// the game's current config. `camera` is moved to the saved spawn point.
+    pub fn from_manifest(
+        saved: &WorldManifest,
+        current: &WorldManifest,
+        mem_grid: MG,
+        mut camera: Camera,
+        tlc_load_dist_thresh: u32,
+    ) -> Result<Self, ManifestMismatch> {
+        saved.check_compatible(current)?;
+
+        // Camera position is relative to the memory grid
+        let grid_start = mem_grid.start_tlc().0.cast::<f64>().unwrap() * saved.tlc_size as f64;
+        camera.position = VoxelPos(
+            (Point3::from(saved.spawn) - grid_start.to_vec())
+                .cast::<f32>()
+                .unwrap(),
+        );
+
+        Ok(World::new(
+            mem_grid,
+            camera,
+            saved.tlc_size,
+            tlc_load_dist_thresh,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest() -> WorldManifest {
+        WorldManifest {
+            format_version: WorldManifest::FORMAT_VERSION,
+            seed: 42,
+            chunk_size_exp: 3,
+            tlc_size: 64,
+            lods: vec![LodManifest {
+                lvl: 0,
+                sublvl: 0,
+                sublvl_y: 0,
+            }],
+            spawn: [0.; 3],
+            generator: "test".to_string(),
+            generator_version: 2,
+        }
+    }
+
+    #[test]
+    fn test_check_compatible() {
+        let current = manifest();
+        assert_eq!(manifest().check_compatible(&current), Ok(()));
+
+        let older = WorldManifest {
+            generator_version: 1,
+            spawn: [10., 20., 30.],
+            ..manifest()
+        };
+        assert_eq!(older.check_compatible(&current), Ok(()));
+
+        let newer = WorldManifest {
+            generator_version: 3,
+            ..manifest()
+        };
+        assert_eq!(
+            newer.check_compatible(&current),
+            Err(ManifestMismatch::GeneratorVersion {
+                saved: 3,
+                current: 2
+            })
+        );
+
+        let mut lods = manifest();
+        lods.lods[0].sublvl_y = 1;
+        assert_eq!(lods.check_compatible(&current), Err(ManifestMismatch::Lods));
+
+        let seed = WorldManifest {
+            seed: 1,
+            ..manifest()
+        };
+        assert_eq!(
+            seed.check_compatible(&current),
+            Err(ManifestMismatch::Seed {
+                saved: 1,
+                current: 42
+            })
+        );
+    }
+}
@@ -1,7 +1,10 @@
 use std::marker::PhantomData;
 
 use crate::loader::{ChunkLoadQueueItem, LayerChunk, TakeChunkForLoading, TakenChunk};
-use crate::world::mem_grid::utils::{amod, cubed, index_for_pos};
+use crate::world::mem_grid::pin::{
+    MemoryGridPinChunks, PinnedChunkStats, PinnedChunks, DEFAULT_MAX_PINNED_CHUNKS,
+};
+use crate::world::mem_grid::utils::{amod_axes, index_for_pos, pos_for_index, squared};
 use crate::world::mem_grid::{EditMemoryGridChunk, MemoryGrid, MemoryGridLoadChunks};
 use crate::world::{BufferChunkState, TlcPos, TlcVector};
 use cgmath::{EuclideanSpace, Point3, Vector3};
@@ -14,7 +17,9 @@ pub struct MemoryGridLayerMetadata<MD> {
     #[getset(get = "pub")]
     start_tlc: TlcPos<i64>,
     #[getset(get = "pub")]
-    size: usize, // grid size (or render area size + 1)
+    size: usize, // grid size in x and z (or render area size + 1)
+    #[getset(get = "pub")]
+    size_y: usize, // grid size in y
     #[getset(get = "pub")]
     offsets: TlcVector<usize>,
     #[getset(get = "pub")]
@@ -35,6 +40,44 @@ pub struct MemoryGridLayer<C, MD = (), S = ()> {
     /// global mutable state, exposed for mutation during editing
     #[getset(get = "pub", get_mut = "pub")]
     state: S,
+    /// Called by `shift` on chunks that scroll out of the loaded area (see `shift_with_eviction`)
+    on_evict: Option<fn(TlcPos<i64>, &mut C)>,
+    /// Created when the first chunk is pinned (see `MemoryGridPinChunks`)
+    pins: Option<PinnedChunks<C>>,
+}
+
+impl<C> MemoryGridLayer<C> {
+    /// Layer of per-chunk data (e.g. a biome ID, entity spawn lists or a flag for chunks that need saving) with a
+    /// loaded area of `loaded_area_size` chunks, centered in `main`, e.g. the `VoxelMemoryGrid` of a `layer_set!`
+    /// grid this layer is part of. If `main` is shorter than that in y, the layer is as tall as `main`. Every chunk
+    /// starts as `init()`. Like any layer, chunks are queued to load as the grid shifts, and their data is taken for
+    /// loading and returned the same way as the main layer's.
+    pub fn centered_in(
+        main: &impl MemoryGrid,
+        loaded_area_size: usize,
+        mut init: impl FnMut() -> C,
+    ) -> Self {
+        let size = loaded_area_size + 1;
+        assert!(
+            size <= main.size(),
+            "Layer of size {} does not fit in a grid of size {}",
+            size,
+            main.size()
+        );
+        let size_y = size.min(main.size_y());
+        let sizes = Vector3::new(size, size_y, size);
+        let start_tlc = main.start_tlc().0 + (main.axis_sizes() - sizes).map(|a| a as i64 / 2);
+        MemoryGridLayer::new_with_size_y(
+            (0..squared(size) * size_y)
+                .map(|_| LayerChunk::new(init()))
+                .collect(),
+            TlcPos(start_tlc),
+            size,
+            size_y,
+            (),
+            (),
+        )
+    }
 }
 
 impl<C, MD, S> MemoryGridLayer<C, MD, S> {
@@ -45,35 +88,71 @@ impl<C, MD, S> MemoryGridLayer<C, MD, S> {
         extra_metadata: MD,
         state: S,
     ) -> Self {
-        debug_assert!(chunks.len() == cubed(size));
+        Self::new_with_size_y(chunks, start_tlc, size, size, extra_metadata, state)
+    }
+
+    /// Like `new`, but with a different size in y than in x and z, e.g. for worlds that are much wider than they
+    /// are tall. `chunks` has `size * size * size_y` chunks.
+    pub fn new_with_size_y(
+        chunks: Vec<LayerChunk<C>>,
+        start_tlc: TlcPos<i64>,
+        size: usize,
+        size_y: usize,
+        extra_metadata: MD,
+        state: S,
+    ) -> Self {
+        debug_assert!(chunks.len() == squared(size) * size_y);
         MemoryGridLayer {
             chunks,
             metadata: MemoryGridLayerMetadata {
                 start_tlc,
                 size,
-                offsets: Self::calc_offsets_for(start_tlc, size),
+                size_y,
+                offsets: Self::calc_offsets_for(start_tlc, Vector3::new(size, size_y, size)),
                 extra: extra_metadata,
             },
             state,
+            on_evict: None,
+            pins: None,
         }
     }
 
+    /// Call `on_evict` with the position and data of each chunk that scrolls out of the loaded area when
+    /// shifting, e.g. when this layer is part of a `layer_set!` grid that shifts it with `shift`.
+    pub fn with_eviction_handler(mut self, on_evict: fn(TlcPos<i64>, &mut C)) -> Self {
+        self.on_evict = Some(on_evict);
+        self
+    }
+
     pub fn chunks_and_state_mut(&mut self) -> (&mut Vec<LayerChunk<C>>, &mut S) {
         (&mut self.chunks, &mut self.state)
     }
 
-    pub fn calc_offsets_for(start_tlc: TlcPos<i64>, size: usize) -> TlcVector<usize> {
-        TlcVector(amod(start_tlc.0, size).to_vec())
+    /// `sizes` is the size of the layer in each axis
+    pub fn calc_offsets_for(start_tlc: TlcPos<i64>, sizes: Vector3<usize>) -> TlcVector<usize> {
+        TlcVector(amod_axes(start_tlc.0, sizes).to_vec())
     }
 
     pub fn calc_offsets(&self) -> TlcVector<usize> {
-        Self::calc_offsets_for(self.metadata().start_tlc, self.metadata().size)
+        Self::calc_offsets_for(self.metadata().start_tlc, self.axis_sizes())
     }
 
     /// Given a chunk position in this layer's virtual grid, return the physical grid position.
     /// (basically, just apply the current offsets)
     pub fn grid_pos_for_vgrid_pos(&self, vgrid_pos: TlcVector<usize>) -> TlcVector<usize> {
-        TlcVector((vgrid_pos.0.map(|a| a as usize) + self.metadata.offsets.0) % self.metadata.size)
+        let sizes = self.axis_sizes();
+        let pos = vgrid_pos.0 + self.metadata.offsets.0;
+        TlcVector(Vector3::new(
+            pos.x % sizes.x,
+            pos.y % sizes.y,
+            pos.z % sizes.z,
+        ))
+    }
+
+    /// Slot that chunk positions map to regardless of the offsets: each coordinate modulo the layer's size in that
+    /// axis (see `slot_of`)
+    pub fn index_for_tlc_pos(&self, pos: TlcPos<i64>) -> usize {
+        self.index_for_grid_pos(TlcVector(amod_axes(pos.0, self.axis_sizes()).to_vec()))
     }
 
     pub fn index_for_grid_pos(&self, grid_pos: TlcVector<usize>) -> usize {
@@ -89,23 +168,24 @@ impl<C, MD, S> MemoryGridLayer<C, MD, S> {
         pos: TlcPos<i64>,
         buffer_chunk_states: [BufferChunkState; 3],
     ) -> Option<TlcVector<usize>> {
+        let sizes = self.axis_sizes();
         let mut i = 0;
         if let Point3 {
             x: Some(x),
             y: Some(y),
             z: Some(z),
         } = (pos.0 - self.start_tlc().0.to_vec()).map(|a| {
-            let state = buffer_chunk_states[i];
+            let (state, size) = (buffer_chunk_states[i], sizes[i]);
             i += 1;
             if a < 0 {
                 if a == -1 && state == BufferChunkState::LoadedLower {
-                    Some(self.size() - 1)
+                    Some(size - 1)
                 } else {
                     None
                 }
-            } else if a >= self.size() as i64 - 1 {
-                if a == self.size() as i64 - 1 && state == BufferChunkState::LoadedUpper {
-                    Some(self.size() - 1)
+            } else if a >= size as i64 - 1 {
+                if a == size as i64 - 1 && state == BufferChunkState::LoadedUpper {
+                    Some(size - 1)
                 } else {
                     None
                 }
@@ -118,6 +198,89 @@ impl<C, MD, S> MemoryGridLayer<C, MD, S> {
             None
         }
     }
+
+    /// Slot (index in `chunks`) of the chunk at `pos`, or `None` if it isn't loaded given `buffer_chunk_states`.
+    ///
+    /// Slots wrap around: on each axis, a position maps to its coordinate modulo the grid size, regardless of the
+    /// start TLC. So a chunk keeps the same slot for as long as it stays loaded, and when a shift scrolls it out, the
+    /// chunk that scrolls in on the opposite side of the loaded area takes over its slot. The lower and upper buffer
+    /// chunks on an axis share a slot, which holds whichever of them `buffer_chunk_states` says is loaded.
+    pub fn slot_of(
+        &self,
+        pos: TlcPos<i64>,
+        buffer_chunk_states: [BufferChunkState; 3],
+    ) -> Option<usize> {
+        Some(self.index_for_vgrid_pos(self.chunk_vgrid_pos(pos, buffer_chunk_states)?))
+    }
+
+    /// Inverse of `slot_of`: position of the chunk that `slot` currently holds, or `None` if it is a buffer chunk
+    /// slot that isn't loaded (or out of range)
+    pub fn pos_of(
+        &self,
+        slot: usize,
+        buffer_chunk_states: [BufferChunkState; 3],
+    ) -> Option<TlcPos<i64>> {
+        let sizes = self.axis_sizes();
+        if slot >= self.chunks.len() {
+            return None;
+        }
+        let grid_pos = pos_for_index(slot, self.size());
+        let mut pos = self.start_tlc().0;
+        for ax in 0..3 {
+            let size = sizes[ax];
+            let vgrid = (grid_pos[ax] + size - self.metadata.offsets.0[ax]) % size;
+            pos[ax] += if vgrid < size - 1 {
+                vgrid as i64
+            } else {
+                match buffer_chunk_states[ax] {
+                    BufferChunkState::LoadedLower => -1,
+                    BufferChunkState::LoadedUpper => size as i64 - 1,
+                    BufferChunkState::Unloaded => return None,
+                }
+            };
+        }
+        Some(TlcPos(pos))
+    }
+
+    /// Position and slot of every loaded chunk (including loaded buffer chunks), ordered by position: by x, then y,
+    /// then z. The order only depends on the start TLC and `buffer_chunk_states`, not on where chunks are stored, so
+    /// it can be used to write chunks to a save file or send them over the network in a consistent order.
+    pub fn slots_in_order(
+        &self,
+        buffer_chunk_states: [BufferChunkState; 3],
+    ) -> impl Iterator<Item = (TlcPos<i64>, usize)> + '_ {
+        let sizes = self.axis_sizes().map(|a| a as i64);
+        let mut ax = 0;
+        let ranges = buffer_chunk_states.map(|state| {
+            let size = sizes[ax];
+            ax += 1;
+            match state {
+                BufferChunkState::Unloaded => 0..size - 1,
+                BufferChunkState::LoadedLower => -1..size - 1,
+                BufferChunkState::LoadedUpper => 0..size,
+            }
+        });
+        let start_tlc = self.start_tlc().0;
+        let [xs, ys, zs] = ranges;
+        xs.flat_map(move |x| {
+            let zs = zs.clone();
+            ys.clone()
+                .flat_map(move |y| zs.clone().map(move |z| Vector3 { x, y, z }))
+        })
+        .map(move |rel| {
+            let pos = TlcPos(start_tlc + rel);
+            (pos, self.slot_of(pos, buffer_chunk_states).unwrap())
+        })
+    }
+
+    /// Every loaded chunk with its position, in the same order as `slots_in_order`
+    pub fn iter_chunks(
+        &self,
+        buffer_chunk_states: [BufferChunkState; 3],
+    ) -> impl Iterator<Item = (TlcPos<i64>, &LayerChunk<C>)> + '_ {
+        self.slots_in_order(buffer_chunk_states)
+            .map(|(pos, slot)| (pos, &self.chunks[slot]))
+    }
 }
 
 impl<C, MD, S> MemoryGridLoadChunks for MemoryGridLayer<C, MD, S> {
@@ -125,14 +288,12 @@ impl<C, MD, S> MemoryGridLoadChunks for MemoryGridLayer<C, MD, S> {
 
     fn queue_load_all(&mut self) -> Vec<ChunkLoadQueueItem<Self::ChunkLoadQueueItemData>> {
         let start_tlc = self.metadata().start_tlc.0;
-        let size = self.metadata().size;
-
-        println!("{:?}  {}", start_tlc, size);
+        let sizes = self.axis_sizes().map(|a| a as i64);
 
-        (0..size as i64 - 1)
+        (0..sizes.x - 1)
             .flat_map(|x| {
-                (0..size as i64 - 1).flat_map(move |y| {
-                    (0..size as i64 - 1).map(move |z| ChunkLoadQueueItem {
+                (0..sizes.y - 1).flat_map(move |y| {
+                    (0..sizes.z - 1).map(move |z| ChunkLoadQueueItem {
                         pos: TlcPos(start_tlc + Vector3 { x, y, z }),
                         data: (),
                     })
@@ -145,6 +306,58 @@ impl<C, MD, S> MemoryGridLoadChunks for MemoryGridLayer<C, MD, S> {
         &mut self,
         shift: &MemGridShift,
     ) -> Vec<ChunkLoadQueueItem<Self::ChunkLoadQueueItemData>> {
+        let on_evict = self.on_evict;
+        self.shift_with_eviction(shift, |pos, data| {
+            if let Some(on_evict) = on_evict {
+                on_evict(pos, data);
+            }
+        })
+    }
+
+    fn invalidate_region(
+        &mut self,
+        min_tlc: TlcPos<i64>,
+        max_tlc: TlcPos<i64>,
+    ) -> Vec<ChunkLoadQueueItem<Self::ChunkLoadQueueItemData>> {
+        let start_tlc = self.metadata.start_tlc.0;
+        let sizes = self.axis_sizes();
+        // Intersect the region with the loaded area (not including buffer chunks)
+        let [xs, ys, zs] = [0, 1, 2].map(|ax| {
+            min_tlc.0[ax].max(start_tlc[ax])
+                ..=max_tlc.0[ax].min(start_tlc[ax] + sizes[ax] as i64 - 2)
+        });
+
+        let mut queue = vec![];
+        for x in xs {
+            for y in ys.clone() {
+                for z in zs.clone() {
+                    let pos = TlcPos(Point3 { x, y, z });
+                    let idx = self.index_for_tlc_pos(pos);
+                    // If the chunk is missing, this makes the result of the load in flight stale so it is loaded
+                    // again once it is returned. Otherwise the chunk is marked invalid until it is reloaded.
+                    self.chunks[idx].bump_version();
+                    let _ = self.chunks[idx].set_invalid();
+                    queue.push(ChunkLoadQueueItem { pos, data: () });
+                }
+            }
+        }
+        queue
+    }
+}
+
+impl<C, MD, S> MemoryGridLayer<C, MD, S> {
+    /// Same as `MemoryGridLoadChunks::shift`, but calls `on_chunk_evicted` with the position and data of each chunk
+    /// whose slot is about to be reused by a newly queued chunk, before that chunk is loaded into it. Composite memory
+    /// grids can use this to tear down anything held by chunks that scroll out of the loaded area (entities, physics
+    /// colliders, etc.). Chunks that are not currently valid (e.g. taken for loading) are skipped, as are pinned
+    /// chunks, whose data is moved to the overflow list instead.
+    pub fn shift_with_eviction<F: FnMut(TlcPos<i64>, &mut C)>(
+        &mut self,
+        shift: &MemGridShift,
+        mut on_chunk_evicted: F,
+    ) -> Vec<ChunkLoadQueueItem<()>> {
+        let old_start_tlc = self.metadata.start_tlc;
+
         // Apply the shift to start_tlc
         self.metadata.start_tlc.0 += shift.offset_delta().cast::<i64>().unwrap();
 
@@ -152,9 +365,143 @@ impl<C, MD, S> MemoryGridLoadChunks for MemoryGridLayer<C, MD, S> {
         self.metadata.offsets = self.calc_offsets();
 
         // Queue all the chunks that need to be loaded based on the shift
-        shift.collect_chunks_to_load(self.metadata().size, self.metadata().start_tlc, |pos| {
+        let sizes = self.axis_sizes();
+        let queue = shift.collect_chunks_to_load(sizes, self.metadata().start_tlc, |pos| {
             ChunkLoadQueueItem { pos, data: () }
-        })
+        });
+
+        for item in queue.iter() {
+            // Chunk positions map to slots modulo the grid size regardless of offsets
+            let idx = self.index_for_tlc_pos(item.pos);
+            if let Some(evicted_pos) = evicted_chunk_pos(item.pos, old_start_tlc, sizes) {
+                // A load still in flight for the evicted chunk would otherwise be returned as valid data
+                self.chunks[idx].bump_version();
+                let pinned = self.pins.as_mut().map_or(false, |pins| {
+                    pins.evict(evicted_pos, idx, self.chunks[idx].get())
+                });
+                if !pinned {
+                    if let Some(data) = self.chunks[idx].get_mut() {
+                        on_chunk_evicted(evicted_pos, data);
+                    }
+                }
+            }
+            if let Some(pins) = self.pins.as_mut() {
+                pins.queue(item.pos, idx);
+            }
+        }
+
+        queue
+    }
+}
+
+/// Position of the chunk that occupied the slot `new_pos` maps to before a shift, given the start TLC before the
+/// shift and the grid's size in each axis. Returns `None` if the slot already held `new_pos`.
+fn evicted_chunk_pos(
+    new_pos: TlcPos<i64>,
+    old_start_tlc: TlcPos<i64>,
+    sizes: Vector3<usize>,
+) -> Option<TlcPos<i64>> {
+    let mut pos = new_pos.0;
+    for ax in 0..3 {
+        let size = sizes[ax] as i64;
+        let (p, start) = (new_pos.0[ax], old_start_tlc.0[ax]);
+        let q = start + (p - start).rem_euclid(size);
+        pos[ax] = if q == start + size - 1 {
+            // The buffer chunk slot could have held either the lower or upper buffer chunk. If neither is `p`, the
+            // chunk being evicted is the one furthest from `p` since the grid moved away from it.
+            let lower = start - 1;
+            if p == lower || p == q {
+                p
+            } else if (p - lower).abs() > (p - q).abs() {
+                lower
+            } else {
+                q
+            }
+        } else {
+            q
+        };
+    }
+
+    if pos == new_pos.0 {
+        None
+    } else {
+        Some(TlcPos(pos))
+    }
+}
+
+impl<C: Clone, MD, S> MemoryGridLayer<C, MD, S> {
+    fn pins_mut(&mut self) -> &mut PinnedChunks<C> {
+        self.pins.get_or_insert_with(|| PinnedChunks::new(C::clone))
+    }
+
+    /// Set how many chunks can be pinned at once (`DEFAULT_MAX_PINNED_CHUNKS` by default). Chunks that are already
+    /// pinned stay pinned.
+    pub fn set_max_pinned_chunks(&mut self, max: usize) {
+        self.pins_mut().set_max_pinned(max);
+    }
+}
+
+impl<C, MD, S> MemoryGridLayer<C, MD, S> {
+    pub fn is_pinned(&self, pos: TlcPos<i64>) -> bool {
+        self.pins.as_ref().map_or(false, |pins| pins.is_pinned(pos))
+    }
+
+    /// Data of a pinned chunk that is outside of the loaded area. Chunks in the loaded area are edited with
+    /// `edit_chunk` as usual.
+    pub fn overflowed_chunk(&self, pos: TlcPos<i64>) -> Option<&C> {
+        self.pins.as_ref()?.overflowed(pos)
+    }
+
+    /// Mutable version of `overflowed_chunk`, so pinned chunks can keep being updated while they are outside of the
+    /// loaded area
+    pub fn overflowed_chunk_mut(&mut self, pos: TlcPos<i64>) -> Option<&mut C> {
+        self.pins.as_mut()?.overflowed_mut(pos)
+    }
+
+    /// Pinned data that will be put back in slot `chunk_idx` when data taken with `version` is returned to it, see
+    /// `return_taken`
+    pub(crate) fn restoring_chunk(&self, chunk_idx: usize, version: u64) -> Option<&C> {
+        if version == self.chunks[chunk_idx].version() {
+            self.pins.as_ref()?.restoring(chunk_idx)
+        } else {
+            None
+        }
+    }
+
+    /// Return data taken from slot `chunk_idx` for loading, where `version` is the slot's version when it was taken
+    /// (see `LayerChunk::return_data`). A pinned chunk that came back into the loaded area gets its own data back
+    /// instead of what was loaded.
+    pub(crate) fn return_taken(&mut self, chunk_idx: usize, mut data: C, version: u64) -> bool {
+        if version == self.chunks[chunk_idx].version() {
+            if let Some(pinned) = self
+                .pins
+                .as_mut()
+                .and_then(|pins| pins.take_restoring(chunk_idx))
+            {
+                data = pinned;
+            }
+        }
+        self.chunks[chunk_idx].return_data(data, version)
+    }
+}
+
+impl<C: Clone, MD, S> MemoryGridPinChunks for MemoryGridLayer<C, MD, S> {
+    fn pin_chunk(&mut self, pos: TlcPos<i64>) -> Result<(), ()> {
+        self.pins_mut().pin(pos)
+    }
+
+    fn unpin_chunk(&mut self, pos: TlcPos<i64>) -> bool {
+        self.pins.as_mut().map_or(false, |pins| pins.unpin(pos))
+    }
+
+    fn pinned_chunk_stats(&self) -> PinnedChunkStats {
+        self.pins.as_ref().map_or(
+            PinnedChunkStats {
+                max_pinned: DEFAULT_MAX_PINNED_CHUNKS,
+                ..Default::default()
+            },
+            |pins| pins.stats(),
+        )
     }
 }
 
@@ -162,6 +509,9 @@ impl<C, MD, S> MemoryGrid for MemoryGridLayer<C, MD, S> {
     fn size(&self) -> usize {
         self.metadata().size
     }
+    fn size_y(&self) -> usize {
+        self.metadata().size_y
+    }
     fn start_tlc(&self) -> TlcPos<i64> {
         self.metadata().start_tlc
     }
@@ -213,6 +563,7 @@ impl<'a, C, MD, S> TakeChunkForLoading<DefaultTakenLayerChunk<C, MD, S>, ()>
         // metadata: &MD,
     ) -> DefaultTakenLayerChunk<C, MD, S> {
         DefaultTakenLayerChunk {
+            version: self.chunk.version(),
             chunk: self.chunk.take().unwrap(),
             chunk_idx: self.chunk_idx,
             _md: PhantomData,
@@ -225,6 +576,7 @@ impl<'a, C, MD, S> TakeChunkForLoading<DefaultTakenLayerChunk<C, MD, S>, ()>
 pub struct DefaultTakenLayerChunk<C, MD = (), S = ()> {
     pub chunk: C,
     pub chunk_idx: usize,
+    pub version: u64,
     _md: PhantomData<MD>,
     _s: PhantomData<S>,
 }
@@ -232,19 +584,28 @@ pub struct DefaultTakenLayerChunk<C, MD = (), S = ()> {
 impl<C: Send, MD: Send, S: Send> TakenChunk for DefaultTakenLayerChunk<C, MD, S> {
     type MemoryGrid = MemoryGridLayer<C, MD, S>;
 
-    fn return_data(self, grid: &mut Self::MemoryGrid) {
-        grid.chunks_mut()[self.chunk_idx] = LayerChunk::new(self.chunk);
+    /// Puts the chunk back "valid", since the loader only takes data to load it, unless the slot's version changed
+    /// while it was taken (see `LayerChunk::return_data`).
+    fn return_data(self, grid: &mut Self::MemoryGrid) -> bool {
+        grid.return_taken(self.chunk_idx, self.chunk, self.version)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use cgmath::Point3;
+    use cgmath::{Array, Point3, Vector3};
 
     use crate::{
-        loader::LayerChunk,
+        loader::{LayerChunk, TakeChunkForLoading, TakenChunk},
         world::{
-            mem_grid::{layer::MemoryGridLayer, utils::cubed, EditMemoryGridChunk},
+            mem_grid::{
+                layer::{evicted_chunk_pos, MemoryGridLayer},
+                pin::MemoryGridPinChunks,
+                self_check::{check_centered_in, MemoryGridSelfCheck, SelfCheckReport},
+                utils::cubed,
+                EditMemoryGridChunk, MemGridShift, MemoryGrid, MemoryGridLoadChunks, ShiftGridAxis,
+                ShiftGridAxisVal,
+            },
             BufferChunkState, TlcPos,
         },
     };
@@ -345,4 +706,220 @@ mod tests {
             )
             .is_none());
     }
+
+    #[test]
+    fn test_evicted_chunk_pos() {
+        let old_start = TlcPos(Point3 { x: 0, y: 0, z: 0 });
+        let sizes = Vector3::from_value(SIZE);
+        // Shifting +1 in x: chunk at x=SIZE-1 (previously buffer) replaces x=-1 (lower buffer)
+        // or the chunk at x=0 depending on which slot it maps to.
+        assert_eq!(
+            evicted_chunk_pos(TlcPos(Point3 { x: 32, y: 3, z: 4 }), old_start, sizes),
+            Some(TlcPos(Point3 { x: 0, y: 3, z: 4 }))
+        );
+        assert_eq!(
+            evicted_chunk_pos(TlcPos(Point3 { x: -2, y: 3, z: 4 }), old_start, sizes),
+            Some(TlcPos(Point3 { x: 30, y: 3, z: 4 }))
+        );
+        // Slot already holds this chunk
+        assert_eq!(
+            evicted_chunk_pos(TlcPos(Point3 { x: 5, y: 3, z: 4 }), old_start, sizes),
+            None
+        );
+    }
+
+    /// Load `value` into the chunk at `pos` the way the chunk loader would, returning whether it was current
+    fn load(layer: &mut MemoryGridLayer<u8>, pos: TlcPos<i64>, value: u8) -> bool {
+        let mut taken = {
+            let mut editor = layer
+                .edit_chunk(pos, [BufferChunkState::Unloaded; 3])
+                .unwrap();
+            editor.mark_invalid().unwrap();
+            editor.take_data_for_loading(&())
+        };
+        taken.chunk = value;
+        taken.return_data(layer)
+    }
+
+    #[test]
+    fn test_returned_chunk_is_valid() {
+        let mut layer = MemoryGridLayer::new(
+            (0..cubed(4)).map(|_| LayerChunk::new(0u8)).collect(),
+            TlcPos(Point3 { x: 0, y: 0, z: 0 }),
+            4,
+            (),
+            (),
+        );
+        let pos = TlcPos(Point3 { x: 1, y: 1, z: 1 });
+        let states = [BufferChunkState::Unloaded; 3];
+        assert!(layer.edit_chunk(pos, states).unwrap().chunk.get().is_none());
+        assert!(load(&mut layer, pos, 3));
+        assert_eq!(layer.edit_chunk(pos, states).unwrap().chunk.get(), Some(&3));
+
+        // Editing counts as a change to the chunk
+        let mut editor = layer.edit_chunk(pos, states).unwrap();
+        let version = editor.chunk.version();
+        *editor.chunk.get_mut().unwrap() += 1;
+        assert_eq!(editor.chunk.version(), version + 1);
+
+        // Data that was taken before the chunk changed is stale and is returned invalid
+        let taken = {
+            let mut editor = layer.edit_chunk(pos, states).unwrap();
+            editor.mark_invalid().unwrap();
+            editor.take_data_for_loading(&())
+        };
+        layer.invalidate_region(pos, pos);
+        assert!(!taken.return_data(&mut layer));
+        assert!(layer.edit_chunk(pos, states).unwrap().chunk.get().is_none());
+    }
+
+    #[test]
+    fn test_pinned_chunk_survives_shift() {
+        let mut layer = MemoryGridLayer::new(
+            (0..cubed(4)).map(|_| LayerChunk::new(0u8)).collect(),
+            TlcPos(Point3 { x: 0, y: 0, z: 0 }),
+            4,
+            (),
+            (),
+        );
+        let pos = TlcPos(Point3 { x: 0, y: 1, z: 1 });
+        let states = [BufferChunkState::Unloaded; 3];
+        layer.set_max_pinned_chunks(1);
+        assert_eq!(layer.pin_chunk(pos), Ok(()));
+        assert_eq!(
+            layer.pin_chunk(TlcPos(Point3 { x: 1, y: 1, z: 1 })),
+            Err(())
+        );
+        assert!(load(&mut layer, pos, 7));
+
+        let shift_x = |chunks| {
+            MemGridShift::new([
+                ShiftGridAxis::Shift(ShiftGridAxisVal::new(chunks, false)),
+                ShiftGridAxis::DoNothing,
+                ShiftGridAxis::DoNothing,
+            ])
+            .unwrap()
+        };
+        layer.shift(&shift_x(2));
+        assert!(layer.edit_chunk(pos, states).is_none());
+        assert_eq!(layer.pinned_chunk_stats().overflowed, 1);
+        *layer.overflowed_chunk_mut(pos).unwrap() += 1;
+
+        // Coming back, the chunk is queued like any other, but keeps its data instead of what is loaded
+        let queue = layer.shift(&shift_x(-2));
+        assert!(queue.iter().any(|item| item.pos == pos));
+        assert_eq!(layer.pinned_chunk_stats().restoring, 1);
+        assert!(load(&mut layer, pos, 0));
+        assert_eq!(layer.edit_chunk(pos, states).unwrap().chunk.get(), Some(&8));
+        assert_eq!(layer.pinned_chunk_stats().restoring, 0);
+        assert!(layer.unpin_chunk(pos));
+    }
+
+    #[test]
+    fn test_slot_mapping_round_trips() {
+        let mut layer = MemoryGridLayer::new(
+            (0..cubed(4)).map(|_| LayerChunk::new(0u8)).collect(),
+            TlcPos(Point3 { x: -5, y: 2, z: 7 }),
+            4,
+            (),
+            (),
+        );
+        layer.shift(
+            &MemGridShift::new([
+                ShiftGridAxis::Shift(ShiftGridAxisVal::new(2, false)),
+                ShiftGridAxis::DoNothing,
+                ShiftGridAxis::Shift(ShiftGridAxisVal::new(-1, false)),
+            ])
+            .unwrap(),
+        );
+        let states = [
+            BufferChunkState::LoadedLower,
+            BufferChunkState::Unloaded,
+            BufferChunkState::LoadedUpper,
+        ];
+        let slots: Vec<_> = layer.slots_in_order(states).collect();
+        assert_eq!(slots.len(), 4 * 3 * 4);
+        assert_eq!(slots[0].0, TlcPos(Point3 { x: -4, y: 2, z: 6 }));
+        let mut seen = vec![false; cubed(4)];
+        for (pos, slot) in slots {
+            assert!(!seen[slot]);
+            seen[slot] = true;
+            assert_eq!(layer.pos_of(slot, states), Some(pos));
+            assert!(layer.edit_chunk(pos, states).is_some());
+        }
+        // Slots of the y buffer chunks aren't loaded
+        assert_eq!(seen.iter().filter(|s| !**s).count(), 16);
+        let unloaded = seen.iter().position(|s| !s).unwrap();
+        assert_eq!(layer.pos_of(unloaded, states), None);
+    }
+
+    #[test]
+    fn test_non_cubic_layer() {
+        let mut layer = MemoryGridLayer::new_with_size_y(
+            (0..4 * 4 * 2).map(|_| LayerChunk::new(0u8)).collect(),
+            TlcPos(Point3 { x: 0, y: -1, z: 0 }),
+            4,
+            2,
+            (),
+            (),
+        );
+        assert_eq!(layer.axis_sizes(), Vector3::new(4, 2, 4));
+        assert_eq!(layer.queue_load_all().len(), 3 * 3);
+
+        // Shifting up replaces the only layer of chunks in y
+        let queue = layer.shift(
+            &MemGridShift::new([
+                ShiftGridAxis::DoNothing,
+                ShiftGridAxis::Shift(ShiftGridAxisVal::new(1, false)),
+                ShiftGridAxis::DoNothing,
+            ])
+            .unwrap(),
+        );
+        assert_eq!(queue.len(), 3 * 3);
+        assert!(queue.iter().all(|item| item.pos.0.y == 0));
+        let mut report = SelfCheckReport::default();
+        layer.self_check("layer", [BufferChunkState::LoadedUpper; 3], &mut report);
+        assert!(report.is_ok(), "{}", report);
+
+        let states = [
+            BufferChunkState::Unloaded,
+            BufferChunkState::LoadedUpper,
+            BufferChunkState::Unloaded,
+        ];
+        let mut seen = vec![false; 4 * 4 * 2];
+        for (pos, slot) in layer.slots_in_order(states) {
+            assert!(!seen[slot]);
+            seen[slot] = true;
+            assert_eq!(layer.pos_of(slot, states), Some(pos));
+        }
+        assert_eq!(seen.iter().filter(|s| **s).count(), 3 * 2 * 3);
+        assert!(layer
+            .edit_chunk(TlcPos(Point3 { x: 0, y: 2, z: 0 }), states)
+            .is_none());
+    }
+
+    #[test]
+    fn test_layer_centered_in() {
+        let main = MemoryGridLayer::new(
+            (0..cubed(8)).map(|_| LayerChunk::new(())).collect(),
+            TlcPos(Point3 { x: -4, y: 0, z: 2 }),
+            8,
+            (),
+            (),
+        );
+        let mut biomes = MemoryGridLayer::centered_in(&main, 3, || 7u8);
+        assert_eq!(biomes.size(), 4);
+        assert_eq!(biomes.start_tlc(), TlcPos(Point3 { x: -2, y: 2, z: 4 }));
+        let mut report = SelfCheckReport::default();
+        check_centered_in("biomes", &biomes, &main, &mut report);
+        assert!(report.is_ok());
+
+        let editor = biomes
+            .edit_chunk(
+                TlcPos(Point3 { x: -2, y: 2, z: 4 }),
+                [BufferChunkState::Unloaded; 3],
+            )
+            .unwrap();
+        assert_eq!(editor.chunk.get(), Some(&7));
+    }
 }
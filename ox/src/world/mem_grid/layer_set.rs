@@ -1,6 +1,418 @@
-// #[macro_export]
-// macro_rules! layer_set {
-//     ($($layer: ident)*,) => {
-//
-//     }
-// }
+use crate::loader::ChunkLoadQueueItem;
+use cgmath::Point3;
+use hashbrown::HashMap;
+
+/// Define a memory grid made of a main layer plus extra layers (e.g. a `VoxelMemoryGrid` plus a
+/// `MemoryGridLayer` for entities), along with its chunk editor, taken chunk and chunk load queue item data
+/// types and the `MemoryGrid`, `MemoryGridLoadChunks`, `MemoryGridRegenerateChunks`, `EditMemoryGridChunk`,
+/// `TakeChunkForLoading`, `TakenChunk`, `ChunkEditorVoxels`, `MemoryGridSelfCheck` and `MemoryGridPinChunks` impls
+/// that tie them together.
+///
+/// ```ignore
+/// ox::layer_set! {
+///     pub struct WorldMemoryGrid<const N: usize> {
+///         voxel: VoxelMemoryGrid<N> [Block] => TakenChunkVoxelEditor<Block, N>,
+///         entity: MemoryGridLayer<Entities> => DefaultTakenLayerChunk<Entities>,
+///     }
+///     editor: WorldChunkEditor,
+///     taken: TakenWorldChunkEditor,
+///     queue_item_data: WorldChunkLoadQueueItemData,
+/// }
+/// ```
+///
+/// Each layer is given as `name: grid type [M] => taken chunk type`, where `[M]` optionally picks which
+/// `EditMemoryGridChunk<M>` impl to use (e.g. the voxel type for `VoxelMemoryGrid`). The first layer is the
+/// main layer: its size and start TLC are the grid's, and a chunk can only be edited if it is in the main
+/// layer. Extra layers are `Option`s in the chunk editor and taken chunk since they may be smaller. Their
+/// chunk load queue item data must implement `Default`, which is used when a chunk is only queued for other
+/// layers. Chunks are regenerated (`MemoryGridRegenerateChunks`) only in the main layer. Pinned chunks are pinned in
+/// every layer, and the pinned chunk stats are the main layer's.
+///
+/// Write these types by hand instead if layers need to interact when loading or shifting.
+#[macro_export]
+macro_rules! layer_set {
+    (
+        $(#[$attr:meta])*
+        $vis:vis struct $grid:ident $(<const $n:ident: usize>)? {
+            $main:ident: $main_ty:ty $([$main_m:ty])? => $main_taken:ty,
+            $($layer:ident: $layer_ty:ty $([$layer_m:ty])? => $layer_taken:ty),* $(,)?
+        }
+        editor: $editor:ident,
+        taken: $taken:ident,
+        queue_item_data: $data:ident $(,)?
+    ) => {
+        $(#[$attr])*
+        #[derive(Debug)]
+        $vis struct $grid<$(const $n: usize)?> {
+            pub $main: $main_ty,
+            $(pub $layer: $layer_ty,)*
+        }
+
+        /// Which layers of a chunk to load
+        #[derive(Debug, Clone, PartialEq, Eq, Default)]
+        $vis struct $data<$(const $n: usize)?> {
+            pub $main: Option<<$main_ty as $crate::world::mem_grid::MemoryGridLoadChunks>::ChunkLoadQueueItemData>,
+            $(pub $layer: Option<<$layer_ty as $crate::world::mem_grid::MemoryGridLoadChunks>::ChunkLoadQueueItemData>,)*
+        }
+
+        #[derive(Debug)]
+        $vis struct $editor<'a, $(const $n: usize)?>
+        where
+            $main_ty: 'a,
+            $($layer_ty: 'a,)*
+        {
+            pub $main: <$main_ty as $crate::world::mem_grid::EditMemoryGridChunk<$($main_m)?>>::ChunkEditor<'a>,
+            $(pub $layer: Option<<$layer_ty as $crate::world::mem_grid::EditMemoryGridChunk<$($layer_m)?>>::ChunkEditor<'a>>,)*
+        }
+
+        #[derive(Debug)]
+        $vis struct $taken<$(const $n: usize)?> {
+            pub $main: $main_taken,
+            $(pub $layer: Option<$layer_taken>,)*
+        }
+
+        impl<$(const $n: usize)?> $crate::world::mem_grid::MemoryGrid for $grid<$($n)?> {
+            fn size(&self) -> usize {
+                $crate::world::mem_grid::MemoryGrid::size(&self.$main)
+            }
+
+            fn size_y(&self) -> usize {
+                $crate::world::mem_grid::MemoryGrid::size_y(&self.$main)
+            }
+
+            fn start_tlc(&self) -> $crate::world::TlcPos<i64> {
+                $crate::world::mem_grid::MemoryGrid::start_tlc(&self.$main)
+            }
+        }
+
+        impl<$(const $n: usize)?> $crate::world::mem_grid::MemoryGridLoadChunks for $grid<$($n)?> {
+            type ChunkLoadQueueItemData = $data<$($n)?>;
+
+            fn queue_load_all(
+                &mut self,
+            ) -> Vec<$crate::loader::ChunkLoadQueueItem<Self::ChunkLoadQueueItemData>> {
+                let mut queue = Default::default();
+                $crate::world::mem_grid::layer_set::merge_queue(
+                    &mut queue,
+                    $crate::world::mem_grid::MemoryGridLoadChunks::queue_load_all(&mut self.$main),
+                    |data: &mut Self::ChunkLoadQueueItemData, d| data.$main = Some(d),
+                );
+                $($crate::world::mem_grid::layer_set::merge_queue(
+                    &mut queue,
+                    $crate::world::mem_grid::MemoryGridLoadChunks::queue_load_all(&mut self.$layer),
+                    |data: &mut Self::ChunkLoadQueueItemData, d| data.$layer = Some(d),
+                );)*
+                queue.into_values().collect()
+            }
+
+            fn shift(
+                &mut self,
+                shift: &$crate::world::mem_grid::MemGridShift,
+            ) -> Vec<$crate::loader::ChunkLoadQueueItem<Self::ChunkLoadQueueItemData>> {
+                let mut queue = Default::default();
+                $crate::world::mem_grid::layer_set::merge_queue(
+                    &mut queue,
+                    $crate::world::mem_grid::MemoryGridLoadChunks::shift(&mut self.$main, shift),
+                    |data: &mut Self::ChunkLoadQueueItemData, d| data.$main = Some(d),
+                );
+                $($crate::world::mem_grid::layer_set::merge_queue(
+                    &mut queue,
+                    $crate::world::mem_grid::MemoryGridLoadChunks::shift(&mut self.$layer, shift),
+                    |data: &mut Self::ChunkLoadQueueItemData, d| data.$layer = Some(d),
+                );)*
+                queue.into_values().collect()
+            }
+
+            fn invalidate_region(
+                &mut self,
+                min_tlc: $crate::world::TlcPos<i64>,
+                max_tlc: $crate::world::TlcPos<i64>,
+            ) -> Vec<$crate::loader::ChunkLoadQueueItem<Self::ChunkLoadQueueItemData>> {
+                let mut queue = Default::default();
+                $crate::world::mem_grid::layer_set::merge_queue(
+                    &mut queue,
+                    $crate::world::mem_grid::MemoryGridLoadChunks::invalidate_region(
+                        &mut self.$main,
+                        min_tlc,
+                        max_tlc,
+                    ),
+                    |data: &mut Self::ChunkLoadQueueItemData, d| data.$main = Some(d),
+                );
+                $($crate::world::mem_grid::layer_set::merge_queue(
+                    &mut queue,
+                    $crate::world::mem_grid::MemoryGridLoadChunks::invalidate_region(
+                        &mut self.$layer,
+                        min_tlc,
+                        max_tlc,
+                    ),
+                    |data: &mut Self::ChunkLoadQueueItemData, d| data.$layer = Some(d),
+                );)*
+                queue.into_values().collect()
+            }
+        }
+
+        impl<$(const $n: usize)?> $crate::world::mem_grid::MemoryGridRegenerateChunks for $grid<$($n)?>
+        where
+            for<'ox> $main_ty: $crate::world::mem_grid::MemoryGridRegenerateChunks,
+        {
+            #[allow(clippy::needless_update)]
+            fn queue_regenerate_unedited(
+                &mut self,
+            ) -> Vec<$crate::loader::ChunkLoadQueueItem<Self::ChunkLoadQueueItemData>> {
+                $crate::world::mem_grid::MemoryGridRegenerateChunks::queue_regenerate_unedited(
+                    &mut self.$main,
+                )
+                .into_iter()
+                .map(|item| $crate::loader::ChunkLoadQueueItem {
+                    pos: item.pos,
+                    data: $data {
+                        $main: Some(item.data),
+                        ..Default::default()
+                    },
+                })
+                .collect()
+            }
+        }
+
+        impl<$(const $n: usize)?> $crate::world::mem_grid::EditMemoryGridChunk for $grid<$($n)?> {
+            type ChunkEditor<'a> = $editor<'a, $($n)?>
+            where
+                Self: 'a;
+
+            fn edit_chunk(
+                &mut self,
+                pos: $crate::world::TlcPos<i64>,
+                buffer_chunk_states: [$crate::world::BufferChunkState; 3],
+            ) -> Option<Self::ChunkEditor<'_>> {
+                Some($editor {
+                    $main: <$main_ty as $crate::world::mem_grid::EditMemoryGridChunk<$($main_m)?>>::edit_chunk(
+                        &mut self.$main,
+                        pos,
+                        buffer_chunk_states,
+                    )?,
+                    $($layer: <$layer_ty as $crate::world::mem_grid::EditMemoryGridChunk<$($layer_m)?>>::edit_chunk(
+                        &mut self.$layer,
+                        pos,
+                        buffer_chunk_states,
+                    ),)*
+                })
+            }
+        }
+
+        impl<'a, $(const $n: usize)?> $crate::loader::TakeChunkForLoading<$taken<$($n)?>, $data<$($n)?>>
+            for $editor<'a, $($n)?>
+        {
+            fn should_still_load(&self, queue_item: &$data<$($n)?>) -> bool {
+                let mut should_load = true;
+                if let Some(d) = queue_item.$main.as_ref() {
+                    should_load &= <_ as $crate::loader::TakeChunkForLoading<$main_taken, _>>::should_still_load(
+                        &self.$main,
+                        d,
+                    );
+                }
+                $(if let (Some(e), Some(d)) = (self.$layer.as_ref(), queue_item.$layer.as_ref()) {
+                    should_load &= <_ as $crate::loader::TakeChunkForLoading<$layer_taken, _>>::should_still_load(e, d);
+                })*
+                should_load
+            }
+
+            fn mark_invalid(&mut self) -> Result<(), ()> {
+                #[allow(unused_mut)]
+                let mut r = <_ as $crate::loader::TakeChunkForLoading<$main_taken, _>>::mark_invalid(
+                    &mut self.$main,
+                );
+                $(if let Some(e) = self.$layer.as_mut() {
+                    r = r.and(<_ as $crate::loader::TakeChunkForLoading<$layer_taken, _>>::mark_invalid(e));
+                })*
+                r
+            }
+
+            fn take_data_for_loading(&mut self, queue_item: &$data<$($n)?>) -> $taken<$($n)?> {
+                $taken {
+                    $main: <_ as $crate::loader::TakeChunkForLoading<$main_taken, _>>::take_data_for_loading(
+                        &mut self.$main,
+                        queue_item
+                            .$main
+                            .as_ref()
+                            .expect("Chunk was queued to load without data for the main layer"),
+                    ),
+                    // Extra layers were marked invalid too, so they are always reloaded
+                    $($layer: self.$layer.as_mut().map(|e| {
+                        <_ as $crate::loader::TakeChunkForLoading<$layer_taken, _>>::take_data_for_loading(
+                            e,
+                            &queue_item.$layer.clone().unwrap_or_default(),
+                        )
+                    }),)*
+                }
+            }
+        }
+
+        impl<$(const $n: usize)?> $crate::loader::TakenChunk for $taken<$($n)?> {
+            type MemoryGrid = $grid<$($n)?>;
+
+            fn return_data(self, grid: &mut Self::MemoryGrid) -> bool {
+                let mut current = true;
+                $(if let Some(taken) = self.$layer {
+                    current &= $crate::loader::TakenChunk::return_data(taken, &mut grid.$layer);
+                })*
+                current &= $crate::loader::TakenChunk::return_data(self.$main, &mut grid.$main);
+                current
+            }
+        }
+
+        impl<$(const $n: usize)?> $crate::world::mem_grid::self_check::MemoryGridSelfCheck for $grid<$($n)?>
+        where
+            for<'ox> $main_ty: $crate::world::mem_grid::self_check::MemoryGridSelfCheck,
+            $(for<'ox> $layer_ty: $crate::world::mem_grid::self_check::MemoryGridSelfCheck,)*
+        {
+            fn self_check(
+                &self,
+                name: &str,
+                buffer_chunk_states: [$crate::world::BufferChunkState; 3],
+                report: &mut $crate::world::mem_grid::self_check::SelfCheckReport,
+            ) {
+                $crate::world::mem_grid::self_check::MemoryGridSelfCheck::self_check(
+                    &self.$main,
+                    &format!("{}.{}", name, stringify!($main)),
+                    buffer_chunk_states,
+                    report,
+                );
+                $(
+                    let layer_name = format!("{}.{}", name, stringify!($layer));
+                    $crate::world::mem_grid::self_check::MemoryGridSelfCheck::self_check(
+                        &self.$layer,
+                        &layer_name,
+                        buffer_chunk_states,
+                        report,
+                    );
+                    $crate::world::mem_grid::self_check::check_centered_in(
+                        &layer_name,
+                        &self.$layer,
+                        &self.$main,
+                        report,
+                    );
+                )*
+            }
+        }
+
+        impl<$(const $n: usize)?> $crate::world::mem_grid::pin::MemoryGridPinChunks for $grid<$($n)?>
+        where
+            for<'ox> $main_ty: $crate::world::mem_grid::pin::MemoryGridPinChunks,
+            $(for<'ox> $layer_ty: $crate::world::mem_grid::pin::MemoryGridPinChunks,)*
+        {
+            fn pin_chunk(&mut self, pos: $crate::world::TlcPos<i64>) -> Result<(), ()> {
+                use $crate::world::mem_grid::pin::MemoryGridPinChunks;
+                let was_pinned = self.$main.pinned_chunk_stats().pinned;
+                self.$main.pin_chunk(pos)?;
+                let r = Ok(())$(.and(self.$layer.pin_chunk(pos)))*;
+                if r.is_err() && self.$main.pinned_chunk_stats().pinned > was_pinned {
+                    // Don't leave the chunk pinned in only some of the layers
+                    self.unpin_chunk(pos);
+                }
+                r
+            }
+
+            fn unpin_chunk(&mut self, pos: $crate::world::TlcPos<i64>) -> bool {
+                use $crate::world::mem_grid::pin::MemoryGridPinChunks;
+                $(self.$layer.unpin_chunk(pos);)*
+                self.$main.unpin_chunk(pos)
+            }
+
+            fn pinned_chunk_stats(&self) -> $crate::world::mem_grid::pin::PinnedChunkStats {
+                $crate::world::mem_grid::pin::MemoryGridPinChunks::pinned_chunk_stats(&self.$main)
+            }
+        }
+
+        impl<'a, $(const $n: usize,)? OxVE: $crate::voxel_type::VoxelTypeEnum, const OX_N: usize>
+            $crate::ray::ChunkEditorVoxels<OxVE, OX_N> for $editor<'a, $($n)?>
+        where
+            <$main_ty as $crate::world::mem_grid::EditMemoryGridChunk<$($main_m)?>>::ChunkEditor<'a>:
+                $crate::ray::ChunkEditorVoxels<OxVE, OX_N>,
+        {
+            fn voxels(&self) -> &$crate::world::mem_grid::voxel::grid::ChunkVoxelEditor<'_, OxVE, OX_N> {
+                $crate::ray::ChunkEditorVoxels::voxels(&self.$main)
+            }
+        }
+    };
+}
+
+/// Used by `layer_set!` to combine the chunks queued by each layer into one queue item per chunk
+#[doc(hidden)]
+pub fn merge_queue<D: Default, L>(
+    queue: &mut HashMap<Point3<i64>, ChunkLoadQueueItem<D>>,
+    items: Vec<ChunkLoadQueueItem<L>>,
+    mut set: impl FnMut(&mut D, L),
+) {
+    for item in items {
+        let entry = queue
+            .entry(item.pos.0)
+            .or_insert_with(|| ChunkLoadQueueItem {
+                pos: item.pos,
+                data: D::default(),
+            });
+        set(&mut entry.data, item.data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::loader::{LayerChunk, TakeChunkForLoading, TakenChunk};
+    use crate::world::mem_grid::layer::{DefaultTakenLayerChunk, MemoryGridLayer};
+    use crate::world::mem_grid::utils::cubed;
+    use crate::world::mem_grid::{EditMemoryGridChunk, MemoryGridLoadChunks};
+    use crate::world::{BufferChunkState, TlcPos};
+    use cgmath::Point3;
+
+    crate::layer_set! {
+        struct TestGrid {
+            big: MemoryGridLayer<u8> => DefaultTakenLayerChunk<u8>,
+            small: MemoryGridLayer<u8> => DefaultTakenLayerChunk<u8>,
+        }
+        editor: TestChunkEditor,
+        taken: TakenTestChunk,
+        queue_item_data: TestChunkLoadQueueItemData,
+    }
+
+    fn layer(size: usize) -> MemoryGridLayer<u8> {
+        MemoryGridLayer::new(
+            (0..cubed(size)).map(|_| LayerChunk::new(0)).collect(),
+            TlcPos(Point3 { x: 0, y: 0, z: 0 }),
+            size,
+            (),
+            (),
+        )
+    }
+
+    #[test]
+    fn test_layer_set() {
+        let mut grid = TestGrid {
+            big: layer(3),
+            small: layer(2),
+        };
+        let queue = grid.queue_load_all();
+        assert_eq!(queue.len(), 8);
+        let item = queue
+            .into_iter()
+            .find(|item| item.data.small.is_some())
+            .unwrap();
+        assert!(item.data.big.is_some());
+
+        let mut taken = {
+            let mut editor = grid
+                .edit_chunk(item.pos, [BufferChunkState::Unloaded; 3])
+                .unwrap();
+            assert!(editor.should_still_load(&item.data));
+            editor.mark_invalid().unwrap();
+            editor.take_data_for_loading(&item.data)
+        };
+        taken.big.chunk = 1;
+        taken.small.as_mut().unwrap().chunk = 2;
+        assert!(taken.return_data(&mut grid));
+
+        let editor = grid
+            .edit_chunk(item.pos, [BufferChunkState::Unloaded; 3])
+            .unwrap();
+        assert_eq!(editor.big.chunk.get(), Some(&1));
+        assert_eq!(editor.small.unwrap().chunk.get(), Some(&2));
+    }
+}
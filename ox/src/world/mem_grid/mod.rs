@@ -2,15 +2,18 @@ use std::ops::Range;
 
 use crate::world::{BufferChunkState, TlcPos};
 use crate::{loader::ChunkLoadQueueItem, world::TlcVector};
-use cgmath::{Array, EuclideanSpace, MetricSpace, Point3, Vector3};
+use cgmath::{EuclideanSpace, MetricSpace, Point3, Vector3};
 use derive_new::new;
 use getset::CopyGetters;
 
 pub mod layer;
-mod layer_set;
-pub mod utils;
+pub mod layer_set;
+pub mod pin;
+pub mod self_check;
 pub mod voxel;
 
+pub use ox_core::utils;
+
 #[derive(new, CopyGetters, Debug)]
 pub struct ShiftGridAxisVal {
     #[get_copy = "pub"]
@@ -20,7 +23,7 @@ pub struct ShiftGridAxisVal {
 }
 impl ShiftGridAxisVal {
     fn n_to_load(&self) -> i32 {
-        self.chunks - self.preloaded_first as i32
+        self.chunks.abs() - self.preloaded_first as i32
     }
 
     /// When shifting this axis, what range of chunks should be loaded
@@ -143,75 +146,86 @@ impl MemGridShift {
         }
     }
 
+    /// Direction the shift loads new chunks toward on each axis: 1 if it shifts in the positive direction or loads
+    /// the upper buffer chunks, -1 for the negative direction or lower buffer chunks, and 0 otherwise
+    pub fn leading_direction(&self) -> Vector3<i32> {
+        Vector3::from(self.0.each_ref().map(|axis| match axis {
+            ShiftGridAxis::Shift(shift) => shift.chunks.signum(),
+            ShiftGridAxis::LoadUpperBufferChunks => 1,
+            ShiftGridAxis::LoadLowerBufferChunks => -1,
+            ShiftGridAxis::MaintainUpperLoadedBufferChunks
+            | ShiftGridAxis::MaintainLowerLoadedBufferChunks
+            | ShiftGridAxis::DoNothing => 0,
+        }))
+    }
+
+    /// `mem_grid_size` is the size of the memory grid in each axis, including buffer chunks
     pub fn collect_chunks_to_load<O, F: Fn(TlcPos<i64>) -> O>(
         &self,
-        mem_grid_size: usize,
+        mem_grid_size: Vector3<usize>,
         start_tlc: TlcPos<i64>,
         f: F,
     ) -> Vec<O> {
-        // ENHANCEMENT: Do this without all the collect()s in the middle, causes closure escape problems though
-
-        let active_grid_size = mem_grid_size - 1;
+        let mut chunks = vec![];
+        let active_grid_size = mem_grid_size.map(|a| a - 1);
 
         // Note: when shifting multiple axes at once, this scheme would queue the corner chunks to load twice.
         // The `load_overlapping_*` bools are to make sure this only happens once without needing to dedup after.
-        [
+        for (a, (b, load_overlapping_b), (c, load_overlapping_c)) in [
             (0, (1, true), (2, true)),
             (1, (2, true), (0, false)),
             (2, (0, false), (1, false)),
-        ]
-        .into_iter()
-        .filter_map(|(a, (b, load_overlapping_b), (c, load_overlapping_c))| {
-            self.0[a]
-                .load_range_for_main_axis(active_grid_size)
-                .map(|range| {
-                    range
-                        .flat_map(|av| {
-                            self.0[b]
-                                .load_range_for_other_axis(active_grid_size, load_overlapping_b)
-                                .into_iter()
-                                .flat_map(|bv| {
-                                    self.0[c]
-                                        .load_range_for_other_axis(
-                                            active_grid_size,
-                                            load_overlapping_c,
-                                        )
-                                        .into_iter()
-                                        .map(|cv| {
-                                            f(TlcPos(
-                                                abc_pos(av, bv as i32, cv as i32, a, b, c).0
-                                                    + start_tlc.0.to_vec(),
-                                            ))
-                                        })
-                                        .collect::<Vec<_>>()
-                                })
-                                .collect::<Vec<_>>()
-                        })
-                        .collect::<Vec<_>>()
-                })
-        })
-        .flatten()
-        .collect()
+        ] {
+            let Some(range) = self.0[a].load_range_for_main_axis(active_grid_size[a]) else {
+                continue;
+            };
+            let b_range =
+                self.0[b].load_range_for_other_axis(active_grid_size[b], load_overlapping_b);
+            let c_range =
+                self.0[c].load_range_for_other_axis(active_grid_size[c], load_overlapping_c);
+            for av in range {
+                for bv in b_range.clone() {
+                    for cv in c_range.clone() {
+                        chunks.push(f(TlcPos(
+                            abc_pos(av, bv, cv, a, b, c).0 + start_tlc.0.to_vec(),
+                        )));
+                    }
+                }
+            }
+        }
+        chunks
     }
 }
 
 pub trait MemoryGrid: Sized {
-    /// Size including any buffer chunks
+    /// Size in the x and z axes including any buffer chunks
     fn size(&self) -> usize;
 
+    /// Size in the y axis including any buffer chunks. The same as `size` unless the grid is shorter (or taller)
+    /// than it is wide, see `VoxelLODCreateParams::render_area_size_y`.
+    fn size_y(&self) -> usize {
+        self.size()
+    }
+
+    /// Size in each axis including any buffer chunks
+    fn axis_sizes(&self) -> Vector3<usize> {
+        Vector3::new(self.size(), self.size_y(), self.size())
+    }
+
     fn start_tlc(&self) -> TlcPos<i64>;
 
     fn center_chunk_pos(&self) -> TlcPos<i64> {
-        TlcPos(self.start_tlc().0 + Vector3::from_value(self.size() as i64 / 2 - 1))
+        self.start_tlc() + TlcVector(self.axis_sizes().map(|a| a as i64 / 2 - 1))
     }
 
     fn chunk_vgrid_pos_in(
         global_tlc_pos: TlcPos<i64>,
         grid_start_tlc: TlcPos<i64>,
     ) -> Option<TlcVector<usize>> {
-        (global_tlc_pos.0 - grid_start_tlc.0.to_vec())
+        (global_tlc_pos - grid_start_tlc)
+            .0
             .cast::<usize>()
-            .map(|v| TlcVector(v.to_vec()))
+            .map(TlcVector)
     }
 
     fn chunk_vgrid_pos(&self, global_tlc_pos: TlcPos<i64>) -> Option<TlcVector<usize>> {
@@ -219,7 +233,7 @@ pub trait MemoryGrid: Sized {
     }
 
     fn chunk_loading_priority(&self, chunk_pos: TlcPos<i64>) -> u32 {
-        let center_pos = Vector3::from_value((self.size() - 1) as f32 / 2.);
+        let center_pos = self.axis_sizes().map(|a| (a - 1) as f32 / 2.);
         let chunk_pos = (chunk_pos.0 - self.start_tlc().0).map(|a| a as f32);
         u32::MAX - (center_pos.distance(chunk_pos) * 10.0) as u32
     }
@@ -238,6 +252,25 @@ pub trait MemoryGridLoadChunks {
         &mut self,
         shift: &MemGridShift,
     ) -> Vec<ChunkLoadQueueItem<Self::ChunkLoadQueueItemData>>;
+
+    /// Mark the chunks in the memory grid from `min_tlc` to `max_tlc` (inclusive) invalid and queue them to be
+    /// loaded again, e.g. because their data changed somewhere else. Chunks that are missing (taken for loading)
+    /// can't be marked invalid, so the load in flight is made stale instead, which the chunk loader requeues when it
+    /// is returned. Like `queue_load_all`, this does not include buffer chunks.
+    fn invalidate_region(
+        &mut self,
+        min_tlc: TlcPos<i64>,
+        max_tlc: TlcPos<i64>,
+    ) -> Vec<ChunkLoadQueueItem<Self::ChunkLoadQueueItemData>>;
+}
+
+/// Memory grids that can reload chunks that are already loaded, e.g. after the chunk generator changed.
+pub trait MemoryGridRegenerateChunks: MemoryGridLoadChunks {
+    /// Queue all chunks in the memory grid that have not been edited since they were loaded to be loaded
+    /// again, so they pick up changes to the chunk generator without losing edits. Does not queue buffer chunks.
+    fn queue_regenerate_unedited(
+        &mut self,
+    ) -> Vec<ChunkLoadQueueItem<Self::ChunkLoadQueueItemData>>;
 }
 
 pub trait EditMemoryGridChunk<M = ()>: MemoryGrid {
@@ -261,3 +294,31 @@ pub trait EditMemoryGridChunk<M = ()>: MemoryGrid {
         buffer_chunk_states: [BufferChunkState; 3],
     ) -> Option<Self::ChunkEditor<'_>>;
 }
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Point3, Vector3};
+
+    use super::{MemGridShift, ShiftGridAxis, ShiftGridAxisVal};
+    use crate::world::TlcPos;
+
+    #[test]
+    fn test_negative_shift_loads_leading_chunks() {
+        // Shifting toward -x by 2 loads the first 2 x slices of the active grid, or just the first if the second was
+        // already loaded as a buffer chunk
+        let start_tlc = TlcPos(Point3 { x: -2, y: 0, z: 0 });
+        for (preloaded_first, n_slices) in [(false, 2), (true, 1)] {
+            let shift = MemGridShift::new([
+                ShiftGridAxis::Shift(ShiftGridAxisVal::new(-2, preloaded_first)),
+                ShiftGridAxis::DoNothing,
+                ShiftGridAxis::DoNothing,
+            ])
+            .unwrap();
+            let chunks = shift.collect_chunks_to_load(Vector3::new(4, 4, 4), start_tlc, |pos| pos);
+            assert_eq!(chunks.len(), n_slices * 3 * 3);
+            assert!(chunks
+                .iter()
+                .all(|pos| (start_tlc.0.x..start_tlc.0.x + n_slices as i64).contains(&pos.0.x)));
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! Pinned chunks keep their data when they scroll out of the loaded area, e.g. a base with machines that should keep
+//! running while the player is away. See `World::pin_chunk`.
+
+use crate::world::mem_grid::MemoryGrid;
+use crate::world::TlcPos;
+use cgmath::Point3;
+use hashbrown::{HashMap, HashSet};
+
+/// Default for how many chunks can be pinned in a layer at once, see `MemoryGridLayer::set_max_pinned_chunks`
+pub const DEFAULT_MAX_PINNED_CHUNKS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PinnedChunkStats {
+    pub pinned: usize,
+    /// Pinned chunks outside of the loaded area, whose data is held in the overflow list
+    pub overflowed: usize,
+    /// Pinned chunks that are back in the loaded area and waiting for their slot to be loaded so their data can be
+    /// put back in place of the loaded data
+    pub restoring: usize,
+    pub max_pinned: usize,
+}
+
+/// Memory grids whose chunks can be pinned. Pinned chunks are not invalidated when they leave the loaded area
+/// during a shift. Instead their data is moved to an overflow list where it can still be updated, and it is put
+/// back when they come back into the loaded area instead of the data the chunk loader loads for them.
+pub trait MemoryGridPinChunks: MemoryGrid {
+    /// Returns `Err` if the maximum number of pinned chunks is reached. Pinning a chunk that is already pinned
+    /// does nothing.
+    fn pin_chunk(&mut self, pos: TlcPos<i64>) -> Result<(), ()>;
+
+    /// Returns whether the chunk was pinned. If it is outside of the loaded area, its data is dropped.
+    fn unpin_chunk(&mut self, pos: TlcPos<i64>) -> bool;
+
+    fn pinned_chunk_stats(&self) -> PinnedChunkStats;
+}
+
+/// Pinned chunks of a `MemoryGridLayer` and the data of those outside of its loaded area
+#[derive(Debug)]
+pub(crate) struct PinnedChunks<C> {
+    pinned: HashSet<Point3<i64>>,
+    overflow: HashMap<Point3<i64>, C>,
+    // Data to put back when the slot (by index) is returned from loading
+    restoring: HashMap<usize, C>,
+    max_pinned: usize,
+    // Pinned data is copied out of a slot when it is evicted since the slot keeps its data until it is loaded
+    clone: fn(&C) -> C,
+}
+
+impl<C> PinnedChunks<C> {
+    pub fn new(clone: fn(&C) -> C) -> Self {
+        PinnedChunks {
+            pinned: HashSet::new(),
+            overflow: HashMap::new(),
+            restoring: HashMap::new(),
+            max_pinned: DEFAULT_MAX_PINNED_CHUNKS,
+            clone,
+        }
+    }
+
+    pub fn set_max_pinned(&mut self, max_pinned: usize) {
+        self.max_pinned = max_pinned;
+    }
+
+    pub fn pin(&mut self, pos: TlcPos<i64>) -> Result<(), ()> {
+        if self.pinned.contains(&pos.0) {
+            Ok(())
+        } else if self.pinned.len() >= self.max_pinned {
+            Err(())
+        } else {
+            self.pinned.insert(pos.0);
+            Ok(())
+        }
+    }
+
+    pub fn unpin(&mut self, pos: TlcPos<i64>) -> bool {
+        self.overflow.remove(&pos.0);
+        self.pinned.remove(&pos.0)
+    }
+
+    pub fn is_pinned(&self, pos: TlcPos<i64>) -> bool {
+        self.pinned.contains(&pos.0)
+    }
+
+    pub fn overflowed(&self, pos: TlcPos<i64>) -> Option<&C> {
+        self.overflow.get(&pos.0)
+    }
+
+    pub fn overflowed_mut(&mut self, pos: TlcPos<i64>) -> Option<&mut C> {
+        self.overflow.get_mut(&pos.0)
+    }
+
+    /// Called when the chunk at `pos` is evicted from slot `idx` during a shift, with its data if it is valid.
+    /// Returns whether the chunk is pinned, in which case its data was kept.
+    pub fn evict(&mut self, pos: TlcPos<i64>, idx: usize, data: Option<&C>) -> bool {
+        // If the chunk was evicted again before it was restored, the slot never got its data back
+        let restoring = self.restoring.remove(&idx);
+        if !self.pinned.contains(&pos.0) {
+            return false;
+        }
+        if let Some(data) = restoring.or_else(|| data.map(self.clone)) {
+            self.overflow.insert(pos.0, data);
+        }
+        true
+    }
+
+    /// Called when the chunk at `pos` is queued to be loaded into slot `idx` during a shift
+    pub fn queue(&mut self, pos: TlcPos<i64>, idx: usize) {
+        if let Some(data) = self.overflow.remove(&pos.0) {
+            self.restoring.insert(idx, data);
+        }
+    }
+
+    pub fn restoring(&self, idx: usize) -> Option<&C> {
+        self.restoring.get(&idx)
+    }
+
+    /// Data to use instead of the data just loaded into slot `idx`, if it holds a pinned chunk
+    pub fn take_restoring(&mut self, idx: usize) -> Option<C> {
+        self.restoring.remove(&idx)
+    }
+
+    pub fn stats(&self) -> PinnedChunkStats {
+        PinnedChunkStats {
+            pinned: self.pinned.len(),
+            overflowed: self.overflow.len(),
+            restoring: self.restoring.len(),
+            max_pinned: self.max_pinned,
+        }
+    }
+}
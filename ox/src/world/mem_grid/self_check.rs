@@ -0,0 +1,252 @@
+//! Consistency checks for memory grids (see `World::self_check`), to catch a desync between chunk positions,
+//! grid slots and buffer chunk states where it happens instead of through a panic somewhere downstream.
+
+use crate::world::mem_grid::layer::MemoryGridLayer;
+use crate::world::mem_grid::utils::squared;
+use crate::world::mem_grid::MemoryGrid;
+use crate::world::{BufferChunkState, TlcPos, TlcVector};
+use cgmath::Vector3;
+use hashbrown::HashMap;
+use std::fmt::{Display, Formatter};
+
+/// Problems found by `MemoryGridSelfCheck::self_check`, each prefixed with the name of the layer it was found in
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SelfCheckReport {
+    pub problems: Vec<String>,
+}
+
+impl SelfCheckReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    pub fn problem(&mut self, layer: &str, problem: impl Display) {
+        self.problems.push(format!("{}: {}", layer, problem));
+    }
+}
+
+impl Display for SelfCheckReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_ok() {
+            return write!(f, "Memory grid self check passed");
+        }
+        writeln!(
+            f,
+            "Memory grid self check found {} problems:",
+            self.problems.len()
+        )?;
+        for problem in self.problems.iter() {
+            writeln!(f, "    {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+pub trait MemoryGridSelfCheck: MemoryGrid {
+    /// Check that this grid is consistent given the current buffer chunk states, adding any problems to
+    /// `report`. `name` identifies this grid in the report.
+    fn self_check(
+        &self,
+        name: &str,
+        buffer_chunk_states: [BufferChunkState; 3],
+        report: &mut SelfCheckReport,
+    );
+}
+
+impl<C, MD, S> MemoryGridSelfCheck for MemoryGridLayer<C, MD, S> {
+    /// Checks that every position in range (including loaded buffer chunks) maps to its own slot, that no position
+    /// out of range maps to a slot, and that the offsets match the start TLC.
+    fn self_check(
+        &self,
+        name: &str,
+        buffer_chunk_states: [BufferChunkState; 3],
+        report: &mut SelfCheckReport,
+    ) {
+        let sizes = self.axis_sizes();
+        let n_chunks = squared(sizes.x) * sizes.y;
+        if self.chunks().len() != n_chunks {
+            report.problem(
+                name,
+                format!("has {} chunks, expected {}", self.chunks().len(), n_chunks),
+            );
+            return;
+        }
+        if *self.metadata().offsets() != self.calc_offsets() {
+            report.problem(
+                name,
+                format!(
+                    "offsets {:?} don't match start TLC {:?}",
+                    self.metadata().offsets().0,
+                    self.start_tlc().0
+                ),
+            );
+        }
+
+        // Also go one position past the buffer chunks on each side to catch positions that are mapped but shouldn't be
+        let start_tlc = self.start_tlc();
+        let mut slots: HashMap<usize, TlcPos<i64>> = HashMap::new();
+        for x in -2..sizes.x as i64 + 1 {
+            for y in -2..sizes.y as i64 + 1 {
+                for z in -2..sizes.z as i64 + 1 {
+                    let rel = Vector3 { x, y, z };
+                    let pos = TlcPos(start_tlc.0 + rel);
+                    let in_range =
+                        (0..3).all(|i| in_range_on_axis(rel[i], sizes[i], buffer_chunk_states[i]));
+
+                    match (self.chunk_vgrid_pos(pos, buffer_chunk_states), in_range) {
+                        (None, false) => {}
+                        (None, true) => {
+                            report.problem(name, format!("{:?} is in range but not mapped", pos.0))
+                        }
+                        (Some(_), false) => {
+                            report.problem(name, format!("{:?} is out of range but mapped", pos.0))
+                        }
+                        (Some(vgrid_pos), true) => {
+                            let idx = self.index_for_vgrid_pos(vgrid_pos);
+                            // Shifting relies on positions mapping to slots modulo the grid size
+                            let expected_idx = self.index_for_tlc_pos(pos);
+                            if idx != expected_idx {
+                                report.problem(
+                                    name,
+                                    format!(
+                                        "{:?} maps to slot {} instead of {}",
+                                        pos.0, idx, expected_idx
+                                    ),
+                                );
+                            }
+                            if let Some(other) = slots.insert(idx, pos) {
+                                report.problem(
+                                    name,
+                                    format!(
+                                        "{:?} and {:?} both map to slot {}",
+                                        other.0, pos.0, idx
+                                    ),
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Whether a position (relative to the start TLC) is loaded along one axis
+fn in_range_on_axis(rel: i64, size: usize, state: BufferChunkState) -> bool {
+    (0..size as i64 - 1).contains(&rel)
+        || (rel == -1 && state == BufferChunkState::LoadedLower)
+        || (rel == size as i64 - 1 && state == BufferChunkState::LoadedUpper)
+}
+
+/// Check that `grid` is centered in `main`, which is how LODs and extra layers are placed relative to the main
+/// layer so that they all shift together.
+pub fn check_centered_in<G: MemoryGrid, M: MemoryGrid>(
+    name: &str,
+    grid: &G,
+    main: &M,
+    report: &mut SelfCheckReport,
+) {
+    let (sizes, main_sizes) = (grid.axis_sizes(), main.axis_sizes());
+    if (0..3).any(|ax| sizes[ax] > main_sizes[ax]) {
+        report.problem(
+            name,
+            format!(
+                "size {:?} is larger than the main layer's size {:?}",
+                sizes, main_sizes
+            ),
+        );
+        return;
+    }
+    let expected = main.start_tlc() + TlcVector((main_sizes - sizes).map(|a| (a / 2) as i64));
+    if grid.start_tlc() != expected {
+        report.problem(
+            name,
+            format!(
+                "start TLC {:?} disagrees with the main layer, expected {:?}",
+                grid.start_tlc().0,
+                expected.0
+            ),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::LayerChunk;
+    use crate::world::mem_grid::layer::DefaultTakenLayerChunk;
+    use crate::world::mem_grid::utils::cubed;
+    use crate::world::mem_grid::{
+        MemGridShift, MemoryGridLoadChunks, ShiftGridAxis, ShiftGridAxisVal,
+    };
+    use cgmath::{Array, Point3};
+
+    crate::layer_set! {
+        struct TestGrid {
+            big: MemoryGridLayer<u8> => DefaultTakenLayerChunk<u8>,
+            small: MemoryGridLayer<u8> => DefaultTakenLayerChunk<u8>,
+        }
+        editor: TestChunkEditor,
+        taken: TakenTestChunk,
+        queue_item_data: TestChunkLoadQueueItemData,
+    }
+
+    fn layer(size: usize, start: i64) -> MemoryGridLayer<u8> {
+        MemoryGridLayer::new(
+            (0..cubed(size)).map(|_| LayerChunk::new(0)).collect(),
+            TlcPos(Point3::from_value(start)),
+            size,
+            (),
+            (),
+        )
+    }
+
+    fn check(grid: &TestGrid, buffer_chunk_states: [BufferChunkState; 3]) -> SelfCheckReport {
+        let mut report = SelfCheckReport::default();
+        grid.self_check("grid", buffer_chunk_states, &mut report);
+        report
+    }
+
+    #[test]
+    fn test_self_check_after_shifts() {
+        let mut grid = TestGrid {
+            big: layer(5, -3),
+            small: layer(3, -2),
+        };
+        for states in [
+            [BufferChunkState::Unloaded; 3],
+            [BufferChunkState::LoadedUpper; 3],
+            [
+                BufferChunkState::LoadedLower,
+                BufferChunkState::Unloaded,
+                BufferChunkState::LoadedUpper,
+            ],
+        ] {
+            let report = check(&grid, states);
+            assert!(report.is_ok(), "{}", report);
+        }
+
+        for chunks in [1, -2, 3] {
+            let shift = MemGridShift::new([
+                ShiftGridAxis::Shift(ShiftGridAxisVal::new(chunks, false)),
+                ShiftGridAxis::DoNothing,
+                ShiftGridAxis::Shift(ShiftGridAxisVal::new(-chunks, false)),
+            ])
+            .unwrap();
+            grid.shift(&shift);
+            let report = check(&grid, [BufferChunkState::Unloaded; 3]);
+            assert!(report.is_ok(), "{}", report);
+        }
+    }
+
+    #[test]
+    fn test_self_check_finds_misplaced_layer() {
+        let grid = TestGrid {
+            big: layer(5, 0),
+            small: layer(3, 0),
+        };
+        let report = check(&grid, [BufferChunkState::Unloaded; 3]);
+        assert_eq!(report.problems.len(), 1, "{}", report);
+        assert!(report.problems[0].starts_with("grid.small: start TLC"));
+    }
+}
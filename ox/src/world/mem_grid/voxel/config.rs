@@ -0,0 +1,111 @@
+use super::grid::{lod_tlc_size, VoxelChunkLoadQueueItemData};
+use super::limits::{check_lod_limits, DeviceLimits};
+use super::lod::VoxelLODCreateParams;
+use super::VoxelMemoryGrid;
+use crate::renderer::binding::BindingMapBuilder;
+use crate::renderer::shader_defs::ShaderDefs;
+use crate::world::mem_grid::utils::ChunkSize;
+use getset::{CopyGetters, Getters};
+use vulkano::device::physical::PhysicalDevice;
+
+/// LOD configuration shared by everything that has one entry per LOD: the world's `VoxelMemoryGrid<N>`, the
+/// renderer's `VoxelData<N>` (both from `VoxelMemoryGrid::from_config`) and chunk loading queue items
+/// (`VoxelChunkLoadQueueItemData<N>`). Creating them all from the same config makes a mismatched number of LODs
+/// a type error instead of a panic (or silently wrong data) at runtime.
+#[derive(Clone, Debug, Getters, CopyGetters)]
+pub struct LodConfig<const N: usize> {
+    #[get = "pub"]
+    lods: [VoxelLODCreateParams; N],
+    #[get_copy = "pub"]
+    chunk_size: ChunkSize,
+}
+
+impl LodConfig<5> {
+    /// See `VoxelMemoryGrid::standard_config`
+    pub fn standard(
+        render_distance_tlcs: usize,
+        chunk_size: ChunkSize,
+        bindings: &mut BindingMapBuilder,
+    ) -> Self {
+        LodConfig::new(
+            VoxelMemoryGrid::standard_config(render_distance_tlcs, chunk_size, bindings),
+            chunk_size,
+        )
+    }
+}
+
+impl<const N: usize> LodConfig<N> {
+    /// LODs must be ordered by increasing lvl/sublvl, see `VoxelMemoryGrid::new` for the full requirements
+    pub fn new(lods: [VoxelLODCreateParams; N], chunk_size: ChunkSize) -> Self {
+        assert!(N > 0, "LodConfig needs at least one LOD");
+        for lod in lods.iter() {
+            lod.validate(chunk_size);
+        }
+        LodConfig { lods, chunk_size }
+    }
+
+    /// The lowest fidelity LOD, which has the largest render area
+    pub fn largest_lod(&self) -> &VoxelLODCreateParams {
+        &self.lods[N - 1]
+    }
+
+    /// Size of a top level chunk in LOD 0 voxels
+    pub fn tlc_size(&self) -> usize {
+        lod_tlc_size(self.chunk_size, self.largest_lod().lvl, 0, 0)
+    }
+
+    /// Render area size of the largest LOD in TLCs, i.e. the loaded area that is rendered
+    pub fn render_area_size(&self) -> usize {
+        self.largest_lod().render_area_size
+    }
+
+    /// Like `render_area_size` for the y axis
+    pub fn render_area_size_y(&self) -> usize {
+        self.largest_lod().resolved_render_area_size_y()
+    }
+
+    /// Queue item data for loading the LODs where `lods` is true
+    pub fn queue_item_data(&self, lods: [bool; N]) -> VoxelChunkLoadQueueItemData<N> {
+        VoxelChunkLoadQueueItemData { lods }
+    }
+
+    pub fn into_parts(self) -> ([VoxelLODCreateParams; N], ChunkSize) {
+        (self.lods, self.chunk_size)
+    }
+
+    /// Check that this config's buffers fit within `physical_device`'s limits (see `check_lod_limits`), e.g.
+    /// to fall back to a smaller render distance. `VoxelMemoryGrid::from_config` panics with the same
+    /// description otherwise.
+    pub fn check_device_limits(&self, physical_device: &PhysicalDevice) -> Result<(), String> {
+        check_lod_limits(
+            &self.lods,
+            self.chunk_size,
+            &DeviceLimits::of(physical_device),
+        )
+    }
+
+    /// Shader definitions for this config, see `ShaderDefs::for_voxel_grid`
+    pub fn shader_defs(&self) -> ShaderDefs {
+        ShaderDefs::for_voxel_grid(&self.lods, self.chunk_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_standard_lod_config() {
+        let mut bindings = BindingMapBuilder::new();
+        let chunk_size = ChunkSize::new(3);
+        let config = LodConfig::standard(11, chunk_size, &mut bindings);
+        assert_eq!(config.largest_lod().lvl, 2);
+        assert_eq!(config.tlc_size(), 64);
+        assert_eq!(config.render_area_size(), 23);
+        assert_eq!(config.render_area_size_y(), 23);
+        assert_eq!(
+            config.queue_item_data([true; 5]).lods,
+            [true, true, true, true, true]
+        );
+    }
+}
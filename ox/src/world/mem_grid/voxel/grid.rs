@@ -2,31 +2,56 @@ use super::lod::{
     LodChunkEditorMaybeUnloaded, TakenLodChunk, VoxelLODCreateParams, VoxelMemoryGridLod,
 };
 use crate::loader::{ChunkLoadQueueItem, TakeChunkForLoading, TakenChunk};
+use crate::renderer::binding::BindingMapBuilder;
 use crate::renderer::component::voxels::lod::VoxelLODUpdate;
 use crate::renderer::component::voxels::VoxelData;
-use crate::voxel_type::VoxelTypeEnum;
+use crate::renderer::UploadBudget;
+use crate::voxel_type::{VoxelSchema, VoxelTypeEnum};
 use crate::world::mem_grid::layer::MemoryGridLayer;
-use crate::world::mem_grid::utils::{ChunkSize, IteratorWithIndexing, VoxelPosInLod};
+use crate::world::mem_grid::pin::{MemoryGridPinChunks, PinnedChunkStats};
+use crate::world::mem_grid::self_check::{check_centered_in, MemoryGridSelfCheck, SelfCheckReport};
+use crate::world::mem_grid::utils::{squared, ChunkSize, IteratorWithIndexing, VoxelPosInLod};
+use crate::world::mem_grid::voxel::downsample::Downsample;
 use crate::world::mem_grid::voxel::gpu_defs::ChunkVoxels;
+use crate::world::mem_grid::voxel::limits::{check_lod_limits, DeviceLimits};
 use crate::world::mem_grid::voxel::lod::{
-    update_bitmask_from_lower_lod_untracked, LodChunkDataVariant, LodChunkDataVariantMut,
-    LodChunkEditorVariantMut, UpdateRegion,
+    apply_to_voxel_indices_in_lower_lod, apply_to_voxels_in_lod,
+    update_bitmask_from_lower_lod_untracked, DownsampleCounts, LodChunkDataVariant,
+    LodChunkDataVariantMut, LodChunkEditorVariantMut, UpdateRegion,
 };
-use crate::world::mem_grid::{EditMemoryGridChunk, MemoryGrid, MemoryGridLoadChunks};
-use crate::world::{TlcPos, VoxelPos};
-use cgmath::{Array, EuclideanSpace, Vector3};
+use crate::world::mem_grid::voxel::LodConfig;
+use crate::world::mem_grid::{
+    EditMemoryGridChunk, MemGridShift, MemoryGrid, MemoryGridLoadChunks, MemoryGridRegenerateChunks,
+};
+use crate::world::storage::ChunkStore;
+use crate::world::{BufferChunkState, TlcPos, TlcVector, VoxelPos};
+use cgmath::{InnerSpace, Point3, Vector3};
 use getset::{CopyGetters, Getters};
 use hashbrown::{HashMap, HashSet};
+use std::borrow::Cow;
+use std::ops::Range;
 use std::sync::Arc;
 use unzip_array_of_tuple::unzip_array_of_tuple;
+use vulkano::device::DeviceOwned;
 use vulkano::memory::allocator::MemoryAllocator;
 
+pub use ox_core::voxel::lod::lod_tlc_size;
+
 #[derive(Debug, Getters)]
 pub struct VoxelMemoryGrid<const N: usize> {
     #[get = "pub"]
     lods: [VoxelMemoryGridLod; N],
     #[get = "pub"]
     metadata: VoxelMemoryGridMetadata,
+    // TLCs that have been edited with `ChunkVoxelEditor::set_voxel` since they were last loaded
+    edited_tlcs: HashSet<Point3<i64>>,
+    // Edited TLCs that haven't been saved to `chunk_store` since they were edited
+    unsaved_tlcs: HashSet<Point3<i64>>,
+    // Game data attached to TLCs with `ChunkVoxelEditor::set_user_data` or loaded from `chunk_store`
+    user_data: HashMap<Point3<i64>, Vec<u8>>,
+    // TLCs whose user data changed since it was last saved to `chunk_store`
+    unsaved_user_data: HashSet<Point3<i64>>,
+    chunk_store: Option<Arc<dyn ChunkStore>>,
 }
 
 #[derive(CopyGetters, Clone, Copy, Debug)]
@@ -43,8 +68,12 @@ pub struct VoxelMemoryGridMetadata {
     largest_lod: LodId, // which (lvl, sublvl) has the largest grid size
     #[get_copy = "pub"]
     chunk_size: ChunkSize,
+    /// Render area size of the largest LOD in TLCs, i.e. the loaded area that is rendered
+    #[get_copy = "pub"]
+    render_area_size: usize,
+    /// Like `render_area_size` for the y axis, see `VoxelLODCreateParams::render_area_size_y`
     #[get_copy = "pub"]
-    lod_block_fill_thresh: f32,
+    render_area_size_y: usize,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -52,6 +81,28 @@ pub struct VoxelChunkLoadQueueItemData<const N: usize> {
     pub lods: [bool; N],
 }
 
+// serde only implements arrays up to a fixed length, not for any `N`, so the LOD flags are (de)serialized as a
+// sequence
+#[cfg(feature = "serde")]
+impl<const N: usize> serde::Serialize for VoxelChunkLoadQueueItemData<N> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.lods.as_slice().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, const N: usize> serde::Deserialize<'de> for VoxelChunkLoadQueueItemData<N> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let lods = Vec::<bool>::deserialize(deserializer)?;
+        let n = lods.len();
+        Ok(VoxelChunkLoadQueueItemData {
+            lods: lods.try_into().map_err(|_| {
+                serde::de::Error::invalid_length(n, &format!("{} LOD flags", N).as_str())
+            })?,
+        })
+    }
+}
+
 impl VoxelMemoryGridMetadata {
     pub fn tlc_size(&self) -> usize {
         self.chunk_size.size().pow(self.largest_lod.lvl as u32)
@@ -59,10 +110,55 @@ impl VoxelMemoryGridMetadata {
     }
 }
 
-/// Size (on one side) of top level chunks in units of an LOD's voxels, where the LOD is specified by lvl and sublvl.
-/// This number can be cubed to get the number of voxels.
-pub fn lod_tlc_size(chunk_size: ChunkSize, largest_lvl: u8, lvl: u8, sublvl: u8) -> usize {
-    1usize << (chunk_size.exp() * (largest_lvl - lvl) - sublvl)
+impl VoxelMemoryGrid<5> {
+    /// LOD params for the standard 5 LOD setup used by the sandbox and `raytrace.comp`: LOD 0 at sublvls
+    /// 0, 1 and 2, then lvl 1 and lvl 2, with the shader's bindings (`lod_{lvl}_{sublvl}_bitmask` at 8..=12
+    /// and `lod_{lvl}_{sublvl}_voxel_ids` at 4..=7, none for lvl 2). The lvl 1 and 2 LODs load
+    /// `render_distance_tlcs` TLCs in each direction around the center chunk and each finer LOD covers about
+    /// half the distance of the next one, so e.g. 11 gives render area sizes 3, 5, 9, 23 and 23. Pass the
+    /// result to `VoxelMemoryGrid::new`.
+    pub fn standard_config(
+        render_distance_tlcs: usize,
+        chunk_size: ChunkSize,
+        bindings: &mut BindingMapBuilder,
+    ) -> [VoxelLODCreateParams; 5] {
+        assert!(
+            chunk_size.exp() >= 3,
+            "Standard LOD config needs a chunk size of at least 8 to have sublvls 0..=2"
+        );
+
+        // Render distance of each LOD (not including the center chunk)
+        let dist_0_2 = render_distance_tlcs / 3 + 1;
+        let dist_0_1 = dist_0_2 / 2;
+        let dist_0_0 = dist_0_1 / 2;
+        let dist_1_0 = render_distance_tlcs.max(dist_0_2);
+
+        let mut lod =
+            |lvl: u8, sublvl: u8, dist: usize, bitmask_binding: u32, voxel_ids_binding| {
+                VoxelLODCreateParams {
+                    voxel_resolution: chunk_size.size().pow(lvl as u32) * 2usize.pow(sublvl as u32),
+                    lvl,
+                    sublvl,
+                    sublvl_y: None,
+                    render_area_size: dist * 2 + 1,
+                    render_area_size_y: None,
+                    bitmask_binding: bindings
+                        .register_at(&format!("lod_{}_{}_bitmask", lvl, sublvl), bitmask_binding),
+                    voxel_ids_binding: voxel_ids_binding.map(|index: u32| {
+                        bindings.register_at(&format!("lod_{}_{}_voxel_ids", lvl, sublvl), index)
+                    }),
+                    downsample: Downsample::default(),
+                }
+            };
+
+        [
+            lod(0, 0, dist_0_0, 8, Some(4)),
+            lod(0, 1, dist_0_1, 9, Some(5)),
+            lod(0, 2, dist_0_2, 10, Some(6)),
+            lod(1, 0, dist_1_0, 11, Some(7)),
+            lod(2, 0, dist_1_0, 12, None),
+        ]
+    }
 }
 
 impl<const N: usize> VoxelMemoryGrid<N> {
@@ -75,6 +171,30 @@ impl<const N: usize> VoxelMemoryGrid<N> {
             .next()
     }
 
+    pub(super) fn lods_mut(&mut self) -> &mut [VoxelMemoryGridLod; N] {
+        &mut self.lods
+    }
+
+    /// Create the grid and the renderer's voxel data component from `config`, see `LodConfig`
+    pub fn from_config(
+        config: LodConfig<N>,
+        memory_allocator: Arc<dyn MemoryAllocator>,
+        start_tlc: TlcPos<i64>,
+    ) -> (Self, VoxelData<N>) {
+        let (lod_params, chunk_size) = config.into_parts();
+        VoxelMemoryGrid::new(lod_params, memory_allocator, chunk_size, start_tlc)
+    }
+
+    /// `from_config` for a game's `VoxelSchema`, checking that its voxel types fit in its ID format first
+    pub fn from_schema<S: VoxelSchema>(
+        config: LodConfig<N>,
+        memory_allocator: Arc<dyn MemoryAllocator>,
+        start_tlc: TlcPos<i64>,
+    ) -> (Self, VoxelData<N>) {
+        S::validate();
+        VoxelMemoryGrid::from_config(config, memory_allocator, start_tlc)
+    }
+
     pub fn new(
         lod_params: [VoxelLODCreateParams; N],
         memory_allocator: Arc<dyn MemoryAllocator>,
@@ -94,9 +214,16 @@ impl<const N: usize> VoxelMemoryGrid<N> {
             "LOD params contained duplicate LODs (lvl and sublvl are the same)"
         );
 
-        let (largest_lvl, largest_sublvl, size) = lod_params
+        let (largest_lvl, largest_sublvl, size, size_y) = lod_params
             .iter()
-            .map(|lod| (lod.lvl, lod.sublvl, lod.render_area_size))
+            .map(|lod| {
+                (
+                    lod.lvl,
+                    lod.sublvl,
+                    lod.render_area_size,
+                    lod.resolved_render_area_size_y(),
+                )
+            })
             .max()
             .unwrap();
         assert!(
@@ -110,16 +237,59 @@ impl<const N: usize> VoxelMemoryGrid<N> {
                     && lod_params[i].sublvl < lod_params[i + 1].sublvl)),
             "LODs must have increasing lvl/sublvl",
         );
+        assert!(
+            lod_params
+                .iter()
+                .filter(|lod| lod.lvl == largest_lvl)
+                .all(|lod| lod.resolved_sublvl_y() == 0),
+            "Largest lvl LOD (lowest fidelity) should have sublvl_y 0"
+        );
+        assert!(
+            (0..(lod_params.len() - 1)).all(|i| lod_params[i].lvl != lod_params[i + 1].lvl
+                || lod_params[i].resolved_sublvl_y() <= lod_params[i + 1].resolved_sublvl_y()),
+            "LODs within the same lvl must have non-decreasing sublvl_y",
+        );
+        // The shader only has `SUBLVL_Y` for lvl 0 sublvls and treats every other LOD as isotropic
+        assert!(
+            lod_params
+                .iter()
+                .all(|lod| lod.lvl == 0 || lod.resolved_sublvl_y() == lod.sublvl),
+            "Only lvl 0 LODs can have a sublvl_y different from their sublvl",
+        );
+        assert!(
+            lod_params
+                .iter()
+                .all(|lod| lod.render_area_size <= size
+                    && lod.resolved_render_area_size_y() <= size_y),
+            "Largest lvl LOD (lowest fidelity) should have the largest render area on every axis",
+        );
+        if let Err(problems) = check_lod_limits(
+            &lod_params,
+            chunk_size,
+            &DeviceLimits::of(memory_allocator.device().physical_device()),
+        ) {
+            panic!("LOD config exceeds device limits:\n{}", problems);
+        }
 
         let (grid_lods, lods) = unzip_array_of_tuple(lod_params.map(|params| {
             let lod_tlc_size = lod_tlc_size(chunk_size, largest_lvl, params.lvl, params.sublvl);
-            let start_tlc = TlcPos(
-                start_tlc.0 + Vector3::from_value(((size - params.render_area_size) / 2) as i64),
+            let lod_tlc_size_y = lod_tlc_size(
+                chunk_size,
+                largest_lvl,
+                params.lvl,
+                params.resolved_sublvl_y(),
             );
+            let margin = ((size - params.render_area_size) / 2) as i64;
+            let start_tlc = start_tlc
+                + TlcVector(Vector3::new(
+                    margin,
+                    ((size_y - params.resolved_render_area_size_y()) / 2) as i64,
+                    margin,
+                ));
             VoxelMemoryGridLod::new_voxel_lod(
                 params,
                 start_tlc,
-                lod_tlc_size,
+                squared(lod_tlc_size) * lod_tlc_size_y,
                 Arc::clone(&memory_allocator),
             )
         }));
@@ -132,8 +302,14 @@ impl<const N: usize> VoxelMemoryGrid<N> {
                     sublvl: largest_sublvl,
                 },
                 chunk_size,
-                lod_block_fill_thresh: 0.00000001,
+                render_area_size: size,
+                render_area_size_y: size_y,
             },
+            edited_tlcs: HashSet::new(),
+            unsaved_tlcs: HashSet::new(),
+            user_data: HashMap::new(),
+            unsaved_user_data: HashSet::new(),
+            chunk_store: None,
         };
 
         debug_assert!(
@@ -146,8 +322,276 @@ impl<const N: usize> VoxelMemoryGrid<N> {
         (grid, VoxelData::new(lods))
     }
 
-    pub fn get_updates(&mut self) -> [Vec<VoxelLODUpdate>; N] {
-        self.lods.each_mut().map(|lod| lod.aggregate_updates(true))
+    /// Whether the TLC at `pos` has been edited since it was last loaded
+    pub fn is_edited(&self, pos: TlcPos<i64>) -> bool {
+        self.edited_tlcs.contains(&pos.0)
+    }
+
+    /// User data attached to the TLC at `pos` (see `ChunkVoxelEditor::set_user_data`)
+    pub fn user_data(&self, pos: TlcPos<i64>) -> Option<&[u8]> {
+        self.user_data.get(&pos.0).map(Vec::as_slice)
+    }
+
+    /// Save edited chunks to `store` when they scroll out of the full resolution LOD, which would otherwise
+    /// lose their edits, and chunks' user data when they scroll out of the grid. `ChunkLoader` reads chunks saved
+    /// in `store` back instead of calling the load function (see `TakenChunk::load_stored`). Call `save_edited`
+    /// before exiting to save the rest.
+    pub fn set_chunk_store(&mut self, store: Arc<dyn ChunkStore>) {
+        self.chunk_store = Some(store);
+    }
+
+    pub fn chunk_store(&self) -> Option<&Arc<dyn ChunkStore>> {
+        self.chunk_store.as_ref()
+    }
+
+    /// Number of chunks with edits or user data that haven't been saved to the chunk store yet
+    pub fn n_unsaved(&self) -> usize {
+        self.unsaved_tlcs.union(&self.unsaved_user_data).count()
+    }
+
+    /// Save every edited chunk that hasn't been saved since it was last edited to the chunk store (see
+    /// `set_chunk_store`), and any user data that changed since it was last saved, e.g. before exiting after
+    /// `World::shutdown`. Returns the number of chunks whose voxels were saved, or the errors of the chunks that
+    /// couldn't be, which stay unsaved.
+    pub fn save_edited(&mut self) -> Result<usize, String> {
+        let positions: Vec<_> = self.unsaved_tlcs.iter().map(|&pos| TlcPos(pos)).collect();
+        let r = self.save_unsaved(positions);
+        let positions: Vec<_> = self
+            .unsaved_user_data
+            .iter()
+            .map(|&pos| TlcPos(pos))
+            .collect();
+        match (r, self.save_unsaved_user_data(positions)) {
+            (Ok(n_saved), Ok(())) => Ok(n_saved),
+            (Err(e), Ok(())) | (Ok(_), Err(e)) => Err(e),
+            (Err(e1), Err(e2)) => Err(format!("{}\n{}", e1, e2)),
+        }
+    }
+
+    fn save_unsaved_user_data(&mut self, positions: Vec<TlcPos<i64>>) -> Result<(), String> {
+        let Some(store) = self.chunk_store.clone() else {
+            return Err("No chunk store set".to_string());
+        };
+        let mut errors = vec![];
+        for pos in positions {
+            let data = self.user_data.get(&pos.0).map(Vec::as_slice);
+            match store.save_user_data(pos, data) {
+                Ok(()) => {
+                    self.unsaved_user_data.remove(&pos.0);
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+
+    fn save_unsaved(&mut self, positions: Vec<TlcPos<i64>>) -> Result<usize, String> {
+        let Some(store) = self.chunk_store.clone() else {
+            return Err("No chunk store set".to_string());
+        };
+        let mut errors = vec![];
+        let mut n_saved = 0;
+        for pos in positions {
+            match self.full_lod_voxels(pos) {
+                Some(voxels) => match store.save(pos, &voxels) {
+                    Ok(()) => {
+                        self.unsaved_tlcs.remove(&pos.0);
+                        n_saved += 1;
+                    }
+                    Err(e) => errors.push(e),
+                },
+                // Not loaded anymore (e.g. taken for loading, which replaces the edits anyway)
+                None => {
+                    self.unsaved_tlcs.remove(&pos.0);
+                }
+            }
+        }
+        if errors.is_empty() {
+            Ok(n_saved)
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+
+    /// Full resolution voxels of the chunk at `pos`, if they are loaded
+    fn full_lod_voxels(&self, pos: TlcPos<i64>) -> Option<Cow<'_, ChunkVoxels>> {
+        let lod = &self.lods[0];
+        let rel = pos.0 - lod.start_tlc().0;
+        // Edited chunks can be buffer chunks, which share a slot with the buffer chunk on the other side
+        let buffer_chunk_states = [rel.x, rel.y, rel.z].map(|a| {
+            if a < 0 {
+                BufferChunkState::LoadedLower
+            } else {
+                BufferChunkState::LoadedUpper
+            }
+        });
+        let slot = lod.slot_of(pos, buffer_chunk_states)?;
+        lod.chunks()[slot].get()?.voxel_ids()
+    }
+
+    /// Save edited chunks that `shift` moves out of the full resolution LOD, since they can't be edited or
+    /// saved after that
+    fn save_chunks_leaving_full_lod(&mut self, shift: &MemGridShift) {
+        if self.chunk_store.is_none() || self.unsaved_tlcs.is_empty() {
+            return;
+        }
+        let lod = &self.lods[0];
+        let start = lod.start_tlc().0 + shift.offset_delta().cast::<i64>().unwrap();
+        let sizes = lod.axis_sizes().cast::<i64>().unwrap();
+        let leaving: Vec<_> = self
+            .unsaved_tlcs
+            .iter()
+            .filter(|pos| {
+                (0..3).any(|ax| !(start[ax]..start[ax] + sizes[ax] - 1).contains(&pos[ax]))
+            })
+            .map(|&pos| TlcPos(pos))
+            .collect();
+        if let Err(e) = self.save_unsaved(leaving) {
+            println!("WARNING: failed to save edited chunks: {}", e);
+        }
+    }
+
+    /// Save user data of chunks that `shift` moves out of the grid, since it is forgotten after that
+    fn save_user_data_leaving_grid(&mut self, shift: &MemGridShift) {
+        if self.chunk_store.is_none() || self.unsaved_user_data.is_empty() {
+            return;
+        }
+        let start = self.start_tlc().0 + shift.offset_delta().cast::<i64>().unwrap();
+        let sizes = self.axis_sizes().cast::<i64>().unwrap();
+        let leaving: Vec<_> = self
+            .unsaved_user_data
+            .iter()
+            .filter(|pos| (0..3).any(|ax| !(start[ax]..start[ax] + sizes[ax]).contains(&pos[ax])))
+            .map(|&pos| TlcPos(pos))
+            .collect();
+        if let Err(e) = self.save_unsaved_user_data(leaving) {
+            println!("WARNING: failed to save chunk user data: {}", e);
+        }
+    }
+
+    /// Number of update regions over every LOD that `get_updates` would return without an upload budget
+    pub fn n_pending_updates(&self) -> usize {
+        self.lods
+            .iter()
+            .map(|lod| lod.pending_update_regions().len())
+            .sum()
+    }
+
+    /// Take the updates to send to the GPU that fit in `budget` (usually `Renderer::upload_budget`), e.g. once per
+    /// frame. Updates that don't fit stay queued for later calls, and the chunks nearest the center of the grid
+    /// (usually the camera) are updated first. Updates are read from the grid when they are returned, so queued
+    /// chunks are always uploaded with their latest data.
+    pub fn get_updates(&mut self, budget: &UploadBudget) -> [Vec<VoxelLODUpdate>; N] {
+        if budget.is_unlimited() {
+            return self.lods.each_mut().map(|lod| lod.aggregate_updates(true));
+        }
+
+        // Pending regions of every LOD, nearest the center of the grid first. The sort is stable, so at the same
+        // distance finer LODs go first.
+        let mut pending = vec![];
+        for (lod_i, lod) in self.lods.iter_mut().enumerate() {
+            let center = lod.start_tlc().0.cast::<f64>().unwrap()
+                + lod.axis_sizes().map(|a| (a - 1) as f64 / 2.);
+            for region in lod.pending_update_regions() {
+                // Buffer chunks are at the edge of the LOD either way, so treat them as the upper one
+                let distance = lod
+                    .pos_of(region.chunk_idx, [BufferChunkState::LoadedUpper; 3])
+                    .map_or(f64::INFINITY, |pos| {
+                        (pos.0.cast::<f64>().unwrap() - center).magnitude2()
+                    });
+                let bytes = lod.update_region_bytes(&region);
+                pending.push((distance, lod_i, region, bytes));
+            }
+            lod.clear_update_regions();
+        }
+        pending.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut taken: [Vec<UpdateRegion>; N] = std::array::from_fn(|_| vec![]);
+        let (mut frame_bytes, mut n_regions) = (0, 0);
+        let mut pending = pending.into_iter();
+        for (_, lod_i, region, bytes) in pending.by_ref() {
+            if !budget.fits(frame_bytes, n_regions, bytes) {
+                self.lods[lod_i].state_mut().updated_regions.push(region);
+                break;
+            }
+            frame_bytes += bytes;
+            n_regions += 1;
+            taken[lod_i].push(region);
+        }
+        // Queue the rest for the next call
+        for (_, lod_i, region, _) in pending {
+            self.lods[lod_i].state_mut().updated_regions.push(region);
+        }
+
+        let lods = &self.lods;
+        let taken = &taken;
+        std::array::from_fn(move |i| lods[i].updates_for_regions(&taken[i]))
+    }
+
+    /// Approximate heap memory used by the voxel data of chunks in the grid, excluding chunks that are being loaded.
+    /// Chunks that are mostly empty or solid are stored sparsely and use much less (see `LodChunkData::compact`).
+    pub fn heap_bytes(&self) -> usize {
+        self.lods
+            .iter()
+            .flat_map(|lod| lod.chunks().iter().filter_map(|chunk| chunk.get()))
+            .map(|chunk| chunk.heap_bytes())
+            .sum()
+    }
+
+    /// Install chunks that were loaded outside of `ChunkLoader`, e.g. read in bulk from disk. Each item is a
+    /// TLC position and its full resolution (LOD 0) voxel IDs; lower resolution LODs are downsampled from them
+    /// the same way as when a chunk is loaded normally. Every LOD present for the chunk is marked valid and
+    /// queued to be sent to the GPU, which happens on the next `get_updates` like any other update.
+    ///
+    /// Chunks that are outside the grid or whose data is currently taken by a loading thread are not
+    /// installed; their positions are returned. If these chunks may also be queued in a `ChunkLoader`, remove
+    /// them with `ChunkLoader::dequeue` so the loader doesn't overwrite them.
+    pub fn commit_bulk_load<VE: VoxelTypeEnum>(
+        &mut self,
+        chunks: impl IntoIterator<Item = (TlcPos<i64>, ChunkVoxels)>,
+    ) -> Vec<TlcPos<i64>> {
+        let metadata = self.metadata.clone();
+        let n_voxels = metadata.tlc_size().pow(3);
+        let mut rejected = vec![];
+
+        for (pos, voxels) in chunks {
+            assert_eq!(
+                voxels.n_voxels(),
+                n_voxels,
+                "Voxels for TLC {:?} are not full resolution",
+                pos.0
+            );
+
+            let taken = match <Self as EditMemoryGridChunk<VE>>::edit_chunk(
+                self,
+                pos,
+                [BufferChunkState::Unloaded; 3],
+            ) {
+                None => None,
+                Some(mut editor) => match editor.mark_all_lods_invalid() {
+                    Err(()) => None,
+                    Ok(()) => {
+                        let item = VoxelChunkLoadQueueItemData {
+                            lods: editor.lods.each_ref().map(|lod| lod.is_some()),
+                        };
+                        Some(editor.take_data_for_loading(&item))
+                    }
+                },
+            };
+            let Some(mut taken) = taken else {
+                rejected.push(pos);
+                continue;
+            };
+
+            taken.load_full_lod_voxels(pos, &voxels, &metadata);
+            taken.return_data(self);
+        }
+
+        rejected
     }
 
     fn apply_to_lods_and_queue_chunks_mut<
@@ -172,6 +616,28 @@ impl<const N: usize> VoxelMemoryGrid<N> {
         chunks.into_values().collect()
     }
 
+    /// Forget edits and user data of chunks that are no longer in the grid, unless they are pinned
+    fn forget_chunks_outside_grid(&mut self) {
+        let (start, sizes) = (self.start_tlc().0, self.axis_sizes().cast::<i64>().unwrap());
+        let lods = &self.lods;
+        let keep = |pos: &Point3<i64>| {
+            (0..3).all(|ax| (start[ax]..start[ax] + sizes[ax]).contains(&pos[ax]))
+                || lods[0].is_pinned(TlcPos(*pos))
+        };
+        self.edited_tlcs.retain(keep);
+        self.unsaved_tlcs.retain(keep);
+        self.user_data.retain(|pos, _| keep(pos));
+        self.unsaved_user_data.retain(keep);
+    }
+
+    /// Set how many chunks can be pinned at once (`DEFAULT_MAX_PINNED_CHUNKS` by default). Chunks that are already
+    /// pinned stay pinned.
+    pub fn set_max_pinned_chunks(&mut self, max: usize) {
+        for lod in self.lods.iter_mut() {
+            lod.set_max_pinned_chunks(max);
+        }
+    }
+
     fn largest_lod(&self) -> &VoxelMemoryGridLod {
         self.lod(
             self.metadata().largest_lod.lvl,
@@ -190,11 +656,61 @@ impl<const N: usize> MemoryGridLoadChunks for VoxelMemoryGrid<N> {
 
     fn shift(
         &mut self,
-        shift: &crate::world::mem_grid::MemGridShift,
+        shift: &MemGridShift,
     ) -> Vec<ChunkLoadQueueItem<Self::ChunkLoadQueueItemData>> {
+        self.save_chunks_leaving_full_lod(shift);
+        self.save_user_data_leaving_grid(shift);
         let r = self.apply_to_lods_and_queue_chunks_mut(|lod| lod.shift(shift));
+        self.forget_chunks_outside_grid();
         r
     }
+
+    fn invalidate_region(
+        &mut self,
+        min_tlc: TlcPos<i64>,
+        max_tlc: TlcPos<i64>,
+    ) -> Vec<ChunkLoadQueueItem<Self::ChunkLoadQueueItemData>> {
+        self.apply_to_lods_and_queue_chunks_mut(|lod| lod.invalidate_region(min_tlc, max_tlc))
+    }
+}
+
+impl<const N: usize> MemoryGridRegenerateChunks for VoxelMemoryGrid<N> {
+    fn queue_regenerate_unedited(
+        &mut self,
+    ) -> Vec<ChunkLoadQueueItem<Self::ChunkLoadQueueItemData>> {
+        let mut chunks = self.apply_to_lods_and_queue_chunks_mut(|lod| lod.queue_load_all());
+        chunks.retain(|item| !self.edited_tlcs.contains(&item.pos.0));
+        chunks
+    }
+}
+
+/// Chunks are pinned in every LOD, and each LOD keeps the data of a pinned chunk that leaves it in its own overflow
+/// list (see `MemoryGridLayer::overflowed_chunk`). The edits and user data of pinned chunks are kept while they are
+/// outside of the grid. The pinned chunk stats are the largest LOD's, so a chunk only counts as overflowed once it
+/// leaves the grid.
+impl<const N: usize> MemoryGridPinChunks for VoxelMemoryGrid<N> {
+    fn pin_chunk(&mut self, pos: TlcPos<i64>) -> Result<(), ()> {
+        let was_pinned = self.lods[0].is_pinned(pos);
+        let r = self.lods.iter_mut().try_for_each(|lod| lod.pin_chunk(pos));
+        if r.is_err() && !was_pinned {
+            // Don't leave the chunk pinned in only some of the LODs
+            self.unpin_chunk(pos);
+        }
+        r
+    }
+
+    fn unpin_chunk(&mut self, pos: TlcPos<i64>) -> bool {
+        let mut was_pinned = false;
+        for lod in self.lods.iter_mut() {
+            was_pinned |= lod.unpin_chunk(pos);
+        }
+        self.forget_chunks_outside_grid();
+        was_pinned
+    }
+
+    fn pinned_chunk_stats(&self) -> PinnedChunkStats {
+        self.largest_lod().pinned_chunk_stats()
+    }
 }
 
 impl<const N: usize> MemoryGrid for VoxelMemoryGrid<N> {
@@ -202,15 +718,50 @@ impl<const N: usize> MemoryGrid for VoxelMemoryGrid<N> {
         self.largest_lod().size()
     }
 
+    fn size_y(&self) -> usize {
+        self.largest_lod().size_y()
+    }
+
     fn start_tlc(&self) -> TlcPos<i64> {
         self.largest_lod().start_tlc()
     }
 }
 
+impl<const N: usize> MemoryGridSelfCheck for VoxelMemoryGrid<N> {
+    fn self_check(
+        &self,
+        name: &str,
+        buffer_chunk_states: [BufferChunkState; 3],
+        report: &mut SelfCheckReport,
+    ) {
+        for lod in self.lods.iter() {
+            let lod_name = format!(
+                "{} LOD {}.{}",
+                name,
+                lod.metadata().extra().lvl,
+                lod.metadata().extra().sublvl
+            );
+            lod.self_check(&lod_name, buffer_chunk_states, report);
+            check_centered_in(&lod_name, lod, self, report);
+        }
+    }
+}
+
+/// `ChunkVoxelEditor` for the voxel types of the `VoxelSchema` `S`
+pub type SchemaChunkEditor<'a, S, const N: usize> =
+    ChunkVoxelEditor<'a, <S as VoxelSchema>::VoxelType, N>;
+
 #[derive(Debug, Getters)]
 pub struct ChunkVoxelEditor<'a, VE: VoxelTypeEnum, const N: usize> {
     #[getset(get = "pub")]
     lods: [Option<LodChunkEditorMaybeUnloaded<'a, VE>>; N], // When this chunk is too far away for an LOD to have data, it is `None` here
+    pos: TlcPos<i64>,
+    edited_tlcs: &'a mut HashSet<Point3<i64>>,
+    unsaved_tlcs: &'a mut HashSet<Point3<i64>>,
+    user_data: &'a mut HashMap<Point3<i64>, Vec<u8>>,
+    unsaved_user_data: &'a mut HashSet<Point3<i64>>,
+    chunk_store: Option<&'a Arc<dyn ChunkStore>>,
+    metadata: &'a VoxelMemoryGridMetadata,
 }
 
 impl<VE: VoxelTypeEnum, const N: usize> EditMemoryGridChunk<VE> for VoxelMemoryGrid<N> {
@@ -231,6 +782,13 @@ impl<VE: VoxelTypeEnum, const N: usize> EditMemoryGridChunk<VE> for VoxelMemoryG
                     buffer_chunk_states,
                 )
             }),
+            pos,
+            edited_tlcs: &mut self.edited_tlcs,
+            unsaved_tlcs: &mut self.unsaved_tlcs,
+            user_data: &mut self.user_data,
+            unsaved_user_data: &mut self.unsaved_user_data,
+            chunk_store: self.chunk_store.as_ref(),
+            metadata: &self.metadata,
         };
         if e.lods.iter().all(|lod| lod.is_none()) {
             None
@@ -277,6 +835,10 @@ impl<'a, const N: usize, VE: VoxelTypeEnum>
                 false => *lod = None,
             }
         }
+        // Loading replaces the chunk's voxels, so it no longer has edits. User data is kept, since it is newer than
+        // any that is loaded.
+        self.edited_tlcs.remove(&self.pos.0);
+        self.unsaved_tlcs.remove(&self.pos.0);
         TakenChunkVoxelEditor::new(self).unwrap()
     }
 
@@ -286,6 +848,28 @@ impl<'a, const N: usize, VE: VoxelTypeEnum>
 }
 
 impl<'a, VE: VoxelTypeEnum, const N: usize> ChunkVoxelEditor<'a, VE, N> {
+    /// Game data attached to this chunk, e.g. quest state or ownership claims
+    pub fn user_data(&self) -> Option<&[u8]> {
+        self.user_data.get(&self.pos.0).map(Vec::as_slice)
+    }
+
+    /// Attach game data to this chunk, replacing any that was attached before, or remove it if `data` is `None`.
+    /// The data is opaque to the engine: serialize it however the game likes. It is kept as long as the chunk is in
+    /// the grid and saved to the chunk store (see `VoxelMemoryGrid::set_chunk_store`) with `save_edited` or when
+    /// the chunk scrolls out of the grid, and read back when the chunk is loaded with
+    /// `ChunkLoader`.
+    pub fn set_user_data(&mut self, data: Option<Vec<u8>>) {
+        match data {
+            Some(data) => {
+                self.user_data.insert(self.pos.0, data);
+            }
+            None => {
+                self.user_data.remove(&self.pos.0);
+            }
+        }
+        self.unsaved_user_data.insert(self.pos.0);
+    }
+
     pub fn mark_all_lods_invalid(&mut self) -> Result<(), ()> {
         let mut r = Ok(());
         for lod_o in self.lods.iter_mut() {
@@ -312,10 +896,8 @@ impl<'a, VE: VoxelTypeEnum, const N: usize> ChunkVoxelEditor<'a, VE, N> {
             return Err(());
         }
 
-        let mut iter = self.lods.iter_mut();
-        let mut first_lod = iter
-            .next()
-            .unwrap()
+        let (first, rest) = self.lods.split_at_mut(1);
+        let mut first_lod = first[0]
             .as_mut()
             .expect("Tried to set_voxel in a chunk where full LOD was not loaded")
             .as_loaded()
@@ -324,27 +906,205 @@ impl<'a, VE: VoxelTypeEnum, const N: usize> ChunkVoxelEditor<'a, VE, N> {
             LodChunkEditorVariantMut::WithVoxels(lod) => lod,
             LodChunkEditorVariantMut::WithoutVoxels(_) => panic!(),
         };
+        let was_visible = first_lod.data().bitmask().get(index);
         first_lod.set_voxel(index, voxel_typ);
         let first_lod = first_lod.data();
 
-        for lod in iter.filter_map(|x| x.as_mut()) {
-            let (lvl, sublvl) = (lod.lvl(), lod.sublvl());
-            let lod_pos = VoxelPosInLod {
-                pos: pos.0,
-                lvl: 0,
-                sublvl: 0,
+        let full_lod_pos = VoxelPosInLod::in_full_lod(pos);
+        // Bitmask-only LODs are updated from the previous bitmask-only LOD (or the full LOD) instead of the full
+        // LOD, so only the children of the one voxel covering the edit are read. Once a bit doesn't change, the
+        // bits covering it in coarser bitmask-only LODs can't change either, so they are skipped.
+        let mut bitmask_changed = first_lod.bitmask().get(index) != was_visible;
+        let mut finer_bitmask_lod: Option<usize> = None;
+        for i in 0..rest.len() {
+            let (finer_lods, lods) = rest.split_at_mut(i);
+            let Some(lod) = lods[0].as_mut() else {
+                continue;
+            };
+            let (lvl, sublvl, sublvl_y) = (lod.lvl(), lod.sublvl(), lod.sublvl_y());
+            let lod_pos = full_lod_pos.in_other_lod(lvl, sublvl, sublvl_y, meta.chunk_size);
+            let lod_index = lod_pos.index(meta.chunk_size, meta.largest_lod.lvl);
+            let downsample = lod.downsample().clone();
+            let mut lod = lod.as_loaded().unwrap();
+            match lod.with_voxel_ids_mut() {
+                LodChunkEditorVariantMut::WithVoxels(mut lod) => {
+                    lod.update_voxel_from_lower_lod::<VE>(
+                        &first_lod,
+                        lod_pos,
+                        lod_index,
+                        0,
+                        0,
+                        0,
+                        meta.chunk_size,
+                        meta.largest_lod.lvl,
+                        &downsample,
+                    );
+                }
+                LodChunkEditorVariantMut::WithoutVoxels(mut lod) => {
+                    if bitmask_changed {
+                        let (finer_bitmask, finer_lvl, finer_sublvl, finer_sublvl_y) =
+                            match finer_bitmask_lod {
+                                None => (*first_lod.bitmask(), 0, 0, 0),
+                                Some(j) => {
+                                    let finer = finer_lods[j].as_ref().unwrap();
+                                    // Made dense when it was edited above
+                                    let LodChunkDataVariant::WithoutVoxels(finer_bitmask) =
+                                        finer.data().get().unwrap().check_voxel_ids()
+                                    else {
+                                        unreachable!()
+                                    };
+                                    (finer_bitmask, finer.lvl(), finer.sublvl(), finer.sublvl_y())
+                                }
+                            };
+                        bitmask_changed = lod.update_bitmask_bit_from_lower_lod(
+                            lod_pos,
+                            lod_index,
+                            finer_bitmask,
+                            finer_lvl,
+                            finer_sublvl,
+                            finer_sublvl_y,
+                            meta.chunk_size,
+                            meta.largest_lod.lvl,
+                        );
+                    }
+                    finer_bitmask_lod = Some(i);
+                }
             }
-            .in_other_lod(lvl, sublvl, meta.chunk_size);
-            lod.as_loaded().unwrap().update_voxel_from_lower_lod::<VE>(
-                lod_pos,
-                lod_pos.index(meta.chunk_size, meta.largest_lod.lvl),
-                &first_lod,
-                0,
-                0,
-                meta.chunk_size,
-                meta.largest_lod.lvl,
-            );
         }
+        self.edited_tlcs.insert(self.pos.0);
+        self.unsaved_tlcs.insert(self.pos.0);
+
+        Ok(())
+    }
+
+    /// Set every voxel in `range` (positions within this TLC in LOD 0 voxels, end exclusive and clamped to the
+    /// TLC) to `f(pos)`. Much faster than calling `set_voxel` for each voxel when filling a region: each
+    /// affected voxel in the other LODs is recomputed only once and GPU update regions are coalesced into
+    /// runs. Like `set_voxel`, requires that this TLC has full LOD.
+    pub fn set_voxels_in_region(
+        &mut self,
+        range: Range<VoxelPos<u32>>,
+        f: impl Fn(VoxelPos<u32>) -> VE,
+        meta: &VoxelMemoryGridMetadata,
+    ) -> Result<(), ()> {
+        if self.lods.iter_mut().any(|lod| match lod {
+            None => false,
+            Some(lod) => lod.as_loaded().is_none(),
+        }) {
+            return Err(());
+        }
+        let end = range.end.0.map(|a| a.min(meta.tlc_size() as u32));
+        let start = range.start.0;
+        if start.x >= end.x || start.y >= end.y || start.z >= end.z {
+            return Ok(());
+        }
+
+        let (first, rest) = self.lods.split_at_mut(1);
+        let mut first_lod = first[0]
+            .as_mut()
+            .expect("Tried to set_voxels_in_region in a chunk where full LOD was not loaded")
+            .as_loaded()
+            .unwrap();
+        let mut first_lod = match first_lod.with_voxel_ids_mut() {
+            LodChunkEditorVariantMut::WithVoxels(lod) => lod,
+            LodChunkEditorVariantMut::WithoutVoxels(_) => panic!(),
+        };
+        let mut voxels = vec![];
+        for y in start.y..end.y {
+            for z in start.z..end.z {
+                for x in start.x..end.x {
+                    let pos = VoxelPos(Point3 { x, y, z });
+                    let index = VoxelPosInLod::in_full_lod(pos)
+                        .index(meta.chunk_size, meta.largest_lod.lvl);
+                    voxels.push((index, f(pos)));
+                }
+            }
+        }
+        first_lod.set_voxels(voxels);
+        let first_lod = first_lod.data();
+
+        // Voxels of an LOD that cover the region, each only once
+        let lod_voxels = |lvl: u8, sublvl: u8, sublvl_y: u8| {
+            let corner = |pos: Point3<u32>| {
+                VoxelPosInLod::in_full_lod(VoxelPos(pos))
+                    .in_other_lod(lvl, sublvl, sublvl_y, meta.chunk_size)
+                    .pos
+            };
+            let (lo, hi) = (corner(start), corner(end.map(|a| a - 1)));
+            let mut voxels = vec![];
+            for y in lo.y..=hi.y {
+                for z in lo.z..=hi.z {
+                    for x in lo.x..=hi.x {
+                        let pos = VoxelPosInLod {
+                            pos: Point3 { x, y, z },
+                            lvl,
+                            sublvl,
+                            sublvl_y,
+                        };
+                        voxels.push((pos, pos.index(meta.chunk_size, meta.largest_lod.lvl)));
+                    }
+                }
+            }
+            voxels
+        };
+
+        // Like in `set_voxel`, bitmask-only LODs are updated from the previous bitmask-only LOD, and skipped once
+        // a finer one didn't change
+        let mut bitmask_changed = true;
+        let mut finer_bitmask_lod: Option<usize> = None;
+        for i in 0..rest.len() {
+            let (finer_lods, lods) = rest.split_at_mut(i);
+            let Some(lod) = lods[0].as_mut() else {
+                continue;
+            };
+            let voxels = lod_voxels(lod.lvl(), lod.sublvl(), lod.sublvl_y());
+            let downsample = lod.downsample().clone();
+            let mut lod = lod.as_loaded().unwrap();
+            match lod.with_voxel_ids_mut() {
+                LodChunkEditorVariantMut::WithVoxels(mut lod) => {
+                    lod.update_voxels_from_lower_lod::<VE>(
+                        &first_lod,
+                        voxels,
+                        0,
+                        0,
+                        0,
+                        meta.chunk_size,
+                        meta.largest_lod.lvl,
+                        &downsample,
+                    );
+                }
+                LodChunkEditorVariantMut::WithoutVoxels(mut lod) => {
+                    if bitmask_changed {
+                        let (finer_bitmask, finer_lvl, finer_sublvl, finer_sublvl_y) =
+                            match finer_bitmask_lod {
+                                None => (*first_lod.bitmask(), 0, 0, 0),
+                                Some(j) => {
+                                    let finer = finer_lods[j].as_ref().unwrap();
+                                    // Made dense when it was edited above
+                                    let LodChunkDataVariant::WithoutVoxels(finer_bitmask) =
+                                        finer.data().get().unwrap().check_voxel_ids()
+                                    else {
+                                        unreachable!()
+                                    };
+                                    (finer_bitmask, finer.lvl(), finer.sublvl(), finer.sublvl_y())
+                                }
+                            };
+                        bitmask_changed = lod.update_bitmask_bits_from_lower_lod(
+                            voxels,
+                            finer_bitmask,
+                            finer_lvl,
+                            finer_sublvl,
+                            finer_sublvl_y,
+                            meta.chunk_size,
+                            meta.largest_lod.lvl,
+                        );
+                    }
+                    finer_bitmask_lod = Some(i);
+                }
+            }
+        }
+        self.edited_tlcs.insert(self.pos.0);
+        self.unsaved_tlcs.insert(self.pos.0);
 
         Ok(())
     }
@@ -354,17 +1114,81 @@ impl<'a, VE: VoxelTypeEnum, const N: usize> ChunkVoxelEditor<'a, VE, N> {
 pub struct TakenChunkVoxelEditor<VE: VoxelTypeEnum, const N: usize> {
     #[get = "pub"]
     lods: [Option<TakenLodChunk<VE>>; N], // When this chunk is too far away for an LOD to have data, it is `None` here
+    pos: TlcPos<i64>,
+    // Set when loading; only used if the chunk doesn't have user data in the grid already
+    user_data: Option<Vec<u8>>,
+    // Chunk store of the grid, if it has one, to load the chunk from in `load_stored`
+    store: Option<(Arc<dyn ChunkStore>, VoxelMemoryGridMetadata)>,
 }
 
 impl<VE: VoxelTypeEnum, const N: usize> TakenChunk for TakenChunkVoxelEditor<VE, N> {
     type MemoryGrid = VoxelMemoryGrid<N>;
 
-    fn return_data(self, grid: &mut VoxelMemoryGrid<N>) {
-        self.queue_to_sync_to_gpu(grid);
+    fn return_data(mut self, grid: &mut VoxelMemoryGrid<N>) -> bool {
+        // A pinned chunk that comes back into the full resolution LOD gets its voxels from before it left back. The
+        // other LODs were loaded along with it, so they are downsampled from those voxels to match. Since these are
+        // the chunk's own voxels rather than generated ones, it counts as edited.
+        let restored = self.lods[0].as_ref().and_then(|lod| {
+            grid.lods[0]
+                .restoring_chunk(lod.chunk_idx(), lod.version())
+                .and_then(|data| data.voxel_ids())
+                .map(Cow::into_owned)
+        });
+        if let Some(voxels) = restored {
+            self.load_full_lod_voxels(self.pos, &voxels, &grid.metadata);
+            grid.edited_tlcs.insert(self.pos.0);
+        }
+
+        let mut current = true;
         for (lod, editor_lod) in grid.lods.iter_mut().zip(self.lods) {
             if let Some(elod) = editor_lod {
-                elod.return_data(lod);
+                let chunk_idx = elod.chunk_idx();
+                if elod.return_data(lod) {
+                    // Only sync current data to the GPU; stale data will be reloaded
+                    let n_voxels = lod.metadata().extra().voxels_per_tlc;
+                    lod.state_mut().updated_regions.push(UpdateRegion {
+                        chunk_idx,
+                        voxel_idx: 0,
+                        n_voxels,
+                    });
+                } else {
+                    current = false;
+                }
+            }
+        }
+        // User data that changed in the grid (including being removed) is newer than what was loaded
+        if current && !grid.unsaved_user_data.contains(&self.pos.0) {
+            if let Some(data) = self.user_data {
+                grid.user_data.entry(self.pos.0).or_insert(data);
+            }
+        }
+        current
+    }
+
+    /// Load the chunk from the voxels saved in the grid's chunk store (see `VoxelMemoryGrid::set_chunk_store`), if
+    /// there are any. User data saved for the chunk is loaded either way.
+    fn load_stored(&mut self) -> bool {
+        let Some((store, metadata)) = self.store.take() else {
+            return false;
+        };
+        if let Some(data) = store.load_user_data(self.pos) {
+            self.user_data = Some(data);
+        }
+        match store.load(self.pos) {
+            Some(voxels) if voxels.n_voxels() == metadata.tlc_size().pow(3) => {
+                self.load_full_lod_voxels(self.pos, &voxels, &metadata);
+                true
             }
+            Some(voxels) => {
+                println!(
+                    "WARNING: saved chunk {:?} has {} voxels instead of {}, generating it instead",
+                    self.pos.0,
+                    voxels.n_voxels(),
+                    metadata.tlc_size().pow(3)
+                );
+                false
+            }
+            None => false,
         }
     }
 }
@@ -380,6 +1204,11 @@ impl<VE: VoxelTypeEnum, const N: usize> TakenChunkVoxelEditor<VE, N> {
         } else {
             Ok(Self {
                 lods: lods.map(|l| l.unwrap()),
+                pos: ce.pos,
+                user_data: None,
+                store: ce
+                    .chunk_store
+                    .map(|store| (Arc::clone(store), ce.metadata.clone())),
             })
         }
     }
@@ -387,7 +1216,10 @@ impl<VE: VoxelTypeEnum, const N: usize> TakenChunkVoxelEditor<VE, N> {
     /// Load a chunk using `gen_func` to generate the voxel data where needed.
     /// This will load all non-`None` LODs in `self`, so if a LOD exists
     /// but shouldn't be loaded, the reference to that LOD should be set to `None` in `self`.
-    pub fn load_new<F: Fn(TlcPos<i64>, u8, u8, &mut ChunkVoxels, usize, u8)>(
+    ///
+    /// `gen_func` is passed the chunk position, lvl, sublvl, sublvl_y, voxels to fill, TLC size and largest lvl.
+    /// Afterwards, LODs that are mostly empty or solid are stored sparsely (see `LodChunkData::compact`).
+    pub fn load_new<F: Fn(TlcPos<i64>, u8, u8, u8, &mut ChunkVoxels, usize, u8)>(
         &mut self,
         pos: TlcPos<i64>,
         gen_func: F,
@@ -398,6 +1230,7 @@ impl<VE: VoxelTypeEnum, const N: usize> TakenChunkVoxelEditor<VE, N> {
             index: usize,
             lvl: u8,
             sublvl: u8,
+            sublvl_y: u8,
         }
 
         // Last lvl/sublvl that contained voxel ID info
@@ -410,6 +1243,8 @@ impl<VE: VoxelTypeEnum, const N: usize> TakenChunkVoxelEditor<VE, N> {
             if let Some(lod_data) = lod {
                 let lvl = lod_data.lvl();
                 let sublvl = lod_data.sublvl();
+                let sublvl_y = lod_data.sublvl_y();
+                let downsample = lod_data.downsample().clone();
                 let data = lod_data.data_mut();
 
                 // Need to load the info in this chunk
@@ -432,10 +1267,13 @@ impl<VE: VoxelTypeEnum, const N: usize> TakenChunkVoxelEditor<VE, N> {
                                 last_vox_data,
                                 lvl,
                                 sublvl,
+                                sublvl_y,
                                 last_vox_lod.lvl,
                                 last_vox_lod.sublvl,
+                                last_vox_lod.sublvl_y,
                                 metadata.chunk_size,
                                 metadata.largest_lod().lvl,
+                                &downsample,
                             );
                         } else {
                             // Generate voxels
@@ -443,6 +1281,7 @@ impl<VE: VoxelTypeEnum, const N: usize> TakenChunkVoxelEditor<VE, N> {
                                 pos,
                                 lvl,
                                 sublvl,
+                                sublvl_y,
                                 data.overwrite::<VE>().chunk.raw_voxel_ids_mut(),
                                 metadata.tlc_size(),
                                 metadata.largest_lod().lvl,
@@ -452,6 +1291,7 @@ impl<VE: VoxelTypeEnum, const N: usize> TakenChunkVoxelEditor<VE, N> {
                         last_vox_lod = Some(LodId {
                             lvl,
                             sublvl,
+                            sublvl_y,
                             index: i,
                         });
                     }
@@ -459,15 +1299,17 @@ impl<VE: VoxelTypeEnum, const N: usize> TakenChunkVoxelEditor<VE, N> {
                         // If this chunk only has a bitmask, update from previous LOD bitmask
                         update_bitmask_from_lower_lod_untracked(
                             data,
-                            lods_to_index[first_bitmask_lod.as_ref().unwrap().index]
+                            &lods_to_index[first_bitmask_lod.as_ref().unwrap().index]
                                 .as_ref()
                                 .unwrap()
                                 .data()
                                 .bitmask(),
                             lvl,
                             sublvl,
+                            sublvl_y,
                             first_bitmask_lod.as_ref().unwrap().lvl,
                             first_bitmask_lod.as_ref().unwrap().sublvl,
+                            first_bitmask_lod.as_ref().unwrap().sublvl_y,
                             metadata.chunk_size(),
                             metadata.largest_lod().lvl,
                         )
@@ -478,22 +1320,84 @@ impl<VE: VoxelTypeEnum, const N: usize> TakenChunkVoxelEditor<VE, N> {
                     first_bitmask_lod = Some(LodId {
                         lvl,
                         sublvl,
+                        sublvl_y,
                         index: i,
                     });
                 }
             }
         });
+
+        // Only after all LODs are loaded, since lower resolution LODs are loaded from the dense data of higher
+        // resolution ones
+        for lod in self.lods.iter_mut().flatten() {
+            lod.data_mut().compact();
+        }
     }
 
-    // pub unsafe fn set_all_lods_valid(&mut self) {
-    //     for lod_o in self.lods.iter_mut() {
-    //         if let Some(lod) = lod_o {
-    //             unsafe {
-    //                 (&mut **lod.data_mut()).set_valid();
-    //             }
-    //         }
-    //     }
-    // }
+    /// Load a chunk from its full resolution (LOD 0) voxels, downsampling them for the LODs that are lower
+    /// resolution
+    pub fn load_full_lod_voxels(
+        &mut self,
+        pos: TlcPos<i64>,
+        voxels: &ChunkVoxels,
+        metadata: &VoxelMemoryGridMetadata,
+    ) {
+        let (chunk_size, largest_lvl) = (metadata.chunk_size, metadata.largest_lod.lvl);
+        // Only the first LOD with voxel IDs is generated, but which one that is depends on where the chunk is
+        let downsamples: Vec<((u8, u8, u8), Downsample)> = self
+            .lods
+            .iter()
+            .flatten()
+            .map(|lod| {
+                (
+                    (lod.lvl(), lod.sublvl(), lod.sublvl_y()),
+                    lod.downsample().clone(),
+                )
+            })
+            .collect();
+        self.load_new(
+            pos,
+            |_, lvl, sublvl, sublvl_y, out, _, _| {
+                if (lvl, sublvl, sublvl_y) == (0, 0, 0) {
+                    out.clone_from(voxels);
+                    return;
+                }
+                let downsample = downsamples
+                    .iter()
+                    .find(|(lod, _)| *lod == (lvl, sublvl, sublvl_y))
+                    .map(|(_, downsample)| downsample)
+                    .unwrap();
+                // This chunk doesn't have full resolution in the grid, so downsample to the first LOD it has
+                apply_to_voxels_in_lod(lvl, sublvl, sublvl_y, chunk_size, largest_lvl, |vpos| {
+                    let index = vpos.index(chunk_size, largest_lvl);
+                    let mut counts = DownsampleCounts::default();
+                    apply_to_voxel_indices_in_lower_lod(
+                        vpos,
+                        index,
+                        0,
+                        0,
+                        0,
+                        chunk_size,
+                        largest_lvl,
+                        |idx| counts.add(VE::from_id(voxels[idx])),
+                    );
+                    out[index] = counts.result(downsample).unwrap_or(VE::empty()).id();
+                });
+            },
+            metadata,
+        );
+    }
+
+    /// User data to give the chunk when it is returned, if it doesn't have any in the grid already
+    pub fn user_data(&self) -> Option<&[u8]> {
+        self.user_data.as_deref()
+    }
+
+    /// Set initial user data for the chunk, e.g. while generating it. It is only used if the chunk doesn't have
+    /// any user data in the grid when it is returned, and it isn't saved to the chunk store until it changes.
+    pub fn set_user_data(&mut self, data: Option<Vec<u8>>) {
+        self.user_data = data;
+    }
 
     // For each LOD of this chunk, add a region to the `updated_regions` covering this
     // chunk's data so that it is sync'd to GPU
@@ -518,15 +1422,9 @@ pub fn voxel_pos_in_tlc_from_global_pos(
     chunk_size: ChunkSize,
     largest_chunk_lvl: u8,
 ) -> (TlcPos<i64>, VoxelPos<u32>) {
-    let tlc_size = chunk_size.size().pow(largest_chunk_lvl as u32);
-    let global_tlc = global_pos.0 / tlc_size as i64;
     (
-        TlcPos(global_tlc),
-        VoxelPos(
-            (global_pos.0 - (global_tlc * tlc_size as i64).to_vec())
-                .cast::<u32>()
-                .unwrap(),
-        ),
+        global_pos.to_tlc(chunk_size, largest_chunk_lvl),
+        global_pos.in_tlc(chunk_size, largest_chunk_lvl),
     )
 }
 
@@ -536,14 +1434,13 @@ pub fn global_voxel_pos_from_pos_in_tlc(
     chunk_size: ChunkSize,
     largest_chunk_lvl: u8,
 ) -> VoxelPos<i64> {
-    let tlc_size = chunk_size.size().pow(largest_chunk_lvl as u32) as i64;
-    VoxelPos(tlc.0 * tlc_size + pos.0.map(|a| a as i64).to_vec())
+    pos.in_global(tlc, chunk_size, largest_chunk_lvl)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use cgmath::Point3;
+    use cgmath::{Array, Point3};
     use enum_iterator::Sequence;
     use num_derive::{FromPrimitive, ToPrimitive};
 
@@ -551,8 +1448,14 @@ mod tests {
         loader::LayerChunk,
         renderer::test_context::TestContext,
         voxel_type::{Material, VoxelTypeDefinition},
-        world::{camera::Camera, mem_grid::voxel::ChunkBitmask, World},
+        world::{
+            camera::Camera,
+            mem_grid::{voxel::ChunkBitmask, ShiftGridAxis, ShiftGridAxisVal},
+            storage::FileChunkStore,
+            World,
+        },
     };
+    use std::fs;
 
     const CHUNK_SIZE: ChunkSize = ChunkSize::new(3);
 
@@ -599,47 +1502,63 @@ mod tests {
             y: -6,
             z: -6,
         });
+        let mut bindings = BindingMapBuilder::new();
         let (mg, _) = VoxelMemoryGrid::new(
             [
                 VoxelLODCreateParams {
                     voxel_resolution: 1,
                     lvl: 0,
                     sublvl: 0,
+                    sublvl_y: None,
                     render_area_size: 1,
-                    bitmask_binding: 8,
-                    voxel_ids_binding: Some(4),
+                    render_area_size_y: None,
+                    bitmask_binding: bindings.register_at("lod_0_0_bitmask", 8),
+                    voxel_ids_binding: Some(bindings.register_at("lod_0_0_voxel_ids", 4)),
+                    downsample: Downsample::default(),
                 },
                 VoxelLODCreateParams {
                     voxel_resolution: 2,
                     lvl: 0,
                     sublvl: 1,
+                    sublvl_y: None,
                     render_area_size: 3,
-                    bitmask_binding: 9,
-                    voxel_ids_binding: Some(5),
+                    render_area_size_y: None,
+                    bitmask_binding: bindings.register_at("lod_0_1_bitmask", 9),
+                    voxel_ids_binding: Some(bindings.register_at("lod_0_1_voxel_ids", 5)),
+                    downsample: Downsample::default(),
                 },
                 VoxelLODCreateParams {
                     voxel_resolution: 4,
                     lvl: 0,
                     sublvl: 2,
+                    sublvl_y: None,
                     render_area_size: 7,
-                    bitmask_binding: 10,
-                    voxel_ids_binding: Some(6),
+                    render_area_size_y: None,
+                    bitmask_binding: bindings.register_at("lod_0_2_bitmask", 10),
+                    voxel_ids_binding: Some(bindings.register_at("lod_0_2_voxel_ids", 6)),
+                    downsample: Downsample::default(),
                 },
                 VoxelLODCreateParams {
                     voxel_resolution: 8,
                     lvl: 1,
                     sublvl: 0,
+                    sublvl_y: None,
                     render_area_size: 15,
-                    bitmask_binding: 11,
-                    voxel_ids_binding: Some(7),
+                    render_area_size_y: None,
+                    bitmask_binding: bindings.register_at("lod_1_0_bitmask", 11),
+                    voxel_ids_binding: Some(bindings.register_at("lod_1_0_voxel_ids", 7)),
+                    downsample: Downsample::default(),
                 },
                 VoxelLODCreateParams {
                     voxel_resolution: 64,
                     lvl: 2,
                     sublvl: 0,
+                    sublvl_y: None,
                     render_area_size: 15,
-                    bitmask_binding: 12,
+                    render_area_size_y: None,
+                    bitmask_binding: bindings.register_at("lod_2_0_bitmask", 12),
                     voxel_ids_binding: None,
+                    downsample: Downsample::default(),
                 },
             ],
             Arc::clone(&renderer_context.memory_allocator) as Arc<dyn MemoryAllocator>,
@@ -719,4 +1638,356 @@ mod tests {
             .bitmask()
             .get(0));
     }
+
+    #[test]
+    fn test_commit_bulk_load() {
+        let renderer_context = TestContext::new();
+        let mut bindings = BindingMapBuilder::new();
+        let (mut mg, _) = VoxelMemoryGrid::new(
+            VoxelMemoryGrid::standard_config(7, CHUNK_SIZE, &mut bindings),
+            Arc::clone(&renderer_context.memory_allocator) as Arc<dyn MemoryAllocator>,
+            CHUNK_SIZE,
+            TlcPos(Point3::from_value(-6)),
+        );
+
+        // The only chunk with full resolution
+        let center = TlcPos(Point3::from_value(1));
+        let outside = TlcPos(Point3 { x: 100, y: 0, z: 0 });
+        let mut voxels = ChunkVoxels::new_blank(mg.metadata().tlc_size().pow(3));
+        voxels[0] = Block::SOLID as u8;
+
+        let rejected = mg.commit_bulk_load::<Block>([(center, voxels.clone()), (outside, voxels)]);
+        assert_eq!(rejected, vec![outside]);
+
+        let editor = <VoxelMemoryGrid<5> as EditMemoryGridChunk<Block>>::edit_chunk(
+            &mut mg,
+            center,
+            [BufferChunkState::Unloaded; 3],
+        )
+        .unwrap();
+        assert!(editor.lods().iter().all(|lod| lod.is_some()));
+        for lod in editor.lods().iter().flatten() {
+            let chunk = lod.data().get().expect("Committed chunk was not valid");
+            assert!(chunk.bitmask().get(0));
+            assert!(!chunk.bitmask().get(1));
+        }
+        // Only one voxel is set, so the full resolution LOD is stored sparsely
+        let full_lod = editor.lods()[0].as_ref().unwrap().data().get().unwrap();
+        assert!(full_lod.is_sparse());
+        assert_eq!(full_lod.voxel_id(0), Some(Block::SOLID as u8));
+        drop(editor);
+        assert!(mg
+            .lods
+            .iter()
+            .all(|lod| lod.state().updated_regions.len() == 1));
+    }
+
+    #[test]
+    fn test_regenerate_unedited() {
+        let renderer_context = TestContext::new();
+        let mut bindings = BindingMapBuilder::new();
+        let (mut mg, _) = VoxelMemoryGrid::new(
+            VoxelMemoryGrid::standard_config(7, CHUNK_SIZE, &mut bindings),
+            Arc::clone(&renderer_context.memory_allocator) as Arc<dyn MemoryAllocator>,
+            CHUNK_SIZE,
+            TlcPos(Point3::from_value(-6)),
+        );
+        let meta = mg.metadata().clone();
+        let (edited, unedited) = (TlcPos(Point3::from_value(1)), TlcPos(Point3::from_value(0)));
+        let voxels = ChunkVoxels::new_blank(meta.tlc_size().pow(3));
+        assert!(mg
+            .commit_bulk_load::<Block>([(edited, voxels.clone()), (unedited, voxels)])
+            .is_empty());
+        assert!(!mg.is_edited(edited));
+
+        let mut editor = <VoxelMemoryGrid<5> as EditMemoryGridChunk<Block>>::edit_chunk(
+            &mut mg,
+            edited,
+            [BufferChunkState::Unloaded; 3],
+        )
+        .unwrap();
+        let pos = VoxelPos(Point3::from_value(0));
+        let index = VoxelPosInLod::in_full_lod(pos).index(CHUNK_SIZE, meta.largest_lod.lvl);
+        editor.set_voxel(pos, index, Block::SOLID, &meta).unwrap();
+        drop(editor);
+        assert!(mg.is_edited(edited));
+        assert!(!mg.is_edited(unedited));
+
+        // Every chunk in the grid except the edited one is queued
+        let queued = mg.queue_regenerate_unedited();
+        assert!(queued.iter().any(|item| item.pos == unedited));
+        assert!(queued.iter().all(|item| item.pos != edited));
+        assert_eq!(queued.len(), mg.queue_load_all().len() - 1);
+
+        // Loading the chunk again replaces its edits
+        let mut editor = <VoxelMemoryGrid<5> as EditMemoryGridChunk<Block>>::edit_chunk(
+            &mut mg,
+            edited,
+            [BufferChunkState::Unloaded; 3],
+        )
+        .unwrap();
+        editor.mark_all_lods_invalid().unwrap();
+        let item = VoxelChunkLoadQueueItemData {
+            lods: editor.lods().each_ref().map(|lod| lod.is_some()),
+        };
+        let taken = editor.take_data_for_loading(&item);
+        assert!(!mg.is_edited(edited));
+        assert!(taken.return_data(&mut mg));
+        assert!(mg
+            .queue_regenerate_unedited()
+            .iter()
+            .any(|item| item.pos == edited));
+    }
+
+    #[test]
+    fn test_pinned_chunk_survives_shift() {
+        let renderer_context = TestContext::new();
+        let mut bindings = BindingMapBuilder::new();
+        let (mut mg, _) = VoxelMemoryGrid::new(
+            VoxelMemoryGrid::standard_config(11, CHUNK_SIZE, &mut bindings),
+            Arc::clone(&renderer_context.memory_allocator) as Arc<dyn MemoryAllocator>,
+            CHUNK_SIZE,
+            TlcPos(Point3::from_value(-10)),
+        );
+        let meta = mg.metadata().clone();
+        // The full resolution LOD loads chunks 0..=2 in each axis
+        let center = TlcPos(Point3::from_value(1));
+        let voxels = ChunkVoxels::new_blank(meta.tlc_size().pow(3));
+        assert!(mg.commit_bulk_load::<Block>([(center, voxels)]).is_empty());
+        assert_eq!(mg.pin_chunk(center), Ok(()));
+
+        let mut editor = <VoxelMemoryGrid<5> as EditMemoryGridChunk<Block>>::edit_chunk(
+            &mut mg,
+            center,
+            [BufferChunkState::Unloaded; 3],
+        )
+        .unwrap();
+        let pos = VoxelPos(Point3::from_value(0));
+        let index = VoxelPosInLod::in_full_lod(pos).index(CHUNK_SIZE, meta.largest_lod.lvl);
+        editor.set_voxel(pos, index, Block::SOLID, &meta).unwrap();
+        drop(editor);
+
+        let shift_x = |chunks| {
+            MemGridShift::new([
+                ShiftGridAxis::Shift(ShiftGridAxisVal::new(chunks, false)),
+                ShiftGridAxis::DoNothing,
+                ShiftGridAxis::DoNothing,
+            ])
+            .unwrap()
+        };
+        // The chunk leaves the finer LODs but stays in the grid
+        mg.shift(&shift_x(3));
+        assert!(mg.lods()[0].overflowed_chunk(center).is_some());
+        assert_eq!(mg.pinned_chunk_stats().overflowed, 0);
+        assert!(mg.is_edited(center));
+
+        // Coming back, it is loaded like any other chunk but keeps its voxels, and the other LODs match them
+        let queue = mg.shift(&shift_x(-3));
+        let item = queue.into_iter().find(|item| item.pos == center).unwrap();
+        let mut editor = <VoxelMemoryGrid<5> as EditMemoryGridChunk<Block>>::edit_chunk(
+            &mut mg,
+            center,
+            [BufferChunkState::Unloaded; 3],
+        )
+        .unwrap();
+        editor.mark_all_lods_invalid().unwrap();
+        let mut taken = editor.take_data_for_loading(&item.data);
+        taken.load_new(center, |_, _, _, _, _, _, _| {}, &meta);
+        assert!(taken.return_data(&mut mg));
+        assert!(mg.is_edited(center));
+        assert_eq!(mg.lods()[0].pinned_chunk_stats().restoring, 0);
+
+        let editor = <VoxelMemoryGrid<5> as EditMemoryGridChunk<Block>>::edit_chunk(
+            &mut mg,
+            center,
+            [BufferChunkState::Unloaded; 3],
+        )
+        .unwrap();
+        let full_lod = editor.lods()[0].as_ref().unwrap().data().get().unwrap();
+        assert_eq!(full_lod.voxel_id(index), Some(Block::SOLID as u8));
+        for lod in editor.lods().iter().flatten() {
+            assert!(lod.data().get().unwrap().bitmask().get(0));
+        }
+        drop(editor);
+        assert!(mg.unpin_chunk(center));
+    }
+
+    #[test]
+    fn test_upload_budget() {
+        let renderer_context = TestContext::new();
+        let mut bindings = BindingMapBuilder::new();
+        let (mut mg, _) = VoxelMemoryGrid::new(
+            VoxelMemoryGrid::standard_config(7, CHUNK_SIZE, &mut bindings),
+            Arc::clone(&renderer_context.memory_allocator) as Arc<dyn MemoryAllocator>,
+            CHUNK_SIZE,
+            TlcPos(Point3::from_value(-6)),
+        );
+        let voxels = ChunkVoxels::new_blank(mg.metadata().tlc_size().pow(3));
+        let center = TlcPos(Point3::from_value(1));
+        assert!(mg.commit_bulk_load::<Block>([(center, voxels)]).is_empty());
+        // One region per LOD
+        let n_pending = mg.n_pending_updates();
+        assert_eq!(n_pending, 5);
+
+        let budget = UploadBudget {
+            max_regions: Some(1),
+            ..Default::default()
+        };
+        for i in 1..=n_pending {
+            let n_updates: usize = mg.get_updates(&budget).iter().map(|u| u.len()).sum();
+            assert_eq!(n_updates, 1);
+            assert_eq!(mg.n_pending_updates(), n_pending - i);
+        }
+        let n_updates: usize = mg.get_updates(&budget).iter().map(|u| u.len()).sum();
+        assert_eq!(n_updates, 0);
+    }
+
+    #[test]
+    fn test_chunk_user_data() {
+        let renderer_context = TestContext::new();
+        let mut bindings = BindingMapBuilder::new();
+        let (mut mg, _) = VoxelMemoryGrid::new(
+            VoxelMemoryGrid::standard_config(7, CHUNK_SIZE, &mut bindings),
+            Arc::clone(&renderer_context.memory_allocator) as Arc<dyn MemoryAllocator>,
+            CHUNK_SIZE,
+            TlcPos(Point3::from_value(-6)),
+        );
+        let dir = std::env::temp_dir().join(format!("ox_user_data_test_{}", std::process::id()));
+        let store = Arc::new(FileChunkStore::new(&dir).unwrap());
+        mg.set_chunk_store(Arc::clone(&store) as Arc<dyn ChunkStore>);
+        let pos = TlcPos(Point3::from_value(1));
+
+        let mut editor = <VoxelMemoryGrid<5> as EditMemoryGridChunk<Block>>::edit_chunk(
+            &mut mg,
+            pos,
+            [BufferChunkState::Unloaded; 3],
+        )
+        .unwrap();
+        assert_eq!(editor.user_data(), None);
+        editor.set_user_data(Some(b"claimed".to_vec()));
+        drop(editor);
+        assert_eq!(mg.user_data(pos), Some(&b"claimed"[..]));
+        assert_eq!(mg.n_unsaved(), 1);
+
+        // User data alone doesn't save the chunk's voxels
+        assert_eq!(mg.save_edited(), Ok(0));
+        assert_eq!(mg.n_unsaved(), 0);
+        assert_eq!(store.load_user_data(pos), Some(b"claimed".to_vec()));
+        assert_eq!(store.load(pos), None);
+
+        // Loading the chunk reads its user data back
+        mg.user_data.clear();
+        let mut editor = <VoxelMemoryGrid<5> as EditMemoryGridChunk<Block>>::edit_chunk(
+            &mut mg,
+            pos,
+            [BufferChunkState::Unloaded; 3],
+        )
+        .unwrap();
+        editor.mark_all_lods_invalid().unwrap();
+        let item = VoxelChunkLoadQueueItemData {
+            lods: editor.lods().each_ref().map(|lod| lod.is_some()),
+        };
+        let mut taken = editor.take_data_for_loading(&item);
+        assert!(!taken.load_stored());
+        assert_eq!(taken.user_data(), Some(&b"claimed"[..]));
+        assert!(taken.return_data(&mut mg));
+        assert_eq!(mg.user_data(pos), Some(&b"claimed"[..]));
+        assert_eq!(mg.n_unsaved(), 0);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_set_voxels_in_region() {
+        let renderer_context = TestContext::new();
+        let center = TlcPos(Point3::from_value(1));
+        let new_grid = || {
+            let mut bindings = BindingMapBuilder::new();
+            let (mut mg, _) = VoxelMemoryGrid::new(
+                VoxelMemoryGrid::standard_config(7, CHUNK_SIZE, &mut bindings),
+                Arc::clone(&renderer_context.memory_allocator) as Arc<dyn MemoryAllocator>,
+                CHUNK_SIZE,
+                TlcPos(Point3::from_value(-6)),
+            );
+            let voxels = ChunkVoxels::new_blank(mg.metadata().tlc_size().pow(3));
+            assert!(mg.commit_bulk_load::<Block>([(center, voxels)]).is_empty());
+            mg
+        };
+        fn edit(mg: &mut VoxelMemoryGrid<5>, pos: TlcPos<i64>) -> ChunkVoxelEditor<'_, Block, 5> {
+            <VoxelMemoryGrid<5> as EditMemoryGridChunk<Block>>::edit_chunk(
+                mg,
+                pos,
+                [BufferChunkState::Unloaded; 3],
+            )
+            .unwrap()
+        }
+
+        let (mut bulk, mut single) = (new_grid(), new_grid());
+        let meta = bulk.metadata().clone();
+        let (start, end) = (Point3::new(3, 5, 7), Point3::new(13, 9, 12));
+        let block = |pos: VoxelPos<u32>| {
+            if (pos.0.x + pos.0.z) % 3 == 0 {
+                Block::AIR
+            } else {
+                Block::SOLID
+            }
+        };
+
+        edit(&mut bulk, center)
+            .set_voxels_in_region(VoxelPos(start)..VoxelPos(end), block, &meta)
+            .unwrap();
+        {
+            let mut editor = edit(&mut single, center);
+            for y in start.y..end.y {
+                for z in start.z..end.z {
+                    for x in start.x..end.x {
+                        let pos = VoxelPos(Point3 { x, y, z });
+                        let index =
+                            VoxelPosInLod::in_full_lod(pos).index(CHUNK_SIZE, meta.largest_lod.lvl);
+                        editor.set_voxel(pos, index, block(pos), &meta).unwrap();
+                    }
+                }
+            }
+        }
+        // Both mark the same blocks dirty, which takes far fewer copy regions than voxels
+        for (a, b) in bulk.lods.iter().zip(single.lods.iter()) {
+            let summary = |regions: Vec<UpdateRegion>| {
+                regions
+                    .iter()
+                    .map(|r| (r.chunk_idx, r.voxel_idx, r.n_voxels))
+                    .collect::<Vec<_>>()
+            };
+            let regions = summary(a.state().dirty_blocks.regions());
+            assert_eq!(regions, summary(b.state().dirty_blocks.regions()));
+            assert!(regions.len() < 10);
+        }
+
+        // Same result as setting each voxel on its own
+        let (bulk_editor, single_editor) = (edit(&mut bulk, center), edit(&mut single, center));
+        for (a, b) in bulk_editor.lods().iter().zip(single_editor.lods().iter()) {
+            let a = a.as_ref().unwrap().data().get().unwrap();
+            let b = b.as_ref().unwrap().data().get().unwrap();
+            assert_eq!(a.bitmask(), b.bitmask());
+            assert_eq!(a.voxel_ids(), b.voxel_ids());
+        }
+    }
+
+    #[test]
+    fn test_standard_config() {
+        let sizes = |dist| {
+            let mut bindings = BindingMapBuilder::new();
+            let params = VoxelMemoryGrid::standard_config(dist, CHUNK_SIZE, &mut bindings);
+            for p in params.iter() {
+                p.validate(CHUNK_SIZE);
+            }
+            assert_eq!(params[3].bitmask_binding.index(), 11);
+            assert_eq!(params[4].bitmask_binding.index(), 12);
+            assert!(params[4].voxel_ids_binding.is_none());
+            params.map(|p| p.render_area_size)
+        };
+        // Same as the sandbox and `test_edit_voxel_grid`
+        assert_eq!(sizes(11), [3, 5, 9, 23, 23]);
+        assert_eq!(sizes(7), [1, 3, 7, 15, 15]);
+        assert_eq!(sizes(0), [1, 1, 3, 3, 3]);
+    }
 }
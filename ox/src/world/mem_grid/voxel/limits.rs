@@ -0,0 +1,197 @@
+//! Checks that a LOD configuration fits within a device's limits. Without this, a configuration that is too
+//! large only fails once its buffers are created or bound, with an error from vulkano that doesn't say which
+//! LOD is the problem.
+
+use super::grid::lod_tlc_size;
+use super::lod::VoxelLODCreateParams;
+use crate::renderer::component::voxels::data::{VoxelBitmask, VoxelTypeIDs};
+use crate::world::mem_grid::utils::{squared, ChunkSize};
+use hashbrown::HashMap;
+use std::mem::size_of;
+use vulkano::device::physical::PhysicalDevice;
+use vulkano::memory::MemoryHeapFlags;
+
+/// Device limits that LOD buffers are checked against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceLimits {
+    /// Largest storage buffer that can be bound, in bytes
+    pub max_storage_buffer_range: u64,
+    /// Largest single allocation, in bytes, if the device reports it
+    pub max_memory_allocation_size: Option<u64>,
+    pub max_per_stage_storage_buffers: u32,
+    pub max_descriptor_set_storage_buffers: u32,
+    /// Size of the largest device local memory heap, in bytes
+    pub device_local_memory: Option<u64>,
+}
+
+impl DeviceLimits {
+    pub fn of(physical_device: &PhysicalDevice) -> Self {
+        let properties = physical_device.properties();
+        DeviceLimits {
+            max_storage_buffer_range: properties.max_storage_buffer_range as u64,
+            max_memory_allocation_size: properties.max_memory_allocation_size,
+            max_per_stage_storage_buffers: properties.max_per_stage_descriptor_storage_buffers,
+            max_descriptor_set_storage_buffers: properties.max_descriptor_set_storage_buffers,
+            device_local_memory: physical_device
+                .memory_properties()
+                .memory_heaps
+                .iter()
+                .filter(|heap| heap.flags.intersects(MemoryHeapFlags::DEVICE_LOCAL))
+                .map(|heap| heap.size)
+                .max(),
+        }
+    }
+}
+
+/// Size in bytes of each of a LOD's device local buffers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LodBufferSizes {
+    pub bitmask: u64,
+    pub voxel_ids: Option<u64>,
+}
+
+impl LodBufferSizes {
+    /// `largest_lvl` is the lvl of the lowest fidelity LOD in the same config, which determines the TLC size
+    pub fn of(params: &VoxelLODCreateParams, chunk_size: ChunkSize, largest_lvl: u8) -> Self {
+        let voxels_per_tlc = squared(lod_tlc_size(
+            chunk_size,
+            largest_lvl,
+            params.lvl,
+            params.sublvl,
+        )) * lod_tlc_size(
+            chunk_size,
+            largest_lvl,
+            params.lvl,
+            params.resolved_sublvl_y(),
+        );
+        // Same sizes as `VoxelBitmask::new_vec` and `VoxelTypeIDs::new_vec` for each chunk
+        let n_chunks = params.n_chunks() as u64;
+        let bitmask_per_tlc = ((voxels_per_tlc + 127) / 128 * size_of::<VoxelBitmask>()) as u64;
+        let ids_per_tlc = ((voxels_per_tlc * VoxelTypeIDs::BITS_PER_VOXEL + 127) / 128
+            * size_of::<VoxelTypeIDs>()) as u64;
+        LodBufferSizes {
+            bitmask: n_chunks * bitmask_per_tlc,
+            voxel_ids: params.voxel_ids_binding.map(|_| n_chunks * ids_per_tlc),
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.bitmask + self.voxel_ids.unwrap_or(0)
+    }
+}
+
+/// Check that the buffers for `lods` fit within `limits`: each buffer must be bindable and allocatable, all of
+/// them together must fit in device local memory, there must be few enough of them to bind at once, and no two
+/// may share a binding. Returns a description of every problem, naming the LOD it was found in.
+pub fn check_lod_limits(
+    lods: &[VoxelLODCreateParams],
+    chunk_size: ChunkSize,
+    limits: &DeviceLimits,
+) -> Result<(), String> {
+    let mut problems = vec![];
+    let largest_lvl = lods.iter().map(|lod| lod.lvl).max().unwrap_or(0);
+    let max_buffer_size = limits
+        .max_memory_allocation_size
+        .map_or(limits.max_storage_buffer_range, |size| {
+            size.min(limits.max_storage_buffer_range)
+        });
+
+    let mut total_bytes = 0;
+    let mut n_buffers = 0;
+    let mut bindings: HashMap<u32, String> = HashMap::new();
+    for lod in lods.iter() {
+        let name = format!("LOD lvl {} sublvl {}", lod.lvl, lod.sublvl);
+        let sizes = LodBufferSizes::of(lod, chunk_size, largest_lvl);
+        total_bytes += sizes.total();
+
+        let buffers = [
+            (
+                "bitmask",
+                Some((sizes.bitmask, lod.bitmask_binding.index())),
+            ),
+            (
+                "voxel ID",
+                sizes
+                    .voxel_ids
+                    .zip(lod.voxel_ids_binding.map(|b| b.index())),
+            ),
+        ];
+        for (kind, buffer) in buffers {
+            let Some((size, binding)) = buffer else {
+                continue;
+            };
+            n_buffers += 1;
+            if size > max_buffer_size {
+                problems.push(format!(
+                    "{}: {} buffer is {} bytes, but the device's largest storage buffer is {} bytes \
+                     (reduce its render_area_size of {})",
+                    name, kind, size, max_buffer_size, lod.render_area_size
+                ));
+            }
+            if let Some(other) = bindings.insert(binding, format!("{} {} buffer", name, kind)) {
+                problems.push(format!(
+                    "{}: {} buffer uses binding {}, which is also used by the {}",
+                    name, kind, binding, other
+                ));
+            }
+        }
+    }
+
+    let max_buffers = limits
+        .max_per_stage_storage_buffers
+        .min(limits.max_descriptor_set_storage_buffers);
+    if n_buffers > max_buffers {
+        problems.push(format!(
+            "LODs use {} storage buffers, but the device can only bind {} at once",
+            n_buffers, max_buffers
+        ));
+    }
+    if let Some(memory) = limits.device_local_memory {
+        if total_bytes > memory {
+            problems.push(format!(
+                "LOD buffers need {} bytes in total, but the device only has {} bytes of device local memory",
+                total_bytes, memory
+            ));
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer::binding::BindingMapBuilder;
+    use crate::world::mem_grid::voxel::LodConfig;
+
+    #[test]
+    fn test_check_lod_limits() {
+        let chunk_size = ChunkSize::new(3);
+        let config = LodConfig::standard(11, chunk_size, &mut BindingMapBuilder::new());
+        let sizes = LodBufferSizes::of(&config.lods()[4], chunk_size, 2);
+        // 24^3 chunks, each a single voxel in one 16 byte bitmask word
+        assert_eq!(sizes.bitmask, 24u64.pow(3) * 16);
+        assert_eq!(sizes.voxel_ids, None);
+
+        let mut limits = DeviceLimits {
+            max_storage_buffer_range: u32::MAX as u64,
+            max_memory_allocation_size: None,
+            max_per_stage_storage_buffers: 16,
+            max_descriptor_set_storage_buffers: 16,
+            device_local_memory: None,
+        };
+        assert_eq!(check_lod_limits(config.lods(), chunk_size, &limits), Ok(()));
+
+        limits.max_per_stage_storage_buffers = 8;
+        limits.max_memory_allocation_size = Some(8_000_000);
+        let problems = check_lod_limits(config.lods(), chunk_size, &limits).unwrap_err();
+        let problems: Vec<_> = problems.lines().collect();
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[0].starts_with("LOD lvl 0 sublvl 0: voxel ID buffer"));
+        assert!(problems[1].starts_with("LODs use 9 storage buffers"));
+    }
+}
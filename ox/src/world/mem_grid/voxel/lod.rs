@@ -1,35 +1,70 @@
 use crate::loader::LayerChunk;
-use crate::renderer::component::voxels::data::VoxelTypeIDs;
+use crate::renderer::binding::{Binding, StorageBuffer};
+use crate::renderer::component::voxels::data::{VoxelBitmask, VoxelTypeIDs};
 use crate::renderer::component::voxels::lod::RendererVoxelLOD;
 use crate::renderer::component::voxels::lod::{VoxelIDUpdate, VoxelLODUpdate};
 use crate::voxel_type::VoxelTypeEnum;
 use crate::world::mem_grid::layer::MemoryGridLayer;
-use crate::world::mem_grid::utils::{cubed, ChunkSize, VoxelPosInLod};
+use crate::world::mem_grid::utils::{squared, ChunkSize, VoxelPosInLod};
+use crate::world::mem_grid::voxel::brickmap::{dense_heap_bytes, Brickmap};
+use crate::world::mem_grid::voxel::downsample::Downsample;
 use crate::world::mem_grid::voxel::gpu_defs::{ChunkBitmask, ChunkVoxels};
 use crate::world::mem_grid::EditMemoryGridChunk;
 use crate::world::TlcPos;
-use cgmath::Point3;
 use getset::{CopyGetters, Getters, MutGetters};
-use hashbrown::HashMap;
+use std::borrow::Cow;
 use std::marker::PhantomData;
 use std::sync::Arc;
 use vulkano::command_buffer::BufferCopy;
 use vulkano::memory::allocator::MemoryAllocator;
 
-use super::grid::lod_tlc_size;
+pub(crate) use ox_core::voxel::lod::{apply_to_voxel_indices_in_lower_lod, apply_to_voxels_in_lod};
+use ox_core::voxel::lod::{DownsampleCounts as CoreDownsampleCounts, VoxelTypeInfo};
 
 #[derive(Clone, Debug)]
 pub struct VoxelLODCreateParams {
-    pub voxel_resolution: usize,
+    pub voxel_resolution: usize, // resolution in the x and z axes
     pub lvl: u8,
     pub sublvl: u8,
-    pub render_area_size: usize, // size in chunks of one dimension, so total chunks loaded = render_area_size^3
-    pub bitmask_binding: u32,
-    pub voxel_ids_binding: Option<u32>,
+    /// Sublvl to use for the y axis. If `None`, this is the same as `sublvl`. Setting this lower than `sublvl`
+    /// keeps more vertical detail than horizontal detail (e.g. full vertical detail but halved horizontal). Only
+    /// lvl 0 LODs can be anisotropic; LODs at higher lvls are always isotropic.
+    pub sublvl_y: Option<u8>,
+    pub render_area_size: usize, // size in chunks of the x and z axes
+    /// Size in chunks of the y axis. If `None`, this is the same as `render_area_size`. Setting this lower loads
+    /// less vertically than horizontally, e.g. 15x5x15 chunks. Like `render_area_size`, this should be odd.
+    pub render_area_size_y: Option<usize>,
+    pub bitmask_binding: Binding<StorageBuffer>,
+    pub voxel_ids_binding: Option<Binding<StorageBuffer>>,
+    /// How this LOD's voxels are computed from a higher resolution LOD's. Not used for LOD 0 or for LODs
+    /// without voxel IDs.
+    pub downsample: Downsample,
 }
 impl VoxelLODCreateParams {
     pub fn validate(&self, chunk_size: ChunkSize) {
         debug_assert!(self.voxel_resolution == chunk_size.size().pow(self.lvl as u32) as usize * 2usize.pow(self.sublvl as u32), "VoxelLODCreateParams invalid: voxel resolution for lvl {} sublvl {} expected to be chunk_size^lvl * 2^sublvl = {}", self.lvl, self.sublvl, chunk_size.size().pow(self.lvl as u32) * 2usize.pow(self.sublvl as u32));
+        assert!(
+            self.resolved_sublvl_y() < chunk_size.exp(),
+            "VoxelLODCreateParams invalid: sublvl_y for lvl {} sublvl {} must be less than {}",
+            self.lvl,
+            self.sublvl,
+            chunk_size.exp(),
+        );
+    }
+
+    /// Sublvl used for the y axis
+    pub fn resolved_sublvl_y(&self) -> u8 {
+        self.sublvl_y.unwrap_or(self.sublvl)
+    }
+
+    /// Render area size in chunks of the y axis
+    pub fn resolved_render_area_size_y(&self) -> usize {
+        self.render_area_size_y.unwrap_or(self.render_area_size)
+    }
+
+    /// Number of chunks in the memory grid layer for this LOD, including the extra buffer chunk on each axis
+    pub fn n_chunks(&self) -> usize {
+        squared(self.render_area_size + 1) * (self.resolved_render_area_size_y() + 1)
     }
 }
 
@@ -37,20 +72,112 @@ impl VoxelLODCreateParams {
 pub struct LodMetadata {
     pub lvl: u8,
     pub sublvl: u8,
+    pub sublvl_y: u8,
     pub voxels_per_tlc: usize,
+    pub downsample: Downsample,
 }
 
 #[derive(Clone, Debug)]
 pub struct LodState {
+    /// Regions to copy to the GPU, usually whole chunks. Edits of single voxels are tracked in `dirty_blocks`.
     pub updated_regions: Vec<UpdateRegion>,
+    pub dirty_blocks: DirtyBlocks,
 }
 
-#[derive(Clone, Debug, Getters)]
+/// Voxels per block of `DirtyBlocks`. Voxel indices are ordered by chunk, so with a chunk size of 8 a block of LOD 0
+/// voxels is one 8x8x8 lvl 1 chunk.
+pub const DIRTY_BLOCK_VOXELS: usize = 512;
+
+/// Which blocks of `DIRTY_BLOCK_VOXELS` voxels in each chunk of an LOD were edited since the updates were last
+/// aggregated. Tracking edits per block instead of as one `UpdateRegion` per voxel means heavy editing produces at
+/// most one copy region per block, aligned to blocks, with no sorting or merging of arbitrary ranges. Some unedited
+/// voxels around the edits are copied too.
+#[derive(Clone, Debug)]
+pub struct DirtyBlocks {
+    voxels_per_tlc: usize,
+    blocks_per_chunk: usize,
+    blocks: Vec<bool>,
+    chunk_is_dirty: Vec<bool>,
+    /// Chunks with at least one dirty block, so only those have to be scanned
+    dirty_chunks: Vec<usize>,
+}
+
+impl DirtyBlocks {
+    pub fn new(n_chunks: usize, voxels_per_tlc: usize) -> Self {
+        let blocks_per_chunk = voxels_per_tlc.div_ceil(DIRTY_BLOCK_VOXELS);
+        DirtyBlocks {
+            voxels_per_tlc,
+            blocks_per_chunk,
+            blocks: vec![false; n_chunks * blocks_per_chunk],
+            chunk_is_dirty: vec![false; n_chunks],
+            dirty_chunks: vec![],
+        }
+    }
+
+    pub fn mark(&mut self, chunk_idx: usize, voxel_idx: usize) {
+        self.blocks[chunk_idx * self.blocks_per_chunk + voxel_idx / DIRTY_BLOCK_VOXELS] = true;
+        if !self.chunk_is_dirty[chunk_idx] {
+            self.chunk_is_dirty[chunk_idx] = true;
+            self.dirty_chunks.push(chunk_idx);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dirty_chunks.is_empty()
+    }
+
+    /// One region per run of consecutive dirty blocks in each chunk
+    pub fn regions(&self) -> Vec<UpdateRegion> {
+        let mut regions: Vec<UpdateRegion> = vec![];
+        for &chunk_idx in self.dirty_chunks.iter() {
+            let start = chunk_idx * self.blocks_per_chunk;
+            let blocks = &self.blocks[start..start + self.blocks_per_chunk];
+            for (block, _) in blocks.iter().enumerate().filter(|(_, &dirty)| dirty) {
+                let voxel_idx = block * DIRTY_BLOCK_VOXELS;
+                let n_voxels = DIRTY_BLOCK_VOXELS.min(self.voxels_per_tlc - voxel_idx);
+                match regions.last_mut() {
+                    Some(last)
+                        if last.chunk_idx == chunk_idx
+                            && last.voxel_idx + last.n_voxels == voxel_idx =>
+                    {
+                        last.n_voxels += n_voxels
+                    }
+                    _ => regions.push(UpdateRegion {
+                        chunk_idx,
+                        voxel_idx,
+                        n_voxels,
+                    }),
+                }
+            }
+        }
+        regions
+    }
+
+    pub fn clear(&mut self) {
+        for chunk_idx in self.dirty_chunks.drain(..) {
+            let start = chunk_idx * self.blocks_per_chunk;
+            self.blocks[start..start + self.blocks_per_chunk].fill(false);
+            self.chunk_is_dirty[chunk_idx] = false;
+        }
+    }
+}
+
+/// Voxel data of one chunk in one LOD. Chunks are either dense, in the same layout as the GPU buffers, or sparse
+/// (a `Brickmap`), which stores runs of identical voxels as a single ID and is much smaller for chunks that are
+/// mostly empty or solid. Loaded chunks are made sparse when that saves memory (see `compact`) and are made dense
+/// again when edited; sparse chunks are converted to the GPU layout when they are sent to the GPU.
+#[derive(Clone, Debug)]
 pub struct LodChunkData {
-    #[get = "pub"]
-    bitmask: ChunkBitmask,
-    #[get = "pub"]
-    voxel_ids: Option<ChunkVoxels>, // voxel ids are optional because some LODs only have a bitmask
+    repr: LodChunkRepr,
+}
+
+#[derive(Clone, Debug)]
+enum LodChunkRepr {
+    Dense {
+        bitmask: ChunkBitmask,
+        voxel_ids: Option<ChunkVoxels>, // voxel ids are optional because some LODs only have a bitmask
+    },
+    Sparse(Brickmap),
 }
 
 #[derive(Clone, Debug, Getters)]
@@ -82,103 +209,141 @@ impl VoxelMemoryGridLod {
     pub fn new_voxel_lod(
         params: VoxelLODCreateParams,
         start_tlc: TlcPos<i64>,
-        lod_tlc_size: usize,
+        voxels_per_tlc: usize,
         buffer_allocator: Arc<dyn MemoryAllocator>,
     ) -> (Self, RendererVoxelLOD) {
         assert!(
-            params.render_area_size % 2 == 1,
+            params.render_area_size % 2 == 1 && params.resolved_render_area_size_y() % 2 == 1,
             "Render area sizes should be odd so they have a center chunk"
         );
-        let bitmask =
-            vec![ChunkBitmask::new_blank(cubed(lod_tlc_size)); cubed(params.render_area_size + 1)];
-        let voxels = params.voxel_ids_binding.map(|_| {
-            vec![
-                Some(ChunkVoxels::new_blank(cubed(lod_tlc_size)));
-                cubed(params.render_area_size + 1)
-            ]
-        });
+        let n_chunks = params.n_chunks();
+        let has_voxel_ids = params.voxel_ids_binding.is_some();
         let renderer_lod = RendererVoxelLOD::new(
-            bitmask
-                .iter()
-                .flat_map(|c| &c.bitmask)
-                .copied()
+            (0..n_chunks)
+                .flat_map(|_| VoxelBitmask::new_vec(voxels_per_tlc))
                 .collect::<Vec<_>>()
                 .into_iter(), // ENHANCEMENT: Do this better (and below)
-            voxels.as_ref().map(|voxs| {
-                voxs.iter()
-                    .flat_map(|c| &c.as_ref().unwrap().ids)
-                    .copied()
+            has_voxel_ids.then(|| {
+                (0..n_chunks)
+                    .flat_map(|_| VoxelTypeIDs::new_vec(voxels_per_tlc))
                     .collect::<Vec<_>>()
                     .into_iter()
             }),
             params.bitmask_binding,
             params.voxel_ids_binding,
+            voxels_per_tlc,
             buffer_allocator,
         );
 
         (
-            MemoryGridLayer::new(
-                bitmask
-                    .into_iter()
-                    .zip(
-                        voxels.unwrap_or(
-                            (0..cubed(params.render_area_size + 1))
-                                .map(|_| None)
-                                .collect(),
-                        ),
-                    )
-                    .map(|(bm, vx)| {
-                        LayerChunk::new(LodChunkData {
-                            bitmask: bm,
-                            voxel_ids: vx,
-                        })
+            MemoryGridLayer::new_with_size_y(
+                (0..n_chunks)
+                    .map(|_| {
+                        LayerChunk::new(LodChunkData::new_empty(voxels_per_tlc, has_voxel_ids))
                     })
                     .collect(),
                 start_tlc,
                 params.render_area_size + 1,
+                params.resolved_render_area_size_y() + 1,
                 LodMetadata {
-                    voxels_per_tlc: cubed(lod_tlc_size),
+                    voxels_per_tlc,
                     lvl: params.lvl,
                     sublvl: params.sublvl,
+                    sublvl_y: params.resolved_sublvl_y(),
+                    downsample: params.downsample,
                 },
                 LodState {
                     updated_regions: vec![],
+                    dirty_blocks: DirtyBlocks::new(n_chunks, voxels_per_tlc),
                 },
             ),
             renderer_lod,
         )
     }
 
-    /// Aggregate the values from updated_regions to pass to the renderer and reset these
-    /// tracked regions if clear_regions=true.
+    /// Aggregate the values from updated_regions and dirty_blocks to pass to the renderer and reset
+    /// these if clear_regions=true.
     pub fn aggregate_updates(&mut self, clear_regions: bool) -> Vec<VoxelLODUpdate> {
+        let regions = self.pending_update_regions();
+        if clear_regions {
+            self.clear_update_regions();
+        }
+        self.updates_for_regions(&regions)
+    }
+
+    /// Regions of loaded chunks in updated_regions and dirty_blocks that the next updates would copy
+    pub fn pending_update_regions(&self) -> Vec<UpdateRegion> {
         let voxels_per_tlc = self.metadata().extra().voxels_per_tlc;
-        let (chunks, state) = self.chunks_and_state_mut();
-        let updates: Vec<VoxelLODUpdate> = state
+        let state = self.state();
+
+        // Dirty blocks of chunks that are copied in full anyway don't need their own regions
+        let mut full_chunks: Vec<usize> = state
             .updated_regions
             .iter()
-            .filter_map(|region| {
-                // skip updates to chunks that are not loaded
-                chunks[region.chunk_idx].get().map(|chunk| VoxelLODUpdate {
-                    bitmask: &chunk.bitmask.bitmask,
-                    bitmask_updated_region: region.bitmask_copy_region(voxels_per_tlc),
-                    id_update: chunk.voxel_ids.as_ref().map(|ids| VoxelIDUpdate {
-                        ids: &ids.ids,
-                        updated_region: region.voxel_id_copy_region(voxels_per_tlc),
-                    }),
-                })
-            })
+            .filter(|region| region.voxel_idx == 0 && region.n_voxels >= voxels_per_tlc)
+            .map(|region| region.chunk_idx)
             .collect();
+        full_chunks.sort_unstable();
+        let dirty_regions = state
+            .dirty_blocks
+            .regions()
+            .into_iter()
+            .filter(|region| full_chunks.binary_search(&region.chunk_idx).is_err());
+
+        state
+            .updated_regions
+            .iter()
+            .cloned()
+            .chain(dirty_regions)
+            // skip updates to chunks that are not loaded
+            .filter(|region| self.chunks()[region.chunk_idx].get().is_some())
+            .collect()
+    }
 
-        if clear_regions {
-            state.updated_regions.clear();
-        }
+    pub fn clear_update_regions(&mut self) {
+        let state = self.state_mut();
+        state.updated_regions.clear();
+        state.dirty_blocks.clear();
+    }
 
-        updates
+    /// Bytes copied to the GPU to update `region`
+    pub fn update_region_bytes(&self, region: &UpdateRegion) -> u64 {
+        let voxels_per_tlc = self.metadata().extra().voxels_per_tlc;
+        let has_ids = self.chunks()[region.chunk_idx]
+            .get()
+            .is_some_and(|chunk| chunk.has_voxel_ids());
+        region.bitmask_copy_region(voxels_per_tlc).size
+            + if has_ids {
+                region.voxel_id_copy_region(voxels_per_tlc).size
+            } else {
+                0
+            }
+    }
+
+    /// Updates to pass to the renderer for `regions`, skipping regions of chunks that are not loaded
+    pub fn updates_for_regions(&self, regions: &[UpdateRegion]) -> Vec<VoxelLODUpdate> {
+        let voxels_per_tlc = self.metadata().extra().voxels_per_tlc;
+        regions
+            .iter()
+            .filter_map(|region| {
+                self.chunks()[region.chunk_idx].get().map(|chunk| {
+                    let (bitmask, ids) = chunk.gpu_data();
+                    VoxelLODUpdate {
+                        bitmask,
+                        bitmask_updated_region: region.bitmask_copy_region(voxels_per_tlc),
+                        id_update: ids.map(|ids| VoxelIDUpdate {
+                            ids,
+                            updated_region: region.voxel_id_copy_region(voxels_per_tlc),
+                        }),
+                    }
+                })
+            })
+            .collect()
     }
 }
 
-/// Does not save an update region for this update
+/// Does not save an update region for this update. Returns whether the bit changed. The lower LOD can be any LOD
+/// with a bitmask computed the same way (or the full LOD), so an edit can be propagated one LOD at a time.
 pub fn update_bitmask_bit_from_lower_lod_untracked(
     bitmask: &mut ChunkBitmask,
     voxel_pos: VoxelPosInLod,
@@ -186,9 +351,10 @@ pub fn update_bitmask_bit_from_lower_lod_untracked(
     lower_lod_bitmask: &ChunkBitmask,
     lower_lvl: u8,
     lower_sublvl: u8,
+    lower_sublvl_y: u8,
     chunk_size: ChunkSize,
     largest_chunk_lvl: u8,
-) {
+) -> bool {
     // Index of the lower corner of the 2x2x2 area in the lower LOD data we want to look at
     let mut visible_count = 0;
     let mut count = 0;
@@ -198,6 +364,7 @@ pub fn update_bitmask_bit_from_lower_lod_untracked(
         voxel_index,
         lower_lvl,
         lower_sublvl,
+        lower_sublvl_y,
         chunk_size,
         largest_chunk_lvl,
         |idx| {
@@ -209,7 +376,9 @@ pub fn update_bitmask_bit_from_lower_lod_untracked(
         },
     );
 
+    let was_visible = bitmask.get(voxel_index);
     bitmask.set_block(voxel_index, visible_count > 0);
+    was_visible != (visible_count > 0)
 }
 
 /// For LODs where there is only a bitmask and no voxel ID data, update the bitmask given a
@@ -220,14 +389,17 @@ pub fn update_bitmask_from_lower_lod_untracked(
     lower_lod_bitmask: &ChunkBitmask,
     curr_lvl: u8,
     curr_sublvl: u8,
+    curr_sublvl_y: u8,
     lower_lvl: u8,
     lower_sublvl: u8,
+    lower_sublvl_y: u8,
     chunk_size: ChunkSize,
     largest_chunk_lvl: u8,
 ) {
     apply_to_voxels_in_lod(
         curr_lvl,
         curr_sublvl,
+        curr_sublvl_y,
         chunk_size,
         largest_chunk_lvl,
         |voxel_pos| {
@@ -239,6 +411,7 @@ pub fn update_bitmask_from_lower_lod_untracked(
                 lower_lod_bitmask,
                 lower_lvl,
                 lower_sublvl,
+                lower_sublvl_y,
                 chunk_size,
                 largest_chunk_lvl,
             );
@@ -256,23 +429,138 @@ pub enum LodChunkDataVariantMut<'a> {
 }
 
 impl LodChunkData {
+    /// Sparse chunk where every voxel is empty
+    pub fn new_empty(n_voxels: usize, has_voxel_ids: bool) -> Self {
+        LodChunkData {
+            repr: LodChunkRepr::Sparse(Brickmap::new_uniform(n_voxels, has_voxel_ids, 0, false)),
+        }
+    }
+
+    pub fn is_sparse(&self) -> bool {
+        matches!(self.repr, LodChunkRepr::Sparse(_))
+    }
+
+    pub fn has_voxel_ids(&self) -> bool {
+        match &self.repr {
+            LodChunkRepr::Dense { voxel_ids, .. } => voxel_ids.is_some(),
+            LodChunkRepr::Sparse(brickmap) => brickmap.has_voxel_ids(),
+        }
+    }
+
+    pub fn is_visible(&self, index: usize) -> bool {
+        match &self.repr {
+            LodChunkRepr::Dense { bitmask, .. } => bitmask.get(index),
+            LodChunkRepr::Sparse(brickmap) => brickmap.is_visible(index),
+        }
+    }
+
+    /// `None` if this LOD only has a bitmask
+    pub fn voxel_id(&self, index: usize) -> Option<u8> {
+        match &self.repr {
+            LodChunkRepr::Dense { voxel_ids, .. } => voxel_ids.as_ref().map(|ids| ids[index]),
+            LodChunkRepr::Sparse(brickmap) => brickmap.voxel_id(index),
+        }
+    }
+
+    /// The bitmask, converted to the dense layout if the chunk is sparse. For single voxels, `is_visible` doesn't
+    /// need to convert it.
+    pub fn bitmask(&self) -> Cow<'_, ChunkBitmask> {
+        match &self.repr {
+            LodChunkRepr::Dense { bitmask, .. } => Cow::Borrowed(bitmask),
+            LodChunkRepr::Sparse(brickmap) => Cow::Owned(brickmap.to_dense().0),
+        }
+    }
+
+    /// The voxel IDs if this LOD has them, converted to the dense layout if the chunk is sparse. For single voxels,
+    /// `voxel_id` doesn't need to convert them.
+    pub fn voxel_ids(&self) -> Option<Cow<'_, ChunkVoxels>> {
+        match &self.repr {
+            LodChunkRepr::Dense { voxel_ids, .. } => voxel_ids.as_ref().map(Cow::Borrowed),
+            LodChunkRepr::Sparse(brickmap) => brickmap.to_dense().1.map(Cow::Owned),
+        }
+    }
+
+    /// Bitmask and voxel IDs in the layout of the GPU buffers
+    fn gpu_data(&self) -> (Cow<'_, [VoxelBitmask]>, Option<Cow<'_, [VoxelTypeIDs]>>) {
+        match &self.repr {
+            LodChunkRepr::Dense { bitmask, voxel_ids } => (
+                Cow::Borrowed(bitmask.bitmask.as_slice()),
+                voxel_ids
+                    .as_ref()
+                    .map(|ids| Cow::Borrowed(ids.ids.as_slice())),
+            ),
+            LodChunkRepr::Sparse(brickmap) => {
+                let (bitmask, voxel_ids) = brickmap.to_dense();
+                (
+                    Cow::Owned(bitmask.bitmask),
+                    voxel_ids.map(|ids| Cow::Owned(ids.ids)),
+                )
+            }
+        }
+    }
+
+    /// Approximate heap memory used by this chunk's voxel data
+    pub fn heap_bytes(&self) -> usize {
+        match &self.repr {
+            LodChunkRepr::Dense { bitmask, voxel_ids } => {
+                dense_heap_bytes(bitmask, voxel_ids.as_ref())
+            }
+            LodChunkRepr::Sparse(brickmap) => brickmap.heap_bytes(),
+        }
+    }
+
+    /// Make the chunk sparse if that at least halves its memory use. Returns whether it is sparse.
+    pub fn compact(&mut self) -> bool {
+        if let LodChunkRepr::Dense { bitmask, voxel_ids } = &self.repr {
+            let brickmap = Brickmap::from_dense(bitmask, voxel_ids.as_ref());
+            if brickmap.heap_bytes() * 2 <= dense_heap_bytes(bitmask, voxel_ids.as_ref()) {
+                self.repr = LodChunkRepr::Sparse(brickmap);
+            }
+        }
+        self.is_sparse()
+    }
+
+    /// Convert a sparse chunk to the dense layout, e.g. before editing it
+    pub fn make_dense(&mut self) {
+        if let LodChunkRepr::Sparse(brickmap) = &self.repr {
+            let (bitmask, voxel_ids) = brickmap.to_dense();
+            self.repr = LodChunkRepr::Dense { bitmask, voxel_ids };
+        }
+    }
+
+    /// Panics if the chunk is sparse. Chunks are dense after `check_voxel_ids_mut` (or `make_dense`), so this is
+    /// for reading LODs of a chunk that is being loaded or edited.
     pub fn check_voxel_ids(&self) -> LodChunkDataVariant {
-        match self.voxel_ids.as_ref() {
-            Some(voxel_ids) => LodChunkDataVariant::WithVoxels(LodChunkDataWithVoxels {
-                bitmask: &self.bitmask,
-                voxel_ids,
-            }),
-            None => LodChunkDataVariant::WithoutVoxels(&self.bitmask),
+        match &self.repr {
+            LodChunkRepr::Dense {
+                bitmask,
+                voxel_ids: Some(voxel_ids),
+            } => LodChunkDataVariant::WithVoxels(LodChunkDataWithVoxels { bitmask, voxel_ids }),
+            LodChunkRepr::Dense {
+                bitmask,
+                voxel_ids: None,
+            } => LodChunkDataVariant::WithoutVoxels(bitmask),
+            LodChunkRepr::Sparse(_) => {
+                panic!("Tried to access the dense voxel data of a sparse chunk")
+            }
         }
     }
 
+    /// Makes the chunk dense, since editing it would change the bricks that are stored sparsely
     pub fn check_voxel_ids_mut(&mut self) -> LodChunkDataVariantMut {
-        match self.voxel_ids.as_mut() {
-            Some(voxel_ids) => LodChunkDataVariantMut::WithVoxels(LodChunkDataWithVoxelsMut {
-                bitmask: &mut self.bitmask,
-                voxel_ids,
-            }),
-            None => LodChunkDataVariantMut::WithoutVoxels(&mut self.bitmask),
+        self.make_dense();
+        match &mut self.repr {
+            LodChunkRepr::Dense {
+                bitmask,
+                voxel_ids: Some(voxel_ids),
+            } => {
+                LodChunkDataVariantMut::WithVoxels(LodChunkDataWithVoxelsMut { bitmask, voxel_ids })
+            }
+            LodChunkRepr::Dense {
+                bitmask,
+                voxel_ids: None,
+            } => LodChunkDataVariantMut::WithoutVoxels(bitmask),
+            LodChunkRepr::Sparse(_) => unreachable!(),
         }
     }
 }
@@ -280,6 +568,7 @@ impl LodChunkData {
 #[derive(Debug, Getters)]
 pub struct UpdatedRegionsMut<'a> {
     regions: &'a mut Vec<UpdateRegion>,
+    dirty_blocks: &'a mut DirtyBlocks,
     #[getset(get = "pub")]
     chunk_idx: usize,
 }
@@ -291,16 +580,14 @@ impl<'a> UpdatedRegionsMut<'a> {
     {
         UpdatedRegionsMut {
             regions: self.regions,
+            dirty_blocks: self.dirty_blocks,
             chunk_idx: self.chunk_idx,
         }
     }
 
-    fn add_region(&mut self, voxel_idx: usize, n_voxels: usize) {
-        self.regions.push(UpdateRegion {
-            chunk_idx: self.chunk_idx,
-            voxel_idx,
-            n_voxels,
-        });
+    /// Mark the block containing `voxel_idx` to be copied to the GPU
+    fn mark_dirty(&mut self, voxel_idx: usize) {
+        self.dirty_blocks.mark(self.chunk_idx, voxel_idx);
     }
 }
 
@@ -326,14 +613,18 @@ impl<'a> LodChunkDataWithVoxelsMut<'a> {
         lower_lod: LodChunkDataWithVoxels,
         curr_lvl: u8,
         curr_sublvl: u8,
+        curr_sublvl_y: u8,
         lower_lvl: u8,
         lower_sublvl: u8,
+        lower_sublvl_y: u8,
         chunk_size: ChunkSize,
         largest_chunk_lvl: u8,
+        downsample: &Downsample,
     ) {
         apply_to_voxels_in_lod(
             curr_lvl,
             curr_sublvl,
+            curr_sublvl_y,
             chunk_size,
             largest_chunk_lvl,
             |pos| {
@@ -344,8 +635,10 @@ impl<'a> LodChunkDataWithVoxelsMut<'a> {
                     index,
                     lower_lvl,
                     lower_sublvl,
+                    lower_sublvl_y,
                     chunk_size,
                     largest_chunk_lvl,
+                    downsample,
                 );
                 self.voxel_ids[index] = voxel_id.unwrap_or(VE::empty()).id();
                 self.bitmask
@@ -361,22 +654,22 @@ impl<'a> LodChunkDataWithVoxelsMut<'a> {
         index: usize,
         lower_lvl: u8,
         lower_sublvl: u8,
+        lower_sublvl_y: u8,
         chunk_size: ChunkSize,
         largest_chunk_lvl: u8,
+        downsample: &Downsample,
     ) -> Option<VE> {
-        let mut visible_count = 0;
-        let mut count = 0;
-        let mut type_counts = HashMap::<VE, u32>::new();
+        let mut counts = DownsampleCounts::default();
 
         apply_to_voxel_indices_in_lower_lod(
             pos,
             index,
             lower_lvl,
             lower_sublvl,
+            lower_sublvl_y,
             chunk_size,
             largest_chunk_lvl,
             |idx| {
-                count += 1;
                 debug_assert!(
                     idx < lower_lod.voxel_ids.n_voxels(),
                     "bad voxel index: lower_lod.voxel_ids[{}] for {}-{}",
@@ -384,33 +677,11 @@ impl<'a> LodChunkDataWithVoxelsMut<'a> {
                     lower_lvl,
                     lower_sublvl,
                 );
-                let id = lower_lod.voxel_ids[idx];
-                let vox_type = VE::from_u8(id).unwrap();
-                if vox_type.def().is_visible {
-                    visible_count += 1;
-                    match type_counts.get_mut(&vox_type) {
-                        None => {
-                            type_counts.insert(vox_type, 1);
-                        }
-                        Some(c) => {
-                            *c += 1;
-                        }
-                    }
-                }
+                counts.add(VE::from_id(lower_lod.voxel_ids[idx]));
             },
         );
 
-        if visible_count > 0 {
-            Some(
-                type_counts
-                    .into_iter()
-                    .max_by_key(|a| a.1)
-                    .map(|(k, _)| k)
-                    .unwrap(),
-            )
-        } else {
-            None
-        }
+        counts.result(downsample)
     }
 
     /// Overwrite voxel data. This will allow editing of the voxel IDs directly and automatically
@@ -438,6 +709,10 @@ pub struct LodChunkEditorMaybeUnloaded<'a, VE: VoxelTypeEnum> {
     lvl: u8,
     #[get_copy = "pub"]
     sublvl: u8,
+    #[get_copy = "pub"]
+    sublvl_y: u8,
+    #[get = "pub"]
+    downsample: Downsample,
 }
 
 pub struct LodChunkEditor<'a> {
@@ -468,15 +743,23 @@ impl<VE: VoxelTypeEnum> EditMemoryGridChunk<VE>
     ) -> Option<Self::ChunkEditor<'_>> {
         let vgrid_pos = self.chunk_vgrid_pos(pos, buffer_chunk_states)?;
         let chunk_idx = self.index_for_vgrid_pos(vgrid_pos);
-        let (lvl, sublvl) = (self.metadata().extra().lvl, self.metadata().extra().sublvl);
+        let (lvl, sublvl, sublvl_y) = (
+            self.metadata().extra().lvl,
+            self.metadata().extra().sublvl,
+            self.metadata().extra().sublvl_y,
+        );
+        let downsample = self.metadata().extra().downsample.clone();
         let (chunks, state) = self.chunks_and_state_mut();
         Some(LodChunkEditorMaybeUnloaded {
             voxel_type_enum: PhantomData,
             data: &mut chunks[chunk_idx],
             sublvl,
+            sublvl_y,
             lvl,
+            downsample,
             updated_regions: UpdatedRegionsMut {
                 regions: &mut state.updated_regions,
+                dirty_blocks: &mut state.dirty_blocks,
                 chunk_idx,
             },
         })
@@ -516,6 +799,7 @@ pub enum LodChunkEditorVariantMut<'a> {
 }
 
 impl<'a> LodChunkEditor<'a> {
+    /// Panics if the chunk is sparse, see `LodChunkData::check_voxel_ids`
     pub fn with_voxel_ids(&self) -> LodChunkEditorVariant {
         match self.data.check_voxel_ids() {
             LodChunkDataVariant::WithVoxels(data) => {
@@ -553,8 +837,10 @@ impl<'a> LodChunkEditor<'a> {
         lower_lod: &LodChunkDataWithVoxels,
         lower_lvl: u8,
         lower_sublvl: u8,
+        lower_sublvl_y: u8,
         chunk_size: ChunkSize,
         largest_chunk_lvl: u8,
+        downsample: &Downsample,
     ) {
         match self.with_voxel_ids_mut() {
             LodChunkEditorVariantMut::WithVoxels(mut lod) => lod.update_voxel_from_lower_lod::<VE>(
@@ -563,19 +849,23 @@ impl<'a> LodChunkEditor<'a> {
                 voxel_index,
                 lower_lvl,
                 lower_sublvl,
+                lower_sublvl_y,
                 chunk_size,
                 largest_chunk_lvl,
+                downsample,
             ),
-            LodChunkEditorVariantMut::WithoutVoxels(mut lod) => lod
-                .update_bitmask_bit_from_lower_lod(
+            LodChunkEditorVariantMut::WithoutVoxels(mut lod) => {
+                lod.update_bitmask_bit_from_lower_lod(
                     voxel_pos,
                     voxel_index,
                     lower_lod.bitmask,
                     lower_lvl,
                     lower_sublvl,
+                    lower_sublvl_y,
                     chunk_size,
                     largest_chunk_lvl,
-                ),
+                );
+            }
         }
     }
 }
@@ -597,27 +887,6 @@ pub struct LodChunkEditorWithoutVoxels<'a> {
     bitmask: &'a ChunkBitmask,
 }
 
-pub fn apply_to_voxels_in_lod<F: FnMut(VoxelPosInLod)>(
-    lvl: u8,
-    sublvl: u8,
-    chunk_size: ChunkSize,
-    largest_chunk_lvl: u8,
-    mut f: F,
-) {
-    let curr_lod_tlc_size = lod_tlc_size(chunk_size, largest_chunk_lvl, lvl, sublvl) as u32;
-    for y in 0..curr_lod_tlc_size {
-        for z in 0..curr_lod_tlc_size {
-            for x in 0..curr_lod_tlc_size {
-                f(VoxelPosInLod {
-                    pos: Point3 { x, y, z },
-                    lvl,
-                    sublvl,
-                });
-            }
-        }
-    }
-}
-
 impl<'a> LodChunkEditorWithVoxelsMut<'a> {
     pub fn borrow_mut<'b>(&'b mut self) -> LodChunkEditorWithVoxelsMut<'b>
     where
@@ -650,13 +919,20 @@ impl<'a> LodChunkEditorWithVoxelsMut<'a> {
         });
     }
 
-    /// Set a single voxel and add an update region for later GPU transfer
+    /// Set a single voxel and mark its block dirty for later GPU transfer (see `DirtyBlocks`)
     pub fn set_voxel<VE: VoxelTypeEnum>(&mut self, index: usize, voxel_typ: VE) {
         self.data.voxel_ids[index] = voxel_typ.to_u8().unwrap();
         self.data
             .bitmask
             .set_block(index, voxel_typ.def().is_visible);
-        self.updated_regions.add_region(index, 1);
+        self.updated_regions.mark_dirty(index);
+    }
+
+    /// `set_voxel` for each of `voxels` (index and type)
+    pub fn set_voxels<VE: VoxelTypeEnum>(&mut self, voxels: impl IntoIterator<Item = (usize, VE)>) {
+        for (index, voxel_typ) in voxels {
+            self.set_voxel(index, voxel_typ);
+        }
     }
 
     /// Recalculate LOD voxels from a lower LOD (i.e. a higher resolution LOD). Syncs entire buffer to GPU.
@@ -665,19 +941,25 @@ impl<'a> LodChunkEditorWithVoxelsMut<'a> {
         lower_lod: LodChunkDataWithVoxels,
         curr_lvl: u8,
         curr_sublvl: u8,
+        curr_sublvl_y: u8,
         lower_lvl: u8,
         lower_sublvl: u8,
+        lower_sublvl_y: u8,
         chunk_size: ChunkSize,
         largest_chunk_lvl: u8,
+        downsample: &Downsample,
     ) {
         self.data.update_from_lower_lod_voxels_untracked::<VE>(
             lower_lod,
             curr_lvl,
             curr_sublvl,
+            curr_sublvl_y,
             lower_lvl,
             lower_sublvl,
+            lower_sublvl_y,
             chunk_size,
             largest_chunk_lvl,
+            downsample,
         );
         self.update_full_buffer_gpu();
     }
@@ -689,8 +971,10 @@ impl<'a> LodChunkEditorWithVoxelsMut<'a> {
         index: usize,
         lower_lvl: u8,
         lower_sublvl: u8,
+        lower_sublvl_y: u8,
         chunk_size: ChunkSize,
         largest_chunk_lvl: u8,
+        downsample: &Downsample,
     ) {
         let voxel_type = self.data.calc_voxel_from_lower_lod::<VE>(
             &lower_lod,
@@ -698,11 +982,46 @@ impl<'a> LodChunkEditorWithVoxelsMut<'a> {
             index,
             lower_lvl,
             lower_sublvl,
+            lower_sublvl_y,
             chunk_size,
             largest_chunk_lvl,
+            downsample,
         );
         self.set_voxel(index, voxel_type.unwrap_or(VE::empty()));
     }
+
+    /// Like `update_voxel_from_lower_lod` for each of `voxels` (position and index in this LOD), with update
+    /// regions coalesced like `set_voxels`
+    pub fn update_voxels_from_lower_lod<VE: VoxelTypeEnum>(
+        &mut self,
+        lower_lod: &LodChunkDataWithVoxels,
+        voxels: impl IntoIterator<Item = (VoxelPosInLod, usize)>,
+        lower_lvl: u8,
+        lower_sublvl: u8,
+        lower_sublvl_y: u8,
+        chunk_size: ChunkSize,
+        largest_chunk_lvl: u8,
+        downsample: &Downsample,
+    ) {
+        let voxels: Vec<(usize, VE)> = voxels
+            .into_iter()
+            .map(|(pos, index)| {
+                let voxel_type = self.data.calc_voxel_from_lower_lod::<VE>(
+                    lower_lod,
+                    pos,
+                    index,
+                    lower_lvl,
+                    lower_sublvl,
+                    lower_sublvl_y,
+                    chunk_size,
+                    largest_chunk_lvl,
+                    downsample,
+                );
+                (index, voxel_type.unwrap_or(VE::empty()))
+            })
+            .collect();
+        self.set_voxels(voxels);
+    }
 }
 
 impl<'a> LodChunkEditorWithoutVoxelsMut<'a> {
@@ -717,6 +1036,13 @@ impl<'a> LodChunkEditorWithoutVoxelsMut<'a> {
         });
     }
 
+    /// Set whether a single voxel is visible and mark its block dirty for later GPU transfer
+    pub fn set_visible(&mut self, index: usize, visible: bool) {
+        self.bitmask.set_block(index, visible);
+        self.updated_regions.mark_dirty(index);
+    }
+
+    /// Returns whether the bit changed. Its block is only marked dirty if it did.
     pub fn update_bitmask_bit_from_lower_lod(
         &mut self,
         voxel_pos: VoxelPosInLod,
@@ -724,20 +1050,53 @@ impl<'a> LodChunkEditorWithoutVoxelsMut<'a> {
         lower_lod_bitmask: &ChunkBitmask,
         lower_lvl: u8,
         lower_sublvl: u8,
+        lower_sublvl_y: u8,
         chunk_size: ChunkSize,
         largest_chunk_lvl: u8,
-    ) {
-        update_bitmask_bit_from_lower_lod_untracked(
+    ) -> bool {
+        let changed = update_bitmask_bit_from_lower_lod_untracked(
             self.bitmask,
             voxel_pos,
             voxel_index,
             lower_lod_bitmask,
             lower_lvl,
             lower_sublvl,
+            lower_sublvl_y,
             chunk_size,
             largest_chunk_lvl,
         );
-        self.updated_regions.add_region(voxel_index, 1);
+        if changed {
+            self.updated_regions.mark_dirty(voxel_index);
+        }
+        changed
+    }
+
+    /// `update_bitmask_bit_from_lower_lod` for each of `voxels` (position and index in this LOD). Returns whether
+    /// any bit changed.
+    pub fn update_bitmask_bits_from_lower_lod(
+        &mut self,
+        voxels: impl IntoIterator<Item = (VoxelPosInLod, usize)>,
+        lower_lod_bitmask: &ChunkBitmask,
+        lower_lvl: u8,
+        lower_sublvl: u8,
+        lower_sublvl_y: u8,
+        chunk_size: ChunkSize,
+        largest_chunk_lvl: u8,
+    ) -> bool {
+        let mut any_changed = false;
+        for (voxel_pos, voxel_index) in voxels {
+            any_changed |= self.update_bitmask_bit_from_lower_lod(
+                voxel_pos,
+                voxel_index,
+                lower_lod_bitmask,
+                lower_lvl,
+                lower_sublvl,
+                lower_sublvl_y,
+                chunk_size,
+                largest_chunk_lvl,
+            );
+        }
+        any_changed
     }
 
     /// For LODs where there is only a bitmask and no voxel ID data, update the bitmask given a
@@ -747,8 +1106,10 @@ impl<'a> LodChunkEditorWithoutVoxelsMut<'a> {
         lower_lod_bitmask: &ChunkBitmask,
         curr_lvl: u8,
         curr_sublvl: u8,
+        curr_sublvl_y: u8,
         lower_lvl: u8,
         lower_sublvl: u8,
+        lower_sublvl_y: u8,
         chunk_size: ChunkSize,
         largest_chunk_lvl: u8,
     ) {
@@ -757,8 +1118,10 @@ impl<'a> LodChunkEditorWithoutVoxelsMut<'a> {
             lower_lod_bitmask,
             curr_lvl,
             curr_sublvl,
+            curr_sublvl_y,
             lower_lvl,
             lower_sublvl,
+            lower_sublvl_y,
             chunk_size,
             largest_chunk_lvl,
         );
@@ -766,9 +1129,19 @@ impl<'a> LodChunkEditorWithoutVoxelsMut<'a> {
     }
 }
 
+/// Replace voxel IDs that aren't valid for `VE` according to `VE::unknown_voxel_policy` so they aren't sent to
+/// the GPU. Panics on unknown IDs if the policy is `Error`.
+pub fn replace_unknown_voxel_ids<VE: VoxelTypeEnum>(voxels: &mut ChunkVoxels) {
+    for i in 0..voxels.n_voxels() {
+        if VE::from_u8(voxels[i]).is_none() {
+            voxels[i] = VE::from_id(voxels[i]).id();
+        }
+    }
+}
+
 pub fn calc_full_bitmask<VE: VoxelTypeEnum>(voxels: &ChunkVoxels, bitmask: &mut ChunkBitmask) {
     for i in 0..voxels.n_voxels() {
-        if VE::from_u8(voxels[i]).unwrap().def().is_visible {
+        if VE::from_id(voxels[i]).def().is_visible {
             bitmask.set_block_true(i);
         } else {
             bitmask.set_block_false(i);
@@ -776,94 +1149,38 @@ pub fn calc_full_bitmask<VE: VoxelTypeEnum>(voxels: &ChunkVoxels, bitmask: &mut
     }
 }
 
-/// Given a current lvl/sublvl and a lower lvl/sublvl, find all the voxels in the lower LOD that make
-/// up the voxel at `index`/`pt` in the current LOD and return an iterator over their indices.
-fn apply_to_voxel_indices_in_lower_lod<F: FnMut(usize)>(
-    voxel: VoxelPosInLod,
-    voxel_index: usize,
-    lower_lvl: u8,
-    lower_sublvl: u8,
-    chunk_size: ChunkSize,
-    largest_chunk_lvl: u8,
-    mut f: F,
-) {
-    if voxel.sublvl > 0 {
-        // First find all voxels in same lvl but sublvl zero.
-        // If voxel.lvl == lower_lvl then just convert to lower_sublvl instead of zero.
-        // Thus, `target_sublvl` is either zero or `lower_sublvl`
-        let (target_sublvl, target_sublvl_is_final) = if voxel.lvl == lower_lvl {
-            debug_assert!(lower_sublvl < voxel.sublvl);
-            (lower_sublvl, true)
-        } else {
-            (0, false)
-        };
+/// Counts the voxel types in the lower LOD voxels that make up one voxel of a higher LOD
+#[derive(Debug)]
+pub(crate) struct DownsampleCounts<VE> {
+    counts: CoreDownsampleCounts,
+    _voxel_type: PhantomData<VE>,
+}
 
-        let scale_relative_to_target_sublvl = 1u32 << (voxel.sublvl - target_sublvl);
-        let pos_in_target = voxel.pos * scale_relative_to_target_sublvl; // botleft pos in target sublvl
-        let start_idx_in_target = VoxelPosInLod {
-            pos: pos_in_target,
-            lvl: voxel.lvl,
-            sublvl: target_sublvl,
-        }
-        .index(chunk_size, largest_chunk_lvl);
-        // index increment when shifting z by 1 in target sublvl
-        let target_z_incr = 1u32 << (chunk_size.exp() - target_sublvl as u8);
-        // index increment when shifting y by 1 in target sublvl
-        let target_y_incr = target_z_incr * target_z_incr;
-        // (shifting x is always an increment of 1)
-
-        if target_sublvl_is_final {
-            for dy in 0..scale_relative_to_target_sublvl {
-                for dz in 0..scale_relative_to_target_sublvl {
-                    for dx in 0..scale_relative_to_target_sublvl {
-                        f(start_idx_in_target
-                            + (dx + dy * target_y_incr + dz * target_z_incr) as usize);
-                    }
-                }
-            }
-        } else {
-            for dy in 0..scale_relative_to_target_sublvl {
-                for dz in 0..scale_relative_to_target_sublvl {
-                    for dx in 0..scale_relative_to_target_sublvl {
-                        apply_to_voxel_indices_in_lower_lod_for_lvl(
-                            start_idx_in_target
-                                + (dx + dy * target_y_incr + dz * target_z_incr) as usize,
-                            voxel.lvl,
-                            lower_lvl,
-                            lower_sublvl,
-                            chunk_size,
-                            &mut f,
-                        );
-                    }
-                }
-            }
+impl<VE> Default for DownsampleCounts<VE> {
+    fn default() -> Self {
+        DownsampleCounts {
+            counts: CoreDownsampleCounts::default(),
+            _voxel_type: PhantomData,
         }
-    } else {
-        apply_to_voxel_indices_in_lower_lod_for_lvl(
-            voxel_index,
-            voxel.lvl,
-            lower_lvl,
-            lower_sublvl,
-            chunk_size,
-            &mut f,
-        );
     }
 }
 
-/// `apply_to_voxel_indices_in_lower_lod` special case where sublvl == 0
-fn apply_to_voxel_indices_in_lower_lod_for_lvl<F: FnMut(usize)>(
-    voxel_index: usize,
-    lvl: u8,
-    lower_lvl: u8,
-    lower_sublvl: u8,
-    chunk_size: ChunkSize,
-    f: &mut F,
-) {
-    let scale_relative_to_lower = 1u32 << (chunk_size.exp() * (lvl - lower_lvl) - lower_sublvl);
-    let lower_voxels_per = cubed(scale_relative_to_lower) as usize;
-    let first_idx_in_lower = voxel_index * lower_voxels_per;
-    for idx in first_idx_in_lower..(first_idx_in_lower + lower_voxels_per) {
-        f(idx);
+impl<VE: VoxelTypeEnum> DownsampleCounts<VE> {
+    pub fn add(&mut self, vox_type: VE) {
+        self.counts.add(vox_type.id(), voxel_type_info(vox_type));
+    }
+
+    /// The voxel type `downsample` picks for the counted voxels, `None` if it should be empty
+    pub fn result(self, downsample: &Downsample) -> Option<VE> {
+        self.counts.result(downsample).map(VE::from_id)
+    }
+}
+
+/// What `ox_core::voxel::lod` needs to know to downsample voxels of this type
+pub fn voxel_type_info<VE: VoxelTypeEnum>(vox_type: VE) -> VoxelTypeInfo {
+    VoxelTypeInfo {
+        is_visible: vox_type.def().is_visible,
+        opacity: vox_type.opacity(),
     }
 }
 
@@ -878,6 +1195,12 @@ pub struct TakenLodChunk<VE: VoxelTypeEnum> {
     lvl: u8,
     #[get_copy = "pub"]
     sublvl: u8,
+    #[get_copy = "pub"]
+    sublvl_y: u8,
+    #[get = "pub"]
+    downsample: Downsample,
+    #[get_copy = "pub"]
+    version: u64,
 }
 
 impl<VE: VoxelTypeEnum> TakenLodChunk<VE> {
@@ -887,22 +1210,29 @@ impl<VE: VoxelTypeEnum> TakenLodChunk<VE> {
             data,
             lvl,
             sublvl,
+            sublvl_y,
+            downsample,
             updated_regions,
         }: &mut LodChunkEditorMaybeUnloaded<VE>,
     ) -> Option<Self> {
+        let version = data.version();
         Some(Self {
             voxel_type_enum: PhantomData,
             data: data.take()?,
             chunk_idx: updated_regions.chunk_idx,
             lvl: *lvl,
             sublvl: *sublvl,
+            sublvl_y: *sublvl_y,
+            downsample: downsample.clone(),
+            version,
         })
     }
 }
 
 impl<VE: VoxelTypeEnum> TakenLodChunk<VE> {
-    pub fn return_data(self, lod: &mut VoxelMemoryGridLod) {
-        lod.chunks_mut()[self.chunk_idx] = LayerChunk::new_valid(self.data);
+    /// Returns false if the data was stale and so was left invalid
+    pub fn return_data(self, lod: &mut VoxelMemoryGridLod) -> bool {
+        lod.return_taken(self.chunk_idx, self.data, self.version)
     }
 }
 
@@ -914,6 +1244,7 @@ pub struct LodChunkOverwriter<'a, VE: VoxelTypeEnum> {
 
 impl<'a, VE: VoxelTypeEnum> Drop for LodChunkOverwriter<'a, VE> {
     fn drop(&mut self) {
+        replace_unknown_voxel_ids::<VE>(self.chunk.voxel_ids);
         calc_full_bitmask::<VE>(&self.chunk.voxel_ids, &mut self.chunk.bitmask);
     }
 }
@@ -953,56 +1284,128 @@ mod tests {
     use enum_iterator::Sequence;
     use num_derive::{FromPrimitive, ToPrimitive};
 
-    use crate::voxel_type::{Material, VoxelTypeDefinition};
+    use crate::voxel_type::{Material, UnknownVoxelPolicy, VoxelTypeDefinition};
+    use crate::world::mem_grid::utils::cubed;
+    use crate::world::VoxelPos;
+    use cgmath::Point3;
 
     use super::*;
 
     #[test]
-    fn test_apply_to_all_voxels_in_lod_0_0() {
-        let mut indices = [false; 64 * 64 * 64];
-        let cs = ChunkSize::new(3);
-        apply_to_voxels_in_lod(0, 0, cs, 2, |pos| {
-            let idx = pos.index(cs, 2);
-            assert!(!indices[idx]);
-            indices[idx] = true;
-        });
-        assert!(indices.into_iter().all(|x| x));
+    fn test_compact_lod_chunk() {
+        let n_voxels = cubed(64);
+        let mut chunk = LodChunkData::new_empty(n_voxels, true);
+        assert!(chunk.is_sparse());
+        assert_eq!(chunk.voxel_id(10), Some(0));
+
+        // Editing makes it dense
+        match chunk.check_voxel_ids_mut() {
+            LodChunkDataVariantMut::WithVoxels(data) => {
+                data.voxel_ids[10] = 2;
+                data.bitmask.set_block_true(10);
+            }
+            LodChunkDataVariantMut::WithoutVoxels(_) => panic!(),
+        }
+        assert!(!chunk.is_sparse());
+        let dense_bytes = chunk.heap_bytes();
+        let (bitmask, ids) = chunk.gpu_data();
+        let (bitmask, ids) = (bitmask.into_owned(), ids.unwrap().into_owned());
+
+        // Only one voxel is set, so it is much smaller when sparse but has the same data
+        assert!(chunk.compact());
+        assert!(chunk.heap_bytes() * 10 < dense_bytes);
+        assert!(chunk.is_visible(10));
+        assert!(!chunk.is_visible(11));
+        assert_eq!(chunk.voxel_id(10), Some(2));
+        let (sparse_bitmask, sparse_ids) = chunk.gpu_data();
+        assert_eq!(*sparse_bitmask, *bitmask);
+        assert_eq!(*sparse_ids.unwrap(), *ids);
     }
 
     #[test]
-    fn test_apply_to_all_voxels_in_lod_0_1() {
-        let mut indices = [false; 32 * 32 * 32];
-        let cs = ChunkSize::new(3);
-        apply_to_voxels_in_lod(0, 1, cs, 2, |pos| {
-            let idx = pos.index(cs, 2);
-            assert!(!indices[idx]);
-            indices[idx] = true;
-        });
-        assert!(indices.into_iter().all(|x| x));
+    fn test_dirty_block_regions() {
+        let mut dirty = DirtyBlocks::new(4, DIRTY_BLOCK_VOXELS * 3 + 8);
+        assert!(dirty.is_empty());
+        for voxel_idx in [5, 2, DIRTY_BLOCK_VOXELS + 9, DIRTY_BLOCK_VOXELS * 3 + 1] {
+            dirty.mark(3, voxel_idx);
+        }
+        dirty.mark(1, DIRTY_BLOCK_VOXELS * 2);
+        let summary = |dirty: &DirtyBlocks| {
+            dirty
+                .regions()
+                .iter()
+                .map(|r| (r.chunk_idx, r.voxel_idx, r.n_voxels))
+                .collect::<Vec<_>>()
+        };
+        // Adjacent blocks are merged and the last, partial block ends with the chunk
+        assert_eq!(
+            summary(&dirty),
+            vec![
+                (3, 0, DIRTY_BLOCK_VOXELS * 2),
+                (3, DIRTY_BLOCK_VOXELS * 3, 8),
+                (1, DIRTY_BLOCK_VOXELS * 2, DIRTY_BLOCK_VOXELS),
+            ]
+        );
+        dirty.clear();
+        assert!(dirty.is_empty());
+        assert!(summary(&dirty).is_empty());
     }
 
     #[test]
-    fn test_apply_to_all_voxels_in_lod_0_2() {
-        let mut indices = [false; 16 * 16 * 16];
+    fn test_bitmask_bit_updated_from_finer_bitmask_lod() {
         let cs = ChunkSize::new(3);
-        apply_to_voxels_in_lod(0, 2, cs, 2, |pos| {
-            let idx = pos.index(cs, 2);
-            assert!(!indices[idx]);
-            indices[idx] = true;
-        });
-        assert!(indices.into_iter().all(|x| x));
-    }
+        // (lvl, sublvl, voxels per TLC side) of a full LOD followed by bitmask-only LODs
+        let lods = [(0, 0, 64), (0, 2, 16), (1, 0, 8), (1, 2, 2)];
+        let mut bitmasks: Vec<_> = lods
+            .iter()
+            .map(|&(_, _, size)| ChunkBitmask::new_blank(cubed(size)))
+            .collect();
+        let full_pos = VoxelPosInLod::in_full_lod(VoxelPos(Point3::new(37, 5, 60)));
+
+        for visible in [true, false] {
+            bitmasks[0].set_block(full_pos.index(cs, 2), visible);
+            // Each bit is updated from the LOD before it, and changes with the full LOD voxel
+            for i in 1..lods.len() {
+                let (lvl, sublvl, _) = lods[i];
+                let (finer_lvl, finer_sublvl, _) = lods[i - 1];
+                let pos = full_pos.in_other_lod(lvl, sublvl, sublvl, cs);
+                let (finer, curr) = bitmasks.split_at_mut(i);
+                let update = |bitmask: &mut ChunkBitmask| {
+                    update_bitmask_bit_from_lower_lod_untracked(
+                        bitmask,
+                        pos,
+                        pos.index(cs, 2),
+                        &finer[i - 1],
+                        finer_lvl,
+                        finer_sublvl,
+                        finer_sublvl,
+                        cs,
+                        2,
+                    )
+                };
+                assert!(update(&mut curr[0]));
+                assert!(!update(&mut curr[0]));
+            }
 
-    #[test]
-    fn test_apply_to_all_voxels_in_lod_1_0() {
-        let mut indices = [false; 8 * 8 * 8];
-        let cs = ChunkSize::new(3);
-        apply_to_voxels_in_lod(1, 0, cs, 2, |pos| {
-            let idx = pos.index(cs, 2);
-            assert!(!indices[idx]);
-            indices[idx] = true;
-        });
-        assert!(indices.into_iter().all(|x| x));
+            // Same result as recomputing the whole bitmask from the full LOD
+            for i in 1..lods.len() {
+                let (lvl, sublvl, size) = lods[i];
+                let mut full = ChunkBitmask::new_blank(cubed(size));
+                update_bitmask_from_lower_lod_untracked(
+                    &mut full,
+                    &bitmasks[0],
+                    lvl,
+                    sublvl,
+                    sublvl,
+                    0,
+                    0,
+                    0,
+                    cs,
+                    2,
+                );
+                assert_eq!(full, bitmasks[i], "lvl {} sublvl {}", lvl, sublvl);
+            }
+        }
     }
 
     #[derive(Debug, Sequence, Clone, Copy, FromPrimitive, ToPrimitive, Hash, PartialEq, Eq)]
@@ -1038,6 +1441,10 @@ mod tests {
         fn empty() -> Block {
             Block::AIR
         }
+
+        fn unknown_voxel_policy() -> UnknownVoxelPolicy {
+            UnknownVoxelPolicy::ReplaceWithEmpty
+        }
     }
 
     #[test]
@@ -1054,4 +1461,13 @@ mod tests {
         };
         assert_eq!(bm, true_bm);
     }
+
+    #[test]
+    fn test_replace_unknown_voxel_ids() {
+        let mut voxels = ChunkVoxels::new_blank(32 * 32 * 32);
+        voxels[0] = 1;
+        voxels[1] = 200;
+        replace_unknown_voxel_ids::<Block>(&mut voxels);
+        assert_eq!((voxels[0], voxels[1]), (1, 0));
+    }
 }
@@ -0,0 +1,287 @@
+//! Optional higher resolution layer below LOD 0 ("microvoxels"). Each LOD 0 voxel of a chunk that has a slot
+//! allocated is split into 2x2x2 or 4x4x4 microvoxels, so detail only costs memory where it's requested (e.g.
+//! near the player) instead of raising the resolution of the whole world.
+//!
+//! Slots are per level 1 chunk (`chunk_size` LOD 0 voxels per side) and are addressed by the chunk's global
+//! position in units of level 1 chunks, i.e. `floor(global LOD 0 voxel position / chunk_size)`. The shader
+//! (with `MICROVOXELS` set) looks up the slot of a LOD 0 voxel it hits in the slot table and, if there is one,
+//! continues traversal through that voxel's microvoxels.
+
+use crate::renderer::component::voxels::data::VoxelTypeIDs;
+use crate::renderer::component::voxels::micro::MicrovoxelSlot;
+use crate::voxel_type::VoxelTypeEnum;
+use crate::world::mem_grid::utils::{cubed, ChunkSize};
+use crate::world::mem_grid::voxel::downsample::Downsample;
+use crate::world::mem_grid::voxel::gpu_defs::ChunkVoxels;
+use crate::world::mem_grid::voxel::lod::DownsampleCounts;
+use cgmath::{Point3, Vector3};
+use hashbrown::HashMap;
+use std::mem::size_of;
+use vulkano::command_buffer::BufferCopy;
+
+#[derive(Debug, Clone, Copy)]
+pub struct MicrovoxelParams {
+    /// Microvoxels per LOD 0 voxel per side will be 2^`scale_exp`. Must be 1 or 2.
+    pub scale_exp: u8,
+    /// Maximum number of chunks that can have microvoxels at once
+    pub n_slots: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct MicrovoxelUpdate<'a> {
+    pub ids: &'a [VoxelTypeIDs],
+    pub ids_updated_regions: Vec<BufferCopy>,
+    pub slots: &'a [MicrovoxelSlot],
+    pub slots_updated_regions: Vec<BufferCopy>,
+}
+
+#[derive(Debug)]
+pub struct MicrovoxelGrid {
+    params: MicrovoxelParams,
+    chunk_size: ChunkSize,
+    slots: Vec<MicrovoxelSlot>,
+    slot_of_chunk: HashMap<Point3<i64>, usize>,
+    /// Microvoxel IDs of all slots, one after another
+    ids: ChunkVoxels,
+    dirty_ids: Vec<bool>,
+    dirty_slots: Vec<bool>,
+}
+
+impl MicrovoxelGrid {
+    pub fn new(params: MicrovoxelParams, chunk_size: ChunkSize) -> Self {
+        assert!(
+            params.scale_exp == 1 || params.scale_exp == 2,
+            "Microvoxel scale_exp must be 1 or 2"
+        );
+        let voxels_per_slot = cubed(chunk_size.size() << params.scale_exp);
+        MicrovoxelGrid {
+            params,
+            chunk_size,
+            slots: vec![MicrovoxelSlot::default(); params.n_slots],
+            slot_of_chunk: HashMap::new(),
+            ids: ChunkVoxels::new_blank(voxels_per_slot * params.n_slots),
+            dirty_ids: vec![false; params.n_slots],
+            dirty_slots: vec![false; params.n_slots],
+        }
+    }
+
+    pub fn params(&self) -> MicrovoxelParams {
+        self.params
+    }
+
+    /// Microvoxels per chunk per side
+    pub fn slot_size(&self) -> usize {
+        self.chunk_size.size() << self.params.scale_exp
+    }
+
+    pub fn voxels_per_slot(&self) -> usize {
+        cubed(self.slot_size())
+    }
+
+    /// Level 1 chunk containing the LOD 0 voxel at global position `voxel`
+    pub fn chunk_of(&self, voxel: Point3<i64>) -> Point3<i64> {
+        let size = self.chunk_size.size() as i64;
+        voxel.map(|a| a.div_euclid(size))
+    }
+
+    pub fn slot(&self, chunk: Point3<i64>) -> Option<usize> {
+        self.slot_of_chunk.get(&chunk).copied()
+    }
+
+    pub fn n_free_slots(&self) -> usize {
+        self.params.n_slots - self.slot_of_chunk.len()
+    }
+
+    /// Give `chunk` a slot, filling each microvoxel with the ID of the LOD 0 voxel it's in, which `lod0_id`
+    /// returns given a position within the chunk. Returns the existing slot if `chunk` already has one and
+    /// `None` if all slots are in use.
+    pub fn allocate<F: FnMut(Point3<u32>) -> u8>(
+        &mut self,
+        chunk: Point3<i64>,
+        mut lod0_id: F,
+    ) -> Option<usize> {
+        if let Some(slot) = self.slot(chunk) {
+            return Some(slot);
+        }
+        let slot = self.slots.iter().position(|s| s.in_use == 0)?;
+
+        let scale_exp = self.params.scale_exp;
+        let slot_size = self.slot_size() as u32;
+        let start = slot * self.voxels_per_slot();
+        for z in 0..slot_size {
+            for y in 0..slot_size {
+                for x in 0..slot_size {
+                    let id = lod0_id(Point3::new(x >> scale_exp, y >> scale_exp, z >> scale_exp));
+                    self.ids[start + micro_index(Point3::new(x, y, z), slot_size)] = id;
+                }
+            }
+        }
+
+        self.slots[slot] = MicrovoxelSlot {
+            chunk: chunk.map(|a| a as i32).into(),
+            in_use: 1,
+        };
+        self.slot_of_chunk.insert(chunk, slot);
+        self.dirty_ids[slot] = true;
+        self.dirty_slots[slot] = true;
+        Some(slot)
+    }
+
+    /// Release the slot of `chunk`. Returns false if it didn't have one.
+    pub fn free(&mut self, chunk: Point3<i64>) -> bool {
+        match self.slot_of_chunk.remove(&chunk) {
+            None => false,
+            Some(slot) => {
+                self.slots[slot].in_use = 0;
+                self.dirty_slots[slot] = true;
+                true
+            }
+        }
+    }
+
+    /// Free every slot whose chunk `keep` returns false for, e.g. chunks that are too far from the player
+    pub fn retain<F: FnMut(Point3<i64>) -> bool>(&mut self, mut keep: F) {
+        let to_free: Vec<_> = self
+            .slot_of_chunk
+            .keys()
+            .copied()
+            .filter(|chunk| !keep(*chunk))
+            .collect();
+        for chunk in to_free {
+            self.free(chunk);
+        }
+    }
+
+    /// Microvoxel ID at `pos` (in microvoxels relative to the chunk), or `None` if `chunk` has no slot
+    pub fn get(&self, chunk: Point3<i64>, pos: Point3<u32>) -> Option<u8> {
+        let slot = self.slot(chunk)?;
+        Some(self.ids[slot * self.voxels_per_slot() + micro_index(pos, self.slot_size() as u32)])
+    }
+
+    /// Set the microvoxel at `pos` (in microvoxels relative to the chunk). Fails if `chunk` has no slot.
+    pub fn set(&mut self, chunk: Point3<i64>, pos: Point3<u32>, id: u8) -> Result<(), ()> {
+        let slot = self.slot(chunk).ok_or(())?;
+        let slot_size = self.slot_size() as u32;
+        debug_assert!(pos.x < slot_size && pos.y < slot_size && pos.z < slot_size);
+        let index = slot * self.voxels_per_slot() + micro_index(pos, slot_size);
+        self.ids[index] = id;
+        self.dirty_ids[slot] = true;
+        Ok(())
+    }
+
+    /// Voxel type to use for the LOD 0 voxel at `voxel_pos` (relative to the chunk) given its microvoxels,
+    /// i.e. the most common visible type among them, or empty if none are visible. Useful to keep LOD 0 (and
+    /// so the lower LODs and collision) in sync after editing microvoxels. Returns `None` if `chunk` has no
+    /// slot.
+    pub fn lod0_voxel<VE: VoxelTypeEnum>(
+        &self,
+        chunk: Point3<i64>,
+        voxel_pos: Point3<u32>,
+    ) -> Option<VE> {
+        let slot = self.slot(chunk)?;
+        let scale = 1u32 << self.params.scale_exp;
+        let slot_size = self.slot_size() as u32;
+        let start = slot * self.voxels_per_slot();
+        let mut counts = DownsampleCounts::default();
+        for z in 0..scale {
+            for y in 0..scale {
+                for x in 0..scale {
+                    let pos = voxel_pos.map(|a| a * scale) + Vector3::new(x, y, z);
+                    counts.add(VE::from_id(self.ids[start + micro_index(pos, slot_size)]));
+                }
+            }
+        }
+        Some(counts.result(&Downsample::default()).unwrap_or(VE::empty()))
+    }
+
+    /// Regions of the microvoxel ID and slot table buffers changed since the last call
+    pub fn get_updates(&mut self) -> MicrovoxelUpdate<'_> {
+        let slot_id_bytes = (self.voxels_per_slot() * VoxelTypeIDs::BITS_PER_VOXEL / 8) as u64;
+        let slot_bytes = size_of::<MicrovoxelSlot>() as u64;
+        MicrovoxelUpdate {
+            ids_updated_regions: take_dirty_regions(&mut self.dirty_ids, slot_id_bytes),
+            slots_updated_regions: take_dirty_regions(&mut self.dirty_slots, slot_bytes),
+            ids: &self.ids.ids,
+            slots: &self.slots,
+        }
+    }
+}
+
+/// Index of a microvoxel within its slot. Same ordering as voxels within a chunk elsewhere (x, then z, then y).
+fn micro_index(pos: Point3<u32>, slot_size: u32) -> usize {
+    (pos.x + pos.y * slot_size * slot_size + pos.z * slot_size) as usize
+}
+
+/// Copy regions for each dirty entry of `dirty` (each `entry_bytes` long), merging adjacent ones, and clear
+/// `dirty`.
+fn take_dirty_regions(dirty: &mut [bool], entry_bytes: u64) -> Vec<BufferCopy> {
+    let mut regions: Vec<BufferCopy> = vec![];
+    for (i, d) in dirty.iter_mut().enumerate() {
+        if !*d {
+            continue;
+        }
+        *d = false;
+        let offset = i as u64 * entry_bytes;
+        match regions.last_mut() {
+            Some(last) if last.src_offset + last.size == offset => last.size += entry_bytes,
+            _ => regions.push(BufferCopy {
+                src_offset: offset,
+                dst_offset: offset,
+                size: entry_bytes,
+                ..Default::default()
+            }),
+        }
+    }
+    regions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_microvoxel_slots() {
+        let params = MicrovoxelParams {
+            scale_exp: 1,
+            n_slots: 3,
+        };
+        let mut grid = MicrovoxelGrid::new(params, ChunkSize::new(2));
+        assert_eq!(grid.slot_size(), 8);
+        assert_eq!(grid.chunk_of(Point3::new(-1, 4, 3)), Point3::new(-1, 1, 0));
+
+        let a = Point3::new(0, 0, 0);
+        let b = Point3::new(-1, 2, 0);
+        assert_eq!(grid.allocate(a, |_| 0), Some(0));
+        assert_eq!(grid.allocate(b, |pos| (pos.x == 3) as u8), Some(1));
+        assert_eq!(grid.allocate(b, |_| 0), Some(1));
+        assert_eq!(grid.n_free_slots(), 1);
+
+        // Each microvoxel starts as the LOD 0 voxel it's in
+        assert_eq!(grid.get(b, Point3::new(6, 0, 0)), Some(1));
+        assert_eq!(grid.get(b, Point3::new(7, 7, 7)), Some(1));
+        assert_eq!(grid.get(b, Point3::new(5, 0, 0)), Some(0));
+        assert_eq!(grid.get(Point3::new(5, 5, 5), Point3::new(0, 0, 0)), None);
+
+        let updates = grid.get_updates();
+        assert_eq!(updates.ids_updated_regions.len(), 1);
+        assert_eq!(updates.ids_updated_regions[0].size, 2 * 8 * 8 * 8);
+        assert_eq!(updates.slots[1].chunk, [-1, 2, 0]);
+        assert!(grid.get_updates().ids_updated_regions.is_empty());
+
+        grid.set(b, Point3::new(1, 2, 3), 1).unwrap();
+        assert_eq!(grid.get(b, Point3::new(1, 2, 3)), Some(1));
+        assert!(grid
+            .set(Point3::new(5, 5, 5), Point3::new(0, 0, 0), 1)
+            .is_err());
+        let updates = grid.get_updates();
+        assert_eq!(updates.ids_updated_regions[0].src_offset, 8 * 8 * 8);
+        assert!(updates.slots_updated_regions.is_empty());
+
+        grid.retain(|chunk| chunk != a);
+        assert_eq!(grid.slot(a), None);
+        assert_eq!(grid.allocate(Point3::new(1, 1, 1), |_| 0), Some(0));
+        assert!(grid.free(b));
+        assert!(!grid.free(b));
+        assert_eq!(grid.n_free_slots(), 2);
+    }
+}
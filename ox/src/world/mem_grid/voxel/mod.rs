@@ -1,7 +1,14 @@
-pub(crate) mod gpu_defs;
+pub mod config;
 pub mod grid;
-mod lod;
+pub mod limits;
+pub(crate) mod lod;
+pub mod micro;
+pub(crate) mod seams;
 
-pub use gpu_defs::{ChunkBitmask, ChunkVoxels};
+pub(crate) use ox_core::voxel::{brickmap, gpu_defs};
+pub use ox_core::voxel::{downsample, ChunkBitmask, ChunkVoxels, Downsample, DownsamplePolicy};
+
+pub use config::LodConfig;
 pub use grid::VoxelMemoryGrid;
 pub use lod::VoxelLODCreateParams;
+pub use micro::{MicrovoxelGrid, MicrovoxelParams};
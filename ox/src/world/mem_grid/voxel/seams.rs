@@ -0,0 +1,320 @@
+//! Stitching of seams between neighboring chunks that are rendered at different LODs. Each LOD of a chunk is
+//! generated on its own, so where a chunk drawn at a coarse LOD borders one drawn at a finer LOD, the coarse voxels
+//! along the shared face can leave gaps that the finer neighbor's surface doesn't (e.g. a thin wall or the top layer
+//! of terrain that is too small to fill a coarse voxel), which show up as cracks along chunk borders.
+//!
+//! `VoxelMemoryGrid::stitch_seams` fixes this after chunks are loaded: each coarse voxel on the face is made visible
+//! if any of the finer neighbor's voxels touching it across the face are, using the most common of their types.
+//! Voxels are only ever filled in, never removed, so stitching again is harmless.
+
+use crate::voxel_type::VoxelTypeEnum;
+use crate::world::mem_grid::utils::{ChunkSize, VoxelPosInLod};
+use crate::world::mem_grid::voxel::grid::{lod_tlc_size, VoxelMemoryGrid};
+use crate::world::mem_grid::voxel::lod::LodChunkEditorVariantMut;
+use crate::world::mem_grid::EditMemoryGridChunk;
+use crate::world::{BufferChunkState, TlcPos, TlcVector, World};
+use cgmath::{Point3, Vector3};
+use hashbrown::{HashMap, HashSet};
+
+/// Axis and direction of the six face neighbors of a chunk
+const FACES: [(usize, bool); 6] = [
+    (0, false),
+    (0, true),
+    (1, false),
+    (1, true),
+    (2, false),
+    (2, true),
+];
+
+/// An LOD of a chunk on one side of a seam
+#[derive(Debug, Clone, Copy)]
+struct SeamLod {
+    lvl: u8,
+    sublvl: u8,
+    sublvl_y: u8,
+}
+
+impl SeamLod {
+    fn tlc_size(&self, chunk_size: ChunkSize, largest_chunk_lvl: u8) -> Vector3<u32> {
+        let size = lod_tlc_size(chunk_size, largest_chunk_lvl, self.lvl, self.sublvl) as u32;
+        let size_y = lod_tlc_size(chunk_size, largest_chunk_lvl, self.lvl, self.sublvl_y) as u32;
+        Vector3::new(size, size_y, size)
+    }
+
+    fn index(&self, pos: Point3<u32>, chunk_size: ChunkSize, largest_chunk_lvl: u8) -> usize {
+        VoxelPosInLod {
+            pos,
+            lvl: self.lvl,
+            sublvl: self.sublvl,
+            sublvl_y: self.sublvl_y,
+        }
+        .index(chunk_size, largest_chunk_lvl)
+    }
+}
+
+/// Empty voxels on the face of a chunk at LOD `coarse` (the face on axis `ax`, on the positive side if `positive`)
+/// that touch visible voxels of the neighbor at LOD `fine` across that face. Returns their indices along with the
+/// most common ID of those visible voxels (lowest on ties), or `None` if the fine LOD only has a bitmask.
+/// `fine_voxel` gives the visibility and ID of a voxel of the neighbor by index.
+#[allow(clippy::too_many_arguments)]
+fn seam_fills(
+    coarse: SeamLod,
+    fine: SeamLod,
+    ax: usize,
+    positive: bool,
+    chunk_size: ChunkSize,
+    largest_chunk_lvl: u8,
+    coarse_visible: impl Fn(usize) -> bool,
+    fine_voxel: impl Fn(usize) -> (bool, Option<u8>),
+) -> Vec<(usize, Option<u8>)> {
+    let coarse_size = coarse.tlc_size(chunk_size, largest_chunk_lvl);
+    let fine_size = fine.tlc_size(chunk_size, largest_chunk_lvl);
+    let ratio = fine_size.zip(coarse_size, |f, c| f / c);
+    let coarse_layer = if positive { coarse_size[ax] - 1 } else { 0 };
+    let fine_layer = if positive { 0 } else { fine_size[ax] - 1 };
+    let (ax_b, ax_c) = ((ax + 1) % 3, (ax + 2) % 3);
+
+    let mut fills = vec![];
+    for b in 0..coarse_size[ax_b] {
+        for c in 0..coarse_size[ax_c] {
+            let mut pos = Point3::new(0, 0, 0);
+            pos[ax] = coarse_layer;
+            pos[ax_b] = b;
+            pos[ax_c] = c;
+            let index = coarse.index(pos, chunk_size, largest_chunk_lvl);
+            if coarse_visible(index) {
+                continue;
+            }
+
+            let mut any_visible = false;
+            let mut id_counts: HashMap<u8, u32> = HashMap::new();
+            for fb in b * ratio[ax_b]..(b + 1) * ratio[ax_b] {
+                for fc in c * ratio[ax_c]..(c + 1) * ratio[ax_c] {
+                    let mut fine_pos = Point3::new(0, 0, 0);
+                    fine_pos[ax] = fine_layer;
+                    fine_pos[ax_b] = fb;
+                    fine_pos[ax_c] = fc;
+                    let (visible, id) =
+                        fine_voxel(fine.index(fine_pos, chunk_size, largest_chunk_lvl));
+                    if visible {
+                        any_visible = true;
+                        if let Some(id) = id {
+                            *id_counts.entry(id).or_insert(0) += 1;
+                        }
+                    }
+                }
+            }
+            if any_visible {
+                let id = id_counts
+                    .into_iter()
+                    .max_by_key(|(id, count)| (*count, std::cmp::Reverse(*id)))
+                    .map(|(id, _)| id);
+                fills.push((index, id));
+            }
+        }
+    }
+    fills
+}
+
+impl<const N: usize> VoxelMemoryGrid<N> {
+    /// Index of the finest LOD that has valid data for the chunk at `pos`, i.e. the LOD it is drawn at
+    fn finest_loaded_lod(
+        &self,
+        pos: TlcPos<i64>,
+        buffer_chunk_states: [BufferChunkState; 3],
+    ) -> Option<usize> {
+        self.lods().iter().position(|lod| {
+            lod.slot_of(pos, buffer_chunk_states)
+                .is_some_and(|slot| lod.chunks()[slot].get().is_some())
+        })
+    }
+
+    /// Stitch the seams between each chunk in `positions` and its six neighbors, in both directions, where both
+    /// are loaded and one is drawn at a coarser LOD than the other (see the module docs). Pass the chunks that
+    /// just finished loading, i.e. `ChunkLoader::returned_last`, after each `ChunkLoader::sync`. Returns the number
+    /// of voxels that were filled in.
+    pub fn stitch_seams<VE: VoxelTypeEnum>(
+        &mut self,
+        positions: &[TlcPos<i64>],
+        buffer_chunk_states: [BufferChunkState; 3],
+    ) -> usize {
+        // (coarser chunk, axis and direction to its neighbor), so that chunks loaded next to each other in the
+        // same sync are only stitched once
+        let mut faces = HashSet::new();
+        for pos in positions {
+            for (ax, positive) in FACES {
+                let mut offset = Vector3::new(0, 0, 0);
+                offset[ax] = if positive { 1 } else { -1 };
+                let neighbor = *pos + TlcVector(offset);
+                faces.insert((pos.0, ax, positive));
+                faces.insert((neighbor.0, ax, !positive));
+            }
+        }
+        faces
+            .into_iter()
+            .map(|(pos, ax, positive)| {
+                self.stitch_face::<VE>(TlcPos(pos), ax, positive, buffer_chunk_states)
+            })
+            .sum()
+    }
+
+    /// Stitch the face of the chunk at `pos` on axis `ax` (positive side if `positive`) to its neighbor there if
+    /// the neighbor is drawn at a finer LOD
+    fn stitch_face<VE: VoxelTypeEnum>(
+        &mut self,
+        pos: TlcPos<i64>,
+        ax: usize,
+        positive: bool,
+        buffer_chunk_states: [BufferChunkState; 3],
+    ) -> usize {
+        let mut offset = Vector3::new(0, 0, 0);
+        offset[ax] = if positive { 1 } else { -1 };
+        let neighbor = pos + TlcVector(offset);
+        let (Some(coarse_i), Some(fine_i)) = (
+            self.finest_loaded_lod(pos, buffer_chunk_states),
+            self.finest_loaded_lod(neighbor, buffer_chunk_states),
+        ) else {
+            return 0;
+        };
+        if fine_i >= coarse_i {
+            return 0;
+        }
+
+        let chunk_size = self.metadata().chunk_size();
+        let largest_chunk_lvl = self.metadata().largest_lod().lvl();
+        let seam_lod = |i: usize| {
+            let extra = self.lods()[i].metadata().extra();
+            SeamLod {
+                lvl: extra.lvl,
+                sublvl: extra.sublvl,
+                sublvl_y: extra.sublvl_y,
+            }
+        };
+        let (coarse, fine) = (seam_lod(coarse_i), seam_lod(fine_i));
+        let (Some(fine_slot), Some(coarse_slot)) = (
+            self.lods()[fine_i].slot_of(neighbor, buffer_chunk_states),
+            self.lods()[coarse_i].slot_of(pos, buffer_chunk_states),
+        ) else {
+            return 0;
+        };
+        let fine_data = self.lods()[fine_i].chunks()[fine_slot].get().unwrap();
+        let coarse_data = self.lods()[coarse_i].chunks()[coarse_slot].get().unwrap();
+        let fills = seam_fills(
+            coarse,
+            fine,
+            ax,
+            positive,
+            chunk_size,
+            largest_chunk_lvl,
+            |index| coarse_data.is_visible(index),
+            |index| (fine_data.is_visible(index), fine_data.voxel_id(index)),
+        );
+        if fills.is_empty() {
+            return 0;
+        }
+
+        let lod = &mut self.lods_mut()[coarse_i];
+        let mut editor =
+            <_ as EditMemoryGridChunk<VE>>::edit_chunk(lod, pos, buffer_chunk_states).unwrap();
+        let mut editor = editor.as_loaded().unwrap();
+        let mut n_filled = 0;
+        match editor.with_voxel_ids_mut() {
+            LodChunkEditorVariantMut::WithVoxels(mut lod) => {
+                for (index, id) in fills {
+                    // A finer LOD without voxel IDs next to a coarser one with them doesn't happen with valid LOD
+                    // configs, since only the coarsest LODs are bitmask-only
+                    if let Some(voxel_type) = id.and_then(VE::try_from_id) {
+                        lod.set_voxel(index, voxel_type);
+                        n_filled += 1;
+                    }
+                }
+            }
+            LodChunkEditorVariantMut::WithoutVoxels(mut lod) => {
+                for (index, _) in fills {
+                    lod.set_visible(index, true);
+                    n_filled += 1;
+                }
+            }
+        }
+        n_filled
+    }
+}
+
+impl<const N: usize> World<VoxelMemoryGrid<N>> {
+    /// `VoxelMemoryGrid::stitch_seams` with this world's buffer chunks, e.g.
+    /// `world.stitch_seams::<Block>(loader.returned_last())` after `loader.sync(...)`
+    pub fn stitch_seams<VE: VoxelTypeEnum>(&mut self, positions: &[TlcPos<i64>]) -> usize {
+        let buffer_chunk_states = *self.metadata().buffer_chunk_states();
+        self.mem_grid
+            .stitch_seams::<VE>(positions, buffer_chunk_states)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seam_fills() {
+        // TLCs of 16 LOD 0 voxels. The coarse chunk (lvl 1, 4 voxels per side) is on the negative x side of the
+        // fine one (LOD 0), whose face has a surface at y = 5.
+        let (cs, largest) = (ChunkSize::new(2), 2);
+        let coarse = SeamLod {
+            lvl: 1,
+            sublvl: 0,
+            sublvl_y: 0,
+        };
+        let fine = SeamLod {
+            lvl: 0,
+            sublvl: 0,
+            sublvl_y: 0,
+        };
+        let mut fine_ids = vec![None; 16 * 16 * 16];
+        for y in 0..5 {
+            for z in 0..16 {
+                // Mostly stone (2) with some dirt (1) on the layer facing the coarse chunk
+                let id = if z % 4 == 0 { 1 } else { 2 };
+                fine_ids[fine.index(Point3::new(0, y, z), cs, largest)] = Some(id);
+                // Not on the face, so ignored
+                fine_ids[fine.index(Point3::new(1, y + 8, z), cs, largest)] = Some(1);
+            }
+        }
+        let fine_voxel = |index: usize| (fine_ids[index].is_some(), fine_ids[index]);
+
+        // The coarse chunk already has its bottom layer of voxels
+        let coarse_visible =
+            |index: usize| (0..4).any(|z| index == coarse.index(Point3::new(3, 0, z), cs, largest));
+        let fills = seam_fills(
+            coarse,
+            fine,
+            0,
+            true,
+            cs,
+            largest,
+            coarse_visible,
+            fine_voxel,
+        );
+        let mut expected: Vec<_> = (0..4)
+            .map(|z| (coarse.index(Point3::new(3, 1, z), cs, largest), Some(2)))
+            .collect();
+        let mut fills = fills;
+        fills.sort();
+        expected.sort();
+        assert_eq!(fills, expected);
+
+        // Nothing to fill from the other side or on the opposite face
+        let fills = seam_fills(fine, coarse, 0, false, cs, largest, |_| true, fine_voxel);
+        assert!(fills.is_empty());
+        let fills = seam_fills(
+            coarse,
+            fine,
+            0,
+            false,
+            cs,
+            largest,
+            |_| false,
+            |_| (false, None),
+        );
+        assert!(fills.is_empty());
+    }
+}
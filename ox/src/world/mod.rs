@@ -1,32 +1,41 @@
-use crate::loader::TakenChunk;
-use cgmath::{Array, EuclideanSpace, Point3, Vector3};
+use crate::config::OxConfig;
+use crate::loader::{LoaderShutdownReport, TakenChunk};
+use crate::util::OwnerThread;
+use cgmath::{EuclideanSpace, Point3, Vector3};
 use getset::Getters;
 use mem_grid::{MemGridShift, ShiftGridAxis, ShiftGridAxisVal};
 use num_traits::Zero;
+use std::fmt::Debug;
+use std::sync::Arc;
 use std::time::Duration;
 
 pub mod camera;
+pub mod diff;
+pub mod ecs;
+pub mod generator;
+pub mod manifest;
 pub mod mem_grid;
+pub mod priority;
+pub mod storage;
+
+pub use diff::diff;
+pub use generator::{TerrainGenerator, WorldGenerator, WorldSeed};
+pub use manifest::WorldManifest;
+pub use ox_core::units::{TlcPos, TlcVector, VoxelPos, VoxelVector, WorldScale};
+pub use priority::{CenterDistance, PriorityPolicy, ViewCone};
 
 use crate::loader::ChunkLoader;
-use crate::world::mem_grid::{EditMemoryGridChunk, MemoryGrid, MemoryGridLoadChunks};
+use crate::physics::is_voxel_solid;
+use crate::ray::ChunkEditorVoxels;
+use crate::voxel_type::VoxelTypeEnum;
+use crate::world::mem_grid::pin::{MemoryGridPinChunks, PinnedChunkStats};
+use crate::world::mem_grid::utils::ChunkSize;
+use crate::world::mem_grid::{
+    EditMemoryGridChunk, MemoryGrid, MemoryGridLoadChunks, MemoryGridRegenerateChunks,
+};
+use camera::collision::constrain_movement;
 use camera::{controller::CameraController, Camera};
-
-/// Position in units of top level chunks
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
-pub struct TlcPos<T>(pub Point3<T>);
-
-/// Vector in units of top level chunks
-#[derive(Clone, Copy, Debug)]
-pub struct TlcVector<T>(pub Vector3<T>);
-
-/// Position in units of 1 (i.e. LOD 0 voxels)
-#[derive(Clone, Copy, Debug)]
-pub struct VoxelPos<T>(pub Point3<T>);
-
-/// Vector in units of 1 (i.e. LOD 0 voxels)
-#[derive(Clone, Copy, Debug)]
-pub struct VoxelVector<T>(pub Vector3<T>);
+use priority::ChunkPriorityInput;
 
 #[derive(Getters, Debug)]
 pub struct WorldMetadata {
@@ -37,6 +46,9 @@ pub struct WorldMetadata {
     // State of the buffer chunks in each axis
     #[get = "pub"]
     buffer_chunk_states: [BufferChunkState; 3],
+    /// Meters per LOD 0 voxel, see `World::set_scale`
+    #[get = "pub"]
+    scale: WorldScale,
 }
 
 #[derive(Getters, Debug)]
@@ -46,6 +58,12 @@ pub struct World<MG> {
     camera: Camera,
     #[get = "pub"]
     metadata: WorldMetadata,
+    #[get = "pub"]
+    owner_thread: OwnerThread,
+    // When true, no new chunk loads are dispatched and the memory grid is not shifted
+    streaming_paused: bool,
+    // Orders queued chunks, see `set_priority_policy`
+    priority_policy: Arc<dyn PriorityPolicy>,
 }
 
 /// Whether the buffer chunks for a specific axis are unloaded, have the upper (larger coordinate)
@@ -57,7 +75,27 @@ pub enum BufferChunkState {
     LoadedLower = 2,
 }
 
+#[cfg(feature = "debug-checks")]
+impl<MG: mem_grid::self_check::MemoryGridSelfCheck> World<MG> {
+    /// Check that the memory grid is consistent with the current buffer chunk states: every TLC in range maps to
+    /// exactly one slot in each layer, nothing out of range is mapped, and all layers and LODs agree about the
+    /// start TLC. Returns a report of any problems instead of panicking, e.g. to print after a suspicious shift.
+    pub fn self_check(&self) -> mem_grid::self_check::SelfCheckReport {
+        let mut report = mem_grid::self_check::SelfCheckReport::default();
+        self.mem_grid
+            .self_check("mem_grid", self.metadata.buffer_chunk_states, &mut report);
+        report
+    }
+}
+
 impl<MG: MemoryGrid> World<MG> {
+    /// `World::new` with the load distance threshold and scale from `config`
+    pub fn with_config(mem_grid: MG, camera: Camera, tlc_size: usize, config: &OxConfig) -> Self {
+        let mut world = Self::new(mem_grid, camera, tlc_size, config.load_dist_thresh);
+        world.set_scale(WorldScale::new(config.meters_per_voxel));
+        world
+    }
+
     pub fn new(mem_grid: MG, camera: Camera, tlc_size: usize, tlc_load_dist_thresh: u32) -> Self {
         World {
             mem_grid,
@@ -66,11 +104,67 @@ impl<MG: MemoryGrid> World<MG> {
                 tlc_size,
                 tlc_load_dist_thresh,
                 buffer_chunk_states: [BufferChunkState::Unloaded; 3],
+                scale: WorldScale::default(),
             },
+            owner_thread: OwnerThread::current(),
+            streaming_paused: false,
+            priority_policy: Arc::new(CenterDistance),
         }
     }
 
+    /// Pause or resume world streaming. While paused, `ChunkLoader::sync` still returns chunks that
+    /// finish loading but does not start loading any new ones, and `move_camera` moves the camera
+    /// without shifting the memory grid. Useful for pause menus, cutscenes and benchmarking.
+    ///
+    /// The camera should stay near the center of the loaded area while paused since the grid will not
+    /// follow it until streaming is resumed.
+    pub fn set_streaming_paused(&mut self, paused: bool) {
+        self.owner_thread
+            .debug_assert_current("World::set_streaming_paused");
+        self.streaming_paused = paused;
+    }
+
+    pub fn streaming_paused(&self) -> bool {
+        self.streaming_paused
+    }
+
+    /// Set the policy that decides which queued chunks load first, `CenterDistance` by default. E.g. `ViewCone`
+    /// loads chunks in front of the camera before ones behind it at the same distance. Only affects chunks queued
+    /// after this is called.
+    pub fn set_priority_policy(&mut self, policy: impl PriorityPolicy + 'static) {
+        self.owner_thread
+            .debug_assert_current("World::set_priority_policy");
+        self.priority_policy = Arc::new(policy);
+    }
+
+    /// Loading priority of the chunk at `pos` under the world's `PriorityPolicy`
+    pub fn chunk_loading_priority(&self, pos: TlcPos<i64>) -> u32 {
+        self.chunk_priority(pos, false)
+    }
+
+    fn chunk_priority(&self, pos: TlcPos<i64>, leading: bool) -> u32 {
+        self.priority_policy.priority(&ChunkPriorityInput {
+            chunk_center: Point3::from_vec(
+                (pos.0 - self.mem_grid.start_tlc().0).map(|a| a as f32 + 0.5),
+            ),
+            grid_center: Point3::from_vec(self.mem_grid.axis_sizes().map(|a| a as f32 / 2.)),
+            camera: self.camera.position.0 / self.metadata.tlc_size as f32,
+            camera_forward: self.camera.forward(),
+            leading,
+        })
+    }
+
+    /// Set how many meters an LOD 0 voxel represents (1 by default). Everything in ox stays in voxels; gameplay
+    /// code converts its speeds, gravity, ray lengths and fog distances with `metadata().scale()`.
+    pub fn set_scale(&mut self, scale: WorldScale) {
+        self.metadata.scale = scale;
+    }
+
+    /// Resolution of the frames the camera is rendered to in physical pixels, i.e. `window.inner_size()` or
+    /// `Renderer::frame_size`, not the logical size
     pub fn set_camera_res(&mut self, width: u32, height: u32) {
+        self.owner_thread
+            .debug_assert_current("World::set_camera_res");
         self.camera.resolution = (width, height);
     }
 
@@ -78,23 +172,24 @@ impl<MG: MemoryGrid> World<MG> {
     /// in the virtual memory grid. This involves checking the state of the buffer chunks to
     /// see where chunks on the edge of the loaded area might end up in the vgrid.
     pub fn chunk_vgrid_pos(&self, global_tlc_pos: TlcPos<i64>) -> Option<TlcVector<usize>> {
+        let sizes = self.mem_grid.axis_sizes();
         let mut i = 0;
-        if let Point3 {
+        if let Vector3 {
             x: Some(x),
             y: Some(y),
             z: Some(z),
-        } = (global_tlc_pos.0 - self.mem_grid.start_tlc().0.to_vec()).map(|a| {
-            let state = self.metadata.buffer_chunk_states[i];
+        } = (global_tlc_pos - self.mem_grid.start_tlc()).0.map(|a| {
+            let (state, size) = (self.metadata.buffer_chunk_states[i], sizes[i]);
             i += 1;
             if a < 0 {
                 if a == -1 && state == BufferChunkState::LoadedLower {
-                    Some(self.mem_grid.size() - 1)
+                    Some(size - 1)
                 } else {
                     None
                 }
-            } else if a >= self.mem_grid.size() as i64 - 1 {
-                if a == self.mem_grid.size() as i64 - 1 && state == BufferChunkState::LoadedUpper {
-                    Some(self.mem_grid.size() - 1)
+            } else if a >= size as i64 - 1 {
+                if a == size as i64 - 1 && state == BufferChunkState::LoadedUpper {
+                    Some(size - 1)
                 } else {
                     None
                 }
@@ -114,12 +209,71 @@ impl<QI: Eq, MG: MemoryGrid + MemoryGridLoadChunks<ChunkLoadQueueItemData = QI>>
     where
         BC: TakenChunk<MemoryGrid = MG>,
     {
+        self.owner_thread
+            .debug_assert_current("World::queue_load_all");
         for chunk in self.mem_grid.queue_load_all() {
-            let prio = self.mem_grid.chunk_loading_priority(chunk.pos);
+            let prio = self.chunk_loading_priority(chunk.pos);
             loader.enqueue(chunk, prio);
         }
     }
 
+    /// Pause streaming and shut down `loader`, waiting up to `timeout` for in-progress chunks to be
+    /// returned. See `ChunkLoader::shutdown`.
+    pub fn shutdown<BC>(
+        &mut self,
+        loader: &mut ChunkLoader<QI, BC>,
+        timeout: Duration,
+    ) -> LoaderShutdownReport
+    where
+        BC: TakenChunk<MemoryGrid = MG> + 'static,
+        QI: Clone + Send + Debug + 'static,
+    {
+        self.owner_thread.debug_assert_current("World::shutdown");
+        self.streaming_paused = true;
+        loader.shutdown(self, timeout)
+    }
+
+    /// Reload all chunks that haven't been edited since they were loaded, in the background (after any
+    /// other queued chunks). The load function is passed to `ChunkLoader::sync` each frame, so to swap the
+    /// chunk generator at runtime, start passing a load function with the new generator and then call this.
+    pub fn regenerate_unedited<BC>(&mut self, loader: &mut ChunkLoader<QI, BC>)
+    where
+        BC: TakenChunk<MemoryGrid = MG>,
+        MG: MemoryGridRegenerateChunks,
+    {
+        self.owner_thread
+            .debug_assert_current("World::regenerate_unedited");
+        for chunk in self.mem_grid.queue_regenerate_unedited() {
+            loader.enqueue_background(chunk);
+        }
+    }
+
+    /// Reload the chunks from `min_tlc` to `max_tlc` (inclusive) that are in the memory grid, e.g. when a server
+    /// says a region changed wholesale. They are marked invalid right away so they can't be edited until they are
+    /// reloaded, and queued like any other chunk (see `set_priority_policy`). Chunks that are currently loading are
+    /// loaded again once their load finishes, since its result is out of date. Returns the positions of the
+    /// invalidated chunks, so the game can drop anything it derived from them; each is in
+    /// `ChunkLoader::returned_last` once it has been reloaded.
+    pub fn invalidate_region<BC>(
+        &mut self,
+        min_tlc: TlcPos<i64>,
+        max_tlc: TlcPos<i64>,
+        loader: &mut ChunkLoader<QI, BC>,
+    ) -> Vec<TlcPos<i64>>
+    where
+        BC: TakenChunk<MemoryGrid = MG>,
+    {
+        self.owner_thread
+            .debug_assert_current("World::invalidate_region");
+        let chunks = self.mem_grid.invalidate_region(min_tlc, max_tlc);
+        let positions = chunks.iter().map(|chunk| chunk.pos).collect();
+        for chunk in chunks {
+            let prio = self.chunk_loading_priority(chunk.pos);
+            loader.enqueue(chunk, prio);
+        }
+        positions
+    }
+
     pub fn move_camera<BC>(
         &mut self,
         camera_controller: &mut impl CameraController,
@@ -128,12 +282,58 @@ impl<QI: Eq, MG: MemoryGrid + MemoryGridLoadChunks<ChunkLoadQueueItemData = QI>>
     ) where
         BC: TakenChunk<MemoryGrid = MG>,
     {
+        self.owner_thread.debug_assert_current("World::move_camera");
         camera_controller.apply(&mut self.camera, dt);
+        self.follow_camera(loader);
+    }
+
+    /// Like `move_camera`, but if `camera_controller` has collision enabled (see
+    /// `CameraController::collision`), the camera is kept from moving into visible LOD 0 voxels. Voxels in
+    /// chunks that aren't loaded or are outside the LOD 0 area are treated as empty so the camera doesn't get
+    /// stuck while chunks load.
+    pub fn move_camera_with_collision<BC, const N: usize, VE: VoxelTypeEnum, M>(
+        &mut self,
+        camera_controller: &mut impl CameraController,
+        dt: Duration,
+        loader: &mut ChunkLoader<QI, BC>,
+        chunk_size: ChunkSize,
+        largest_chunk_lvl: u8,
+    ) where
+        BC: TakenChunk<MemoryGrid = MG>,
+        MG: EditMemoryGridChunk<M>,
+        for<'a> MG::ChunkEditor<'a>: ChunkEditorVoxels<VE, N>,
+    {
+        self.owner_thread
+            .debug_assert_current("World::move_camera_with_collision");
+        let before = self.camera.position;
+        camera_controller.apply(&mut self.camera, dt);
+
+        if let Some(collision) = camera_controller.collision() {
+            let target = self.camera.position.0;
+            let is_solid = |pos: Point3<i64>| {
+                is_voxel_solid::<N, VE, MG, M>(self, pos, chunk_size, largest_chunk_lvl)
+            };
+            let position = constrain_movement(before.0, target, collision.radius, is_solid);
+            self.camera.position = VoxelPos(position);
+        }
+
+        self.follow_camera(loader);
+    }
+
+    /// Shift the memory grid to follow the camera, queueing any chunks that need to be loaded
+    fn follow_camera<BC>(&mut self, loader: &mut ChunkLoader<QI, BC>)
+    where
+        BC: TakenChunk<MemoryGrid = MG>,
+    {
+        if self.streaming_paused {
+            // Leave the grid (and buffer chunks) as is until streaming is resumed
+            return;
+        }
 
         // Delta in units of top level chunks; 0 if still in the same TLC
         let tlc_delta = (self.camera.position.0 / (self.metadata.tlc_size as f32))
             .map(|a| a.floor() as i64)
-            - Point3::<i64>::from_value(((self.mem_grid.size() - 2) / 2) as i64);
+            - Point3::from_vec(self.mem_grid.axis_sizes().map(|a| ((a - 2) / 2) as i64));
 
         // When we move to a different top level chunk, we have to adjust the camera position since it is relative to the current memory grid.
         if !tlc_delta.is_zero() {
@@ -146,9 +346,10 @@ impl<QI: Eq, MG: MemoryGrid + MemoryGridLoadChunks<ChunkLoadQueueItemData = QI>>
         }
 
         let center_chunk_cam_pos = self.camera.position.0
-            - Vector3::from_value(
-                self.metadata.tlc_size as f32 * (self.mem_grid.size() - 2) as f32 / 2.,
-            );
+            - self
+                .mem_grid
+                .axis_sizes()
+                .map(|a| self.metadata.tlc_size as f32 * (a - 2) as f32 / 2.);
 
         // Shift memory grid and handle buffer chunks
         MemGridShift::new([0, 1, 2].map(|ax| {
@@ -210,12 +411,27 @@ impl<QI: Eq, MG: MemoryGrid + MemoryGridLoadChunks<ChunkLoadQueueItemData = QI>>
             }
         }))
         .map(|shift| {
+            let leading = shift.leading_direction();
             for chunk in self.mem_grid.shift(&shift) {
-                let priority = self.mem_grid.chunk_loading_priority(chunk.pos);
+                let priority = self.shifted_chunk_priority(chunk.pos, leading);
                 loader.enqueue(chunk, priority);
             }
         });
     }
+
+    /// Loading priority of a chunk queued by a shift. Chunks ahead of the camera on an axis the shift loads toward
+    /// (see `MemGridShift::leading_direction`), i.e. the newly visible buffer chunks or the leading edge of the
+    /// grid, are passed to the priority policy as `leading`. With `CenterDistance`, that makes the slab the camera
+    /// is moving toward load, and be uploaded to the GPU, first, nearest the camera first, instead of after chunks
+    /// the player can't see missing yet.
+    fn shifted_chunk_priority(&self, pos: TlcPos<i64>, leading: Vector3<i32>) -> u32 {
+        let chunk_center = (pos.0 - self.mem_grid.start_tlc().0).map(|a| a as f32 + 0.5);
+        let camera = self.camera.position.0 / self.metadata.tlc_size as f32;
+        let ahead = (0..3).any(|ax| {
+            leading[ax] != 0 && (chunk_center[ax] - camera[ax].floor()) * leading[ax] as f32 > 0.
+        });
+        self.chunk_priority(pos, ahead)
+    }
 }
 
 impl<MG: MemoryGrid> World<MG> {
@@ -226,7 +442,29 @@ impl<MG: MemoryGrid> World<MG> {
     where
         MG: EditMemoryGridChunk<M>,
     {
+        self.owner_thread.debug_assert_current("World::edit_chunk");
         self.mem_grid
             .edit_chunk(global_tlc_pos, self.metadata().buffer_chunk_states)
     }
 }
+
+impl<MG: MemoryGridPinChunks> World<MG> {
+    /// Keep the chunk at `global_tlc_pos` loaded regardless of where the camera goes, e.g. for a base with machines
+    /// that should keep running. When it leaves the loaded area, its data is moved to an overflow list where it can
+    /// still be updated (e.g. `MemoryGridLayer::overflowed_chunk_mut`) instead of being invalidated, and it is put
+    /// back when the chunk comes back. Returns `Err` if the maximum number of pinned chunks is reached.
+    pub fn pin_chunk(&mut self, global_tlc_pos: TlcPos<i64>) -> Result<(), ()> {
+        self.owner_thread.debug_assert_current("World::pin_chunk");
+        self.mem_grid.pin_chunk(global_tlc_pos)
+    }
+
+    /// Returns whether the chunk was pinned. If it is outside of the loaded area, its data is dropped.
+    pub fn unpin_chunk(&mut self, global_tlc_pos: TlcPos<i64>) -> bool {
+        self.owner_thread.debug_assert_current("World::unpin_chunk");
+        self.mem_grid.unpin_chunk(global_tlc_pos)
+    }
+
+    pub fn pinned_chunk_stats(&self) -> PinnedChunkStats {
+        self.mem_grid.pinned_chunk_stats()
+    }
+}
@@ -0,0 +1,127 @@
+//! Order chunks are loaded in. Every chunk queued by `World` gets a priority from the world's `PriorityPolicy` (see
+//! `World::set_priority_policy`), and the chunk loader starts the highest priority chunks first.
+
+use cgmath::{InnerSpace, MetricSpace, Point3, Vector3};
+use std::fmt::Debug;
+
+/// Where a chunk is relative to the camera and the memory grid. Positions are in TLCs, relative to the start of
+/// the memory grid like `Camera::position` (which is in voxels).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChunkPriorityInput {
+    pub chunk_center: Point3<f32>,
+    pub grid_center: Point3<f32>,
+    pub camera: Point3<f32>,
+    /// Unit vector the camera is looking along, see `Camera::forward`
+    pub camera_forward: Vector3<f32>,
+    /// Whether the chunk was queued by a shift of the memory grid and is ahead of the camera on an axis the grid
+    /// moved along (see `MemGridShift::leading_direction`), i.e. the slab the camera is moving toward
+    pub leading: bool,
+}
+
+impl ChunkPriorityInput {
+    /// Distance from the camera to the center of the chunk in TLCs
+    pub fn camera_distance(&self) -> f32 {
+        self.chunk_center.distance(self.camera)
+    }
+}
+
+/// Decides the loading priority of chunks, higher first. To keep `LoadRateLimit::immediate_priority` meaningful,
+/// priorities should be `u32::MAX` minus 10 times some distance in TLCs, like the built-in policies.
+pub trait PriorityPolicy: Debug + Send + Sync {
+    fn priority(&self, chunk: &ChunkPriorityInput) -> u32;
+}
+
+/// Priority for a chunk `distance` TLCs away
+pub fn distance_priority(distance: f32) -> u32 {
+    u32::MAX - (distance * 10.0) as u32
+}
+
+/// Nearest the center of the memory grid first, except that `leading` chunks are ordered by their distance to the
+/// camera so the area the camera is moving into loads first. The default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CenterDistance;
+
+impl PriorityPolicy for CenterDistance {
+    fn priority(&self, chunk: &ChunkPriorityInput) -> u32 {
+        if chunk.leading {
+            distance_priority(chunk.camera_distance())
+        } else {
+            distance_priority(chunk.chunk_center.distance(chunk.grid_center))
+        }
+    }
+}
+
+/// Nearest the camera first, with chunks away from the direction the camera is looking treated as further away:
+/// a chunk's distance is multiplied by `1 + behind_weight * (1 - cos) / 2`, where `cos` is the cosine of the angle
+/// between the camera's forward vector and the direction to the chunk. So chunks straight ahead keep their
+/// distance and ones straight behind count as `1 + behind_weight` times as far.
+#[derive(Debug, Clone, Copy)]
+pub struct ViewCone {
+    pub behind_weight: f32,
+}
+
+impl Default for ViewCone {
+    /// Chunks behind the camera load like ones in front of it that are 3 times as far away
+    fn default() -> Self {
+        ViewCone { behind_weight: 2. }
+    }
+}
+
+impl PriorityPolicy for ViewCone {
+    fn priority(&self, chunk: &ChunkPriorityInput) -> u32 {
+        let to_chunk = chunk.chunk_center - chunk.camera;
+        let distance = to_chunk.magnitude();
+        // The chunk the camera is in has no direction
+        let cos = if distance > 0.5 {
+            (to_chunk / distance).dot(chunk.camera_forward)
+        } else {
+            1.
+        };
+        distance_priority(distance * (1. + self.behind_weight * (1. - cos) / 2.))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Array;
+
+    fn input(chunk_center: Point3<f32>, leading: bool) -> ChunkPriorityInput {
+        ChunkPriorityInput {
+            chunk_center,
+            grid_center: Point3::from_value(4.),
+            camera: Point3::new(4.5, 4., 4.),
+            camera_forward: Vector3::unit_x(),
+            leading,
+        }
+    }
+
+    #[test]
+    fn test_priority_policies() {
+        let ahead = input(Point3::new(7.5, 4., 4.), false);
+        let behind = input(Point3::new(1.5, 4., 4.), false);
+        let side = input(Point3::new(4.5, 4., 7.), false);
+
+        // Distance to the center of the grid, or to the camera for leading chunks
+        assert_eq!(CenterDistance.priority(&ahead), u32::MAX - 35);
+        assert_eq!(CenterDistance.priority(&behind), u32::MAX - 25);
+        assert_eq!(
+            CenterDistance.priority(&input(Point3::new(7.5, 4., 4.), true)),
+            u32::MAX - 30
+        );
+
+        // Chunks in front of the camera first at the same distance, then ones to the side
+        let view_cone = ViewCone::default();
+        assert_eq!(view_cone.priority(&ahead), u32::MAX - 30);
+        assert_eq!(view_cone.priority(&side), u32::MAX - 60);
+        assert_eq!(view_cone.priority(&behind), u32::MAX - 90);
+        assert_eq!(
+            view_cone.priority(&input(Point3::new(4.5, 4., 4.), false)),
+            u32::MAX
+        );
+        assert_eq!(
+            ViewCone { behind_weight: 0. }.priority(&behind),
+            view_cone.priority(&ahead)
+        );
+    }
+}
@@ -0,0 +1,496 @@
+//! Persisting edited chunks across runs. A `ChunkStore` holds the full resolution (LOD 0) voxels of chunks that
+//! were edited. Give one to the voxel memory grid with `VoxelMemoryGrid::set_chunk_store` so edited chunks are
+//! saved when they scroll out of the full resolution area and `ChunkLoader` reads saved chunks instead of
+//! generating them, and call `VoxelMemoryGrid::save_edited` before exiting.
+//!
+//! Stores can also hold an opaque blob of game data per chunk (see `ChunkVoxelEditor::set_user_data`), which is
+//! saved and loaded along with the chunk whether or not its voxels were edited.
+
+use crate::loader::ChunkLoader;
+use crate::pregen::PregenProgress;
+use crate::renderer::component::voxels::data::VoxelTypeIDs;
+use crate::world::diff::ChunkSource;
+use crate::world::mem_grid::voxel::ChunkVoxels;
+use crate::world::TlcPos;
+use cgmath::Point3;
+use hashbrown::HashMap;
+use std::fmt::Debug;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Storage for chunk data, by default the full resolution voxels of chunks. Methods are called from loading and
+/// generation threads, so they take `&self`.
+pub trait ChunkStore<D = ChunkVoxels>: Send + Sync + Debug {
+    /// Data saved for `pos`, or `None` if there isn't any (or it couldn't be read), in which case the chunk is
+    /// generated instead
+    fn load(&self, pos: TlcPos<i64>) -> Option<D>;
+
+    /// Save data for `pos`, replacing any that was saved before
+    fn save(&self, pos: TlcPos<i64>, data: &D) -> Result<(), String>;
+
+    /// Whether data is saved for `pos`
+    fn contains(&self, pos: TlcPos<i64>) -> bool {
+        self.load(pos).is_some()
+    }
+
+    /// User data saved for `pos`, or `None` if there isn't any. Stores that don't support user data never have any.
+    fn load_user_data(&self, _pos: TlcPos<i64>) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Save user data for `pos`, replacing any that was saved before, or remove it if `data` is `None`
+    fn save_user_data(&self, pos: TlcPos<i64>, data: Option<&[u8]>) -> Result<(), String> {
+        match data {
+            None => Ok(()),
+            Some(_) => Err(format!(
+                "Chunk store does not support user data, can't save it for chunk {:?}",
+                pos.0
+            )),
+        }
+    }
+
+    /// Generate every top level chunk within `radius` of `center` with `generator` and save it to this store on
+    /// `loader`'s threads. See `ChunkLoader::pregenerate`.
+    fn pregenerate<QI, BC, G, P>(
+        self: Arc<Self>,
+        loader: &mut ChunkLoader<QI, BC>,
+        generator: Arc<G>,
+        center: TlcPos<i64>,
+        radius: u32,
+        progress_cb: P,
+    ) -> PregenProgress
+    where
+        Self: Sized + 'static,
+        QI: Eq,
+        G: Fn(TlcPos<i64>) -> D + Send + Sync + 'static,
+        P: FnMut(&PregenProgress) -> bool,
+    {
+        loader.pregenerate(self, generator, center, radius, progress_cb)
+    }
+}
+
+/// `ChunkStore` that just keeps chunks in memory. Useful for tests or for generating a small area up front.
+#[derive(Debug)]
+pub struct InMemoryChunkStore<D> {
+    chunks: Mutex<HashMap<Point3<i64>, D>>,
+}
+
+impl<D> Default for InMemoryChunkStore<D> {
+    fn default() -> Self {
+        InMemoryChunkStore {
+            chunks: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<D> InMemoryChunkStore<D> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn take(&self, pos: TlcPos<i64>) -> Option<D> {
+        self.chunks.lock().unwrap().remove(&pos.0)
+    }
+
+    pub fn positions(&self) -> Vec<TlcPos<i64>> {
+        self.chunks
+            .lock()
+            .unwrap()
+            .keys()
+            .map(|&pos| TlcPos(pos))
+            .collect()
+    }
+
+    /// Call `f` with the data stored for `pos`, if any
+    pub fn with_chunk<R>(&self, pos: TlcPos<i64>, f: impl FnOnce(&D) -> R) -> Option<R> {
+        self.chunks.lock().unwrap().get(&pos.0).map(f)
+    }
+}
+
+impl<D: Clone + Send + Debug> ChunkStore<D> for InMemoryChunkStore<D> {
+    fn load(&self, pos: TlcPos<i64>) -> Option<D> {
+        self.with_chunk(pos, D::clone)
+    }
+
+    fn save(&self, pos: TlcPos<i64>, data: &D) -> Result<(), String> {
+        self.chunks.lock().unwrap().insert(pos.0, data.clone());
+        Ok(())
+    }
+
+    fn contains(&self, pos: TlcPos<i64>) -> bool {
+        self.chunks.lock().unwrap().contains_key(&pos.0)
+    }
+}
+
+/// FNV-1a hash of `bytes`, used to detect corrupted chunk data
+pub fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, &b| {
+        (hash ^ b as u32).wrapping_mul(0x01000193)
+    })
+}
+
+/// Chunk data along with a checksum of its bytes so corruption can be detected when it is read back, e.g. from a
+/// chunk file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChecksummedChunk<D> {
+    data: D,
+    checksum: u32,
+}
+
+impl<D: AsRef<[u8]>> ChecksummedChunk<D> {
+    pub fn new(data: D) -> Self {
+        ChecksummedChunk {
+            checksum: checksum(data.as_ref()),
+            data,
+        }
+    }
+
+    /// Create from data and a checksum that were read separately, e.g. from a file
+    pub fn from_parts(data: D, checksum: u32) -> Self {
+        ChecksummedChunk { data, checksum }
+    }
+
+    pub fn checksum(&self) -> u32 {
+        self.checksum
+    }
+
+    pub fn is_valid(&self) -> bool {
+        checksum(self.data.as_ref()) == self.checksum
+    }
+
+    /// Data if it matches its checksum
+    pub fn verify(self) -> Option<D> {
+        if self.is_valid() {
+            Some(self.data)
+        } else {
+            None
+        }
+    }
+}
+
+impl<D: AsRef<[u8]>> AsRef<[u8]> for ChecksummedChunk<D> {
+    fn as_ref(&self) -> &[u8] {
+        self.data.as_ref()
+    }
+}
+
+const MAGIC: &[u8; 4] = b"OXCH";
+const FORMAT_VERSION: u32 = 2;
+const HEADER_LEN: usize = 4 + 4 + 8;
+const CHECKSUM_LEN: usize = 4;
+
+/// Encode voxels as a chunk file: magic, format version, number of voxels, one byte per voxel ID, then a checksum
+/// of everything before it, so a corrupted header is detected too. Integers are little endian.
+fn encode_chunk(voxels: &ChunkVoxels) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_LEN + voxels.n_voxels() + CHECKSUM_LEN);
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(voxels.n_voxels() as u64).to_le_bytes());
+    bytes.extend(voxels.ids.iter().flat_map(|ids| ids.indices));
+    let checksum = ChecksummedChunk::new(&bytes[..]).checksum();
+    bytes.extend_from_slice(&checksum.to_le_bytes());
+    bytes
+}
+
+/// Inverse of `encode_chunk`
+fn decode_chunk(bytes: &[u8]) -> Result<ChunkVoxels, String> {
+    if bytes.len() < HEADER_LEN + CHECKSUM_LEN || &bytes[0..4] != MAGIC {
+        return Err("Not a chunk file".to_string());
+    }
+    let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+    if version != FORMAT_VERSION {
+        return Err(format!("Unsupported chunk format version {}", version));
+    }
+    let (contents, checksum) = bytes.split_at(bytes.len() - CHECKSUM_LEN);
+    let contents =
+        ChecksummedChunk::from_parts(contents, u32::from_le_bytes(checksum.try_into().unwrap()))
+            .verify()
+            .ok_or("Checksum mismatch")?;
+
+    // Check the number of voxels against the IDs that are actually there before allocating anything for them
+    let n_voxels = u64::from_le_bytes(contents[8..16].try_into().unwrap());
+    let ids = &contents[HEADER_LEN..];
+    let ids_per_element = 128 / VoxelTypeIDs::BITS_PER_VOXEL;
+    if n_voxels != ids.len() as u64 || ids.len() % ids_per_element != 0 {
+        return Err(format!(
+            "Expected {} voxel IDs but found {}",
+            n_voxels,
+            ids.len()
+        ));
+    }
+
+    let mut voxels = ChunkVoxels::new_blank(ids.len());
+    for (element, chunk) in voxels.ids.iter_mut().zip(ids.chunks_exact(ids_per_element)) {
+        element.indices.copy_from_slice(chunk);
+    }
+    Ok(voxels)
+}
+
+/// `ChunkStore` that keeps one file per chunk in a directory, named after the chunk's TLC position (e.g.
+/// `3_-1_0.oxc`), and the chunk's user data, if any, next to it (e.g. `3_-1_0.oxu`). Files are written to a
+/// temporary file first and then renamed, so a crash mid-save leaves the previous version intact. Files that fail
+/// their checksum are ignored with a warning and the chunk is generated again.
+#[derive(Debug, Clone)]
+pub struct FileChunkStore {
+    dir: PathBuf,
+}
+
+impl FileChunkStore {
+    /// Use `dir` to store chunks, creating it if it doesn't exist
+    pub fn new(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(FileChunkStore { dir })
+    }
+
+    fn path(&self, pos: TlcPos<i64>) -> PathBuf {
+        self.dir
+            .join(format!("{}_{}_{}.oxc", pos.0.x, pos.0.y, pos.0.z))
+    }
+
+    fn user_data_path(&self, pos: TlcPos<i64>) -> PathBuf {
+        self.path(pos).with_extension("oxu")
+    }
+}
+
+const USER_DATA_MAGIC: &[u8; 4] = b"OXUD";
+const USER_DATA_HEADER_LEN: usize = 4 + 4 + 8 + 4;
+
+/// Encode user data as a user data file: magic, format version, length, checksum, then the data. Integers are
+/// little endian.
+fn encode_user_data(data: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(USER_DATA_HEADER_LEN + data.len());
+    bytes.extend_from_slice(USER_DATA_MAGIC);
+    bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(&ChecksummedChunk::new(data).checksum().to_le_bytes());
+    bytes.extend_from_slice(data);
+    bytes
+}
+
+/// Inverse of `encode_user_data`
+fn decode_user_data(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    if bytes.len() < USER_DATA_HEADER_LEN || &bytes[0..4] != USER_DATA_MAGIC {
+        return Err("Not a user data file".to_string());
+    }
+    let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+    if version != FORMAT_VERSION {
+        return Err(format!("Unsupported user data format version {}", version));
+    }
+    let len = u64::from_le_bytes(bytes[8..16].try_into().unwrap()) as usize;
+    let checksum = u32::from_le_bytes(bytes[16..20].try_into().unwrap());
+    let data = &bytes[USER_DATA_HEADER_LEN..];
+    if data.len() != len {
+        return Err(format!("Expected {} bytes but found {}", len, data.len()));
+    }
+    ChecksummedChunk::from_parts(data, checksum)
+        .verify()
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| "Checksum mismatch".to_string())
+}
+
+/// Position of the chunk stored in a file named `name`, if it is a chunk file
+fn parse_chunk_file_name(name: &str) -> Option<TlcPos<i64>> {
+    let mut coords = name.strip_suffix(".oxc")?.split('_').map(str::parse);
+    let pos = Point3::new(
+        coords.next()?.ok()?,
+        coords.next()?.ok()?,
+        coords.next()?.ok()?,
+    );
+    coords.next().is_none().then_some(TlcPos(pos))
+}
+
+impl ChunkStore for FileChunkStore {
+    fn load(&self, pos: TlcPos<i64>) -> Option<ChunkVoxels> {
+        let bytes = match fs::read(self.path(pos)) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                println!("WARNING: failed to read chunk {:?}: {}", pos.0, e);
+                return None;
+            }
+        };
+        match decode_chunk(&bytes) {
+            Ok(voxels) => Some(voxels),
+            Err(e) => {
+                println!(
+                    "WARNING: ignoring saved chunk {:?}, it will be generated again: {}",
+                    pos.0, e
+                );
+                None
+            }
+        }
+    }
+
+    fn save(&self, pos: TlcPos<i64>, voxels: &ChunkVoxels) -> Result<(), String> {
+        let path = self.path(pos);
+        let tmp_path = path.with_extension("oxc.tmp");
+        fs::write(&tmp_path, encode_chunk(voxels))
+            .and_then(|()| fs::rename(&tmp_path, &path))
+            .map_err(|e| format!("Failed to save chunk {:?}: {}", pos.0, e))
+    }
+
+    fn contains(&self, pos: TlcPos<i64>) -> bool {
+        self.path(pos).exists()
+    }
+
+    fn load_user_data(&self, pos: TlcPos<i64>) -> Option<Vec<u8>> {
+        let bytes = match fs::read(self.user_data_path(pos)) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                println!(
+                    "WARNING: failed to read user data of chunk {:?}: {}",
+                    pos.0, e
+                );
+                return None;
+            }
+        };
+        match decode_user_data(&bytes) {
+            Ok(data) => Some(data),
+            Err(e) => {
+                println!(
+                    "WARNING: ignoring saved user data of chunk {:?}: {}",
+                    pos.0, e
+                );
+                None
+            }
+        }
+    }
+
+    fn save_user_data(&self, pos: TlcPos<i64>, data: Option<&[u8]>) -> Result<(), String> {
+        let path = self.user_data_path(pos);
+        let r = match data {
+            Some(data) => {
+                let tmp_path = path.with_extension("oxu.tmp");
+                fs::write(&tmp_path, encode_user_data(data))
+                    .and_then(|()| fs::rename(&tmp_path, &path))
+            }
+            None => match fs::remove_file(&path) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+                r => r,
+            },
+        };
+        r.map_err(|e| format!("Failed to save user data of chunk {:?}: {}", pos.0, e))
+    }
+}
+
+impl ChunkSource for FileChunkStore {
+    fn chunk_positions(&self) -> Vec<TlcPos<i64>> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return vec![];
+        };
+        entries
+            .filter_map(|entry| parse_chunk_file_name(entry.ok()?.file_name().to_str()?))
+            .collect()
+    }
+
+    fn voxel_ids(&self, pos: TlcPos<i64>) -> Option<Vec<u8>> {
+        let voxels = self.load(pos)?;
+        Some((0..voxels.n_voxels()).map(|i| voxels[i]).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksummed_chunk() {
+        let stored = ChecksummedChunk::new(vec![1u8, 2, 3, 4]);
+        assert!(stored.is_valid());
+        assert_eq!(stored.clone().verify(), Some(vec![1, 2, 3, 4]));
+        let corrupt = ChecksummedChunk::from_parts(vec![1u8, 2, 255, 4], stored.checksum());
+        assert_eq!(corrupt.verify(), None);
+    }
+
+    #[test]
+    fn test_encode_decode_chunk() {
+        let mut voxels = ChunkVoxels::new_blank(512);
+        voxels[3] = 7;
+        voxels[511] = 2;
+        let mut bytes = encode_chunk(&voxels);
+        assert_eq!(decode_chunk(&bytes), Ok(voxels));
+
+        *bytes.last_mut().unwrap() ^= 1;
+        assert!(decode_chunk(&bytes).is_err());
+        assert!(decode_chunk(&bytes[..10]).is_err());
+        assert!(decode_chunk(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_decode_chunk_bad_voxel_count() {
+        // A corrupted voxel count fails the checksum instead of being allocated
+        let mut bytes = encode_chunk(&ChunkVoxels::new_blank(64));
+        bytes[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(decode_chunk(&bytes), Err("Checksum mismatch".to_string()));
+
+        // A count that doesn't match the IDs is rejected even with a valid checksum
+        let len = bytes.len() - CHECKSUM_LEN;
+        bytes.truncate(len);
+        let checksum = ChecksummedChunk::new(&bytes[..]).checksum();
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+        assert_eq!(
+            decode_chunk(&bytes),
+            Err(format!("Expected {} voxel IDs but found 64", u64::MAX))
+        );
+    }
+
+    #[test]
+    fn test_encode_decode_user_data() {
+        let mut bytes = encode_user_data(b"owner: 7");
+        assert_eq!(decode_user_data(&bytes), Ok(b"owner: 7".to_vec()));
+        assert_eq!(decode_user_data(&encode_user_data(&[])), Ok(vec![]));
+
+        *bytes.last_mut().unwrap() = b'8';
+        assert!(decode_user_data(&bytes).is_err());
+        assert!(decode_user_data(&bytes[..bytes.len() - 1]).is_err());
+        assert!(decode_user_data(&encode_chunk(&ChunkVoxels::new_blank(64))).is_err());
+    }
+
+    #[test]
+    fn test_file_chunk_store() {
+        let dir = std::env::temp_dir().join(format!("ox_storage_test_{}", std::process::id()));
+        let store = FileChunkStore::new(&dir).unwrap();
+        let pos = TlcPos(Point3::new(3, -1, 0));
+        assert!(store.load(pos).is_none());
+
+        let mut voxels = ChunkVoxels::new_blank(64);
+        voxels[10] = 1;
+        store.save(pos, &voxels).unwrap();
+        assert_eq!(store.load(pos), Some(voxels));
+        assert_eq!(store.chunk_positions(), vec![pos]);
+        assert_eq!(store.voxel_ids(pos).unwrap()[10], 1);
+
+        assert!(store.load_user_data(pos).is_none());
+        store.save_user_data(pos, Some(b"quest")).unwrap();
+        assert_eq!(store.load_user_data(pos), Some(b"quest".to_vec()));
+        assert_eq!(store.chunk_positions(), vec![pos]);
+        store.save_user_data(pos, None).unwrap();
+        assert!(store.load_user_data(pos).is_none());
+        store.save_user_data(pos, None).unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_chunk_file_name() {
+        assert_eq!(
+            parse_chunk_file_name("3_-1_0.oxc"),
+            Some(TlcPos(Point3::new(3, -1, 0)))
+        );
+        assert_eq!(parse_chunk_file_name("3_-1.oxc"), None);
+        assert_eq!(parse_chunk_file_name("3_-1_0_1.oxc"), None);
+        assert_eq!(parse_chunk_file_name("3_-1_0.oxc.tmp"), None);
+    }
+}
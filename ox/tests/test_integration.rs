@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::sync::Arc;
 use std::u128;
 
@@ -11,11 +12,11 @@ use ox::renderer::component::voxels::lod::{VoxelIDUpdate, VoxelLODUpdate};
 use ox::voxel_type::{Material, VoxelTypeDefinition, VoxelTypeEnum};
 use ox::world::camera::Camera;
 use ox::world::mem_grid::utils::{cubed, squared, VoxelPosInLod};
-use ox::world::mem_grid::voxel::{VoxelLODCreateParams, VoxelMemoryGrid};
+use ox::world::mem_grid::voxel::{Downsample, VoxelLODCreateParams, VoxelMemoryGrid};
 use ox::world::{TlcPos, World};
 use ox::{
     loader::{ChunkLoadQueueItem, ChunkLoader, ChunkLoaderParams},
-    renderer::test_context::TestContext,
+    renderer::{binding::BindingMapBuilder, test_context::TestContext, UploadBudget},
     world::mem_grid::{
         utils::ChunkSize,
         voxel::grid::{
@@ -71,15 +72,17 @@ fn fill_chunk<const N: usize, VE: VoxelTypeEnum>(
 ) {
     data.load_new(
         chunk.pos,
-        |_, lvl, sublvl, voxel_ids_out, tlc_size, largest_chunk_lvl| {
+        |_, lvl, sublvl, sublvl_y, voxel_ids_out, tlc_size, largest_chunk_lvl| {
             let voxel_size = CHUNK_SIZE.size().pow(lvl as u32) * 2usize.pow(sublvl as u32);
+            let voxel_size_y = CHUNK_SIZE.size().pow(lvl as u32) * 2usize.pow(sublvl_y as u32);
             for x in 0..(tlc_size / voxel_size) as u32 {
-                for y in 0..(tlc_size / voxel_size) as u32 {
+                for y in 0..(tlc_size / voxel_size_y) as u32 {
                     for z in 0..(tlc_size / voxel_size) as u32 {
                         let idx = VoxelPosInLod {
                             pos: Point3 { x, y, z },
                             lvl,
                             sublvl,
+                            sublvl_y,
                         }
                         .index(CHUNK_SIZE, largest_chunk_lvl);
                         voxel_ids_out[idx] = Block::SOLID as u8;
@@ -169,47 +172,63 @@ fn assert_updates_eq(u1: Vec<VoxelLODUpdate>, u2: Vec<VoxelLODUpdate>) {
 fn test_queue_load_all() {
     let renderer_context = TestContext::new();
     let start_tlc = TlcPos(Point3::<i64> { x: 0, y: 0, z: 0 } - Vector3::from_value(7));
+    let mut bindings = BindingMapBuilder::new();
     let (grid, _) = VoxelMemoryGrid::new(
         [
             VoxelLODCreateParams {
                 voxel_resolution: 1,
                 lvl: 0,
                 sublvl: 0,
+                sublvl_y: None,
                 render_area_size: 1,
-                bitmask_binding: 8,
-                voxel_ids_binding: Some(4),
+                render_area_size_y: None,
+                bitmask_binding: bindings.register_at("lod_0_0_bitmask", 8),
+                voxel_ids_binding: Some(bindings.register_at("lod_0_0_voxel_ids", 4)),
+                downsample: Downsample::default(),
             },
             VoxelLODCreateParams {
                 voxel_resolution: 2,
                 lvl: 0,
                 sublvl: 1,
+                sublvl_y: None,
                 render_area_size: 3,
-                bitmask_binding: 9,
-                voxel_ids_binding: Some(5),
+                render_area_size_y: None,
+                bitmask_binding: bindings.register_at("lod_0_1_bitmask", 9),
+                voxel_ids_binding: Some(bindings.register_at("lod_0_1_voxel_ids", 5)),
+                downsample: Downsample::default(),
             },
             VoxelLODCreateParams {
                 voxel_resolution: 4,
                 lvl: 0,
                 sublvl: 2,
+                sublvl_y: None,
                 render_area_size: 7,
-                bitmask_binding: 10,
-                voxel_ids_binding: Some(6),
+                render_area_size_y: None,
+                bitmask_binding: bindings.register_at("lod_0_2_bitmask", 10),
+                voxel_ids_binding: Some(bindings.register_at("lod_0_2_voxel_ids", 6)),
+                downsample: Downsample::default(),
             },
             VoxelLODCreateParams {
                 voxel_resolution: 8,
                 lvl: 1,
                 sublvl: 0,
+                sublvl_y: None,
                 render_area_size: 15,
-                bitmask_binding: 11,
-                voxel_ids_binding: Some(7),
+                render_area_size_y: None,
+                bitmask_binding: bindings.register_at("lod_1_0_bitmask", 11),
+                voxel_ids_binding: Some(bindings.register_at("lod_1_0_voxel_ids", 7)),
+                downsample: Downsample::default(),
             },
             VoxelLODCreateParams {
                 voxel_resolution: 64,
                 lvl: 2,
                 sublvl: 0,
+                sublvl_y: None,
                 render_area_size: 15,
-                bitmask_binding: 12,
+                render_area_size_y: None,
+                bitmask_binding: bindings.register_at("lod_2_0_bitmask", 12),
                 voxel_ids_binding: None,
+                downsample: Downsample::default(),
             },
         ],
         Arc::clone(&renderer_context.memory_allocator) as Arc<dyn MemoryAllocator>,
@@ -271,6 +290,7 @@ fn test_queue_load_all() {
         let mut loader =
             ChunkLoader::<_, TakenChunkVoxelEditor<Block, 5>>::new(ChunkLoaderParams {
                 n_threads: 1,
+                rate_limit: None,
             });
         let md = world.mem_grid.metadata().clone();
         for chunk in queue {
@@ -288,7 +308,7 @@ fn test_queue_load_all() {
 
     // Examine updates that would be made to staging buffers for each LOD
 
-    let [u_0_0, u_0_1, u_0_2, u_1_0, u_2_0] = world.mem_grid.get_updates();
+    let [u_0_0, u_0_1, u_0_2, u_1_0, u_2_0] = world.mem_grid.get_updates(&UploadBudget::default());
     let dummy_bitmask = VoxelBitmask::new_vec(0);
     let dummy_ids = VoxelTypeIDs::new_vec(0);
     let mut u_0_0_bitmask = vec![VoxelBitmask::new_vec(cubed(64)); cubed(2)];
@@ -313,7 +333,7 @@ fn test_queue_load_all() {
     assert_updates_eq(
         u_0_0,
         vec![VoxelLODUpdate {
-            bitmask: &u_0_0_bitmask[0],
+            bitmask: Cow::Borrowed(&u_0_0_bitmask[0]),
             bitmask_updated_region: BufferCopy {
                 src_offset: 0,
                 dst_offset: 0,
@@ -321,7 +341,7 @@ fn test_queue_load_all() {
                 ..Default::default()
             },
             id_update: Some(VoxelIDUpdate {
-                ids: &u_0_0_voxels[0],
+                ids: Cow::Borrowed(&u_0_0_voxels[0]),
                 updated_region: BufferCopy {
                     src_offset: 0,
                     dst_offset: 0,
@@ -346,7 +366,7 @@ fn test_queue_load_all() {
         (
             chunk_idx as usize,
             VoxelLODUpdate {
-                bitmask: &dummy_bitmask,
+                bitmask: Cow::Borrowed(dummy_bitmask),
                 bitmask_updated_region: BufferCopy {
                     src_offset: 0,
                     dst_offset: chunk_idx * (n_vox / 8).max(16),
@@ -355,7 +375,7 @@ fn test_queue_load_all() {
                 },
                 id_update: if ids {
                     Some(VoxelIDUpdate {
-                        ids: &dummy_ids,
+                        ids: Cow::Borrowed(dummy_ids),
                         updated_region: BufferCopy {
                             src_offset: 0,
                             dst_offset: chunk_idx * n_vox.max(16),
@@ -417,9 +437,9 @@ fn test_queue_load_all() {
 
         // put references to the correct ground truth in each update
         for (idx, update) in updates.iter_mut() {
-            update.bitmask = &bitmasks[*idx];
+            update.bitmask = Cow::Borrowed(&bitmasks[*idx]);
             if let Some(ref ids) = ids {
-                update.id_update.as_mut().unwrap().ids = &ids[*idx];
+                update.id_update.as_mut().unwrap().ids = Cow::Borrowed(&ids[*idx]);
             }
         }
 
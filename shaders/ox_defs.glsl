@@ -0,0 +1,39 @@
+// Generated by ox::renderer::shader_defs from the Rust configuration. Do not edit.
+#ifndef OX_DEFS_GLSL
+#define OX_DEFS_GLSL
+
+#define CHUNK_SIZE 8
+#define N_CHUNK_LVLS 2
+#define RENDER_N_TLCS 23
+#define RENDER_N_TLCS_Y 23
+#define N_MATERIAL_ID_BITS 8
+const uint N_SUBLVLS = 3;
+const uint RENDER_N_TLCS_SUBLVL[N_SUBLVLS] = uint[](3, 5, 9);
+const uint RENDER_N_TLCS_Y_SUBLVL[N_SUBLVLS] = uint[](3, 5, 9);
+const uint SUBLVL_Y[N_SUBLVLS] = uint[](0, 1, 2);
+#define BINDING_IMAGE 0
+#define BINDING_MATERIALS 1
+#define BINDING_CAMERA 2
+#define BINDING_UBO 3
+#define BINDING_LOD_0_0_VOXEL_IDS 4
+#define BINDING_LOD_0_1_VOXEL_IDS 5
+#define BINDING_LOD_0_2_VOXEL_IDS 6
+#define BINDING_LOD_1_0_VOXEL_IDS 7
+#define BINDING_LOD_0_0_BITMASK 8
+#define BINDING_LOD_0_1_BITMASK 9
+#define BINDING_LOD_0_2_BITMASK 10
+#define BINDING_LOD_1_0_BITMASK 11
+#define BINDING_LOD_2_0_BITMASK 12
+#define BINDING_HIGHLIGHT 13
+struct Material {
+    vec3 color;
+    vec3 specular_color;
+    vec3 emission_color;
+    float emission_strength; // [0,1]
+    float specular_prob_perpendicular; // [0,1]
+    float specular_prob_parallel; // [0,1]
+    float alpha; // [0,1], 1 is opaque
+    float ior; // >= 1
+};
+
+#endif // OX_DEFS_GLSL